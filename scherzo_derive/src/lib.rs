@@ -1,6 +1,11 @@
 use proc_macro::TokenStream;
-use quote::quote;
-use syn::{parse_macro_input, AttributeArgs, ItemFn};
+use proc_macro2::TokenTree;
+use quote::{quote, ToTokens};
+use std::ops::Not;
+use syn::{
+    parse_macro_input, parse_quote, AttributeArgs, Block, FnArg, ItemFn, Lit, Local, Meta,
+    NestedMeta, Pat, PatIdent, PatType, Stmt, Type, TypePath,
+};
 
 #[proc_macro]
 pub fn impl_db_methods(input: TokenStream) -> TokenStream {
@@ -22,6 +27,10 @@ pub fn impl_db_methods(input: TokenStream) -> TokenStream {
             self. #input .get(key.as_ref()).await.map_err(ServerError::DbError)
         }
 
+        pub async fn compare_and_swap(&self, key: impl AsRef<[u8]>, old: Option<&[u8]>, new: Option<&[u8]>) -> Result<bool, ServerError> {
+            self. #input .compare_and_swap(key.as_ref(), old, new).await.map_err(ServerError::DbError)
+        }
+
         pub async fn contains_key(&self, key: impl AsRef<[u8]>) -> Result<bool, ServerError> {
             self. #input .contains_key(key.as_ref()).await.map_err(ServerError::DbError)
         }
@@ -32,32 +41,407 @@ pub fn impl_db_methods(input: TokenStream) -> TokenStream {
     }).into()
 }
 
-// TODO: move this to hrpc, add error reporting for invalid inputs
 /// Apply a rate limit to this endpoint.
+///
+/// Takes how many requests can be made, and in how many seconds. These
+/// can be overridden per endpoint in config.
 #[proc_macro_attribute]
 pub fn rate(args: TokenStream, input: TokenStream) -> TokenStream {
     let mut args = parse_macro_input!(args as AttributeArgs);
-    let func = parse_macro_input!(input as ItemFn);
+    let ItemFn {
+        attrs,
+        vis,
+        sig,
+        block,
+    } = parse_macro_input!(input as ItemFn);
 
     let dur = args.pop().unwrap();
     let num = args.pop().unwrap();
 
-    let func_name = quote::format_ident!("{}_middleware", func.sig.ident);
+    let endpoint = sig.ident.to_string();
 
     (quote! {
-        fn #func_name (&self) -> Option<harmony_rust_sdk::api::exports::hrpc::server::HrpcLayer> {
-            use harmony_rust_sdk::api::exports::hrpc::server::HrpcLayer;
-
-            (!self.disable_ratelimits)
-                .then(|| HrpcLayer::new(crate::utils::rate_limit(
+        #(#attrs)*
+        #vis #sig {
+            if !self.disable_ratelimits {
+                if let Err(err) = self.deps.rate_limiter.check(
+                    &self.deps.valid_sessions,
+                    &request,
+                    #endpoint,
                     #num,
                     std::time::Duration::from_secs(#dur),
-                    self.deps.config.policy.ratelimit.client_ip_header_name.clone(),
-                    self.deps.config.policy.ratelimit.allowed_ips.clone(),
-                )))
-        }
+                ) {
+                    use harmony_rust_sdk::api::exports::hrpc::server::error::HrpcError;
+
+                    return Box::pin(std::future::ready(Err(HrpcError::from(err))));
+                }
+            }
 
-        #func
+            #block
+        }
     })
     .into()
 }
+
+/// Check that the user is in a guild and has a permission there, before the
+/// rest of a chat handler runs.
+///
+/// Takes `guild`, or `guild, channel` to also check that the channel exists
+/// and to check the permission in it, followed by the permission as a string
+/// or a constant. Without a permission, only membership (and the channel) is
+/// checked, for handlers whose permission depends on the request. The
+/// handler must have `svc` in scope. Where the checks go depends on the
+/// handler's signature:
+///
+/// - Handlers taking the user ID and their request message, like
+///   `handler(svc: &ChatServer, user_id: u64, request: FooRequest)`, are
+///   checked before anything else runs, with the `guild_id` (and
+///   `channel_id`) fields of `request`.
+/// - Handlers taking an hrpc `request: Request<FooRequest>` have to
+///   authenticate and decode it first. The checks are put right after the
+///   `let` that decodes it with `into_message`, which must bind `guild_id`
+///   (and `channel_id`), and `user_id` must be bound before that.
+///
+/// Anything else is a compile error.
+#[proc_macro_attribute]
+pub fn require_perms(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as AttributeArgs);
+    let item = parse_macro_input!(input as ItemFn);
+
+    match expand_require_perms(&args, item) {
+        Ok(item) => item.into_token_stream().into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand_require_perms(args: &[NestedMeta], mut item: ItemFn) -> syn::Result<ItemFn> {
+    let mut in_channel = false;
+    let mut perm = None;
+    for arg in args {
+        match arg {
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("guild") => {}
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("channel") => in_channel = true,
+            NestedMeta::Lit(Lit::Str(lit)) if perm.is_none() => perm = Some(lit.to_token_stream()),
+            NestedMeta::Meta(Meta::Path(path)) if perm.is_none() => {
+                perm = Some(path.to_token_stream())
+            }
+            other => {
+                return Err(syn::Error::new_spanned(
+                    other,
+                    "expected `guild`, `channel` or a permission",
+                ))
+            }
+        }
+    }
+    let request_ty = item
+        .sig
+        .inputs
+        .iter()
+        .find_map(|input| match input {
+            FnArg::Typed(PatType { pat, ty, .. }) if is_ident_pat(pat, "request") => Some(ty),
+            _ => None,
+        })
+        .ok_or_else(|| syn::Error::new_spanned(&item.sig, "expected a `request` argument"))?;
+    let is_hrpc_request = matches!(
+        request_ty.as_ref(),
+        Type::Path(TypePath { path, .. })
+            if path.segments.last().map_or(false, |segment| segment.ident == "Request")
+    );
+
+    if is_hrpc_request.not() {
+        let takes_user_id = item.sig.inputs.iter().any(|input| {
+            matches!(input, FnArg::Typed(PatType { pat, .. }) if is_ident_pat(pat, "user_id"))
+        });
+        if takes_user_id.not() {
+            return Err(syn::Error::new_spanned(
+                &item.sig,
+                "expected a `user_id` argument",
+            ));
+        }
+        let channel_id = in_channel.then(|| quote! { request.channel_id });
+        let checks = perm_checks(quote! { request.guild_id }, channel_id, perm.as_ref());
+        item.block.stmts.splice(0..0, checks.stmts);
+        return Ok(item);
+    }
+
+    let names: &[&str] = if in_channel {
+        &["guild_id", "channel_id"]
+    } else {
+        &["guild_id"]
+    };
+    let stmts = &item.block.stmts;
+    let decoded_at = stmts
+        .iter()
+        .position(|stmt| match stmt {
+            Stmt::Local(Local {
+                init: Some((_, init)),
+                ..
+            }) => mentions(init.to_token_stream(), "into_message"),
+            _ => false,
+        })
+        .ok_or_else(|| {
+            syn::Error::new_spanned(
+                &item.sig,
+                "expected a `let` that decodes the request with `into_message`",
+            )
+        })?;
+    let decoded = match &stmts[decoded_at] {
+        Stmt::Local(local) => local,
+        _ => unreachable!("decoded request is always found in a `let`"),
+    };
+    for name in names {
+        if mentions(decoded.pat.to_token_stream(), name).not() {
+            return Err(syn::Error::new_spanned(
+                &decoded.pat,
+                format!("the decoded request must bind `{}`", name),
+            ));
+        }
+    }
+    let binds_user_id = stmts[..decoded_at].iter().any(|stmt| match stmt {
+        Stmt::Local(local) => mentions(local.pat.to_token_stream(), "user_id"),
+        _ => false,
+    });
+    if binds_user_id.not() {
+        return Err(syn::Error::new_spanned(
+            &decoded.pat,
+            "`user_id` must be bound before the request is decoded",
+        ));
+    }
+
+    let channel_id = in_channel.then(|| quote! { channel_id });
+    let checks = perm_checks(quote! { guild_id }, channel_id, perm.as_ref());
+    item.block
+        .stmts
+        .splice(decoded_at + 1..decoded_at + 1, checks.stmts);
+    Ok(item)
+}
+
+/// The statements that check a user is in a guild (and that a channel
+/// exists in it), and that they have a permission there if one is given.
+fn perm_checks(
+    guild_id: proc_macro2::TokenStream,
+    channel_id: Option<proc_macro2::TokenStream>,
+    perm: Option<&proc_macro2::TokenStream>,
+) -> Block {
+    let mut checks: Block = match &channel_id {
+        Some(channel_id) => parse_quote! {{
+            svc.deps
+                .chat_tree
+                .check_guild_user_channel(#guild_id, user_id, #channel_id)
+                .await?;
+        }},
+        None => parse_quote! {{
+            svc.deps.chat_tree.check_guild_user(#guild_id, user_id).await?;
+        }},
+    };
+    if let Some(perm) = perm {
+        let channel_id = match channel_id {
+            Some(channel_id) => quote! { Some(#channel_id) },
+            None => quote! { None },
+        };
+        checks.stmts.push(parse_quote! {
+            svc.deps
+                .chat_tree
+                .check_perms(#guild_id, #channel_id, user_id, #perm, false)
+                .await?;
+        });
+    }
+    checks
+}
+
+fn is_ident_pat(pat: &Pat, name: &str) -> bool {
+    matches!(pat, Pat::Ident(PatIdent { ident, .. }) if ident == name)
+}
+
+/// Whether some tokens have the given identifier anywhere in them.
+fn mentions(tokens: proc_macro2::TokenStream, name: &str) -> bool {
+    tokens.into_iter().any(|token| match token {
+        TokenTree::Ident(ident) => ident == name,
+        TokenTree::Group(group) => mentions(group.stream(), name),
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use syn::LitStr;
+
+    fn expand(args: &[NestedMeta], item: ItemFn) -> Vec<String> {
+        expand_require_perms(args, item)
+            .unwrap()
+            .block
+            .stmts
+            .iter()
+            .map(|stmt| stmt.to_token_stream().to_string())
+            .collect()
+    }
+
+    fn perm_checks_str(guild_id: proc_macro2::TokenStream, perm: &str) -> Vec<String> {
+        let perm = LitStr::new(perm, proc_macro2::Span::call_site()).to_token_stream();
+        perm_checks(guild_id, None, Some(&perm))
+            .stmts
+            .iter()
+            .map(|stmt| stmt.to_token_stream().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn checks_request_fields_first() {
+        let item: ItemFn = parse_quote! {
+            pub async fn handler(svc: &ChatServer, user_id: u64, request: FooRequest) -> ServerResult<FooResponse> {
+                let FooRequest { guild_id } = request;
+                let guild_id = guild_id + 1;
+                Ok(FooResponse {})
+            }
+        };
+        let stmts = expand(&[parse_quote!(guild), parse_quote!("foo.bar")], item);
+
+        assert_eq!(
+            stmts[..2],
+            perm_checks_str(quote! { request.guild_id }, "foo.bar")[..]
+        );
+        assert_eq!(stmts.len(), 5);
+    }
+
+    #[test]
+    fn checks_channel_from_request_fields() {
+        let item: ItemFn = parse_quote! {
+            pub async fn handler(svc: &ChatServer, user_id: u64, request: FooRequest) -> ServerResult<FooResponse> {
+                Ok(FooResponse {})
+            }
+        };
+        let stmts = expand(
+            &[
+                parse_quote!(guild),
+                parse_quote!(channel),
+                parse_quote!("foo.bar"),
+            ],
+            item,
+        );
+
+        assert!(stmts[0].contains(
+            "check_guild_user_channel (request . guild_id , user_id , request . channel_id)"
+        ));
+        assert!(stmts[1].contains("Some (request . channel_id)"));
+    }
+
+    #[test]
+    fn checks_hrpc_request_after_decoding() {
+        let item: ItemFn = parse_quote! {
+            pub async fn handler(svc: &ChatServer, request: Request<FooRequest>) -> ServerResult<Response<FooResponse>> {
+                let user_id = svc.deps.valid_sessions.auth(&request)?;
+                let FooRequest { guild_id, role_id } = request.into_message().await?;
+                let guild_id = 0;
+                Ok(FooResponse {}.into_response())
+            }
+        };
+        let stmts = expand(&[parse_quote!(guild), parse_quote!("foo.bar")], item);
+
+        assert_eq!(
+            stmts[2..4],
+            perm_checks_str(quote! { guild_id }, "foo.bar")[..]
+        );
+        assert!(stmts[4].starts_with("let guild_id = 0"));
+    }
+
+    #[test]
+    fn takes_permission_constants() {
+        let item: ItemFn = parse_quote! {
+            pub async fn handler(svc: &ChatServer, user_id: u64, request: FooRequest) -> ServerResult<FooResponse> {
+                Ok(FooResponse {})
+            }
+        };
+        let stmts = expand(
+            &[
+                parse_quote!(guild),
+                parse_quote!(channel),
+                parse_quote!(all_permissions::MESSAGES_VIEW),
+            ],
+            item,
+        );
+
+        assert!(stmts[1].contains("user_id , all_permissions :: MESSAGES_VIEW , false"));
+    }
+
+    #[test]
+    fn rejects_hrpc_request_not_binding_ids() {
+        let item: ItemFn = parse_quote! {
+            pub async fn handler(svc: &ChatServer, request: Request<FooRequest>) -> ServerResult<Response<FooResponse>> {
+                let user_id = svc.deps.valid_sessions.auth(&request)?;
+                let FooRequest { guild_id } = request.into_message().await?;
+                Ok(FooResponse {}.into_response())
+            }
+        };
+        let err = expand_require_perms(
+            &[
+                parse_quote!(guild),
+                parse_quote!(channel),
+                parse_quote!("foo.bar"),
+            ],
+            item,
+        )
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("must bind `channel_id`"));
+    }
+
+    #[test]
+    fn rejects_hrpc_request_authenticated_after_decoding() {
+        let item: ItemFn = parse_quote! {
+            pub async fn handler(svc: &ChatServer, request: Request<FooRequest>) -> ServerResult<Response<FooResponse>> {
+                let FooRequest { guild_id } = request.into_message().await?;
+                let user_id = 0;
+                Ok(FooResponse {}.into_response())
+            }
+        };
+        let err = expand_require_perms(&[parse_quote!(guild), parse_quote!("foo.bar")], item)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("`user_id` must be bound"));
+    }
+
+    #[test]
+    fn rejects_handler_without_request() {
+        let item: ItemFn = parse_quote! {
+            pub async fn handler(svc: &ChatServer, user_id: u64, req: FooRequest) -> ServerResult<FooResponse> {
+                Ok(FooResponse {})
+            }
+        };
+        assert!(
+            expand_require_perms(&[parse_quote!(guild), parse_quote!("foo.bar")], item).is_err()
+        );
+    }
+
+    #[test]
+    fn checks_only_membership_without_permission() {
+        let item: ItemFn = parse_quote! {
+            pub async fn handler(svc: &ChatServer, user_id: u64, request: FooRequest) -> ServerResult<FooResponse> {
+                Ok(FooResponse {})
+            }
+        };
+        let stmts = expand(&[parse_quote!(guild)], item);
+
+        assert_eq!(stmts.len(), 2);
+        assert!(stmts[0].contains("check_guild_user (request . guild_id , user_id)"));
+    }
+
+    #[test]
+    fn rejects_two_permissions() {
+        let item: ItemFn = parse_quote! {
+            pub async fn handler(svc: &ChatServer, user_id: u64, request: FooRequest) -> ServerResult<FooResponse> {
+                Ok(FooResponse {})
+            }
+        };
+        assert!(expand_require_perms(
+            &[
+                parse_quote!(guild),
+                parse_quote!("foo.bar"),
+                parse_quote!("foo.baz")
+            ],
+            item
+        )
+        .is_err());
+    }
+}
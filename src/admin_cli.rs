@@ -0,0 +1,310 @@
+//! One-shot admin commands run by `scherzo admin <command>`, so scripted
+//! maintenance doesn't need the interactive console.
+//!
+//! Commands open the database directly when the server isn't running. While
+//! it is running, the server holds the database lock, so the commands go
+//! through the admin endpoints under `/_scherzo/admin/` instead, using the
+//! admin token from the config.
+
+use std::{fmt::Write, ops::Not, path::Path};
+
+use hrpc::client::transport::http::hyper::http_client;
+use hyper::{header, http, Body, Uri};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    check,
+    config::Config,
+    db::{self, Db},
+    impls::{
+        auth::AuthTree,
+        chat::ChatTree,
+        prelude::ServerResult,
+        rest::admin::{
+            self, GenerateRegistrationTokenRequest, GenerateRegistrationTokenResponse,
+            ListGuildsRequest, ListGuildsResponse, ResetPasswordRequest, ResetPasswordResponse,
+        },
+    },
+    ServerError,
+};
+
+pub const USAGE: &str = r#"usage: scherzo admin <command> [args] [--db <path>] [--url <url>]
+
+commands:
+  generate-registration-token          generates a registration token
+  list-guilds                          lists all guilds
+  reset-password <user id | email> [new password]
+                                       sets a new password for a user, generating one if not given
+
+arguments after `--` aren't parsed as flags, for passwords starting with `-`.
+
+the database is opened directly if the server isn't running. otherwise the
+commands are sent to the server's admin API at `--url` (defaults to the port
+in `config.toml` on localhost), which needs `policy.admin_token` to be set."#;
+
+#[derive(Debug)]
+pub enum AdminCommand {
+    GenerateRegistrationToken,
+    ListGuilds,
+    ResetPassword(ResetPasswordRequest),
+}
+
+/// Parsed arguments of `scherzo admin`.
+#[derive(Debug)]
+pub struct AdminArgs {
+    pub command: AdminCommand,
+    /// URL of the running server, used if the database can't be opened.
+    pub url: Option<String>,
+}
+
+impl AdminArgs {
+    /// Parses the arguments after `admin`. Flags the server parses for every
+    /// command, like `--db`, are skipped. Everything after `--` is taken as
+    /// is, so passwords can start with `-`.
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut url = None;
+        let mut positional = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--" => {
+                    positional.extend(args.by_ref().cloned());
+                }
+                "--db" | "--outbound-url" => {
+                    args.next();
+                }
+                // the server's own flags, see `main`
+                "--enable-tokio-console"
+                | "--enable-jaeger"
+                | "-d"
+                | "--debug"
+                | "-v"
+                | "--verbose"
+                | "-q"
+                | "--quiet" => {}
+                "--url" => url = Some(args.next().ok_or("need url after `--url`")?.clone()),
+                arg if arg.starts_with('-') => return Err(format!("unknown flag `{}`", arg)),
+                _ => positional.push(arg.clone()),
+            }
+        }
+
+        let command = match positional.first().map(String::as_str) {
+            Some("generate-registration-token") => AdminCommand::GenerateRegistrationToken,
+            Some("list-guilds") => AdminCommand::ListGuilds,
+            Some("reset-password") => AdminCommand::ResetPassword(ResetPasswordRequest {
+                user: positional.get(1).ok_or("need user id or email")?.clone(),
+                password: positional.get(2).cloned(),
+            }),
+            Some(command) => return Err(format!("no such command `{}`", command)),
+            None => return Err("need a command".to_string()),
+        };
+
+        Ok(Self { command, url })
+    }
+}
+
+/// Runs an admin command, returning what to print.
+pub async fn run(args: AdminArgs, config_path: &Path, db_path: &str) -> Result<String, String> {
+    let config = if config_path.exists() {
+        check::load_config(config_path)?
+    } else {
+        Config::default()
+    };
+
+    match db::open_database(db_path.to_string(), config.db.clone()).await {
+        Ok(db) => {
+            let output = run_on_db(&db, args.command)
+                .await
+                .map_err(|err| err.to_string());
+            db.flush()
+                .await
+                .map_err(|err| format!("couldn't flush database: {}", err))?;
+            output
+        }
+        Err(err) => {
+            // the running server holds the database lock
+            let url = args.url.unwrap_or_else(|| {
+                let scheme = if config.tls.is_some() {
+                    "https"
+                } else {
+                    "http"
+                };
+                format!("{}://localhost:{}", scheme, config.port)
+            });
+            let token = match config.policy.admin_token.as_deref() {
+                Some(token) if token.is_empty().not() => token,
+                _ => {
+                    return Err(format!(
+                        "couldn't open the database ({}), and the admin API can't be used since `policy.admin_token` isn't set",
+                        err
+                    ))
+                }
+            };
+            run_with_api(&url, token, args.command)
+                .await
+                .map_err(|api_err| {
+                    format!(
+                        "couldn't open the database ({}), or use the admin API: {}",
+                        err, api_err
+                    )
+                })
+        }
+    }
+}
+
+async fn run_on_db(db: &Db, command: AdminCommand) -> ServerResult<String> {
+    let output = match command {
+        AdminCommand::GenerateRegistrationToken => {
+            let token = AuthTree::new(db)
+                .await
+                .map_err(ServerError::from)?
+                .put_rand_reg_token()
+                .await?;
+            format_registration_token(GenerateRegistrationTokenResponse {
+                token: token.into(),
+            })
+        }
+        AdminCommand::ListGuilds => {
+            let chat_tree = ChatTree::new(db).await.map_err(ServerError::from)?;
+            format_guilds(admin::list_guilds_logic(&chat_tree).await?)
+        }
+        AdminCommand::ResetPassword(request) => {
+            let auth_tree = AuthTree::new(db).await.map_err(ServerError::from)?;
+            format_password(admin::reset_password_logic(&auth_tree, request).await?)
+        }
+    };
+    Ok(output)
+}
+
+async fn run_with_api(url: &str, token: &str, command: AdminCommand) -> Result<String, String> {
+    let output = match command {
+        AdminCommand::GenerateRegistrationToken => format_registration_token(
+            call_api(
+                url,
+                token,
+                "generate-registration-token",
+                &GenerateRegistrationTokenRequest {},
+            )
+            .await?,
+        ),
+        AdminCommand::ListGuilds => {
+            format_guilds(call_api(url, token, "list-guilds", &ListGuildsRequest {}).await?)
+        }
+        AdminCommand::ResetPassword(request) => {
+            format_password(call_api(url, token, "reset-password", &request).await?)
+        }
+    };
+    Ok(output)
+}
+
+/// Calls an admin endpoint of the running server with the admin token.
+async fn call_api<Req: Serialize, Resp: DeserializeOwned>(
+    url: &str,
+    token: &str,
+    endpoint: &str,
+    request: &Req,
+) -> Result<Resp, String> {
+    let url = format!("{}/_scherzo/admin/{}", url.trim_end_matches('/'), endpoint);
+    let uri: Uri = url
+        .parse()
+        .map_err(|err| format!("invalid url {}: {}", url, err))?;
+    let body = serde_json::to_vec(request).map_err(|err| err.to_string())?;
+    let request = http::Request::builder()
+        .method(http::Method::POST)
+        .uri(uri)
+        .header(header::AUTHORIZATION, token)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .map_err(|err| format!("invalid request to {}: {}", url, err))?;
+
+    let http = http_client(&mut hyper::Client::builder());
+    let response = http
+        .request(request)
+        .await
+        .map_err(|err| format!("couldn't reach the server at {}: {}", url, err))?;
+    let status = response.status();
+    let raw = hyper::body::to_bytes(response.into_body())
+        .await
+        .map_err(|err| format!("couldn't read response from {}: {}", url, err))?;
+    if status.is_success().not() {
+        return Err(format!(
+            "server responded with {}: {}",
+            status,
+            String::from_utf8_lossy(&raw)
+        ));
+    }
+    serde_json::from_slice(&raw).map_err(|err| format!("invalid response from {}: {}", url, err))
+}
+
+fn format_registration_token(response: GenerateRegistrationTokenResponse) -> String {
+    response.token
+}
+
+fn format_guilds(response: ListGuildsResponse) -> String {
+    let mut output = String::new();
+    for guild in response.guilds {
+        let _ = writeln!(
+            output,
+            "{}: {} (owners: {:?})",
+            guild.guild_id, guild.name, guild.owner_ids
+        );
+    }
+    output
+}
+
+fn format_password(response: ResetPasswordResponse) -> String {
+    format!(
+        "password for user {} set to: {}",
+        response.user_id, response.password
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<AdminArgs, String> {
+        AdminArgs::parse(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn parses_commands_around_flags() {
+        let args = parse(&[
+            "--db",
+            "list-guilds",
+            "reset-password",
+            "user@example.org",
+            "--url",
+            "http://localhost",
+        ])
+        .unwrap();
+        let AdminCommand::ResetPassword(request) = args.command else {
+            panic!("expected reset-password, got {:?}", args.command);
+        };
+        assert_eq!(request.user, "user@example.org");
+        assert_eq!(request.password, None);
+        assert_eq!(args.url.as_deref(), Some("http://localhost"));
+
+        let args = parse(&["list-guilds", "-q"]).unwrap();
+        assert!(matches!(args.command, AdminCommand::ListGuilds));
+    }
+
+    #[test]
+    fn takes_arguments_after_double_dash_as_is() {
+        let args = parse(&["reset-password", "--", "user@example.org", "-secret"]).unwrap();
+        let AdminCommand::ResetPassword(request) = args.command else {
+            panic!("expected reset-password, got {:?}", args.command);
+        };
+        assert_eq!(request.user, "user@example.org");
+        assert_eq!(request.password.as_deref(), Some("-secret"));
+    }
+
+    #[test]
+    fn rejects_bad_commands() {
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["reset-password"]).is_err());
+        assert!(parse(&["drop-database"]).is_err());
+        assert!(parse(&["list-guilds", "--force"]).is_err());
+        assert!(parse(&["reset-password", "user@example.org", "-secret"]).is_err());
+    }
+}
@@ -1,22 +1,62 @@
-use std::{error::Error, fmt::Display, io::prelude::*, mem::size_of};
+use std::{error::Error, fmt::Display, io::prelude::*, path::Path};
 
 use scherzo::{
+    admin_cli::{self, AdminArgs},
     config::DbConfig,
-    db::deser_guild,
     impls::{auth::AuthTree, chat::ChatTree},
 };
 
+const USAGE: &str = r#"usage: scherzo_cmd <command> [args]
+
+commands:
+  list accounts                        lists all registered emails
+  list guilds                          lists all guilds
+  list channels <guild id>             lists all channels in a guild
+  generate registration-token          generates a registration token
+  reset-password <user id | email> [new password]
+                                       sets a new password for a user, generating one if not given
+
+the database is read from the path in `SCHERZO_DB` (defaults to `./db`).
+note: the server must not be running while listing accounts or channels, since
+the database can only be opened by one process at a time. the other commands
+are the same as `scherzo admin`, and go through the admin API of the running
+server instead."#;
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let args = std::env::args().skip(1).collect::<Vec<String>>();
     let db_path = std::env::var("SCHERZO_DB").unwrap_or_else(|_| "./db".to_string());
 
+    let command = match args.first().map(String::as_str) {
+        Some(command) => command,
+        None => exit_with_msg(USAGE, 1),
+    };
+    if matches!(command, "help" | "-h" | "--help") {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+
+    let admin_args = match (command, args.get(1).map(String::as_str)) {
+        ("generate", Some("registration-token")) => {
+            Some(vec!["generate-registration-token".to_string()])
+        }
+        ("list", Some("guilds")) => Some(vec!["list-guilds".to_string()]),
+        ("generate-registration-token" | "list-guilds" | "reset-password", _) => Some(args.clone()),
+        _ => None,
+    };
+    if let Some(admin_args) = admin_args {
+        let admin_args = AdminArgs::parse(&admin_args)?;
+        let output = admin_cli::run(admin_args, Path::new("./config.toml"), &db_path).await?;
+        writeln!(std::io::stdout(), "{}", output.trim_end())?;
+        return Ok(());
+    }
+
     let db = scherzo::db::open_db(db_path, DbConfig::default()).await;
 
     let auth_tree = AuthTree::new(&db).await?;
     let chat_tree = ChatTree::new(&db).await?;
 
-    match args.first().map(String::as_str).ok_or("no command")? {
+    match command {
         "list" => match args.get(1).map(String::as_str).ok_or("need list name")? {
             "accounts" => {
                 let mut stdout = std::io::stdout();
@@ -30,19 +70,6 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     }
                 }
             }
-            "guilds" => {
-                let mut stdout = std::io::stdout();
-                for res in chat_tree.chat_tree.iter().await {
-                    let (key, val) = res?;
-
-                    if key.len() == size_of::<u64>() {
-                        let guild = deser_guild(val);
-                        let id =
-                            u64::from_be_bytes(key.try_into().expect("failed to convert to id"));
-                        writeln!(stdout, "{}: {:#?}", id, guild)?;
-                    }
-                }
-            }
             "channels" => {
                 let guild_id = args
                     .get(2)
@@ -65,9 +92,11 @@ async fn main() -> Result<(), Box<dyn Error>> {
             }
             _ => exit_with_msg("no such list", 1),
         },
-        _ => exit_with_msg("no such command", 1),
+        _ => exit_with_msg(USAGE, 1),
     }
 
+    db.flush().await?;
+
     Ok(())
 }
 
@@ -2,7 +2,10 @@ use std::{collections::HashMap, path::PathBuf};
 
 use hrpc::BoxError;
 use itertools::Itertools;
+#[cfg(feature = "postgres")]
+use scherzo::db::postgres::shared as postgres;
 use scherzo::{
+    config::DbConfig,
     db::{self, sled::shared as sled, sqlite::shared as sqlite, Batch},
     utils::evec::EVec,
 };
@@ -15,8 +18,8 @@ fn main() -> Result<(), BoxError> {
 
     if args.len() < 4 {
         println!(
-            "usage: {} <db path> <db type> <db target type>\nexample: {} ./db sled sqlite",
-            args[0], args[0]
+            "usage: {} <db path> <db type> <db target type> [target postgres url]\nexample: {} ./db sled sqlite\nexample: {} ./db sled postgres postgres://localhost/scherzo",
+            args[0], args[0], args[0]
         );
         std::process::exit(1);
     }
@@ -27,7 +30,19 @@ fn main() -> Result<(), BoxError> {
     let db_path = args.pop().expect("expected db path");
     let db_type = args.pop().expect("expected db type to migrate to");
     let db_target = args.pop().expect("expected db type to migrate to");
-    let db_config = scherzo::config::DbConfig::default();
+    let mut db_config = scherzo::config::DbConfig::default();
+
+    // postgres databases live outside of the db path, so the source db can be left as is
+    if db_target == "postgres" {
+        db_config.postgres_url = Some(args.pop().expect("expected postgres url"));
+        return rt.block_on(migrate(
+            db_path.clone(),
+            db_type,
+            db_path,
+            db_target,
+            db_config,
+        ));
+    }
 
     let mut db_backup_path = PathBuf::from(db_path.clone());
     db_backup_path.set_file_name(format!(
@@ -39,25 +54,30 @@ fn main() -> Result<(), BoxError> {
 
     let db_backup_path = db_backup_path.into_os_string().into_string().unwrap();
 
-    rt.block_on(async move {
-        let src_db = match db_type.as_str() {
-            "sled" => Db::Sled(sled::open_database(db_backup_path, db_config.clone()).await?),
-            "sqlite" => Db::Sqlite(sqlite::open_database(db_backup_path, db_config.clone()).await?),
-            _ => panic!("no such db"),
-        };
-
-        let target_db = match db_target.as_str() {
-            "sled" => Db::Sled(sled::open_database(db_path, db_config).await?),
-            "sqlite" => Db::Sqlite(sqlite::open_database(db_path, db_config).await?),
-            _ => panic!("no such db"),
-        };
-
-        let vals = src_db.iter().await?;
-        target_db.insert(vals).await?;
-        target_db.flush().await?;
+    rt.block_on(migrate(
+        db_backup_path,
+        db_type,
+        db_path,
+        db_target,
+        db_config,
+    ))
+}
 
-        Ok(())
-    })
+async fn migrate(
+    src_path: String,
+    src_type: String,
+    target_path: String,
+    target_type: String,
+    db_config: DbConfig,
+) -> Result<(), BoxError> {
+    let src_db = Db::open(src_path, &src_type, db_config.clone()).await?;
+    let target_db = Db::open(target_path, &target_type, db_config).await?;
+
+    let vals = src_db.iter().await?;
+    target_db.insert(vals).await?;
+    target_db.flush().await?;
+
+    Ok(())
 }
 
 type ValueMap = HashMap<&'static [u8], Vec<(EVec, EVec)>, ahash::RandomState>;
@@ -65,9 +85,22 @@ type ValueMap = HashMap<&'static [u8], Vec<(EVec, EVec)>, ahash::RandomState>;
 enum Db {
     Sled(sled::Db),
     Sqlite(sqlite::Db),
+    #[cfg(feature = "postgres")]
+    Postgres(postgres::Db),
 }
 
 impl Db {
+    async fn open(path: String, db_type: &str, db_config: DbConfig) -> Result<Self, BoxError> {
+        let db = match db_type {
+            "sled" => Db::Sled(sled::open_database(path, db_config).await?),
+            "sqlite" => Db::Sqlite(sqlite::open_database(path, db_config).await?),
+            #[cfg(feature = "postgres")]
+            "postgres" => Db::Postgres(postgres::open_database(path, db_config).await?),
+            _ => panic!("no such db"),
+        };
+        Ok(db)
+    }
+
     async fn iter(&self) -> Result<ValueMap, BoxError> {
         let mut treemap = HashMap::with_hasher(ahash::RandomState::new());
         for name in db::TREES {
@@ -92,6 +125,17 @@ impl Db {
                         })?,
                     );
                 }
+                #[cfg(feature = "postgres")]
+                Self::Postgres(db) => {
+                    let tree = db.open_tree(name).await?;
+                    treemap.insert(
+                        name,
+                        tree.iter().await.fold_ok(Vec::new(), |mut all, item| {
+                            all.push(item);
+                            all
+                        })?,
+                    );
+                }
             }
         }
         Ok(treemap)
@@ -116,6 +160,15 @@ impl Db {
                     }
                     tree.apply_batch(batch).await?;
                 }
+                #[cfg(feature = "postgres")]
+                Self::Postgres(db) => {
+                    let tree = db.open_tree(name).await?;
+                    let mut batch = Batch::default();
+                    for (k, v) in values.remove(name).expect("no such tree") {
+                        batch.insert(k, v);
+                    }
+                    tree.apply_batch(batch).await?;
+                }
             }
         }
         Ok(())
@@ -125,6 +178,8 @@ impl Db {
         match self {
             Self::Sled(db) => db.flush().await?,
             Self::Sqlite(db) => db.flush().await?,
+            #[cfg(feature = "postgres")]
+            Self::Postgres(db) => db.flush().await?,
         }
         Ok(())
     }
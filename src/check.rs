@@ -0,0 +1,477 @@
+//! Checks run by `scherzo check`, to find out if the server would start
+//! fine before restarting it, for example in deployment pipelines.
+//!
+//! Nothing the running server uses is changed: the database is copied to a
+//! temporary directory, and migrations are tried on the copy. Postgres
+//! databases can't be copied, so for them pending migrations are only listed.
+
+use std::{
+    fmt::{self, Display, Formatter},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use hrpc::client::transport::http::hyper::{http_client, HttpClient};
+use hyper::{http, Body, Uri};
+
+use crate::{
+    config::{Config, TlsConfig},
+    db::{
+        self,
+        migration::{apply_migrations, get_db_version, MIGRATIONS},
+        sync::HOST_PREFIX,
+        Db,
+    },
+};
+
+/// How long outbound requests can take before they count as failed.
+const OUTBOUND_TIMEOUT: Duration = Duration::from_secs(10);
+/// Most federated hosts to try reaching, so the check doesn't take forever.
+const MAX_HOSTS_TO_REACH: usize = 10;
+/// Certificates expiring sooner than this are warned about.
+const CERT_EXPIRY_WARNING_SECS: i64 = 14 * 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Ok,
+    Skipped,
+    Warning,
+    Failed,
+}
+
+impl Display for CheckStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CheckStatus::Ok => "ok",
+            CheckStatus::Skipped => "skipped",
+            CheckStatus::Warning => "warning",
+            CheckStatus::Failed => "FAILED",
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    pub message: String,
+}
+
+#[derive(Debug, Default)]
+pub struct CheckReport {
+    pub checks: Vec<Check>,
+}
+
+impl CheckReport {
+    fn push(&mut self, name: impl Into<String>, status: CheckStatus, message: impl Into<String>) {
+        self.checks.push(Check {
+            name: name.into(),
+            status,
+            message: message.into(),
+        });
+    }
+
+    pub fn failed(&self) -> bool {
+        self.checks
+            .iter()
+            .any(|check| check.status == CheckStatus::Failed)
+    }
+}
+
+impl Display for CheckReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            writeln!(f, "[{}] {}: {}", check.status, check.name, check.message)?;
+        }
+        let failed = self
+            .checks
+            .iter()
+            .filter(|check| check.status == CheckStatus::Failed)
+            .count();
+        write!(f, "{} checks, {} failed", self.checks.len(), failed)
+    }
+}
+
+/// Runs all checks for the main host and the virtual hosts in the config.
+///
+/// `outbound_url` is fetched to check that the media proxy can reach the
+/// internet. The media proxy isn't checked if it isn't given.
+pub async fn run(config_path: &Path, db_path: &str, outbound_url: Option<&str>) -> CheckReport {
+    let mut report = CheckReport::default();
+
+    let config = match load_config(config_path) {
+        Ok(config) => {
+            report.push("config", CheckStatus::Ok, "parsed");
+            config
+        }
+        Err(err) => {
+            report.push("config", CheckStatus::Failed, err);
+            return report;
+        }
+    };
+
+    let http = http_client(&mut hyper::Client::builder());
+
+    let mut hosts = vec![(config.host.clone(), db_path.to_string(), config.clone())];
+    for vhost in &config.virtual_hosts {
+        hosts.push((
+            vhost.host.clone(),
+            vhost.db_path.clone(),
+            vhost.apply(&config),
+        ));
+    }
+    for (host, db_path, config) in hosts {
+        check_host(&mut report, &http, &host, &db_path, &config).await;
+    }
+
+    match outbound_url {
+        Some(url) => {
+            let (status, message) = match reach(&http, url).await {
+                Ok(message) => (CheckStatus::Ok, message),
+                Err(err) => (CheckStatus::Failed, err),
+            };
+            report.push(format!("mediaproxy: {}", url), status, message);
+        }
+        None => report.push(
+            "mediaproxy",
+            CheckStatus::Skipped,
+            "pass `--outbound-url <url>` to check that outbound requests work",
+        ),
+    }
+
+    report
+}
+
+/// Reads the config without writing the default one if it's missing, unlike
+/// when starting the server.
+pub(crate) fn load_config(path: &Path) -> Result<Config, String> {
+    let raw =
+        std::fs::read(path).map_err(|err| format!("couldn't read {}: {}", path.display(), err))?;
+    toml::from_slice(&raw).map_err(|err| format!("couldn't parse {}: {}", path.display(), err))
+}
+
+async fn check_host(
+    report: &mut CheckReport,
+    http: &HttpClient,
+    host: &str,
+    db_path: &str,
+    config: &Config,
+) {
+    let name = |check: &str| format!("{} {}", host, check);
+
+    if host.is_empty() {
+        report.push(
+            name("host"),
+            CheckStatus::Warning,
+            "`host` isn't set, federation and media links won't work",
+        );
+    }
+
+    if config.media.s3.is_none() {
+        let root = &config.media.media_root;
+        let (status, message) = if root.is_dir() {
+            (CheckStatus::Ok, format!("{} exists", root.display()))
+        } else {
+            (
+                CheckStatus::Warning,
+                format!("{} doesn't exist, it will be created", root.display()),
+            )
+        };
+        report.push(name("media root"), status, message);
+    }
+
+    if let Some(federation) = &config.federation {
+        let (status, message) = check_federation_key(&federation.key);
+        report.push(name("federation key"), status, message);
+    }
+
+    match &config.tls {
+        Some(tls) => {
+            let (status, message) = check_tls(tls);
+            report.push(name("tls"), status, message);
+        }
+        None => report.push(name("tls"), CheckStatus::Skipped, "TLS isn't set up"),
+    }
+
+    let db = match check_db(report, &name("database"), db_path, config).await {
+        Some(db) => db,
+        None => return,
+    };
+
+    if config.federation.is_some() {
+        check_federation(report, http, &name("federation"), &db.0, config).await;
+    }
+    db.cleanup();
+}
+
+fn check_federation_key(path: &Path) -> (CheckStatus, String) {
+    match std::fs::read(path) {
+        Ok(raw) => match ed25519_compact::KeyPair::from_slice(&raw) {
+            Ok(_) => (CheckStatus::Ok, format!("{} is valid", path.display())),
+            Err(_) => (
+                CheckStatus::Failed,
+                format!("{} isn't a valid key", path.display()),
+            ),
+        },
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => (
+            CheckStatus::Warning,
+            format!(
+                "{} doesn't exist, a new key will be generated",
+                path.display()
+            ),
+        ),
+        Err(err) => (
+            CheckStatus::Failed,
+            format!("couldn't read {}: {}", path.display(), err),
+        ),
+    }
+}
+
+fn check_tls(tls: &TlsConfig) -> (CheckStatus, String) {
+    if let Err(err) = std::fs::metadata(&tls.key_file) {
+        return (
+            CheckStatus::Failed,
+            format!("couldn't read {}: {}", tls.key_file.display(), err),
+        );
+    }
+    let raw = match std::fs::read(&tls.cert_file) {
+        Ok(raw) => raw,
+        Err(err) => {
+            return (
+                CheckStatus::Failed,
+                format!("couldn't read {}: {}", tls.cert_file.display(), err),
+            )
+        }
+    };
+    // the first certificate is the server's, the rest are the chain
+    let not_after = x509_parser::pem::parse_x509_pem(&raw)
+        .ok()
+        .and_then(|(_, pem)| Some(pem.parse_x509().ok()?.validity().not_after.timestamp()));
+    let Some(not_after) = not_after else {
+        return (
+            CheckStatus::Failed,
+            format!("{} isn't a valid certificate", tls.cert_file.display()),
+        );
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() as i64);
+    let days_left = (not_after - now) / (24 * 60 * 60);
+    if not_after <= now {
+        (
+            CheckStatus::Failed,
+            format!("{} has expired", tls.cert_file.display()),
+        )
+    } else if not_after - now < CERT_EXPIRY_WARNING_SECS {
+        (
+            CheckStatus::Warning,
+            format!("{} expires in {} days", tls.cert_file.display(), days_left),
+        )
+    } else {
+        (
+            CheckStatus::Ok,
+            format!(
+                "{} is valid for {} more days",
+                tls.cert_file.display(),
+                days_left
+            ),
+        )
+    }
+}
+
+/// A database opened for checking, with where its copy is if it was copied.
+struct CheckedDb(Db, Option<PathBuf>);
+
+impl CheckedDb {
+    fn cleanup(self) {
+        let CheckedDb(db, copy) = self;
+        drop(db);
+        if let Some(copy) = copy {
+            let res = if copy.is_dir() {
+                std::fs::remove_dir_all(&copy)
+            } else {
+                std::fs::remove_file(&copy)
+            };
+            if let Err(err) = res {
+                tracing::warn!("couldn't remove database copy {}: {}", copy.display(), err);
+            }
+        }
+    }
+}
+
+/// Copies the database somewhere temporary, so it can be opened while the
+/// server has it open, and migrations can be tried on it. Returns `None` for
+/// databases that don't live in the database path.
+fn copy_db(db_path: &str) -> std::io::Result<Option<PathBuf>> {
+    let path = Path::new(db_path);
+    if cfg!(all(
+        feature = "postgres",
+        not(feature = "sled"),
+        not(feature = "sqlite")
+    )) || !path.exists()
+    {
+        return Ok(None);
+    }
+    let copy = std::env::temp_dir().join(format!(
+        "scherzo_check_{}",
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos())
+    ));
+    if path.is_dir() {
+        crate::utils::copy_dir_all(path.to_path_buf(), copy.clone())?;
+    } else {
+        std::fs::copy(path, &copy)?;
+    }
+    Ok(Some(copy))
+}
+
+async fn check_db(
+    report: &mut CheckReport,
+    name: &str,
+    db_path: &str,
+    config: &Config,
+) -> Option<CheckedDb> {
+    if !Path::new(db_path).exists() && config.db.postgres_url.is_none() {
+        report.push(
+            name,
+            CheckStatus::Warning,
+            format!("{} doesn't exist, a new database will be created", db_path),
+        );
+        return None;
+    }
+
+    let copy = match copy_db(db_path) {
+        Ok(copy) => copy,
+        Err(err) => {
+            report.push(
+                name,
+                CheckStatus::Failed,
+                format!("couldn't copy {}: {}", db_path, err),
+            );
+            return None;
+        }
+    };
+    let open_path = copy
+        .as_ref()
+        .map_or_else(|| db_path.to_string(), |copy| copy.display().to_string());
+    let db = match db::open_database(open_path, config.db.clone()).await {
+        Ok(db) => CheckedDb(db, copy),
+        Err(err) => {
+            report.push(
+                name,
+                CheckStatus::Failed,
+                format!("couldn't open {}: {}", db_path, err),
+            );
+            return None;
+        }
+    };
+
+    let (version, needs_migration) = match get_db_version(&db.0).await {
+        Ok(version) => version,
+        Err(err) => {
+            report.push(
+                name,
+                CheckStatus::Failed,
+                format!("couldn't get database version: {}", err),
+            );
+            return Some(db);
+        }
+    };
+    if !needs_migration {
+        report.push(
+            name,
+            CheckStatus::Ok,
+            format!("opened, at version {} with no pending migrations", version),
+        );
+        return Some(db);
+    }
+
+    let pending = MIGRATIONS.len() - version;
+    if db.1.is_none() {
+        report.push(
+            name,
+            CheckStatus::Warning,
+            format!(
+                "{} migrations are pending, they can't be tried on a copy of this database",
+                pending
+            ),
+        );
+        return Some(db);
+    }
+    match apply_migrations(&db.0, version).await {
+        Ok(()) => report.push(
+            name,
+            CheckStatus::Ok,
+            format!(
+                "opened, {} pending migrations applied cleanly to a copy",
+                pending
+            ),
+        ),
+        Err(err) => report.push(
+            name,
+            CheckStatus::Failed,
+            format!("migrating a copy from version {} failed: {}", version, err),
+        ),
+    }
+    Some(db)
+}
+
+/// Tries to reach the hosts in the allow list, and the hosts that were
+/// federated with before.
+async fn check_federation(
+    report: &mut CheckReport,
+    http: &HttpClient,
+    name: &str,
+    db: &Db,
+    config: &Config,
+) {
+    let mut hosts = config
+        .federation
+        .as_ref()
+        .map_or_else(Vec::new, |federation| federation.host_allow_list.clone());
+    if let Ok(sync_tree) = db.open_tree(b"sync").await {
+        for (key, _) in sync_tree.scan_prefix(HOST_PREFIX).await.flatten() {
+            let host = String::from_utf8_lossy(&key[HOST_PREFIX.len()..]).into_owned();
+            if !hosts.contains(&host) {
+                hosts.push(host);
+            }
+        }
+    }
+
+    if hosts.is_empty() {
+        report.push(name, CheckStatus::Skipped, "no hosts federated with yet");
+        return;
+    }
+    for host in hosts.into_iter().take(MAX_HOSTS_TO_REACH) {
+        let url = format!("{}/_harmony/about", host.trim_end_matches('/'));
+        let (status, message) = match reach(http, &url).await {
+            Ok(message) => (CheckStatus::Ok, message),
+            // other hosts being down doesn't stop the server from starting
+            Err(err) => (CheckStatus::Warning, err),
+        };
+        report.push(format!("{}: {}", name, host), status, message);
+    }
+}
+
+/// Makes a request to a URL, succeeding if there is any response.
+async fn reach(http: &HttpClient, url: &str) -> Result<String, String> {
+    let uri: Uri = url
+        .parse()
+        .map_err(|err| format!("invalid url {}: {}", url, err))?;
+    let request = http::Request::builder()
+        .method(http::Method::GET)
+        .uri(uri)
+        .body(Body::empty())
+        .map_err(|err| format!("invalid request to {}: {}", url, err))?;
+    match tokio::time::timeout(OUTBOUND_TIMEOUT, http.request(request)).await {
+        Ok(Ok(response)) => Ok(format!("reachable, responded with {}", response.status())),
+        Ok(Err(err)) => Err(format!("couldn't reach {}: {}", url, err)),
+        Err(_) => Err(format!(
+            "couldn't reach {} in {} seconds",
+            url,
+            OUTBOUND_TIMEOUT.as_secs()
+        )),
+    }
+}
@@ -0,0 +1,192 @@
+//! Exporting and importing the whole database to and from a single file,
+//! independent of the DB backend.
+//!
+//! The archive starts with [`MAGIC`], followed by the format version and the
+//! database version as big endian `u32`s. Then, for every tree:
+//! - the length of the tree name as a `u8`, followed by the name,
+//! - the number of entries as a big endian `u64`,
+//! - and every entry, as the length of the key as a big endian `u32`, the key,
+//! the length of the value as a big endian `u32` and the value.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use super::{migration::get_db_version, Batch, Db, DbError, DbResult, TREES};
+
+pub const MAGIC: &[u8] = b"SCHERZODUMP";
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Number of entries to insert per batch while importing.
+const IMPORT_BATCH_SIZE: usize = 1000;
+
+/// Number of entries in every tree that was exported or imported.
+#[derive(Debug, Default, Clone)]
+pub struct DumpStats {
+    pub trees: Vec<(String, u64)>,
+}
+
+pub(super) fn io_err(err: io::Error) -> DbError {
+    DbError {
+        inner: Box::new(err),
+    }
+}
+
+fn invalid_data(msg: String) -> DbError {
+    io_err(io::Error::new(io::ErrorKind::InvalidData, msg))
+}
+
+/// Writes every tree of the database to an archive at `path`. The file must not exist.
+pub async fn export(db: &Db, path: &Path) -> DbResult<DumpStats> {
+    let file = File::options()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(io_err)?;
+    let mut writer = BufWriter::new(file);
+    let stats = write_archive(db, &mut writer).await?;
+    writer.flush().map_err(io_err)?;
+
+    Ok(stats)
+}
+
+/// Writes every tree of the database to `writer`, in the archive format.
+pub async fn write_archive(db: &Db, writer: &mut impl Write) -> DbResult<DumpStats> {
+    let (db_version, _) = get_db_version(db).await?;
+
+    writer.write_all(MAGIC).map_err(io_err)?;
+    writer
+        .write_all(&FORMAT_VERSION.to_be_bytes())
+        .map_err(io_err)?;
+    writer
+        .write_all(&(db_version as u32).to_be_bytes())
+        .map_err(io_err)?;
+
+    let mut stats = DumpStats::default();
+    for name in TREES {
+        let tree = db.open_tree(name).await?;
+        let entries = tree.iter().await.collect::<DbResult<Vec<_>>>()?;
+
+        writer.write_all(&[name.len() as u8]).map_err(io_err)?;
+        writer.write_all(name).map_err(io_err)?;
+        writer
+            .write_all(&(entries.len() as u64).to_be_bytes())
+            .map_err(io_err)?;
+        for (key, value) in &entries {
+            write_bytes(writer, key).map_err(io_err)?;
+            write_bytes(writer, value).map_err(io_err)?;
+        }
+
+        stats.trees.push((
+            String::from_utf8_lossy(name).into_owned(),
+            entries.len() as u64,
+        ));
+    }
+
+    Ok(stats)
+}
+
+/// Reads an archive at `path` into the database. Existing entries with the same
+/// keys are overwritten, others are left as is. The archive must have been
+/// exported from a database with the same version.
+pub async fn import(db: &Db, path: &Path) -> DbResult<DumpStats> {
+    let file = File::open(path).map_err(io_err)?;
+    let mut reader = BufReader::new(file);
+    let dump_db_version = read_header(&mut reader)?;
+    let (db_version, _) = get_db_version(db).await?;
+    if dump_db_version != db_version {
+        return Err(invalid_data(format!(
+            "dump is of database version {}, but the database is at version {}",
+            dump_db_version, db_version
+        )));
+    }
+
+    read_trees(db, &mut reader).await
+}
+
+/// Reads the start of an archive, returning the database version it was
+/// exported from.
+pub fn read_header(reader: &mut impl Read) -> DbResult<usize> {
+    let mut magic = [0; MAGIC.len()];
+    reader.read_exact(&mut magic).map_err(io_err)?;
+    if magic != MAGIC {
+        return Err(invalid_data("not a scherzo database dump".to_string()));
+    }
+    let format_version = read_u32(reader).map_err(io_err)?;
+    if format_version != FORMAT_VERSION {
+        return Err(invalid_data(format!(
+            "unsupported dump format version {}",
+            format_version
+        )));
+    }
+    read_u32(reader)
+        .map(|version| version as usize)
+        .map_err(io_err)
+}
+
+/// Reads the trees of an archive, after its header, into the database.
+pub async fn read_trees(db: &Db, reader: &mut impl Read) -> DbResult<DumpStats> {
+    let mut stats = DumpStats::default();
+    for _ in 0..TREES.len() {
+        let mut name_len = [0; 1];
+        match reader.read_exact(&mut name_len) {
+            // dumps made before a tree was added don't have it
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            res => res.map_err(io_err)?,
+        }
+        let mut name = vec![0; name_len[0] as usize];
+        reader.read_exact(&mut name).map_err(io_err)?;
+        if !TREES.contains(&name.as_slice()) {
+            return Err(invalid_data(format!(
+                "unknown tree {}",
+                String::from_utf8_lossy(&name)
+            )));
+        }
+
+        let tree = db.open_tree(&name).await?;
+        let count = read_u64(reader).map_err(io_err)?;
+        let mut batch = Batch::default();
+        for index in 0..count {
+            let key = read_bytes(reader).map_err(io_err)?;
+            let value = read_bytes(reader).map_err(io_err)?;
+            batch.insert(key, value);
+            if (index as usize + 1) % IMPORT_BATCH_SIZE == 0 {
+                tree.apply_batch(std::mem::take(&mut batch)).await?;
+            }
+        }
+        tree.apply_batch(batch).await?;
+
+        stats
+            .trees
+            .push((String::from_utf8_lossy(&name).into_owned(), count));
+    }
+    db.flush().await?;
+
+    Ok(stats)
+}
+
+pub(super) fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    writer.write_all(&(bytes.len() as u32).to_be_bytes())?;
+    writer.write_all(bytes)
+}
+
+pub(super) fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut raw = [0; 4];
+    reader.read_exact(&mut raw)?;
+    Ok(u32::from_be_bytes(raw))
+}
+
+pub fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut raw = [0; 8];
+    reader.read_exact(&mut raw)?;
+    Ok(u64::from_be_bytes(raw))
+}
+
+pub(super) fn read_bytes(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let len = read_u32(reader)? as usize;
+    let mut bytes = vec![0; len];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
@@ -0,0 +1,37 @@
+use super::*;
+
+use db::{
+    chat::{make_banned_member_key, BanInfo},
+    rkyv_ser, Batch,
+};
+
+/// Bans used to only store the time they were made at, this turns them into
+/// [`BanInfo`]s with no reason, made by no one.
+pub(super) fn migrate(db: &Db) -> BoxFuture<'_, DbResult<()>> {
+    const BAN_KEY_LEN: usize = make_banned_member_key(0, 0).len();
+
+    let fut = async move {
+        let chat_tree = db.open_tree(b"chat").await?;
+
+        let mut batch = Batch::default();
+        for res in chat_tree.iter().await {
+            let (key, val) = res?;
+
+            if key.len() == BAN_KEY_LEN && key[8] == 7 {
+                let Ok(banned_at) = <[u8; 8]>::try_from(val) else {
+                    continue;
+                };
+                let ban = BanInfo {
+                    banned_by: 0,
+                    banned_at: u64::from_be_bytes(banned_at),
+                    reason: String::new(),
+                    expires_at: None,
+                };
+                batch.insert(key, rkyv_ser(&ban));
+            }
+        }
+        chat_tree.apply_batch(batch).await
+    };
+
+    Box::pin(fut)
+}
@@ -7,16 +7,22 @@ use crate::db;
 
 use super::{Db, DbResult};
 
+mod add_ban_info;
 mod add_next_msg_ids;
 mod initial_db_version;
+mod move_tokens_to_sessions;
 mod remove_log_chan_id_from_admin_keys;
+mod split_pull_queues;
 
 type Migration = for<'a> fn(&'a Db) -> BoxFuture<'a, DbResult<()>>;
 
-pub const MIGRATIONS: [Migration; 3] = [
+pub const MIGRATIONS: [Migration; 6] = [
     initial_db_version::migrate,
     add_next_msg_ids::migrate,
     remove_log_chan_id_from_admin_keys::migrate,
+    move_tokens_to_sessions::migrate,
+    add_ban_info::migrate,
+    split_pull_queues::migrate,
 ];
 
 pub async fn get_db_version(db: &Db) -> DbResult<(usize, bool)> {
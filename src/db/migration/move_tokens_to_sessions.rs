@@ -0,0 +1,52 @@
+use std::mem::size_of;
+
+use super::*;
+
+use db::{
+    auth::{atime_key, make_session_key, SessionInfo, TOKEN_PREFIX},
+    rkyv_ser, Batch,
+};
+
+/// Moves the single session each user had into the per session keys, so users
+/// can be logged in from multiple devices.
+pub(super) fn migrate(db: &Db) -> BoxFuture<'_, DbResult<()>> {
+    let fut = async move {
+        let auth_tree = db.open_tree(b"auth").await?;
+
+        let mut batch = Batch::default();
+        for res in auth_tree.scan_prefix(TOKEN_PREFIX).await {
+            let (key, token_raw) = res?;
+            let (_, id_raw) = key.split_at(TOKEN_PREFIX.len());
+            if id_raw.len() != size_of::<u64>() {
+                continue;
+            }
+            // Safety: we checked the length above
+            let user_id = u64::from_be_bytes(unsafe { id_raw.try_into().unwrap_unchecked() });
+            let Ok(token) = std::str::from_utf8(&token_raw) else {
+                continue;
+            };
+
+            let atime = auth_tree
+                .get(&atime_key(user_id))
+                .await?
+                .and_then(|raw| Some(u64::from_be_bytes(raw.as_ref().try_into().ok()?)))
+                .unwrap_or(0);
+            let session = SessionInfo {
+                session_id: rand::random(),
+                device: String::new(),
+                created_at: atime,
+                last_used: atime,
+                refreshed_at: atime,
+            };
+
+            batch.insert(make_session_key(user_id, token), rkyv_ser(&session));
+            batch.remove(key);
+            batch.remove(atime_key(user_id));
+        }
+        auth_tree.apply_batch(batch).await?;
+
+        Ok(())
+    };
+
+    Box::pin(fut)
+}
@@ -0,0 +1,39 @@
+use super::*;
+
+use harmony_rust_sdk::api::sync::PullResponse;
+use rkyv::Deserialize;
+
+use db::{
+    rkyv_arch, rkyv_ser,
+    sync::{make_pull_queue_key, HOST_PREFIX},
+    Batch,
+};
+
+/// The events queued for a host used to be stored together under the host
+/// key, and were all removed once pulled. This moves each of them to their
+/// own key, so hosts can pull them a page at a time.
+pub(super) fn migrate(db: &Db) -> BoxFuture<'_, DbResult<()>> {
+    let fut = async move {
+        let sync_tree = db.open_tree(b"sync").await?;
+
+        let mut batch = Batch::default();
+        for res in sync_tree.scan_prefix(HOST_PREFIX).await {
+            let (key, val) = res?;
+            if val.is_empty() {
+                continue;
+            }
+
+            let host = String::from_utf8_lossy(&key[HOST_PREFIX.len()..]).into_owned();
+            let queue: PullResponse = rkyv_arch::<PullResponse>(&val)
+                .deserialize(&mut rkyv::Infallible)
+                .unwrap();
+            for (id, event) in queue.event_queue.iter().enumerate() {
+                batch.insert(make_pull_queue_key(&host, id as u64), rkyv_ser(event));
+            }
+            batch.insert(key, []);
+        }
+        sync_tree.apply_batch(batch).await
+    };
+
+    Box::pin(fut)
+}
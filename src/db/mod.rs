@@ -46,18 +46,34 @@ use rkyv::{
 };
 use tracing::Instrument;
 
+pub mod dump;
 pub mod migration;
+pub mod op_log;
+#[cfg(feature = "postgres")]
+pub mod postgres;
 #[cfg(feature = "sled")]
 pub mod sled;
 #[cfg(feature = "sqlite")]
 pub mod sqlite;
 
+#[cfg(all(feature = "postgres", not(feature = "sled"), not(feature = "sqlite")))]
+pub use self::postgres::shared::*;
 #[cfg(feature = "sled")]
 pub use self::sled::shared::*;
 #[cfg(all(feature = "sqlite", not(feature = "sled")))]
 pub use self::sqlite::shared::*;
 
-pub const TREES: [&[u8]; 6] = [b"auth", b"chat", b"sync", b"version", b"profile", b"emote"];
+pub const TREES: [&[u8]; 9] = [
+    b"auth",
+    b"chat",
+    b"sync",
+    b"version",
+    b"profile",
+    b"emote",
+    b"media",
+    b"audit",
+    b"ratelimit",
+];
 
 pub async fn open_db(db_path: String, db_config: DbConfig) -> Db {
     let span = tracing::info_span!("scherzo::db", path = %db_path);
@@ -94,6 +110,27 @@ impl Batch {
     }
 }
 
+/// How database operations are using the threads they run on, for backends
+/// that run blocking operations on a thread pool.
+#[derive(Debug, Default, Clone, Copy, serde::Serialize)]
+pub struct BlockingPoolStats {
+    /// Maximum number of threads operations can use at once.
+    pub budget: usize,
+    pub in_use: usize,
+    pub operations: u64,
+    /// Operations that had to wait because all threads were in use.
+    pub contended_operations: u64,
+    /// Total time operations spent waiting for a thread, in microseconds.
+    pub total_wait_micros: u64,
+}
+
+/// Number of entries in a tree and their total size, in bytes.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TreeUsage {
+    pub entries: u64,
+    pub bytes: u64,
+}
+
 #[derive(Debug)]
 pub struct DbError {
     pub inner: Box<dyn StdError + Sync + Send>,
@@ -124,6 +161,7 @@ pub mod profile {
 
     pub const USER_PREFIX: &[u8] = b"user_";
     pub const FOREIGN_PREFIX: &[u8] = b"fuser_";
+    pub const ACCOUNT_LINK_CHALLENGE_PREFIX: &[u8] = b"link_challenge_";
 
     pub const fn make_local_to_foreign_user_key(local_id: u64) -> [u8; 15] {
         concat_static(&[FOREIGN_PREFIX, &local_id.to_be_bytes(), &[2]])
@@ -143,14 +181,76 @@ pub mod profile {
         concat_static(&[USER_PREFIX, &user_id.to_be_bytes()])
     }
 
+    pub const fn make_user_note_prefix(author_id: u64) -> [u8; 14] {
+        concat_static(&[&make_user_profile_key(author_id), &[2]])
+    }
+
+    /// Note written by `author_id` about `user_id`, only visible to its author.
+    pub const fn make_user_note_key(author_id: u64, user_id: u64) -> [u8; 22] {
+        concat_static(&[&make_user_note_prefix(author_id), &user_id.to_be_bytes()])
+    }
+
+    /// When the user was last seen online, in seconds since the Unix epoch.
+    pub const fn make_user_last_seen_key(user_id: u64) -> [u8; 14] {
+        concat_static(&[&make_user_profile_key(user_id), &[3]])
+    }
+
+    pub const fn make_user_blocked_prefix(user_id: u64) -> [u8; 14] {
+        concat_static(&[&make_user_profile_key(user_id), &[4]])
+    }
+
+    /// Users blocked by `user_id`, holding the time they were blocked at.
+    pub const fn make_user_blocked_key(user_id: u64, blocked_id: u64) -> [u8; 22] {
+        concat_static(&[
+            &make_user_blocked_prefix(user_id),
+            &blocked_id.to_be_bytes(),
+        ])
+    }
+
+    pub const fn make_user_metadata_prefix(user_id: u64) -> [u8; 14] {
+        concat_static(&[&make_user_profile_key(user_id), &[1]])
+    }
+
     pub fn make_user_metadata_key(user_id: u64, app_id: &str) -> Vec<u8> {
         [
-            make_user_profile_key(user_id).as_ref(),
-            &[1],
+            make_user_metadata_prefix(user_id).as_ref(),
             app_id.as_bytes(),
         ]
         .concat()
     }
+
+    pub const fn make_account_link_prefix(user_id: u64) -> [u8; 14] {
+        concat_static(&[&make_user_profile_key(user_id), &[5]])
+    }
+
+    /// Accounts on other homeservers that `user_id` proved they own.
+    pub fn make_account_link_key(user_id: u64, foreign_id: u64, host: &str) -> Vec<u8> {
+        [
+            make_account_link_prefix(user_id).as_ref(),
+            &foreign_id.to_be_bytes(),
+            host.as_bytes(),
+        ]
+        .concat()
+    }
+
+    /// Accounts on other homeservers that are linked to a local user, holding
+    /// the local user ID.
+    pub fn make_linked_foreign_user_key(foreign_id: u64, host: &str) -> Vec<u8> {
+        [
+            FOREIGN_PREFIX,
+            &[3],
+            &foreign_id.to_be_bytes(),
+            host.as_bytes(),
+        ]
+        .concat()
+    }
+
+    /// Challenges for linking an account on another homeserver, by the hash
+    /// of their secret. They hold when they expire, the user who started
+    /// linking, and the host of the account they are linking.
+    pub fn make_account_link_challenge_key(secret_hash: &[u8]) -> Vec<u8> {
+        [ACCOUNT_LINK_CHALLENGE_PREFIX, secret_hash].concat()
+    }
 }
 
 pub mod emote {
@@ -178,13 +278,48 @@ pub mod emote {
     pub const fn make_equipped_emote_key(user_id: u64, pack_id: u64) -> [u8; 22] {
         concat_static(&[&make_equipped_emote_prefix(user_id), &pack_id.to_be_bytes()])
     }
+
+    /// Key of the order a user wants their equipped packs to be looked up in
+    pub const fn make_emote_pack_priority_key(user_id: u64) -> [u8; 14] {
+        concat_static(&[USER_PREFIX, &user_id.to_be_bytes(), &[8]])
+    }
+
+    pub const EMOTE_ALIAS_PREFIX: &[u8] = b"emotea_";
+
+    pub const fn make_emote_alias_prefix(pack_id: u64) -> [u8; 15] {
+        concat_static(&[EMOTE_ALIAS_PREFIX, &pack_id.to_be_bytes()])
+    }
+
+    pub fn make_emote_alias_key(pack_id: u64, alias: &str) -> Vec<u8> {
+        [EMOTE_ALIAS_PREFIX, &pack_id.to_be_bytes(), alias.as_bytes()].concat()
+    }
+
+    pub const EMOTE_PACK_VERSION_PREFIX: &[u8] = b"emotev_";
+
+    /// Key of the version of a pack, which goes up every time its emotes or aliases change
+    pub const fn make_emote_pack_version_key(pack_id: u64) -> [u8; 15] {
+        concat_static(&[EMOTE_PACK_VERSION_PREFIX, &pack_id.to_be_bytes()])
+    }
 }
 
 pub mod chat {
+    use rkyv::{Archive, Deserialize, Serialize};
+
     use super::concat_static;
 
     pub const INVITE_PREFIX: &[u8] = b"invite_";
     pub const ADMIN_GUILD_KEY: &[u8] = b"admin_guild_key_data";
+    pub const REPORT_PREFIX: &[u8] = b"report_";
+    pub const GUILD_USAGE_PREFIX: &[u8] = b"guild_usage_";
+    pub const INVITE_GRANT_PREFIX: &[u8] = b"invgrant_";
+    pub const INVITE_INFO_PREFIX: &[u8] = b"invinfo_";
+    pub const INVITE_USES_PREFIX: &[u8] = b"invuses_";
+    pub const SCHEDULED_PREFIX: &[u8] = b"scheduled_";
+    pub const STICKY_PREFIX: &[u8] = b"sticky_";
+    pub const BAN_LIST_SUBS_PREFIX: &[u8] = b"banlist_subs_";
+    pub const BAN_EXPIRY_PREFIX: &[u8] = b"ban_expiry_";
+    pub const DISAPPEAR_EXPIRY_PREFIX: &[u8] = b"disappear_expiry_";
+    pub const PUBLIC_GUILD_PREFIX: &[u8] = b"public_guild_";
 
     // perms
 
@@ -232,6 +367,45 @@ pub mod chat {
 
     // message
 
+    pub const fn make_reaction_roles_key(
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+    ) -> [u8; 26] {
+        concat_static(&[
+            &make_chan_key(guild_id, channel_id),
+            &[4],
+            &message_id.to_be_bytes(),
+        ])
+    }
+
+    pub const fn make_gallery_prefix(guild_id: u64, channel_id: u64) -> [u8; 18] {
+        concat_static(&[&make_chan_key(guild_id, channel_id), &[1]])
+    }
+
+    pub const fn make_gallery_entry_key(
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+    ) -> [u8; 26] {
+        concat_static(&[
+            &make_gallery_prefix(guild_id, channel_id),
+            &message_id.to_be_bytes(),
+        ])
+    }
+
+    pub const fn make_chan_gallery_key(guild_id: u64, channel_id: u64) -> [u8; 18] {
+        concat_static(&[&make_chan_key(guild_id, channel_id), &[2]])
+    }
+
+    pub const fn make_stage_key(guild_id: u64, channel_id: u64) -> [u8; 18] {
+        concat_static(&[&make_chan_key(guild_id, channel_id), &[3]])
+    }
+
+    pub const fn make_chan_topic_history_key(guild_id: u64, channel_id: u64) -> [u8; 18] {
+        concat_static(&[&make_chan_key(guild_id, channel_id), &[5]])
+    }
+
     pub const fn make_pinned_msgs_key(guild_id: u64, channel_id: u64) -> [u8; 18] {
         concat_static(&[&make_chan_key(guild_id, channel_id), &[6]])
     }
@@ -240,18 +414,78 @@ pub mod chat {
         concat_static(&[&make_chan_key(guild_id, channel_id), &[7]])
     }
 
+    pub const fn make_quarantine_prefix(guild_id: u64, channel_id: u64) -> [u8; 18] {
+        concat_static(&[&make_chan_key(guild_id, channel_id), &[10]])
+    }
+
+    pub const fn make_quarantine_key(guild_id: u64, channel_id: u64, message_id: u64) -> [u8; 26] {
+        concat_static(&[
+            &make_quarantine_prefix(guild_id, channel_id),
+            &message_id.to_be_bytes(),
+        ])
+    }
+
+    pub const fn make_msg_acks_prefix(guild_id: u64, channel_id: u64) -> [u8; 18] {
+        concat_static(&[&make_chan_key(guild_id, channel_id), &[11]])
+    }
+
+    /// Messages that must be acknowledged have this key, holding the sorted
+    /// IDs of the users who acknowledged them.
+    pub const fn make_msg_acks_key(guild_id: u64, channel_id: u64, message_id: u64) -> [u8; 26] {
+        concat_static(&[
+            &make_msg_acks_prefix(guild_id, channel_id),
+            &message_id.to_be_bytes(),
+        ])
+    }
+
+    /// Roles whose members auto join a channel. Channels without this are
+    /// joined by everyone.
+    pub const fn make_chan_role_gate_key(guild_id: u64, channel_id: u64) -> [u8; 18] {
+        concat_static(&[&make_chan_key(guild_id, channel_id), &[12]])
+    }
+
+    /// Disappearing message settings of a channel.
+    pub const fn make_chan_disappearing_key(guild_id: u64, channel_id: u64) -> [u8; 18] {
+        concat_static(&[&make_chan_key(guild_id, channel_id), &[13]])
+    }
+
+    pub const fn make_unread_disappearing_prefix(guild_id: u64, channel_id: u64) -> [u8; 18] {
+        concat_static(&[&make_chan_key(guild_id, channel_id), &[14]])
+    }
+
+    /// Messages that disappear after they are read have this key until they
+    /// are read, holding their author ID and TTL as two u64s.
+    pub const fn make_unread_disappearing_key(
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+    ) -> [u8; 26] {
+        concat_static(&[
+            &make_unread_disappearing_prefix(guild_id, channel_id),
+            &message_id.to_be_bytes(),
+        ])
+    }
+
+    pub const fn make_channel_perms_prefix(guild_id: u64, channel_id: u64) -> [u8; 18] {
+        concat_static(&[&make_chan_key(guild_id, channel_id), &[8]])
+    }
+
     pub const fn make_role_channel_perms_prefix(
         guild_id: u64,
         channel_id: u64,
         role_id: u64,
     ) -> [u8; 26] {
         concat_static(&[
-            &make_chan_key(guild_id, channel_id),
-            &[8],
+            &make_channel_perms_prefix(guild_id, channel_id),
             &role_id.to_be_bytes(),
         ])
     }
 
+    /// Channels whose permissions are synced with their category have this key.
+    pub const fn make_chan_perm_sync_key(guild_id: u64, channel_id: u64) -> [u8; 18] {
+        concat_static(&[&make_chan_key(guild_id, channel_id), &[15]])
+    }
+
     pub const fn make_msg_prefix(guild_id: u64, channel_id: u64) -> [u8; 18] {
         concat_static(&[&make_chan_key(guild_id, channel_id), &[9]])
     }
@@ -279,10 +513,35 @@ pub mod chat {
         .concat()
     }
 
+    /// Threads are stored under the message they were started from, so they
+    /// are deleted with it. The thread itself is stored at the prefix, and its
+    /// messages after it.
+    pub const fn make_thread_prefix(guild_id: u64, channel_id: u64, message_id: u64) -> [u8; 27] {
+        concat_static(&[&make_msg_key(guild_id, channel_id, message_id), &[1]])
+    }
+
+    pub const fn make_thread_msg_key(
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+        thread_message_id: u64,
+    ) -> [u8; 35] {
+        concat_static(&[
+            &make_thread_prefix(guild_id, channel_id, message_id),
+            &thread_message_id.to_be_bytes(),
+        ])
+    }
+
     // message
 
     // member
 
+    /// Ban list sources that banned a user, only there for users banned by a
+    /// ban list. Longer than ban keys so it's not mistaken for one when listing bans.
+    pub const fn make_ban_provenance_key(guild_id: u64, user_id: u64) -> [u8; 18] {
+        concat_static(&[&make_banned_member_key(guild_id, user_id), &[1]])
+    }
+
     pub const fn make_member_key(guild_id: u64, user_id: u64) -> [u8; 17] {
         concat_static(&[&make_guild_mem_prefix(guild_id), &user_id.to_be_bytes()])
     }
@@ -330,6 +589,75 @@ pub mod chat {
         concat_static(&[&guild_id.to_be_bytes(), &[1, 1]])
     }
 
+    pub const fn make_guild_theme_key(guild_id: u64) -> [u8; 10] {
+        concat_static(&[&guild_id.to_be_bytes(), &[1, 4]])
+    }
+
+    pub const fn make_guild_screening_key(guild_id: u64) -> [u8; 10] {
+        concat_static(&[&guild_id.to_be_bytes(), &[1, 5]])
+    }
+
+    pub const fn make_screening_application_prefix(guild_id: u64) -> [u8; 10] {
+        concat_static(&[&guild_id.to_be_bytes(), &[1, 6]])
+    }
+
+    pub const fn make_screening_application_key(guild_id: u64, user_id: u64) -> [u8; 18] {
+        concat_static(&[
+            &make_screening_application_prefix(guild_id),
+            &user_id.to_be_bytes(),
+        ])
+    }
+
+    pub const fn make_guild_link_scanning_opt_out_key(guild_id: u64) -> [u8; 10] {
+        concat_static(&[&guild_id.to_be_bytes(), &[1, 7]])
+    }
+
+    pub const fn make_guild_retention_key(guild_id: u64) -> [u8; 10] {
+        concat_static(&[&guild_id.to_be_bytes(), &[1, 9]])
+    }
+
+    pub const fn make_guild_widget_key(guild_id: u64) -> [u8; 10] {
+        concat_static(&[&guild_id.to_be_bytes(), &[1, 12]])
+    }
+
+    pub const fn make_guild_text_macros_key(guild_id: u64) -> [u8; 10] {
+        concat_static(&[&guild_id.to_be_bytes(), &[1, 13]])
+    }
+
+    pub const fn make_guild_emoji_key(guild_id: u64) -> [u8; 10] {
+        concat_static(&[&guild_id.to_be_bytes(), &[1, 14]])
+    }
+
+    pub const fn make_guild_integrations_key(guild_id: u64) -> [u8; 10] {
+        concat_static(&[&guild_id.to_be_bytes(), &[1, 15]])
+    }
+
+    pub const fn make_guild_locale_key(guild_id: u64) -> [u8; 10] {
+        concat_static(&[&guild_id.to_be_bytes(), &[1, 16]])
+    }
+
+    /// Channels gated by a role, so that role changes only look at the
+    /// channels of the changed roles.
+    pub const fn make_role_gated_channels_key(guild_id: u64, role_id: u64) -> [u8; 18] {
+        concat_static(&[&guild_id.to_be_bytes(), &[1, 10], &role_id.to_be_bytes()])
+    }
+
+    /// Role gated channels a member auto joined.
+    pub const fn make_member_gated_channels_key(guild_id: u64, user_id: u64) -> [u8; 18] {
+        concat_static(&[&guild_id.to_be_bytes(), &[1, 11], &user_id.to_be_bytes()])
+    }
+
+    pub const fn make_role_member_cap_prefix(guild_id: u64) -> [u8; 10] {
+        concat_static(&[&guild_id.to_be_bytes(), &[1, 8]])
+    }
+
+    pub const fn make_role_member_cap_key(guild_id: u64, role_id: u64) -> [u8; 18] {
+        concat_static(&[
+            &make_role_member_cap_prefix(guild_id),
+            &role_id.to_be_bytes(),
+        ])
+    }
+
     pub fn make_guild_list_key(user_id: u64, guild_id: u64, host: &str) -> Vec<u8> {
         [
             make_guild_list_key_prefix(user_id).as_ref(),
@@ -348,14 +676,419 @@ pub mod chat {
     pub fn make_invite_key(name: &str) -> Vec<u8> {
         [INVITE_PREFIX, name.as_bytes()].concat()
     }
+
+    pub fn make_invite_grant_key(name: &str) -> Vec<u8> {
+        [INVITE_GRANT_PREFIX, name.as_bytes()].concat()
+    }
+
+    pub fn make_invite_info_key(name: &str) -> Vec<u8> {
+        [INVITE_INFO_PREFIX, name.as_bytes()].concat()
+    }
+
+    pub fn make_invite_uses_key(name: &str) -> Vec<u8> {
+        [INVITE_USES_PREFIX, name.as_bytes()].concat()
+    }
+
+    pub const fn make_report_key(report_id: u64) -> [u8; 15] {
+        concat_static(&[REPORT_PREFIX, &report_id.to_be_bytes()])
+    }
+
+    pub const fn make_guild_usage_key(guild_id: u64) -> [u8; 20] {
+        concat_static(&[GUILD_USAGE_PREFIX, &guild_id.to_be_bytes()])
+    }
+
+    /// Public guilds are kept under their own prefix instead of under the
+    /// guild, so they can all be found for the guild directory.
+    pub const fn make_public_guild_key(guild_id: u64) -> [u8; 21] {
+        concat_static(&[PUBLIC_GUILD_PREFIX, &guild_id.to_be_bytes()])
+    }
+
+    /// Scheduled messages are keyed by their send time first, so that
+    /// due messages can be found with a range scan. [tag:scheduled_msg_key]
+    pub const fn make_scheduled_msg_key(scheduled_for: u64, scheduled_id: u64) -> [u8; 26] {
+        concat_static(&[
+            SCHEDULED_PREFIX,
+            &scheduled_for.to_be_bytes(),
+            &scheduled_id.to_be_bytes(),
+        ])
+    }
+
+    /// Ban lists are kept under their own prefix instead of under the guild, so
+    /// all subscribed guilds can be found for syncing. [tag:ban_list_subs_key]
+    pub const fn make_ban_list_subs_key(guild_id: u64) -> [u8; 21] {
+        concat_static(&[BAN_LIST_SUBS_PREFIX, &guild_id.to_be_bytes()])
+    }
+
+    /// Expiry times of temporary bans, keyed by time first so that expired
+    /// bans can be found with a range scan. [tag:ban_expiry_key]
+    pub const fn make_ban_expiry_key(expires_at: u64, guild_id: u64, user_id: u64) -> [u8; 35] {
+        concat_static(&[
+            BAN_EXPIRY_PREFIX,
+            &expires_at.to_be_bytes(),
+            &guild_id.to_be_bytes(),
+            &user_id.to_be_bytes(),
+        ])
+    }
+
+    /// Times disappearing messages are deleted at, keyed by time first so
+    /// that expired messages can be found with a range scan. [tag:disappear_expiry_key]
+    pub const fn make_disappear_expiry_key(
+        expires_at: u64,
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+    ) -> [u8; 49] {
+        concat_static(&[
+            DISAPPEAR_EXPIRY_PREFIX,
+            &expires_at.to_be_bytes(),
+            &guild_id.to_be_bytes(),
+            &channel_id.to_be_bytes(),
+            &message_id.to_be_bytes(),
+        ])
+    }
+
+    /// Sticky messages are kept under their own prefix instead of under the
+    /// channel, so they can all be found for rebroadcasting. [tag:sticky_msg_key]
+    pub const fn make_sticky_msg_key(guild_id: u64, channel_id: u64) -> [u8; 23] {
+        concat_static(&[
+            STICKY_PREFIX,
+            &guild_id.to_be_bytes(),
+            &channel_id.to_be_bytes(),
+        ])
+    }
+
+    /// Locale and timezone of a guild, used for the messages the server
+    /// writes in it.
+    #[derive(Debug, Default, Clone, Archive, Serialize, Deserialize)]
+    pub struct GuildLocale {
+        /// BCP 47 language tag, like `en-US`.
+        pub locale: Option<String>,
+        /// IANA timezone name, like `Europe/Istanbul`.
+        pub timezone: Option<String>,
+    }
+
+    /// Theming information of a guild. The protocol guild type has no place
+    /// for it, so it is also put in the guild's metadata for clients.
+    #[derive(Debug, Default, Clone, Archive, Serialize, Deserialize)]
+    pub struct GuildTheme {
+        /// File ID of the banner image.
+        pub banner: Option<String>,
+        /// File ID of the splash image.
+        pub splash: Option<String>,
+        pub theme_color: Option<i32>,
+    }
+
+    /// Settings of the public widget of a guild. Guilds without one don't have
+    /// a widget.
+    #[derive(Debug, Default, Clone, Archive, Serialize, Deserialize)]
+    pub struct WidgetSettings {
+        /// Invite shown on the widget, so people seeing it can join.
+        pub invite_id: Option<String>,
+    }
+
+    /// A canned response of a guild. Sending a message that is only `/<name>`
+    /// sends the text of the macro instead.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    pub struct TextMacro {
+        pub name: String,
+        pub text: String,
+        pub created_by: u64,
+    }
+
+    /// A custom emoji of a guild, which every member can use, unlike emotes
+    /// which come from the emote packs a user equipped.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    pub struct GuildEmoji {
+        pub name: String,
+        pub image_id: String,
+        pub created_by: u64,
+        pub created_at: u64,
+    }
+
+    #[derive(
+        Debug,
+        Clone,
+        Copy,
+        PartialEq,
+        Eq,
+        Archive,
+        Serialize,
+        Deserialize,
+        serde::Serialize,
+        serde::Deserialize,
+    )]
+    #[serde(rename_all = "snake_case")]
+    pub enum IntegrationKind {
+        Webhook,
+        Bot,
+        Bridge,
+        Feed,
+        /// Not registered, ban list subscriptions are listed along with integrations.
+        BanList,
+    }
+
+    /// Something installed in a guild that acts on it from outside, registered
+    /// so admins can see who installed it and uninstall it.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    pub struct GuildIntegration {
+        pub id: u64,
+        pub kind: IntegrationKind,
+        pub name: String,
+        /// The bot user the integration acts as, which is kicked when it is uninstalled.
+        pub bot_user_id: Option<u64>,
+        pub installed_by: u64,
+        /// In seconds since unix epoch.
+        pub installed_at: u64,
+    }
+
+    /// Membership screening of a guild. Users joining a guild with screening
+    /// must acknowledge the rules and answer the questions before they can join.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    pub struct MembershipScreening {
+        pub rules: String,
+        pub questions: Vec<String>,
+        /// Whether to let users in as soon as they acknowledge the rules,
+        /// instead of waiting for a moderator to review their answers.
+        pub auto_accept: bool,
+    }
+
+    /// Answers of a user to a guild's membership screening, waiting for review.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    pub struct ScreeningApplication {
+        pub answers: Vec<String>,
+        /// Time of submission, in seconds since unix epoch.
+        pub submitted_at: u64,
+        /// Role the invite the user applied with gives, given once they are accepted.
+        pub grant_role_id: Option<u64>,
+    }
+
+    /// A change made to the topic of a channel.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    pub struct TopicChange {
+        /// The new topic. Empty if the topic was removed.
+        pub topic: String,
+        pub author_id: u64,
+        /// Time of the change, in seconds since unix epoch.
+        pub changed_at: u64,
+    }
+
+    /// A list of users to ban that guilds can subscribe to.
+    #[derive(
+        Debug,
+        Clone,
+        PartialEq,
+        Eq,
+        Hash,
+        Archive,
+        Serialize,
+        Deserialize,
+        serde::Serialize,
+        serde::Deserialize,
+    )]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    pub enum BanListSource {
+        /// A ban list file from the server config, by name.
+        File { name: String },
+        /// Users banned by another guild on this server.
+        Guild { guild_id: u64 },
+    }
+
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    pub struct BanListSubscription {
+        pub source: BanListSource,
+        pub subscribed_by: u64,
+        /// Time of the subscription, in seconds since unix epoch.
+        pub subscribed_at: u64,
+    }
+
+    /// A message shown at the top of a channel.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    pub struct StickyMessage {
+        pub text: String,
+        pub author_id: u64,
+        /// Time the message was set, in seconds since unix epoch.
+        pub set_at: u64,
+        /// How often the message is broadcast again, in seconds.
+        pub rebroadcast_interval: Option<u64>,
+        /// Time the message was last broadcast, in seconds since unix epoch.
+        pub last_broadcast: u64,
+    }
+
+    /// When the countdown of a disappearing message starts.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Archive, Serialize, Deserialize)]
+    pub enum DisappearAfter {
+        Send,
+        /// Once someone other than the author reads the message.
+        Read,
+    }
+
+    /// Disappearing message settings of a channel. They apply to messages
+    /// sent after they are set.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    pub struct DisappearingMessages {
+        pub after: DisappearAfter,
+        /// How long messages are kept once their countdown starts, in seconds.
+        pub ttl_secs: u64,
+        pub set_by: u64,
+        /// Time the settings were set, in seconds since unix epoch.
+        pub set_at: u64,
+    }
+
+    /// A ban of a user from a guild.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    pub struct BanInfo {
+        /// Moderator who made the ban, 0 if it wasn't made by a guild member.
+        pub banned_by: u64,
+        /// Time of the ban, in seconds since unix epoch.
+        pub banned_at: u64,
+        pub reason: String,
+        /// Time the ban is lifted at, in seconds since unix epoch. Not set for
+        /// permanent bans.
+        pub expires_at: Option<u64>,
+    }
+
+    /// State of a stage channel. Only speakers can talk in a stage channel,
+    /// everyone else is a listener.
+    #[derive(Debug, Default, Clone, Archive, Serialize, Deserialize)]
+    pub struct StageState {
+        pub speakers: Vec<u64>,
+        /// Listeners who requested to become a speaker.
+        pub raised_hands: Vec<u64>,
+        /// Listeners who were invited to become a speaker.
+        pub invited: Vec<u64>,
+    }
+
+    /// A thread started from a message.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    pub struct Thread {
+        pub name: String,
+        pub created_by: u64,
+        pub created_at: u64,
+        /// ID the next message sent in the thread gets.
+        pub next_message_id: u64,
+        pub message_count: u64,
+        pub last_message_at: Option<u64>,
+        /// Users that get the events of the thread.
+        pub subscribers: Vec<u64>,
+    }
+
+    /// A message held back for review by the guild's moderators. The message
+    /// itself is stored as usual, but is only visible to moderators and its
+    /// author while this exists.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    pub struct QuarantinedMessage {
+        pub reason: String,
+        /// Time of the quarantine, in seconds since unix epoch.
+        pub quarantined_at: u64,
+    }
+
+    /// State of a report in the homeserver admins' queue.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Archive, Serialize, Deserialize)]
+    pub enum ReportState {
+        Open,
+        Reviewing,
+        Resolved,
+    }
+
+    /// A report about a message, escalated by guild moderators to the homeserver admins.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    pub struct EscalatedReport {
+        pub guild_id: u64,
+        pub channel_id: u64,
+        pub message_id: u64,
+        /// Author of the reported message.
+        pub author_id: u64,
+        /// Text of the reported message at the time of the escalation,
+        /// kept in case the message gets deleted.
+        pub content: String,
+        pub reason: String,
+        /// User who escalated the report, 0 if it was created by the server itself.
+        pub escalated_by: u64,
+        /// Time of the escalation, in seconds since unix epoch.
+        pub escalated_at: u64,
+        pub state: ReportState,
+        /// Admin who is handling the report.
+        pub assignee: Option<u64>,
+        pub resolution_note: Option<String>,
+        /// Time of the last change, in seconds since unix epoch.
+        pub updated_at: u64,
+    }
+
+    /// Resources used by a guild. These are kept under a global prefix so that
+    /// all guilds can be listed by usage.
+    #[derive(Debug, Default, Clone, Copy, Archive, Serialize, Deserialize)]
+    pub struct GuildUsage {
+        pub message_count: u64,
+        /// Total size of attachments and photos in messages, in bytes.
+        pub media_bytes: u64,
+        /// Number of emote reactions on messages.
+        pub emote_count: u64,
+    }
+
+    /// Extra data of an invite that doesn't fit in the protocol invite type.
+    /// Kept under its own prefix so that invite scans aren't affected.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    pub struct InviteRoleGrant {
+        /// Role given to users who join with the invite.
+        pub role_id: u64,
+        /// User who attached the role to the invite.
+        pub granted_by: u64,
+    }
+
+    /// Who made an invite and until when it can be used. Kept under its own
+    /// prefix like [`InviteRoleGrant`]. Invites made before this was added
+    /// don't have one.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    pub struct InviteInfo {
+        pub created_by: u64,
+        /// In seconds since unix epoch.
+        pub created_at: u64,
+        /// Time the invite stops working at, in seconds since unix epoch. Not
+        /// set if the invite doesn't expire.
+        pub expires_at: Option<u64>,
+    }
+
+    /// A user joining (or applying to join) a guild with an invite.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    pub struct InviteUse {
+        pub user_id: u64,
+        /// In seconds since unix epoch.
+        pub used_at: u64,
+    }
+
+    /// A text message that will be sent by its author at a later time.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    pub struct ScheduledMessage {
+        pub guild_id: u64,
+        pub channel_id: u64,
+        pub author_id: u64,
+        pub text: String,
+        pub in_reply_to: Option<u64>,
+    }
+
+    /// Binds reacting with an emote on a message to getting a role.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    pub struct ReactionRole {
+        pub image_id: String,
+        pub role_id: u64,
+    }
 }
 
 pub mod auth {
+    use rkyv::{Archive, Deserialize, Serialize};
+
     use super::concat_static;
 
     pub const ATIME_PREFIX: &[u8] = b"atime_";
     pub const TOKEN_PREFIX: &[u8] = b"token_";
     pub const REG_TOKEN_PREFIX: &[u8] = b"reg_token_";
+    pub const SESSION_PREFIX: &[u8] = b"session_";
+    pub const OIDC_SUBJECT_PREFIX: &[u8] = b"oidc_";
+    pub const PASSWORD_RESET_PREFIX: &[u8] = b"pass_reset_";
+    pub const REFRESH_TOKEN_PREFIX: &[u8] = b"rtoken_";
+    pub const SESSION_REFRESH_TOKEN_PREFIX: &[u8] = b"rsession_";
+    pub const LOGIN_FAILURES_PREFIX: &[u8] = b"lfail_";
+    pub const IP_BAN_PREFIX: &[u8] = b"ipban_";
 
     pub const fn token_key(user_id: u64) -> [u8; 14] {
         concat_static(&[TOKEN_PREFIX, &user_id.to_be_bytes()])
@@ -368,14 +1101,430 @@ pub mod auth {
     pub fn reg_token_key(token_hashed: &[u8]) -> Vec<u8> {
         [REG_TOKEN_PREFIX, token_hashed].concat()
     }
+
+    pub fn password_reset_key(token_hashed: &[u8]) -> Vec<u8> {
+        [PASSWORD_RESET_PREFIX, token_hashed].concat()
+    }
+
+    pub fn refresh_token_key(token_hashed: &[u8]) -> Vec<u8> {
+        [REFRESH_TOKEN_PREFIX, token_hashed].concat()
+    }
+
+    pub const fn make_user_refresh_tokens_prefix(user_id: u64) -> [u8; 17] {
+        concat_static(&[SESSION_REFRESH_TOKEN_PREFIX, &user_id.to_be_bytes()])
+    }
+
+    /// Value is the hashed refresh token of the session.
+    pub const fn make_session_refresh_token_key(user_id: u64, session_id: u64) -> [u8; 25] {
+        concat_static(&[
+            SESSION_REFRESH_TOKEN_PREFIX,
+            &user_id.to_be_bytes(),
+            &session_id.to_be_bytes(),
+        ])
+    }
+
+    /// Keyed by email instead of user ID, so that accounts that don't exist
+    /// are treated the same as ones that do.
+    pub fn make_account_login_failures_key(email: &str) -> Vec<u8> {
+        [LOGIN_FAILURES_PREFIX, &[0], email.as_bytes()].concat()
+    }
+
+    pub fn make_ip_login_failures_key(ip: &str) -> Vec<u8> {
+        [LOGIN_FAILURES_PREFIX, &[1], ip.as_bytes()].concat()
+    }
+
+    /// Failed logins to an account from one IP, so lockouts from one IP
+    /// don't lock the owner of the account out too.
+    pub fn make_account_ip_login_failures_key(email: &str, ip: &str) -> Vec<u8> {
+        [
+            LOGIN_FAILURES_PREFIX,
+            &[2],
+            ip.as_bytes(),
+            &[0],
+            email.as_bytes(),
+        ]
+        .concat()
+    }
+
+    /// Keyed by the banned network, like `192.0.2.0/24`.
+    pub fn make_ip_ban_key(network: &str) -> Vec<u8> {
+        [IP_BAN_PREFIX, network.as_bytes()].concat()
+    }
+
+    pub const fn make_user_sessions_prefix(user_id: u64) -> [u8; 16] {
+        concat_static(&[SESSION_PREFIX, &user_id.to_be_bytes()])
+    }
+
+    /// Value is the ID of the local user linked to the OpenID Connect account.
+    pub fn make_oidc_subject_key(issuer: &str, subject: &str) -> Vec<u8> {
+        [
+            OIDC_SUBJECT_PREFIX,
+            issuer.as_bytes(),
+            &[0],
+            subject.as_bytes(),
+        ]
+        .concat()
+    }
+
+    // [tag:session_key]
+    pub fn make_session_key(user_id: u64, token: &str) -> Vec<u8> {
+        [
+            make_user_sessions_prefix(user_id).as_ref(),
+            token.as_bytes(),
+        ]
+        .concat()
+    }
+
+    /// Splits a session key into the user ID and the session token.
+    pub fn split_session_key(key: &[u8]) -> Option<(u64, &str)> {
+        let rest = key.strip_prefix(SESSION_PREFIX)?;
+        let (id_raw, token_raw) = rest.split_at(rest.len().min(8));
+        let user_id = u64::from_be_bytes(id_raw.try_into().ok()?);
+        let token = std::str::from_utf8(token_raw).ok()?;
+        Some((user_id, token))
+    }
+
+    /// A password reset token that was sent to a user by email.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    pub struct PasswordReset {
+        pub user_id: u64,
+        /// In seconds since unix epoch.
+        pub expires_at: u64,
+    }
+
+    /// A refresh token, which can be used to get a new token for a session
+    /// once its token expired.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    pub struct RefreshToken {
+        pub user_id: u64,
+        pub session_id: u64,
+        /// In seconds since unix epoch.
+        pub created_at: u64,
+        /// In seconds since unix epoch.
+        pub expires_at: u64,
+    }
+
+    /// Recent failed logins to an account or from an IP.
+    #[derive(Debug, Clone, Default, Archive, Serialize, Deserialize)]
+    pub struct LoginFailures {
+        pub count: u32,
+        /// In seconds since unix epoch.
+        pub last_failed_at: u64,
+        /// Logins aren't allowed until this time, in seconds since unix epoch.
+        /// 0 if they aren't locked out.
+        pub locked_until: u64,
+    }
+
+    /// A network that isn't allowed to send requests.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    pub struct IpBan {
+        pub reason: String,
+        /// In seconds since unix epoch.
+        pub banned_at: u64,
+        /// In seconds since unix epoch. 0 if the ban doesn't expire.
+        pub expires_at: u64,
+    }
+
+    /// A session a user logged in with.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    pub struct SessionInfo {
+        /// ID used to refer to the session without exposing its token.
+        pub session_id: u64,
+        /// The user agent of the client that created the session.
+        pub device: String,
+        /// In seconds since unix epoch.
+        pub created_at: u64,
+        /// In seconds since unix epoch.
+        pub last_used: u64,
+        /// When the token of the session was last replaced, or when the
+        /// session was created if it never was. In seconds since unix epoch.
+        pub refreshed_at: u64,
+    }
 }
 
 pub mod sync {
+    use harmony_rust_sdk::api::sync::Event;
+    use rkyv::{Archive, Deserialize, Serialize};
+
+    use super::concat_static;
+
     pub const HOST_PREFIX: &[u8] = b"host_";
+    pub const TRUSTED_HOST_PREFIX: &[u8] = b"trusted_";
+    pub const ALLOWED_HOST_PREFIX: &[u8] = b"fedallow_";
+    pub const BLOCKED_HOST_PREFIX: &[u8] = b"fedblock_";
+    pub const FEDERATION_INVITE_PREFIX: &[u8] = b"fedinvite_";
+    pub const EVENT_LOG_PREFIX: &[u8] = b"evlog_";
+    pub const QUEUED_SINCE_PREFIX: &[u8] = b"queuedsince_";
+    pub const DEAD_LETTER_PREFIX: &[u8] = b"deadletter_";
+    pub const OUTBOX_PREFIX: &[u8] = b"outbox_";
+    pub const PULL_QUEUE_PREFIX: &[u8] = b"pullq_";
+
+    /// Keys are ordered by the time the event was received at, `id` is only
+    /// there to keep events received at the same second apart.
+    // [tag:event_log_key]
+    pub fn make_event_log_key(received_at: u64, id: u64) -> Vec<u8> {
+        [
+            EVENT_LOG_PREFIX,
+            received_at.to_be_bytes().as_ref(),
+            id.to_be_bytes().as_ref(),
+        ]
+        .concat()
+    }
+
+    /// A federation event received from another homeserver.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    pub struct LoggedEvent {
+        pub host: String,
+        pub event: Event,
+    }
+
+    /// Events that couldn't be delivered to a host, kept so they can be
+    /// inspected and requeued by an admin.
+    pub const fn make_dead_letter_key(id: u64) -> [u8; 19] {
+        concat_static(&[DEAD_LETTER_PREFIX, &id.to_be_bytes()])
+    }
+
+    /// An event that couldn't be delivered to a host.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    pub struct DeadLetter {
+        pub host: String,
+        /// Why the event couldn't be delivered.
+        pub reason: String,
+        /// In seconds since unix epoch.
+        pub failed_at: u64,
+        pub event: Event,
+    }
+
+    /// Events waiting to be pushed to a host. The host is followed by a zero
+    /// byte, so the prefix of a host never matches another host.
+    pub fn make_outbox_prefix(host: &str) -> Vec<u8> {
+        [OUTBOX_PREFIX, host.as_bytes(), &[0]].concat()
+    }
 
+    /// Keys are ordered by `seq`, which keeps events in the order they were dispatched in.
+    pub fn make_outbox_key(host: &str, seq: u64) -> Vec<u8> {
+        [make_outbox_prefix(host).as_slice(), &seq.to_be_bytes()].concat()
+    }
+
+    /// Events waiting for a host to pull them. Like outbox keys, the host is
+    /// followed by a zero byte.
+    pub fn make_pull_queue_prefix(host: &str) -> Vec<u8> {
+        [PULL_QUEUE_PREFIX, host.as_bytes(), &[0]].concat()
+    }
+
+    /// Keys are ordered by `id`, the sequence number the event had in the
+    /// outbox. Hosts acknowledge pulled events by it.
+    pub fn make_pull_queue_key(host: &str, id: u64) -> Vec<u8> {
+        [make_pull_queue_prefix(host).as_slice(), &id.to_be_bytes()].concat()
+    }
+
+    /// Time the oldest event in the queue of a host was queued at.
+    pub fn make_queued_since_key(host: &str) -> Vec<u8> {
+        [QUEUED_SINCE_PREFIX, host.as_bytes()].concat()
+    }
+
+    /// Marks a host that is federated with, so events are pulled from it.
+    /// Used to hold the whole event queue of the host, before events were
+    /// queued under [`make_pull_queue_key`].
     pub fn make_host_key(host: &str) -> Vec<u8> {
         [HOST_PREFIX, host.as_bytes()].concat()
     }
+
+    pub fn make_trusted_host_key(host: &str) -> Vec<u8> {
+        [TRUSTED_HOST_PREFIX, host.as_bytes()].concat()
+    }
+
+    /// Hosts added to the federation allow list at runtime.
+    pub fn make_allowed_host_key(host: &str) -> Vec<u8> {
+        [ALLOWED_HOST_PREFIX, host.as_bytes()].concat()
+    }
+
+    /// Hosts added to the federation block list at runtime.
+    pub fn make_blocked_host_key(host: &str) -> Vec<u8> {
+        [BLOCKED_HOST_PREFIX, host.as_bytes()].concat()
+    }
+
+    pub fn make_federation_invite_key(hashed_secret: &[u8]) -> Vec<u8> {
+        [FEDERATION_INVITE_PREFIX, hashed_secret].concat()
+    }
+}
+
+pub mod media {
+    use rkyv::{Archive, Deserialize, Serialize};
+
+    pub const THUMBNAIL_PREFIX: &[u8] = b"thumb_";
+
+    pub fn make_thumbnail_prefix(file_id: &str) -> Vec<u8> {
+        [THUMBNAIL_PREFIX, file_id.as_bytes(), b"_"].concat()
+    }
+
+    pub fn make_thumbnail_key(file_id: &str, size: u32) -> Vec<u8> {
+        [
+            make_thumbnail_prefix(file_id).as_slice(),
+            size.to_be_bytes().as_ref(),
+        ]
+        .concat()
+    }
+
+    pub const OWNER_PREFIX: &[u8] = b"owner_";
+    pub const POSTED_PREFIX: &[u8] = b"posted_";
+    /// Secret used to sign share links.
+    pub const SHARE_KEY: &[u8] = b"share_key";
+    pub const REMOTE_MEDIA_PREFIX: &[u8] = b"remote_";
+
+    /// Value is the ID of the user that uploaded the file.
+    pub fn make_owner_key(file_id: &str) -> Vec<u8> {
+        [OWNER_PREFIX, file_id.as_bytes()].concat()
+    }
+
+    /// Value is the ID the media fetched from another homeserver is cached under.
+    pub fn make_remote_media_key(host: &str, file_id: &str) -> Vec<u8> {
+        [
+            REMOTE_MEDIA_PREFIX,
+            host.as_bytes(),
+            b"/",
+            file_id.as_bytes(),
+        ]
+        .concat()
+    }
+
+    pub fn make_posted_prefix(file_id: &str) -> Vec<u8> {
+        [POSTED_PREFIX, file_id.as_bytes(), b"_"].concat()
+    }
+
+    // [tag:media_posted_key]
+    pub fn make_posted_key(file_id: &str, guild_id: u64, channel_id: u64) -> Vec<u8> {
+        [
+            make_posted_prefix(file_id).as_slice(),
+            guild_id.to_be_bytes().as_ref(),
+            channel_id.to_be_bytes().as_ref(),
+        ]
+        .concat()
+    }
+
+    /// A thumbnail that was generated for an uploaded image.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    pub struct ThumbnailInfo {
+        pub width: u32,
+        pub height: u32,
+    }
+}
+
+pub mod audit {
+    use rkyv::{Archive, Deserialize, Serialize};
+
+    use super::{chat::IntegrationKind, concat_static};
+
+    /// Entries are keyed by guild first and then by time, so the log of a guild
+    /// can be read in order with a range scan. [tag:audit_log_key]
+    pub const fn make_audit_log_key(guild_id: u64, created_at: u64, entry_id: u64) -> [u8; 24] {
+        concat_static(&[
+            &guild_id.to_be_bytes(),
+            &created_at.to_be_bytes(),
+            &entry_id.to_be_bytes(),
+        ])
+    }
+
+    /// A permission set on a role, as recorded in the audit log.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize, serde::Serialize)]
+    pub struct AuditPermission {
+        pub matches: String,
+        pub ok: bool,
+    }
+
+    /// A moderation or administrative action taken in a guild.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize, serde::Serialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    pub enum AuditAction {
+        UserKicked {
+            user_id: u64,
+        },
+        UserBanned {
+            user_id: u64,
+        },
+        UserUnbanned {
+            user_id: u64,
+        },
+        RoleCreated {
+            role_id: u64,
+            name: String,
+        },
+        RoleUpdated {
+            role_id: u64,
+        },
+        RoleDeleted {
+            role_id: u64,
+        },
+        UserRolesUpdated {
+            user_id: u64,
+            given_role_ids: Vec<u64>,
+            taken_role_ids: Vec<u64>,
+        },
+        ChannelCreated {
+            channel_id: u64,
+            name: String,
+        },
+        ChannelDeleted {
+            channel_id: u64,
+        },
+        PermissionsUpdated {
+            role_id: u64,
+            channel_id: Option<u64>,
+            permissions: Vec<AuditPermission>,
+        },
+        QuarantinedMessageApproved {
+            channel_id: u64,
+            message_id: u64,
+            author_id: u64,
+        },
+        QuarantinedMessageDeleted {
+            channel_id: u64,
+            message_id: u64,
+            author_id: u64,
+        },
+        MessageEdited {
+            channel_id: u64,
+            message_id: u64,
+            diff: TextDiff,
+        },
+        IntegrationInstalled {
+            integration_id: u64,
+            kind: IntegrationKind,
+            name: String,
+        },
+        IntegrationUninstalled {
+            integration_id: u64,
+            kind: IntegrationKind,
+            name: String,
+        },
+        ScreeningApplicationReviewed {
+            user_id: u64,
+            accepted: bool,
+        },
+    }
+
+    /// What changed in the text of an edited message, as the one range of
+    /// characters that differs between the old and the new text.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize, serde::Serialize)]
+    pub struct TextDiff {
+        /// Truncated SHA3-256 hash of the old text, in hex.
+        pub old_hash: String,
+        /// Offset of the changed range, in characters.
+        pub start: u32,
+        /// Text that was removed from the old text at `start`.
+        pub removed: String,
+        /// Text that was put in its place.
+        pub inserted: String,
+    }
+
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize, serde::Serialize)]
+    pub struct AuditLogEntry {
+        /// User who took the action, 0 if it was taken by homeserver admins or the server itself.
+        pub actor_id: u64,
+        pub action: AuditAction,
+        /// Time of the action, in seconds since unix epoch.
+        pub created_at: u64,
+    }
 }
 
 crate::impl_deser! {
@@ -387,6 +1536,39 @@ crate::impl_deser! {
     role, Role;
     emote, Emote;
     emote_pack, EmotePack;
+    guild_theme, chat::GuildTheme;
+    guild_locale, chat::GuildLocale;
+    widget_settings, chat::WidgetSettings;
+    text_macros, Vec<chat::TextMacro>;
+    guild_emoji, Vec<chat::GuildEmoji>;
+    guild_integrations, Vec<chat::GuildIntegration>;
+    topic_history, Vec<chat::TopicChange>;
+    reaction_roles, Vec<chat::ReactionRole>;
+    stage, chat::StageState;
+    thread, chat::Thread;
+    sticky_message, chat::StickyMessage;
+    disappearing_messages, chat::DisappearingMessages;
+    ban_list_subscriptions, Vec<chat::BanListSubscription>;
+    ban_provenance, Vec<chat::BanListSource>;
+    ban_info, chat::BanInfo;
+    screening, chat::MembershipScreening;
+    screening_application, chat::ScreeningApplication;
+    report, chat::EscalatedReport;
+    guild_usage, chat::GuildUsage;
+    invite_role_grant, chat::InviteRoleGrant;
+    invite_info, chat::InviteInfo;
+    invite_uses, Vec<chat::InviteUse>;
+    scheduled_message, chat::ScheduledMessage;
+    quarantined_message, chat::QuarantinedMessage;
+    audit_log_entry, audit::AuditLogEntry;
+    logged_event, sync::LoggedEvent;
+    dead_letter, sync::DeadLetter;
+    outbox_event, harmony_rust_sdk::api::sync::Event;
+    session, auth::SessionInfo;
+    password_reset, auth::PasswordReset;
+    refresh_token, auth::RefreshToken;
+    login_failures, auth::LoginFailures;
+    ip_ban, auth::IpBan;
 }
 
 pub fn deser_invite_entry_guild_id(data: &[u8]) -> u64 {
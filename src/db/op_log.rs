@@ -0,0 +1,325 @@
+//! Log of committed tree writes, for a standby to replicate.
+//!
+//! Every write is recorded with a sequence number once it is committed. A
+//! batch is one entry, so it is applied all at once on the standby too. Only
+//! the latest `capacity` entries are kept in memory. A standby that falls
+//! further behind, or follows a primary that restarted, has to load a
+//! snapshot again. Standbys tell logs apart by their epoch, which is picked
+//! at random every time the log is enabled.
+//!
+//! While the log is enabled, writes are committed one at a time, so that
+//! they are logged in the order they were committed in.
+//!
+//! Entries are sent to a standby as:
+//! - the epoch, as a big endian `u64`,
+//! - and for every entry, the sequence number as a big endian `u64`, the
+//! length of the tree name as a `u8` followed by the name, and the number of
+//! writes as a big endian `u32`. Every write is the key, then `1` and the
+//! value for inserts, or `0` for removes. Keys and values are prefixed with
+//! their length as a big endian `u32`, like in [`super::dump`].
+
+use std::{
+    collections::VecDeque,
+    future::Future,
+    io::{self, Read, Write},
+    ops::Not,
+    sync::Arc,
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+use tokio::sync::{watch, Mutex as AsyncMutex};
+
+use crate::utils::evec::EVec;
+
+use super::{
+    dump::{io_err, read_bytes, read_u32, read_u64, write_bytes},
+    Batch, DbResult,
+};
+
+/// A write to a key. `None` removes the key.
+pub type KeyWrite = (EVec, Option<EVec>);
+
+#[derive(Debug, Clone)]
+pub struct Op {
+    pub tree: Vec<u8>,
+    pub writes: Vec<KeyWrite>,
+}
+
+impl Op {
+    pub fn into_batch(self) -> Batch {
+        Batch {
+            inserts: self.writes,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OpLog {
+    inner: Arc<OpLogInner>,
+}
+
+#[derive(Debug)]
+struct OpLogInner {
+    state: Mutex<OpLogState>,
+    /// Held while a write is committed and logged.
+    commit_lock: AsyncMutex<()>,
+    /// Sequence number of the latest entry, for standbys waiting for writes.
+    last_seq_tx: watch::Sender<u64>,
+    last_seq_rx: watch::Receiver<u64>,
+}
+
+#[derive(Debug, Default)]
+struct OpLogState {
+    /// Zero while the log is disabled.
+    capacity: usize,
+    epoch: u64,
+    last_seq: u64,
+    ops: VecDeque<(u64, Arc<Op>)>,
+}
+
+impl Default for OpLog {
+    fn default() -> Self {
+        let (last_seq_tx, last_seq_rx) = watch::channel(0);
+        Self {
+            inner: Arc::new(OpLogInner {
+                state: Mutex::new(OpLogState::default()),
+                commit_lock: AsyncMutex::new(()),
+                last_seq_tx,
+                last_seq_rx,
+            }),
+        }
+    }
+}
+
+impl OpLog {
+    /// Starts logging writes, keeping the latest `capacity` of them.
+    pub fn enable(&self, capacity: usize) {
+        let mut state = self.inner.state.lock();
+        state.capacity = capacity.max(1);
+        state.epoch = rand::random();
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.inner.state.lock().capacity > 0
+    }
+
+    /// Gets the epoch of the log, and the sequence number of its latest entry.
+    pub fn position(&self) -> (u64, u64) {
+        let state = self.inner.state.lock();
+        (state.epoch, state.last_seq)
+    }
+
+    /// Commits a write, and logs `writes` if it succeeded. `writes` should
+    /// only be made if the log is enabled.
+    pub(super) async fn commit<T>(
+        &self,
+        tree: &[u8],
+        writes: Option<Vec<KeyWrite>>,
+        commit: impl Future<Output = DbResult<T>>,
+    ) -> DbResult<T> {
+        let writes = match writes {
+            Some(writes) if writes.is_empty().not() => writes,
+            _ => return commit.await,
+        };
+
+        let _guard = self.inner.commit_lock.lock().await;
+        let res = commit.await?;
+        self.push(tree, writes);
+        Ok(res)
+    }
+
+    /// Like [`OpLog::commit`], for a compare and swap. `writes` are only
+    /// logged if the swap happened.
+    pub(super) async fn commit_swap(
+        &self,
+        tree: &[u8],
+        writes: Option<Vec<KeyWrite>>,
+        commit: impl Future<Output = DbResult<bool>>,
+    ) -> DbResult<bool> {
+        let writes = match writes {
+            Some(writes) if writes.is_empty().not() => writes,
+            _ => return commit.await,
+        };
+
+        let _guard = self.inner.commit_lock.lock().await;
+        let swapped = commit.await?;
+        if swapped {
+            self.push(tree, writes);
+        }
+        Ok(swapped)
+    }
+
+    fn push(&self, tree: &[u8], writes: Vec<KeyWrite>) {
+        let seq = {
+            let mut state = self.inner.state.lock();
+            state.last_seq += 1;
+            let seq = state.last_seq;
+            state.ops.push_back((
+                seq,
+                Arc::new(Op {
+                    tree: tree.to_vec(),
+                    writes,
+                }),
+            ));
+            while state.ops.len() > state.capacity {
+                state.ops.pop_front();
+            }
+            seq
+        };
+        let _ = self.inner.last_seq_tx.send(seq);
+    }
+
+    /// Gets at most `max` entries after `after`. Returns `None` if the log
+    /// isn't at `epoch` anymore, or the entries were already dropped.
+    pub fn read(&self, epoch: u64, after: u64, max: usize) -> Option<Vec<(u64, Arc<Op>)>> {
+        let state = self.inner.state.lock();
+        if state.capacity == 0 || state.epoch != epoch || after > state.last_seq {
+            return None;
+        }
+        let first_seq = state
+            .ops
+            .front()
+            .map_or(state.last_seq + 1, |(seq, _)| *seq);
+        if after + 1 < first_seq {
+            return None;
+        }
+        let skip = (after + 1 - first_seq) as usize;
+        Some(state.ops.iter().skip(skip).take(max).cloned().collect())
+    }
+
+    /// Like [`OpLog::read`], but waits for up to `timeout` for new entries if
+    /// there are none yet.
+    pub async fn wait(
+        &self,
+        epoch: u64,
+        after: u64,
+        max: usize,
+        timeout: Duration,
+    ) -> Option<Vec<(u64, Arc<Op>)>> {
+        let mut last_seq = self.inner.last_seq_rx.clone();
+        let ops = self.read(epoch, after, max)?;
+        if ops.is_empty().not() {
+            return Some(ops);
+        }
+        let _ = tokio::time::timeout(timeout, async {
+            while *last_seq.borrow() <= after {
+                if last_seq.changed().await.is_err() {
+                    break;
+                }
+            }
+        })
+        .await;
+        self.read(epoch, after, max)
+    }
+}
+
+/// Encodes entries to send them to a standby.
+pub fn encode_ops(epoch: u64, ops: &[(u64, Arc<Op>)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_ops(&mut out, epoch, ops).expect("writing to a vec can't fail");
+    out
+}
+
+fn write_ops(writer: &mut impl Write, epoch: u64, ops: &[(u64, Arc<Op>)]) -> io::Result<()> {
+    writer.write_all(&epoch.to_be_bytes())?;
+    for (seq, op) in ops {
+        writer.write_all(&seq.to_be_bytes())?;
+        writer.write_all(&[op.tree.len() as u8])?;
+        writer.write_all(&op.tree)?;
+        writer.write_all(&(op.writes.len() as u32).to_be_bytes())?;
+        for (key, value) in &op.writes {
+            write_bytes(writer, key.as_ref())?;
+            match value {
+                Some(value) => {
+                    writer.write_all(&[1])?;
+                    write_bytes(writer, value.as_ref())?;
+                }
+                None => writer.write_all(&[0])?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Decodes entries received from a primary, returning the epoch of its log
+/// and the entries.
+pub fn decode_ops(mut raw: &[u8]) -> DbResult<(u64, Vec<(u64, Op)>)> {
+    let reader = &mut raw;
+    let epoch = read_u64(reader).map_err(io_err)?;
+    let mut ops = Vec::new();
+    while reader.is_empty().not() {
+        let seq = read_u64(reader).map_err(io_err)?;
+        let mut name_len = [0; 1];
+        reader.read_exact(&mut name_len).map_err(io_err)?;
+        let mut tree = vec![0; name_len[0] as usize];
+        reader.read_exact(&mut tree).map_err(io_err)?;
+        let count = read_u32(reader).map_err(io_err)?;
+        let mut writes = Vec::with_capacity(count.min(1024) as usize);
+        for _ in 0..count {
+            let key = read_bytes(reader).map_err(io_err)?;
+            let mut kind = [0; 1];
+            reader.read_exact(&mut kind).map_err(io_err)?;
+            let value = match kind[0] {
+                0 => None,
+                _ => Some(read_bytes(reader).map_err(io_err)?.into()),
+            };
+            writes.push((key.into(), value));
+        }
+        ops.push((seq, Op { tree, writes }));
+    }
+    Ok((epoch, ops))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write(key: &[u8]) -> Vec<KeyWrite> {
+        vec![(key.into(), Some(b"value".as_ref().into()))]
+    }
+
+    #[tokio::test]
+    async fn reads_logged_writes_after_position() {
+        let log = OpLog::default();
+        log.commit(b"chat", Some(write(b"a")), async { Ok(()) })
+            .await
+            .unwrap();
+        // not logged while disabled
+        assert_eq!(log.position().1, 0);
+
+        log.enable(2);
+        let (epoch, _) = log.position();
+        for key in [b"a", b"b", b"c"] {
+            log.commit(b"chat", Some(write(key)), async { Ok(()) })
+                .await
+                .unwrap();
+        }
+        assert_eq!(log.position(), (epoch, 3));
+
+        let ops = log.read(epoch, 1, 10).unwrap();
+        assert_eq!(ops.iter().map(|(seq, _)| *seq).collect::<Vec<_>>(), [2, 3]);
+        assert!(log.read(epoch, 3, 10).unwrap().is_empty());
+        // the first write was dropped, and the epoch must match
+        assert!(log.read(epoch, 0, 10).is_none());
+        assert!(log.read(epoch.wrapping_add(1), 2, 10).is_none());
+    }
+
+    #[test]
+    fn encodes_and_decodes_ops() {
+        let op = Op {
+            tree: b"auth".to_vec(),
+            writes: vec![
+                (b"key".as_ref().into(), Some(b"value".as_ref().into())),
+                (b"gone".as_ref().into(), None),
+            ],
+        };
+        let raw = encode_ops(7, &[(4, Arc::new(op.clone()))]);
+        let (epoch, ops) = decode_ops(&raw).unwrap();
+        assert_eq!(epoch, 7);
+        assert_eq!(ops.len(), 1);
+        assert_eq!(ops[0].0, 4);
+        assert_eq!(ops[0].1.tree, op.tree);
+        assert_eq!(ops[0].1.writes, op.writes);
+    }
+}
@@ -0,0 +1,415 @@
+use sqlx::{postgres::PgConnectOptions, ConnectOptions, PgPool};
+
+use super::{DbError, DbResult};
+
+use crate::config::DbConfig;
+
+pub mod shared {
+    use std::{
+        ops::{Not, RangeInclusive},
+        str::FromStr,
+        time::Duration,
+    };
+
+    use hrpc::exports::futures_util::StreamExt;
+    use itertools::Either;
+    use smol_str::SmolStr;
+    use sqlx::Row;
+
+    use crate::{
+        db::{op_log::OpLog, Batch, BlockingPoolStats, TreeUsage},
+        utils::evec::EVec,
+    };
+
+    use super::*;
+
+    /// Connects to the PostgreSQL database at `postgres_url` in the config.
+    /// If it isn't set, `db_path` is used as the connection URL.
+    pub async fn open_database(db_path: String, db_config: DbConfig) -> DbResult<Db> {
+        let url = db_config.postgres_url.unwrap_or(db_path);
+        let mut conf = PgConnectOptions::from_str(&url)?;
+
+        conf.log_statements(tracing::log::LevelFilter::Trace)
+            .log_slow_statements(tracing::log::LevelFilter::Error, Duration::from_secs(2));
+
+        let pool = PgPool::connect_with(conf).await?;
+
+        Ok(Db {
+            pool,
+            op_log: OpLog::default(),
+        })
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct Db {
+        pool: PgPool,
+        op_log: OpLog,
+    }
+
+    impl Db {
+        pub async fn open_tree(&self, name: &[u8]) -> DbResult<Tree> {
+            let name = std::str::from_utf8(name).map_err(|err| DbError {
+                inner: Box::new(err),
+            })?;
+            // tree names are quoted since some of them (like `version`) are keywords
+            let table = format!("\"tree_{}\"", name);
+
+            let mut conn = self.pool.acquire().await?;
+            sqlx::query(&format!(
+                "CREATE TABLE IF NOT EXISTS {} ( \"key\" BYTEA PRIMARY KEY, \"value\" BYTEA NOT NULL );",
+                table
+            ))
+            .execute(&mut conn)
+            .await?;
+
+            Ok(Tree {
+                pool: self.pool.clone(),
+                name: name.into(),
+                op_log: self.op_log.clone(),
+                get_query: format!("SELECT value FROM {} WHERE key = $1", table).into(),
+                insert_query: format!(
+                    "INSERT INTO {} (key, value) VALUES ($1, $2) ON CONFLICT (key) DO UPDATE SET value = EXCLUDED.value",
+                    table
+                )
+                .into(),
+                remove_query: format!("DELETE FROM {} WHERE key = $1 RETURNING value", table)
+                    .into(),
+                contains_key_query: format!(
+                    "SELECT EXISTS (SELECT value FROM {} WHERE key = $1)",
+                    table
+                )
+                .into(),
+                iter_from_query: format!(
+                    "SELECT key, value FROM {} WHERE key >= $1 ORDER BY key ASC",
+                    table
+                )
+                .into(),
+                iter_query: format!("SELECT key, value FROM {} ORDER BY key ASC", table).into(),
+                usage_query: format!(
+                    "SELECT COUNT(*), COALESCE(SUM(LENGTH(key) + LENGTH(value)), 0)::BIGINT FROM {}",
+                    table
+                )
+                .into(),
+                swap_query: format!("UPDATE {} SET value = $3 WHERE key = $1 AND value = $2", table).into(),
+                swap_remove_query: format!("DELETE FROM {} WHERE key = $1 AND value = $2", table).into(),
+                swap_insert_query: format!("INSERT INTO {} (key, value) VALUES ($1, $2) ON CONFLICT (key) DO NOTHING", table).into(),
+            })
+        }
+
+        pub async fn flush(&self) -> DbResult<()> {
+            Ok(())
+        }
+
+        pub async fn size_on_disk(&self) -> DbResult<u64> {
+            let mut conn = self.pool.acquire().await?;
+
+            let row = sqlx::query("SELECT pg_database_size(current_database())")
+                .fetch_one(&mut conn)
+                .await?;
+
+            Ok(row.get::<i64, _>(0) as u64)
+        }
+
+        /// Reclaims space taken by dead rows.
+        pub async fn compact(&self) -> DbResult<()> {
+            let mut conn = self.pool.acquire().await?;
+
+            sqlx::query("VACUUM").execute(&mut conn).await?;
+
+            Ok(())
+        }
+
+        pub fn compact_reclaims_space(&self) -> bool {
+            true
+        }
+
+        /// sqlx runs queries on its own connection pool, there is no blocking pool.
+        pub fn blocking_pool_stats(&self) -> Option<BlockingPoolStats> {
+            None
+        }
+
+        pub fn op_log(&self) -> &OpLog {
+            &self.op_log
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct Tree {
+        pool: PgPool,
+        name: SmolStr,
+        op_log: OpLog,
+        get_query: SmolStr,
+        insert_query: SmolStr,
+        remove_query: SmolStr,
+        contains_key_query: SmolStr,
+        iter_from_query: SmolStr,
+        iter_query: SmolStr,
+        usage_query: SmolStr,
+        swap_query: SmolStr,
+        swap_remove_query: SmolStr,
+        swap_insert_query: SmolStr,
+    }
+
+    impl Tree {
+        pub async fn get(&self, key: &[u8]) -> DbResult<Option<EVec>> {
+            let mut conn = self.pool.acquire().await?;
+
+            let val = sqlx::query(self.get_query.as_str())
+                .bind(key)
+                .fetch_optional(&mut conn)
+                .await?;
+
+            Ok(val.map(|r| EVec::Owned(r.get::<Vec<u8>, _>(0))))
+        }
+
+        pub async fn insert(&self, key: &[u8], value: impl AsRef<[u8]>) -> DbResult<Option<EVec>> {
+            let writes = self
+                .op_log
+                .is_enabled()
+                .then(|| vec![(key.into(), Some(value.as_ref().into()))]);
+            let commit = async {
+                let mut conn = self.pool.acquire().await?;
+
+                let val = sqlx::query(self.insert_query.as_str())
+                    .bind(key)
+                    .bind(value.as_ref())
+                    .fetch_optional(&mut conn)
+                    .await?;
+
+                Ok::<_, DbError>(val.map(|r| EVec::Owned(r.get::<Vec<u8>, _>(0))))
+            };
+            self.op_log
+                .commit(self.name.as_bytes(), writes, commit)
+                .await
+        }
+
+        pub async fn remove(&self, key: &[u8]) -> DbResult<Option<EVec>> {
+            let writes = self.op_log.is_enabled().then(|| vec![(key.into(), None)]);
+            let commit = async {
+                let mut conn = self.pool.acquire().await?;
+
+                let val = sqlx::query(self.remove_query.as_str())
+                    .bind(key)
+                    .fetch_optional(&mut conn)
+                    .await?;
+
+                Ok::<_, DbError>(val.map(|r| EVec::Owned(r.get::<Vec<u8>, _>(0))))
+            };
+            self.op_log
+                .commit(self.name.as_bytes(), writes, commit)
+                .await
+        }
+
+        pub async fn contains_key(&self, key: &[u8]) -> DbResult<bool> {
+            let mut conn = self.pool.acquire().await?;
+
+            let row = sqlx::query(self.contains_key_query.as_str())
+                .bind(key)
+                .fetch_one(&mut conn)
+                .await?;
+
+            Ok(row.get(0))
+        }
+
+        /// Sets `key` to `new` if it's currently `old`, where `None` means
+        /// the key doesn't exist. Returns whether the swap happened.
+        pub async fn compare_and_swap(
+            &self,
+            key: &[u8],
+            old: Option<&[u8]>,
+            new: Option<&[u8]>,
+        ) -> DbResult<bool> {
+            let writes = self
+                .op_log
+                .is_enabled()
+                .then(|| vec![(key.into(), new.map(Into::into))]);
+            let commit = async {
+                let mut conn = self.pool.acquire().await?;
+
+                // every case is a single statement, so it is atomic on its own
+                let changed = match (old, new) {
+                    (Some(old), Some(new)) => sqlx::query(self.swap_query.as_str())
+                        .bind(key)
+                        .bind(old)
+                        .bind(new)
+                        .execute(&mut conn)
+                        .await?
+                        .rows_affected(),
+                    (Some(old), None) => sqlx::query(self.swap_remove_query.as_str())
+                        .bind(key)
+                        .bind(old)
+                        .execute(&mut conn)
+                        .await?
+                        .rows_affected(),
+                    (None, Some(new)) => sqlx::query(self.swap_insert_query.as_str())
+                        .bind(key)
+                        .bind(new)
+                        .execute(&mut conn)
+                        .await?
+                        .rows_affected(),
+                    (None, None) => {
+                        let exists: bool = sqlx::query(self.contains_key_query.as_str())
+                            .bind(key)
+                            .fetch_one(&mut conn)
+                            .await?
+                            .get(0);
+                        return Ok(exists.not());
+                    }
+                };
+
+                Ok::<_, DbError>(changed == 1)
+            };
+            self.op_log
+                .commit_swap(self.name.as_bytes(), writes, commit)
+                .await
+        }
+
+        pub async fn apply_batch(&self, batch: Batch) -> DbResult<()> {
+            let writes = self.op_log.is_enabled().then(|| batch.inserts.clone());
+            let commit = async {
+                let mut txn = self.pool.begin().await?;
+
+                for (key, val) in batch.inserts {
+                    if let Some(value) = val {
+                        sqlx::query(self.insert_query.as_str())
+                            .bind(key.as_ref())
+                            .bind(value.as_ref())
+                            .execute(&mut txn)
+                            .await?;
+                    } else {
+                        sqlx::query(self.remove_query.as_str())
+                            .bind(key.as_ref())
+                            .execute(&mut txn)
+                            .await?;
+                    }
+                }
+
+                txn.commit().await?;
+
+                Ok::<_, DbError>(())
+            };
+            self.op_log
+                .commit(self.name.as_bytes(), writes, commit)
+                .await
+        }
+
+        pub async fn iter(&self) -> impl Iterator<Item = DbResult<(EVec, EVec)>> {
+            let mut conn = match self.pool.acquire().await {
+                Ok(conn) => conn,
+                Err(err) => return Either::Left(std::iter::once(Err(err.into()))),
+            };
+
+            let row_query = sqlx::query(self.iter_query.as_str()).fetch_all(&mut conn);
+
+            let rows = match row_query.await {
+                Ok(rows) => rows,
+                Err(err) => return Either::Left(std::iter::once(Err(err.into()))),
+            };
+
+            Either::Right(
+                rows.into_iter()
+                    .map(|r| Ok((EVec::Owned(r.get(0)), EVec::Owned(r.get(1))))),
+            )
+        }
+
+        pub async fn scan_prefix(
+            &self,
+            prefix: &[u8],
+        ) -> impl Iterator<Item = DbResult<(EVec, EVec)>> {
+            let mut conn = match self.pool.acquire().await {
+                Ok(conn) => conn,
+                Err(err) => return Either::Left(std::iter::once(Err(err.into()))),
+            };
+
+            let mut stream = sqlx::query(self.iter_from_query.as_str())
+                .bind(prefix)
+                .fetch(&mut conn);
+
+            let mut items = Vec::new();
+
+            while let Some(result) = stream.next().await {
+                match result {
+                    Ok(row) => {
+                        let key: Vec<u8> = row.get(0);
+                        if key.starts_with(prefix) {
+                            let value: Vec<u8> = row.get(1);
+                            items.push(Ok((EVec::Owned(key), EVec::Owned(value))));
+                        } else {
+                            break;
+                        }
+                    }
+                    Err(err) => {
+                        items.push(Err(err.into()));
+                    }
+                }
+            }
+
+            Either::Right(items.into_iter())
+        }
+
+        pub async fn range(
+            &self,
+            range: RangeInclusive<&[u8]>,
+        ) -> impl Iterator<Item = DbResult<(EVec, EVec)>> + DoubleEndedIterator {
+            let mut conn = match self.pool.acquire().await {
+                Ok(conn) => conn,
+                Err(err) => return Either::Left(std::iter::once(Err(err.into()))),
+            };
+
+            let start_key = range.start();
+            let end_key = range.end();
+
+            let mut stream = sqlx::query(self.iter_from_query.as_str())
+                .bind(start_key)
+                .fetch(&mut conn);
+
+            let mut items = Vec::new();
+
+            while let Some(result) = stream.next().await {
+                match result {
+                    Ok(row) => {
+                        let key: Vec<u8> = row.get(0);
+                        if key.as_slice() <= *end_key {
+                            let value: Vec<u8> = row.get(1);
+                            items.push(Ok((EVec::Owned(key), EVec::Owned(value))));
+                        } else {
+                            break;
+                        }
+                    }
+                    Err(err) => {
+                        items.push(Err(err.into()));
+                    }
+                }
+            }
+
+            Either::Right(items.into_iter())
+        }
+
+        pub async fn usage(&self) -> DbResult<TreeUsage> {
+            let mut conn = self.pool.acquire().await?;
+
+            let row = sqlx::query(self.usage_query.as_str())
+                .fetch_one(&mut conn)
+                .await?;
+
+            Ok(TreeUsage {
+                entries: row.get::<i64, _>(0) as u64,
+                bytes: row.get::<i64, _>(1) as u64,
+            })
+        }
+
+        pub async fn verify_integrity(&self) -> DbResult<()> {
+            Ok(())
+        }
+    }
+}
+
+// sqlite already converts sqlx errors
+#[cfg(not(feature = "sqlite"))]
+impl From<sqlx::Error> for DbError {
+    fn from(err: sqlx::Error) -> Self {
+        DbError {
+            inner: Box::new(err),
+        }
+    }
+}
@@ -1,16 +1,97 @@
-use std::ops::RangeInclusive;
+use std::{
+    ops::RangeInclusive,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
 
-use hrpc::common::future::Ready;
+use tokio::{sync::Semaphore, task::JoinError};
 
 use crate::{config::DbConfig, utils::evec::EVec};
 
-use super::{Batch, DbError, DbResult};
+use super::{op_log::OpLog, Batch, BlockingPoolStats, DbError, DbResult, TreeUsage};
 
-type SledFut<T> = Ready<DbResult<T>>;
+type DbIter = std::vec::IntoIter<DbResult<(EVec, EVec)>>;
 
-pub mod shared {
-    use hrpc::common::future::ready;
+/// Runs sled operations on tokio's blocking threads, so they don't stall the
+/// executor. At most `budget` operations run at once, the rest wait for a
+/// thread to be free.
+#[derive(Debug, Clone)]
+struct BlockingPool {
+    inner: Arc<BlockingPoolInner>,
+}
+
+#[derive(Debug)]
+struct BlockingPoolInner {
+    permits: Semaphore,
+    budget: usize,
+    operations: AtomicU64,
+    contended_operations: AtomicU64,
+    total_wait_micros: AtomicU64,
+}
+
+impl BlockingPool {
+    fn new(budget: usize) -> Self {
+        let budget = budget.max(1);
+        Self {
+            inner: Arc::new(BlockingPoolInner {
+                permits: Semaphore::new(budget),
+                budget,
+                operations: AtomicU64::new(0),
+                contended_operations: AtomicU64::new(0),
+                total_wait_micros: AtomicU64::new(0),
+            }),
+        }
+    }
 
+    async fn run<T, F>(&self, f: F) -> DbResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> DbResult<T> + Send + 'static,
+    {
+        let inner = &self.inner;
+        inner.operations.fetch_add(1, Ordering::Relaxed);
+        let _permit = match inner.permits.try_acquire() {
+            Ok(permit) => permit,
+            Err(_) => {
+                inner.contended_operations.fetch_add(1, Ordering::Relaxed);
+                let started_at = Instant::now();
+                let permit = inner
+                    .permits
+                    .acquire()
+                    .await
+                    .expect("semaphore is never closed");
+                inner
+                    .total_wait_micros
+                    .fetch_add(started_at.elapsed().as_micros() as u64, Ordering::Relaxed);
+                permit
+            }
+        };
+
+        tokio::task::spawn_blocking(f).await?
+    }
+
+    fn stats(&self) -> BlockingPoolStats {
+        let inner = &self.inner;
+        BlockingPoolStats {
+            budget: inner.budget,
+            in_use: inner.budget - inner.permits.available_permits(),
+            operations: inner.operations.load(Ordering::Relaxed),
+            contended_operations: inner.contended_operations.load(Ordering::Relaxed),
+            total_wait_micros: inner.total_wait_micros.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn collect_iter(iter: impl Iterator<Item = sled::Result<(sled::IVec, sled::IVec)>>) -> DbIter {
+    iter.map(|res| res.map(|(a, b)| (a.into(), b.into())).map_err(Into::into))
+        .collect::<Vec<_>>()
+        .into_iter()
+}
+
+pub mod shared {
     use super::*;
 
     pub async fn open_database(db_path: String, db_config: DbConfig) -> DbResult<Db> {
@@ -37,10 +118,13 @@ pub mod shared {
                 }
             }
 
-            Ok(Db { inner: db })
+            Ok(Db {
+                inner: db,
+                pool: BlockingPool::new(db_config.sled_blocking_threads),
+                op_log: OpLog::default(),
+            })
         })
-        .await
-        .unwrap()
+        .await?
     }
 
     pub fn open_temp() -> Db {
@@ -49,22 +133,34 @@ pub mod shared {
             .open()
             .expect("failed to create temp db");
 
-        Db { inner }
+        Db {
+            inner,
+            pool: BlockingPool::new(DbConfig::default().sled_blocking_threads),
+            op_log: OpLog::default(),
+        }
     }
 
     #[derive(Debug, Clone)]
     pub struct Db {
         inner: sled::Db,
+        pool: BlockingPool,
+        op_log: OpLog,
     }
 
     impl Db {
-        pub fn open_tree(&self, name: &[u8]) -> SledFut<Tree> {
-            ready(
-                self.inner
-                    .open_tree(name)
-                    .map_err(Into::into)
-                    .map(|tree| Tree { inner: tree }),
-            )
+        pub async fn open_tree(&self, name: &[u8]) -> DbResult<Tree> {
+            let db = self.inner.clone();
+            let name = name.to_vec();
+            let inner = self
+                .pool
+                .run(move || db.open_tree(name).map_err(Into::into))
+                .await?;
+
+            Ok(Tree {
+                inner,
+                pool: self.pool.clone(),
+                op_log: self.op_log.clone(),
+            })
         }
 
         pub async fn flush(&self) -> DbResult<()> {
@@ -74,82 +170,182 @@ pub mod shared {
                 .map(|_| ())
                 .map_err(Into::into)
         }
+
+        pub async fn size_on_disk(&self) -> DbResult<u64> {
+            let db = self.inner.clone();
+            self.pool
+                .run(move || db.size_on_disk().map_err(Into::into))
+                .await
+        }
+
+        /// sled has no way to force its segment cleaner to run, it reclaims
+        /// space on its own as segments get rewritten. Flushing makes sure
+        /// everything written so far is on disk, so that freed segments can be reused.
+        pub async fn compact(&self) -> DbResult<()> {
+            self.flush().await
+        }
+
+        /// Compacting only flushes, so it doesn't reclaim any space by itself.
+        pub fn compact_reclaims_space(&self) -> bool {
+            false
+        }
+
+        pub fn blocking_pool_stats(&self) -> Option<BlockingPoolStats> {
+            Some(self.pool.stats())
+        }
+
+        pub fn op_log(&self) -> &OpLog {
+            &self.op_log
+        }
     }
 
     #[derive(Debug, Clone)]
     pub struct Tree {
         inner: sled::Tree,
+        pool: BlockingPool,
+        op_log: OpLog,
     }
 
     impl Tree {
-        pub fn get(&self, key: &[u8]) -> SledFut<Option<EVec>> {
-            ready(
-                self.inner
-                    .get(key)
+        pub async fn get(&self, key: &[u8]) -> DbResult<Option<EVec>> {
+            let tree = self.inner.clone();
+            let key = sled::IVec::from(key);
+            self.pool
+                .run(move || {
+                    tree.get(key)
+                        .map_err(Into::into)
+                        .map(|opt| opt.map(|i| i.into()))
+                })
+                .await
+        }
+
+        pub async fn insert(
+            &self,
+            key: &[u8],
+            value: impl Into<sled::IVec>,
+        ) -> DbResult<Option<EVec>> {
+            let tree = self.inner.clone();
+            let key = sled::IVec::from(key);
+            let value = value.into();
+            let writes = self
+                .op_log
+                .is_enabled()
+                .then(|| vec![(key.clone().into(), Some(value.clone().into()))]);
+            let commit = self.pool.run(move || {
+                tree.insert(key, value)
                     .map_err(Into::into)
-                    .map(|opt| opt.map(|i| i.into())),
-            )
+                    .map(|opt| opt.map(|i| i.into()))
+            });
+            self.op_log.commit(&self.inner.name(), writes, commit).await
         }
 
-        pub fn insert(&self, key: &[u8], value: impl Into<sled::IVec>) -> SledFut<Option<EVec>> {
-            ready(
-                self.inner
-                    .insert(key, value)
+        pub async fn remove(&self, key: &[u8]) -> DbResult<Option<EVec>> {
+            let tree = self.inner.clone();
+            let key = sled::IVec::from(key);
+            let writes = self
+                .op_log
+                .is_enabled()
+                .then(|| vec![(key.clone().into(), None)]);
+            let commit = self.pool.run(move || {
+                tree.remove(key)
                     .map_err(Into::into)
-                    .map(|opt| opt.map(|i| i.into())),
-            )
+                    .map(|opt| opt.map(|i| i.into()))
+            });
+            self.op_log.commit(&self.inner.name(), writes, commit).await
         }
 
-        pub fn remove(&self, key: &[u8]) -> SledFut<Option<EVec>> {
-            ready(
-                self.inner
-                    .remove(key)
+        /// Sets `key` to `new` if it's currently `old`, where `None` means
+        /// the key doesn't exist. Returns whether the swap happened.
+        pub async fn compare_and_swap(
+            &self,
+            key: &[u8],
+            old: Option<&[u8]>,
+            new: Option<&[u8]>,
+        ) -> DbResult<bool> {
+            let tree = self.inner.clone();
+            let key = sled::IVec::from(key);
+            let old = old.map(sled::IVec::from);
+            let new = new.map(sled::IVec::from);
+            let writes = self
+                .op_log
+                .is_enabled()
+                .then(|| vec![(key.clone().into(), new.clone().map(Into::into))]);
+            let commit = self.pool.run(move || {
+                tree.compare_and_swap(key, old, new)
+                    .map(|res| res.is_ok())
                     .map_err(Into::into)
-                    .map(|opt| opt.map(|i| i.into())),
-            )
+            });
+            self.op_log
+                .commit_swap(&self.inner.name(), writes, commit)
+                .await
         }
 
-        pub fn scan_prefix<'a>(
-            &'a self,
-            prefix: &[u8],
-        ) -> Ready<impl Iterator<Item = DbResult<(EVec, EVec)>> + 'a> {
-            ready(
-                self.inner
-                    .scan_prefix(prefix)
-                    .map(|res| res.map(|(a, b)| (a.into(), b.into())).map_err(Into::into)),
-            )
+        pub async fn scan_prefix(&self, prefix: &[u8]) -> DbIter {
+            let tree = self.inner.clone();
+            let prefix = sled::IVec::from(prefix);
+            self.pool
+                .run(move || Ok(collect_iter(tree.scan_prefix(prefix))))
+                .await
+                .unwrap_or_else(|err| vec![Err(err)].into_iter())
         }
 
-        pub fn iter(&self) -> Ready<impl Iterator<Item = DbResult<(EVec, EVec)>> + '_> {
-            ready(
-                self.inner
-                    .iter()
-                    .map(|res| res.map(|(a, b)| (a.into(), b.into())).map_err(Into::into)),
-            )
+        pub async fn iter(&self) -> DbIter {
+            let tree = self.inner.clone();
+            self.pool
+                .run(move || Ok(collect_iter(tree.iter())))
+                .await
+                .unwrap_or_else(|err| vec![Err(err)].into_iter())
         }
 
-        pub fn apply_batch(&self, batch: Batch) -> SledFut<()> {
-            ready(self.inner.apply_batch(batch.into()).map_err(Into::into))
+        pub async fn apply_batch(&self, batch: Batch) -> DbResult<()> {
+            let tree = self.inner.clone();
+            let writes = self.op_log.is_enabled().then(|| batch.inserts.clone());
+            let commit = self
+                .pool
+                .run(move || tree.apply_batch(batch.into()).map_err(Into::into));
+            self.op_log.commit(&self.inner.name(), writes, commit).await
         }
 
-        pub fn contains_key(&self, key: &[u8]) -> SledFut<bool> {
-            ready(self.inner.contains_key(key).map_err(Into::into))
+        pub async fn contains_key(&self, key: &[u8]) -> DbResult<bool> {
+            let tree = self.inner.clone();
+            let key = sled::IVec::from(key);
+            self.pool
+                .run(move || tree.contains_key(key).map_err(Into::into))
+                .await
+        }
+
+        pub async fn range(&self, range: RangeInclusive<&[u8]>) -> DbIter {
+            let tree = self.inner.clone();
+            let (start, end) = (
+                sled::IVec::from(*range.start()),
+                sled::IVec::from(*range.end()),
+            );
+            self.pool
+                .run(move || Ok(collect_iter(tree.range(start..=end))))
+                .await
+                .unwrap_or_else(|err| vec![Err(err)].into_iter())
         }
 
-        pub fn range<'a>(
-            &'a self,
-            range: RangeInclusive<&[u8]>,
-        ) -> Ready<impl Iterator<Item = DbResult<(EVec, EVec)>> + DoubleEndedIterator + 'a>
-        {
-            ready(
-                self.inner
-                    .range(range)
-                    .map(|res| res.map(|(a, b)| (a.into(), b.into())).map_err(Into::into)),
-            )
+        pub async fn usage(&self) -> DbResult<TreeUsage> {
+            let tree = self.inner.clone();
+            self.pool
+                .run(move || {
+                    tree.iter()
+                        .try_fold(TreeUsage::default(), |mut usage, res| {
+                            let (key, value) = res?;
+                            usage.entries += 1;
+                            usage.bytes += (key.len() + value.len()) as u64;
+                            Ok(usage)
+                        })
+                })
+                .await
         }
 
-        pub fn verify_integrity(&self) -> SledFut<()> {
-            ready(self.inner.verify_integrity().map_err(Into::into))
+        pub async fn verify_integrity(&self) -> DbResult<()> {
+            let tree = self.inner.clone();
+            self.pool
+                .run(move || tree.verify_integrity().map_err(Into::into))
+                .await
         }
     }
 }
@@ -162,6 +358,15 @@ impl From<sled::Error> for DbError {
     }
 }
 
+// a database operation panicked, or was cancelled since the runtime is shutting down
+impl From<JoinError> for DbError {
+    fn from(inner: JoinError) -> Self {
+        DbError {
+            inner: Box::new(inner),
+        }
+    }
+}
+
 impl From<Batch> for sled::Batch {
     fn from(batch: Batch) -> Self {
         let mut sled = sled::Batch::default();
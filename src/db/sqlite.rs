@@ -8,14 +8,20 @@ use super::{DbError, DbResult};
 use crate::config::DbConfig;
 
 pub mod shared {
-    use std::{ops::RangeInclusive, time::Duration};
+    use std::{
+        ops::{Not, RangeInclusive},
+        time::Duration,
+    };
 
     use hrpc::exports::futures_util::StreamExt;
     use itertools::Either;
     use smol_str::SmolStr;
     use sqlx::Row;
 
-    use crate::{db::Batch, utils::evec::EVec};
+    use crate::{
+        db::{op_log::OpLog, Batch, BlockingPoolStats, TreeUsage},
+        utils::evec::EVec,
+    };
 
     use super::*;
 
@@ -30,12 +36,16 @@ pub mod shared {
 
         let pool = SqlitePool::connect_with(conf).await?;
 
-        Ok(Db { pool })
+        Ok(Db {
+            pool,
+            op_log: OpLog::default(),
+        })
     }
 
     #[derive(Debug, Clone)]
     pub struct Db {
         pool: SqlitePool,
+        op_log: OpLog,
     }
 
     impl Db {
@@ -54,6 +64,8 @@ pub mod shared {
 
             Ok(Tree {
                 pool: self.pool.clone(),
+                name: name.into(),
+                op_log: self.op_log.clone(),
                 get_query: format!("SELECT value FROM {} WHERE key = ?", name).into(),
                 insert_query: format!("INSERT OR REPLACE INTO {} (key, value) VALUES (?, ?)", name)
                     .into(),
@@ -69,23 +81,77 @@ pub mod shared {
                 )
                 .into(),
                 iter_query: format!("SELECT key, value FROM {} ORDER BY key ASC", name).into(),
+                usage_query: format!(
+                    "SELECT COUNT(*), COALESCE(SUM(LENGTH(key) + LENGTH(value)), 0) FROM {}",
+                    name
+                )
+                .into(),
+                swap_query: format!("UPDATE {} SET value = ? WHERE key = ? AND value = ?", name)
+                    .into(),
+                swap_remove_query: format!("DELETE FROM {} WHERE key = ? AND value = ?", name)
+                    .into(),
+                swap_insert_query: format!(
+                    "INSERT OR IGNORE INTO {} (key, value) VALUES (?, ?)",
+                    name
+                )
+                .into(),
             })
         }
 
         pub async fn flush(&self) -> DbResult<()> {
             Ok(())
         }
+
+        pub async fn size_on_disk(&self) -> DbResult<u64> {
+            let mut conn = self.pool.acquire().await?;
+
+            let row = sqlx::query(
+                "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+            )
+            .fetch_one(&mut conn)
+            .await?;
+
+            Ok(row.get::<i64, _>(0) as u64)
+        }
+
+        /// Rebuilds the database file, dropping free pages.
+        pub async fn compact(&self) -> DbResult<()> {
+            let mut conn = self.pool.acquire().await?;
+
+            sqlx::query("VACUUM").execute(&mut conn).await?;
+
+            Ok(())
+        }
+
+        pub fn compact_reclaims_space(&self) -> bool {
+            true
+        }
+
+        /// sqlx runs queries on its own connection pool, there is no blocking pool.
+        pub fn blocking_pool_stats(&self) -> Option<BlockingPoolStats> {
+            None
+        }
+
+        pub fn op_log(&self) -> &OpLog {
+            &self.op_log
+        }
     }
 
     #[derive(Debug, Clone)]
     pub struct Tree {
         pool: SqlitePool,
+        name: SmolStr,
+        op_log: OpLog,
         get_query: SmolStr,
         insert_query: SmolStr,
         remove_query: SmolStr,
         contains_key_query: SmolStr,
         iter_from_query: SmolStr,
         iter_query: SmolStr,
+        usage_query: SmolStr,
+        swap_query: SmolStr,
+        swap_remove_query: SmolStr,
+        swap_insert_query: SmolStr,
     }
 
     impl Tree {
@@ -101,26 +167,41 @@ pub mod shared {
         }
 
         pub async fn insert(&self, key: &[u8], value: impl AsRef<[u8]>) -> DbResult<Option<EVec>> {
-            let mut conn = self.pool.acquire().await?;
-
-            let val = sqlx::query(self.insert_query.as_str())
-                .bind(key)
-                .bind(value.as_ref())
-                .fetch_optional(&mut conn)
-                .await?;
-
-            Ok(val.map(|r| EVec::Owned(r.get::<Vec<u8>, _>(0))))
+            let writes = self
+                .op_log
+                .is_enabled()
+                .then(|| vec![(key.into(), Some(value.as_ref().into()))]);
+            let commit = async {
+                let mut conn = self.pool.acquire().await?;
+
+                let val = sqlx::query(self.insert_query.as_str())
+                    .bind(key)
+                    .bind(value.as_ref())
+                    .fetch_optional(&mut conn)
+                    .await?;
+
+                Ok::<_, DbError>(val.map(|r| EVec::Owned(r.get::<Vec<u8>, _>(0))))
+            };
+            self.op_log
+                .commit(self.name.as_bytes(), writes, commit)
+                .await
         }
 
         pub async fn remove(&self, key: &[u8]) -> DbResult<Option<EVec>> {
-            let mut conn = self.pool.acquire().await?;
+            let writes = self.op_log.is_enabled().then(|| vec![(key.into(), None)]);
+            let commit = async {
+                let mut conn = self.pool.acquire().await?;
 
-            let val = sqlx::query(self.remove_query.as_str())
-                .bind(key)
-                .fetch_optional(&mut conn)
-                .await?;
+                let val = sqlx::query(self.remove_query.as_str())
+                    .bind(key)
+                    .fetch_optional(&mut conn)
+                    .await?;
 
-            Ok(val.map(|r| EVec::Owned(r.get::<Vec<u8>, _>(0))))
+                Ok::<_, DbError>(val.map(|r| EVec::Owned(r.get::<Vec<u8>, _>(0))))
+            };
+            self.op_log
+                .commit(self.name.as_bytes(), writes, commit)
+                .await
         }
 
         pub async fn contains_key(&self, key: &[u8]) -> DbResult<bool> {
@@ -134,27 +215,86 @@ pub mod shared {
             Ok(row.get(0))
         }
 
+        /// Sets `key` to `new` if it's currently `old`, where `None` means
+        /// the key doesn't exist. Returns whether the swap happened.
+        pub async fn compare_and_swap(
+            &self,
+            key: &[u8],
+            old: Option<&[u8]>,
+            new: Option<&[u8]>,
+        ) -> DbResult<bool> {
+            let writes = self
+                .op_log
+                .is_enabled()
+                .then(|| vec![(key.into(), new.map(Into::into))]);
+            let commit = async {
+                let mut conn = self.pool.acquire().await?;
+
+                // every case is a single statement, so it is atomic on its own
+                let changed = match (old, new) {
+                    (Some(old), Some(new)) => sqlx::query(self.swap_query.as_str())
+                        .bind(new)
+                        .bind(key)
+                        .bind(old)
+                        .execute(&mut conn)
+                        .await?
+                        .rows_affected(),
+                    (Some(old), None) => sqlx::query(self.swap_remove_query.as_str())
+                        .bind(key)
+                        .bind(old)
+                        .execute(&mut conn)
+                        .await?
+                        .rows_affected(),
+                    (None, Some(new)) => sqlx::query(self.swap_insert_query.as_str())
+                        .bind(key)
+                        .bind(new)
+                        .execute(&mut conn)
+                        .await?
+                        .rows_affected(),
+                    (None, None) => {
+                        let exists: bool = sqlx::query(self.contains_key_query.as_str())
+                            .bind(key)
+                            .fetch_one(&mut conn)
+                            .await?
+                            .get(0);
+                        return Ok(exists.not());
+                    }
+                };
+
+                Ok::<_, DbError>(changed == 1)
+            };
+            self.op_log
+                .commit_swap(self.name.as_bytes(), writes, commit)
+                .await
+        }
+
         pub async fn apply_batch(&self, batch: Batch) -> DbResult<()> {
-            let mut txn = self.pool.begin().await?;
-
-            for (key, val) in batch.inserts {
-                if let Some(value) = val {
-                    sqlx::query(self.insert_query.as_str())
-                        .bind(key.as_ref())
-                        .bind(value.as_ref())
-                        .execute(&mut txn)
-                        .await?;
-                } else {
-                    sqlx::query(self.remove_query.as_str())
-                        .bind(key.as_ref())
-                        .execute(&mut txn)
-                        .await?;
+            let writes = self.op_log.is_enabled().then(|| batch.inserts.clone());
+            let commit = async {
+                let mut txn = self.pool.begin().await?;
+
+                for (key, val) in batch.inserts {
+                    if let Some(value) = val {
+                        sqlx::query(self.insert_query.as_str())
+                            .bind(key.as_ref())
+                            .bind(value.as_ref())
+                            .execute(&mut txn)
+                            .await?;
+                    } else {
+                        sqlx::query(self.remove_query.as_str())
+                            .bind(key.as_ref())
+                            .execute(&mut txn)
+                            .await?;
+                    }
                 }
-            }
 
-            txn.commit().await?;
+                txn.commit().await?;
 
-            Ok(())
+                Ok::<_, DbError>(())
+            };
+            self.op_log
+                .commit(self.name.as_bytes(), writes, commit)
+                .await
         }
 
         pub async fn iter(&self) -> impl Iterator<Item = DbResult<(EVec, EVec)>> {
@@ -249,6 +389,19 @@ pub mod shared {
             Either::Right(items.into_iter())
         }
 
+        pub async fn usage(&self) -> DbResult<TreeUsage> {
+            let mut conn = self.pool.acquire().await?;
+
+            let row = sqlx::query(self.usage_query.as_str())
+                .fetch_one(&mut conn)
+                .await?;
+
+            Ok(TreeUsage {
+                entries: row.get::<i64, _>(0) as u64,
+                bytes: row.get::<i64, _>(1) as u64,
+            })
+        }
+
         pub async fn verify_integrity(&self) -> DbResult<()> {
             Ok(())
         }
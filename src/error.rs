@@ -103,6 +103,8 @@ pub enum ServerError {
     CantGetHostKey(SmolStr),
     InvalidTokenData,
     InvalidTokenSignature,
+    /// A federation payload wasn't signed, or its signature doesn't match it.
+    InvalidEventSignature,
     InvalidTime,
     CouldntVerifyTokenData,
     InvalidToken,
@@ -125,12 +127,45 @@ pub enum ServerError {
     MustNotBeLastOwner,
     ContentCantBeSentByUser,
     InvalidProtoMessage(DecodeBodyError),
+    InvalidJsonBody(serde_json::Error),
+    NoSuchPermissionPreset(SmolStr),
+    NotAStageSpeaker,
+    NotAStageChannel,
+    ScreeningRequired,
+    NotAnAdmin,
+    NoSuchReport(u64),
+    BlockedDomain(SmolStr),
+    MessageNonceInUse,
+    GalleryMediaOnly,
+    CategoryHasNoMessages,
+    RoleMemberCapReached(u64),
+    SearchDisabled,
+    InvalidSearchQuery,
+    RemoteMediaTooLarge,
+    RemoteMediaQuotaExceeded(SmolStr),
+    MediaAccessDenied,
+    InvalidMediaLink,
+    InvalidCursor,
+    TooManyReactions(usize),
+    /// The target user's highest role, or a role being given or taken, is
+    /// equal to or higher than the acting user's highest role.
+    RoleHierarchy,
+    /// The disk the database is on is nearly full, so nothing can be changed.
+    ServerReadOnly,
+    /// The disk media is stored on is nearly full.
+    UploadsDisabled,
+    /// Logins to an account or from an IP are locked out for this long, because
+    /// of too many failed logins.
+    LoginLocked(Duration),
+    /// The IP the request came from is banned from the server.
+    IpBanned,
 }
 
 impl StdError for ServerError {
     fn source(&self) -> Option<&(dyn StdError + 'static)> {
         match self {
             ServerError::InvalidProtoMessage(err) => Some(err),
+            ServerError::InvalidJsonBody(err) => Some(err),
             ServerError::InvalidAgainst(err) => Some(err),
             ServerError::InvalidUrl(err) => Some(err),
             ServerError::IoError(err) => Some(err),
@@ -262,6 +297,9 @@ impl Display for ServerError {
             ServerError::CantGetHostKey(host) => write!(f, "can't get host key: {}", host),
             ServerError::InvalidTokenData => write!(f, "token data is invalid"),
             ServerError::InvalidTokenSignature => write!(f, "token signature is invalid"),
+            ServerError::InvalidEventSignature => {
+                write!(f, "federation payload signature is missing or invalid")
+            }
             ServerError::InvalidTime => write!(f, "invalid time"),
             ServerError::InvalidToken => write!(f, "token is invalid"),
             ServerError::InvalidAgainst(err) => write!(f, "malformed Against header: {}", err),
@@ -297,6 +335,73 @@ impl Display for ServerError {
             ServerError::ContentCantBeSentByUser => {
                 f.write_str("this content type cannot be used by a regular user")
             }
+            ServerError::InvalidJsonBody(err) => write!(f, "invalid JSON body: {}", err),
+            ServerError::NoSuchPermissionPreset(name) => {
+                write!(f, "permission preset {} does not exist", name)
+            }
+            ServerError::NotAStageSpeaker => {
+                f.write_str("only speakers can talk in stage channels")
+            }
+            ServerError::NotAStageChannel => f.write_str("channel is not a stage channel"),
+            ServerError::ScreeningRequired => {
+                f.write_str("this guild requires membership screening, submit answers to join it")
+            }
+            ServerError::NotAnAdmin => f.write_str("only homeserver admins can do this"),
+            ServerError::NoSuchReport(id) => write!(f, "report {} does not exist", id),
+            ServerError::BlockedDomain(domain) => {
+                write!(f, "links to {} are not allowed on this server", domain)
+            }
+            ServerError::MessageNonceInUse => {
+                f.write_str("a message with this echo id is still being sent")
+            }
+            ServerError::GalleryMediaOnly => {
+                f.write_str("only attachments and photos can be sent in gallery channels")
+            }
+            ServerError::CategoryHasNoMessages => {
+                f.write_str("category channels only group other channels, and have no messages")
+            }
+            ServerError::RoleMemberCapReached(role_id) => {
+                write!(f, "role {} has reached its member cap", role_id)
+            }
+            ServerError::SearchDisabled => f.write_str("message search is disabled on this server"),
+            ServerError::InvalidSearchQuery => f.write_str("search query is invalid"),
+            ServerError::RemoteMediaTooLarge => {
+                f.write_str("media on the other homeserver is too large to fetch")
+            }
+            ServerError::MediaAccessDenied => {
+                f.write_str("you don't have access to the requested media")
+            }
+            ServerError::InvalidMediaLink => f.write_str("media link is invalid or has expired"),
+            ServerError::TooManyReactions(max) => write!(
+                f,
+                "messages can't have reactions with more than {} different emotes",
+                max
+            ),
+            ServerError::InvalidCursor => {
+                f.write_str("cursor is invalid or was made for a different request")
+            }
+            ServerError::RoleHierarchy => f.write_str(
+                "you can't do this to members or roles that are equal to or higher than your highest role",
+            ),
+            ServerError::RemoteMediaQuotaExceeded(host) => {
+                write!(
+                    f,
+                    "media fetch quota for host {} is used up, try again later",
+                    host
+                )
+            }
+            ServerError::ServerReadOnly => f.write_str(
+                "server is out of disk space and is read-only for now, try again later",
+            ),
+            ServerError::UploadsDisabled => f.write_str(
+                "server is out of disk space for media, uploads are disabled for now",
+            ),
+            ServerError::LoginLocked(rem) => write!(
+                f,
+                "too many failed logins, logging in is locked for {} more minutes",
+                (rem.as_secs() + 59) / 60
+            ),
+            ServerError::IpBanned => f.write_str("your IP is banned from this server"),
         }
     }
 }
@@ -338,6 +443,7 @@ impl ServerError {
             | ServerError::FailedToAuthSync
             | ServerError::InvalidTokenData
             | ServerError::InvalidTokenSignature
+            | ServerError::InvalidEventSignature
             | ServerError::InvalidTime
             | ServerError::CouldntVerifyTokenData
             | ServerError::InvalidToken
@@ -358,8 +464,30 @@ impl ServerError {
             )
             | ServerError::MustNotBeLastOwner
             | ServerError::ContentCantBeSentByUser
-            | ServerError::InvalidProtoMessage(_) => StatusCode::BAD_REQUEST,
-            ServerError::FederationDisabled | ServerError::HostNotAllowed => StatusCode::FORBIDDEN,
+            | ServerError::InvalidProtoMessage(_)
+            | ServerError::InvalidJsonBody(_)
+            | ServerError::NoSuchPermissionPreset(_)
+            | ServerError::NotAStageSpeaker
+            | ServerError::NotAStageChannel
+            | ServerError::ScreeningRequired
+            | ServerError::NoSuchReport(_)
+            | ServerError::BlockedDomain(_)
+            | ServerError::MessageNonceInUse
+            | ServerError::GalleryMediaOnly
+            | ServerError::CategoryHasNoMessages
+            | ServerError::RoleMemberCapReached(_)
+            | ServerError::SearchDisabled
+            | ServerError::InvalidSearchQuery
+            | ServerError::InvalidCursor
+            | ServerError::TooManyReactions(_)
+            | ServerError::RemoteMediaTooLarge => StatusCode::BAD_REQUEST,
+            ServerError::FederationDisabled
+            | ServerError::HostNotAllowed
+            | ServerError::NotAnAdmin
+            | ServerError::MediaAccessDenied
+            | ServerError::InvalidMediaLink
+            | ServerError::RoleHierarchy
+            | ServerError::IpBanned => StatusCode::FORBIDDEN,
             ServerError::IoError(_)
             | ServerError::InternalServerError
             | ServerError::HttpError(_)
@@ -369,9 +497,14 @@ impl ServerError {
             | ServerError::WebRTCError(_)
             | ServerError::DbError(_)
             | ServerError::MultipartError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::TooFast(_) => StatusCode::TOO_MANY_REQUESTS,
+            ServerError::TooFast(_)
+            | ServerError::RemoteMediaQuotaExceeded(_)
+            | ServerError::LoginLocked(_) => StatusCode::TOO_MANY_REQUESTS,
             ServerError::MediaNotFound | ServerError::LinkNotFound(_) => StatusCode::NOT_FOUND,
             ServerError::NotImplemented => StatusCode::NOT_IMPLEMENTED,
+            ServerError::ServerReadOnly | ServerError::UploadsDisabled => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
         }
     }
 
@@ -439,6 +572,7 @@ impl ServerError {
             ServerError::FailedToAuthSync => "h.bad-auth",
             ServerError::InvalidTokenData => "h.bad-token-data",
             ServerError::InvalidTokenSignature => "h.bad-token-signature",
+            ServerError::InvalidEventSignature => "h.bad-event-signature",
             ServerError::InvalidTime => "h.bad-time",
             ServerError::CouldntVerifyTokenData => "h.token-verify-failure",
             ServerError::InvalidToken => "h.bad-token",
@@ -455,6 +589,31 @@ impl ServerError {
             ServerError::InvalidRegistrationToken => "h.invalid-registration-token",
             ServerError::MustNotBeLastOwner => "h.last-owner-in-guild",
             ServerError::ContentCantBeSentByUser => "h.content-not-allowed-for-user",
+            ServerError::InvalidJsonBody(_) => "h.bad-json",
+            ServerError::NoSuchPermissionPreset(_) => "h.bad-permission-preset",
+            ServerError::NotAStageSpeaker => "h.not-a-speaker",
+            ServerError::NotAStageChannel => "h.not-a-stage-channel",
+            ServerError::ScreeningRequired => "h.screening-required",
+            ServerError::NotAnAdmin => "h.not-an-admin",
+            ServerError::NoSuchReport(_) => "h.no-such-report",
+            ServerError::BlockedDomain(_) => "h.blocked-domain",
+            ServerError::MessageNonceInUse => "h.message-nonce-in-use",
+            ServerError::GalleryMediaOnly => "h.gallery-media-only",
+            ServerError::CategoryHasNoMessages => "h.category-has-no-messages",
+            ServerError::RoleMemberCapReached(_) => "h.role-member-cap-reached",
+            ServerError::SearchDisabled => "h.search-disabled",
+            ServerError::InvalidSearchQuery => "h.invalid-search-query",
+            ServerError::RemoteMediaTooLarge => "h.remote-media-too-large",
+            ServerError::RemoteMediaQuotaExceeded(_) => "h.remote-media-quota-exceeded",
+            ServerError::MediaAccessDenied => "h.media-access-denied",
+            ServerError::InvalidMediaLink => "h.invalid-media-link",
+            ServerError::InvalidCursor => "h.invalid-cursor",
+            ServerError::TooManyReactions(_) => "h.too-many-reactions",
+            ServerError::RoleHierarchy => "h.role-hierarchy",
+            ServerError::ServerReadOnly => "h.server-read-only",
+            ServerError::UploadsDisabled => "h.uploads-disabled",
+            ServerError::LoginLocked(_) => "h.login-locked",
+            ServerError::IpBanned => "h.ip-banned",
         }
     }
 
@@ -464,7 +623,14 @@ impl ServerError {
         }
 
         match id {
-            "h.federation-disabled" | "h.host-not-allowed" => Some(StatusCode::FORBIDDEN),
+            "h.federation-disabled"
+            | "h.host-not-allowed"
+            | "h.not-an-admin"
+            | "h.media-access-denied"
+            | "h.invalid-media-link"
+            | "h.role-hierarchy" => Some(StatusCode::FORBIDDEN),
+            "h.server-read-only" | "h.uploads-disabled" => Some(StatusCode::SERVICE_UNAVAILABLE),
+            "h.login-locked" => Some(StatusCode::TOO_MANY_REQUESTS),
             _ => Some(StatusCode::BAD_REQUEST),
         }
     }
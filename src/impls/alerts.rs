@@ -0,0 +1,198 @@
+//! Alerts sent to the operator when something needs their attention, to the
+//! webhook or email address set in config.
+//!
+//! Every problem has a key, and an alert for a key isn't sent again until
+//! `cooldown_mins` passed, so a problem that stays around doesn't flood the
+//! operator with alerts.
+
+use std::time::{Duration, Instant};
+
+use ahash::RandomState;
+use dashmap::DashMap;
+use hyper::{http, Body};
+use serde::Serialize;
+
+use crate::{config::AlertsConfig, db::sync::QUEUED_SINCE_PREFIX};
+
+use super::{email, get_time_secs, prelude::*, HttpClient};
+
+/// How often federated hosts are checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const SECS_IN_MIN: u64 = 60;
+
+/// Tracks which alerts were sent, and failed logins.
+#[derive(Default)]
+pub struct Alerts {
+    /// When an alert was last sent for each problem.
+    last_sent: DashMap<String, Instant, RandomState>,
+    /// Failed logins of each account in the current window, with when the
+    /// window started.
+    failed_logins: DashMap<SmolStr, (Instant, u32), RandomState>,
+}
+
+#[derive(Debug, Serialize)]
+struct AlertPayload<'a> {
+    host: &'a str,
+    /// Identifies the problem, for example `peer-down:example.org`.
+    key: &'a str,
+    message: &'a str,
+    /// In seconds since unix epoch.
+    raised_at: u64,
+}
+
+impl Alerts {
+    /// Sends an alert about a problem, unless one was sent about it recently.
+    pub fn raise(&self, deps: &Dependencies, key: String, message: String) {
+        let Some(config) = deps.config.alerts.clone() else {
+            return;
+        };
+
+        let cooldown = Duration::from_secs(config.cooldown_mins * SECS_IN_MIN);
+        let recently_sent = self
+            .last_sent
+            .get(&key)
+            .map_or(false, |sent_at| sent_at.elapsed() < cooldown);
+        if recently_sent {
+            return;
+        }
+        self.last_sent.insert(key.clone(), Instant::now());
+
+        tracing::warn!("raising alert {}: {}", key, message);
+
+        let http = deps.http.clone();
+        let email_config = deps.config.email.clone();
+        let host = deps.config.host.clone();
+        tokio::spawn(async move {
+            if let Some(url) = &config.webhook_url {
+                let payload = AlertPayload {
+                    host: &host,
+                    key: &key,
+                    message: &message,
+                    raised_at: get_time_secs(),
+                };
+                if let Err(err) = send_webhook(&http, url, &payload).await {
+                    tracing::error!("couldn't send alert {} to webhook: {}", key, err);
+                }
+            }
+            if let Some(to) = &config.email {
+                let Some(email_config) = &email_config else {
+                    tracing::error!("can't email alert {}, email isn't set up", key);
+                    return;
+                };
+                let subject = format!("[{}] {}", host, key);
+                // errors are already logged
+                drop(email::send(email_config, to, &subject, message).await);
+            }
+        });
+    }
+
+    /// Records a failed login to an account, and sends an alert if there were
+    /// too many recently.
+    pub fn record_failed_login(&self, deps: &Dependencies, email: &str) {
+        let Some(config) = &deps.config.alerts else {
+            return;
+        };
+
+        let window = Duration::from_secs(config.failed_logins_window_mins * SECS_IN_MIN);
+        let failed = {
+            let mut entry = self
+                .failed_logins
+                .entry(email.into())
+                .or_insert_with(|| (Instant::now(), 0));
+            if entry.0.elapsed() >= window {
+                *entry = (Instant::now(), 0);
+            }
+            entry.1 += 1;
+            entry.1
+        };
+
+        if failed >= config.failed_logins_threshold {
+            self.raise(
+                deps,
+                format!("failed-logins:{}", email),
+                format!(
+                    "{} failed logins to {} in the last {} minutes",
+                    failed, email, config.failed_logins_window_mins
+                ),
+            );
+        }
+    }
+
+    /// Removes failed logins whose window is over.
+    fn prune_failed_logins(&self, config: &AlertsConfig) {
+        let window = Duration::from_secs(config.failed_logins_window_mins * SECS_IN_MIN);
+        self.failed_logins
+            .retain(|_, (started_at, _)| started_at.elapsed() < window);
+    }
+}
+
+async fn send_webhook(
+    http: &HttpClient,
+    url: &str,
+    payload: &AlertPayload<'_>,
+) -> Result<(), ServerError> {
+    let request = http::Request::builder()
+        .method(http::Method::POST)
+        .uri(url)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::to_vec(payload).expect("must be valid json"),
+        ))
+        .map_err(|err| {
+            tracing::error!("invalid alert webhook url {}: {}", url, err);
+            ServerError::InternalServerError
+        })?;
+
+    let response = http.request(request).await?;
+    if !response.status().is_success() {
+        tracing::error!("alert webhook responded with {}", response.status());
+        return Err(ServerError::InternalServerError);
+    }
+
+    Ok(())
+}
+
+/// Spawns a task that checks for federated hosts that are down. Free disk
+/// space is checked by the disk watchdog.
+pub fn spawn_checker(deps: Arc<Dependencies>) {
+    let Some(config) = deps.config.alerts.clone() else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        tracing::info!("starting alert check task");
+        loop {
+            tokio::time::sleep(CHECK_INTERVAL).await;
+
+            if let Err(err) = check_peers(&deps, &config).await {
+                tracing::error!("couldn't check federated hosts for alerts: {}", err);
+            }
+            deps.alerts.prune_failed_logins(&config);
+        }
+    });
+}
+
+/// Sends alerts for hosts whose queued events weren't pulled for too long.
+async fn check_peers(deps: &Dependencies, config: &AlertsConfig) -> Result<(), ServerError> {
+    let now = get_time_secs();
+    let down_after = config.peer_down_after_mins * SECS_IN_MIN;
+    for res in deps.sync_tree.scan_prefix(QUEUED_SINCE_PREFIX).await {
+        let (key, value) = res?;
+        let queued_since = value.as_ref().try_into().map_or(now, u64::from_be_bytes);
+        let down_for = now.saturating_sub(queued_since);
+        if down_for >= down_after {
+            let (_, host_raw) = key.split_at(QUEUED_SINCE_PREFIX.len());
+            let host = String::from_utf8_lossy(host_raw);
+            deps.alerts.raise(
+                deps,
+                format!("peer-down:{}", host),
+                format!(
+                    "federated host {} has been unreachable for {} minutes",
+                    host,
+                    down_for / SECS_IN_MIN
+                ),
+            );
+        }
+    }
+    Ok(())
+}
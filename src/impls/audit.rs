@@ -0,0 +1,75 @@
+//! Per-guild audit log of moderation and administrative actions, so guild
+//! admins can review who did what and when.
+
+use db::audit::{make_audit_log_key, AuditAction, AuditLogEntry};
+
+use super::{gen_rand_u64, get_time_secs, prelude::*};
+use crate::utils::cursor::{Page, Pager};
+
+/// Records actions taken by `actor_id` in a guild.
+pub async fn record_all(
+    deps: &Dependencies,
+    guild_id: u64,
+    actor_id: u64,
+    actions: Vec<AuditAction>,
+) -> Result<(), ServerError> {
+    let created_at = get_time_secs();
+    let mut batch = Batch::default();
+    for action in actions {
+        let entry = AuditLogEntry {
+            actor_id,
+            action,
+            created_at,
+        };
+        batch.insert(
+            make_audit_log_key(guild_id, created_at, gen_rand_u64()),
+            rkyv_ser(&entry),
+        );
+    }
+    deps.audit_tree.apply_batch(batch).await?;
+    Ok(())
+}
+
+/// Records an action taken by `actor_id` in a guild.
+pub async fn record(
+    deps: &Dependencies,
+    guild_id: u64,
+    actor_id: u64,
+    action: AuditAction,
+) -> Result<(), ServerError> {
+    record_all(deps, guild_id, actor_id, vec![action]).await
+}
+
+/// Gets the next page of a guild's audit log from before the given time (or
+/// from now, if not given), in the direction of the pager.
+pub async fn get_audit_log(
+    deps: &Dependencies,
+    guild_id: u64,
+    before: Option<u64>,
+    pager: &Pager,
+) -> Result<Page<AuditLogEntry>, ServerError> {
+    let start = make_audit_log_key(guild_id, 0, 0);
+    let end = match before {
+        Some(before) => make_audit_log_key(guild_id, before.saturating_sub(1), u64::MAX),
+        None => make_audit_log_key(guild_id, u64::MAX, u64::MAX),
+    };
+    let Some((start, end)) = pager.range(&start, &end) else {
+        return Ok(Page::empty());
+    };
+    let entries = deps
+        .audit_tree
+        .range(start.as_slice()..=end.as_slice())
+        .await;
+    Ok(pager.collect(entries, |_, value| Some(db::deser_audit_log_entry(value)))?)
+}
+
+/// Removes the whole audit log of a guild.
+pub async fn delete_audit_log(deps: &Dependencies, guild_id: u64) -> Result<(), ServerError> {
+    let mut batch = Batch::default();
+    for res in deps.audit_tree.scan_prefix(&guild_id.to_be_bytes()).await {
+        let (key, _) = res?;
+        batch.remove(key);
+    }
+    deps.audit_tree.apply_batch(batch).await?;
+    Ok(())
+}
@@ -4,15 +4,22 @@ pub async fn handler(
     svc: &AuthServer,
     _: Request<BeginAuthRequest>,
 ) -> ServerResult<Response<BeginAuthResponse>> {
+    let mut options = ["login", "register", "refresh-session"]
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    if svc.deps.config.auth.oidc.is_some() {
+        options.push("oidc".to_string());
+    }
+    if svc.deps.config.email.is_some() {
+        options.push("forgot-password".to_string());
+    }
     let initial_step = AuthStep {
         can_go_back: false,
         fallback_url: String::default(),
         step: Some(auth_step::Step::Choice(auth_step::Choice {
             title: "initial".to_string(),
-            options: ["login", "register"]
-                .iter()
-                .map(ToString::to_string)
-                .collect(),
+            options,
         })),
     };
 
@@ -0,0 +1,95 @@
+//! Authenticating users against an LDAP directory.
+
+use ldap3::{ldap_escape, LdapConnAsync, Scope, SearchEntry};
+
+use crate::config::LdapConfig;
+
+use super::*;
+
+/// A user found in the LDAP directory, whose password was verified.
+pub struct LdapUser {
+    pub email: String,
+    pub username: String,
+}
+
+fn ldap_err(err: ldap3::LdapError) -> ServerError {
+    tracing::error!("ldap error: {}", err);
+    ServerError::InternalServerError
+}
+
+/// Looks up the user with the given login (which can be their email or the configured
+/// user attribute), and verifies their password by binding as them.
+///
+/// Returns `None` if no such user is in the directory.
+pub async fn authenticate(
+    config: &LdapConfig,
+    login: &str,
+    password: &[u8],
+) -> ServerResult<Option<LdapUser>> {
+    let wrong_password = || ServerError::WrongEmailOrPassword {
+        email: login.into(),
+    };
+
+    // an empty password would make the bind unauthenticated, which always succeeds
+    if password.is_empty() {
+        bail!(wrong_password());
+    }
+    let password = std::str::from_utf8(password).map_err(|_| wrong_password())?;
+
+    let (conn, mut ldap) = LdapConnAsync::new(&config.url).await.map_err(ldap_err)?;
+    ldap3::drive!(conn);
+
+    if let Some(bind_dn) = &config.bind_dn {
+        ldap.simple_bind(bind_dn, config.bind_password.as_deref().unwrap_or(""))
+            .await
+            .and_then(|res| res.success())
+            .map_err(ldap_err)?;
+    }
+
+    let login_escaped = ldap_escape(login);
+    let filter = format!(
+        "(|({}={})({}={}))",
+        config.user_attribute, login_escaped, config.email_attribute, login_escaped
+    );
+    let (entries, _) = ldap
+        .search(
+            &config.base_dn,
+            Scope::Subtree,
+            &filter,
+            vec![
+                config.email_attribute.as_str(),
+                config.username_attribute.as_str(),
+            ],
+        )
+        .await
+        .and_then(|res| res.success())
+        .map_err(ldap_err)?;
+
+    // don't guess which user is meant if the login is ambiguous
+    let mut entries = entries.into_iter();
+    let (Some(entry), None) = (entries.next(), entries.next()) else {
+        let _ = ldap.unbind().await;
+        return Ok(None);
+    };
+    let mut entry = SearchEntry::construct(entry);
+
+    let bind_res = ldap
+        .simple_bind(&entry.dn, password)
+        .await
+        .map_err(ldap_err)?;
+    let _ = ldap.unbind().await;
+    if bind_res.rc != 0 {
+        bail!(wrong_password());
+    }
+
+    let mut take_attr = |name: &str| {
+        entry
+            .attrs
+            .remove(name)
+            .and_then(|values| values.into_iter().next())
+    };
+    let email = take_attr(&config.email_attribute).unwrap_or_else(|| login.to_string());
+    let username = take_attr(&config.username_attribute).unwrap_or_else(|| login.to_string());
+
+    Ok(Some(LdapUser { email, username }))
+}
@@ -4,6 +4,7 @@ pub async fn handler(
     svc: &AuthServer,
     request: Request<LoginFederatedRequest>,
 ) -> Result<Response<LoginFederatedResponse>, HrpcServerError> {
+    let device = sessions::get_device(&request);
     let LoginFederatedRequest {
         auth_token,
         server_id,
@@ -13,8 +14,9 @@ pub async fn handler(
 
     if let Some(token) = auth_token {
         let keys_manager = svc.keys_manager()?;
-        let pubkey = keys_manager.get_key(server_id.into()).await?;
-        keys::verify_token(&token, &pubkey)?;
+        keys_manager
+            .verify_host_token(server_id.into(), &token)
+            .await?;
         let TokenData {
             user_id: foreign_id,
             server_id,
@@ -67,7 +69,7 @@ pub async fn handler(
             session_token: session_token.to_string(),
             user_id: local_user_id,
         };
-        svc.deps.valid_sessions.insert(session_token, local_user_id);
+        sessions::create_session(&svc.deps, local_user_id, session_token, device).await?;
 
         return Ok((LoginFederatedResponse {
             session: Some(session),
@@ -1,4 +1,7 @@
-use std::time::Duration;
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
 
 use ahash::RandomState;
 use dashmap::DashMap;
@@ -12,7 +15,7 @@ use sha3::Digest;
 use tokio::sync::mpsc::{self, Sender};
 use tracing::Instrument;
 
-use crate::key::{self as keys, Manager as KeyManager};
+use crate::key::Manager as KeyManager;
 
 use super::{gen_rand_arr, gen_rand_inline_str, gen_rand_u64, get_time_secs, prelude::*};
 
@@ -27,14 +30,48 @@ pub mod begin_auth;
 pub mod check_logged_in;
 pub mod federate;
 pub mod key;
+#[cfg(feature = "ldap")]
+pub mod ldap;
 pub mod login_federated;
 pub mod next_step;
+pub mod oidc;
+pub mod protection;
+pub mod refresh_tokens;
+pub mod registration_challenge;
+pub mod sessions;
 pub mod step_back;
 pub mod stream_steps;
 
-const SESSION_EXPIRE: u64 = 60 * 60 * 24 * 2;
+pub type SessionMap = Arc<DashMap<SmolStr, ValidSession, RandomState>>;
+
+/// A session token that can be used to authenticate.
+pub struct ValidSession {
+    pub user_id: u64,
+    /// In seconds since unix epoch.
+    last_used: AtomicU64,
+    /// When the token stops working, in seconds since unix epoch. Only set
+    /// for sessions that have a refresh token.
+    expires_at: Option<u64>,
+}
+
+impl ValidSession {
+    pub fn new(user_id: u64, last_used: u64) -> Self {
+        Self {
+            user_id,
+            last_used: AtomicU64::new(last_used),
+            expires_at: None,
+        }
+    }
 
-pub type SessionMap = Arc<DashMap<SmolStr, u64, RandomState>>;
+    pub fn expiring_at(mut self, expires_at: Option<u64>) -> Self {
+        self.expires_at = expires_at;
+        self
+    }
+
+    pub fn last_used(&self) -> u64 {
+        self.last_used.load(Ordering::Relaxed)
+    }
+}
 
 pub trait AuthExt {
     fn auth_header_map(&self, headers: &HeaderMap) -> Result<u64, ServerError>;
@@ -47,33 +84,43 @@ pub trait AuthExt {
     }
 }
 
-impl AuthExt for DashMap<SmolStr, u64, RandomState> {
+impl AuthExt for DashMap<SmolStr, ValidSession, RandomState> {
     fn auth_header_map(&self, headers: &HeaderMap) -> Result<u64, ServerError> {
-        let auth_id = headers
-            .get(http::header::AUTHORIZATION)
-            .map_or_else(
-                || {
-                    // Specific handling for web clients
-                    headers
-                        .get(http::header::SEC_WEBSOCKET_PROTOCOL)
-                        .and_then(|h| h.to_str().ok())
-                        .and_then(|v| v.split(',').map(str::trim).last())
-                },
-                |val| val.to_str().ok(),
-            )
-            .unwrap_or("");
-
-        self.get(auth_id)
-            .as_deref()
-            .copied()
+        let now = get_time_secs();
+        self.get(get_session_token(headers))
+            .filter(|session| session.expires_at.map_or(true, |at| now < at))
+            .map(|session| {
+                session.last_used.store(now, Ordering::Relaxed);
+                session.user_id
+            })
             .map_or(Err(ServerError::Unauthenticated), Ok)
     }
 }
+
+/// Gets the session token a request was sent with, empty if there is none.
+pub fn get_session_token(headers: &HeaderMap) -> &str {
+    headers
+        .get(http::header::AUTHORIZATION)
+        .map_or_else(
+            || {
+                // Specific handling for web clients
+                headers
+                    .get(http::header::SEC_WEBSOCKET_PROTOCOL)
+                    .and_then(|h| h.to_str().ok())
+                    .and_then(|v| v.split(',').map(str::trim).last())
+            },
+            |val| val.to_str().ok(),
+        )
+        .unwrap_or("")
+}
+
 #[derive(Clone)]
 pub struct AuthServer {
     step_map: Arc<DashMap<SmolStr, Vec<AuthStep>, RandomState>>,
     send_step: Arc<DashMap<SmolStr, Sender<AuthStep>, RandomState>>,
     queued_steps: Arc<DashMap<SmolStr, Vec<AuthStep>, RandomState>>,
+    /// OpenID Connect states sent to the provider, by auth ID.
+    oidc_states: Arc<DashMap<SmolStr, SmolStr, RandomState>>,
     disable_ratelimits: bool,
     deps: Arc<Dependencies>,
 }
@@ -83,90 +130,41 @@ impl AuthServer {
         let att = deps.auth_tree.clone();
         let ptt = deps.profile_tree.clone();
         let vs = deps.valid_sessions.clone();
+        let auth_config = deps.config.auth.clone();
+
+        #[cfg(not(feature = "ldap"))]
+        if deps.config.auth.ldap.is_some() {
+            tracing::warn!("ldap is configured, but scherzo wasn't built with the `ldap` feature");
+        }
+        #[cfg(not(feature = "email"))]
+        if deps.config.email.is_some() {
+            tracing::warn!(
+                "email is configured, but scherzo wasn't built with the `email` feature"
+            );
+        }
 
         tokio::spawn(
             (async move {
                 tracing::info!("starting auth session expiration check thread");
 
-                // Safety: the right portion of the key after split at the prefix length MUST be a valid u64
-                async unsafe fn scan_tree_for(
-                    att: &Tree,
-                    prefix: &[u8],
-                ) -> ServerResult<Vec<(u64, EVec)>> {
-                    let len = prefix.len();
-                    att.scan_prefix(prefix)
-                        .await
-                        .try_fold(Vec::new(), move |mut all, res| {
-                            let (key, val) = res.map_err(ServerError::from)?;
-                            all.push((
-                                u64::from_be_bytes(
-                                    key.split_at(len).1.try_into().unwrap_unchecked(),
-                                ),
-                                val,
-                            ));
-                            ServerResult::Ok(all)
-                        })
-                }
-
                 loop {
-                    // Safety: we never insert non u64 keys for tokens [tag:token_u64_key]
-                    let tokens = unsafe { scan_tree_for(&att.inner, TOKEN_PREFIX).await };
-                    // Safety: we never insert non u64 keys for atimes [tag:atime_u64_key]
-                    let atimes = unsafe { scan_tree_for(&att.inner, ATIME_PREFIX).await };
-
-                    match tokens.and_then(|tokens| Ok((tokens, atimes?))) {
-                        Ok((tokens, atimes)) => {
-                            let mut batch = Batch::default();
-                            for (id, raw_token) in tokens {
-                                if let Ok(profile) = ptt.get_profile_logic(id).await {
-                                    for (oid, raw_atime) in &atimes {
-                                        if id.eq(oid) {
-                                            // Safety: raw_atime's we store are always u64s [tag:atime_u64_value]
-                                            let secs = u64::from_be_bytes(unsafe {
-                                                raw_atime.as_ref().try_into().unwrap_unchecked()
-                                            });
-                                            let auth_how_old = get_time_secs() - secs;
-                                            // Safety: all of our tokens are valid str's, we never generate invalid ones [ref:alphanumeric_auth_token_gen]
-                                            let token = unsafe {
-                                                std::str::from_utf8_unchecked(raw_token.as_ref())
-                                            };
-
-                                            if vs.contains_key(token) {
-                                                // [ref:atime_u64_key] [ref:atime_u64_value]
-                                                batch.insert(
-                                                    atime_key(id).to_vec(),
-                                                    get_time_secs().to_be_bytes().to_vec(),
-                                                );
-                                            } else if !profile.is_bot
-                                                && auth_how_old >= SESSION_EXPIRE
-                                            {
-                                                tracing::debug!("user {} session has expired", id);
-                                                batch.remove(token_key(id).to_vec());
-                                                batch.remove(atime_key(id).to_vec());
-                                                vs.remove(token);
-                                            } else {
-                                                // Safety: all of our tokens are 22 chars long, so this can never panic [ref:auth_token_length]
-                                                vs.insert(SmolStr::new_inline(token), id);
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                            if let Err(err) = att
-                                .inner
-                                .apply_batch(batch)
-                                .await
-                                .map_err(ServerError::DbError)
-                            {
-                                tracing::error!("error applying auth token batch: {}", err);
-                            }
-                        }
-                        Err(err) => {
-                            tracing::error!("error scanning tree for tokens: {}", err);
-                        }
+                    if let Err(err) = sessions::check_sessions(&att, &ptt, &vs, &auth_config).await
+                    {
+                        tracing::error!("error checking auth sessions: {}", err);
+                    }
+                    if let Err(err) = att.remove_expired_password_resets_logic().await {
+                        tracing::error!("error removing expired password resets: {}", err);
+                    }
+                    if let Err(err) = att
+                        .remove_expired_login_failures_logic(
+                            auth_config.protection.failure_window_secs,
+                        )
+                        .await
+                    {
+                        tracing::error!("error removing expired failed logins: {}", err);
                     }
 
-                    std::thread::sleep(Duration::from_secs(60 * 5));
+                    tokio::time::sleep(Duration::from_secs(60 * 5)).await;
                 }
             })
             .instrument(tracing::info_span!("auth_session_check")),
@@ -176,6 +174,7 @@ impl AuthServer {
             step_map: DashMap::default().into(),
             send_step: DashMap::default().into(),
             queued_steps: DashMap::default().into(),
+            oidc_states: DashMap::default().into(),
             disable_ratelimits: deps.config.policy.ratelimit.disable,
             deps,
         }
@@ -215,13 +214,7 @@ impl AuthServer {
     }
 
     fn is_host_allowed(&self, host: &str) -> Result<(), ServerError> {
-        self.deps
-            .config
-            .federation
-            .as_ref()
-            .map_or(Err(ServerError::FederationDisabled), |conf| {
-                conf.is_host_allowed(host)
-            })
+        self.deps.is_host_allowed(host)
     }
 }
 
@@ -276,6 +269,131 @@ impl AuthTree {
         }
         Ok(token)
     }
+
+    /// Gets the ID of the user registered with the given email
+    pub async fn get_user_id_by_email(&self, email: &str) -> ServerResult<Option<u64>> {
+        Ok(self.get(email.as_bytes()).await?.map(|raw| {
+            // Safety: this unwrap can never cause UB since we only store u64
+            u64::from_be_bytes(unsafe { raw.try_into().unwrap_unchecked() })
+        }))
+    }
+
+    /// Sets a new password for a user, and removes all of their sessions
+    /// and refresh tokens
+    pub async fn reset_password_logic(
+        &self,
+        user_id: u64,
+        new_password: &[u8],
+    ) -> ServerResult<()> {
+        if !self.contains_key(user_id.to_be_bytes()).await? {
+            bail!(ServerError::NoSuchUser(user_id));
+        }
+
+        let mut batch = Batch::default();
+        batch.insert(user_id.to_be_bytes(), hash_password(new_password).as_ref());
+        for (token, _) in self.get_sessions_logic(user_id).await? {
+            batch.remove(make_session_key(user_id, &token));
+        }
+        for (session_id, token_hashed) in self.get_refresh_tokens_logic(user_id).await? {
+            batch.remove(make_session_refresh_token_key(user_id, session_id));
+            batch.remove(refresh_token_key(&token_hashed));
+        }
+        self.apply_batch(batch).await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_expired_password_resets_logic(&self) -> ServerResult<()> {
+        let now = get_time_secs();
+        let mut batch = Batch::default();
+        for res in self.scan_prefix(PASSWORD_RESET_PREFIX).await {
+            let (key, value) = res?;
+            if now >= db::deser_password_reset(value).expires_at {
+                batch.remove(key);
+            }
+        }
+        self.apply_batch(batch).await?;
+        Ok(())
+    }
+
+    /// Gets the recent failed logins stored under a key, made with
+    /// [`make_account_login_failures_key`], [`make_ip_login_failures_key`] or
+    /// [`make_account_ip_login_failures_key`].
+    pub async fn get_login_failures_logic(&self, key: &[u8]) -> ServerResult<LoginFailures> {
+        let failures = self
+            .get(key)
+            .await?
+            .map_or_else(LoginFailures::default, db::deser_login_failures);
+
+        Ok(failures)
+    }
+
+    /// Removes failed logins that are forgotten and aren't locking anything out.
+    pub async fn remove_expired_login_failures_logic(&self, window_secs: u64) -> ServerResult<()> {
+        let now = get_time_secs();
+        let mut batch = Batch::default();
+        for res in self.scan_prefix(LOGIN_FAILURES_PREFIX).await {
+            let (key, value) = res?;
+            let failures = db::deser_login_failures(value);
+            if now >= failures.locked_until && now >= failures.last_failed_at + window_secs {
+                batch.remove(key);
+            }
+        }
+        self.apply_batch(batch).await?;
+        Ok(())
+    }
+
+    /// Gets all sessions of a user, with their tokens.
+    pub async fn get_sessions_logic(
+        &self,
+        user_id: u64,
+    ) -> ServerResult<Vec<(SmolStr, SessionInfo)>> {
+        self.scan_prefix(make_user_sessions_prefix(user_id))
+            .await
+            .map(|res| {
+                let (key, value) = res?;
+                // [ref:session_key]
+                let token = split_session_key(&key)
+                    .map_or_else(SmolStr::default, |(_, token)| token.into());
+                Ok((token, db::deser_session(value)))
+            })
+            .collect()
+    }
+
+    /// Gets the IDs of the sessions of a user that have a refresh token, with
+    /// their hashed refresh tokens.
+    pub async fn get_refresh_tokens_logic(
+        &self,
+        user_id: u64,
+    ) -> ServerResult<Vec<(u64, Vec<u8>)>> {
+        let prefix = make_user_refresh_tokens_prefix(user_id);
+        self.scan_prefix(&prefix)
+            .await
+            .map(|res| {
+                let (key, value) = res?;
+                // Safety: the rest of the key is always a u64 session ID
+                let session_id = u64::from_be_bytes(unsafe {
+                    key.split_at(prefix.len()).1.try_into().unwrap_unchecked()
+                });
+                Ok((session_id, value.to_vec()))
+            })
+            .collect()
+    }
+
+    /// Adds removing the refresh token of a session, if it has one, to a batch.
+    pub async fn remove_refresh_token_logic(
+        &self,
+        batch: &mut Batch,
+        user_id: u64,
+        session_id: u64,
+    ) -> ServerResult<()> {
+        let key = make_session_refresh_token_key(user_id, session_id);
+        if let Some(token_hashed) = self.get(key).await? {
+            batch.remove(refresh_token_key(&token_hashed));
+            batch.remove(key);
+        }
+        Ok(())
+    }
 }
 
 #[inline(always)]
@@ -298,6 +416,21 @@ const USERNAME_FIELD_ERR: ServerError = ServerError::WrongTypeForField {
     expected: SmolStr::new_inline("text"),
 };
 
+const CODE_FIELD_ERR: ServerError = ServerError::WrongTypeForField {
+    name: SmolStr::new_inline("code"),
+    expected: SmolStr::new_inline("text"),
+};
+
+const STATE_FIELD_ERR: ServerError = ServerError::WrongTypeForField {
+    name: SmolStr::new_inline("state"),
+    expected: SmolStr::new_inline("text"),
+};
+
+const CHALLENGE_FIELD_ERR: ServerError = ServerError::WrongTypeForField {
+    name: SmolStr::new_inline("challenge"),
+    expected: SmolStr::new_inline("text"),
+};
+
 const TOKEN_FIELD_ERR: ServerError = ServerError::WrongTypeForField {
     name: SmolStr::new_inline("token"),
     expected: SmolStr::new_inline("bytes"),
@@ -336,6 +469,21 @@ fn try_get_password(values: &mut Vec<Field>) -> ServerResult<Vec<u8>> {
     try_get_bytes(values, PASSWORD_FIELD_ERR)
 }
 
+#[inline(always)]
+fn try_get_code(values: &mut Vec<Field>) -> ServerResult<String> {
+    try_get_string(values, CODE_FIELD_ERR)
+}
+
+#[inline(always)]
+fn try_get_state(values: &mut Vec<Field>) -> ServerResult<String> {
+    try_get_string(values, STATE_FIELD_ERR)
+}
+
+#[inline(always)]
+fn try_get_challenge(values: &mut Vec<Field>) -> ServerResult<String> {
+    try_get_string(values, CHALLENGE_FIELD_ERR)
+}
+
 #[inline(always)]
 fn try_get_token(values: &mut Vec<Field>) -> ServerResult<Vec<u8>> {
     try_get_bytes(values, TOKEN_FIELD_ERR)
@@ -3,6 +3,9 @@ use harmony_rust_sdk::api::auth::{auth_step::form::FormField, next_step_request:
 use super::*;
 
 pub mod login;
+pub mod oidc;
+pub mod password_reset;
+pub mod refresh_session;
 pub mod registration;
 
 // While implementing new choices / forms, make sure to:
@@ -12,6 +15,8 @@ pub async fn handler(
     svc: &AuthServer,
     req: Request<NextStepRequest>,
 ) -> ServerResult<Response<NextStepResponse>> {
+    let device = sessions::get_device(&req);
+    let client_ip = svc.deps.rate_limiter.client_ip(&req);
     let NextStepRequest {
         auth_id,
         step: maybe_step,
@@ -48,7 +53,8 @@ pub async fn handler(
             tracing::debug!("client replied with {:#?}", step);
             match step {
                 next_step_request::Step::Choice(next_step_request::Choice { choice }) => {
-                    let auth_step::Step::Choice(auth_step::Choice { options, .. }) = current_step else {
+                    let auth_step::Step::Choice(auth_step::Choice { options, .. }) = current_step
+                    else {
                         bail!(ServerError::WrongStep {
                             expected: SmolStr::new_inline("form"),
                             got: SmolStr::new_inline("choice"),
@@ -56,7 +62,7 @@ pub async fn handler(
                     };
 
                     if options.contains(&choice) {
-                        next_step = handle_choice(svc, choice.as_str())?;
+                        next_step = handle_choice(svc, &auth_id, choice.as_str())?;
                         step_stack.push(next_step.clone());
                     } else {
                         bail!(ServerError::NoSuchChoice {
@@ -69,7 +75,8 @@ pub async fn handler(
                     let auth_step::Step::Form(auth_step::Form {
                         fields: auth_fields,
                         title,
-                    }) = current_step else {
+                    }) = current_step
+                    else {
                         bail!(ServerError::WrongStep {
                             expected: SmolStr::new_inline("choice"),
                             got: SmolStr::new_inline("form"),
@@ -82,13 +89,36 @@ pub async fn handler(
 
                     // handle new forms here
                     match title.as_str() {
-                        "login" => next_step = login::handle(svc, &mut values).await?,
-                        "register" => next_step = registration::handle(svc, &mut values).await?,
+                        "login" => {
+                            next_step = login::handle(svc, &mut values, device, client_ip).await?
+                        }
+                        "register" => {
+                            next_step =
+                                registration::handle(svc, &auth_id, &mut values, device).await?
+                        }
+                        "refresh-session" => {
+                            next_step = refresh_session::handle(svc, &mut values, device).await?
+                        }
+                        "oidc" => {
+                            next_step = oidc::handle(svc, &auth_id, &mut values, device).await?
+                        }
+                        "forgot-password" => {
+                            next_step = password_reset::handle_forgot(svc, &mut values).await?
+                        }
+                        "reset-password" => {
+                            next_step =
+                                password_reset::handle_reset(svc, &mut values, device).await?
+                        }
                         title => bail!((
                             "h.invalid-form",
                             format!("invalid form name used: {}", title)
                         )),
                     }
+
+                    // forms can lead to other forms, which have to be checked against
+                    if let Some(auth_step::Step::Form(_)) = &next_step.step {
+                        step_stack.push(next_step.clone());
+                    }
                 }
             }
         }
@@ -117,6 +147,7 @@ pub async fn handler(
         );
         svc.step_map.remove(auth_id.as_str());
         svc.queued_steps.remove(auth_id.as_str());
+        svc.oidc_states.remove(auth_id.as_str());
     }
 
     Ok((NextStepResponse {
@@ -125,7 +156,7 @@ pub async fn handler(
     .into_response())
 }
 
-pub fn handle_choice(svc: &AuthServer, choice: &str) -> ServerResult<AuthStep> {
+pub fn handle_choice(svc: &AuthServer, auth_id: &str, choice: &str) -> ServerResult<AuthStep> {
     let step = match choice {
         "login" => AuthStep {
             can_go_back: true,
@@ -165,6 +196,13 @@ pub fn handle_choice(svc: &AuthServer, choice: &str) -> ServerResult<AuthStep> {
                     r#type: "password".to_string(),
                 });
             }
+            // the challenge itself can be fetched from `/_scherzo/auth/registration-challenge`
+            if svc.deps.config.policy.registration_challenge.is_some() {
+                fields.push(auth_step::form::FormField {
+                    name: "challenge".to_string(),
+                    r#type: "text".to_string(),
+                });
+            }
             AuthStep {
                 can_go_back: true,
                 fallback_url: String::default(),
@@ -174,6 +212,54 @@ pub fn handle_choice(svc: &AuthServer, choice: &str) -> ServerResult<AuthStep> {
                 })),
             }
         }
+        "refresh-session" => AuthStep {
+            can_go_back: true,
+            fallback_url: String::default(),
+            step: Some(auth_step::Step::Form(auth_step::Form {
+                title: "refresh-session".to_string(),
+                fields: vec![auth_step::form::FormField {
+                    name: "token".to_string(),
+                    r#type: "password".to_string(),
+                }],
+            })),
+        },
+        "forgot-password" => AuthStep {
+            can_go_back: true,
+            fallback_url: String::default(),
+            step: Some(auth_step::Step::Form(auth_step::Form {
+                title: "forgot-password".to_string(),
+                fields: vec![auth_step::form::FormField {
+                    name: "email".to_string(),
+                    r#type: "email".to_string(),
+                }],
+            })),
+        },
+        "oidc" => {
+            let Some(oidc_config) = &svc.deps.config.auth.oidc else {
+                bail!(("h.invalid-choice", "OpenID Connect login is not enabled"));
+            };
+            let state = gen_rand_inline_str();
+            let fallback_url = super::oidc::authorization_url(oidc_config, &state);
+            svc.oidc_states.insert(auth_id.into(), state);
+            AuthStep {
+                can_go_back: true,
+                // clients open this to log in, and submit the code and state they get back
+                fallback_url,
+                step: Some(auth_step::Step::Form(auth_step::Form {
+                    title: "oidc".to_string(),
+                    fields: vec![
+                        auth_step::form::FormField {
+                            name: "code".to_string(),
+                            r#type: "text".to_string(),
+                        },
+                        auth_step::form::FormField {
+                            name: "state".to_string(),
+                            r#type: "text".to_string(),
+                        },
+                    ],
+                })),
+            }
+        }
         choice => bail!((
             "h.invalid-choice",
             format!("got invalid choice: {}", choice),
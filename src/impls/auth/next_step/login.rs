@@ -1,17 +1,42 @@
+use std::net::IpAddr;
+
+use crate::impls::auth::protection::{self, LoginSource};
+
 use super::*;
 
-pub async fn handle(svc: &AuthServer, values: &mut Vec<Field>) -> ServerResult<AuthStep> {
+pub async fn handle(
+    svc: &AuthServer,
+    values: &mut Vec<Field>,
+    device: String,
+    client_ip: Option<IpAddr>,
+) -> ServerResult<AuthStep> {
     let auth_tree = &svc.deps.auth_tree;
 
     let password_raw = try_get_password(values)?;
-    let password_hashed = hash_password(password_raw);
+    let password_hashed = hash_password(&password_raw);
     let email = try_get_email(values)?;
 
+    let source = LoginSource {
+        email: &email,
+        ip: client_ip,
+    };
+    protection::check_login(&svc.deps, &source).await?;
+
+    #[cfg(feature = "ldap")]
+    if let Some(ldap_config) = &svc.deps.config.auth.ldap {
+        // users that aren't in the directory can still log in with a local account
+        if let Some(ldap_user) = ldap::authenticate(ldap_config, &email, &password_raw).await? {
+            let user_id = get_or_create_ldap_user(svc, ldap_user).await?;
+            return start_session(svc, user_id, &email, device).await;
+        }
+    }
+
     let maybe_user_id = auth_tree.get(email.as_bytes()).await?.map(|raw| {
         // Safety: this unwrap can never cause UB since we only store u64
         u64::from_be_bytes(unsafe { raw.try_into().unwrap_unchecked() })
     });
     let Some(user_id) = maybe_user_id else {
+        protection::record_failed_login(&svc.deps, &source, None).await?;
         bail!(ServerError::WrongEmailOrPassword {
             email: email.into(),
         });
@@ -23,29 +48,29 @@ pub async fn handle(svc: &AuthServer, values: &mut Vec<Field>) -> ServerResult<A
         .await?
         .map_or(false, |pass| pass.as_ref() == password_hashed.as_ref());
     if !is_password_correct {
+        svc.deps.alerts.record_failed_login(&svc.deps, &email);
+        protection::record_failed_login(&svc.deps, &source, Some(user_id)).await?;
         bail!(ServerError::WrongEmailOrPassword {
             email: email.into(),
         });
     }
 
+    protection::clear_failed_logins(&svc.deps, &source).await?;
+
+    start_session(svc, user_id, &email, device).await
+}
+
+async fn start_session(
+    svc: &AuthServer,
+    user_id: u64,
+    email: &str,
+    device: String,
+) -> ServerResult<AuthStep> {
     let session_token = svc.gen_auth_token(); // [ref:alphanumeric_auth_token_gen] [ref:auth_token_length]
-    let mut batch = Batch::default();
-    // [ref:token_u64_key]
-    batch.insert(token_key(user_id), session_token.as_str().as_bytes());
-    batch.insert(
-        // [ref:atime_u64_key]
-        atime_key(user_id),
-        // [ref:atime_u64_value]
-        get_time_secs().to_be_bytes(),
-    );
-    auth_tree.apply_batch(batch).await?;
+    sessions::create_session(&svc.deps, user_id, session_token.clone(), device).await?;
 
     tracing::debug!("user {} logged in with email {}", user_id, email);
 
-    svc.deps
-        .valid_sessions
-        .insert(session_token.clone(), user_id);
-
     Ok(AuthStep {
         can_go_back: false,
         fallback_url: String::default(),
@@ -55,3 +80,34 @@ pub async fn handle(svc: &AuthServer, values: &mut Vec<Field>) -> ServerResult<A
         })),
     })
 }
+
+/// Gets the local account linked to an LDAP user by email, creating one if there is none.
+#[cfg(feature = "ldap")]
+async fn get_or_create_ldap_user(svc: &AuthServer, ldap_user: ldap::LdapUser) -> ServerResult<u64> {
+    let auth_tree = &svc.deps.auth_tree;
+
+    if let Some(user_id) = auth_tree.get_user_id_by_email(&ldap_user.email).await? {
+        return Ok(user_id);
+    }
+
+    let user_id = svc.gen_user_id().await?;
+
+    let mut batch = Batch::default();
+    batch.insert(ldap_user.email.into_bytes(), user_id.to_be_bytes());
+    // the password is checked by the directory, so no password hash will ever match this
+    batch.insert(user_id.to_be_bytes(), []);
+    auth_tree.apply_batch(batch).await?;
+
+    let buf = rkyv_ser(&Profile {
+        user_name: ldap_user.username,
+        ..Default::default()
+    });
+    svc.deps
+        .profile_tree
+        .insert(make_user_profile_key(user_id), buf)
+        .await?;
+
+    tracing::debug!("new user {} created from ldap", user_id);
+
+    Ok(user_id)
+}
@@ -0,0 +1,83 @@
+use crate::impls::auth::oidc::{self as provider, OidcUser};
+
+use super::*;
+
+pub async fn handle(
+    svc: &AuthServer,
+    auth_id: &str,
+    values: &mut Vec<Field>,
+    device: String,
+) -> ServerResult<AuthStep> {
+    let Some(oidc_config) = &svc.deps.config.auth.oidc else {
+        bail!(("h.invalid-form", "OpenID Connect login is not enabled"));
+    };
+
+    let state = try_get_state(values)?;
+    let code = try_get_code(values)?;
+    // a state can only be tried once, so it can't be guessed
+    let expected_state = svc.oidc_states.remove(auth_id).map(|(_, state)| state);
+    if expected_state.as_deref() != Some(state.as_str()) {
+        bail!((
+            "h.invalid-oidc-state",
+            "the OpenID Connect state doesn't match this auth session"
+        ));
+    }
+    if code.is_empty() {
+        bail!(("h.invalid-oidc-code", "code can't be empty"));
+    }
+
+    let oidc_user = provider::authenticate(&svc.deps.http, oidc_config, &code).await?;
+    let user_id = get_or_create_oidc_user(svc, &oidc_config.issuer, oidc_user).await?;
+
+    let session_token = svc.gen_auth_token(); // [ref:alphanumeric_auth_token_gen] [ref:auth_token_length]
+    sessions::create_session(&svc.deps, user_id, session_token.clone(), device).await?;
+
+    tracing::debug!("user {} logged in with openid connect", user_id);
+
+    Ok(AuthStep {
+        can_go_back: false,
+        fallback_url: String::default(),
+        step: Some(auth_step::Step::Session(Session {
+            user_id,
+            session_token: session_token.into(),
+        })),
+    })
+}
+
+/// Gets the local account linked to a provider account, creating one if there is none.
+async fn get_or_create_oidc_user(
+    svc: &AuthServer,
+    issuer: &str,
+    oidc_user: OidcUser,
+) -> ServerResult<u64> {
+    let auth_tree = &svc.deps.auth_tree;
+
+    let subject_key = make_oidc_subject_key(issuer, &oidc_user.sub);
+    if let Some(raw) = auth_tree.get(&subject_key).await? {
+        // Safety: this unwrap can never cause UB since we only store u64
+        return Ok(u64::from_be_bytes(unsafe {
+            raw.try_into().unwrap_unchecked()
+        }));
+    }
+
+    let user_id = svc.gen_user_id().await?;
+
+    let mut batch = Batch::default();
+    batch.insert(subject_key, user_id.to_be_bytes());
+    // the password is checked by the provider, so no password hash will ever match this
+    batch.insert(user_id.to_be_bytes(), []);
+    auth_tree.apply_batch(batch).await?;
+
+    let buf = rkyv_ser(&Profile {
+        user_name: oidc_user.username(),
+        ..Default::default()
+    });
+    svc.deps
+        .profile_tree
+        .insert(make_user_profile_key(user_id), buf)
+        .await?;
+
+    tracing::debug!("new user {} created from openid connect", user_id);
+
+    Ok(user_id)
+}
@@ -0,0 +1,120 @@
+//! Resetting a forgotten password with a token sent by email.
+
+use crate::impls::email;
+
+use super::*;
+
+const INVALID_TOKEN_ERR: (&str, &str) = (
+    "h.invalid-password-reset-token",
+    "password reset token is invalid or has expired",
+);
+
+/// Sends a password reset token to the email of an account, if there is one.
+pub async fn handle_forgot(svc: &AuthServer, values: &mut Vec<Field>) -> ServerResult<AuthStep> {
+    let Some(email_config) = &svc.deps.config.email else {
+        bail!(("h.invalid-form", "password resets are not enabled"));
+    };
+    let auth_tree = &svc.deps.auth_tree;
+
+    let email = try_get_email(values)?;
+
+    // the same step is returned whether the account exists or not, so that
+    // this can't be used to find out which emails have accounts
+    if let Some(user_id) = auth_tree.get_user_id_by_email(&email).await? {
+        let ttl = svc.deps.config.auth.password_reset_ttl;
+        let token = gen_rand_inline_str();
+        let reset = PasswordReset {
+            user_id,
+            expires_at: get_time_secs() + ttl,
+        };
+        auth_tree
+            .insert(
+                password_reset_key(hash_password(token.as_bytes()).as_ref()),
+                rkyv_ser(&reset),
+            )
+            .await?;
+
+        let body = format!(
+            "Someone asked to reset the password of your account on {}.\n\n\
+            Your password reset token is: {}\n\n\
+            It can be used for {} minutes. If you didn't ask for this, you can ignore this email.",
+            svc.deps.config.host,
+            token,
+            ttl / 60,
+        );
+        if let Err(err) = email::send(email_config, &email, "Password reset", body).await {
+            tracing::error!(
+                "couldn't send password reset email to user {}: {}",
+                user_id,
+                err
+            );
+        } else {
+            tracing::debug!("sent password reset email to user {}", user_id);
+        }
+    }
+
+    Ok(AuthStep {
+        can_go_back: true,
+        fallback_url: String::default(),
+        step: Some(auth_step::Step::Form(auth_step::Form {
+            title: "reset-password".to_string(),
+            fields: vec![
+                auth_step::form::FormField {
+                    name: "token".to_string(),
+                    r#type: "password".to_string(),
+                },
+                auth_step::form::FormField {
+                    name: "password".to_string(),
+                    r#type: "new-password".to_string(),
+                },
+            ],
+        })),
+    })
+}
+
+/// Sets a new password with a password reset token, logging the user in.
+pub async fn handle_reset(
+    svc: &AuthServer,
+    values: &mut Vec<Field>,
+    device: String,
+) -> ServerResult<AuthStep> {
+    let auth_tree = &svc.deps.auth_tree;
+
+    let password_raw = try_get_password(values)?;
+    if password_raw.is_empty() {
+        bail!(("h.invalid-password", "password can't be empty"));
+    }
+    let token_raw = try_get_token(values)?;
+
+    let key = password_reset_key(hash_password(token_raw).as_ref());
+    let Some(raw) = auth_tree.get(&key).await? else {
+        bail!(INVALID_TOKEN_ERR);
+    };
+    let reset = db::deser_password_reset(raw);
+    auth_tree.remove(&key).await?;
+    if get_time_secs() >= reset.expires_at {
+        bail!(INVALID_TOKEN_ERR);
+    }
+
+    // whoever knew the old password shouldn't stay logged in
+    for (token, _) in auth_tree.get_sessions_logic(reset.user_id).await? {
+        svc.deps.valid_sessions.remove(token.as_str());
+    }
+    auth_tree
+        .reset_password_logic(reset.user_id, &password_raw)
+        .await?;
+
+    let session_token = svc.gen_auth_token(); // [ref:alphanumeric_auth_token_gen] [ref:auth_token_length]
+    sessions::create_session(&svc.deps, reset.user_id, session_token.clone(), device).await?;
+
+    tracing::debug!("user {} reset their password", reset.user_id);
+
+    Ok(AuthStep {
+        can_go_back: false,
+        fallback_url: String::default(),
+        step: Some(auth_step::Step::Session(Session {
+            user_id: reset.user_id,
+            session_token: session_token.into(),
+        })),
+    })
+}
@@ -0,0 +1,34 @@
+use super::*;
+
+pub async fn handle(
+    svc: &AuthServer,
+    values: &mut Vec<Field>,
+    device: String,
+) -> ServerResult<AuthStep> {
+    let token_raw = try_get_token(values)?;
+    let Ok(old_token) = std::str::from_utf8(&token_raw) else {
+        bail!(ServerError::Unauthenticated);
+    };
+    let Some(user_id) = svc
+        .deps
+        .valid_sessions
+        .get(old_token)
+        .map(|session| session.user_id)
+    else {
+        bail!(ServerError::Unauthenticated);
+    };
+
+    let session_token = svc.gen_auth_token(); // [ref:alphanumeric_auth_token_gen] [ref:auth_token_length]
+    sessions::refresh_session(&svc.deps, user_id, old_token, session_token.clone(), device).await?;
+
+    tracing::debug!("user {} refreshed a session", user_id);
+
+    Ok(AuthStep {
+        can_go_back: false,
+        fallback_url: String::default(),
+        step: Some(auth_step::Step::Session(Session {
+            user_id,
+            session_token: session_token.into(),
+        })),
+    })
+}
@@ -1,8 +1,19 @@
 use super::*;
 
-pub async fn handle(svc: &AuthServer, values: &mut Vec<Field>) -> ServerResult<AuthStep> {
+pub async fn handle(
+    svc: &AuthServer,
+    auth_id: &str,
+    values: &mut Vec<Field>,
+    device: String,
+) -> ServerResult<AuthStep> {
     let auth_tree = &svc.deps.auth_tree;
 
+    // checked first, so nothing else is looked at for unsolved challenges
+    if svc.deps.config.policy.registration_challenge.is_some() {
+        let solution = try_get_challenge(values)?;
+        registration_challenge::verify(&svc.deps, auth_id, &solution).await?;
+    }
+
     if svc.deps.config.policy.disable_registration {
         let token_raw = try_get_token(values)?;
         if token_raw.is_empty() {
@@ -45,14 +56,6 @@ pub async fn handle(svc: &AuthServer, values: &mut Vec<Field>) -> ServerResult<A
     let mut batch = Batch::default();
     batch.insert(email.into_bytes(), user_id.to_be_bytes());
     batch.insert(user_id.to_be_bytes(), password_hashed.as_ref());
-    // [ref:token_u64_key]
-    batch.insert(token_key(user_id), session_token.as_str().as_bytes());
-    batch.insert(
-        // [ref:atime_u64_key]
-        atime_key(user_id),
-        // [ref:atime_u64_value]
-        get_time_secs().to_be_bytes(),
-    );
     auth_tree.apply_batch(batch).await?;
 
     let buf = rkyv_ser(&Profile {
@@ -66,9 +69,7 @@ pub async fn handle(svc: &AuthServer, values: &mut Vec<Field>) -> ServerResult<A
 
     tracing::debug!("new user {} registered", user_id);
 
-    svc.deps
-        .valid_sessions
-        .insert(session_token.clone(), user_id);
+    sessions::create_session(&svc.deps, user_id, session_token.clone(), device).await?;
 
     Ok(AuthStep {
         can_go_back: false,
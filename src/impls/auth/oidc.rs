@@ -0,0 +1,112 @@
+//! Authenticating users with an OpenID Connect provider.
+
+use hrpc::client::transport::http::hyper::HttpClient;
+use hyper::{body::Buf, Body, Method, StatusCode};
+use serde::Deserialize;
+
+use crate::config::OidcConfig;
+
+use super::*;
+
+/// An account of the provider, from its userinfo endpoint.
+#[derive(Debug, Deserialize)]
+pub struct OidcUser {
+    pub sub: String,
+    #[serde(default)]
+    pub preferred_username: Option<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+impl OidcUser {
+    /// Name to use for a new local account of this user.
+    pub fn username(&self) -> String {
+        self.preferred_username
+            .as_ref()
+            .or(self.name.as_ref())
+            .unwrap_or(&self.sub)
+            .clone()
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+fn oidc_err(err: impl std::fmt::Display) -> ServerError {
+    tracing::error!("oidc error: {}", err);
+    ServerError::InternalServerError
+}
+
+/// URL of the provider's login page. The state is random instead of the auth
+/// ID, since the URL ends up in browser history and the provider's logs, and
+/// is checked against the one the client gets back to prevent login CSRF.
+pub fn authorization_url(config: &OidcConfig, state: &str) -> String {
+    format!(
+        "{}?response_type=code&client_id={}&redirect_uri={}&scope={}&state={}",
+        config.authorization_endpoint,
+        urlencoding::encode(&config.client_id),
+        urlencoding::encode(&config.redirect_uri),
+        urlencoding::encode(&config.scopes.join(" ")),
+        urlencoding::encode(state),
+    )
+}
+
+/// Exchanges a code the provider gave for an access token, and uses it to get
+/// the user that logged in.
+pub async fn authenticate(
+    http: &HttpClient,
+    config: &OidcConfig,
+    code: &str,
+) -> ServerResult<OidcUser> {
+    let body = format!(
+        "grant_type=authorization_code&code={}&redirect_uri={}&client_id={}&client_secret={}",
+        urlencoding::encode(code),
+        urlencoding::encode(&config.redirect_uri),
+        urlencoding::encode(&config.client_id),
+        urlencoding::encode(&config.client_secret),
+    );
+    let request = http::Request::builder()
+        .method(Method::POST)
+        .uri(config.token_endpoint.as_str())
+        .header(
+            http::header::CONTENT_TYPE,
+            "application/x-www-form-urlencoded",
+        )
+        .header(http::header::ACCEPT, "application/json")
+        .body(Body::from(body))
+        .map_err(oidc_err)?;
+    let response = http.request(request).await.map_err(ServerError::from)?;
+    if response.status() == StatusCode::BAD_REQUEST {
+        // the code was wrong, expired or already used
+        bail!(("h.invalid-oidc-code", "the OpenID Connect code is invalid"));
+    }
+    let TokenResponse { access_token } = read_json(response).await?;
+
+    let request = http::Request::builder()
+        .method(Method::GET)
+        .uri(config.userinfo_endpoint.as_str())
+        .header(
+            http::header::AUTHORIZATION,
+            format!("Bearer {}", access_token),
+        )
+        .header(http::header::ACCEPT, "application/json")
+        .body(Body::empty())
+        .map_err(oidc_err)?;
+    let response = http.request(request).await.map_err(ServerError::from)?;
+    read_json(response).await
+}
+
+async fn read_json<T: serde::de::DeserializeOwned>(
+    response: http::Response<Body>,
+) -> ServerResult<T> {
+    let status = response.status();
+    let body = hyper::body::aggregate(response.into_body())
+        .await
+        .map_err(ServerError::from)?;
+    if !status.is_success() {
+        bail!(oidc_err(format!("provider responded with {}", status)));
+    }
+    Ok(serde_json::from_reader(body.reader()).map_err(oidc_err)?)
+}
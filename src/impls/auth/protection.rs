@@ -0,0 +1,305 @@
+//! Protection against passwords being guessed.
+//!
+//! Failed logins are counted per account, per IP, and per account and IP.
+//! After a few, the next login has to wait before it can be tried, and the
+//! wait doubles with every failed login. After many, logins from the IP, or
+//! to the account from the IP, are locked out for a while, and the owner of
+//! the account is told by email. Accounts aren't locked out for everyone, so
+//! guessing from one IP can't keep their owner out. Failed logins are
+//! forgotten once there wasn't one for `failure_window_secs`.
+
+use std::{net::IpAddr, sync::atomic::Ordering};
+
+use crate::{config::LoginProtectionConfig, impls::email};
+
+use super::*;
+
+/// Where a login came from, to count its failures against.
+pub struct LoginSource<'a> {
+    pub email: &'a str,
+    pub ip: Option<IpAddr>,
+}
+
+/// A key failed logins are counted under.
+struct FailuresKey {
+    key: Vec<u8>,
+    /// How many failures lock the key out, 0 if it's never locked out.
+    lockout_after: u32,
+    /// Whether locking the key out locks logins to the account.
+    locks_account: bool,
+}
+
+/// Emails are compared ignoring case and surrounding whitespace, so failures
+/// can't be spread over spellings of the same email.
+fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+impl<'a> LoginSource<'a> {
+    /// Keys the failures are stored under. Without an IP, only the account
+    /// can be locked out.
+    fn keys(&self, config: &LoginProtectionConfig) -> Vec<FailuresKey> {
+        let email = normalize_email(self.email);
+        let account = FailuresKey {
+            key: make_account_login_failures_key(&email),
+            lockout_after: 0,
+            locks_account: false,
+        };
+        let Some(ip) = self.ip.map(|ip| ip.to_string()) else {
+            return vec![FailuresKey {
+                lockout_after: config.account_lockout_after_failures,
+                locks_account: true,
+                ..account
+            }];
+        };
+        vec![
+            account,
+            FailuresKey {
+                key: make_account_ip_login_failures_key(&email, &ip),
+                lockout_after: config.account_lockout_after_failures,
+                locks_account: true,
+            },
+            FailuresKey {
+                key: make_ip_login_failures_key(&ip),
+                lockout_after: config.ip_lockout_after_failures,
+                locks_account: false,
+            },
+        ]
+    }
+}
+
+/// How long a login has to wait after `count` failed logins, in seconds.
+fn delay_for(count: u32, config: &LoginProtectionConfig) -> u64 {
+    if config.delay_after_failures == 0 || count < config.delay_after_failures {
+        return 0;
+    }
+    let doublings = count - config.delay_after_failures;
+    config
+        .base_delay_secs
+        .saturating_mul(1_u64.checked_shl(doublings).unwrap_or(u64::MAX))
+        .min(config.max_delay_secs)
+}
+
+/// Checks if a login can be tried now, given the failed logins before it.
+fn check_failures(
+    failures: &LoginFailures,
+    config: &LoginProtectionConfig,
+    now: u64,
+) -> Result<(), ServerError> {
+    if now < failures.locked_until {
+        return Err(ServerError::LoginLocked(Duration::from_secs(
+            failures.locked_until - now,
+        )));
+    }
+    if now >= failures.last_failed_at + config.failure_window_secs {
+        return Ok(());
+    }
+    let allowed_at = failures.last_failed_at + delay_for(failures.count, config);
+    if now < allowed_at {
+        return Err(ServerError::TooFast(Duration::from_secs(allowed_at - now)));
+    }
+    Ok(())
+}
+
+/// Counts a failed login. Returns whether it locked logins out.
+fn add_failure(
+    failures: &mut LoginFailures,
+    config: &LoginProtectionConfig,
+    lockout_after: u32,
+    now: u64,
+) -> bool {
+    if now >= failures.last_failed_at + config.failure_window_secs {
+        failures.count = 0;
+    }
+    failures.count += 1;
+    failures.last_failed_at = now;
+    if lockout_after > 0 && failures.count >= lockout_after {
+        failures.count = 0;
+        failures.locked_until = now + config.lockout_secs;
+        return true;
+    }
+    false
+}
+
+/// Fails if a login has to wait because of earlier failed logins, or is locked out.
+pub async fn check_login(deps: &Dependencies, source: &LoginSource<'_>) -> ServerResult<()> {
+    let config = &deps.config.auth.protection;
+    let now = get_time_secs();
+    for FailuresKey { key, .. } in source.keys(config) {
+        let failures = deps.auth_tree.get_login_failures_logic(&key).await?;
+        if let Err(err) = check_failures(&failures, config, now) {
+            deps.diagnostics
+                .rejected_logins
+                .fetch_add(1, Ordering::Relaxed);
+            return Err(err.into());
+        }
+    }
+    Ok(())
+}
+
+/// Counts a failed login to an account that might not exist. If it locks the
+/// account out and the account exists, its owner is emailed about it.
+pub async fn record_failed_login(
+    deps: &Dependencies,
+    source: &LoginSource<'_>,
+    user_id: Option<u64>,
+) -> ServerResult<()> {
+    let config = &deps.config.auth.protection;
+    let now = get_time_secs();
+    deps.diagnostics
+        .failed_logins
+        .fetch_add(1, Ordering::Relaxed);
+
+    let mut batch = Batch::default();
+    let mut account_locked = false;
+    for FailuresKey {
+        key,
+        lockout_after,
+        locks_account,
+    } in source.keys(config)
+    {
+        let mut failures = deps.auth_tree.get_login_failures_logic(&key).await?;
+        if add_failure(&mut failures, config, lockout_after, now) {
+            deps.diagnostics
+                .login_lockouts
+                .fetch_add(1, Ordering::Relaxed);
+            account_locked |= locks_account;
+            tracing::warn!(
+                "locked out logins {} for {} seconds after too many failed logins",
+                if locks_account {
+                    "to an account"
+                } else {
+                    "from an IP"
+                },
+                config.lockout_secs
+            );
+        }
+        batch.insert(key, rkyv_ser(&failures));
+    }
+    deps.auth_tree.apply_batch(batch).await?;
+
+    if let (true, Some(user_id), Some(email_config)) =
+        (account_locked, user_id, deps.config.email.clone())
+    {
+        let to = source.email.to_string();
+        let from = source
+            .ip
+            .map_or_else(String::new, |ip| format!(" from {}", ip));
+        let body = format!(
+            "There were {} failed logins to your account on {}{}, so logging in to it{} is \
+            locked for {} minutes.\n\n\
+            If these weren't you, someone might be trying to guess your password. \
+            Consider changing it to a stronger one.",
+            config.account_lockout_after_failures,
+            deps.config.host,
+            from,
+            from,
+            config.lockout_secs / 60,
+        );
+        tokio::spawn(async move {
+            if let Err(err) = email::send(&email_config, &to, "Failed logins", body).await {
+                tracing::error!(
+                    "couldn't send failed logins email to user {}: {}",
+                    user_id,
+                    err
+                );
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// Forgets the failed logins to an account, after it was logged in to.
+pub async fn clear_failed_logins(
+    deps: &Dependencies,
+    source: &LoginSource<'_>,
+) -> ServerResult<()> {
+    let email = normalize_email(source.email);
+    let mut batch = Batch::default();
+    batch.remove(make_account_login_failures_key(&email));
+    if let Some(ip) = source.ip {
+        batch.remove(make_account_ip_login_failures_key(&email, &ip.to_string()));
+    }
+    deps.auth_tree.apply_batch(batch).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn delays_grow_then_lock_out() {
+        let config = LoginProtectionConfig::default();
+        let mut failures = LoginFailures::default();
+        let now = 1_000_000;
+
+        for _ in 0..config.delay_after_failures {
+            assert!(!add_failure(&mut failures, &config, 10, now));
+        }
+        assert!(matches!(
+            check_failures(&failures, &config, now),
+            Err(ServerError::TooFast(_))
+        ));
+        assert!(check_failures(&failures, &config, now + config.base_delay_secs).is_ok());
+
+        assert!(!add_failure(&mut failures, &config, 10, now));
+        assert_eq!(
+            delay_for(failures.count, &config),
+            config.base_delay_secs * 2
+        );
+
+        for _ in failures.count..9 {
+            assert!(!add_failure(&mut failures, &config, 10, now));
+        }
+        assert!(add_failure(&mut failures, &config, 10, now));
+        assert!(matches!(
+            check_failures(&failures, &config, now + config.lockout_secs - 1),
+            Err(ServerError::LoginLocked(_))
+        ));
+        assert!(check_failures(&failures, &config, now + config.lockout_secs).is_ok());
+    }
+
+    #[test]
+    fn account_lockouts_are_scoped_to_ips() {
+        let config = LoginProtectionConfig::default();
+        let ip = "192.0.2.1".parse().ok();
+        let keys = LoginSource {
+            email: " User@Example.org ",
+            ip,
+        }
+        .keys(&config);
+        let same_keys = LoginSource {
+            email: "user@example.org",
+            ip,
+        }
+        .keys(&config);
+        assert!(keys.iter().zip(&same_keys).all(|(a, b)| a.key == b.key));
+
+        let locking: Vec<_> = keys.iter().filter(|key| key.lockout_after > 0).collect();
+        assert_eq!(locking.len(), 2);
+        assert!(locking
+            .iter()
+            .all(|key| key.key != make_account_login_failures_key("user@example.org")));
+
+        let keys = LoginSource {
+            email: "user@example.org",
+            ip: None,
+        }
+        .keys(&config);
+        assert_eq!(keys.len(), 1);
+        assert!(keys[0].locks_account);
+    }
+
+    #[test]
+    fn failures_are_forgotten_after_window() {
+        let config = LoginProtectionConfig::default();
+        let mut failures = LoginFailures::default();
+        for _ in 0..5 {
+            add_failure(&mut failures, &config, 0, 0);
+        }
+        add_failure(&mut failures, &config, 0, config.failure_window_secs);
+        assert_eq!(failures.count, 1);
+    }
+}
@@ -0,0 +1,209 @@
+//! Refresh tokens, which let clients keep a session without its token being
+//! usable forever.
+//!
+//! A logged in client gets a refresh token for its session with the
+//! `auth/refresh-token` endpoint. From then on, the token of the session only
+//! works for `access_token_ttl`, and the client gets a new one with the
+//! `auth/refresh` endpoint, which also replaces the refresh token. Refresh
+//! tokens are stored hashed, and are removed along with their session.
+
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+const INVALID_REFRESH_TOKEN_ERR: (&str, &str) = (
+    "h.invalid-refresh-token",
+    "refresh token is invalid or has expired",
+);
+
+/// Stores a new refresh token for a session, replacing the one it had before.
+async fn issue_refresh_token(
+    deps: &Dependencies,
+    user_id: u64,
+    session_id: u64,
+) -> ServerResult<(SmolStr, RefreshToken)> {
+    let token = gen_rand_inline_str();
+    let token_hashed = hash_password(token.as_bytes());
+    let now = get_time_secs();
+    let refresh_token = RefreshToken {
+        user_id,
+        session_id,
+        created_at: now,
+        expires_at: now + deps.config.auth.refresh_token_ttl,
+    };
+
+    let mut batch = Batch::default();
+    deps.auth_tree
+        .remove_refresh_token_logic(&mut batch, user_id, session_id)
+        .await?;
+    batch.insert(
+        refresh_token_key(token_hashed.as_ref()),
+        rkyv_ser(&refresh_token),
+    );
+    batch.insert(
+        make_session_refresh_token_key(user_id, session_id),
+        token_hashed.as_ref(),
+    );
+    deps.auth_tree.apply_batch(batch).await?;
+
+    Ok((token, refresh_token))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateRefreshTokenRequest {}
+
+#[derive(Debug, Serialize)]
+pub struct CreateRefreshTokenResponse {
+    pub refresh_token: SmolStr,
+    /// When the refresh token expires if it isn't used, in seconds since unix epoch.
+    pub refresh_token_expires_at: u64,
+    /// When the token of the session stops working, in seconds since unix epoch.
+    pub session_token_expires_at: u64,
+}
+
+/// Creates a refresh token for the session the request was made with. Any
+/// refresh token the session had before stops working.
+pub async fn create_refresh_token_handler(
+    deps: &Dependencies,
+    user_id: u64,
+    current_token: &str,
+    _request: CreateRefreshTokenRequest,
+) -> ServerResult<CreateRefreshTokenResponse> {
+    let Some(raw) = deps
+        .auth_tree
+        .get(make_session_key(user_id, current_token))
+        .await?
+    else {
+        bail!(ServerError::Unauthenticated);
+    };
+    let session = db::deser_session(raw);
+
+    let (token, refresh_token) = issue_refresh_token(deps, user_id, session.session_id).await?;
+
+    let session_token_expires_at = session.refreshed_at + deps.config.auth.access_token_ttl;
+    let last_used = deps
+        .valid_sessions
+        .get(current_token)
+        .map_or(session.last_used, |valid| valid.last_used());
+    deps.valid_sessions.insert(
+        current_token.into(),
+        ValidSession::new(user_id, last_used).expiring_at(Some(session_token_expires_at)),
+    );
+
+    Ok(CreateRefreshTokenResponse {
+        refresh_token: token,
+        refresh_token_expires_at: refresh_token.expires_at,
+        session_token_expires_at,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RefreshResponse {
+    pub user_id: u64,
+    pub session_token: SmolStr,
+    /// When the new session token stops working, in seconds since unix epoch.
+    pub session_token_expires_at: u64,
+    /// Replaces the refresh token the request was made with, which can't be
+    /// used anymore.
+    pub refresh_token: SmolStr,
+    /// When the new refresh token expires if it isn't used, in seconds since unix epoch.
+    pub refresh_token_expires_at: u64,
+}
+
+/// Gets a new token for the session of a refresh token, and a new refresh
+/// token. Doesn't need the session token, since it might have stopped working.
+pub async fn refresh_handler(
+    deps: &Dependencies,
+    device: String,
+    request: RefreshRequest,
+) -> ServerResult<RefreshResponse> {
+    let token_hashed = hash_password(request.refresh_token.as_bytes());
+    let key = refresh_token_key(token_hashed.as_ref());
+    let Some(raw) = deps.auth_tree.get(&key).await? else {
+        bail!(INVALID_REFRESH_TOKEN_ERR);
+    };
+    let RefreshToken {
+        user_id,
+        session_id,
+        expires_at,
+        ..
+    } = db::deser_refresh_token(&raw);
+    if get_time_secs() >= expires_at {
+        bail!(INVALID_REFRESH_TOKEN_ERR);
+    }
+    // a refresh token can only be used once, even by requests racing each other
+    let consumed = deps
+        .auth_tree
+        .compare_and_swap(&key, Some(raw.as_ref()), None)
+        .await?;
+    if !consumed {
+        bail!(INVALID_REFRESH_TOKEN_ERR);
+    }
+
+    let maybe_token = deps
+        .auth_tree
+        .get_sessions_logic(user_id)
+        .await?
+        .into_iter()
+        .find_map(|(token, session)| (session.session_id == session_id).then(|| token));
+    let Some(old_token) = maybe_token else {
+        bail!(INVALID_REFRESH_TOKEN_ERR);
+    };
+
+    let session_token = gen_rand_inline_str();
+    sessions::refresh_session(deps, user_id, &old_token, session_token.clone(), device).await?;
+    let (refresh_token, refresh_info) = issue_refresh_token(deps, user_id, session_id).await?;
+
+    tracing::debug!("user {} refreshed a session with a refresh token", user_id);
+
+    Ok(RefreshResponse {
+        user_id,
+        session_token,
+        session_token_expires_at: get_time_secs() + deps.config.auth.access_token_ttl,
+        refresh_token,
+        refresh_token_expires_at: refresh_info.expires_at,
+    })
+}
+
+#[cfg(all(test, feature = "sled"))]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn racing_refreshes_use_token_once() {
+        let db = crate::db::open_temp();
+        let (deps, _) = Dependencies::new(&db, crate::config::Config::default())
+            .await
+            .unwrap();
+        sessions::create_session(&deps, 1, "token".into(), String::new())
+            .await
+            .unwrap();
+        let refresh_token =
+            create_refresh_token_handler(&deps, 1, "token", CreateRefreshTokenRequest {})
+                .await
+                .unwrap()
+                .refresh_token;
+
+        let refreshes = (0..8)
+            .map(|_| {
+                let deps = deps.clone();
+                let request = RefreshRequest {
+                    refresh_token: refresh_token.to_string(),
+                };
+                tokio::spawn(async move { refresh_handler(&deps, String::new(), request).await })
+            })
+            .collect::<Vec<_>>();
+        let mut refreshed = 0;
+        for refresh in refreshes {
+            if refresh.await.unwrap().is_ok() {
+                refreshed += 1;
+            }
+        }
+        assert_eq!(refreshed, 1);
+    }
+}
@@ -0,0 +1,156 @@
+//! Challenges users have to solve to register, to slow down automated sign ups.
+
+use hyper::{body::Buf, Body, Method};
+use serde::{Deserialize, Serialize};
+
+use crate::config::RegistrationChallengeConfig;
+
+use super::*;
+
+const HCAPTCHA_VERIFY_URL: &str = "https://hcaptcha.com/siteverify";
+
+const CHALLENGE_FAILED_ERR: (&str, &str) = (
+    "h.registration-challenge-failed",
+    "registration challenge wasn't solved",
+);
+
+/// Checks the solution a client sent for the registration challenge of an auth session.
+pub async fn verify(deps: &Dependencies, auth_id: &str, solution: &str) -> ServerResult<()> {
+    let Some(config) = &deps.config.policy.registration_challenge else {
+        return Ok(());
+    };
+    if solution.is_empty() {
+        bail!(CHALLENGE_FAILED_ERR);
+    }
+
+    let solved = match config {
+        RegistrationChallengeConfig::Hcaptcha { site_key, secret } => {
+            verify_hcaptcha(deps, site_key, secret, solution).await?
+        }
+        RegistrationChallengeConfig::ProofOfWork { difficulty } => {
+            leading_zero_bits(&pow_hash(auth_id, solution)) >= *difficulty
+        }
+    };
+    if !solved {
+        bail!(CHALLENGE_FAILED_ERR);
+    }
+
+    Ok(())
+}
+
+fn pow_hash(auth_id: &str, nonce: &str) -> Vec<u8> {
+    sha3::Sha3_256::digest(format!("{}:{}", auth_id, nonce).as_bytes()).to_vec()
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+#[derive(Deserialize)]
+struct HcaptchaResponse {
+    success: bool,
+}
+
+async fn verify_hcaptcha(
+    deps: &Dependencies,
+    site_key: &str,
+    secret: &str,
+    response: &str,
+) -> ServerResult<bool> {
+    let body = format!(
+        "secret={}&response={}&sitekey={}",
+        urlencoding::encode(secret),
+        urlencoding::encode(response),
+        urlencoding::encode(site_key),
+    );
+    let request = http::Request::builder()
+        .method(Method::POST)
+        .uri(HCAPTCHA_VERIFY_URL)
+        .header(
+            http::header::CONTENT_TYPE,
+            "application/x-www-form-urlencoded",
+        )
+        .body(Body::from(body))
+        .expect("must be valid request");
+    let response = deps
+        .http
+        .request(request)
+        .await
+        .map_err(ServerError::from)?;
+    let body = hyper::body::aggregate(response.into_body())
+        .await
+        .map_err(ServerError::from)?;
+    let HcaptchaResponse { success } = serde_json::from_reader(body.reader()).map_err(|err| {
+        tracing::error!("couldn't parse hcaptcha response: {}", err);
+        ServerError::InternalServerError
+    })?;
+
+    Ok(success)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetRegistrationChallengeRequest {}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum RegistrationChallenge {
+    Hcaptcha {
+        site_key: String,
+    },
+    /// The nonce has to make the SHA3-256 hash of `<auth id>:<nonce>` start
+    /// with `difficulty` zero bits.
+    ProofOfWork {
+        difficulty: u32,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetRegistrationChallengeResponse {
+    /// Not set if registration isn't challenged.
+    pub challenge: Option<RegistrationChallenge>,
+}
+
+/// Gets the challenge that has to be solved to register.
+pub async fn get_registration_challenge_handler(
+    deps: &Dependencies,
+    _request: GetRegistrationChallengeRequest,
+) -> ServerResult<GetRegistrationChallengeResponse> {
+    let challenge = deps
+        .config
+        .policy
+        .registration_challenge
+        .as_ref()
+        .map(|config| match config {
+            RegistrationChallengeConfig::Hcaptcha { site_key, .. } => {
+                RegistrationChallenge::Hcaptcha {
+                    site_key: site_key.clone(),
+                }
+            }
+            RegistrationChallengeConfig::ProofOfWork { difficulty } => {
+                RegistrationChallenge::ProofOfWork {
+                    difficulty: *difficulty,
+                }
+            }
+        });
+
+    Ok(GetRegistrationChallengeResponse { challenge })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn counts_leading_zero_bits() {
+        assert_eq!(leading_zero_bits(&[0, 0, 0b0001_0000, 0xff]), 19);
+        assert_eq!(leading_zero_bits(&[0xff, 0]), 0);
+        assert_eq!(leading_zero_bits(&[0, 0]), 16);
+    }
+}
@@ -0,0 +1,332 @@
+//! Sessions users are logged in with, and endpoints to see and revoke them.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::AuthConfig, impls::profile::ProfileTree};
+
+use super::*;
+
+/// Longest user agent kept as the device of a session.
+const MAX_DEVICE_LEN: usize = 128;
+
+/// Gets the device a request was sent from, to show it in the session list.
+pub fn get_device<T>(request: &Request<T>) -> String {
+    request
+        .header_map()
+        .map(get_device_from_headers)
+        .unwrap_or_default()
+}
+
+/// Gets the device a request was sent from, from the headers of the request.
+pub fn get_device_from_headers(headers: &HeaderMap) -> String {
+    headers
+        .get(http::header::USER_AGENT)
+        .and_then(|val| val.to_str().ok())
+        .map(|user_agent| user_agent.chars().take(MAX_DEVICE_LEN).collect())
+        .unwrap_or_default()
+}
+
+/// Stores a new session for a user, and makes its token usable.
+pub async fn create_session(
+    deps: &Dependencies,
+    user_id: u64,
+    token: SmolStr,
+    device: String,
+) -> ServerResult<()> {
+    let now = get_time_secs();
+    let session = SessionInfo {
+        session_id: gen_rand_u64(),
+        device,
+        created_at: now,
+        last_used: now,
+        refreshed_at: now,
+    };
+    deps.auth_tree
+        .insert(make_session_key(user_id, &token), rkyv_ser(&session))
+        .await?;
+    deps.valid_sessions
+        .insert(token, ValidSession::new(user_id, now));
+    Ok(())
+}
+
+/// Replaces the token of a session with a new one, keeping the rest of the
+/// session. The old token can't be used anymore afterwards.
+pub async fn refresh_session(
+    deps: &Dependencies,
+    user_id: u64,
+    old_token: &str,
+    new_token: SmolStr,
+    device: String,
+) -> ServerResult<()> {
+    let old_key = make_session_key(user_id, old_token);
+    let Some(raw) = deps.auth_tree.get(&old_key).await? else {
+        bail!(ServerError::Unauthenticated);
+    };
+    let mut session = db::deser_session(raw.clone());
+
+    let now = get_time_secs();
+    session.last_used = now;
+    session.refreshed_at = now;
+    if !device.is_empty() {
+        session.device = device;
+    }
+
+    // the old token can only be refreshed once, even by requests racing each other
+    let consumed = deps
+        .auth_tree
+        .compare_and_swap(&old_key, Some(raw.as_ref()), None)
+        .await?;
+    if !consumed {
+        bail!(ServerError::Unauthenticated);
+    }
+    deps.auth_tree
+        .insert(make_session_key(user_id, &new_token), rkyv_ser(&session))
+        .await?;
+
+    let expires_at = access_expires_at(deps, user_id, &session).await?;
+    deps.valid_sessions.remove(old_token);
+    deps.valid_sessions.insert(
+        new_token,
+        ValidSession::new(user_id, now).expiring_at(expires_at),
+    );
+    Ok(())
+}
+
+/// When the token of a session stops working, if the session has a refresh token.
+async fn access_expires_at(
+    deps: &Dependencies,
+    user_id: u64,
+    session: &SessionInfo,
+) -> ServerResult<Option<u64>> {
+    let has_refresh_token = deps
+        .auth_tree
+        .contains_key(make_session_refresh_token_key(user_id, session.session_id))
+        .await?;
+    Ok(has_refresh_token.then(|| session.refreshed_at + deps.config.auth.access_token_ttl))
+}
+
+/// Whether a session can't be used anymore, either because it wasn't used
+/// for too long or because it wasn't refreshed in time.
+fn is_expired(config: &AuthConfig, session: &SessionInfo, last_used: u64, now: u64) -> bool {
+    now.saturating_sub(last_used) >= config.session_ttl
+        || config.session_max_age.map_or(false, |max_age| {
+            now.saturating_sub(session.refreshed_at) >= max_age
+        })
+}
+
+/// Loads stored sessions into the valid sessions, saves when they were last
+/// used and removes the ones that expired. Sessions that have a refresh token
+/// only expire with their refresh token.
+pub(super) async fn check_sessions(
+    auth_tree: &AuthTree,
+    profile_tree: &ProfileTree,
+    valid_sessions: &SessionMap,
+    config: &AuthConfig,
+) -> ServerResult<()> {
+    let now = get_time_secs();
+    let mut is_bot = HashMap::new();
+    let mut batch = Batch::default();
+
+    let mut refreshable = HashSet::new();
+    let mut refresh_expired = HashSet::new();
+    for res in auth_tree.scan_prefix(REFRESH_TOKEN_PREFIX).await {
+        let (key, value) = res?;
+        let refresh_token = db::deser_refresh_token(value);
+        let session = (refresh_token.user_id, refresh_token.session_id);
+        if now >= refresh_token.expires_at {
+            batch.remove(key);
+            batch.remove(make_session_refresh_token_key(session.0, session.1));
+            refresh_expired.insert(session);
+        } else {
+            refreshable.insert(session);
+        }
+    }
+
+    for res in auth_tree.scan_prefix(SESSION_PREFIX).await {
+        let (key, value) = res?;
+        // [ref:session_key]
+        let Some((user_id, token)) = split_session_key(&key) else {
+            continue;
+        };
+        let user_is_bot = match is_bot.get(&user_id) {
+            Some(user_is_bot) => *user_is_bot,
+            None => {
+                let Ok(profile) = profile_tree.get_profile_logic(user_id).await else {
+                    continue;
+                };
+                is_bot.insert(user_id, profile.is_bot);
+                profile.is_bot
+            }
+        };
+
+        let mut session = db::deser_session(&value);
+        let last_used = valid_sessions
+            .get(token)
+            .map_or(session.last_used, |valid| valid.last_used());
+
+        let has_refresh_token = refreshable.contains(&(user_id, session.session_id));
+        let expired = !has_refresh_token
+            && (refresh_expired.contains(&(user_id, session.session_id))
+                || (!user_is_bot && is_expired(config, &session, last_used, now)));
+        // the token of a session with a refresh token stops working after a
+        // while, but the session is kept so it can still be refreshed
+        let access_expires_at =
+            has_refresh_token.then(|| session.refreshed_at + config.access_token_ttl);
+
+        if expired {
+            tracing::debug!("user {} session has expired", user_id);
+            valid_sessions.remove(token);
+            batch.remove(key);
+        } else if access_expires_at.map_or(false, |at| now >= at) {
+            if last_used != session.last_used {
+                session.last_used = last_used;
+                batch.insert(key, rkyv_ser(&session));
+            }
+            valid_sessions.remove(token);
+        } else if last_used != session.last_used {
+            session.last_used = last_used;
+            batch.insert(key, rkyv_ser(&session));
+        } else if !valid_sessions.contains_key(token) {
+            valid_sessions.insert(
+                token.into(),
+                ValidSession::new(user_id, last_used).expiring_at(access_expires_at),
+            );
+        }
+    }
+
+    auth_tree.apply_batch(batch).await?;
+
+    Ok(())
+}
+
+/// Removes a session of a user along with its refresh token, so its token
+/// can't be used anymore.
+async fn revoke_session(deps: &Dependencies, user_id: u64, token: &str) -> ServerResult<()> {
+    let key = make_session_key(user_id, token);
+    let mut batch = Batch::default();
+    if let Some(raw) = deps.auth_tree.get(&key).await? {
+        let session_id = db::deser_session(raw).session_id;
+        deps.auth_tree
+            .remove_refresh_token_logic(&mut batch, user_id, session_id)
+            .await?;
+    }
+    batch.remove(key);
+    deps.auth_tree.apply_batch(batch).await?;
+    deps.valid_sessions.remove(token);
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetSessionsRequest {}
+
+#[derive(Debug, Serialize)]
+pub struct SessionEntry {
+    pub session_id: u64,
+    /// User agent of the client that logged in, empty if it didn't send one.
+    pub device: String,
+    /// In seconds since unix epoch.
+    pub created_at: u64,
+    /// In seconds since unix epoch.
+    pub last_used: u64,
+    /// When the token of the session was last replaced, in seconds since unix epoch.
+    pub refreshed_at: u64,
+    /// Whether this is the session the request was made with.
+    pub current: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetSessionsResponse {
+    pub sessions: Vec<SessionEntry>,
+}
+
+/// Lists the sessions the user is logged in with, most recently used first.
+pub async fn get_sessions_handler(
+    deps: &Dependencies,
+    user_id: u64,
+    current_token: &str,
+    _request: GetSessionsRequest,
+) -> ServerResult<GetSessionsResponse> {
+    let mut sessions = deps
+        .auth_tree
+        .get_sessions_logic(user_id)
+        .await?
+        .into_iter()
+        .map(|(token, session)| SessionEntry {
+            session_id: session.session_id,
+            device: session.device,
+            created_at: session.created_at,
+            refreshed_at: session.refreshed_at,
+            last_used: deps
+                .valid_sessions
+                .get(token.as_str())
+                .map_or(session.last_used, |valid| valid.last_used()),
+            current: token.as_str() == current_token,
+        })
+        .collect::<Vec<_>>();
+    sessions.sort_unstable_by(|a, b| b.last_used.cmp(&a.last_used));
+
+    Ok(GetSessionsResponse { sessions })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RevokeSessionRequest {
+    pub session_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RevokeSessionResponse {}
+
+/// Revokes one of the user's sessions, logging that device out.
+pub async fn revoke_session_handler(
+    deps: &Dependencies,
+    user_id: u64,
+    request: RevokeSessionRequest,
+) -> ServerResult<RevokeSessionResponse> {
+    let RevokeSessionRequest { session_id } = request;
+
+    let maybe_token = deps
+        .auth_tree
+        .get_sessions_logic(user_id)
+        .await?
+        .into_iter()
+        .find_map(|(token, session)| (session.session_id == session_id).then(|| token));
+    let Some(token) = maybe_token else {
+        bail!((
+            "h.no-such-session",
+            format!("no session with ID {}", session_id)
+        ));
+    };
+
+    revoke_session(deps, user_id, &token).await?;
+
+    Ok(RevokeSessionResponse {})
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RevokeOtherSessionsRequest {}
+
+#[derive(Debug, Serialize)]
+pub struct RevokeOtherSessionsResponse {
+    /// How many sessions were revoked.
+    pub revoked: usize,
+}
+
+/// Revokes all of the user's sessions except the one the request was made with.
+pub async fn revoke_other_sessions_handler(
+    deps: &Dependencies,
+    user_id: u64,
+    current_token: &str,
+    _request: RevokeOtherSessionsRequest,
+) -> ServerResult<RevokeOtherSessionsResponse> {
+    let mut revoked = 0;
+    for (token, _) in deps.auth_tree.get_sessions_logic(user_id).await? {
+        if token.as_str() != current_token {
+            revoke_session(deps, user_id, &token).await?;
+            revoked += 1;
+        }
+    }
+
+    Ok(RevokeOtherSessionsResponse { revoked })
+}
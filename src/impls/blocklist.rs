@@ -0,0 +1,211 @@
+use std::{
+    collections::HashSet,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use parking_lot::RwLock;
+
+use crate::config::DomainBlocklistConfig;
+
+use super::*;
+
+/// Domains that can't be linked to in messages or fetched through the media proxy.
+///
+/// A domain also blocks all of its subdomains.
+pub struct DomainBlocklist {
+    domains: RwLock<HashSet<SmolStr>>,
+    /// Messages rejected for containing a blocked domain.
+    pub blocked_messages: AtomicU64,
+    /// Messages let through but flagged for containing a blocked domain.
+    pub flagged_messages: AtomicU64,
+    /// Messages held back for moderator review for containing a blocked domain.
+    pub quarantined_messages: AtomicU64,
+    /// Links to blocked domains the media proxy refused to fetch.
+    pub blocked_links: AtomicU64,
+}
+
+impl DomainBlocklist {
+    pub fn new(config: &DomainBlocklistConfig) -> Self {
+        Self {
+            domains: RwLock::new(parse_domains(config.domains.iter().map(String::as_str))),
+            blocked_messages: AtomicU64::new(0),
+            flagged_messages: AtomicU64::new(0),
+            quarantined_messages: AtomicU64::new(0),
+            blocked_links: AtomicU64::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.domains.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.domains.read().is_empty()
+    }
+
+    /// Checks if a host is blocked, either directly or through one of its parent domains.
+    pub fn is_blocked(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        let domains = self.domains.read();
+        let mut domain = host.as_str();
+        loop {
+            if domains.contains(domain) {
+                return true;
+            }
+            match domain.split_once('.') {
+                Some((_, parent)) => domain = parent,
+                None => return false,
+            }
+        }
+    }
+
+    /// Returns the first blocked domain that is linked to in some text.
+    pub fn find_blocked_in_text(&self, text: &str) -> Option<SmolStr> {
+        if self.is_empty() {
+            return None;
+        }
+
+        text.split_whitespace()
+            .filter_map(extract_host)
+            .find(|host| self.is_blocked(host))
+            .map(SmolStr::new)
+    }
+
+    /// Makes sure the URL doesn't point to a blocked domain.
+    pub fn check_url(&self, url: &Uri) -> Result<(), ServerError> {
+        match url.host() {
+            Some(host) if self.is_blocked(host) => {
+                self.blocked_links.fetch_add(1, Ordering::Relaxed);
+                Err(ServerError::BlockedDomain(host.into()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Reloads the blocklist from the config, the blocklist file and the remote blocklist.
+    /// Returns the number of blocked domains.
+    pub async fn reload(
+        &self,
+        config: &DomainBlocklistConfig,
+        http: &HttpClient,
+    ) -> Result<usize, ServerError> {
+        let file = match &config.file {
+            Some(path) => tokio::fs::read_to_string(path).await?,
+            None => String::new(),
+        };
+        let remote = match &config.url {
+            Some(url) => fetch_remote_list(url, http).await?,
+            None => String::new(),
+        };
+
+        let domains = parse_domains(
+            config
+                .domains
+                .iter()
+                .map(String::as_str)
+                .chain(file.lines())
+                .chain(remote.lines()),
+        );
+        let len = domains.len();
+        *self.domains.write() = domains;
+
+        Ok(len)
+    }
+
+    /// Spawns a task that reloads the blocklist periodically, if it has a file or a remote list.
+    pub fn spawn_updater(deps: Arc<Dependencies>) {
+        let config = deps.config.policy.domain_blocklist.clone();
+        if config.file.is_none() && config.url.is_none() {
+            return;
+        }
+
+        tokio::spawn(async move {
+            tracing::info!("starting domain blocklist update task");
+            loop {
+                match deps.domain_blocklist.reload(&config, &deps.http).await {
+                    Ok(len) => tracing::debug!("loaded {} blocked domains", len),
+                    Err(err) => tracing::error!("couldn't update domain blocklist: {}", err),
+                }
+                tokio::time::sleep(Duration::from_secs(config.update_interval)).await;
+            }
+        });
+    }
+}
+
+async fn fetch_remote_list(url: &str, http: &HttpClient) -> Result<String, ServerError> {
+    let url: Uri = url.parse().map_err(ServerError::InvalidUrl)?;
+    let response = http.get(url).await?;
+    if !response.status().is_success() {
+        tracing::warn!(
+            "remote domain blocklist responded with {}",
+            response.status()
+        );
+        return Err(ServerError::InternalServerError);
+    }
+    let body = hyper::body::to_bytes(response.into_body()).await?;
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+/// Parses lines of a blocklist. Empty lines and lines starting with `#` are ignored,
+/// and hosts file style lines (`0.0.0.0 example.com`) are accepted.
+fn parse_domains<'a>(lines: impl Iterator<Item = &'a str>) -> HashSet<SmolStr> {
+    lines
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_whitespace().last())
+        .map(|domain| SmolStr::new(domain.trim_end_matches('.').to_ascii_lowercase()))
+        .collect()
+}
+
+/// Extracts the host from a word if it looks like a link.
+fn extract_host(word: &str) -> Option<&str> {
+    let word = word.trim_matches(|c: char| matches!(c, '<' | '>' | '(' | ')' | '"' | '\''));
+    let rest = match word.split_once("://") {
+        Some((_, rest)) => rest,
+        None if word.starts_with("www.") => word,
+        None => return None,
+    };
+    let authority = rest.split(|c| matches!(c, '/' | '?' | '#')).next()?;
+    let host_port = authority.rsplit('@').next()?;
+    let host = host_port.split(':').next()?;
+    (!host.is_empty()).then(|| host)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DomainBlocklist, DomainBlocklistConfig};
+
+    fn blocklist(domains: &[&str]) -> DomainBlocklist {
+        DomainBlocklist::new(&DomainBlocklistConfig {
+            domains: domains.iter().map(|d| d.to_string()).collect(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn subdomains_blocked() {
+        let blocklist = blocklist(&["bad.example"]);
+        assert!(blocklist.is_blocked("bad.example"));
+        assert!(blocklist.is_blocked("cdn.Bad.Example."));
+        assert!(!blocklist.is_blocked("notbad.example"));
+        assert!(!blocklist.is_blocked("example"));
+    }
+
+    #[test]
+    fn links_in_text() {
+        let blocklist = blocklist(&["0.0.0.0 bad.example", "# comment"]);
+        assert_eq!(
+            blocklist
+                .find_blocked_in_text("look at <https://user@www.bad.example:8080/path?q>")
+                .as_deref(),
+            Some("www.bad.example")
+        );
+        assert!(blocklist
+            .find_blocked_in_text(
+                "bad.example without a scheme is fine, so is https://good.example"
+            )
+            .is_none());
+        assert_eq!(blocklist.len(), 1);
+    }
+}
@@ -0,0 +1,950 @@
+//! Internal event bus.
+//!
+//! Handlers publish typed [`DomainEvent`]s describing what happened on the
+//! server. Anything interested in those (event streams, webhooks, audit log,
+//! automod...) subscribes to the bus, instead of every handler wiring its own
+//! protocol events. Conversion to protocol events only happens at the edge, in
+//! [`DomainEvent::to_broadcasts`].
+
+use harmony_rust_sdk::api::{
+    chat::{
+        self, all_permissions, stream_event as chat_event, FormattedText, LeaveReason,
+        Message as HarmonyMessage, Permission, Reaction,
+    },
+    emote::{self, Emote, EmotePack},
+    harmonytypes::{ItemPosition, Metadata},
+    profile::{self, stream_event as profile_event},
+};
+use tokio::sync::broadcast;
+
+use crate::config::{EventCoalescingConfig, EventReplayConfig};
+
+use super::{
+    chat::{
+        event_coalescing::{CoalesceKey, EventCoalescer},
+        event_replay::EventReplay,
+        EventBroadcast, EventContext, EventSender, EventSub, PermCheck,
+    },
+    prelude::*,
+};
+
+pub type DomainEventReceiver = broadcast::Receiver<Arc<DomainEvent>>;
+
+/// Something that happened on this homeserver.
+#[derive(Debug, Clone)]
+pub enum DomainEvent {
+    MessageSent {
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+        echo_id: Option<u64>,
+        message: HarmonyMessage,
+    },
+    /// A message that was held back for review. Only its author and the
+    /// guild's moderators are told about it.
+    MessageQuarantined {
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+        echo_id: Option<u64>,
+        message: HarmonyMessage,
+    },
+    /// A message was sent in the thread started from `message_id`. Only the
+    /// `subscribers` of the thread get it.
+    ThreadMessageSent {
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+        thread_message_id: u64,
+        message: HarmonyMessage,
+        subscribers: Vec<u64>,
+    },
+    MessageUpdated {
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+        edited_at: u64,
+        new_content: Option<FormattedText>,
+    },
+    MessageDeleted {
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+    },
+    /// Many messages of a channel deleted at once.
+    MessagesDeleted {
+        guild_id: u64,
+        channel_id: u64,
+        message_ids: Vec<u64>,
+    },
+    MessagePinned {
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+    },
+    MessageUnpinned {
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+    },
+    /// `reaction` is the new state of the reaction, `None` if it was removed entirely.
+    ReactionUpdated {
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+        reaction: Option<Reaction>,
+    },
+    Typing {
+        guild_id: u64,
+        channel_id: u64,
+        user_id: u64,
+    },
+    ChannelCreated {
+        guild_id: u64,
+        channel_id: u64,
+        name: String,
+        position: Option<ItemPosition>,
+        kind: i32,
+        metadata: Option<Metadata>,
+    },
+    ChannelUpdated {
+        guild_id: u64,
+        channel_id: u64,
+        new_name: Option<String>,
+        new_metadata: Option<Metadata>,
+    },
+    /// The topic of a channel was changed by `changed_by`. An empty topic
+    /// means it was removed. Clients are told with the `new_metadata` of the
+    /// channel, which has the topic.
+    ChannelTopicChanged {
+        guild_id: u64,
+        channel_id: u64,
+        topic: String,
+        changed_by: u64,
+        new_metadata: Metadata,
+    },
+    ChannelMoved {
+        guild_id: u64,
+        channel_id: u64,
+        new_position: ItemPosition,
+    },
+    ChannelsReordered {
+        guild_id: u64,
+        channel_ids: Vec<u64>,
+    },
+    ChannelDeleted {
+        guild_id: u64,
+        channel_id: u64,
+    },
+    GuildUpdated {
+        guild_id: u64,
+        new_name: Option<String>,
+        new_picture: Option<String>,
+        new_metadata: Option<Metadata>,
+    },
+    /// Custom emoji of a guild were added or deleted. Clients are told with the
+    /// `new_metadata` of the guild, which lists all of its emoji.
+    GuildEmojiUpdated {
+        guild_id: u64,
+        added_emoji: Vec<Emote>,
+        deleted_emoji: Vec<String>,
+        new_metadata: Metadata,
+    },
+    /// `local_members` are the local users that had the guild in their guild list.
+    GuildDeleted {
+        guild_id: u64,
+        local_members: Vec<u64>,
+    },
+    GuildAddedToList {
+        guild_id: u64,
+        user_id: u64,
+    },
+    GuildRemovedFromList {
+        guild_id: u64,
+        user_id: u64,
+    },
+    MemberJoined {
+        guild_id: u64,
+        user_id: u64,
+    },
+    /// A user applied to join a guild that screens its members, and waits
+    /// for a moderator to review their answers.
+    ScreeningApplicationSubmitted {
+        guild_id: u64,
+        user_id: u64,
+    },
+    /// A moderator accepted or rejected a screening application.
+    ScreeningApplicationReviewed {
+        guild_id: u64,
+        user_id: u64,
+        reviewed_by: u64,
+        accepted: bool,
+    },
+    MemberLeft {
+        guild_id: u64,
+        user_id: u64,
+        reason: LeaveReason,
+        /// Reason a moderator gave for banning the member, if they were banned.
+        ban_reason: Option<String>,
+    },
+    RoleCreated {
+        guild_id: u64,
+        role_id: u64,
+        name: String,
+        color: i32,
+        hoist: bool,
+        pingable: bool,
+    },
+    RoleUpdated {
+        guild_id: u64,
+        role_id: u64,
+        new_name: Option<String>,
+        new_color: Option<i32>,
+        new_hoist: Option<bool>,
+        new_pingable: Option<bool>,
+    },
+    RoleMoved {
+        guild_id: u64,
+        role_id: u64,
+        new_position: ItemPosition,
+    },
+    RoleDeleted {
+        guild_id: u64,
+        role_id: u64,
+    },
+    /// `affected_users` are the members that have the role and aren't guild owners.
+    /// `synced_channel_ids` are the channels whose permissions are synced
+    /// with the updated category, and were updated the same way.
+    RolePermissionsUpdated {
+        guild_id: u64,
+        channel_id: Option<u64>,
+        role_id: u64,
+        new_perms: Vec<Permission>,
+        affected_users: Vec<u64>,
+        synced_channel_ids: Vec<u64>,
+    },
+    UserRolesUpdated {
+        guild_id: u64,
+        user_id: u64,
+        new_role_ids: Vec<u64>,
+    },
+    /// Role changes of many users made at once. Each pair is a user ID
+    /// and the new role IDs of that user.
+    UserRolesBulkUpdated {
+        guild_id: u64,
+        updates: Vec<(u64, Vec<u64>)>,
+    },
+    /// `user_ids` are the users that now have the pack equipped.
+    EmotePackAdded {
+        pack: EmotePack,
+        user_ids: Vec<u64>,
+    },
+    /// `user_ids` are the users that had the pack equipped.
+    EmotePackDeleted {
+        pack_id: u64,
+        user_ids: Vec<u64>,
+    },
+    /// Emotes or aliases of a pack changed, and it is now at `version`. Alias
+    /// changes are sent with no added or deleted emotes, telling clients to
+    /// fetch the pack again. `user_ids` are the users that have the pack equipped.
+    EmotePackEmotesUpdated {
+        pack_id: u64,
+        version: u64,
+        added_emotes: Vec<Emote>,
+        deleted_emotes: Vec<String>,
+        user_ids: Vec<u64>,
+    },
+    /// `seen_by` are the users that can see the updated user.
+    ProfileUpdated {
+        user_id: u64,
+        new_username: Option<String>,
+        new_avatar: Option<String>,
+        new_status: Option<i32>,
+        new_is_bot: Option<bool>,
+        seen_by: Vec<u64>,
+    },
+}
+
+impl DomainEvent {
+    /// Guild and channel ID of the event, and what it is about, if it can be
+    /// merged with other events of busy channels.
+    fn coalesce_key(&self) -> Option<(u64, u64, CoalesceKey)> {
+        match self {
+            DomainEvent::ReactionUpdated {
+                guild_id,
+                channel_id,
+                message_id,
+                reaction,
+            } => Some((
+                *guild_id,
+                *channel_id,
+                CoalesceKey::Reaction {
+                    message_id: *message_id,
+                    image_id: reaction
+                        .as_ref()
+                        .and_then(|reaction| reaction.emote.as_ref())
+                        .map(|emote| emote.image_id.clone())
+                        .unwrap_or_default(),
+                },
+            )),
+            DomainEvent::Typing {
+                guild_id,
+                channel_id,
+                user_id,
+            } => Some((
+                *guild_id,
+                *channel_id,
+                CoalesceKey::Typing { user_id: *user_id },
+            )),
+            _ => None,
+        }
+    }
+
+    /// Converts this event to the protocol events sent to event streams.
+    pub fn to_broadcasts(&self) -> Vec<EventBroadcast> {
+        fn guild(guild_id: u64, event: chat_event::Event) -> EventBroadcast {
+            EventBroadcast::new(
+                EventSub::Guild(guild_id),
+                chat::Event::Chat(event),
+                None,
+                EventContext::empty(),
+            )
+        }
+
+        fn channel(guild_id: u64, channel_id: u64, event: chat_event::Event) -> EventBroadcast {
+            EventBroadcast::new(
+                EventSub::Guild(guild_id),
+                chat::Event::Chat(event),
+                Some(PermCheck::new(
+                    guild_id,
+                    Some(channel_id),
+                    all_permissions::MESSAGES_VIEW,
+                    false,
+                )),
+                EventContext::empty(),
+            )
+        }
+
+        fn homeserver(event: chat::Event, user_ids: Vec<u64>) -> EventBroadcast {
+            EventBroadcast::new(
+                EventSub::Homeserver,
+                event,
+                None,
+                EventContext::new(user_ids),
+            )
+        }
+
+        let broadcast = match self.clone() {
+            DomainEvent::MessageSent {
+                guild_id,
+                channel_id,
+                message_id,
+                echo_id,
+                message,
+            } => channel(
+                guild_id,
+                channel_id,
+                chat_event::Event::SentMessage(Box::new(chat_event::MessageSent {
+                    echo_id,
+                    guild_id,
+                    channel_id,
+                    message_id,
+                    message: Some(message),
+                })),
+            ),
+            DomainEvent::ThreadMessageSent {
+                guild_id,
+                channel_id,
+                thread_message_id,
+                message,
+                subscribers,
+                ..
+            } => EventBroadcast::new(
+                EventSub::Guild(guild_id),
+                chat::Event::Chat(chat_event::Event::SentMessage(Box::new(
+                    chat_event::MessageSent {
+                        echo_id: None,
+                        guild_id,
+                        channel_id,
+                        message_id: thread_message_id,
+                        message: Some(message),
+                    },
+                ))),
+                Some(PermCheck::new(
+                    guild_id,
+                    Some(channel_id),
+                    all_permissions::MESSAGES_VIEW,
+                    false,
+                )),
+                EventContext::new(subscribers),
+            ),
+            DomainEvent::MessageQuarantined {
+                guild_id,
+                channel_id,
+                message_id,
+                echo_id,
+                message,
+            } => {
+                let author_id = message.author_id;
+                let event = chat::Event::Chat(chat_event::Event::SentMessage(Box::new(
+                    chat_event::MessageSent {
+                        echo_id,
+                        guild_id,
+                        channel_id,
+                        message_id,
+                        message: Some(message),
+                    },
+                )));
+                return vec![
+                    EventBroadcast::new(
+                        EventSub::Guild(guild_id),
+                        event.clone(),
+                        Some(PermCheck::new(
+                            guild_id,
+                            Some(channel_id),
+                            "messages.quarantine.review",
+                            false,
+                        )),
+                        EventContext::empty(),
+                    ),
+                    EventBroadcast::new(
+                        EventSub::Guild(guild_id),
+                        event,
+                        None,
+                        EventContext::new(vec![author_id]),
+                    ),
+                ];
+            }
+            DomainEvent::MessageUpdated {
+                guild_id,
+                channel_id,
+                message_id,
+                edited_at,
+                new_content,
+            } => channel(
+                guild_id,
+                channel_id,
+                chat_event::Event::EditedMessage(Box::new(chat_event::MessageUpdated {
+                    guild_id,
+                    channel_id,
+                    message_id,
+                    edited_at,
+                    new_content,
+                })),
+            ),
+            DomainEvent::MessageDeleted {
+                guild_id,
+                channel_id,
+                message_id,
+            } => channel(
+                guild_id,
+                channel_id,
+                chat_event::Event::DeletedMessage(chat_event::MessageDeleted {
+                    guild_id,
+                    channel_id,
+                    message_id,
+                }),
+            ),
+            DomainEvent::MessagesDeleted {
+                guild_id,
+                channel_id,
+                message_ids,
+            } => {
+                return message_ids
+                    .into_iter()
+                    .map(|message_id| {
+                        channel(
+                            guild_id,
+                            channel_id,
+                            chat_event::Event::DeletedMessage(chat_event::MessageDeleted {
+                                guild_id,
+                                channel_id,
+                                message_id,
+                            }),
+                        )
+                    })
+                    .collect();
+            }
+            DomainEvent::MessagePinned {
+                guild_id,
+                channel_id,
+                message_id,
+            } => channel(
+                guild_id,
+                channel_id,
+                chat_event::Event::MessagePinned(chat_event::MessagePinned {
+                    guild_id,
+                    channel_id,
+                    message_id,
+                }),
+            ),
+            DomainEvent::MessageUnpinned {
+                guild_id,
+                channel_id,
+                message_id,
+            } => channel(
+                guild_id,
+                channel_id,
+                chat_event::Event::MessageUnpinned(chat_event::MessageUnpinned {
+                    guild_id,
+                    channel_id,
+                    message_id,
+                }),
+            ),
+            DomainEvent::ReactionUpdated {
+                guild_id,
+                channel_id,
+                message_id,
+                reaction,
+            } => channel(
+                guild_id,
+                channel_id,
+                chat_event::Event::ReactionUpdated(chat_event::ReactionUpdated {
+                    guild_id,
+                    channel_id,
+                    message_id,
+                    reaction,
+                }),
+            ),
+            DomainEvent::Typing {
+                guild_id,
+                channel_id,
+                user_id,
+            } => channel(
+                guild_id,
+                channel_id,
+                chat_event::Event::Typing(chat_event::Typing {
+                    user_id,
+                    guild_id,
+                    channel_id,
+                }),
+            ),
+            DomainEvent::ChannelCreated {
+                guild_id,
+                channel_id,
+                name,
+                position,
+                kind,
+                metadata,
+            } => channel(
+                guild_id,
+                channel_id,
+                chat_event::Event::CreatedChannel(chat_event::ChannelCreated {
+                    guild_id,
+                    channel_id,
+                    name,
+                    position,
+                    kind,
+                    metadata,
+                }),
+            ),
+            DomainEvent::ChannelUpdated {
+                guild_id,
+                channel_id,
+                new_name,
+                new_metadata,
+            } => channel(
+                guild_id,
+                channel_id,
+                chat_event::Event::EditedChannel(chat_event::ChannelUpdated {
+                    guild_id,
+                    channel_id,
+                    new_name,
+                    new_metadata,
+                }),
+            ),
+            DomainEvent::ChannelTopicChanged {
+                guild_id,
+                channel_id,
+                new_metadata,
+                ..
+            } => channel(
+                guild_id,
+                channel_id,
+                chat_event::Event::EditedChannel(chat_event::ChannelUpdated {
+                    guild_id,
+                    channel_id,
+                    new_name: None,
+                    new_metadata: Some(new_metadata),
+                }),
+            ),
+            DomainEvent::ChannelMoved {
+                guild_id,
+                channel_id,
+                new_position,
+            } => channel(
+                guild_id,
+                channel_id,
+                chat_event::Event::EditedChannelPosition(chat_event::ChannelPositionUpdated {
+                    guild_id,
+                    channel_id,
+                    new_position: Some(new_position),
+                }),
+            ),
+            DomainEvent::ChannelsReordered {
+                guild_id,
+                channel_ids,
+            } => guild(
+                guild_id,
+                chat_event::Event::ChannelsReordered(chat_event::ChannelsReordered {
+                    guild_id,
+                    channel_ids,
+                }),
+            ),
+            DomainEvent::ChannelDeleted {
+                guild_id,
+                channel_id,
+            } => guild(
+                guild_id,
+                chat_event::Event::DeletedChannel(chat_event::ChannelDeleted {
+                    guild_id,
+                    channel_id,
+                }),
+            ),
+            DomainEvent::GuildUpdated {
+                guild_id,
+                new_name,
+                new_picture,
+                new_metadata,
+            } => guild(
+                guild_id,
+                chat_event::Event::EditedGuild(chat_event::GuildUpdated {
+                    guild_id,
+                    new_name,
+                    new_picture,
+                    new_metadata,
+                }),
+            ),
+            DomainEvent::GuildEmojiUpdated {
+                guild_id,
+                new_metadata,
+                ..
+            } => guild(
+                guild_id,
+                chat_event::Event::EditedGuild(chat_event::GuildUpdated {
+                    guild_id,
+                    new_name: None,
+                    new_picture: None,
+                    new_metadata: Some(new_metadata),
+                }),
+            ),
+            DomainEvent::GuildDeleted {
+                guild_id,
+                local_members,
+            } => {
+                return vec![
+                    guild(
+                        guild_id,
+                        chat_event::Event::DeletedGuild(chat_event::GuildDeleted { guild_id }),
+                    ),
+                    homeserver(
+                        chat::Event::Chat(chat_event::Event::GuildRemovedFromList(
+                            chat_event::GuildRemovedFromList {
+                                guild_id,
+                                homeserver: String::new(),
+                            },
+                        )),
+                        local_members,
+                    ),
+                ];
+            }
+            DomainEvent::GuildAddedToList { guild_id, user_id } => homeserver(
+                chat::Event::Chat(chat_event::Event::GuildAddedToList(
+                    chat_event::GuildAddedToList {
+                        guild_id,
+                        homeserver: String::new(),
+                    },
+                )),
+                vec![user_id],
+            ),
+            DomainEvent::GuildRemovedFromList { guild_id, user_id } => homeserver(
+                chat::Event::Chat(chat_event::Event::GuildRemovedFromList(
+                    chat_event::GuildRemovedFromList {
+                        guild_id,
+                        homeserver: String::new(),
+                    },
+                )),
+                vec![user_id],
+            ),
+            DomainEvent::MemberJoined { guild_id, user_id } => guild(
+                guild_id,
+                chat_event::Event::JoinedMember(chat_event::MemberJoined {
+                    guild_id,
+                    member_id: user_id,
+                }),
+            ),
+            // the protocol has no events for these, moderators get applications
+            // with `chat/screening-applications`
+            DomainEvent::ScreeningApplicationSubmitted { .. }
+            | DomainEvent::ScreeningApplicationReviewed { .. } => return Vec::new(),
+            DomainEvent::MemberLeft {
+                guild_id,
+                user_id,
+                reason,
+                ..
+            } => guild(
+                guild_id,
+                chat_event::Event::LeftMember(chat_event::MemberLeft {
+                    guild_id,
+                    member_id: user_id,
+                    leave_reason: reason.into(),
+                }),
+            ),
+            DomainEvent::RoleCreated {
+                guild_id,
+                role_id,
+                name,
+                color,
+                hoist,
+                pingable,
+            } => guild(
+                guild_id,
+                chat_event::Event::RoleCreated(chat_event::RoleCreated {
+                    guild_id,
+                    role_id,
+                    name,
+                    color,
+                    hoist,
+                    pingable,
+                }),
+            ),
+            DomainEvent::RoleUpdated {
+                guild_id,
+                role_id,
+                new_name,
+                new_color,
+                new_hoist,
+                new_pingable,
+            } => guild(
+                guild_id,
+                chat_event::Event::RoleUpdated(chat_event::RoleUpdated {
+                    guild_id,
+                    role_id,
+                    new_name,
+                    new_color,
+                    new_hoist,
+                    new_pingable,
+                }),
+            ),
+            DomainEvent::RoleMoved {
+                guild_id,
+                role_id,
+                new_position,
+            } => guild(
+                guild_id,
+                chat_event::Event::RoleMoved(chat_event::RoleMoved {
+                    guild_id,
+                    role_id,
+                    new_position: Some(new_position),
+                }),
+            ),
+            DomainEvent::RoleDeleted { guild_id, role_id } => guild(
+                guild_id,
+                chat_event::Event::RoleDeleted(chat_event::RoleDeleted { guild_id, role_id }),
+            ),
+            DomainEvent::RolePermissionsUpdated {
+                guild_id,
+                channel_id,
+                role_id,
+                new_perms,
+                affected_users,
+                synced_channel_ids,
+            } => {
+                let channel_ids = std::iter::once(channel_id)
+                    .chain(synced_channel_ids.into_iter().map(Some))
+                    .collect::<Vec<_>>();
+                let mut broadcasts = Vec::with_capacity(channel_ids.len() * (new_perms.len() + 1));
+                for channel_id in channel_ids {
+                    broadcasts.extend(new_perms.iter().map(|perm| {
+                        EventBroadcast::new(
+                            EventSub::Guild(guild_id),
+                            chat::Event::Chat(chat_event::Event::PermissionUpdated(
+                                chat_event::PermissionUpdated {
+                                    guild_id,
+                                    channel_id,
+                                    query: perm.matches.clone(),
+                                    ok: perm.ok,
+                                },
+                            )),
+                            None,
+                            EventContext::new(affected_users.clone()),
+                        )
+                    }));
+                    broadcasts.push(EventBroadcast::new(
+                        EventSub::Guild(guild_id),
+                        chat::Event::Chat(chat_event::Event::RolePermsUpdated(
+                            chat_event::RolePermissionsUpdated {
+                                guild_id,
+                                channel_id,
+                                role_id,
+                                new_perms: new_perms.clone(),
+                            },
+                        )),
+                        Some(PermCheck::new(guild_id, None, "guild.manage", false)),
+                        EventContext::empty(),
+                    ));
+                }
+                return broadcasts;
+            }
+            DomainEvent::UserRolesUpdated {
+                guild_id,
+                user_id,
+                new_role_ids,
+            } => guild(
+                guild_id,
+                chat_event::Event::UserRolesUpdated(chat_event::UserRolesUpdated {
+                    guild_id,
+                    user_id,
+                    new_role_ids,
+                }),
+            ),
+            DomainEvent::UserRolesBulkUpdated { guild_id, updates } => {
+                return updates
+                    .into_iter()
+                    .map(|(user_id, new_role_ids)| {
+                        guild(
+                            guild_id,
+                            chat_event::Event::UserRolesUpdated(chat_event::UserRolesUpdated {
+                                guild_id,
+                                user_id,
+                                new_role_ids,
+                            }),
+                        )
+                    })
+                    .collect();
+            }
+            DomainEvent::EmotePackAdded { pack, user_ids } => homeserver(
+                chat::Event::Emote(emote::stream_event::Event::EmotePackAdded(
+                    emote::EmotePackAdded { pack: Some(pack) },
+                )),
+                user_ids,
+            ),
+            DomainEvent::EmotePackDeleted { pack_id, user_ids } => homeserver(
+                chat::Event::Emote(emote::stream_event::Event::EmotePackDeleted(
+                    emote::EmotePackDeleted { pack_id },
+                )),
+                user_ids,
+            ),
+            DomainEvent::EmotePackEmotesUpdated {
+                pack_id,
+                added_emotes,
+                deleted_emotes,
+                user_ids,
+                ..
+            } => homeserver(
+                chat::Event::Emote(emote::stream_event::Event::EmotePackEmotesUpdated(
+                    emote::EmotePackEmotesUpdated {
+                        pack_id,
+                        added_emotes,
+                        deleted_emotes,
+                    },
+                )),
+                user_ids,
+            ),
+            DomainEvent::ProfileUpdated {
+                user_id,
+                new_username,
+                new_avatar,
+                new_status,
+                new_is_bot,
+                seen_by,
+            } => homeserver(
+                chat::Event::Profile(profile_event::Event::ProfileUpdated(
+                    profile::ProfileUpdated {
+                        user_id,
+                        new_username,
+                        new_avatar,
+                        new_status,
+                        new_is_bot,
+                    },
+                )),
+                seen_by,
+            ),
+        };
+
+        vec![broadcast]
+    }
+}
+
+/// Fans domain events out to internal subscribers and event streams.
+pub struct EventBus {
+    domain_events: broadcast::Sender<Arc<DomainEvent>>,
+    stream_events: EventSender,
+    replay: EventReplay,
+    coalescer: EventCoalescer,
+}
+
+impl EventBus {
+    pub fn new(
+        stream_events: EventSender,
+        replay_config: &EventReplayConfig,
+        coalescing_config: &EventCoalescingConfig,
+    ) -> Self {
+        Self {
+            domain_events: broadcast::channel(2048).0,
+            stream_events,
+            replay: EventReplay::new(replay_config),
+            coalescer: EventCoalescer::new(coalescing_config),
+        }
+    }
+
+    /// Gets the events kept for event streams to resume from.
+    pub fn replay(&self) -> &EventReplay {
+        &self.replay
+    }
+
+    /// Publishes an event to internal subscribers, and sends the protocol
+    /// events it converts to to event streams.
+    pub fn publish(&self, event: DomainEvent) {
+        tracing::debug!(
+            "broadcasting events to {} receivers",
+            self.stream_events.receiver_count()
+        );
+
+        let coalesce_key = event.coalesce_key();
+        for broadcast in event.to_broadcasts() {
+            let broadcast = match &coalesce_key {
+                Some((guild_id, channel_id, key)) => {
+                    self.coalescer
+                        .push(*guild_id, *channel_id, key.clone(), broadcast)
+                }
+                None => Some(broadcast),
+            };
+            if let Some(broadcast) = broadcast {
+                self.replay.send(&self.stream_events, broadcast);
+            }
+        }
+        drop(self.domain_events.send(Arc::new(event)));
+    }
+
+    /// Sends the events held for busy channels whose window is over.
+    pub fn send_coalesced(&self) {
+        for broadcast in self.coalescer.take_due() {
+            self.replay.send(&self.stream_events, broadcast);
+        }
+    }
+
+    /// Subscribes to domain events published from now on.
+    pub fn subscribe(&self) -> DomainEventReceiver {
+        self.domain_events.subscribe()
+    }
+
+    /// Returns the amount of internal subscribers.
+    pub fn receiver_count(&self) -> usize {
+        self.domain_events.receiver_count()
+    }
+}
+
+/// Sends the events held for busy channels once their window is over.
+pub fn spawn_coalesced_sender(deps: Arc<Dependencies>) {
+    let Some(window) = deps.event_bus.coalescer.window() else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(window).await;
+            deps.event_bus.send_coalesced();
+        }
+    });
+}
@@ -1,5 +1,6 @@
 use super::*;
 
+#[require_perms(guild, "channels.manage.create")]
 pub async fn handler(
     svc: &ChatServer,
     request: Request<CreateChannelRequest>,
@@ -16,11 +17,6 @@ pub async fn handler(
 
     let chat_tree = &svc.deps.chat_tree;
 
-    chat_tree.check_guild_user(guild_id, user_id).await?;
-    chat_tree
-        .check_perms(guild_id, None, user_id, "channels.manage.create", false)
-        .await?;
-
     let channel_id = chat_tree
         .create_channel_logic(
             guild_id,
@@ -30,25 +26,25 @@ pub async fn handler(
             position.clone(),
         )
         .await?;
-
-    svc.send_event_through_chan(
-        EventSub::Guild(guild_id),
-        stream_event::Event::CreatedChannel(stream_event::ChannelCreated {
-            guild_id,
+    audit::record(
+        &svc.deps,
+        guild_id,
+        user_id,
+        AuditAction::ChannelCreated {
             channel_id,
-            name: channel_name,
-            position,
-            kind,
-            metadata,
-        }),
-        Some(PermCheck::new(
-            guild_id,
-            Some(channel_id),
-            "messages.view",
-            false,
-        )),
-        EventContext::empty(),
-    );
+            name: channel_name.clone(),
+        },
+    )
+    .await?;
+
+    svc.deps.event_bus.publish(DomainEvent::ChannelCreated {
+        guild_id,
+        channel_id,
+        name: channel_name,
+        position,
+        kind,
+        metadata,
+    });
 
     Ok((CreateChannelResponse { channel_id }).into_response())
 }
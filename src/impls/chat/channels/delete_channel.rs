@@ -1,5 +1,6 @@
 use super::*;
 
+#[require_perms(guild, channel, "channels.manage.delete")]
 pub async fn handler(
     svc: &ChatServer,
     request: Request<DeleteChannelRequest>,
@@ -14,26 +15,39 @@ pub async fn handler(
     let chat_tree = &svc.deps.chat_tree;
 
     chat_tree
-        .check_guild_user_channel(guild_id, user_id, channel_id)
-        .await?;
-    chat_tree
-        .check_perms(
-            guild_id,
-            Some(channel_id),
-            user_id,
-            "channels.manage.delete",
-            false,
-        )
+        .set_channel_role_gate_logic(guild_id, channel_id, Vec::new())
         .await?;
 
+    let msg_prefix = make_msg_prefix(guild_id, channel_id);
+    let mut usage = GuildUsage::default();
     let channel_data = chat_tree
         .scan_prefix(&make_chan_key(guild_id, channel_id))
         .await
         .try_fold(Vec::new(), |mut all, res| {
-            all.push(res?.0);
+            let (key, value) = res?;
+            // only count message keys and thread messages, not the reactions stored under them
+            let is_message = key.len() == make_msg_key(0, 0, 0).len();
+            let is_thread_message = key.len() == make_thread_msg_key(0, 0, 0, 0).len()
+                && key[make_msg_key(0, 0, 0).len()] == 1;
+            if (is_message || is_thread_message) && key.starts_with(&msg_prefix) {
+                let message_usage = message_usage(&db::deser_message(value));
+                usage.message_count += message_usage.message_count;
+                usage.media_bytes += message_usage.media_bytes;
+                usage.emote_count += message_usage.emote_count;
+            }
+            all.push(key);
             ServerResult::Ok(all)
         })?;
 
+    // channels synced with a deleted category would be synced with the one before it otherwise
+    let synced_channel_ids = if chat_tree.is_category_channel(guild_id, channel_id).await? {
+        chat_tree
+            .get_synced_channels_logic(guild_id, channel_id)
+            .await?
+    } else {
+        Vec::new()
+    };
+
     // Remove from ordering list
     let key = make_guild_chan_ordering_key(guild_id);
     let mut ordering = chat_tree.get_list_u64_logic(&key).await?;
@@ -48,22 +62,32 @@ pub async fn handler(
     for key in channel_data {
         batch.remove(key);
     }
+    batch.remove(make_sticky_msg_key(guild_id, channel_id));
+    for synced_channel_id in synced_channel_ids {
+        batch.remove(make_chan_perm_sync_key(guild_id, synced_channel_id));
+    }
     batch.insert(key, serialized_ordering);
     chat_tree
         .chat_tree
         .apply_batch(batch)
         .await
         .map_err(ServerError::DbError)?;
+    chat_tree.evict_channel_permissions(guild_id, channel_id);
+    chat_tree
+        .update_guild_usage_logic(guild_id, usage, false)
+        .await?;
+    audit::record(
+        &svc.deps,
+        guild_id,
+        user_id,
+        AuditAction::ChannelDeleted { channel_id },
+    )
+    .await?;
 
-    svc.send_event_through_chan(
-        EventSub::Guild(guild_id),
-        stream_event::Event::DeletedChannel(stream_event::ChannelDeleted {
-            guild_id,
-            channel_id,
-        }),
-        None,
-        EventContext::empty(),
-    );
+    svc.deps.event_bus.publish(DomainEvent::ChannelDeleted {
+        guild_id,
+        channel_id,
+    });
 
     Ok((DeleteChannelResponse {}).into_response())
 }
@@ -0,0 +1,38 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct GetChannelRoleGateRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetChannelRoleGateResponse {
+    /// Roles whose members auto join the channel. Empty if everyone joins it.
+    pub role_ids: Vec<u64>,
+}
+
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetChannelRoleGateRequest,
+) -> ServerResult<GetChannelRoleGateResponse> {
+    let GetChannelRoleGateRequest {
+        guild_id,
+        channel_id,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree
+        .check_guild_user_channel(guild_id, user_id, channel_id)
+        .await?;
+
+    let role_ids = chat_tree
+        .get_channel_role_gate_logic(guild_id, channel_id)
+        .await?;
+
+    Ok(GetChannelRoleGateResponse { role_ids })
+}
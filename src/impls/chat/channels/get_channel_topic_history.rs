@@ -0,0 +1,50 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct GetChannelTopicHistoryRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TopicChangeInfo {
+    pub topic: String,
+    pub author_id: u64,
+    pub changed_at: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetChannelTopicHistoryResponse {
+    /// Topic changes, newest first.
+    pub changes: Vec<TopicChangeInfo>,
+}
+
+#[require_perms(guild, channel, "messages.view")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetChannelTopicHistoryRequest,
+) -> ServerResult<GetChannelTopicHistoryResponse> {
+    let GetChannelTopicHistoryRequest {
+        guild_id,
+        channel_id,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let changes = chat_tree
+        .get_channel_topic_history_logic(guild_id, channel_id)
+        .await?
+        .into_iter()
+        .rev()
+        .map(|change| TopicChangeInfo {
+            topic: change.topic,
+            author_id: change.author_id,
+            changed_at: change.changed_at,
+        })
+        .collect();
+
+    Ok(GetChannelTopicHistoryResponse { changes })
+}
@@ -0,0 +1,39 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct GetDisappearingMessagesRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetDisappearingMessagesResponse {
+    /// Not set if messages in the channel don't disappear.
+    pub settings: Option<DisappearingMessagesInfo>,
+}
+
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetDisappearingMessagesRequest,
+) -> ServerResult<GetDisappearingMessagesResponse> {
+    let GetDisappearingMessagesRequest {
+        guild_id,
+        channel_id,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree
+        .check_guild_user_channel(guild_id, user_id, channel_id)
+        .await?;
+
+    let settings = chat_tree
+        .get_disappearing_messages_logic(guild_id, channel_id)
+        .await?
+        .map(DisappearingMessagesInfo::from);
+
+    Ok(GetDisappearingMessagesResponse { settings })
+}
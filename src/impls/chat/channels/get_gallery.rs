@@ -0,0 +1,116 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+/// Default amount of gallery items returned.
+const DEFAULT_LIMIT: usize = 30;
+/// Maximum amount of gallery items that can be returned at once.
+const MAX_LIMIT: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct GetGalleryRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    /// Only return items older than this message, to get the next page.
+    #[serde(default)]
+    pub before_message_id: Option<u64>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+/// A file in a gallery item.
+#[derive(Debug, Serialize)]
+pub struct GalleryFile {
+    /// Attachment ID, or HMC of a photo.
+    pub id: String,
+    pub name: String,
+    pub mimetype: Option<String>,
+    pub size: u32,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+/// A message with media in a gallery channel.
+#[derive(Debug, Serialize)]
+pub struct GalleryItem {
+    pub message_id: u64,
+    pub author_id: u64,
+    pub created_at: u64,
+    pub files: Vec<GalleryFile>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetGalleryResponse {
+    pub items: Vec<GalleryItem>,
+    pub reached_top: bool,
+}
+
+#[require_perms(guild, channel, "messages.view")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetGalleryRequest,
+) -> ServerResult<GetGalleryResponse> {
+    let GetGalleryRequest {
+        guild_id,
+        channel_id,
+        before_message_id,
+        limit,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    if !chat_tree.is_gallery_channel(guild_id, channel_id).await? {
+        bail!((
+            "h.not-a-gallery-channel",
+            "this channel is not in gallery mode"
+        ));
+    }
+
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let (messages, reached_top) = chat_tree
+        .get_gallery_logic(guild_id, channel_id, before_message_id, limit)
+        .await?;
+
+    let items = messages
+        .into_iter()
+        .map(|(message_id, message)| GalleryItem {
+            message_id,
+            author_id: message.author_id,
+            created_at: message.created_at,
+            files: gallery_files(message),
+        })
+        .collect();
+
+    Ok(GetGalleryResponse { items, reached_top })
+}
+
+fn gallery_files(message: HarmonyMessage) -> Vec<GalleryFile> {
+    match message.content.and_then(|c| c.content) {
+        Some(content::Content::AttachmentMessage(files)) => files
+            .files
+            .into_iter()
+            .map(|file| GalleryFile {
+                id: file.id,
+                name: file.name,
+                mimetype: Some(file.mimetype),
+                size: file.size,
+                width: None,
+                height: None,
+            })
+            .collect(),
+        Some(content::Content::PhotoMessage(photos)) => photos
+            .photos
+            .into_iter()
+            .map(|photo| GalleryFile {
+                id: photo.hmc,
+                name: photo.name,
+                mimetype: None,
+                size: photo.file_size,
+                width: Some(photo.width),
+                height: Some(photo.height),
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
@@ -0,0 +1,38 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct GetTypingUsersRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetTypingUsersResponse {
+    pub user_ids: Vec<u64>,
+    /// How long a typing event lasts, in seconds. Clients should stop showing
+    /// a user as typing if they don't get another typing event in this time.
+    pub timeout_secs: u64,
+}
+
+/// Gets the users currently typing in a channel, for clients that just
+/// opened it and missed the typing events.
+#[require_perms(guild, channel, "messages.view")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetTypingUsersRequest,
+) -> ServerResult<GetTypingUsersResponse> {
+    let GetTypingUsersRequest {
+        guild_id,
+        channel_id,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    Ok(GetTypingUsersResponse {
+        user_ids: svc.deps.typing_indicators.typing_in(guild_id, channel_id),
+        timeout_secs: TYPING_TIMEOUT.as_secs(),
+    })
+}
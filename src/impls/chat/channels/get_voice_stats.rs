@@ -0,0 +1,62 @@
+use super::*;
+
+use serde::Deserialize;
+
+use crate::impls::diagnostics::VoiceParticipantStats;
+
+#[derive(Debug, Deserialize)]
+pub struct GetVoiceStatsRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    /// Only get stats of this user. If not set, gets the stats of everyone
+    /// in the channel, which needs the `voice.stats.view` permission.
+    #[serde(default)]
+    pub user_id: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetVoiceStatsResponse {
+    pub participants: Vec<VoiceParticipantStats>,
+}
+
+/// Gets call quality stats of voice participants. Users can always get their
+/// own stats, moderators can get everyone's to look into bad calls.
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetVoiceStatsRequest,
+) -> ServerResult<GetVoiceStatsResponse> {
+    let GetVoiceStatsRequest {
+        guild_id,
+        channel_id,
+        user_id: for_user_id,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree
+        .check_guild_user_channel(guild_id, user_id, channel_id)
+        .await?;
+    if for_user_id != Some(user_id) {
+        chat_tree
+            .check_perms(
+                guild_id,
+                Some(channel_id),
+                user_id,
+                "voice.stats.view",
+                false,
+            )
+            .await?;
+    }
+
+    let voice_stats = &svc.deps.diagnostics.voice;
+    let participants = match for_user_id {
+        Some(for_user_id) => voice_stats
+            .get(guild_id, channel_id, for_user_id)
+            .into_iter()
+            .collect(),
+        None => voice_stats.in_channel(guild_id, channel_id),
+    };
+
+    Ok(GetVoiceStatsResponse { participants })
+}
@@ -1,9 +1,166 @@
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+
 use super::*;
 
 pub mod create_channel;
 pub mod delete_channel;
+pub mod get_channel_role_gate;
+pub mod get_channel_topic_history;
+pub mod get_disappearing_messages;
+pub mod get_gallery;
 pub mod get_guild_channels;
+pub mod get_typing_users;
+pub mod get_voice_stats;
+pub mod rebroadcast_sticky_message;
+pub mod reorder_channels;
+pub mod set_channel_role_gate;
+pub mod set_channel_topic;
+pub mod set_disappearing_messages;
+pub mod set_gallery_mode;
+pub mod set_sticky_message;
 pub mod typing;
 pub mod update_all_channel_order;
 pub mod update_channel_information;
 pub mod update_channel_order;
+
+/// A sticky message, in the form it's put in the channel metadata.
+#[derive(Debug, Serialize)]
+pub struct StickyMessageInfo {
+    pub text: String,
+    pub author_id: u64,
+    /// Time the message was set, in seconds since unix epoch.
+    pub set_at: u64,
+    /// How often the message is broadcast again, in seconds.
+    pub rebroadcast_interval: Option<u64>,
+}
+
+impl From<StickyMessage> for StickyMessageInfo {
+    fn from(sticky: StickyMessage) -> Self {
+        Self {
+            text: sticky.text,
+            author_id: sticky.author_id,
+            set_at: sticky.set_at,
+            rebroadcast_interval: sticky.rebroadcast_interval,
+        }
+    }
+}
+
+/// When the countdown of a disappearing message starts, as it's put in the
+/// channel metadata and taken in requests.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisappearAfterInfo {
+    Send,
+    Read,
+}
+
+impl From<DisappearAfter> for DisappearAfterInfo {
+    fn from(after: DisappearAfter) -> Self {
+        match after {
+            DisappearAfter::Send => Self::Send,
+            DisappearAfter::Read => Self::Read,
+        }
+    }
+}
+
+impl From<DisappearAfterInfo> for DisappearAfter {
+    fn from(after: DisappearAfterInfo) -> Self {
+        match after {
+            DisappearAfterInfo::Send => Self::Send,
+            DisappearAfterInfo::Read => Self::Read,
+        }
+    }
+}
+
+/// Disappearing message settings, in the form they're put in the channel
+/// metadata. Clients can use them to show how long messages have left.
+#[derive(Debug, Serialize)]
+pub struct DisappearingMessagesInfo {
+    pub after: DisappearAfterInfo,
+    /// How long messages are kept once their countdown starts, in seconds.
+    pub ttl_secs: u64,
+    pub set_by: u64,
+    /// Time the settings were set, in seconds since unix epoch.
+    pub set_at: u64,
+}
+
+impl From<DisappearingMessages> for DisappearingMessagesInfo {
+    fn from(settings: DisappearingMessages) -> Self {
+        Self {
+            after: settings.after.into(),
+            ttl_secs: settings.ttl_secs,
+            set_by: settings.set_by,
+            set_at: settings.set_at,
+        }
+    }
+}
+
+/// How long a user is shown as typing after a typing request.
+pub const TYPING_TIMEOUT: Duration = Duration::from_secs(6);
+/// Typing requests sent sooner than this after the last broadcasted one
+/// only extend the typing state, without broadcasting another event.
+const TYPING_REBROADCAST_AFTER: Duration = Duration::from_secs(3);
+
+/// Users currently typing in channels, keyed by guild, channel and user IDs.
+/// This is only kept in memory, typing states expire on their own.
+#[derive(Default)]
+pub struct TypingIndicators {
+    typing: DashMap<(u64, u64, u64), TypingState, ahash::RandomState>,
+}
+
+struct TypingState {
+    since: Instant,
+    broadcasted_at: Option<Instant>,
+}
+
+impl TypingIndicators {
+    /// Marks a user as typing in a channel. Returns whether a typing event
+    /// should be sent for it.
+    pub fn start(&self, guild_id: u64, channel_id: u64, user_id: u64) -> bool {
+        if self.typing.len() >= 1024 {
+            self.typing
+                .retain(|_, state| state.since.elapsed() < TYPING_TIMEOUT);
+        }
+
+        let now = Instant::now();
+        let mut state = self
+            .typing
+            .entry((guild_id, channel_id, user_id))
+            .or_insert(TypingState {
+                since: now,
+                broadcasted_at: None,
+            });
+        state.since = now;
+        let should_broadcast = state
+            .broadcasted_at
+            .map_or(true, |at| at.elapsed() >= TYPING_REBROADCAST_AFTER);
+        if should_broadcast {
+            state.broadcasted_at = Some(now);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Marks a user as no longer typing in a channel, for example because they sent their message.
+    pub fn stop(&self, guild_id: u64, channel_id: u64, user_id: u64) {
+        self.typing.remove(&(guild_id, channel_id, user_id));
+    }
+
+    /// Gets the users currently typing in a channel.
+    pub fn typing_in(&self, guild_id: u64, channel_id: u64) -> Vec<u64> {
+        self.typing
+            .iter()
+            .filter(|entry| {
+                let (entry_guild_id, entry_channel_id, _) = *entry.key();
+                entry_guild_id == guild_id
+                    && entry_channel_id == channel_id
+                    && entry.since.elapsed() < TYPING_TIMEOUT
+            })
+            .map(|entry| entry.key().2)
+            .collect()
+    }
+}
@@ -0,0 +1,93 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+/// How often sticky messages are checked for rebroadcasting.
+pub const STICKY_MESSAGE_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Deserialize)]
+pub struct RebroadcastStickyMessageRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RebroadcastStickyMessageResponse {}
+
+/// Broadcasts the sticky message of a channel again, so clients show it at the top.
+#[require_perms(guild, channel, "channels.manage.change-information")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: RebroadcastStickyMessageRequest,
+) -> ServerResult<RebroadcastStickyMessageResponse> {
+    let RebroadcastStickyMessageRequest {
+        guild_id,
+        channel_id,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let Some(sticky) = chat_tree
+        .get_sticky_message_logic(guild_id, channel_id)
+        .await?
+    else {
+        bail!(("h.no-sticky-message", "this channel has no sticky message"));
+    };
+    rebroadcast(&svc.deps, guild_id, channel_id, sticky).await?;
+
+    Ok(RebroadcastStickyMessageResponse {})
+}
+
+/// Broadcasts all sticky messages whose rebroadcast interval has passed.
+///
+/// Sticky messages of channels that don't exist anymore are removed.
+pub async fn rebroadcast_due(deps: &Dependencies) -> ServerResult<()> {
+    let chat_tree = &deps.chat_tree;
+    let now = get_time_secs();
+
+    for (guild_id, channel_id, sticky) in chat_tree.get_sticky_messages_logic().await? {
+        if !chat_tree
+            .contains_key(make_chan_key(guild_id, channel_id))
+            .await?
+        {
+            chat_tree
+                .remove(make_sticky_msg_key(guild_id, channel_id))
+                .await?;
+            continue;
+        }
+        let Some(interval) = sticky.rebroadcast_interval else {
+            continue;
+        };
+        if now.saturating_sub(sticky.last_broadcast) < interval {
+            continue;
+        }
+        rebroadcast(deps, guild_id, channel_id, sticky).await?;
+    }
+
+    Ok(())
+}
+
+async fn rebroadcast(
+    deps: &Dependencies,
+    guild_id: u64,
+    channel_id: u64,
+    mut sticky: StickyMessage,
+) -> ServerResult<()> {
+    let chat_tree = &deps.chat_tree;
+
+    let (_, chan_info) = chat_tree.get_channel_logic(guild_id, channel_id).await?;
+    deps.event_bus.publish(DomainEvent::ChannelUpdated {
+        guild_id,
+        channel_id,
+        new_name: None,
+        new_metadata: chan_info.metadata,
+    });
+
+    sticky.last_broadcast = get_time_secs();
+    chat_tree
+        .insert(make_sticky_msg_key(guild_id, channel_id), rkyv_ser(&sticky))
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use std::collections::HashSet;
+
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct CategoryOrder {
+    pub category_id: u64,
+    /// Channels in the category, in order.
+    #[serde(default)]
+    pub channel_ids: Vec<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReorderChannelsRequest {
+    pub guild_id: u64,
+    /// Channels that aren't in a category, in order. They come before every
+    /// category.
+    #[serde(default)]
+    pub uncategorized: Vec<u64>,
+    /// Categories with their channels, in order.
+    #[serde(default)]
+    pub categories: Vec<CategoryOrder>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReorderChannelsResponse {
+    /// The new order of every channel in the guild.
+    pub channel_ids: Vec<u64>,
+}
+
+/// Reorders every channel of a guild at once, moving channels between
+/// categories. A category groups the channels that come after it in the
+/// channel order, until the next category. Every channel of the guild must
+/// be listed exactly once.
+#[require_perms(guild, "channels.manage.move")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: ReorderChannelsRequest,
+) -> ServerResult<ReorderChannelsResponse> {
+    let ReorderChannelsRequest {
+        guild_id,
+        uncategorized,
+        categories,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let mut channel_ids = uncategorized;
+    let mut category_ids = HashSet::with_capacity(categories.len());
+    for category in categories {
+        category_ids.insert(category.category_id);
+        channel_ids.push(category.category_id);
+        channel_ids.extend(category.channel_ids);
+    }
+
+    let mut seen = HashSet::with_capacity(channel_ids.len());
+    for channel_id in &channel_ids {
+        if !seen.insert(*channel_id) {
+            bail!((
+                "h.duplicate-channel",
+                format!("channel {} is listed more than once", channel_id)
+            ));
+        }
+        let is_category = chat_tree.is_category_channel(guild_id, *channel_id).await?;
+        if is_category != category_ids.contains(channel_id) {
+            bail!((
+                "h.invalid-channel-category",
+                format!(
+                    "channel {} must be listed as a category if and only if it is one",
+                    channel_id
+                )
+            ));
+        }
+    }
+    let prefix = make_guild_chan_prefix(guild_id);
+    for res in chat_tree.scan_prefix(&prefix).await {
+        let (key, _) = res?;
+        if key.len() == prefix.len() + size_of::<u64>() {
+            // Safety: this unwrap is safe since we check if it's a valid u64 beforehand
+            let channel_id = u64::from_be_bytes(unsafe {
+                key.split_at(prefix.len()).1.try_into().unwrap_unchecked()
+            });
+            if !seen.contains(&channel_id) {
+                bail!(ServerError::UnderSpecifiedChannels);
+            }
+        }
+    }
+
+    let key = make_guild_chan_ordering_key(guild_id);
+    let serialized_ordering = chat_tree.serialize_list_u64_logic(channel_ids.clone());
+    chat_tree.insert(key, serialized_ordering).await?;
+
+    svc.deps.event_bus.publish(DomainEvent::ChannelsReordered {
+        guild_id,
+        channel_ids: channel_ids.clone(),
+    });
+
+    Ok(ReorderChannelsResponse { channel_ids })
+}
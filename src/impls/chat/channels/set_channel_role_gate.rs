@@ -0,0 +1,40 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct SetChannelRoleGateRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    /// Roles whose members auto join the channel. The channel is only listed
+    /// for members of these roles, unless this is empty.
+    pub role_ids: Vec<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetChannelRoleGateResponse {}
+
+#[require_perms(guild, channel, "channels.role-gates.manage")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: SetChannelRoleGateRequest,
+) -> ServerResult<SetChannelRoleGateResponse> {
+    let SetChannelRoleGateRequest {
+        guild_id,
+        channel_id,
+        role_ids,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    for role_id in &role_ids {
+        chat_tree.does_role_exist(guild_id, *role_id).await?;
+    }
+
+    chat_tree
+        .set_channel_role_gate_logic(guild_id, channel_id, role_ids)
+        .await?;
+
+    Ok(SetChannelRoleGateResponse {})
+}
@@ -0,0 +1,55 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct SetChannelTopicRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    /// The new topic. An empty topic removes the current topic.
+    pub topic: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetChannelTopicResponse {}
+
+#[require_perms(guild, channel, "channels.manage.change-information")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: SetChannelTopicRequest,
+) -> ServerResult<SetChannelTopicResponse> {
+    let SetChannelTopicRequest {
+        guild_id,
+        channel_id,
+        topic,
+    } = request;
+
+    if topic.chars().count() > MAX_TOPIC_LEN {
+        bail!((
+            "h.topic-too-long",
+            format!(
+                "channel topics can be at most {} characters long",
+                MAX_TOPIC_LEN
+            )
+        ));
+    }
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let new_metadata = chat_tree
+        .set_channel_topic_logic(guild_id, channel_id, user_id, topic.clone())
+        .await?;
+
+    svc.deps
+        .event_bus
+        .publish(DomainEvent::ChannelTopicChanged {
+            guild_id,
+            channel_id,
+            topic,
+            changed_by: user_id,
+            new_metadata,
+        });
+
+    Ok(SetChannelTopicResponse {})
+}
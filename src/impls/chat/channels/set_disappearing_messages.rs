@@ -0,0 +1,115 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+/// How often disappearing messages are checked for expiry.
+pub const DISAPPEARING_MESSAGE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Shortest time disappearing messages can be kept for, in seconds.
+pub const MIN_DISAPPEARING_TTL: u64 = 5;
+/// Longest time disappearing messages can be kept for, in seconds.
+pub const MAX_DISAPPEARING_TTL: u64 = 4 * 7 * 24 * 60 * 60;
+
+#[derive(Debug, Deserialize)]
+pub struct SetDisappearingMessagesRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    /// When the countdown of a message starts. Defaults to when it's sent.
+    #[serde(default)]
+    pub after: Option<DisappearAfterInfo>,
+    /// How long messages are kept once their countdown starts, in seconds.
+    /// Zero turns disappearing messages off.
+    pub ttl_secs: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetDisappearingMessagesResponse {}
+
+/// Sets how long messages sent in a channel are kept before they disappear.
+/// Messages that were already sent keep disappearing as they were set to.
+#[require_perms(guild, channel, "channels.manage.change-information")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: SetDisappearingMessagesRequest,
+) -> ServerResult<SetDisappearingMessagesResponse> {
+    let SetDisappearingMessagesRequest {
+        guild_id,
+        channel_id,
+        after,
+        ttl_secs,
+    } = request;
+
+    if ttl_secs != 0 && !(MIN_DISAPPEARING_TTL..=MAX_DISAPPEARING_TTL).contains(&ttl_secs) {
+        bail!((
+            "h.invalid-disappearing-ttl",
+            format!(
+                "disappearing messages must be kept for between {} and {} seconds",
+                MIN_DISAPPEARING_TTL, MAX_DISAPPEARING_TTL
+            )
+        ));
+    }
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let settings = (ttl_secs != 0).then(|| DisappearingMessages {
+        after: after.map_or(DisappearAfter::Send, Into::into),
+        ttl_secs,
+        set_by: user_id,
+        set_at: get_time_secs(),
+    });
+    let new_metadata = chat_tree
+        .put_disappearing_messages_logic(guild_id, channel_id, settings)
+        .await?;
+
+    svc.deps.event_bus.publish(DomainEvent::ChannelUpdated {
+        guild_id,
+        channel_id,
+        new_name: None,
+        new_metadata: Some(new_metadata),
+    });
+
+    Ok(SetDisappearingMessagesResponse {})
+}
+
+/// Deletes the disappearing messages that expired, sending the usual events
+/// for deleted messages, which reach members on other homeservers through
+/// their event streams like any other deletion.
+pub async fn purge_expired(deps: &Dependencies) -> ServerResult<()> {
+    let chat_tree = &deps.chat_tree;
+    let now = get_time_secs();
+
+    let mut expired: HashMap<(u64, u64), Vec<u64>> = HashMap::new();
+    for (guild_id, channel_id, message_id) in chat_tree.get_expired_disappearing_logic(now).await? {
+        expired
+            .entry((guild_id, channel_id))
+            .or_default()
+            .push(message_id);
+    }
+
+    for ((guild_id, channel_id), message_ids) in expired {
+        let mut messages = Vec::with_capacity(message_ids.len());
+        for message_id in message_ids {
+            // the message, or its channel, might have been deleted already
+            if let Some(raw) = chat_tree
+                .get(make_msg_key(guild_id, channel_id, message_id))
+                .await?
+            {
+                messages.push((message_id, db::deser_message(raw)));
+            }
+        }
+        if messages.is_empty() {
+            continue;
+        }
+        chat_tree
+            .delete_messages_logic(guild_id, channel_id, &messages)
+            .await?;
+        deps.event_bus.publish(DomainEvent::MessagesDeleted {
+            guild_id,
+            channel_id,
+            message_ids: messages.into_iter().map(|(id, _)| id).collect(),
+        });
+    }
+    chat_tree.clear_expired_disappearing_logic(now).await?;
+
+    Ok(())
+}
@@ -0,0 +1,42 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct SetGalleryModeRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    /// Whether only attachments and photos can be sent in the channel.
+    pub enabled: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetGalleryModeResponse {}
+
+#[require_perms(guild, channel, "channels.manage.change-information")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: SetGalleryModeRequest,
+) -> ServerResult<SetGalleryModeResponse> {
+    let SetGalleryModeRequest {
+        guild_id,
+        channel_id,
+        enabled,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let new_metadata = chat_tree
+        .set_gallery_mode_logic(guild_id, channel_id, enabled)
+        .await?;
+
+    svc.deps.event_bus.publish(DomainEvent::ChannelUpdated {
+        guild_id,
+        channel_id,
+        new_name: None,
+        new_metadata: Some(new_metadata),
+    });
+
+    Ok(SetGalleryModeResponse {})
+}
@@ -0,0 +1,80 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+/// Maximum length of a sticky message, in bytes.
+pub const MAX_STICKY_MESSAGE_LENGTH: usize = 2000;
+/// Shortest interval sticky messages can be broadcast again at, in seconds.
+pub const MIN_STICKY_REBROADCAST_INTERVAL: u64 = 60;
+
+#[derive(Debug, Deserialize)]
+pub struct SetStickyMessageRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    /// Text of the sticky message. An empty text removes the current sticky message.
+    pub text: String,
+    /// How often the message should be broadcast again, in seconds. If not
+    /// set, it is only broadcast when it's set or when asked to.
+    #[serde(default)]
+    pub rebroadcast_interval: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetStickyMessageResponse {}
+
+/// Sets the message shown at the top of a channel.
+#[require_perms(guild, channel, "channels.manage.change-information")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: SetStickyMessageRequest,
+) -> ServerResult<SetStickyMessageResponse> {
+    let SetStickyMessageRequest {
+        guild_id,
+        channel_id,
+        text,
+        rebroadcast_interval,
+    } = request;
+
+    if text.len() > MAX_STICKY_MESSAGE_LENGTH {
+        bail!((
+            "h.sticky-message-too-long",
+            format!(
+                "sticky messages can be at most {} bytes long",
+                MAX_STICKY_MESSAGE_LENGTH
+            )
+        ));
+    }
+    if rebroadcast_interval.map_or(false, |interval| interval < MIN_STICKY_REBROADCAST_INTERVAL) {
+        bail!((
+            "h.invalid-rebroadcast-interval",
+            format!(
+                "sticky messages can be broadcast at most every {} seconds",
+                MIN_STICKY_REBROADCAST_INTERVAL
+            )
+        ));
+    }
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let now = get_time_secs();
+    let sticky = text.is_empty().not().then(|| StickyMessage {
+        text,
+        author_id: user_id,
+        set_at: now,
+        rebroadcast_interval,
+        last_broadcast: now,
+    });
+    let new_metadata = chat_tree
+        .put_sticky_message_logic(guild_id, channel_id, sticky)
+        .await?;
+
+    svc.deps.event_bus.publish(DomainEvent::ChannelUpdated {
+        guild_id,
+        channel_id,
+        new_name: None,
+        new_metadata: Some(new_metadata),
+    });
+
+    Ok(SetStickyMessageResponse {})
+}
@@ -1,5 +1,6 @@
 use super::*;
 
+#[require_perms(guild, channel, "messages.send")]
 pub async fn handler(
     svc: &ChatServer,
     request: Request<TypingRequest>,
@@ -13,28 +14,17 @@ pub async fn handler(
 
     let chat_tree = &svc.deps.chat_tree;
 
-    chat_tree
-        .check_guild_user_channel(guild_id, user_id, channel_id)
-        .await?;
-    chat_tree
-        .check_perms(guild_id, Some(channel_id), user_id, "messages.send", false)
-        .await?;
-
-    svc.send_event_through_chan(
-        EventSub::Guild(guild_id),
-        stream_event::Event::Typing(stream_event::Typing {
-            user_id,
+    if svc
+        .deps
+        .typing_indicators
+        .start(guild_id, channel_id, user_id)
+    {
+        svc.deps.event_bus.publish(DomainEvent::Typing {
             guild_id,
             channel_id,
-        }),
-        Some(PermCheck::new(
-            guild_id,
-            Some(channel_id),
-            "messages.view",
-            false,
-        )),
-        EventContext::empty(),
-    );
+            user_id,
+        });
+    }
 
     Ok((TypingResponse {}).into_response())
 }
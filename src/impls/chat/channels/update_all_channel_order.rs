@@ -1,5 +1,6 @@
 use super::*;
 
+#[require_perms(guild, "channels.manage.move")]
 pub async fn handler(
     svc: &ChatServer,
     request: Request<UpdateAllChannelOrderRequest>,
@@ -13,11 +14,6 @@ pub async fn handler(
 
     let chat_tree = &svc.deps.chat_tree;
 
-    chat_tree.check_guild_user(guild_id, user_id).await?;
-    chat_tree
-        .check_perms(guild_id, None, user_id, "channels.manage.move", false)
-        .await?;
-
     for channel_id in &channel_ids {
         chat_tree.does_channel_exist(guild_id, *channel_id).await?;
     }
@@ -44,15 +40,10 @@ pub async fn handler(
     let serialized_ordering = chat_tree.serialize_list_u64_logic(channel_ids.clone());
     chat_tree.insert(key, serialized_ordering).await?;
 
-    svc.send_event_through_chan(
-        EventSub::Guild(guild_id),
-        stream_event::Event::ChannelsReordered(stream_event::ChannelsReordered {
-            guild_id,
-            channel_ids,
-        }),
-        None,
-        EventContext::empty(),
-    );
+    svc.deps.event_bus.publish(DomainEvent::ChannelsReordered {
+        guild_id,
+        channel_ids,
+    });
 
     Ok((UpdateAllChannelOrderResponse {}).into_response())
 }
@@ -1,5 +1,6 @@
 use super::*;
 
+#[require_perms(guild, channel, "channels.manage.change-information")]
 pub async fn handler(
     svc: &ChatServer,
     request: Request<UpdateChannelInformationRequest>,
@@ -15,17 +16,6 @@ pub async fn handler(
 
     let chat_tree = &svc.deps.chat_tree;
 
-    chat_tree.check_guild_user(guild_id, user_id).await?;
-    chat_tree
-        .check_perms(
-            guild_id,
-            Some(channel_id),
-            user_id,
-            "channels.manage.change-information",
-            false,
-        )
-        .await?;
-
     let key = make_chan_key(guild_id, channel_id);
     let mut chan_info = if let Some(raw) = chat_tree.get(key).await? {
         db::deser_chan(raw)
@@ -40,29 +30,37 @@ pub async fn handler(
     if let Some(new_name) = new_name.clone() {
         chan_info.channel_name = new_name;
     }
-    if let Some(new_metadata) = new_metadata.clone() {
-        chan_info.metadata = Some(new_metadata);
-    }
+    let new_metadata = new_metadata.map(|mut new_metadata| {
+        // these are managed by the server, so they can't be changed this way
+        for name in [
+            CHANNEL_TOPIC_EXTENSION,
+            CHANNEL_STAGE_EXTENSION,
+            CHANNEL_GALLERY_EXTENSION,
+            CHANNEL_STICKY_EXTENSION,
+            CHANNEL_DISAPPEARING_EXTENSION,
+        ] {
+            new_metadata.extension.remove(name);
+            if let Some(value) = chan_info
+                .metadata
+                .as_mut()
+                .and_then(|metadata| metadata.extension.remove(name))
+            {
+                new_metadata.extension.insert(name.to_string(), value);
+            }
+        }
+        chan_info.metadata = Some(new_metadata.clone());
+        new_metadata
+    });
 
     let buf = rkyv_ser(&chan_info);
     chat_tree.insert(key, buf).await?;
 
-    svc.send_event_through_chan(
-        EventSub::Guild(guild_id),
-        stream_event::Event::EditedChannel(stream_event::ChannelUpdated {
-            guild_id,
-            channel_id,
-            new_name,
-            new_metadata,
-        }),
-        Some(PermCheck::new(
-            guild_id,
-            Some(channel_id),
-            "messages.view",
-            false,
-        )),
-        EventContext::empty(),
-    );
+    svc.deps.event_bus.publish(DomainEvent::ChannelUpdated {
+        guild_id,
+        channel_id,
+        new_name,
+        new_metadata,
+    });
 
     Ok((UpdateChannelInformationResponse {}).into_response())
 }
@@ -0,0 +1,171 @@
+//! Merging bursts of events in busy channels.
+//!
+//! Reaction and typing events are sent to event streams right away, unless
+//! their channel had more than the configured amount of events in the last
+//! window. Then they are held until the window ends, and only the latest
+//! event of every reaction, and of every typing user, is sent. Clients only
+//! care about the latest state of a reaction, so nothing is lost.
+
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+
+use crate::config::EventCoalescingConfig;
+
+use super::*;
+
+/// What a held event is about. A newer event about the same thing replaces it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoalesceKey {
+    /// A reaction of a message, by emote image ID.
+    Reaction {
+        message_id: u64,
+        image_id: String,
+    },
+    Typing {
+        user_id: u64,
+    },
+}
+
+/// Holds events of busy channels, keyed by guild and channel ID.
+pub struct EventCoalescer {
+    window: Duration,
+    burst_threshold: u32,
+    channels: DashMap<(u64, u64), ChannelBurst, ahash::RandomState>,
+}
+
+struct ChannelBurst {
+    window_start: Instant,
+    events_in_window: u32,
+    /// Events waiting to be sent, in the order they were first held.
+    held: Vec<(CoalesceKey, EventBroadcast)>,
+    send_at: Option<Instant>,
+}
+
+impl EventCoalescer {
+    pub fn new(config: &EventCoalescingConfig) -> Self {
+        Self {
+            window: Duration::from_millis(config.window),
+            burst_threshold: config.burst_threshold,
+            channels: DashMap::default(),
+        }
+    }
+
+    /// How often held events should be looked at, `None` if events are never held.
+    pub fn window(&self) -> Option<Duration> {
+        (!self.window.is_zero()).then(|| self.window)
+    }
+
+    /// Returns the broadcast if it should be sent right away, otherwise holds it.
+    pub fn push(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        key: CoalesceKey,
+        broadcast: EventBroadcast,
+    ) -> Option<EventBroadcast> {
+        if self.window.is_zero() {
+            return Some(broadcast);
+        }
+
+        let now = Instant::now();
+        let mut burst = self
+            .channels
+            .entry((guild_id, channel_id))
+            .or_insert_with(|| ChannelBurst {
+                window_start: now,
+                events_in_window: 0,
+                held: Vec::new(),
+                send_at: None,
+            });
+        if now.duration_since(burst.window_start) >= self.window {
+            burst.window_start = now;
+            burst.events_in_window = 0;
+        }
+        burst.events_in_window = burst.events_in_window.saturating_add(1);
+
+        // events aren't sent before the ones already held
+        if burst.held.is_empty() && burst.events_in_window <= self.burst_threshold {
+            return Some(broadcast);
+        }
+        match burst.held.iter_mut().find(|(held_key, _)| *held_key == key) {
+            Some((_, held)) => *held = broadcast,
+            None => burst.held.push((key, broadcast)),
+        }
+        burst.send_at.get_or_insert(now + self.window);
+        None
+    }
+
+    /// Takes the held events whose window is over, to send them.
+    pub fn take_due(&self) -> Vec<EventBroadcast> {
+        let now = Instant::now();
+        let mut due = Vec::new();
+        self.channels.retain(|_, burst| {
+            if burst.send_at.map_or(false, |at| at <= now) {
+                due.extend(burst.held.drain(..).map(|(_, broadcast)| broadcast));
+                burst.send_at = None;
+            }
+            // channels that went quiet don't need to be kept
+            !burst.held.is_empty() || now.duration_since(burst.window_start) < self.window
+        });
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typing(user_id: u64) -> (CoalesceKey, EventBroadcast) {
+        let broadcast = EventBroadcast::new(
+            EventSub::Guild(1),
+            Event::Chat(stream_event::Event::Typing(stream_event::Typing {
+                user_id,
+                guild_id: 1,
+                channel_id: 2,
+            })),
+            None,
+            EventContext::empty(),
+        );
+        (CoalesceKey::Typing { user_id }, broadcast)
+    }
+
+    #[test]
+    fn bursts_are_held_and_merged() {
+        let coalescer = EventCoalescer::new(&EventCoalescingConfig {
+            window: 60_000,
+            burst_threshold: 2,
+        });
+        for user_id in [1, 2] {
+            let (key, broadcast) = typing(user_id);
+            assert!(coalescer.push(1, 2, key, broadcast).is_some());
+        }
+        for user_id in [3, 3, 4, 3] {
+            let (key, broadcast) = typing(user_id);
+            assert!(coalescer.push(1, 2, key, broadcast).is_none());
+        }
+        // other channels aren't affected
+        let (key, broadcast) = typing(5);
+        assert!(coalescer.push(1, 3, key, broadcast).is_some());
+
+        assert!(coalescer.take_due().is_empty());
+        coalescer
+            .channels
+            .get_mut(&(1, 2))
+            .unwrap()
+            .send_at
+            .replace(Instant::now());
+        assert_eq!(coalescer.take_due().len(), 2);
+    }
+
+    #[test]
+    fn zero_window_never_holds() {
+        let coalescer = EventCoalescer::new(&EventCoalescingConfig {
+            window: 0,
+            burst_threshold: 0,
+        });
+        let (key, broadcast) = typing(1);
+        assert!(coalescer.push(1, 2, key, broadcast).is_some());
+        assert!(coalescer.window().is_none());
+    }
+}
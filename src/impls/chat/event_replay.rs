@@ -0,0 +1,351 @@
+//! Replaying events to event streams that reconnect.
+//!
+//! Every broadcast gets a sequence number, counting up separately for every
+//! subscription (a guild, homeserver events or actions), and the latest ones
+//! of every subscription are kept in memory. A client can name its event
+//! stream with the [`STREAM_ID_HEADER`] header, so the server keeps track of
+//! what it sent on it. Until a while after the stream closes, the client can
+//! then open a new stream with the [`RESUME_FROM_HEADER`] header set to
+//! `<stream id>:<events received>`. The new stream is subscribed to what the
+//! old one was, and first gets the events the old one missed, including the
+//! ones that were sent but never received.
+//!
+//! A stream can't be resumed if the events it missed aren't kept anymore. The
+//! client has to sync again then, and open a stream without resuming.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use hyper::HeaderMap;
+use parking_lot::Mutex;
+
+use crate::config::EventReplayConfig;
+
+use super::*;
+
+/// Header naming an event stream, so it can be resumed later.
+pub const STREAM_ID_HEADER: &str = "x-scherzo-stream-id";
+/// Header resuming an event stream, as `<stream id>:<events received>`.
+pub const RESUME_FROM_HEADER: &str = "x-scherzo-resume-from";
+const MAX_STREAM_ID_LEN: usize = 64;
+
+/// Numbers and keeps broadcasts, and what was sent on named event streams.
+pub struct EventReplay {
+    buffer_size: usize,
+    resume_window: Duration,
+    buffers: DashMap<EventSub, EventBuffer, ahash::RandomState>,
+    streams: DashMap<(u64, SmolStr), Arc<Mutex<StreamLog>>, ahash::RandomState>,
+}
+
+#[derive(Default)]
+struct EventBuffer {
+    last_seq: u64,
+    events: VecDeque<Arc<EventBroadcast>>,
+}
+
+/// What an event stream is subscribed to, and which events it was sent.
+#[derive(Default)]
+pub struct StreamLog {
+    subs: HashSet<EventSub, ahash::RandomState>,
+    /// Sequence number of the latest event of every subscription the stream
+    /// got to, whether it was sent or not.
+    positions: HashMap<EventSub, u64, ahash::RandomState>,
+    /// How many events were sent on the stream.
+    sent: u64,
+    /// Subscription and sequence number of the latest events sent, oldest first.
+    recent: VecDeque<(EventSub, u64)>,
+    closed_at: Option<Instant>,
+    /// Set when another stream resumed this one, which should then stop.
+    superseded: bool,
+}
+
+/// An event stream that is being opened.
+pub struct OpenedStream {
+    pub log: Arc<Mutex<StreamLog>>,
+    /// Events the stream missed, to send before anything else.
+    pub replay: Vec<Arc<EventBroadcast>>,
+}
+
+impl EventReplay {
+    pub fn new(config: &EventReplayConfig) -> Self {
+        Self {
+            buffer_size: config.buffer_size,
+            resume_window: Duration::from_secs(config.resume_window),
+            buffers: DashMap::default(),
+            streams: DashMap::default(),
+        }
+    }
+
+    /// Numbers a broadcast, keeps it for replaying and sends it to event streams.
+    pub fn send(&self, sender: &EventSender, mut broadcast: EventBroadcast) {
+        // the buffer stays locked until the broadcast is sent, so streams
+        // receive the events of a subscription in order
+        let mut buffer = self.buffers.entry(broadcast.sub).or_default();
+        buffer.last_seq += 1;
+        broadcast.seq = buffer.last_seq;
+        let broadcast = Arc::new(broadcast);
+        if self.buffer_size > 0 {
+            if buffer.events.len() >= self.buffer_size {
+                buffer.events.pop_front();
+            }
+            buffer.events.push_back(broadcast.clone());
+        }
+        drop(sender.send(broadcast));
+    }
+
+    fn last_seq(&self, sub: EventSub) -> u64 {
+        self.buffers.get(&sub).map_or(0, |buffer| buffer.last_seq)
+    }
+
+    /// Opens an event stream for a user, resuming the one `headers` ask for.
+    pub fn open(&self, user_id: u64, headers: Option<&HeaderMap>) -> ServerResult<OpenedStream> {
+        let header = |name: &str| {
+            headers
+                .and_then(|headers| headers.get(name))
+                .and_then(|value| value.to_str().ok())
+        };
+
+        let stream_id = match header(STREAM_ID_HEADER) {
+            Some(id) if id.is_empty() || id.len() > MAX_STREAM_ID_LEN => bail!((
+                "h.invalid-stream-id",
+                format!(
+                    "stream IDs must be between 1 and {} bytes long",
+                    MAX_STREAM_ID_LEN
+                )
+            )),
+            Some(id) => Some(SmolStr::new(id)),
+            None => None,
+        };
+        let opened = match header(RESUME_FROM_HEADER) {
+            Some(resume_from) => {
+                let Some((id, received)) = resume_from
+                    .rsplit_once(':')
+                    .and_then(|(id, received)| Some((id, received.parse::<u64>().ok()?)))
+                else {
+                    bail!((
+                        "h.invalid-resume-from",
+                        "streams are resumed from `<stream id>:<events received>`"
+                    ));
+                };
+                self.resume(user_id, id, received)?
+            }
+            None => OpenedStream {
+                log: Arc::new(Mutex::new(StreamLog::default())),
+                replay: Vec::new(),
+            },
+        };
+
+        if let Some(stream_id) = stream_id {
+            if self.streams.len() >= 1024 {
+                let window = self.resume_window;
+                self.streams.retain(|_, log| {
+                    let log = log.lock();
+                    !log.superseded && log.closed_at.map_or(true, |at| at.elapsed() < window)
+                });
+            }
+            self.streams
+                .insert((user_id, stream_id), opened.log.clone());
+        }
+
+        Ok(opened)
+    }
+
+    fn resume(&self, user_id: u64, stream_id: &str, received: u64) -> ServerResult<OpenedStream> {
+        const RESUME_FAILED: &str = "h.stream-resume-failed";
+
+        let old_log = self
+            .streams
+            .remove(&(user_id, SmolStr::new(stream_id)))
+            .map(|(_, log)| log);
+        let Some(old_log) = old_log else {
+            bail!((RESUME_FAILED, "there is no stream with this ID to resume"));
+        };
+        let mut old_log = old_log.lock();
+        if old_log
+            .closed_at
+            .map_or(false, |at| at.elapsed() >= self.resume_window)
+        {
+            bail!((RESUME_FAILED, "the stream closed too long ago to resume it"));
+        }
+        let Some(unreceived) = old_log.sent.checked_sub(received) else {
+            bail!((RESUME_FAILED, "more events were received than were sent"));
+        };
+        if unreceived > old_log.recent.len() as u64 {
+            bail!((RESUME_FAILED, "the missed events aren't kept anymore"));
+        }
+        old_log.superseded = true;
+
+        // events that were sent but not received are sent again
+        let mut positions = old_log.positions.clone();
+        let unreceived_from = old_log.recent.len() - unreceived as usize;
+        for (sub, seq) in old_log.recent.iter().skip(unreceived_from) {
+            let position = positions.entry(*sub).or_default();
+            *position = (*position).min(seq - 1);
+        }
+
+        let mut replay = Vec::new();
+        for (sub, position) in &positions {
+            let Some(buffer) = self.buffers.get(sub) else {
+                continue;
+            };
+            if buffer.last_seq <= *position {
+                continue;
+            }
+            let oldest_kept = buffer
+                .events
+                .front()
+                .map_or(buffer.last_seq + 1, |broadcast| broadcast.seq);
+            if oldest_kept > position + 1 {
+                bail!((RESUME_FAILED, "the missed events aren't kept anymore"));
+            }
+            replay.extend(
+                buffer
+                    .events
+                    .iter()
+                    .filter(|broadcast| broadcast.seq > *position)
+                    .cloned(),
+            );
+        }
+
+        let log = StreamLog {
+            subs: old_log.subs.clone(),
+            positions,
+            ..Default::default()
+        };
+        Ok(OpenedStream {
+            log: Arc::new(Mutex::new(log)),
+            replay,
+        })
+    }
+
+    /// Subscribes a stream, starting from the events broadcast after now.
+    pub fn subscribe(&self, log: &Mutex<StreamLog>, sub: EventSub) {
+        let mut log = log.lock();
+        if log.subs.insert(sub) {
+            let last_seq = self.last_seq(sub);
+            log.positions.insert(sub, last_seq);
+        }
+    }
+
+    /// Records that a broadcast was sent on a stream.
+    pub fn record_sent(&self, log: &Mutex<StreamLog>, broadcast: &EventBroadcast) {
+        let mut log = log.lock();
+        log.sent += 1;
+        if self.buffer_size == 0 {
+            return;
+        }
+        if log.recent.len() >= self.buffer_size {
+            log.recent.pop_front();
+        }
+        log.recent.push_back((broadcast.sub, broadcast.seq));
+    }
+}
+
+impl StreamLog {
+    /// Whether a broadcast is for this stream and wasn't handled yet, moving
+    /// the stream past it if so.
+    pub fn advance(&mut self, broadcast: &EventBroadcast) -> bool {
+        if !self.subs.contains(&broadcast.sub) {
+            return false;
+        }
+        let position = self.positions.entry(broadcast.sub).or_default();
+        if broadcast.seq <= *position {
+            return false;
+        }
+        *position = broadcast.seq;
+        true
+    }
+
+    pub fn is_superseded(&self) -> bool {
+        self.superseded
+    }
+
+    pub fn close(&mut self) {
+        self.closed_at = Some(Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn broadcast(guild_id: u64) -> EventBroadcast {
+        EventBroadcast::new(
+            EventSub::Guild(guild_id),
+            Event::Chat(stream_event::Event::Typing(stream_event::Typing::default())),
+            None,
+            EventContext::empty(),
+        )
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn resumes_with_unreceived_events() {
+        let replay = EventReplay::new(&EventReplayConfig::default());
+        let sender = tokio::sync::broadcast::channel(16).0;
+        let mut rx = sender.subscribe();
+
+        let opened = replay
+            .open(1, Some(&headers(&[(STREAM_ID_HEADER, "abc")])))
+            .unwrap();
+        replay.subscribe(&opened.log, EventSub::Guild(1));
+        for guild_id in [1, 2, 1] {
+            replay.send(&sender, broadcast(guild_id));
+        }
+        while let Ok(broadcast) = rx.try_recv() {
+            if opened.log.lock().advance(&broadcast) {
+                replay.record_sent(&opened.log, &broadcast);
+            }
+        }
+        opened.log.lock().close();
+
+        // only the first event sent was received
+        let resumed = replay
+            .open(1, Some(&headers(&[(RESUME_FROM_HEADER, "abc:1")])))
+            .unwrap();
+        assert!(opened.log.lock().is_superseded());
+        let seqs = resumed
+            .replay
+            .iter()
+            .map(|broadcast| (broadcast.sub, broadcast.seq))
+            .collect::<Vec<_>>();
+        assert_eq!(seqs, [(EventSub::Guild(1), 2)]);
+
+        // a stream can only be resumed once
+        assert!(replay
+            .open(1, Some(&headers(&[(RESUME_FROM_HEADER, "abc:1")])))
+            .is_err());
+    }
+
+    #[test]
+    fn doesnt_resume_if_events_were_dropped() {
+        let replay = EventReplay::new(&EventReplayConfig {
+            buffer_size: 2,
+            ..Default::default()
+        });
+        let sender = tokio::sync::broadcast::channel(16).0;
+
+        let opened = replay
+            .open(1, Some(&headers(&[(STREAM_ID_HEADER, "abc")])))
+            .unwrap();
+        replay.subscribe(&opened.log, EventSub::Guild(1));
+        opened.log.lock().close();
+        for _ in 0..3 {
+            replay.send(&sender, broadcast(1));
+        }
+
+        assert!(replay
+            .open(1, Some(&headers(&[(RESUME_FROM_HEADER, "abc:0")])))
+            .is_err());
+    }
+}
@@ -0,0 +1,82 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+/// Most custom emoji a guild can have.
+const MAX_GUILD_EMOJI: usize = 100;
+/// Longest an emoji name can be, in characters.
+const MAX_NAME_LEN: usize = 32;
+
+#[derive(Debug, Deserialize)]
+pub struct AddGuildEmojiRequest {
+    pub guild_id: u64,
+    pub name: String,
+    /// ID of the uploaded image of the emoji.
+    pub image_id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AddGuildEmojiResponse {}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.chars().count() <= MAX_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Adds a custom emoji to a guild, or replaces the image of an emoji with the
+/// same name.
+#[require_perms(guild, "guild.manage.emoji")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: AddGuildEmojiRequest,
+) -> ServerResult<AddGuildEmojiResponse> {
+    let AddGuildEmojiRequest {
+        guild_id,
+        name,
+        image_id,
+    } = request;
+
+    if !is_valid_name(&name) {
+        bail!((
+            "h.invalid-emoji-name",
+            "emoji names must be 1 to 32 letters, digits, `-` or `_`"
+        ));
+    }
+    if image_id.is_empty() {
+        bail!(("h.invalid-emoji-image", "emoji image ID must not be empty"));
+    }
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let mut emoji = chat_tree.get_guild_emoji_logic(guild_id).await?;
+    let new_emoji = GuildEmoji {
+        name: name.clone(),
+        image_id: image_id.clone(),
+        created_by: user_id,
+        created_at: get_time_secs(),
+    };
+    match emoji.iter().position(|emoji| emoji.name == name) {
+        Some(index) => emoji[index] = new_emoji,
+        None if emoji.len() >= MAX_GUILD_EMOJI => bail!((
+            "h.too-many-guild-emoji",
+            "guilds can't have more than 100 emoji"
+        )),
+        None => emoji.push(new_emoji),
+    }
+    emoji.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+
+    let new_metadata = chat_tree.set_guild_emoji_logic(guild_id, emoji).await?;
+
+    svc.deps.event_bus.publish(DomainEvent::GuildEmojiUpdated {
+        guild_id,
+        added_emoji: vec![Emote { name, image_id }],
+        deleted_emoji: Vec::new(),
+        new_metadata,
+    });
+
+    Ok(AddGuildEmojiResponse {})
+}
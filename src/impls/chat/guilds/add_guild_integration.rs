@@ -0,0 +1,113 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+/// Most integrations that can be registered in a guild.
+const MAX_INTEGRATIONS: usize = 50;
+/// Longest an integration name can be, in characters.
+const MAX_NAME_LEN: usize = 64;
+
+#[derive(Debug, Deserialize)]
+pub struct AddGuildIntegrationRequest {
+    pub guild_id: u64,
+    pub kind: IntegrationKind,
+    pub name: String,
+    /// The bot user the integration acts as, if it has one. It must be a
+    /// member of the guild.
+    #[serde(default)]
+    pub bot_user_id: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AddGuildIntegrationResponse {
+    pub integration_id: u64,
+}
+
+/// Registers an integration installed in a guild, so it shows up in the
+/// guild's integrations.
+#[require_perms(guild, "guild.manage.integrations")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: AddGuildIntegrationRequest,
+) -> ServerResult<AddGuildIntegrationResponse> {
+    let AddGuildIntegrationRequest {
+        guild_id,
+        kind,
+        name,
+        bot_user_id,
+    } = request;
+
+    if kind == IntegrationKind::BanList {
+        bail!((
+            "h.invalid-integration-kind",
+            "ban lists are installed by subscribing to them"
+        ));
+    }
+    let name = name.trim().to_string();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+        bail!((
+            "h.invalid-integration-name",
+            "integration names must be 1 to 64 characters"
+        ));
+    }
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    if let Some(bot_user_id) = bot_user_id {
+        chat_tree.is_user_in_guild(guild_id, bot_user_id).await?;
+        let profile = svc.deps.profile_tree.get_profile_logic(bot_user_id).await?;
+        if !profile.is_bot {
+            bail!(("h.not-a-bot", "integrations can only act as bot users"));
+        }
+    }
+
+    let mut integrations = chat_tree.get_guild_integrations_logic(guild_id).await?;
+    if integrations.len() >= MAX_INTEGRATIONS {
+        bail!((
+            "h.too-many-integrations",
+            format!(
+                "guilds can't have more than {} integrations",
+                MAX_INTEGRATIONS
+            )
+        ));
+    }
+    if let Some(bot_user_id) = bot_user_id {
+        if integrations
+            .iter()
+            .any(|integration| integration.bot_user_id == Some(bot_user_id))
+        {
+            bail!((
+                "h.integration-exists",
+                "an integration with this bot is already registered"
+            ));
+        }
+    }
+
+    let integration_id = gen_rand_u64();
+    integrations.push(GuildIntegration {
+        id: integration_id,
+        kind,
+        name: name.clone(),
+        bot_user_id,
+        installed_by: user_id,
+        installed_at: get_time_secs(),
+    });
+    chat_tree
+        .set_guild_integrations_logic(guild_id, integrations)
+        .await?;
+
+    audit::record(
+        &svc.deps,
+        guild_id,
+        user_id,
+        AuditAction::IntegrationInstalled {
+            integration_id,
+            kind,
+            name,
+        },
+    )
+    .await?;
+
+    Ok(AddGuildIntegrationResponse { integration_id })
+}
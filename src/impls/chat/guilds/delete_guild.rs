@@ -1,5 +1,6 @@
 use super::*;
 
+#[require_perms(guild, "guild.manage.delete")]
 pub async fn handler(
     svc: &ChatServer,
     request: Request<DeleteGuildRequest>,
@@ -10,10 +11,14 @@ pub async fn handler(
 
     let chat_tree = &svc.deps.chat_tree;
 
-    chat_tree.check_guild_user(guild_id, user_id).await?;
-    chat_tree
-        .check_perms(guild_id, None, user_id, "guild.manage.delete", false)
-        .await?;
+    delete_guild_logic(svc, guild_id).await?;
+
+    Ok((DeleteGuildResponse {}).into_response())
+}
+
+/// Deletes a guild with everything in it, and notifies its members
+pub async fn delete_guild_logic(svc: &ChatServer, guild_id: u64) -> ServerResult<()> {
+    let chat_tree = &svc.deps.chat_tree;
 
     let guild_members = chat_tree.get_guild_members_logic(guild_id).await?.members;
 
@@ -29,18 +34,15 @@ pub async fn handler(
     for key in guild_data {
         batch.remove(key);
     }
+    batch.remove(make_guild_usage_key(guild_id));
+    batch.remove(make_public_guild_key(guild_id));
     chat_tree
         .chat_tree
         .apply_batch(batch)
         .await
         .map_err(ServerError::DbError)?;
-
-    svc.send_event_through_chan(
-        EventSub::Guild(guild_id),
-        stream_event::Event::DeletedGuild(stream_event::GuildDeleted { guild_id }),
-        None,
-        EventContext::empty(),
-    );
+    chat_tree.evict_guild_permissions(guild_id);
+    audit::delete_audit_log(&svc.deps, guild_id).await?;
 
     let mut local_ids = Vec::new();
     for member_id in guild_members {
@@ -54,21 +56,16 @@ pub async fn handler(
             ),
             None => {
                 chat_tree
-                    .remove_guild_from_guild_list(user_id, guild_id, "")
+                    .remove_guild_from_guild_list(member_id, guild_id, "")
                     .await?;
                 local_ids.push(member_id);
             }
         }
     }
-    svc.send_event_through_chan(
-        EventSub::Homeserver,
-        stream_event::Event::GuildRemovedFromList(stream_event::GuildRemovedFromList {
-            guild_id,
-            homeserver: String::new(),
-        }),
-        None,
-        EventContext::new(local_ids),
-    );
+    svc.deps.event_bus.publish(DomainEvent::GuildDeleted {
+        guild_id,
+        local_members: local_ids,
+    });
 
-    Ok((DeleteGuildResponse {}).into_response())
+    Ok(())
 }
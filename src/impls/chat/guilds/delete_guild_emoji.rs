@@ -0,0 +1,41 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteGuildEmojiRequest {
+    pub guild_id: u64,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeleteGuildEmojiResponse {}
+
+/// Removes a custom emoji from a guild.
+#[require_perms(guild, "guild.manage.emoji")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: DeleteGuildEmojiRequest,
+) -> ServerResult<DeleteGuildEmojiResponse> {
+    let DeleteGuildEmojiRequest { guild_id, name } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let mut emoji = chat_tree.get_guild_emoji_logic(guild_id).await?;
+    let Some(index) = emoji.iter().position(|emoji| emoji.name == name) else {
+        bail!(("h.no-such-guild-emoji", "emoji doesn't exist"));
+    };
+    emoji.remove(index);
+
+    let new_metadata = chat_tree.set_guild_emoji_logic(guild_id, emoji).await?;
+
+    svc.deps.event_bus.publish(DomainEvent::GuildEmojiUpdated {
+        guild_id,
+        added_emoji: Vec::new(),
+        deleted_emoji: vec![name],
+        new_metadata,
+    });
+
+    Ok(DeleteGuildEmojiResponse {})
+}
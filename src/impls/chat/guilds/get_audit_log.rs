@@ -0,0 +1,70 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::audit::AuditLogEntry,
+    utils::cursor::{Direction, Pager},
+};
+
+/// Default amount of entries returned.
+const DEFAULT_LIMIT: u64 = 50;
+/// Maximum amount of entries that can be returned at once.
+const MAX_LIMIT: u64 = 200;
+
+#[derive(Debug, Deserialize)]
+pub struct GetAuditLogRequest {
+    pub guild_id: u64,
+    /// Only return entries from before this time, in seconds since unix epoch.
+    /// Used to page through older entries.
+    #[serde(default)]
+    pub before: Option<u64>,
+    /// `next_cursor` of the previous page. The same `before` must be sent with it.
+    #[serde(default)]
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub limit: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetAuditLogResponse {
+    /// Entries of the audit log, newest first.
+    pub entries: Vec<AuditLogEntry>,
+    /// Cursor to get the next page with, not set if this is the last page.
+    pub next_cursor: Option<String>,
+}
+
+#[require_perms(guild, "guild.audit-log.view")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetAuditLogRequest,
+) -> ServerResult<GetAuditLogResponse> {
+    let GetAuditLogRequest {
+        guild_id,
+        before,
+        cursor,
+        limit,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let filter = [
+        guild_id.to_be_bytes(),
+        before.unwrap_or(u64::MAX).to_be_bytes(),
+    ]
+    .concat();
+    let pager = Pager::new(
+        Direction::Backward,
+        filter,
+        cursor.as_deref(),
+        limit as usize,
+    )?;
+    let page = audit::get_audit_log(&svc.deps, guild_id, before, &pager).await?;
+
+    Ok(GetAuditLogResponse {
+        entries: page.items,
+        next_cursor: page.next_cursor,
+    })
+}
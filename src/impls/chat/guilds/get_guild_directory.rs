@@ -0,0 +1,100 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+/// Most guilds returned at once.
+const MAX_DIRECTORY_PAGE_SIZE: usize = 50;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DirectorySort {
+    /// Guilds with the most members first.
+    Members,
+    /// Guilds sorted by name, alphabetically.
+    Name,
+}
+
+impl Default for DirectorySort {
+    fn default() -> Self {
+        DirectorySort::Members
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetGuildDirectoryRequest {
+    #[serde(default)]
+    pub sort: DirectorySort,
+    /// How many guilds to skip.
+    #[serde(default)]
+    pub offset: usize,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DirectoryGuild {
+    pub guild_id: u64,
+    pub name: String,
+    pub picture: Option<String>,
+    pub member_count: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetGuildDirectoryResponse {
+    pub guilds: Vec<DirectoryGuild>,
+    /// How many guilds are listed in the directory in total.
+    pub total: usize,
+}
+
+/// Lists the public guilds on this homeserver, so users can discover them.
+pub async fn handler(
+    svc: &ChatServer,
+    _user_id: u64,
+    request: GetGuildDirectoryRequest,
+) -> ServerResult<GetGuildDirectoryResponse> {
+    let GetGuildDirectoryRequest {
+        sort,
+        offset,
+        limit,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let mut guilds = Vec::new();
+    for guild_id in chat_tree.get_public_guilds_logic().await? {
+        let guild = chat_tree.get_guild_logic(guild_id).await?;
+        // TODO: keep a member count entry in db instead of counting members every time
+        let member_count = chat_tree
+            .scan_prefix(&make_guild_mem_prefix(guild_id))
+            .await
+            .try_fold(0, |all, res| res.map(|_| all + 1))?;
+        guilds.push(DirectoryGuild {
+            guild_id,
+            name: guild.name,
+            picture: guild.picture,
+            member_count,
+        });
+    }
+
+    match sort {
+        DirectorySort::Members => guilds.sort_unstable_by(|a, b| {
+            b.member_count
+                .cmp(&a.member_count)
+                .then(a.guild_id.cmp(&b.guild_id))
+        }),
+        DirectorySort::Name => guilds.sort_unstable_by(|a, b| {
+            a.name
+                .to_lowercase()
+                .cmp(&b.name.to_lowercase())
+                .then(a.guild_id.cmp(&b.guild_id))
+        }),
+    }
+
+    let total = guilds.len();
+    let limit = limit
+        .unwrap_or(MAX_DIRECTORY_PAGE_SIZE)
+        .clamp(1, MAX_DIRECTORY_PAGE_SIZE);
+    let guilds = guilds.into_iter().skip(offset).take(limit).collect();
+
+    Ok(GetGuildDirectoryResponse { guilds, total })
+}
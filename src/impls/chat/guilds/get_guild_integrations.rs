@@ -0,0 +1,99 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct GetGuildIntegrationsRequest {
+    pub guild_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IntegrationInfo {
+    pub kind: IntegrationKind,
+    pub name: String,
+    /// Set for registered integrations.
+    pub integration_id: Option<u64>,
+    pub bot_user_id: Option<u64>,
+    /// Set for ban list subscriptions.
+    pub ban_list: Option<BanListSource>,
+    /// Not known for bots that joined without being registered.
+    pub installed_by: Option<u64>,
+    /// In seconds since unix epoch.
+    pub installed_at: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetGuildIntegrationsResponse {
+    pub integrations: Vec<IntegrationInfo>,
+}
+
+/// Lists everything installed in a guild that acts on it from outside:
+/// registered integrations, ban list subscriptions, and bots that are members.
+#[require_perms(guild, "guild.manage.integrations")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetGuildIntegrationsRequest,
+) -> ServerResult<GetGuildIntegrationsResponse> {
+    let GetGuildIntegrationsRequest { guild_id } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let registered = chat_tree.get_guild_integrations_logic(guild_id).await?;
+    let registered_bots = registered
+        .iter()
+        .filter_map(|integration| integration.bot_user_id)
+        .collect::<Vec<_>>();
+    let mut integrations = registered
+        .into_iter()
+        .map(|integration| IntegrationInfo {
+            kind: integration.kind,
+            name: integration.name,
+            integration_id: Some(integration.id),
+            bot_user_id: integration.bot_user_id,
+            ban_list: None,
+            installed_by: Some(integration.installed_by),
+            installed_at: Some(integration.installed_at),
+        })
+        .collect::<Vec<_>>();
+
+    for subscription in chat_tree.get_ban_list_subscriptions_logic(guild_id).await? {
+        let name = match &subscription.source {
+            BanListSource::File { name } => name.clone(),
+            BanListSource::Guild { guild_id } => format!("bans of guild {}", guild_id),
+        };
+        integrations.push(IntegrationInfo {
+            kind: IntegrationKind::BanList,
+            name,
+            integration_id: None,
+            bot_user_id: None,
+            ban_list: Some(subscription.source),
+            installed_by: Some(subscription.subscribed_by),
+            installed_at: Some(subscription.subscribed_at),
+        });
+    }
+
+    let members = chat_tree.get_guild_members_logic(guild_id).await?.members;
+    for member_id in members {
+        if registered_bots.contains(&member_id) {
+            continue;
+        }
+        // users from other homeservers don't have a profile here
+        let Ok(profile) = svc.deps.profile_tree.get_profile_logic(member_id).await else {
+            continue;
+        };
+        if profile.is_bot {
+            integrations.push(IntegrationInfo {
+                kind: IntegrationKind::Bot,
+                name: profile.user_name,
+                integration_id: None,
+                bot_user_id: Some(member_id),
+                ban_list: None,
+                installed_by: None,
+                installed_at: None,
+            });
+        }
+    }
+
+    Ok(GetGuildIntegrationsResponse { integrations })
+}
@@ -0,0 +1,25 @@
+use super::*;
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct GetGuildLocaleRequest {
+    pub guild_id: u64,
+}
+
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetGuildLocaleRequest,
+) -> ServerResult<GuildLocaleInfo> {
+    let GetGuildLocaleRequest { guild_id } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree.check_guild_user(guild_id, user_id).await?;
+
+    chat_tree
+        .get_guild_locale_logic(guild_id)
+        .await
+        .map(GuildLocaleInfo::from)
+}
@@ -0,0 +1,27 @@
+use super::*;
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct GetGuildUsageRequest {
+    pub guild_id: u64,
+}
+
+#[require_perms(guild, "guild.manage.change-information")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetGuildUsageRequest,
+) -> ServerResult<GuildUsageInfo> {
+    let GetGuildUsageRequest { guild_id } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let usage = chat_tree.get_guild_usage_logic(guild_id).await?;
+
+    Ok(GuildUsageInfo::new(
+        guild_id,
+        usage,
+        &svc.deps.config.policy.guild_soft_limits,
+    ))
+}
@@ -0,0 +1,30 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct GetLinkScanningRequest {
+    pub guild_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetLinkScanningResponse {
+    /// Whether messages are checked for links to blocked domains.
+    pub enabled: bool,
+}
+
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetLinkScanningRequest,
+) -> ServerResult<GetLinkScanningResponse> {
+    let GetLinkScanningRequest { guild_id } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree.check_guild_user(guild_id, user_id).await?;
+
+    let enabled = chat_tree.is_link_scanning_enabled(guild_id).await?;
+
+    Ok(GetLinkScanningResponse { enabled })
+}
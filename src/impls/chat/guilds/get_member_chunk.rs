@@ -0,0 +1,56 @@
+use super::*;
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct GetMemberChunkRequest {
+    pub guild_id: u64,
+    pub index: usize,
+    /// Version of the member list the chunk is from, from `chat/member-list`.
+    pub version: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetMemberChunkResponse {
+    pub member_ids: Vec<u64>,
+    pub checksum: String,
+}
+
+/// Gets the members in a chunk of a guild's member list. Fails if the member
+/// list changed since the client got its version, so chunks from different
+/// versions don't get mixed.
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetMemberChunkRequest,
+) -> ServerResult<GetMemberChunkResponse> {
+    let GetMemberChunkRequest {
+        guild_id,
+        index,
+        version,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree.check_guild_user(guild_id, user_id).await?;
+
+    let MemberChunks {
+        mut chunks,
+        mut checksums,
+        version: current_version,
+    } = MemberChunks::load(chat_tree, guild_id).await?;
+    if version != current_version {
+        bail!((
+            "h.member-list-changed",
+            "member list changed since this version, get the member list again"
+        ));
+    }
+    if index >= chunks.len() {
+        bail!(("h.invalid-member-chunk", "member chunk doesn't exist"));
+    }
+
+    Ok(GetMemberChunkResponse {
+        member_ids: chunks.swap_remove(index),
+        checksum: checksums.swap_remove(index),
+    })
+}
@@ -0,0 +1,41 @@
+use super::*;
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct GetMemberListRequest {
+    pub guild_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetMemberListResponse {
+    /// Version of the member list, which has to be sent when fetching chunks.
+    pub version: String,
+    pub member_count: usize,
+    /// Checksum of every chunk, in order. Chunks with a checksum the client
+    /// already has don't need to be fetched again.
+    pub chunk_checksums: Vec<String>,
+}
+
+/// Gets the chunks a guild's member list is split into. Clients fetch the
+/// members in them with `chat/member-chunk`, and keep the list up to date
+/// with member join and leave events afterwards.
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetMemberListRequest,
+) -> ServerResult<GetMemberListResponse> {
+    let GetMemberListRequest { guild_id } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree.check_guild_user(guild_id, user_id).await?;
+
+    let chunks = MemberChunks::load(chat_tree, guild_id).await?;
+
+    Ok(GetMemberListResponse {
+        member_count: chunks.chunks.iter().map(Vec::len).sum(),
+        version: chunks.version,
+        chunk_checksums: chunks.checksums,
+    })
+}
@@ -0,0 +1,51 @@
+use super::*;
+
+use serde::Deserialize;
+
+/// How long a poll waits for the member list to change before responding
+/// without any changes.
+const MEMBER_LIST_POLL_TIMEOUT: Duration = Duration::from_secs(25);
+
+#[derive(Debug, Deserialize)]
+pub struct GetMemberListUpdatesRequest {
+    pub subscription_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetMemberListUpdatesResponse {
+    pub member_count: usize,
+    /// The subscribed ranges that changed, with all of their members. Empty
+    /// if nothing changed before the poll timed out.
+    pub ranges: Vec<MemberListRange>,
+}
+
+/// Waits for the subscribed ranges of a member list to change, and gets the
+/// ones that did. Subscriptions that aren't polled for a minute are dropped.
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetMemberListUpdatesRequest,
+) -> ServerResult<GetMemberListUpdatesResponse> {
+    let GetMemberListUpdatesRequest { subscription_id } = request;
+
+    let member_lists = &svc.deps.member_lists;
+    let deadline = tokio::time::Instant::now() + MEMBER_LIST_POLL_TIMEOUT;
+
+    loop {
+        let Some((member_count, ranges, changed)) =
+            member_lists.take_changes(user_id, subscription_id)
+        else {
+            bail!(NO_SUCH_MEMBER_LIST_SUBSCRIPTION_ERR);
+        };
+        if !ranges.is_empty()
+            || tokio::time::timeout_at(deadline, changed.notified())
+                .await
+                .is_err()
+        {
+            return Ok(GetMemberListUpdatesResponse {
+                member_count,
+                ranges,
+            });
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+use crate::utils::cursor::Direction;
+
+/// Most members returned at once.
+const MAX_MEMBERS_PER_PAGE: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct GetMembersRequest {
+    pub guild_id: u64,
+    /// `next_cursor` of the previous page.
+    #[serde(default)]
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetMembersResponse {
+    /// IDs of the members, ordered by ID.
+    pub member_ids: Vec<u64>,
+    /// Cursor to get the next page with, not set if this is the last page.
+    pub next_cursor: Option<String>,
+}
+
+/// Gets the members of a guild a page at a time. Members that join or leave
+/// while paging don't make other members get skipped or returned twice.
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetMembersRequest,
+) -> ServerResult<GetMembersResponse> {
+    let GetMembersRequest {
+        guild_id,
+        cursor,
+        limit,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree.check_guild_user(guild_id, user_id).await?;
+
+    let limit = limit
+        .unwrap_or(MAX_MEMBERS_PER_PAGE)
+        .clamp(1, MAX_MEMBERS_PER_PAGE);
+    let pager = Pager::new(
+        Direction::Forward,
+        guild_id.to_be_bytes(),
+        cursor.as_deref(),
+        limit,
+    )?;
+    let page = chat_tree
+        .get_guild_members_page_logic(guild_id, &pager)
+        .await?;
+
+    Ok(GetMembersResponse {
+        member_ids: page.items,
+        next_cursor: page.next_cursor,
+    })
+}
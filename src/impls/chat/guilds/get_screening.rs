@@ -0,0 +1,68 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct GetScreeningRequest {
+    /// The invite the user wants to join with.
+    pub invite_id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetScreeningResponse {
+    pub guild_id: u64,
+    /// `None` if the guild doesn't have membership screening.
+    pub screening: Option<ScreeningInfo>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScreeningInfo {
+    pub rules: String,
+    #[serde(default)]
+    pub questions: Vec<String>,
+    #[serde(default)]
+    pub auto_accept: bool,
+}
+
+impl From<MembershipScreening> for ScreeningInfo {
+    fn from(screening: MembershipScreening) -> Self {
+        Self {
+            rules: screening.rules,
+            questions: screening.questions,
+            auto_accept: screening.auto_accept,
+        }
+    }
+}
+
+impl From<ScreeningInfo> for MembershipScreening {
+    fn from(screening: ScreeningInfo) -> Self {
+        Self {
+            rules: screening.rules,
+            questions: screening.questions,
+            auto_accept: screening.auto_accept,
+        }
+    }
+}
+
+pub async fn handler(
+    svc: &ChatServer,
+    _user_id: u64,
+    request: GetScreeningRequest,
+) -> ServerResult<GetScreeningResponse> {
+    let chat_tree = &svc.deps.chat_tree;
+
+    let guild_id = chat_tree
+        .get(make_invite_key(&request.invite_id))
+        .await?
+        .ok_or_else(|| ServerError::NoSuchInvite(request.invite_id.into()))
+        .map(|raw| db::deser_invite_entry_guild_id(&raw))?;
+    let screening = chat_tree
+        .get_screening_logic(guild_id)
+        .await?
+        .map(ScreeningInfo::from);
+
+    Ok(GetScreeningResponse {
+        guild_id,
+        screening,
+    })
+}
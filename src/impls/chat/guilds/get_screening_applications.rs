@@ -0,0 +1,46 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct GetScreeningApplicationsRequest {
+    pub guild_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScreeningApplicationInfo {
+    pub user_id: u64,
+    pub answers: Vec<String>,
+    pub submitted_at: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetScreeningApplicationsResponse {
+    /// Applications waiting for review, oldest first.
+    pub applications: Vec<ScreeningApplicationInfo>,
+}
+
+#[require_perms(guild, "members.screening.review")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetScreeningApplicationsRequest,
+) -> ServerResult<GetScreeningApplicationsResponse> {
+    let GetScreeningApplicationsRequest { guild_id } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let mut applications = chat_tree
+        .get_screening_applications_logic(guild_id)
+        .await?
+        .into_iter()
+        .map(|(user_id, application)| ScreeningApplicationInfo {
+            user_id,
+            answers: application.answers,
+            submitted_at: application.submitted_at,
+        })
+        .collect::<Vec<_>>();
+    applications.sort_unstable_by_key(|application| application.submitted_at);
+
+    Ok(GetScreeningApplicationsResponse { applications })
+}
@@ -0,0 +1,47 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct GetTextMacrosRequest {
+    pub guild_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TextMacroInfo {
+    pub name: String,
+    pub text: String,
+    pub created_by: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetTextMacrosResponse {
+    /// Text macros of the guild, sorted by name.
+    pub macros: Vec<TextMacroInfo>,
+}
+
+/// Gets the text macros of a guild, so clients can suggest them while typing.
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetTextMacrosRequest,
+) -> ServerResult<GetTextMacrosResponse> {
+    let GetTextMacrosRequest { guild_id } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree.check_guild_user(guild_id, user_id).await?;
+
+    let macros = chat_tree
+        .get_text_macros_logic(guild_id)
+        .await?
+        .into_iter()
+        .map(|text_macro| TextMacroInfo {
+            name: text_macro.name,
+            text: text_macro.text,
+            created_by: text_macro.created_by,
+        })
+        .collect();
+
+    Ok(GetTextMacrosResponse { macros })
+}
@@ -0,0 +1,35 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct GetWidgetRequest {
+    pub guild_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetWidgetResponse {
+    /// Whether the guild has a public widget.
+    pub enabled: bool,
+    /// Invite shown on the widget.
+    pub invite_id: Option<String>,
+}
+
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetWidgetRequest,
+) -> ServerResult<GetWidgetResponse> {
+    let GetWidgetRequest { guild_id } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree.check_guild_user(guild_id, user_id).await?;
+
+    let settings = chat_tree.get_widget_settings_logic(guild_id).await?;
+
+    Ok(GetWidgetResponse {
+        enabled: settings.is_some(),
+        invite_id: settings.and_then(|settings| settings.invite_id),
+    })
+}
@@ -0,0 +1,183 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct InitialSyncRequest {
+    /// Only sync these guilds, instead of every guild in the guild list.
+    #[serde(default)]
+    pub guild_ids: Option<Vec<u64>>,
+    #[serde(default)]
+    pub skip_channels: bool,
+    #[serde(default)]
+    pub skip_roles: bool,
+    #[serde(default)]
+    pub skip_unread_hints: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SyncProfile {
+    pub user_name: String,
+    pub user_avatar: Option<String>,
+    pub user_status: i32,
+    pub is_bot: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SyncChannel {
+    pub channel_id: u64,
+    pub name: String,
+    pub kind: i32,
+    /// Position of the latest message in the channel, clients can compare it to
+    /// the last message they have seen to tell if the channel has unread messages.
+    pub latest_position: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SyncRole {
+    pub role_id: u64,
+    pub name: String,
+    pub color: i32,
+    pub hoist: bool,
+    pub pingable: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SyncGuildData {
+    pub name: String,
+    pub picture: Option<String>,
+    pub owner_ids: Vec<u64>,
+    pub own_role_ids: Vec<u64>,
+    /// Only includes channels the user can view.
+    pub channels: Vec<SyncChannel>,
+    pub roles: Vec<SyncRole>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SyncGuild {
+    pub guild_id: u64,
+    /// Empty if the guild is on this homeserver.
+    pub server_id: String,
+    /// Only available for guilds on this homeserver, other guilds must be synced
+    /// from their own homeserver.
+    pub data: Option<SyncGuildData>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InitialSyncResponse {
+    pub profile: SyncProfile,
+    pub guilds: Vec<SyncGuild>,
+}
+
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    mut request: InitialSyncRequest,
+) -> ServerResult<InitialSyncResponse> {
+    let chat_tree = &svc.deps.chat_tree;
+
+    let profile = svc.deps.profile_tree.get_profile_logic(user_id).await?;
+    let profile = SyncProfile {
+        user_name: profile.user_name,
+        user_avatar: profile.user_avatar,
+        user_status: svc.deps.presence.status(user_id, profile.user_status),
+        is_bot: profile.is_bot,
+    };
+
+    let mut guild_list = chat_tree.get_guild_list_logic(user_id).await?;
+    if let Some(guild_ids) = request.guild_ids.take() {
+        guild_list.retain(|entry| guild_ids.contains(&entry.guild_id));
+    }
+
+    let mut guilds = Vec::with_capacity(guild_list.len());
+    for GuildListEntry {
+        guild_id,
+        server_id,
+    } in guild_list
+    {
+        // Guild list entries can outlive the guild if removing them failed
+        if server_id.is_empty() && chat_tree.does_guild_exist(guild_id).await.is_err() {
+            continue;
+        }
+        let data = if server_id.is_empty() {
+            Some(sync_guild_data(svc, user_id, guild_id, &request).await?)
+        } else {
+            None
+        };
+        guilds.push(SyncGuild {
+            guild_id,
+            server_id,
+            data,
+        });
+    }
+
+    Ok(InitialSyncResponse { profile, guilds })
+}
+
+async fn sync_guild_data(
+    svc: &ChatServer,
+    user_id: u64,
+    guild_id: u64,
+    request: &InitialSyncRequest,
+) -> ServerResult<SyncGuildData> {
+    let chat_tree = &svc.deps.chat_tree;
+
+    let guild = chat_tree.get_guild_logic(guild_id).await?;
+    let own_role_ids = chat_tree.get_user_roles_logic(guild_id, user_id).await?;
+
+    let mut channels = Vec::new();
+    if !request.skip_channels {
+        let guild_channels = chat_tree
+            .get_guild_channels_logic(guild_id, user_id)
+            .await?
+            .channels;
+        channels.reserve(guild_channels.len());
+        for ChannelWithId {
+            channel_id,
+            channel,
+        } in guild_channels
+        {
+            let channel = channel.unwrap_or_default();
+            let latest_position = if request.skip_unread_hints {
+                None
+            } else {
+                Some(chat_tree.get_last_message_id(guild_id, channel_id).await? - 1)
+            };
+            channels.push(SyncChannel {
+                channel_id,
+                name: channel.channel_name,
+                kind: channel.kind,
+                latest_position,
+            });
+        }
+    }
+
+    let roles = if request.skip_roles {
+        Vec::new()
+    } else {
+        chat_tree
+            .get_guild_roles_logic(guild_id)
+            .await?
+            .into_iter()
+            .map(|RoleWithId { role_id, role }| {
+                let role = role.unwrap_or_default();
+                SyncRole {
+                    role_id,
+                    name: role.name,
+                    color: role.color,
+                    hoist: role.hoist,
+                    pingable: role.pingable,
+                }
+            })
+            .collect()
+    };
+
+    Ok(SyncGuildData {
+        name: guild.name,
+        picture: guild.picture,
+        owner_ids: guild.owner_ids,
+        own_role_ids,
+        channels,
+        roles,
+    })
+}
@@ -7,11 +7,30 @@ pub async fn handler(
     let user_id = svc.deps.valid_sessions.auth(&request)?;
 
     let JoinGuildRequest { invite_id } = request.into_message().await?;
-    let key = make_invite_key(invite_id.as_str());
 
     let chat_tree = &svc.deps.chat_tree;
 
-    let (guild_id, mut invite) = if let Some(raw) = chat_tree.get(&key).await? {
+    let (guild_id, _) = get_joinable_invite(chat_tree, user_id, &invite_id).await?;
+
+    if chat_tree.get_screening_logic(guild_id).await?.is_some() {
+        bail!(ServerError::ScreeningRequired);
+    }
+
+    let grant_role_id = get_invite_grant(chat_tree, &invite_id, guild_id).await?;
+    join_with_invite(svc, &invite_id, guild_id, user_id, grant_role_id).await?;
+
+    Ok((JoinGuildResponse { guild_id }).into_response())
+}
+
+/// Gets the invite with the given ID, making sure the user can join with it
+pub async fn get_joinable_invite(
+    chat_tree: &ChatTree,
+    user_id: u64,
+    invite_id: &str,
+) -> ServerResult<(u64, Invite)> {
+    let key = make_invite_key(invite_id);
+
+    let (guild_id, invite) = if let Some(raw) = chat_tree.get(&key).await? {
         db::deser_invite_entry(raw)
     } else {
         return Err(ServerError::NoSuchInvite(invite_id.into()).into());
@@ -32,32 +51,267 @@ pub async fn handler(
     if is_infinite.not() && invite.use_count >= invite.possible_uses {
         return Err(ServerError::InviteExpired.into());
     }
+    chat_tree.check_invite_not_expired(invite_id).await?;
+
+    Ok((guild_id, invite))
+}
+
+/// Uses up one use of an invite. This should happen before the user is
+/// added to the guild, so that a join that loses a race for the last use of
+/// an invite fails without adding the user.
+pub async fn use_invite(chat_tree: &ChatTree, invite_id: &str, guild_id: u64) -> ServerResult<()> {
+    // the invite could have been deleted and recreated for another guild
+    let invite_guild_id = chat_tree.use_invite_logic(invite_id).await?;
+    if invite_guild_id != guild_id {
+        chat_tree
+            .release_invite_use_logic(invite_id, invite_guild_id)
+            .await?;
+        return Err(ServerError::NoSuchInvite(invite_id.into()).into());
+    }
+    Ok(())
+}
+
+/// Uses up one use of an invite and adds the user to the guild with it. The
+/// use is given back if the user couldn't be added.
+pub async fn join_with_invite(
+    svc: &ChatServer,
+    invite_id: &str,
+    guild_id: u64,
+    user_id: u64,
+    grant_role_id: Option<u64>,
+) -> ServerResult<()> {
+    let chat_tree = &svc.deps.chat_tree;
+
+    use_invite(chat_tree, invite_id, guild_id).await?;
+    if let Err(err) = add_guild_member(svc, guild_id, user_id, grant_role_id).await {
+        chat_tree
+            .release_invite_use_logic(invite_id, guild_id)
+            .await?;
+        return Err(err);
+    }
+    chat_tree.record_invite_use_logic(invite_id, user_id).await
+}
+
+/// Gets the role an invite gives on join, making sure the role has room for another member
+pub async fn get_invite_grant(
+    chat_tree: &ChatTree,
+    invite_id: &str,
+    guild_id: u64,
+) -> ServerResult<Option<u64>> {
+    let role_id = chat_tree
+        .get_invite_role_grant_logic(invite_id)
+        .await?
+        .map(|grant| grant.role_id);
+    check_role_grant(chat_tree, guild_id, role_id).await
+}
+
+/// Makes sure a role given on join has room for another member, dropping it
+/// if it doesn't exist anymore
+pub async fn check_role_grant(
+    chat_tree: &ChatTree,
+    guild_id: u64,
+    role_id: Option<u64>,
+) -> ServerResult<Option<u64>> {
+    let Some(role_id) = role_id else {
+        return Ok(None);
+    };
+    // the role might have been deleted after it was attached to the invite
+    if chat_tree.does_role_exist(guild_id, role_id).await.is_err() {
+        return Ok(None);
+    }
+    chat_tree.check_role_member_cap(guild_id, role_id).await?;
+
+    Ok(Some(role_id))
+}
+
+/// Adds a user to a guild and notifies everyone about it, giving them
+/// `grant_role_id` on top of the default role if there is one
+pub async fn add_guild_member(
+    svc: &ChatServer,
+    guild_id: u64,
+    user_id: u64,
+    grant_role_id: Option<u64>,
+) -> ServerResult<()> {
+    let chat_tree = &svc.deps.chat_tree;
 
+    // roles are given first, so that a user who can't be given a role (like
+    // when it's at its member cap) isn't left in the guild
+    let give_role_ids = std::iter::once(DEFAULT_ROLE_ID)
+        .chain(grant_role_id)
+        .collect();
+    let new_role_ids = chat_tree
+        .manage_user_roles_logic(guild_id, user_id, give_role_ids, Vec::new())
+        .await?;
     chat_tree
         .insert(make_member_key(guild_id, user_id), [])
         .await?;
-    chat_tree.add_default_role_to(guild_id, user_id).await?;
-    invite.use_count += 1;
 
-    svc.send_event_through_chan(
-        EventSub::Guild(guild_id),
-        stream_event::Event::JoinedMember(stream_event::MemberJoined {
+    svc.deps
+        .event_bus
+        .publish(DomainEvent::MemberJoined { guild_id, user_id });
+
+    if grant_role_id.is_some() {
+        svc.deps.event_bus.publish(DomainEvent::UserRolesUpdated {
             guild_id,
-            member_id: user_id,
-        }),
-        None,
-        EventContext::empty(),
-    );
+            user_id,
+            new_role_ids,
+        });
+    }
 
     svc.dispatch_guild_join(guild_id, user_id).await?;
 
-    let buf = rkyv_ser(&invite);
-    chat_tree
-        .insert(
-            key,
-            [guild_id.to_be_bytes().as_ref(), buf.as_ref()].concat(),
-        )
-        .await?;
+    Ok(())
+}
 
-    Ok((JoinGuildResponse { guild_id }).into_response())
+#[cfg(all(test, feature = "sled"))]
+mod test {
+    use super::*;
+
+    use crate::impls::auth::ValidSession;
+
+    async fn test_server() -> (ChatServer, u64) {
+        let mut config = crate::config::Config::default();
+        config.policy.ratelimit.disable = true;
+        let db = crate::db::open_temp();
+        let (deps, _) = Dependencies::new(&db, config).await.unwrap();
+        let guild_id = deps
+            .chat_tree
+            .create_guild_logic(
+                1,
+                "test".to_string(),
+                None,
+                None,
+                guild_kind::Kind::new_normal(guild_kind::Normal::new()),
+            )
+            .await
+            .unwrap();
+        (ChatServer::new(deps), guild_id)
+    }
+
+    fn join_request(svc: &ChatServer, user_id: u64, invite_id: &str) -> Request<JoinGuildRequest> {
+        let token = format!("token-{}", user_id);
+        svc.deps.valid_sessions.insert(
+            token.as_str().into(),
+            ValidSession::new(user_id, get_time_secs()),
+        );
+        let mut request = Request::new(&JoinGuildRequest {
+            invite_id: invite_id.to_string(),
+        });
+        request
+            .get_or_insert_header_map()
+            .insert(http::header::AUTHORIZATION, token.parse().unwrap());
+        request
+    }
+
+    async fn use_count(svc: &ChatServer, invite_id: &str) -> u32 {
+        let raw = svc
+            .deps
+            .chat_tree
+            .get(make_invite_key(invite_id))
+            .await
+            .unwrap()
+            .unwrap();
+        db::deser_invite_entry(raw).1.use_count
+    }
+
+    async fn race_joins(svc: &ChatServer, invite_id: &str, users: u64) -> Vec<u64> {
+        let joins = (10..10 + users)
+            .map(|user_id| {
+                let svc = svc.clone();
+                let request = join_request(&svc, user_id, invite_id);
+                tokio::spawn(async move { (user_id, handler(&svc, request).await) })
+            })
+            .collect::<Vec<_>>();
+        let mut joined = Vec::new();
+        for join in joins {
+            let (user_id, res) = join.await.unwrap();
+            if res.is_ok() {
+                joined.push(user_id);
+            }
+        }
+        joined
+    }
+
+    #[tokio::test]
+    async fn racing_joins_dont_overuse_invite() {
+        let (svc, guild_id) = test_server().await;
+        let chat_tree = &svc.deps.chat_tree;
+        chat_tree
+            .create_invite_logic(guild_id, "race", 2)
+            .await
+            .unwrap();
+
+        let joined = race_joins(&svc, "race", 16).await;
+        assert_eq!(joined.len(), 2);
+        assert_eq!(use_count(&svc, "race").await, 2);
+        for user_id in 10..26 {
+            let is_member = chat_tree.is_user_in_guild(guild_id, user_id).await.is_ok();
+            assert_eq!(is_member, joined.contains(&user_id));
+        }
+    }
+
+    #[tokio::test]
+    async fn infinite_invite_can_be_used_concurrently() {
+        let (svc, guild_id) = test_server().await;
+        svc.deps
+            .chat_tree
+            .create_invite_logic(guild_id, "open", 0)
+            .await
+            .unwrap();
+
+        let joined = race_joins(&svc, "open", 8).await;
+        assert_eq!(joined.len(), 8);
+        assert_eq!(use_count(&svc, "open").await, 8);
+    }
+
+    #[tokio::test]
+    async fn failed_join_gives_invite_use_back() {
+        let (svc, guild_id) = test_server().await;
+        let chat_tree = &svc.deps.chat_tree;
+        chat_tree
+            .create_invite_logic(guild_id, "capped", 1)
+            .await
+            .unwrap();
+        let role_id = chat_tree
+            .add_guild_role_logic(guild_id, None, Role::default())
+            .await
+            .unwrap();
+        chat_tree
+            .set_role_member_cap_logic(guild_id, role_id, Some(0))
+            .await
+            .unwrap();
+
+        // the role is full, so the user can't be added with it
+        let res = join_with_invite(&svc, "capped", guild_id, 10, Some(role_id)).await;
+        assert!(res.is_err());
+        assert!(chat_tree.is_user_in_guild(guild_id, 10).await.is_err());
+        assert_eq!(use_count(&svc, "capped").await, 0);
+
+        let joined = race_joins(&svc, "capped", 1).await;
+        assert_eq!(joined, [10]);
+    }
+
+    #[tokio::test]
+    async fn expired_invite_cant_be_used() {
+        let (svc, guild_id) = test_server().await;
+        let chat_tree = &svc.deps.chat_tree;
+        chat_tree
+            .create_invite_logic(guild_id, "old", 0)
+            .await
+            .unwrap();
+        chat_tree
+            .set_invite_info_logic(
+                "old",
+                Some(InviteInfo {
+                    created_by: 1,
+                    created_at: 0,
+                    expires_at: Some(get_time_secs() - 1),
+                }),
+            )
+            .await
+            .unwrap();
+
+        assert!(race_joins(&svc, "old", 1).await.is_empty());
+        assert_eq!(use_count(&svc, "old").await, 0);
+    }
 }
@@ -17,17 +17,16 @@ pub async fn handler(
         .remove(&make_member_key(guild_id, user_id))
         .await
         .map_err(ServerError::DbError)?;
-
-    svc.send_event_through_chan(
-        EventSub::Guild(guild_id),
-        stream_event::Event::LeftMember(stream_event::MemberLeft {
-            guild_id,
-            member_id: user_id,
-            leave_reason: LeaveReason::WillinglyUnspecified.into(),
-        }),
-        None,
-        EventContext::empty(),
-    );
+    chat_tree
+        .remove(make_member_gated_channels_key(guild_id, user_id))
+        .await?;
+
+    svc.deps.event_bus.publish(DomainEvent::MemberLeft {
+        guild_id,
+        user_id,
+        reason: LeaveReason::WillinglyUnspecified,
+        ban_reason: None,
+    });
 
     svc.dispatch_guild_leave(guild_id, user_id).await?;
 
@@ -0,0 +1,71 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GuildUsageSort {
+    MessageCount,
+    MediaBytes,
+    EmoteCount,
+}
+
+impl Default for GuildUsageSort {
+    fn default() -> Self {
+        GuildUsageSort::MessageCount
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListGuildUsageRequest {
+    #[serde(default)]
+    pub sort_by: GuildUsageSort,
+    /// Maximum number of guilds to return.
+    #[serde(default)]
+    pub limit: Option<usize>,
+    /// Only return guilds that went over a soft limit.
+    #[serde(default)]
+    pub over_limits_only: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListGuildUsageResponse {
+    /// Guilds, heaviest first.
+    pub guilds: Vec<GuildUsageInfo>,
+}
+
+/// Lists the resource usage of all guilds. Only homeserver admins can use this.
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: ListGuildUsageRequest,
+) -> ServerResult<ListGuildUsageResponse> {
+    let ListGuildUsageRequest {
+        sort_by,
+        limit,
+        over_limits_only,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree.check_homeserver_admin(user_id).await?;
+
+    let mut usages = chat_tree.get_all_guild_usage_logic().await?;
+    usages.sort_unstable_by_key(|(_, usage)| {
+        std::cmp::Reverse(match sort_by {
+            GuildUsageSort::MessageCount => usage.message_count,
+            GuildUsageSort::MediaBytes => usage.media_bytes,
+            GuildUsageSort::EmoteCount => usage.emote_count,
+        })
+    });
+
+    let limits = &svc.deps.config.policy.guild_soft_limits;
+    let guilds = usages
+        .into_iter()
+        .map(|(guild_id, usage)| GuildUsageInfo::new(guild_id, usage, limits))
+        .filter(|info| !over_limits_only || !info.over_limits.is_empty())
+        .take(limit.unwrap_or(usize::MAX))
+        .collect();
+
+    Ok(ListGuildUsageResponse { guilds })
+}
@@ -1,14 +1,538 @@
 use super::*;
 
+pub mod add_guild_emoji;
+pub mod add_guild_integration;
 pub mod create_direct_message;
 pub mod create_guild;
 pub mod create_room;
 pub mod delete_guild;
+pub mod delete_guild_emoji;
+pub mod get_audit_log;
 pub mod get_guild;
+pub mod get_guild_directory;
+pub mod get_guild_integrations;
 pub mod get_guild_list;
+pub mod get_guild_locale;
 pub mod get_guild_members;
+pub mod get_guild_theme;
+pub mod get_guild_usage;
+pub mod get_link_scanning;
+pub mod get_member_chunk;
+pub mod get_member_list;
+pub mod get_member_list_updates;
+pub mod get_members;
+pub mod get_screening;
+pub mod get_screening_applications;
+pub mod get_text_macros;
+pub mod get_widget;
+pub mod initial_sync;
 pub mod join_guild;
 pub mod leave_guild;
+pub mod list_guild_usage;
 pub mod preview_guild;
+pub mod preview_guild_theme;
+pub mod remove_guild_integration;
+pub mod review_screening_application;
+pub mod search_members;
+pub mod set_guild_public;
+pub mod set_link_scanning;
+pub mod set_screening;
+pub mod set_text_macro;
+pub mod set_widget;
+pub mod submit_screening;
+pub mod subscribe_to_member_list;
 pub mod update_guild_information;
+pub mod update_guild_locale;
+pub mod update_guild_theme;
 pub mod upgrade_room_to_guild;
+
+use std::time::Instant;
+
+use chrono::TimeZone;
+use chrono_tz::Tz;
+use dashmap::DashMap;
+use serde::Serialize;
+use sha3::Digest;
+use tokio::sync::Notify;
+
+use crate::config::GuildSoftLimitsConfig;
+
+/// Number of members a chunk of a member list is aimed to have.
+pub const MEMBER_CHUNK_SIZE: usize = 1000;
+
+/// A guild's member list split into chunks, so big guilds can be synced in
+/// bounded pieces.
+///
+/// Members are put into chunks by their ID, so a member joining or leaving
+/// only changes the checksum of their own chunk, until the number of chunks
+/// changes. Clients keep the checksums and only fetch the chunks that changed.
+pub struct MemberChunks {
+    chunks: Vec<Vec<u64>>,
+    checksums: Vec<String>,
+    /// Changes whenever any chunk changes.
+    version: String,
+}
+
+impl MemberChunks {
+    pub async fn load(chat_tree: &ChatTree, guild_id: u64) -> ServerResult<Self> {
+        let members = chat_tree.get_guild_members_logic(guild_id).await?.members;
+        Ok(Self::new(&members))
+    }
+
+    fn new(members: &[u64]) -> Self {
+        // a power of two, so a chunk is picked with the low bits of member IDs
+        let chunk_count = (members.len() / MEMBER_CHUNK_SIZE + 1).next_power_of_two();
+        let mut chunks = vec![Vec::new(); chunk_count];
+        for member_id in members {
+            chunks[(*member_id as usize) & (chunk_count - 1)].push(*member_id);
+        }
+
+        let checksums = chunks
+            .iter()
+            .map(|chunk| {
+                let bytes = chunk
+                    .iter()
+                    .flat_map(|id| id.to_be_bytes())
+                    .collect::<Vec<_>>();
+                checksum(&bytes)
+            })
+            .collect::<Vec<_>>();
+        let version = checksum(checksums.concat().as_bytes());
+
+        Self {
+            chunks,
+            checksums,
+            version,
+        }
+    }
+}
+
+/// Most ranges of a member list a subscription can have.
+pub const MAX_MEMBER_LIST_RANGES: usize = 4;
+/// Most members a range of a member list can have.
+pub const MAX_MEMBER_LIST_RANGE_LEN: usize = 200;
+const NO_SUCH_MEMBER_LIST_SUBSCRIPTION_ERR: (&str, &str) = (
+    "h.no-such-member-list-subscription",
+    "member list subscription doesn't exist, subscribe again",
+);
+/// Subscriptions that aren't polled for this long are dropped.
+const MEMBER_LIST_SUBSCRIPTION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Members in a range of a guild's member list, which is ordered by user ID.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MemberListRange {
+    pub start: usize,
+    pub member_ids: Vec<u64>,
+}
+
+/// Ranges of guild member lists clients are subscribed to, so they only get
+/// the members they show instead of the whole list. The member lists of
+/// guilds with subscriptions are kept in memory, and updated as members join
+/// and leave.
+#[derive(Default)]
+pub struct MemberListSubscriptions {
+    subs: DashMap<u64, MemberListSubscription, ahash::RandomState>,
+    /// Sorted member IDs of the guilds that have subscriptions.
+    members: DashMap<u64, Vec<u64>, ahash::RandomState>,
+}
+
+struct MemberListSubscription {
+    user_id: u64,
+    guild_id: u64,
+    ranges: Vec<(usize, usize)>,
+    member_count: usize,
+    /// Ranges as of the latest change of the member list.
+    current: Vec<MemberListRange>,
+    /// Ranges as the client was last sent them.
+    sent: Vec<MemberListRange>,
+    changed: Arc<Notify>,
+    last_polled: Instant,
+}
+
+fn slice_ranges(members: &[u64], ranges: &[(usize, usize)]) -> Vec<MemberListRange> {
+    ranges
+        .iter()
+        .map(|(start, end)| {
+            let start = (*start).min(members.len());
+            let end = (*end).min(members.len());
+            MemberListRange {
+                start,
+                member_ids: members[start..end].to_vec(),
+            }
+        })
+        .collect()
+}
+
+impl MemberListSubscriptions {
+    pub fn has_guild(&self, guild_id: u64) -> bool {
+        self.members.contains_key(&guild_id)
+    }
+
+    /// Subscribes a user to ranges of a guild's member list, or changes the
+    /// ranges of one of their subscriptions. `members` is the member list,
+    /// which must be given for new subscriptions.
+    ///
+    /// Returns the ID of the subscription, the member count and the members
+    /// in the ranges, or `None` if the subscription doesn't exist.
+    pub fn subscribe(
+        &self,
+        user_id: u64,
+        guild_id: u64,
+        subscription_id: Option<u64>,
+        ranges: Vec<(usize, usize)>,
+        members: Option<Vec<u64>>,
+    ) -> Option<(u64, usize, Vec<MemberListRange>)> {
+        self.drop_expired();
+
+        if let Some(members) = members {
+            self.members.insert(guild_id, members);
+        }
+        let members = self.members.get(&guild_id)?;
+        let slices = slice_ranges(&members, &ranges);
+        let member_count = members.len();
+        drop(members);
+
+        let subscription_id = match subscription_id {
+            Some(id) => {
+                let mut sub = self.subs.get_mut(&id)?;
+                if sub.user_id != user_id || sub.guild_id != guild_id {
+                    return None;
+                }
+                sub.ranges = ranges;
+                sub.member_count = member_count;
+                sub.current = slices.clone();
+                sub.sent = slices.clone();
+                sub.last_polled = Instant::now();
+                id
+            }
+            None => {
+                let id = gen_rand_u64();
+                self.subs.insert(
+                    id,
+                    MemberListSubscription {
+                        user_id,
+                        guild_id,
+                        ranges,
+                        member_count,
+                        current: slices.clone(),
+                        sent: slices.clone(),
+                        changed: Default::default(),
+                        last_polled: Instant::now(),
+                    },
+                );
+                id
+            }
+        };
+
+        Some((subscription_id, member_count, slices))
+    }
+
+    /// Takes the ranges of a subscription that changed since they were last
+    /// sent. Returns `None` if the subscription doesn't exist.
+    #[allow(clippy::type_complexity)]
+    pub fn take_changes(
+        &self,
+        user_id: u64,
+        subscription_id: u64,
+    ) -> Option<(usize, Vec<MemberListRange>, Arc<Notify>)> {
+        let mut sub = self
+            .subs
+            .get_mut(&subscription_id)
+            .filter(|sub| sub.user_id == user_id)?;
+        sub.last_polled = Instant::now();
+        let changed = sub
+            .current
+            .iter()
+            .zip(&sub.sent)
+            .filter(|(current, sent)| current != sent)
+            .map(|(current, _)| current.clone())
+            .collect();
+        sub.sent = sub.current.clone();
+        Some((sub.member_count, changed, sub.changed.clone()))
+    }
+
+    /// Updates the member list of a guild after a member joined or left.
+    pub fn member_changed(&self, guild_id: u64, user_id: u64, joined: bool) {
+        let Some(mut members) = self.members.get_mut(&guild_id) else {
+            return;
+        };
+        match (members.binary_search(&user_id), joined) {
+            (Err(index), true) => members.insert(index, user_id),
+            (Ok(index), false) => {
+                members.remove(index);
+            }
+            _ => return,
+        }
+        if !joined {
+            self.subs
+                .retain(|_, sub| sub.guild_id != guild_id || sub.user_id != user_id);
+        }
+
+        for mut sub in self.subs.iter_mut() {
+            if sub.guild_id != guild_id {
+                continue;
+            }
+            sub.member_count = members.len();
+            sub.current = slice_ranges(&members, &sub.ranges);
+            if sub.current != sub.sent {
+                sub.changed.notify_one();
+            }
+        }
+    }
+
+    /// Drops the subscriptions to a guild, for example because it was deleted.
+    pub fn remove_guild(&self, guild_id: u64) {
+        self.subs.retain(|_, sub| sub.guild_id != guild_id);
+        self.members.remove(&guild_id);
+    }
+
+    fn drop_expired(&self) {
+        self.subs
+            .retain(|_, sub| sub.last_polled.elapsed() < MEMBER_LIST_SUBSCRIPTION_TIMEOUT);
+        let guild_ids = self
+            .subs
+            .iter()
+            .map(|sub| sub.guild_id)
+            .collect::<HashSet<_>>();
+        self.members
+            .retain(|guild_id, _| guild_ids.contains(guild_id));
+    }
+}
+
+fn checksum(data: &[u8]) -> String {
+    sha3::Sha3_256::digest(data)[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Theming information of a guild, in a form that can be sent over the JSON API.
+#[derive(Debug, Serialize)]
+pub struct GuildThemeInfo {
+    pub banner: Option<String>,
+    pub splash: Option<String>,
+    pub theme_color: Option<i32>,
+}
+
+impl From<GuildTheme> for GuildThemeInfo {
+    fn from(theme: GuildTheme) -> Self {
+        Self {
+            banner: theme.banner,
+            splash: theme.splash,
+            theme_color: theme.theme_color,
+        }
+    }
+}
+
+/// Locale and timezone of a guild, in a form that can be sent over the JSON API.
+#[derive(Debug, Serialize)]
+pub struct GuildLocaleInfo {
+    pub locale: Option<String>,
+    pub timezone: Option<String>,
+}
+
+impl From<GuildLocale> for GuildLocaleInfo {
+    fn from(locale: GuildLocale) -> Self {
+        Self {
+            locale: locale.locale,
+            timezone: locale.timezone,
+        }
+    }
+}
+
+/// Gets the locale times can be formatted in for a BCP 47 language tag.
+fn chrono_locale(tag: &str) -> Option<chrono::Locale> {
+    chrono::Locale::try_from(tag.replace('-', "_").as_str()).ok()
+}
+
+/// Checks that a locale is a BCP 47 language tag times can be formatted in.
+pub fn validate_locale(locale: &str) -> ServerResult<()> {
+    if chrono_locale(locale).is_none() {
+        bail!((
+            "h.invalid-locale",
+            format!("{} isn't a supported locale, like en-US", locale)
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that a timezone is an IANA timezone name.
+pub fn validate_timezone(timezone: &str) -> ServerResult<()> {
+    if timezone.parse::<Tz>().is_err() {
+        bail!((
+            "h.invalid-timezone",
+            format!(
+                "{} isn't an IANA timezone name, like Europe/Istanbul",
+                timezone
+            )
+        ));
+    }
+    Ok(())
+}
+
+/// Formats a time, in seconds since unix epoch, in a guild's locale and
+/// timezone. Times are in UTC for guilds without a timezone.
+pub fn format_time(locale: &GuildLocale, secs: u64) -> String {
+    let timezone = locale
+        .timezone
+        .as_deref()
+        .and_then(|timezone| timezone.parse::<Tz>().ok())
+        .unwrap_or(Tz::UTC);
+    let time = timezone.timestamp(secs as i64, 0);
+    match locale.locale.as_deref().and_then(chrono_locale) {
+        Some(locale) => time.format_localized("%c %Z", locale).to_string(),
+        None => time.format("%Y-%m-%d %H:%M %Z").to_string(),
+    }
+}
+
+/// A custom emoji of a guild, in a form that can be sent over the JSON API.
+#[derive(Debug, Serialize)]
+pub struct GuildEmojiInfo {
+    pub name: String,
+    pub image_id: String,
+}
+
+impl From<GuildEmoji> for GuildEmojiInfo {
+    fn from(emoji: GuildEmoji) -> Self {
+        Self {
+            name: emoji.name,
+            image_id: emoji.image_id,
+        }
+    }
+}
+
+/// Resource usage of a guild, in a form that can be sent over the JSON API.
+#[derive(Debug, Serialize)]
+pub struct GuildUsageInfo {
+    pub guild_id: u64,
+    pub message_count: u64,
+    pub media_bytes: u64,
+    pub emote_count: u64,
+    /// Names of the soft limits this guild went over.
+    pub over_limits: Vec<&'static str>,
+}
+
+impl GuildUsageInfo {
+    fn new(guild_id: u64, usage: GuildUsage, limits: &GuildSoftLimitsConfig) -> Self {
+        let over = |limit: Option<u64>, used: u64| limit.map_or(false, |limit| used > limit);
+        let over_limits = [
+            (
+                "max_messages",
+                over(limits.max_messages, usage.message_count),
+            ),
+            (
+                "max_media_size",
+                over(
+                    limits.max_media_size.map(|mib| mib * 1024 * 1024),
+                    usage.media_bytes,
+                ),
+            ),
+            ("max_emotes", over(limits.max_emotes, usage.emote_count)),
+        ]
+        .into_iter()
+        .filter_map(|(name, over)| over.then(|| name))
+        .collect();
+
+        Self {
+            guild_id,
+            message_count: usage.message_count,
+            media_bytes: usage.media_bytes,
+            emote_count: usage.emote_count,
+            over_limits,
+        }
+    }
+}
+
+/// Makes sure that the given file ID points to an image uploaded to this server.
+async fn validate_guild_image(media_store: &dyn MediaStore, file_id: &str) -> ServerResult<()> {
+    let file_id = FileId::from_str(file_id).map_err(|_| ServerError::InvalidFileId)?;
+    let id = match &file_id {
+        FileId::External(_) => bail!((
+            "h.guild-image-cant-have-external-url",
+            "guild banner and splash images cant use external URL"
+        )),
+        FileId::Hmc(hmc) => hmc.id(),
+        FileId::Id(id) => id.as_str(),
+    };
+
+    let StoredMedia { reader, .. } = media_store.get(id).await?;
+    let (_, mimetype, _) = read_bufs(reader, is_id_jpeg(id)).await?;
+    if !mimetype.starts_with(b"image/") {
+        bail!(ServerError::NotAnImage);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn member_joining_only_changes_one_chunk() {
+        let members = (0..3000).map(|id| id * 7919).collect::<Vec<u64>>();
+        let before = MemberChunks::new(&members);
+        let mut joined = members.clone();
+        joined.push(12345);
+        let after = MemberChunks::new(&joined);
+
+        assert_eq!(before.checksums.len(), 4);
+        assert_ne!(before.version, after.version);
+        let changed = before
+            .checksums
+            .iter()
+            .zip(&after.checksums)
+            .filter(|(a, b)| a != b)
+            .count();
+        assert_eq!(changed, 1);
+    }
+
+    #[test]
+    fn member_list_subscription_gets_changed_ranges() {
+        let lists = MemberListSubscriptions::default();
+        let (id, count, ranges) = lists
+            .subscribe(1, 7, None, vec![(0, 2), (10, 12)], Some(vec![1, 5, 9]))
+            .unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(ranges[0].member_ids, [1, 5]);
+        assert!(ranges[1].member_ids.is_empty());
+
+        // a member joining past the first range changes nothing
+        lists.member_changed(7, 20, true);
+        let (count, changed, _) = lists.take_changes(1, id).unwrap();
+        assert_eq!(count, 4);
+        assert!(changed.is_empty());
+
+        lists.member_changed(7, 3, true);
+        let (_, changed, _) = lists.take_changes(1, id).unwrap();
+        assert_eq!(
+            changed,
+            [MemberListRange {
+                start: 0,
+                member_ids: vec![1, 3],
+            }]
+        );
+
+        // the subscriber leaving drops their subscription
+        lists.member_changed(7, 1, false);
+        assert!(lists.take_changes(1, id).is_none());
+    }
+
+    #[test]
+    fn formats_times_in_guild_timezone() {
+        assert!(validate_timezone("Europe/Istanbul").is_ok());
+        assert!(validate_timezone("Mars/Olympus_Mons").is_err());
+        assert!(validate_locale("en-US").is_ok());
+        assert!(validate_locale("not a locale").is_err());
+
+        let locale = GuildLocale {
+            locale: None,
+            timezone: Some("Europe/Istanbul".to_string()),
+        };
+        assert_eq!(format_time(&locale, 0), "1970-01-01 02:00 EET");
+        assert_eq!(
+            format_time(&GuildLocale::default(), 0),
+            "1970-01-01 00:00 UTC"
+        );
+    }
+}
@@ -6,8 +6,16 @@ pub async fn handler(
 ) -> ServerResult<Response<PreviewGuildResponse>> {
     let PreviewGuildRequest { invite_id } = request.into_message().await?;
 
-    let chat_tree = &svc.deps.chat_tree;
+    let (_, preview) = preview_guild_logic(&svc.deps.chat_tree, invite_id).await?;
 
+    Ok(preview.into_response())
+}
+
+/// Returns the ID of the guild the invite points to, along with a preview of it
+pub async fn preview_guild_logic(
+    chat_tree: &ChatTree,
+    invite_id: String,
+) -> ServerResult<(u64, PreviewGuildResponse)> {
     let key = make_invite_key(&invite_id);
     let guild_id = chat_tree
         .get(&key)
@@ -22,10 +30,12 @@ pub async fn handler(
         .await
         .try_fold(0, |all, res| res.map(|_| all + 1))?;
 
-    Ok((PreviewGuildResponse {
-        name: guild.name,
-        picture: guild.picture,
-        member_count,
-    })
-    .into_response())
+    Ok((
+        guild_id,
+        PreviewGuildResponse {
+            name: guild.name,
+            picture: guild.picture,
+            member_count,
+        },
+    ))
 }
@@ -0,0 +1,47 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct PreviewGuildThemeRequest {
+    pub invite_id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PreviewGuildThemeResponse {
+    pub name: String,
+    pub picture: Option<String>,
+    pub member_count: u64,
+    #[serde(flatten)]
+    pub theme: GuildThemeInfo,
+    /// Custom emoji of the guild, sorted by name.
+    pub emoji: Vec<GuildEmojiInfo>,
+}
+
+/// Same as `PreviewGuild`, but also includes the theming information and the
+/// custom emoji of the guild.
+/// This doesn't require authentication.
+pub async fn handler(
+    svc: &ChatServer,
+    request: PreviewGuildThemeRequest,
+) -> ServerResult<PreviewGuildThemeResponse> {
+    let chat_tree = &svc.deps.chat_tree;
+
+    let (guild_id, preview) =
+        preview_guild::preview_guild_logic(chat_tree, request.invite_id).await?;
+    let theme = chat_tree.get_guild_theme_logic(guild_id).await?;
+    let emoji = chat_tree
+        .get_guild_emoji_logic(guild_id)
+        .await?
+        .into_iter()
+        .map(GuildEmojiInfo::from)
+        .collect();
+
+    Ok(PreviewGuildThemeResponse {
+        name: preview.name,
+        picture: preview.picture,
+        member_count: preview.member_count,
+        theme: theme.into(),
+        emoji,
+    })
+}
@@ -0,0 +1,143 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+use crate::impls::chat::moderation::ban_lists;
+
+/// Which integration to uninstall. Exactly one of the fields must be set.
+#[derive(Debug, Deserialize)]
+pub struct RemoveGuildIntegrationRequest {
+    pub guild_id: u64,
+    /// A registered integration. Its bot user is kicked, if it has one.
+    #[serde(default)]
+    pub integration_id: Option<u64>,
+    /// A bot that isn't registered, which is kicked.
+    #[serde(default)]
+    pub bot_user_id: Option<u64>,
+    /// A ban list the guild is subscribed to, which it is unsubscribed from.
+    #[serde(default)]
+    pub ban_list: Option<BanListSource>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RemoveGuildIntegrationResponse {}
+
+/// Uninstalls an integration from a guild, removing what it used to act on
+/// the guild along with it.
+#[require_perms(guild, "guild.manage.integrations")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: RemoveGuildIntegrationRequest,
+) -> ServerResult<RemoveGuildIntegrationResponse> {
+    let RemoveGuildIntegrationRequest {
+        guild_id,
+        integration_id,
+        bot_user_id,
+        ban_list,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    match (integration_id, bot_user_id, ban_list) {
+        (Some(integration_id), None, None) => {
+            let mut integrations = chat_tree.get_guild_integrations_logic(guild_id).await?;
+            let Some(index) = integrations
+                .iter()
+                .position(|integration| integration.id == integration_id)
+            else {
+                bail!(("h.no-such-integration", "integration doesn't exist"));
+            };
+            let integration = integrations.remove(index);
+            if let Some(bot_user_id) = integration.bot_user_id {
+                kick_bot(svc, guild_id, user_id, bot_user_id).await?;
+            }
+            chat_tree
+                .set_guild_integrations_logic(guild_id, integrations)
+                .await?;
+            audit::record(
+                &svc.deps,
+                guild_id,
+                user_id,
+                AuditAction::IntegrationUninstalled {
+                    integration_id,
+                    kind: integration.kind,
+                    name: integration.name,
+                },
+            )
+            .await?;
+        }
+        (None, Some(bot_user_id), None) => {
+            let profile = svc.deps.profile_tree.get_profile_logic(bot_user_id).await?;
+            if !profile.is_bot {
+                bail!(("h.not-a-bot", "user isn't a bot"));
+            }
+            kick_bot(svc, guild_id, user_id, bot_user_id).await?;
+        }
+        (None, None, Some(source)) => {
+            let mut subscriptions = chat_tree.get_ban_list_subscriptions_logic(guild_id).await?;
+            let len = subscriptions.len();
+            subscriptions.retain(|subscription| subscription.source != source);
+            if subscriptions.len() == len {
+                bail!((
+                    "h.not-subscribed",
+                    "the guild isn't subscribed to this ban list"
+                ));
+            }
+            chat_tree
+                .set_ban_list_subscriptions_logic(guild_id, subscriptions)
+                .await?;
+            ban_lists::sync_guild(svc, guild_id).await?;
+        }
+        _ => bail!((
+            "h.invalid-integration-selection",
+            "exactly one of integration ID, bot user ID or ban list must be given"
+        )),
+    }
+
+    Ok(RemoveGuildIntegrationResponse {})
+}
+
+/// Kicks the bot user of an integration from a guild.
+async fn kick_bot(
+    svc: &ChatServer,
+    guild_id: u64,
+    user_id: u64,
+    bot_user_id: u64,
+) -> ServerResult<()> {
+    let chat_tree = &svc.deps.chat_tree;
+
+    // the bot might have left already
+    if chat_tree
+        .is_user_in_guild(guild_id, bot_user_id)
+        .await
+        .is_err()
+    {
+        return Ok(());
+    }
+    chat_tree
+        .check_role_hierarchy(guild_id, user_id, Some(bot_user_id), &[])
+        .await?;
+
+    chat_tree.kick_user_logic(guild_id, bot_user_id).await?;
+    audit::record(
+        &svc.deps,
+        guild_id,
+        user_id,
+        AuditAction::UserKicked {
+            user_id: bot_user_id,
+        },
+    )
+    .await?;
+
+    svc.deps.event_bus.publish(DomainEvent::MemberLeft {
+        guild_id,
+        user_id: bot_user_id,
+        reason: LeaveReason::Kicked,
+        ban_reason: None,
+    });
+
+    svc.dispatch_guild_leave(guild_id, bot_user_id).await?;
+
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct ReviewScreeningApplicationRequest {
+    pub guild_id: u64,
+    /// The user whose application to review.
+    pub user_id: u64,
+    /// Whether to let the user in or reject them.
+    pub accept: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReviewScreeningApplicationResponse {}
+
+#[require_perms(guild, "members.screening.review")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: ReviewScreeningApplicationRequest,
+) -> ServerResult<ReviewScreeningApplicationResponse> {
+    let ReviewScreeningApplicationRequest {
+        guild_id,
+        user_id: applicant_id,
+        accept,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let key = make_screening_application_key(guild_id, applicant_id);
+    // only one review of an application counts, even if moderators race each other
+    let reviewed = match chat_tree.get(key).await? {
+        Some(raw) => chat_tree
+            .compare_and_swap(&key, Some(raw.as_ref()), None)
+            .await?
+            .then(|| db::deser_screening_application(raw)),
+        None => None,
+    };
+    let Some(application) = reviewed else {
+        bail!((
+            "h.no-screening-application",
+            "this user has no screening application waiting for review"
+        ));
+    };
+
+    let can_join = chat_tree
+        .is_user_banned_in_guild(guild_id, applicant_id)
+        .await?
+        .not()
+        && chat_tree
+            .is_user_in_guild(guild_id, applicant_id)
+            .await
+            .is_err();
+    if accept && can_join {
+        let grant_role_id =
+            join_guild::check_role_grant(chat_tree, guild_id, application.grant_role_id).await?;
+        join_guild::add_guild_member(svc, guild_id, applicant_id, grant_role_id).await?;
+    }
+
+    svc.deps
+        .event_bus
+        .publish(DomainEvent::ScreeningApplicationReviewed {
+            guild_id,
+            user_id: applicant_id,
+            reviewed_by: user_id,
+            accepted: accept,
+        });
+    audit::record(
+        &svc.deps,
+        guild_id,
+        user_id,
+        AuditAction::ScreeningApplicationReviewed {
+            user_id: applicant_id,
+            accepted: accept,
+        },
+    )
+    .await?;
+
+    Ok(ReviewScreeningApplicationResponse {})
+}
@@ -0,0 +1,135 @@
+use super::*;
+
+use serde::Deserialize;
+
+use crate::utils::cursor::{Cursor, Direction};
+
+/// Most members returned at once.
+const MAX_SEARCH_RESULTS: usize = 100;
+/// Most members looked at for one page. Searches in big guilds return a
+/// cursor to keep going with, even if the page isn't full.
+const MAX_MEMBERS_SCANNED: usize = 5000;
+
+#[derive(Debug, Deserialize)]
+pub struct SearchMembersRequest {
+    pub guild_id: u64,
+    /// Start of the usernames to find, ignoring case.
+    pub query: String,
+    /// `next_cursor` of the previous page.
+    #[serde(default)]
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MemberSearchResult {
+    pub user_id: u64,
+    pub user_name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchMembersResponse {
+    /// Members whose username starts with the query, ordered by ID.
+    pub members: Vec<MemberSearchResult>,
+    /// Cursor to keep searching with, not set if every member was searched.
+    pub next_cursor: Option<String>,
+}
+
+/// Searches the members of a guild by the start of their username, a page
+/// at a time.
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: SearchMembersRequest,
+) -> ServerResult<SearchMembersResponse> {
+    let SearchMembersRequest {
+        guild_id,
+        query,
+        cursor,
+        limit,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree.check_guild_user(guild_id, user_id).await?;
+
+    let query = query.trim().to_lowercase();
+    if query.is_empty() {
+        bail!(("h.empty-search-query", "search query must not be empty"));
+    }
+    let limit = limit
+        .unwrap_or(MAX_SEARCH_RESULTS)
+        .clamp(1, MAX_SEARCH_RESULTS);
+    let filter = [guild_id.to_be_bytes().as_ref(), query.as_bytes()].concat();
+    let pager = Pager::new(Direction::Forward, filter.clone(), cursor.as_deref(), limit)?;
+
+    let prefix = make_guild_mem_prefix(guild_id);
+    let start = make_member_key(guild_id, 0);
+    let end = make_member_key(guild_id, u64::MAX);
+    let Some((start, end)) = pager.range(&start, &end) else {
+        return Ok(SearchMembersResponse {
+            members: Vec::new(),
+            next_cursor: None,
+        });
+    };
+
+    // the first key can be the cursor's, which isn't part of the page
+    let mut keys = Vec::new();
+    let mut scanned_all = true;
+    for res in chat_tree
+        .chat_tree
+        .range(start.as_slice()..=end.as_slice())
+        .await
+    {
+        let (key, _) = res?;
+        if keys.len() > MAX_MEMBERS_SCANNED {
+            scanned_all = false;
+            break;
+        }
+        keys.push(key);
+    }
+
+    let mut entries = Vec::with_capacity(keys.len());
+    for key in keys {
+        // Safety: member keys are always a prefix followed by a u64
+        let member_id = u64::from_be_bytes(unsafe {
+            key.split_at(prefix.len()).1.try_into().unwrap_unchecked()
+        });
+        // users from other homeservers don't have a profile here
+        let user_name = svc
+            .deps
+            .profile_tree
+            .get_profile_logic(member_id)
+            .await
+            .ok()
+            .map(|profile| profile.user_name)
+            .filter(|user_name| user_name.to_lowercase().starts_with(&query));
+        entries.push(ServerResult::Ok((
+            key,
+            user_name.map(|user_name| (member_id, user_name)),
+        )));
+    }
+    let last_scanned = entries
+        .last()
+        .and_then(|res| res.as_ref().ok())
+        .map(|(key, _)| key.as_ref().to_vec());
+
+    let page = pager.collect(entries.into_iter(), |_, found| found)?;
+    let next_cursor = match page.next_cursor {
+        Some(cursor) => Some(cursor),
+        None if !scanned_all => {
+            last_scanned.map(|key| Cursor::new(Direction::Forward, &filter, key).encode())
+        }
+        None => None,
+    };
+
+    Ok(SearchMembersResponse {
+        members: page
+            .items
+            .into_iter()
+            .map(|(user_id, user_name)| MemberSearchResult { user_id, user_name })
+            .collect(),
+        next_cursor,
+    })
+}
@@ -0,0 +1,40 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct SetGuildPublicRequest {
+    pub guild_id: u64,
+    pub public: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetGuildPublicResponse {}
+
+/// Lists a guild in the guild directory, or removes it from there.
+#[require_perms(guild, "guild.manage.change-information")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: SetGuildPublicRequest,
+) -> ServerResult<SetGuildPublicResponse> {
+    let SetGuildPublicRequest { guild_id, public } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let guild = chat_tree.get_guild_logic(guild_id).await?;
+    let is_normal = matches!(
+        guild.kind.and_then(|kind| kind.kind),
+        Some(guild_kind::Kind::Normal(_))
+    );
+    if public && !is_normal {
+        bail!((
+            "h.guild-cant-be-public",
+            "only normal guilds can be listed in the directory"
+        ));
+    }
+
+    chat_tree.set_guild_public(guild_id, public).await?;
+
+    Ok(SetGuildPublicResponse {})
+}
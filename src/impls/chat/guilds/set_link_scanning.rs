@@ -0,0 +1,30 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct SetLinkScanningRequest {
+    pub guild_id: u64,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetLinkScanningResponse {}
+
+/// Opts a guild in or out of checking messages for links to blocked domains.
+#[require_perms(guild, "guild.manage.change-information")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: SetLinkScanningRequest,
+) -> ServerResult<SetLinkScanningResponse> {
+    let SetLinkScanningRequest { guild_id, enabled } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree
+        .set_link_scanning_enabled(guild_id, enabled)
+        .await?;
+
+    Ok(SetLinkScanningResponse {})
+}
@@ -0,0 +1,36 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+use super::get_screening::ScreeningInfo;
+
+#[derive(Debug, Deserialize)]
+pub struct SetScreeningRequest {
+    pub guild_id: u64,
+    /// The new membership screening. `None` disables screening.
+    #[serde(default)]
+    pub screening: Option<ScreeningInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetScreeningResponse {}
+
+#[require_perms(guild, "guild.manage.change-information")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: SetScreeningRequest,
+) -> ServerResult<SetScreeningResponse> {
+    let SetScreeningRequest {
+        guild_id,
+        screening,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree
+        .put_screening_logic(guild_id, screening.map(Into::into))
+        .await?;
+
+    Ok(SetScreeningResponse {})
+}
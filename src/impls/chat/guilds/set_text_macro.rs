@@ -0,0 +1,88 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+/// Most text macros a guild can have.
+const MAX_TEXT_MACROS: usize = 100;
+/// Longest a text macro name can be, in characters.
+const MAX_NAME_LEN: usize = 32;
+/// Longest the text of a text macro can be, in characters.
+const MAX_TEXT_LEN: usize = 4000;
+
+#[derive(Debug, Deserialize)]
+pub struct SetTextMacroRequest {
+    pub guild_id: u64,
+    /// Name of the macro, without the leading `/`.
+    pub name: String,
+    /// Text to send instead of `/<name>`. The macro is removed if this isn't set.
+    #[serde(default)]
+    pub text: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetTextMacroResponse {}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.chars().count() <= MAX_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+/// Creates, replaces or removes a text macro of a guild.
+#[require_perms(guild, "guild.manage.text-macros")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: SetTextMacroRequest,
+) -> ServerResult<SetTextMacroResponse> {
+    let SetTextMacroRequest {
+        guild_id,
+        name,
+        text,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let mut macros = chat_tree.get_text_macros_logic(guild_id).await?;
+    let existing = macros.iter().position(|text_macro| text_macro.name == name);
+    match (text, existing) {
+        (Some(text), existing) => {
+            if !is_valid_name(&name) {
+                bail!((
+                    "h.invalid-text-macro-name",
+                    "text macro names must be 1 to 32 lowercase letters, digits, `-` or `_`"
+                ));
+            }
+            if text.trim().is_empty() || text.chars().count() > MAX_TEXT_LEN {
+                bail!((
+                    "h.invalid-text-macro-text",
+                    "text macro text must not be empty or longer than 4000 characters"
+                ));
+            }
+            let text_macro = TextMacro {
+                name,
+                text,
+                created_by: user_id,
+            };
+            match existing {
+                Some(index) => macros[index] = text_macro,
+                None if macros.len() >= MAX_TEXT_MACROS => bail!((
+                    "h.too-many-text-macros",
+                    "guilds can't have more than 100 text macros"
+                )),
+                None => macros.push(text_macro),
+            }
+            macros.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        }
+        (None, Some(index)) => {
+            macros.remove(index);
+        }
+        (None, None) => bail!(("h.no-such-text-macro", "text macro doesn't exist")),
+    }
+
+    chat_tree.set_text_macros_logic(guild_id, macros).await?;
+
+    Ok(SetTextMacroResponse {})
+}
@@ -0,0 +1,43 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct SetWidgetRequest {
+    pub guild_id: u64,
+    pub enabled: bool,
+    /// Invite to show on the widget, so people seeing it can join.
+    #[serde(default)]
+    pub invite_id: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetWidgetResponse {}
+
+/// Enables or disables the public widget of a guild. Widgets show the name,
+/// picture, member counts and recent activity of the guild to anyone.
+#[require_perms(guild, "guild.manage.change-information")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: SetWidgetRequest,
+) -> ServerResult<SetWidgetResponse> {
+    let SetWidgetRequest {
+        guild_id,
+        enabled,
+        invite_id,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    if let Some(invite_id) = invite_id.as_deref() {
+        chat_tree.check_guild_invite(guild_id, invite_id).await?;
+    }
+
+    let settings = enabled.then(|| WidgetSettings { invite_id });
+    chat_tree
+        .set_widget_settings_logic(guild_id, settings)
+        .await?;
+
+    Ok(SetWidgetResponse {})
+}
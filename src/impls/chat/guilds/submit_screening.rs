@@ -0,0 +1,100 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct SubmitScreeningRequest {
+    /// The invite the user wants to join with.
+    pub invite_id: String,
+    /// Must be `true`, to acknowledge the rules of the guild.
+    pub accept_rules: bool,
+    /// Answers to the questions, in the same order as the questions.
+    #[serde(default)]
+    pub answers: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SubmitScreeningResponse {
+    pub guild_id: u64,
+    /// Whether the user joined the guild right away. If not,
+    /// the answers are waiting for a moderator to review them.
+    pub joined: bool,
+}
+
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: SubmitScreeningRequest,
+) -> ServerResult<SubmitScreeningResponse> {
+    let SubmitScreeningRequest {
+        invite_id,
+        accept_rules,
+        answers,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let (guild_id, _) = join_guild::get_joinable_invite(chat_tree, user_id, &invite_id).await?;
+    let screening = match chat_tree.get_screening_logic(guild_id).await? {
+        Some(screening) => screening,
+        None => bail!((
+            "h.no-screening",
+            "this guild doesn't have membership screening, join it directly"
+        )),
+    };
+
+    if !accept_rules {
+        bail!((
+            "h.rules-not-accepted",
+            "the rules of the guild must be accepted to join it"
+        ));
+    }
+
+    let joined = if screening.auto_accept {
+        let grant_role_id = join_guild::get_invite_grant(chat_tree, &invite_id, guild_id).await?;
+        join_guild::join_with_invite(svc, &invite_id, guild_id, user_id, grant_role_id).await?;
+        true
+    } else {
+        if answers.len() != screening.questions.len() {
+            bail!((
+                "h.bad-screening-answers",
+                format!(
+                    "expected {} answers, got {}",
+                    screening.questions.len(),
+                    answers.len()
+                )
+            ));
+        }
+        let grant_role_id = chat_tree
+            .get_invite_role_grant_logic(&invite_id)
+            .await?
+            .map(|grant| grant.role_id);
+        let application = ScreeningApplication {
+            answers,
+            submitted_at: get_time_secs(),
+            grant_role_id,
+        };
+        join_guild::use_invite(chat_tree, &invite_id, guild_id).await?;
+        let applied = chat_tree
+            .insert(
+                make_screening_application_key(guild_id, user_id),
+                rkyv_ser(&application),
+            )
+            .await;
+        if let Err(err) = applied {
+            chat_tree
+                .release_invite_use_logic(&invite_id, guild_id)
+                .await?;
+            bail!(err);
+        }
+        chat_tree
+            .record_invite_use_logic(&invite_id, user_id)
+            .await?;
+        svc.deps
+            .event_bus
+            .publish(DomainEvent::ScreeningApplicationSubmitted { guild_id, user_id });
+        false
+    };
+
+    Ok(SubmitScreeningResponse { guild_id, joined })
+}
@@ -0,0 +1,109 @@
+use super::*;
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct SubscribeToMemberListRequest {
+    pub guild_id: u64,
+    /// Ranges of the member list to get, as start and end indexes. The end
+    /// index isn't included.
+    pub ranges: Vec<(usize, usize)>,
+    /// Subscription to change the ranges of, for example when the client
+    /// scrolls. A new subscription is made if this isn't set.
+    #[serde(default)]
+    pub subscription_id: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SubscribeToMemberListResponse {
+    pub subscription_id: u64,
+    pub member_count: usize,
+    pub ranges: Vec<MemberListRange>,
+}
+
+/// Gets ranges of a guild's member list, ordered by user ID, and subscribes
+/// to their changes. Clients poll `chat/member-list-updates` with the
+/// subscription to get the ranges again when members join or leave.
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: SubscribeToMemberListRequest,
+) -> ServerResult<SubscribeToMemberListResponse> {
+    let SubscribeToMemberListRequest {
+        guild_id,
+        ranges,
+        subscription_id,
+    } = request;
+
+    if ranges.len() > MAX_MEMBER_LIST_RANGES {
+        bail!((
+            "h.too-many-member-list-ranges",
+            format!(
+                "at most {} ranges of the member list can be subscribed to",
+                MAX_MEMBER_LIST_RANGES
+            )
+        ));
+    }
+    if ranges
+        .iter()
+        .any(|(start, end)| start > end || end - start > MAX_MEMBER_LIST_RANGE_LEN)
+    {
+        bail!((
+            "h.invalid-member-list-range",
+            format!(
+                "member list ranges must end after they start, and have at most {} members",
+                MAX_MEMBER_LIST_RANGE_LEN
+            )
+        ));
+    }
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree.check_guild_user(guild_id, user_id).await?;
+
+    let members = match subscription_id {
+        Some(_) => None,
+        None => Some(chat_tree.get_guild_members_logic(guild_id).await?.members),
+    };
+    let Some((subscription_id, member_count, ranges)) =
+        svc.deps
+            .member_lists
+            .subscribe(user_id, guild_id, subscription_id, ranges, members)
+    else {
+        bail!(NO_SUCH_MEMBER_LIST_SUBSCRIPTION_ERR);
+    };
+
+    Ok(SubscribeToMemberListResponse {
+        subscription_id,
+        member_count,
+        ranges,
+    })
+}
+
+/// Keeps the member lists of guilds with subscriptions up to date.
+pub async fn follow_member_changes(deps: Arc<Dependencies>) {
+    let mut events = deps.event_bus.subscribe();
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(skipped)) => {
+                tracing::warn!(
+                    "member list updates lagged behind, skipped {} events",
+                    skipped
+                );
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        match &*event {
+            DomainEvent::MemberJoined { guild_id, user_id } => {
+                deps.member_lists.member_changed(*guild_id, *user_id, true)
+            }
+            DomainEvent::MemberLeft {
+                guild_id, user_id, ..
+            } => deps.member_lists.member_changed(*guild_id, *user_id, false),
+            DomainEvent::GuildDeleted { guild_id, .. } => deps.member_lists.remove_guild(*guild_id),
+            _ => {}
+        }
+    }
+}
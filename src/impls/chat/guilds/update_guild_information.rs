@@ -1,5 +1,6 @@
 use super::*;
 
+#[require_perms(guild, "guild.manage.change-information")]
 pub async fn handler(
     svc: &ChatServer,
     request: Request<UpdateGuildInformationRequest>,
@@ -15,43 +16,38 @@ pub async fn handler(
 
     let chat_tree = &svc.deps.chat_tree;
 
-    chat_tree.check_guild_user(guild_id, user_id).await?;
-
     let mut guild_info = chat_tree.get_guild_logic(guild_id).await?;
 
-    chat_tree
-        .check_perms(
-            guild_id,
-            None,
-            user_id,
-            "guild.manage.change-information",
-            false,
-        )
-        .await?;
-
     if let Some(new_name) = new_name.clone() {
         guild_info.name = new_name;
     }
     if let Some(new_picture) = new_picture.clone() {
         guild_info.picture = Some(new_picture);
     }
-    if let Some(new_metadata) = new_metadata.clone() {
-        guild_info.metadata = Some(new_metadata);
-    }
+    let new_metadata = new_metadata.map(|mut new_metadata| {
+        // emoji and the theme are managed by the server, so they can't be changed this way
+        for name in [GUILD_EMOJI_EXTENSION, GUILD_THEME_EXTENSION] {
+            new_metadata.extension.remove(name);
+            if let Some(value) = guild_info
+                .metadata
+                .as_mut()
+                .and_then(|metadata| metadata.extension.remove(name))
+            {
+                new_metadata.extension.insert(name.to_string(), value);
+            }
+        }
+        guild_info.metadata = Some(new_metadata.clone());
+        new_metadata
+    });
 
     chat_tree.put_guild_logic(guild_id, guild_info).await?;
 
-    svc.send_event_through_chan(
-        EventSub::Guild(guild_id),
-        stream_event::Event::EditedGuild(stream_event::GuildUpdated {
-            guild_id,
-            new_name,
-            new_picture,
-            new_metadata,
-        }),
-        None,
-        EventContext::empty(),
-    );
+    svc.deps.event_bus.publish(DomainEvent::GuildUpdated {
+        guild_id,
+        new_name,
+        new_picture,
+        new_metadata,
+    });
 
     Ok((UpdateGuildInformationResponse {}).into_response())
 }
@@ -0,0 +1,54 @@
+use super::*;
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateGuildLocaleRequest {
+    pub guild_id: u64,
+    /// BCP 47 language tag of the new locale. An empty string removes the locale.
+    #[serde(default)]
+    pub new_locale: Option<String>,
+    /// IANA name of the new timezone. An empty string removes the timezone,
+    /// so times are in UTC.
+    #[serde(default)]
+    pub new_timezone: Option<String>,
+}
+
+/// Sets the locale and timezone the server writes messages in a guild with.
+#[require_perms(guild, "guild.manage.change-information")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: UpdateGuildLocaleRequest,
+) -> ServerResult<GuildLocaleInfo> {
+    let UpdateGuildLocaleRequest {
+        guild_id,
+        new_locale,
+        new_timezone,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let mut locale = chat_tree.get_guild_locale_logic(guild_id).await?;
+
+    if let Some(new_locale) = new_locale {
+        if new_locale.is_empty() {
+            locale.locale = None;
+        } else {
+            validate_locale(&new_locale)?;
+            locale.locale = Some(new_locale);
+        }
+    }
+    if let Some(new_timezone) = new_timezone {
+        if new_timezone.is_empty() {
+            locale.timezone = None;
+        } else {
+            validate_timezone(&new_timezone)?;
+            locale.timezone = Some(new_timezone);
+        }
+    }
+
+    chat_tree.put_guild_locale_logic(guild_id, &locale).await?;
+
+    Ok(locale.into())
+}
@@ -0,0 +1,74 @@
+use super::*;
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateGuildThemeRequest {
+    pub guild_id: u64,
+    /// File ID of the new banner. An empty string removes the banner.
+    #[serde(default)]
+    pub new_banner: Option<String>,
+    /// File ID of the new splash. An empty string removes the splash.
+    #[serde(default)]
+    pub new_splash: Option<String>,
+    #[serde(default)]
+    pub new_theme_color: Option<i32>,
+    /// Whether to remove the theme color.
+    #[serde(default)]
+    pub reset_theme_color: bool,
+}
+
+#[require_perms(guild, "guild.manage.change-information")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: UpdateGuildThemeRequest,
+) -> ServerResult<GuildThemeInfo> {
+    let UpdateGuildThemeRequest {
+        guild_id,
+        new_banner,
+        new_splash,
+        new_theme_color,
+        reset_theme_color,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+    let media_store = svc.deps.media_store.as_ref();
+
+    let mut theme = chat_tree.get_guild_theme_logic(guild_id).await?;
+
+    if let Some(new_banner) = new_banner {
+        if new_banner.is_empty() {
+            theme.banner = None;
+        } else {
+            validate_guild_image(media_store, &new_banner).await?;
+            theme.banner = Some(new_banner);
+        }
+    }
+    if let Some(new_splash) = new_splash {
+        if new_splash.is_empty() {
+            theme.splash = None;
+        } else {
+            validate_guild_image(media_store, &new_splash).await?;
+            theme.splash = Some(new_splash);
+        }
+    }
+    if reset_theme_color {
+        theme.theme_color = None;
+    } else if let Some(new_theme_color) = new_theme_color {
+        theme.theme_color = Some(new_theme_color);
+    }
+
+    let new_metadata = chat_tree
+        .put_guild_theme_logic(guild_id, theme.clone())
+        .await?;
+
+    svc.deps.event_bus.publish(DomainEvent::GuildUpdated {
+        guild_id,
+        new_name: None,
+        new_picture: None,
+        new_metadata: Some(new_metadata),
+    });
+
+    Ok(theme.into())
+}
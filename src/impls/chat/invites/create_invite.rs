@@ -1,5 +1,6 @@
 use super::*;
 
+#[require_perms(guild, "invites.manage.create")]
 pub async fn handler(
     svc: &ChatServer,
     request: Request<CreateInviteRequest>,
@@ -14,13 +15,18 @@ pub async fn handler(
 
     let chat_tree = &svc.deps.chat_tree;
 
-    chat_tree.check_guild_user(guild_id, user_id).await?;
     chat_tree
-        .check_perms(guild_id, None, user_id, "invites.manage.create", false)
+        .create_invite_logic(guild_id, name.as_str(), possible_uses)
         .await?;
-
     chat_tree
-        .create_invite_logic(guild_id, name.as_str(), possible_uses)
+        .set_invite_info_logic(
+            name.as_str(),
+            Some(InviteInfo {
+                created_by: user_id,
+                created_at: get_time_secs(),
+                expires_at: None,
+            }),
+        )
         .await?;
 
     Ok((CreateInviteResponse { invite_id: name }).into_response())
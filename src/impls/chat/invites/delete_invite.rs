@@ -1,5 +1,6 @@
 use super::*;
 
+#[require_perms(guild, "invites.manage.delete")]
 pub async fn handler(
     svc: &ChatServer,
     request: Request<DeleteInviteRequest>,
@@ -13,16 +14,20 @@ pub async fn handler(
 
     let chat_tree = &svc.deps.chat_tree;
 
-    chat_tree.check_guild_user(guild_id, user_id).await?;
-    chat_tree
-        .check_perms(guild_id, None, user_id, "invites.manage.delete", false)
-        .await?;
-
     chat_tree
         .chat_tree
         .remove(&make_invite_key(invite_id.as_str()))
         .await
         .map_err(ServerError::DbError)?;
+    chat_tree
+        .set_invite_role_grant_logic(invite_id.as_str(), None)
+        .await?;
+    chat_tree
+        .set_invite_info_logic(invite_id.as_str(), None)
+        .await?;
+    chat_tree
+        .remove(make_invite_uses_key(invite_id.as_str()))
+        .await?;
 
     Ok((DeleteInviteResponse {}).into_response())
 }
@@ -1,5 +1,6 @@
 use super::*;
 
+#[require_perms(guild, "invites.view")]
 pub async fn handler(
     svc: &ChatServer,
     request: Request<GetGuildInvitesRequest>,
@@ -10,11 +11,6 @@ pub async fn handler(
 
     let chat_tree = &svc.deps.chat_tree;
 
-    chat_tree.check_guild_user(guild_id, user_id).await?;
-    chat_tree
-        .check_perms(guild_id, None, user_id, "invites.view", false)
-        .await?;
-
     chat_tree
         .get_guild_invites_logic(guild_id)
         .await
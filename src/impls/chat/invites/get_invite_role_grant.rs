@@ -0,0 +1,40 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct GetInviteRoleGrantRequest {
+    pub guild_id: u64,
+    pub invite_id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetInviteRoleGrantResponse {
+    /// Role given to users who join with the invite, if any.
+    pub role_id: Option<u64>,
+    /// User who attached the role to the invite.
+    pub granted_by: Option<u64>,
+}
+
+#[require_perms(guild, "invites.view")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetInviteRoleGrantRequest,
+) -> ServerResult<GetInviteRoleGrantResponse> {
+    let GetInviteRoleGrantRequest {
+        guild_id,
+        invite_id,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree.check_guild_invite(guild_id, &invite_id).await?;
+
+    let grant = chat_tree.get_invite_role_grant_logic(&invite_id).await?;
+
+    Ok(GetInviteRoleGrantResponse {
+        role_id: grant.as_ref().map(|grant| grant.role_id),
+        granted_by: grant.map(|grant| grant.granted_by),
+    })
+}
@@ -0,0 +1,106 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+use crate::utils::cursor::Direction;
+
+#[derive(Debug, Deserialize)]
+pub struct GetInviteStatsRequest {
+    pub guild_id: u64,
+    pub invite_id: String,
+    /// `next_cursor` of the previous page of uses.
+    #[serde(default)]
+    pub cursor: Option<String>,
+    /// How many uses to return at most.
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InviteUseInfo {
+    pub user_id: u64,
+    /// In seconds since unix epoch.
+    pub used_at: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetInviteStatsResponse {
+    /// Not set for invites made before this was recorded.
+    pub created_by: Option<u64>,
+    /// In seconds since unix epoch.
+    pub created_at: Option<u64>,
+    /// In seconds since unix epoch, not set if the invite doesn't expire.
+    pub expires_at: Option<u64>,
+    /// 0 if the invite can be used any number of times.
+    pub possible_uses: u32,
+    pub use_count: u32,
+    /// Latest uses of the invite, oldest first.
+    pub uses: Vec<InviteUseInfo>,
+    /// Cursor to get the next page of uses with, not set if this is the last page.
+    pub next_cursor: Option<String>,
+}
+
+/// Gets who made an invite, when it expires and who used it.
+#[require_perms(guild, "invites.view")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetInviteStatsRequest,
+) -> ServerResult<GetInviteStatsResponse> {
+    let GetInviteStatsRequest {
+        guild_id,
+        invite_id,
+        cursor,
+        limit,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let (invite_guild_id, invite) = match chat_tree.get(make_invite_key(&invite_id)).await? {
+        Some(raw) => db::deser_invite_entry(raw),
+        None => bail!(ServerError::NoSuchInvite(invite_id.into())),
+    };
+    if invite_guild_id != guild_id {
+        bail!(ServerError::NoSuchInvite(invite_id.into()));
+    }
+
+    let info = chat_tree.get_invite_info_logic(&invite_id).await?;
+    let limit = limit
+        .unwrap_or(MAX_INVITE_USE_HISTORY)
+        .clamp(1, MAX_INVITE_USE_HISTORY);
+    let pager = Pager::new(
+        Direction::Forward,
+        invite_id.as_bytes(),
+        cursor.as_deref(),
+        limit,
+    )?;
+    // uses are kept in the order they happened, so they can be paged by time
+    let entries = chat_tree
+        .get_invite_uses_logic(&invite_id)
+        .await?
+        .into_iter()
+        .map(|invite_use| {
+            let key = [
+                invite_use.used_at.to_be_bytes(),
+                invite_use.user_id.to_be_bytes(),
+            ]
+            .concat();
+            ServerResult::Ok((key, invite_use))
+        });
+    let page = pager.collect(entries, |_, invite_use| {
+        Some(InviteUseInfo {
+            user_id: invite_use.user_id,
+            used_at: invite_use.used_at,
+        })
+    })?;
+
+    Ok(GetInviteStatsResponse {
+        created_by: info.as_ref().map(|info| info.created_by),
+        created_at: info.as_ref().map(|info| info.created_at),
+        expires_at: info.and_then(|info| info.expires_at),
+        possible_uses: invite.possible_uses,
+        use_count: invite.use_count,
+        uses: page.items,
+        next_cursor: page.next_cursor,
+    })
+}
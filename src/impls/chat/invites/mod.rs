@@ -3,7 +3,11 @@ use super::*;
 pub mod create_invite;
 pub mod delete_invite;
 pub mod get_guild_invites;
+pub mod get_invite_role_grant;
+pub mod get_invite_stats;
 pub mod get_pending_invites;
 pub mod ignore_pending_invite;
 pub mod invite_user_to_guild;
 pub mod reject_pending_invite;
+pub mod set_invite_expiry;
+pub mod set_invite_role_grant;
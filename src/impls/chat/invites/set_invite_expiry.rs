@@ -0,0 +1,54 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct SetInviteExpiryRequest {
+    pub guild_id: u64,
+    pub invite_id: String,
+    /// How long the invite can be used for after it was made, in seconds.
+    /// `None` makes the invite never expire.
+    pub max_age_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetInviteExpiryResponse {
+    /// When the invite stops working, in seconds since unix epoch.
+    pub expires_at: Option<u64>,
+}
+
+/// Sets how long an invite can be used for.
+#[require_perms(guild, "invites.manage.create")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: SetInviteExpiryRequest,
+) -> ServerResult<SetInviteExpiryResponse> {
+    let SetInviteExpiryRequest {
+        guild_id,
+        invite_id,
+        max_age_secs,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree.check_guild_invite(guild_id, &invite_id).await?;
+
+    // invites made before their creation time was recorded count as made now
+    let mut info = chat_tree
+        .get_invite_info_logic(&invite_id)
+        .await?
+        .unwrap_or_else(|| InviteInfo {
+            created_by: user_id,
+            created_at: get_time_secs(),
+            expires_at: None,
+        });
+    info.expires_at = max_age_secs.map(|secs| info.created_at.saturating_add(secs));
+    let expires_at = info.expires_at;
+
+    chat_tree
+        .set_invite_info_logic(&invite_id, Some(info))
+        .await?;
+
+    Ok(SetInviteExpiryResponse { expires_at })
+}
@@ -0,0 +1,63 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct SetInviteRoleGrantRequest {
+    pub guild_id: u64,
+    pub invite_id: String,
+    /// Role to give to users who join with the invite. `None` stops the
+    /// invite from giving a role.
+    pub role_id: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetInviteRoleGrantResponse {}
+
+/// Attaches a role to an invite, which is given to everyone who joins with it.
+#[require_perms(guild, "invites.manage.create")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: SetInviteRoleGrantRequest,
+) -> ServerResult<SetInviteRoleGrantResponse> {
+    let SetInviteRoleGrantRequest {
+        guild_id,
+        invite_id,
+        role_id,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree.check_guild_invite(guild_id, &invite_id).await?;
+
+    let grant = match role_id {
+        Some(role_id) => {
+            if role_id == DEFAULT_ROLE_ID {
+                bail!((
+                    "h.bad-invite-role",
+                    "everyone already gets the default role on join"
+                ));
+            }
+            chat_tree.does_role_exist(guild_id, role_id).await?;
+            // only users who could give the role themselves can make an invite give it
+            chat_tree
+                .check_perms(guild_id, None, user_id, "roles.user.manage", false)
+                .await?;
+            chat_tree
+                .check_role_hierarchy(guild_id, user_id, None, &[role_id])
+                .await?;
+            Some(InviteRoleGrant {
+                role_id,
+                granted_by: user_id,
+            })
+        }
+        None => None,
+    };
+
+    chat_tree
+        .set_invite_role_grant_logic(&invite_id, grant)
+        .await?;
+
+    Ok(SetInviteRoleGrantResponse {})
+}
@@ -0,0 +1,40 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct AcknowledgeMessageRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    pub message_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AcknowledgeMessageResponse {}
+
+#[require_perms(guild, channel, "messages.view")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: AcknowledgeMessageRequest,
+) -> ServerResult<AcknowledgeMessageResponse> {
+    let AcknowledgeMessageRequest {
+        guild_id,
+        channel_id,
+        message_id,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    if !chat_tree
+        .acknowledge_message_logic(guild_id, channel_id, message_id, user_id)
+        .await?
+    {
+        bail!((
+            "h.acknowledgement-not-required",
+            "this message doesn't need to be acknowledged"
+        ));
+    }
+
+    Ok(AcknowledgeMessageResponse {})
+}
@@ -26,10 +26,29 @@ pub async fn handler(
             )
             .await?;
 
+        let image_id = emote.image_id.clone();
         let reaction = chat_tree
-            .update_reaction(user_id, guild_id, channel_id, message_id, emote, true)
+            .update_reaction(
+                user_id,
+                guild_id,
+                channel_id,
+                message_id,
+                emote,
+                true,
+                svc.deps.config.policy.max_reactions_per_message,
+            )
             .await?;
-        svc.send_reaction_event(guild_id, channel_id, message_id, reaction);
+        let changed = reaction.is_some();
+        svc.deps.event_bus.publish(DomainEvent::ReactionUpdated {
+            guild_id,
+            channel_id,
+            message_id,
+            reaction,
+        });
+        if changed {
+            svc.update_reaction_role(user_id, guild_id, channel_id, message_id, &image_id, true)
+                .await?;
+        }
     }
 
     Ok((AddReactionResponse {}).into_response())
@@ -0,0 +1,68 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct BindReactionRoleRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    pub message_id: u64,
+    /// Image ID of the emote to bind the role to.
+    pub image_id: String,
+    pub role_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BindReactionRoleResponse {}
+
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: BindReactionRoleRequest,
+) -> ServerResult<BindReactionRoleResponse> {
+    let BindReactionRoleRequest {
+        guild_id,
+        channel_id,
+        message_id,
+        image_id,
+        role_id,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree
+        .check_guild_user_channel(guild_id, user_id, channel_id)
+        .await?;
+    chat_tree
+        .check_perms(guild_id, None, user_id, "roles.user.manage", false)
+        .await?;
+    if role_id == DEFAULT_ROLE_ID {
+        bail!((
+            "h.reaction-role-cant-be-default",
+            "the default role can't be bound to a reaction"
+        ));
+    }
+    chat_tree.does_role_exist(guild_id, role_id).await?;
+    // whoever binds a role can hand it out to anyone reacting, so they must be above it
+    chat_tree
+        .check_role_hierarchy(guild_id, user_id, None, &[role_id])
+        .await?;
+    // make sure the message exists
+    chat_tree
+        .get_message_logic(guild_id, channel_id, message_id)
+        .await?;
+
+    let mut bindings = chat_tree
+        .get_reaction_roles_logic(guild_id, channel_id, message_id)
+        .await?;
+    if let Some(binding) = bindings.iter_mut().find(|b| b.image_id == image_id) {
+        binding.role_id = role_id;
+    } else {
+        bindings.push(ReactionRole { image_id, role_id });
+    }
+    chat_tree
+        .put_reaction_roles_logic(guild_id, channel_id, message_id, bindings)
+        .await?;
+
+    Ok(BindReactionRoleResponse {})
+}
@@ -0,0 +1,37 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct CancelScheduledMessageRequest {
+    pub scheduled_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CancelScheduledMessageResponse {}
+
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: CancelScheduledMessageRequest,
+) -> ServerResult<CancelScheduledMessageResponse> {
+    let CancelScheduledMessageRequest { scheduled_id } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let maybe_key = chat_tree
+        .get_scheduled_messages_logic(u64::MAX)
+        .await?
+        .into_iter()
+        .find(|(_, _, id, scheduled)| *id == scheduled_id && scheduled.author_id == user_id)
+        .map(|(key, ..)| key);
+    let Some(key) = maybe_key else {
+        bail!((
+            "h.no-such-scheduled-message",
+            format!("no scheduled message with id {}", scheduled_id)
+        ));
+    };
+    chat_tree.remove(key).await?;
+
+    Ok(CancelScheduledMessageResponse {})
+}
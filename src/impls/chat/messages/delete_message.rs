@@ -17,13 +17,10 @@ pub async fn handler(
     chat_tree
         .check_guild_user_channel(guild_id, user_id, channel_id)
         .await?;
-    if chat_tree
+    let (message, _) = chat_tree
         .get_message_logic(guild_id, channel_id, message_id)
-        .await?
-        .0
-        .author_id
-        != user_id
-    {
+        .await?;
+    if message.author_id != user_id {
         chat_tree
             .check_perms(
                 guild_id,
@@ -35,27 +32,29 @@ pub async fn handler(
             .await?;
     }
 
-    chat_tree
-        .chat_tree
-        .remove(&make_msg_key(guild_id, channel_id, message_id))
-        .await
-        .map_err(ServerError::DbError)?;
-
-    svc.send_event_through_chan(
-        EventSub::Guild(guild_id),
-        stream_event::Event::DeletedMessage(stream_event::MessageDeleted {
-            guild_id,
-            channel_id,
-            message_id,
-        }),
-        Some(PermCheck::new(
-            guild_id,
-            Some(channel_id),
-            "messages.view",
-            false,
-        )),
-        EventContext::empty(),
-    );
+    delete_message_logic(svc, guild_id, channel_id, message_id, message).await?;
 
     Ok((DeleteMessageResponse {}).into_response())
 }
+
+/// Deletes a message along with everything stored with it, and notifies everyone about it
+pub async fn delete_message_logic(
+    svc: &ChatServer,
+    guild_id: u64,
+    channel_id: u64,
+    message_id: u64,
+    message: HarmonyMessage,
+) -> ServerResult<()> {
+    svc.deps
+        .chat_tree
+        .delete_messages_logic(guild_id, channel_id, &[(message_id, message)])
+        .await?;
+
+    svc.deps.event_bus.publish(DomainEvent::MessageDeleted {
+        guild_id,
+        channel_id,
+        message_id,
+    });
+
+    Ok(())
+}
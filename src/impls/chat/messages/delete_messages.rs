@@ -0,0 +1,133 @@
+use super::*;
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+/// Maximum amount of messages that can be deleted at once.
+const MAX_MESSAGES: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteMessagesRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    /// IDs of the messages to delete. Messages that don't exist are skipped.
+    #[serde(default)]
+    pub message_ids: Vec<u64>,
+    /// Deletes the messages sent at or after this time instead, in seconds
+    /// since unix epoch. Can't be used with `message_ids`.
+    #[serde(default)]
+    pub sent_after: Option<u64>,
+    /// Deletes the messages sent before this time instead, in seconds since
+    /// unix epoch. Can't be used with `message_ids`.
+    #[serde(default)]
+    pub sent_before: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeleteMessagesResponse {
+    /// IDs of the messages that were deleted. When deleting a time range, if
+    /// as many messages as can be deleted at once were deleted, there might be
+    /// more left in the range.
+    pub deleted_message_ids: Vec<u64>,
+}
+
+/// Deletes many messages of a channel at once, either by ID or by when they
+/// were sent. Everyone is notified with a single event.
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: DeleteMessagesRequest,
+) -> ServerResult<DeleteMessagesResponse> {
+    let DeleteMessagesRequest {
+        guild_id,
+        channel_id,
+        message_ids,
+        sent_after,
+        sent_before,
+    } = request;
+
+    let by_time = sent_after.is_some() || sent_before.is_some();
+    if by_time == !message_ids.is_empty() {
+        bail!((
+            "h.invalid-message-selection",
+            "either message IDs or a time range must be given"
+        ));
+    }
+    if message_ids.len() > MAX_MESSAGES {
+        bail!((
+            "h.too-many-messages",
+            format!("at most {} messages can be deleted at once", MAX_MESSAGES)
+        ));
+    }
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree
+        .check_guild_user_channel(guild_id, user_id, channel_id)
+        .await?;
+
+    let messages = if by_time {
+        chat_tree
+            .get_messages_sent_between_logic(
+                guild_id,
+                channel_id,
+                sent_after.unwrap_or(0),
+                sent_before.unwrap_or(u64::MAX),
+                MAX_MESSAGES,
+            )
+            .await?
+    } else {
+        let mut seen = HashSet::with_capacity(message_ids.len());
+        let mut messages = Vec::with_capacity(message_ids.len());
+        for message_id in message_ids {
+            if !seen.insert(message_id) {
+                continue;
+            }
+            let key = make_msg_key(guild_id, channel_id, message_id);
+            if let Some(raw) = chat_tree.get(key).await? {
+                messages.push((message_id, db::deser_message(raw)));
+            }
+        }
+        messages
+    };
+
+    if messages
+        .iter()
+        .any(|(_, message)| message.author_id != user_id)
+    {
+        chat_tree
+            .check_perms(
+                guild_id,
+                Some(channel_id),
+                user_id,
+                "messages.manage.delete",
+                false,
+            )
+            .await?;
+    }
+
+    if messages.is_empty() {
+        return Ok(DeleteMessagesResponse {
+            deleted_message_ids: Vec::new(),
+        });
+    }
+
+    chat_tree
+        .delete_messages_logic(guild_id, channel_id, &messages)
+        .await?;
+
+    let deleted_message_ids = messages
+        .into_iter()
+        .map(|(message_id, _)| message_id)
+        .collect::<Vec<_>>();
+    svc.deps.event_bus.publish(DomainEvent::MessagesDeleted {
+        guild_id,
+        channel_id,
+        message_ids: deleted_message_ids.clone(),
+    });
+
+    Ok(DeleteMessagesResponse {
+        deleted_message_ids,
+    })
+}
@@ -0,0 +1,92 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+/// Default amount of users returned.
+const DEFAULT_LIMIT: u64 = 100;
+/// Maximum amount of users that can be returned at once.
+const MAX_LIMIT: u64 = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct GetAcknowledgementsRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    pub message_id: u64,
+    /// Whether to get the members who acknowledged the message, or the ones who didn't.
+    pub acknowledged: bool,
+    /// Only return members with an ID greater than this. Used to page through members.
+    #[serde(default)]
+    pub after: Option<u64>,
+    #[serde(default)]
+    pub limit: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetAcknowledgementsResponse {
+    /// IDs of the requested members, in ascending order.
+    pub user_ids: Vec<u64>,
+    /// How many current members acknowledged the message.
+    pub acknowledged_count: u64,
+    /// How many members the guild has.
+    pub member_count: u64,
+    /// Whether there are more members to get after these.
+    pub has_more: bool,
+}
+
+/// Gets which members of a guild acknowledged a message or didn't. Members who
+/// acknowledged a message and then left the guild aren't counted.
+#[require_perms(guild, channel, "messages.acknowledgements.manage")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetAcknowledgementsRequest,
+) -> ServerResult<GetAcknowledgementsResponse> {
+    let GetAcknowledgementsRequest {
+        guild_id,
+        channel_id,
+        message_id,
+        acknowledged,
+        after,
+        limit,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let Some(acks) = chat_tree
+        .get_message_acks_logic(guild_id, channel_id, message_id)
+        .await?
+    else {
+        bail!((
+            "h.acknowledgement-not-required",
+            "this message doesn't need to be acknowledged"
+        ));
+    };
+
+    let members = chat_tree.get_guild_members_logic(guild_id).await?.members;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+
+    let mut acknowledged_count = 0;
+    let mut user_ids = Vec::new();
+    let mut has_more = false;
+    for member_id in &members {
+        let has_acked = acks.binary_search(member_id).is_ok();
+        if has_acked {
+            acknowledged_count += 1;
+        }
+        if has_acked != acknowledged || after.map_or(false, |after| *member_id <= after) {
+            continue;
+        }
+        if user_ids.len() < limit {
+            user_ids.push(*member_id);
+        } else {
+            has_more = true;
+        }
+    }
+
+    Ok(GetAcknowledgementsResponse {
+        user_ids,
+        acknowledged_count,
+        member_count: members.len() as u64,
+        has_more,
+    })
+}
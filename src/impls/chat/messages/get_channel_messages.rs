@@ -1,5 +1,6 @@
 use super::*;
 
+#[require_perms(guild, channel, "messages.view")]
 pub async fn handler(
     svc: &ChatServer,
     request: Request<GetChannelMessagesRequest>,
@@ -16,14 +17,7 @@ pub async fn handler(
 
     let chat_tree = &svc.deps.chat_tree;
 
-    chat_tree
-        .check_guild_user_channel(guild_id, user_id, channel_id)
-        .await?;
-    chat_tree
-        .check_perms(guild_id, Some(channel_id), user_id, "messages.view", false)
-        .await?;
-
-    chat_tree
+    let mut response = chat_tree
         .get_channel_messages_logic(
             guild_id,
             channel_id,
@@ -31,6 +25,20 @@ pub async fn handler(
             direction.map(|val| Direction::from_i32(val).unwrap_or_default()),
             count,
         )
-        .await
-        .map(IntoResponse::into_response)
+        .await?;
+
+    let hidden = chat_tree
+        .get_hidden_quarantined_ids(guild_id, channel_id, user_id)
+        .await?;
+    if !hidden.is_empty() {
+        response.messages.retain(|message| {
+            !hidden.contains(&message.message_id)
+                || message
+                    .message
+                    .as_ref()
+                    .map_or(false, |message| message.author_id == user_id)
+        });
+    }
+
+    Ok(response.into_response())
 }
@@ -1,34 +1,39 @@
 use super::*;
 
+#[require_perms(guild, channel, "messages.view")]
 pub async fn handler(
     svc: &ChatServer,
     request: Request<GetMessageRequest>,
 ) -> ServerResult<Response<GetMessageResponse>> {
     let user_id = svc.deps.valid_sessions.auth(&request)?;
 
-    let request = request.into_message().await?;
-
     let GetMessageRequest {
         guild_id,
         channel_id,
         message_id,
-    } = request;
+    } = request.into_message().await?;
 
     let chat_tree = &svc.deps.chat_tree;
 
-    chat_tree
-        .check_guild_user_channel(guild_id, user_id, channel_id)
-        .await?;
-    chat_tree
-        .check_perms(guild_id, Some(channel_id), user_id, "messages.view", false)
+    let (message, _) = chat_tree
+        .get_message_logic(guild_id, channel_id, message_id)
         .await?;
 
-    let message = Some(
-        chat_tree
-            .get_message_logic(guild_id, channel_id, message_id)
+    if message.author_id != user_id
+        && chat_tree
+            .get_hidden_quarantined_ids(guild_id, channel_id, user_id)
             .await?
-            .0,
-    );
+            .contains(&message_id)
+    {
+        bail!(ServerError::NoSuchMessage {
+            guild_id,
+            channel_id,
+            message_id,
+        });
+    }
 
-    Ok((GetMessageResponse { message }).into_response())
+    Ok((GetMessageResponse {
+        message: Some(message),
+    })
+    .into_response())
 }
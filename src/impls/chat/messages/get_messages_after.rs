@@ -0,0 +1,106 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+use crate::utils::cursor::Direction;
+
+/// Default amount of messages returned.
+const DEFAULT_LIMIT: u64 = 100;
+/// Maximum amount of messages that can be returned at once.
+const MAX_LIMIT: u64 = 500;
+
+#[derive(Debug, Deserialize)]
+pub struct GetMessagesAfterRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    /// Position of the last message the client has, `0` to start from the beginning.
+    #[serde(default)]
+    pub position: u64,
+    /// `next_cursor` of the previous page. The same `position` must be sent with it.
+    #[serde(default)]
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub limit: Option<u64>,
+}
+
+/// A message that exists at some position in a channel. The message ID is its position,
+/// so the full message can be fetched with `GetMessage` or `GetChannelMessages`.
+#[derive(Debug, Serialize)]
+pub struct MessagePositionInfo {
+    pub position: u64,
+    pub author_id: u64,
+    pub created_at: u64,
+    pub edited_at: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetMessagesAfterResponse {
+    pub messages: Vec<MessagePositionInfo>,
+    /// Position of the latest message sent in the channel. Positions between the
+    /// requested position and this one that aren't returned belong to deleted messages,
+    /// or to quarantined messages the user can't see.
+    pub latest_position: u64,
+    pub reached_bottom: bool,
+    /// Cursor to get the next page with, not set if this is the last page.
+    pub next_cursor: Option<String>,
+}
+
+#[require_perms(guild, channel, "messages.view")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetMessagesAfterRequest,
+) -> ServerResult<GetMessagesAfterResponse> {
+    let GetMessagesAfterRequest {
+        guild_id,
+        channel_id,
+        position,
+        cursor,
+        limit,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let filter = [
+        guild_id.to_be_bytes(),
+        channel_id.to_be_bytes(),
+        position.to_be_bytes(),
+    ]
+    .concat();
+    let pager = Pager::new(
+        Direction::Forward,
+        filter,
+        cursor.as_deref(),
+        limit as usize,
+    )?;
+    let (page, latest_position) = chat_tree
+        .get_messages_after_logic(guild_id, channel_id, position, &pager)
+        .await?;
+    let mut messages = page.items;
+    let hidden = chat_tree
+        .get_hidden_quarantined_ids(guild_id, channel_id, user_id)
+        .await?;
+
+    let reached_bottom = messages
+        .last()
+        .map_or(true, |(position, _)| *position == latest_position);
+    messages
+        .retain(|(position, message)| !hidden.contains(position) || message.author_id == user_id);
+    let messages = messages
+        .into_iter()
+        .map(|(position, message)| MessagePositionInfo {
+            position,
+            author_id: message.author_id,
+            created_at: message.created_at,
+            edited_at: message.edited_at,
+        })
+        .collect();
+
+    Ok(GetMessagesAfterResponse {
+        messages,
+        latest_position,
+        reached_bottom,
+        next_cursor: page.next_cursor,
+    })
+}
@@ -0,0 +1,40 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct GetPendingAcknowledgementsRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetPendingAcknowledgementsResponse {
+    /// IDs of the messages the user still has to acknowledge, oldest first.
+    pub message_ids: Vec<u64>,
+}
+
+/// Gets the messages of a channel the user has yet to acknowledge.
+#[require_perms(guild, channel, "messages.view")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetPendingAcknowledgementsRequest,
+) -> ServerResult<GetPendingAcknowledgementsResponse> {
+    let GetPendingAcknowledgementsRequest {
+        guild_id,
+        channel_id,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let message_ids = chat_tree
+        .get_ack_required_messages_logic(guild_id, channel_id)
+        .await?
+        .into_iter()
+        .filter(|(_, acks)| acks.binary_search(&user_id).is_err())
+        .map(|(message_id, _)| message_id)
+        .collect();
+
+    Ok(GetPendingAcknowledgementsResponse { message_ids })
+}
@@ -1,5 +1,6 @@
 use super::*;
 
+#[require_perms(guild, channel, all_permissions::MESSAGES_VIEW)]
 pub async fn handler(
     svc: &ChatServer,
     request: Request<GetPinnedMessagesRequest>,
@@ -13,20 +14,6 @@ pub async fn handler(
 
     let chat_tree = &svc.deps.chat_tree;
 
-    chat_tree
-        .check_guild_user_channel(guild_id, user_id, channel_id)
-        .await?;
-
-    chat_tree
-        .check_perms(
-            guild_id,
-            Some(channel_id),
-            user_id,
-            all_permissions::MESSAGES_VIEW,
-            false,
-        )
-        .await?;
-
     let pinned_message_ids = chat_tree
         .get_pinned_messages_logic(guild_id, channel_id)
         .await?;
@@ -0,0 +1,107 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+use crate::impls::search::searchable_text;
+
+#[derive(Debug, Deserialize)]
+pub struct GetQuarantinedMessagesRequest {
+    pub guild_id: u64,
+    /// Only get quarantined messages of this channel. Gets them from every
+    /// channel the user can review if not set.
+    #[serde(default)]
+    pub channel_id: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct QuarantinedMessageInfo {
+    pub channel_id: u64,
+    pub message_id: u64,
+    pub author_id: u64,
+    /// Text of the message, if it has any.
+    pub text: Option<String>,
+    pub reason: String,
+    pub quarantined_at: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetQuarantinedMessagesResponse {
+    /// Quarantined messages, oldest first.
+    pub messages: Vec<QuarantinedMessageInfo>,
+}
+
+/// Gets the messages of a guild waiting for review by its moderators.
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetQuarantinedMessagesRequest,
+) -> ServerResult<GetQuarantinedMessagesResponse> {
+    let GetQuarantinedMessagesRequest {
+        guild_id,
+        channel_id,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree.check_guild_user(guild_id, user_id).await?;
+    let channel_ids = match channel_id {
+        Some(channel_id) => {
+            chat_tree
+                .check_guild_user_channel(guild_id, user_id, channel_id)
+                .await?;
+            chat_tree
+                .check_perms(
+                    guild_id,
+                    Some(channel_id),
+                    user_id,
+                    "messages.quarantine.review",
+                    false,
+                )
+                .await?;
+            vec![channel_id]
+        }
+        None => {
+            let channels = chat_tree
+                .get_guild_channels_logic(guild_id, user_id)
+                .await?
+                .channels;
+            let mut channel_ids = Vec::with_capacity(channels.len());
+            for channel in channels {
+                if chat_tree
+                    .can_review_quarantine(guild_id, channel.channel_id, user_id)
+                    .await?
+                {
+                    channel_ids.push(channel.channel_id);
+                }
+            }
+            channel_ids
+        }
+    };
+
+    let mut messages = Vec::new();
+    for channel_id in channel_ids {
+        for (message_id, quarantined) in chat_tree
+            .get_quarantined_messages_logic(guild_id, channel_id)
+            .await?
+        {
+            let Some(raw) = chat_tree
+                .get(make_msg_key(guild_id, channel_id, message_id))
+                .await?
+            else {
+                continue;
+            };
+            let message = db::deser_message(raw);
+            messages.push(QuarantinedMessageInfo {
+                channel_id,
+                message_id,
+                author_id: message.author_id,
+                text: searchable_text(message.content.as_ref()).map(str::to_string),
+                reason: quarantined.reason,
+                quarantined_at: quarantined.quarantined_at,
+            });
+        }
+    }
+    messages.sort_unstable_by_key(|message| message.quarantined_at);
+
+    Ok(GetQuarantinedMessagesResponse { messages })
+}
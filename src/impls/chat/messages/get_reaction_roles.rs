@@ -0,0 +1,39 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct GetReactionRolesRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    pub message_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetReactionRolesResponse {
+    pub bindings: Vec<ReactionRoleInfo>,
+}
+
+#[require_perms(guild, channel, "messages.view")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetReactionRolesRequest,
+) -> ServerResult<GetReactionRolesResponse> {
+    let GetReactionRolesRequest {
+        guild_id,
+        channel_id,
+        message_id,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let bindings = chat_tree
+        .get_reaction_roles_logic(guild_id, channel_id, message_id)
+        .await?
+        .into_iter()
+        .map(ReactionRoleInfo::from)
+        .collect();
+
+    Ok(GetReactionRolesResponse { bindings })
+}
@@ -0,0 +1,73 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+use crate::utils::cursor::Direction;
+
+/// Default amount of users returned.
+const DEFAULT_LIMIT: usize = 100;
+/// Maximum amount of users that can be returned at once.
+const MAX_LIMIT: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct GetReactorsRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    pub message_id: u64,
+    /// Image ID of the emote of the reaction.
+    pub image_id: String,
+    /// `next_cursor` of the previous page.
+    #[serde(default)]
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetReactorsResponse {
+    /// IDs of the users who reacted, in ascending order.
+    pub user_ids: Vec<u64>,
+    /// Cursor to get the next page with, not set if this is the last page.
+    pub next_cursor: Option<String>,
+}
+
+/// Gets who reacted to a message with an emote.
+#[require_perms(guild, channel, "messages.view")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetReactorsRequest,
+) -> ServerResult<GetReactorsResponse> {
+    let GetReactorsRequest {
+        guild_id,
+        channel_id,
+        message_id,
+        image_id,
+        cursor,
+        limit,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree
+        .get_message_logic(guild_id, channel_id, message_id)
+        .await?;
+
+    let filter = [
+        guild_id.to_be_bytes().as_ref(),
+        &channel_id.to_be_bytes(),
+        &message_id.to_be_bytes(),
+        image_id.as_bytes(),
+    ]
+    .concat();
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let pager = Pager::new(Direction::Forward, filter, cursor.as_deref(), limit)?;
+    let page = chat_tree
+        .get_reactors_logic(guild_id, channel_id, message_id, &image_id, &pager)
+        .await?;
+
+    Ok(GetReactorsResponse {
+        user_ids: page.items,
+        next_cursor: page.next_cursor,
+    })
+}
@@ -0,0 +1,56 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct GetScheduledMessagesRequest {
+    pub guild_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScheduledMessageInfo {
+    pub scheduled_id: u64,
+    pub channel_id: u64,
+    pub text: String,
+    pub in_reply_to: Option<u64>,
+    /// When the message will be sent, in seconds since unix epoch.
+    pub scheduled_for: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetScheduledMessagesResponse {
+    /// Messages the user scheduled in the guild, earliest first.
+    pub messages: Vec<ScheduledMessageInfo>,
+}
+
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetScheduledMessagesRequest,
+) -> ServerResult<GetScheduledMessagesResponse> {
+    let GetScheduledMessagesRequest { guild_id } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree.check_guild_user(guild_id, user_id).await?;
+
+    let messages = chat_tree
+        .get_scheduled_messages_logic(u64::MAX)
+        .await?
+        .into_iter()
+        .filter(|(_, _, _, scheduled)| {
+            scheduled.author_id == user_id && scheduled.guild_id == guild_id
+        })
+        .map(
+            |(_, scheduled_for, scheduled_id, scheduled)| ScheduledMessageInfo {
+                scheduled_id,
+                channel_id: scheduled.channel_id,
+                text: scheduled.text,
+                in_reply_to: scheduled.in_reply_to,
+                scheduled_for,
+            },
+        )
+        .collect();
+
+    Ok(GetScheduledMessagesResponse { messages })
+}
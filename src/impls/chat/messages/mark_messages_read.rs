@@ -0,0 +1,54 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct MarkMessagesReadRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    /// The latest message read. Every message before it is marked as read too.
+    pub message_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExpiringMessage {
+    pub message_id: u64,
+    /// Time the message will be deleted at, in seconds since unix epoch.
+    pub expires_at: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MarkMessagesReadResponse {
+    /// Messages whose countdown started because they were read.
+    pub expiring: Vec<ExpiringMessage>,
+}
+
+/// Marks messages of a channel as read, starting the countdown of the ones
+/// that disappear after they are read. Messages aren't counted as read by
+/// their author.
+#[require_perms(guild, channel, "messages.view")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: MarkMessagesReadRequest,
+) -> ServerResult<MarkMessagesReadResponse> {
+    let MarkMessagesReadRequest {
+        guild_id,
+        channel_id,
+        message_id,
+    } = request;
+
+    let expiring = svc
+        .deps
+        .chat_tree
+        .mark_messages_read_logic(guild_id, channel_id, user_id, message_id)
+        .await?
+        .into_iter()
+        .map(|(message_id, expires_at)| ExpiringMessage {
+            message_id,
+            expires_at,
+        })
+        .collect();
+
+    Ok(MarkMessagesReadResponse { expiring })
+}
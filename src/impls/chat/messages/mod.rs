@@ -1,12 +1,131 @@
+use std::time::{Duration, Instant};
+
+use dashmap::{mapref::entry::Entry, DashMap};
+
 use super::*;
 
+pub mod acknowledge_message;
 pub mod add_reaction;
+pub mod bind_reaction_role;
+pub mod cancel_scheduled_message;
 pub mod delete_message;
+pub mod delete_messages;
+pub mod get_acknowledgements;
 pub mod get_channel_messages;
 pub mod get_message;
+pub mod get_messages_after;
+pub mod get_pending_acknowledgements;
 pub mod get_pinned_messages;
+pub mod get_quarantined_messages;
+pub mod get_reaction_roles;
+pub mod get_reactors;
+pub mod get_scheduled_messages;
+pub mod mark_messages_read;
 pub mod pin_message;
+pub mod redact_attachment;
 pub mod remove_reaction;
+pub mod require_acknowledgement;
+pub mod review_quarantined_message;
+pub mod schedule_message;
+pub mod search_messages;
 pub mod send_message;
+pub mod unbind_reaction_role;
 pub mod unpin_message;
 pub mod update_message_text;
+
+/// A reaction role binding, in a form that can be sent over the JSON API.
+#[derive(Debug, serde::Serialize)]
+pub struct ReactionRoleInfo {
+    pub image_id: String,
+    pub role_id: u64,
+}
+
+impl From<ReactionRole> for ReactionRoleInfo {
+    fn from(binding: ReactionRole) -> Self {
+        Self {
+            image_id: binding.image_id,
+            role_id: binding.role_id,
+        }
+    }
+}
+
+/// How long an `echo_id` is remembered after the message it was sent with.
+pub const MESSAGE_NONCE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Identifies a message send by the user, channel and `echo_id` it was sent with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MessageNonce {
+    pub user_id: u64,
+    pub guild_id: u64,
+    pub channel_id: u64,
+    pub echo_id: u64,
+}
+
+enum NonceState {
+    Sending,
+    Sent { message_id: u64, since: Instant },
+}
+
+/// Recently used message nonces, so that retried sends don't duplicate messages.
+#[derive(Default)]
+pub struct MessageNonces {
+    nonces: DashMap<MessageNonce, NonceState, ahash::RandomState>,
+}
+
+impl MessageNonces {
+    /// Reserves a nonce for a message that is about to be sent.
+    ///
+    /// Returns the ID of the message that was already sent with this nonce, if any.
+    /// Fails if a message with this nonce is still being sent.
+    pub fn reserve(&self, nonce: MessageNonce) -> Result<Option<u64>, ServerError> {
+        if self.nonces.len() >= 1024 {
+            self.nonces.retain(|_, state| match state {
+                NonceState::Sending => true,
+                NonceState::Sent { since, .. } => since.elapsed() < MESSAGE_NONCE_TTL,
+            });
+        }
+
+        match self.nonces.entry(nonce) {
+            Entry::Occupied(mut entry) => match entry.get() {
+                NonceState::Sending => Err(ServerError::MessageNonceInUse),
+                NonceState::Sent { message_id, since } => {
+                    if since.elapsed() < MESSAGE_NONCE_TTL {
+                        Ok(Some(*message_id))
+                    } else {
+                        entry.insert(NonceState::Sending);
+                        Ok(None)
+                    }
+                }
+            },
+            Entry::Vacant(entry) => {
+                entry.insert(NonceState::Sending);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Marks a reserved nonce as used by the given message.
+    pub fn complete(&self, nonce: MessageNonce, message_id: u64) {
+        self.nonces.insert(
+            nonce,
+            NonceState::Sent {
+                message_id,
+                since: Instant::now(),
+            },
+        );
+    }
+
+    /// Releases a reserved nonce, so that sending can be retried with it.
+    pub fn release(&self, nonce: MessageNonce) {
+        self.nonces.remove(&nonce);
+    }
+
+    /// Returns the amount of remembered nonces, including expired ones.
+    pub fn len(&self) -> usize {
+        self.nonces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nonces.is_empty()
+    }
+}
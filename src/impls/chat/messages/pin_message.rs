@@ -1,5 +1,6 @@
 use super::*;
 
+#[require_perms(guild, channel, all_permissions::MESSAGES_PINS_ADD)]
 pub async fn handler(
     svc: &ChatServer,
     request: Request<PinMessageRequest>,
@@ -14,40 +15,16 @@ pub async fn handler(
 
     let chat_tree = &svc.deps.chat_tree;
 
-    chat_tree
-        .check_guild_user_channel(guild_id, user_id, channel_id)
-        .await?;
-
-    chat_tree
-        .check_perms(
-            guild_id,
-            Some(channel_id),
-            user_id,
-            all_permissions::MESSAGES_PINS_ADD,
-            false,
-        )
-        .await?;
-
     let key = make_pinned_msgs_key(guild_id, channel_id);
     let mut pinned_msgs_raw = chat_tree.get(key).await?.map_or_else(Vec::new, EVec::into);
     pinned_msgs_raw.extend_from_slice(&message_id.to_be_bytes());
     chat_tree.insert(key, pinned_msgs_raw).await?;
 
-    svc.send_event_through_chan(
-        EventSub::Guild(guild_id),
-        stream_event::Event::MessagePinned(stream_event::MessagePinned {
-            guild_id,
-            channel_id,
-            message_id,
-        }),
-        Some(PermCheck::new(
-            guild_id,
-            Some(channel_id),
-            all_permissions::MESSAGES_VIEW,
-            false,
-        )),
-        EventContext::empty(),
-    );
+    svc.deps.event_bus.publish(DomainEvent::MessagePinned {
+        guild_id,
+        channel_id,
+        message_id,
+    });
 
     Ok(PinMessageResponse::new().into_response())
 }
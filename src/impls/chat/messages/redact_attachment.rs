@@ -0,0 +1,108 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+use crate::impls::rest::{media_access, upload::delete_file};
+
+#[derive(Debug, Deserialize)]
+pub struct RedactAttachmentRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    pub message_id: u64,
+    /// ID of the attachment or photo to remove from the message.
+    pub file_id: String,
+    /// Whether to also delete the file from this server. The file must be
+    /// uploaded to this server, and is kept if another message still has it.
+    #[serde(default)]
+    pub delete_media: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RedactAttachmentResponse {}
+
+/// Removes an attachment or a photo from a message, without deleting the whole message.
+///
+/// This emits an `EditedMessage` event without new content, clients should
+/// fetch the message again to see the new content.
+#[require_perms(guild, channel, "messages.manage.delete")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: RedactAttachmentRequest,
+) -> ServerResult<RedactAttachmentResponse> {
+    let RedactAttachmentRequest {
+        guild_id,
+        channel_id,
+        message_id,
+        file_id,
+        delete_media,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let local_id = if delete_media {
+        match media_access::local_file_id(&svc.deps, &file_id) {
+            Some(id) => Some(id),
+            None => bail!(ServerError::InvalidFileId),
+        }
+    } else {
+        None
+    };
+
+    let (mut message, key) = chat_tree
+        .get_message_logic(guild_id, channel_id, message_id)
+        .await?;
+
+    let media_bytes_before = message_usage(&message).media_bytes;
+    let removed = match message.content.as_mut().and_then(|c| c.content.as_mut()) {
+        Some(content::Content::AttachmentMessage(files)) => {
+            let len = files.files.len();
+            files.files.retain(|attachment| attachment.id != file_id);
+            len != files.files.len()
+        }
+        Some(content::Content::PhotoMessage(photos)) => {
+            let len = photos.photos.len();
+            photos.photos.retain(|photo| photo.hmc != file_id);
+            len != photos.photos.len()
+        }
+        _ => false,
+    };
+    if !removed {
+        bail!((
+            "h.no-such-attachment",
+            "the message doesn't have an attachment with this ID"
+        ));
+    }
+
+    let edited_at = get_time_secs();
+    message.edited_at = Some(edited_at);
+    chat_tree.insert(key, rkyv_ser(&message)).await?;
+    if !is_media_message(&message) {
+        chat_tree
+            .remove(make_gallery_entry_key(guild_id, channel_id, message_id))
+            .await?;
+    }
+    let usage_delta = GuildUsage {
+        media_bytes: media_bytes_before - message_usage(&message).media_bytes,
+        ..Default::default()
+    };
+    chat_tree
+        .update_guild_usage_logic(guild_id, usage_delta, false)
+        .await?;
+
+    if let Some(id) = local_id {
+        if !media_access::is_posted_elsewhere(&svc.deps, &id, &key).await? {
+            delete_file(&svc.deps, &id).await?;
+        }
+    }
+
+    svc.deps.event_bus.publish(DomainEvent::MessageUpdated {
+        guild_id,
+        channel_id,
+        message_id,
+        edited_at,
+        new_content: None,
+    });
+
+    Ok(RedactAttachmentResponse {})
+}
@@ -26,11 +26,27 @@ pub async fn handler(
             )
             .await?;
 
+        let image_id = emote.image_id.clone();
         let reaction = chat_tree
-            .update_reaction(user_id, guild_id, channel_id, message_id, emote, false)
+            .update_reaction(
+                user_id,
+                guild_id,
+                channel_id,
+                message_id,
+                emote,
+                false,
+                svc.deps.config.policy.max_reactions_per_message,
+            )
             .await?;
         if reaction.is_some() {
-            svc.send_reaction_event(guild_id, channel_id, message_id, reaction);
+            svc.deps.event_bus.publish(DomainEvent::ReactionUpdated {
+                guild_id,
+                channel_id,
+                message_id,
+                reaction,
+            });
+            svc.update_reaction_role(user_id, guild_id, channel_id, message_id, &image_id, false)
+                .await?;
         }
     }
 
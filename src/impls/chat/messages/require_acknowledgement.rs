@@ -0,0 +1,43 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct RequireAcknowledgementRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    pub message_id: u64,
+    /// Whether members must acknowledge the message. Unsetting this removes
+    /// the acknowledgements already given.
+    pub required: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RequireAcknowledgementResponse {}
+
+/// Makes members need to acknowledge a message, like an announcement, or not.
+#[require_perms(guild, channel, "messages.acknowledgements.manage")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: RequireAcknowledgementRequest,
+) -> ServerResult<RequireAcknowledgementResponse> {
+    let RequireAcknowledgementRequest {
+        guild_id,
+        channel_id,
+        message_id,
+        required,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree
+        .get_message_logic(guild_id, channel_id, message_id)
+        .await?;
+
+    chat_tree
+        .set_ack_required_logic(guild_id, channel_id, message_id, required)
+        .await?;
+
+    Ok(RequireAcknowledgementResponse {})
+}
@@ -0,0 +1,71 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+use super::delete_message::delete_message_logic;
+
+#[derive(Debug, Deserialize)]
+pub struct ReviewQuarantinedMessageRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    pub message_id: u64,
+    /// Whether to show the message to everyone, or delete it.
+    pub approve: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReviewQuarantinedMessageResponse {}
+
+#[require_perms(guild, channel, "messages.quarantine.review")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: ReviewQuarantinedMessageRequest,
+) -> ServerResult<ReviewQuarantinedMessageResponse> {
+    let ReviewQuarantinedMessageRequest {
+        guild_id,
+        channel_id,
+        message_id,
+        approve,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let key = make_quarantine_key(guild_id, channel_id, message_id);
+    if !chat_tree.contains_key(key).await? {
+        bail!((
+            "h.message-not-quarantined",
+            "this message is not waiting for review"
+        ));
+    }
+    let (message, _) = chat_tree
+        .get_message_logic(guild_id, channel_id, message_id)
+        .await?;
+    let author_id = message.author_id;
+
+    let action = if approve {
+        chat_tree.remove(key).await?;
+        svc.deps.event_bus.publish(DomainEvent::MessageSent {
+            guild_id,
+            channel_id,
+            message_id,
+            echo_id: None,
+            message,
+        });
+        AuditAction::QuarantinedMessageApproved {
+            channel_id,
+            message_id,
+            author_id,
+        }
+    } else {
+        delete_message_logic(svc, guild_id, channel_id, message_id, message).await?;
+        AuditAction::QuarantinedMessageDeleted {
+            channel_id,
+            message_id,
+            author_id,
+        }
+    };
+    audit::record(&svc.deps, guild_id, user_id, action).await?;
+
+    Ok(ReviewQuarantinedMessageResponse {})
+}
@@ -0,0 +1,141 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+use crate::impls::gen_rand_u64;
+
+use super::send_message::send_message;
+
+/// How far in the future a message can be scheduled, in seconds.
+const MAX_SCHEDULE_AHEAD: u64 = 60 * 60 * 24 * 365;
+/// How many messages a user can have scheduled at once.
+const MAX_SCHEDULED_PER_USER: usize = 50;
+/// How often due scheduled messages are checked for.
+pub const SCHEDULED_MESSAGE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Deserialize)]
+pub struct ScheduleMessageRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    pub text: String,
+    #[serde(default)]
+    pub in_reply_to: Option<u64>,
+    /// When to send the message, in seconds since unix epoch.
+    pub scheduled_for: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScheduleMessageResponse {
+    pub scheduled_id: u64,
+}
+
+// permissions are checked again when the message is sent, but failing
+// early is better than the message silently not being sent
+#[require_perms(guild, channel, "messages.send")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: ScheduleMessageRequest,
+) -> ServerResult<ScheduleMessageResponse> {
+    let ScheduleMessageRequest {
+        guild_id,
+        channel_id,
+        text,
+        in_reply_to,
+        scheduled_for,
+    } = request;
+
+    if text.is_empty() {
+        bail!(ServerError::MessageContentCantBeEmpty);
+    }
+    let now = get_time_secs();
+    if scheduled_for <= now || scheduled_for > now + MAX_SCHEDULE_AHEAD {
+        bail!((
+            "h.invalid-schedule-time",
+            "messages must be scheduled for a time in the future, at most a year from now"
+        ));
+    }
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    if chat_tree.is_gallery_channel(guild_id, channel_id).await? {
+        bail!(ServerError::GalleryMediaOnly);
+    }
+
+    let scheduled_count = chat_tree
+        .get_scheduled_messages_logic(u64::MAX)
+        .await?
+        .into_iter()
+        .filter(|(_, _, _, scheduled)| scheduled.author_id == user_id)
+        .count();
+    if scheduled_count >= MAX_SCHEDULED_PER_USER {
+        bail!((
+            "h.too-many-scheduled-messages",
+            format!(
+                "you can't have more than {} messages scheduled",
+                MAX_SCHEDULED_PER_USER
+            )
+        ));
+    }
+
+    let scheduled_id = gen_rand_u64();
+    let scheduled = ScheduledMessage {
+        guild_id,
+        channel_id,
+        author_id: user_id,
+        text,
+        in_reply_to,
+    };
+    chat_tree
+        .insert(
+            make_scheduled_msg_key(scheduled_for, scheduled_id),
+            rkyv_ser(&scheduled),
+        )
+        .await?;
+
+    Ok(ScheduleMessageResponse { scheduled_id })
+}
+
+/// Sends all scheduled messages that are due.
+///
+/// Messages are removed before they are sent, so a message that fails to send
+/// (for example because its author left the guild) is dropped instead of retried.
+pub async fn dispatch_due_messages(svc: &ChatServer) -> ServerResult<()> {
+    let chat_tree = &svc.deps.chat_tree;
+
+    let due = chat_tree
+        .get_scheduled_messages_logic(get_time_secs())
+        .await?;
+    for (key, _, scheduled_id, scheduled) in due {
+        chat_tree.remove(key).await?;
+
+        let ScheduledMessage {
+            guild_id,
+            channel_id,
+            author_id,
+            text,
+            in_reply_to,
+        } = scheduled;
+        let request = SendMessageRequest {
+            guild_id,
+            channel_id,
+            content: Some(Content {
+                content: Some(content::Content::TextMessage(content::TextContent {
+                    content: Some(FormattedText::new(text, Vec::new())),
+                })),
+            }),
+            in_reply_to,
+            ..Default::default()
+        };
+        if let Err(err) = send_message(svc, author_id, request).await {
+            tracing::warn!(
+                "couldn't send scheduled message {} of user {}: {}",
+                scheduled_id,
+                author_id,
+                err
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,117 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+use crate::impls::search::{searchable_text, SearchHit, SearchQuery};
+
+/// Default amount of messages returned.
+const DEFAULT_LIMIT: u64 = 25;
+/// Maximum amount of messages that can be returned at once.
+const MAX_LIMIT: u64 = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct SearchMessagesRequest {
+    pub guild_id: u64,
+    /// Only search in this channel. Searches every channel the user can view if not set.
+    #[serde(default)]
+    pub channel_id: Option<u64>,
+    pub query: String,
+    #[serde(default)]
+    pub limit: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FoundMessage {
+    pub channel_id: u64,
+    pub message_id: u64,
+    pub author_id: u64,
+    pub created_at: u64,
+    pub text: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchMessagesResponse {
+    /// Found messages, best matches first.
+    pub messages: Vec<FoundMessage>,
+}
+
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: SearchMessagesRequest,
+) -> ServerResult<SearchMessagesResponse> {
+    let SearchMessagesRequest {
+        guild_id,
+        channel_id,
+        query,
+        limit,
+    } = request;
+
+    let Some(search) = svc.deps.search.as_deref() else {
+        bail!(ServerError::SearchDisabled);
+    };
+    if query.trim().is_empty() {
+        bail!(ServerError::InvalidSearchQuery);
+    }
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree.check_guild_user(guild_id, user_id).await?;
+    let channel_ids = match channel_id {
+        Some(channel_id) => {
+            chat_tree
+                .check_guild_user_channel(guild_id, user_id, channel_id)
+                .await?;
+            chat_tree
+                .check_perms(guild_id, Some(channel_id), user_id, "messages.view", false)
+                .await?;
+            vec![channel_id]
+        }
+        None => chat_tree
+            .get_guild_channels_logic(guild_id, user_id)
+            .await?
+            .channels
+            .into_iter()
+            .map(|channel| channel.channel_id)
+            .collect(),
+    };
+
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let hits = search
+        .search(&SearchQuery {
+            guild_id,
+            channel_ids,
+            text: query,
+            limit: limit as usize,
+        })
+        .await?;
+
+    let mut messages = Vec::with_capacity(hits.len());
+    for SearchHit {
+        channel_id,
+        message_id,
+        ..
+    } in hits
+    {
+        // the index can be behind the database, so skip anything that's gone
+        let Some(raw) = chat_tree
+            .get(make_msg_key(guild_id, channel_id, message_id))
+            .await?
+        else {
+            continue;
+        };
+        let message = db::deser_message(raw);
+        let Some(text) = searchable_text(message.content.as_ref()) else {
+            continue;
+        };
+        messages.push(FoundMessage {
+            channel_id,
+            message_id,
+            author_id: message.author_id,
+            created_at: message.created_at,
+            text: text.to_string(),
+        });
+    }
+
+    Ok(SearchMessagesResponse { messages })
+}
@@ -6,7 +6,40 @@ pub async fn handler(
 ) -> ServerResult<Response<SendMessageResponse>> {
     let user_id = svc.deps.valid_sessions.auth(&request)?;
 
-    let mut request = request.into_message().await?;
+    let request = request.into_message().await?;
+
+    // If the client gave an echo id, a retry of a send that already went through
+    // gets the original message instead of a duplicate
+    let nonce = request.echo_id.map(|echo_id| MessageNonce {
+        user_id,
+        guild_id: request.guild_id,
+        channel_id: request.channel_id,
+        echo_id,
+    });
+    let nonces = &svc.deps.message_nonces;
+    if let Some(nonce) = nonce {
+        if let Some(message_id) = nonces.reserve(nonce)? {
+            return Ok((SendMessageResponse { message_id }).into_response());
+        }
+    }
+
+    let result = send_message(svc, user_id, request).await;
+    if let Some(nonce) = nonce {
+        match &result {
+            Ok(message_id) => nonces.complete(nonce, *message_id),
+            Err(_) => nonces.release(nonce),
+        }
+    }
+
+    result.map(|message_id| (SendMessageResponse { message_id }).into_response())
+}
+
+/// Sends a message as the user, checking that they are allowed to send it.
+pub async fn send_message(
+    svc: &ChatServer,
+    user_id: u64,
+    mut request: SendMessageRequest,
+) -> ServerResult<u64> {
     let guild_id = request.guild_id;
     let channel_id = request.channel_id;
     let echo_id = request.echo_id;
@@ -19,17 +52,71 @@ pub async fn handler(
     chat_tree
         .check_perms(guild_id, Some(channel_id), user_id, "messages.send", false)
         .await?;
+    if chat_tree.is_category_channel(guild_id, channel_id).await? {
+        bail!(ServerError::CategoryHasNoMessages);
+    }
+    let is_media = matches!(
+        request.content.as_ref().and_then(|c| c.content.as_ref()),
+        Some(content::Content::AttachmentMessage(_) | content::Content::PhotoMessage(_))
+    );
+    chat_tree
+        .check_can_post_in_channel(guild_id, channel_id, user_id, is_media)
+        .await?;
 
     chat_tree.process_message_overrides(request.overrides.as_ref())?;
-    let content = chat_tree
+    let mut content = chat_tree
         .process_message_content(
             request.content.take(),
-            svc.deps.config.media.media_root.as_path(),
+            svc.deps.media_store.as_ref(),
             &svc.deps.config.host,
         )
         .await?;
+    chat_tree
+        .expand_text_macro_logic(guild_id, &mut content)
+        .await?;
+    if let Some(content::Content::TextMessage(content::TextContent {
+        content: Some(text),
+    })) = &mut content.content
+    {
+        svc.deps.profile_tree.resolve_mentions_logic(text).await?;
+    }
+    let text = match &content.content {
+        Some(content::Content::TextMessage(content::TextContent {
+            content: Some(FormattedText { text, .. }),
+        })) => Some(text.clone()),
+        _ => None,
+    };
+    let flagged_domain = match &text {
+        Some(text) => svc.scan_message_links(guild_id, text, true).await?,
+        None => None,
+    };
     request.content = Some(content);
     let (message_id, message) = chat_tree.send_message_logic(user_id, request).await?;
+    if let Some(content) = &message.content {
+        media_access::record_posted(&svc.deps, guild_id, channel_id, content).await?;
+    }
+    svc.deps
+        .typing_indicators
+        .stop(guild_id, channel_id, user_id);
+    presence::user_active(&svc.deps, user_id).await?;
+
+    if let (Some(domain), Some(text)) = (flagged_domain, text) {
+        if svc.deps.config.policy.domain_blocklist.action == BlockedDomainAction::Quarantine {
+            svc.quarantine_message(guild_id, channel_id, message_id, domain)
+                .await?;
+            svc.deps.event_bus.publish(DomainEvent::MessageQuarantined {
+                guild_id,
+                channel_id,
+                message_id,
+                echo_id,
+                message,
+            });
+            return Ok(message_id);
+        }
+
+        svc.flag_message_link(guild_id, channel_id, message_id, user_id, text, domain)
+            .await?;
+    }
 
     let is_cmd_channel = chat_tree
         .admin_guild_keys
@@ -41,7 +128,7 @@ pub async fn handler(
             content: Some(FormattedText { text, .. }),
         })) = message.content.as_ref().and_then(|c| c.content.as_ref())
         {
-            svc.deps.action_processor.run(text).await.ok()
+            svc.deps.action_processor.run(&svc.deps, text).await.ok()
         } else {
             None
         }
@@ -49,23 +136,13 @@ pub async fn handler(
         None
     };
 
-    svc.send_event_through_chan(
-        EventSub::Guild(guild_id),
-        stream_event::Event::SentMessage(Box::new(stream_event::MessageSent {
-            echo_id,
-            guild_id,
-            channel_id,
-            message_id,
-            message: Some(message),
-        })),
-        Some(PermCheck::new(
-            guild_id,
-            Some(channel_id),
-            "messages.view",
-            false,
-        )),
-        EventContext::empty(),
-    );
+    svc.deps.event_bus.publish(DomainEvent::MessageSent {
+        guild_id,
+        channel_id,
+        message_id,
+        echo_id,
+        message,
+    });
 
     if let Some(msg) = action_content {
         let content = content::Content::TextMessage(content::TextContent {
@@ -74,24 +151,80 @@ pub async fn handler(
         let (message_id, message) = chat_tree
             .send_with_system(guild_id, channel_id, content)
             .await?;
-        svc.send_event_through_chan(
-            EventSub::Guild(guild_id),
-            stream_event::Event::SentMessage(Box::new(stream_event::MessageSent {
-                echo_id,
-                guild_id,
-                channel_id,
-                message_id,
-                message: Some(message),
-            })),
-            Some(PermCheck::new(
-                guild_id,
-                Some(channel_id),
-                "messages.view",
-                false,
-            )),
-            EventContext::empty(),
-        );
+        svc.deps.event_bus.publish(DomainEvent::MessageSent {
+            guild_id,
+            channel_id,
+            message_id,
+            echo_id,
+            message,
+        });
     }
 
-    Ok((SendMessageResponse { message_id }).into_response())
+    Ok(message_id)
+}
+
+#[cfg(all(test, feature = "sled"))]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn concurrent_sends_are_written_with_their_ids() {
+        let db = crate::db::open_temp();
+        let chat_tree = ChatTree::new(&db).await.unwrap();
+        let guild_id = chat_tree
+            .create_guild_logic(
+                1,
+                "test".to_string(),
+                None,
+                None,
+                guild_kind::Kind::new_normal(guild_kind::Normal::new()),
+            )
+            .await
+            .unwrap();
+        let channel_id = chat_tree
+            .get_guild_channel_ids_logic(guild_id)
+            .await
+            .unwrap()[0];
+
+        let sends = (0..16)
+            .map(|n| {
+                let chat_tree = chat_tree.clone();
+                tokio::spawn(async move {
+                    let content = content::Content::TextMessage(content::TextContent {
+                        content: Some(FormattedText::new(n.to_string(), Vec::new())),
+                    });
+                    chat_tree
+                        .send_with_system(guild_id, channel_id, content)
+                        .await
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut message_ids = Vec::new();
+        for send in sends {
+            let (message_id, _) = send.await.unwrap().unwrap();
+            assert!(chat_tree
+                .contains_key(make_msg_key(guild_id, channel_id, message_id))
+                .await
+                .unwrap());
+            message_ids.push(message_id);
+        }
+        message_ids.sort_unstable();
+        message_ids.dedup();
+        assert_eq!(message_ids.len(), 16);
+        assert_eq!(
+            chat_tree
+                .get_last_message_id(guild_id, channel_id)
+                .await
+                .unwrap(),
+            message_ids[15] + 1
+        );
+        assert_eq!(
+            chat_tree
+                .get_guild_usage_logic(guild_id)
+                .await
+                .unwrap()
+                .message_count,
+            16
+        );
+    }
 }
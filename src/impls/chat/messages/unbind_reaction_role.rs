@@ -0,0 +1,47 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct UnbindReactionRoleRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    pub message_id: u64,
+    /// Image ID of the emote to remove the binding of.
+    pub image_id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnbindReactionRoleResponse {}
+
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: UnbindReactionRoleRequest,
+) -> ServerResult<UnbindReactionRoleResponse> {
+    let UnbindReactionRoleRequest {
+        guild_id,
+        channel_id,
+        message_id,
+        image_id,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree
+        .check_guild_user_channel(guild_id, user_id, channel_id)
+        .await?;
+    chat_tree
+        .check_perms(guild_id, None, user_id, "roles.user.manage", false)
+        .await?;
+
+    let mut bindings = chat_tree
+        .get_reaction_roles_logic(guild_id, channel_id, message_id)
+        .await?;
+    bindings.retain(|b| b.image_id != image_id);
+    chat_tree
+        .put_reaction_roles_logic(guild_id, channel_id, message_id, bindings)
+        .await?;
+
+    Ok(UnbindReactionRoleResponse {})
+}
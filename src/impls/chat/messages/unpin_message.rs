@@ -1,5 +1,6 @@
 use super::*;
 
+#[require_perms(guild, channel, all_permissions::MESSAGES_PINS_REMOVE)]
 pub async fn handler(
     svc: &ChatServer,
     request: Request<UnpinMessageRequest>,
@@ -14,20 +15,6 @@ pub async fn handler(
 
     let chat_tree = &svc.deps.chat_tree;
 
-    chat_tree
-        .check_guild_user_channel(guild_id, user_id, channel_id)
-        .await?;
-
-    chat_tree
-        .check_perms(
-            guild_id,
-            Some(channel_id),
-            user_id,
-            all_permissions::MESSAGES_PINS_REMOVE,
-            false,
-        )
-        .await?;
-
     let mut pinned_message_ids = chat_tree
         .get_pinned_messages_logic(guild_id, channel_id)
         .await?;
@@ -39,21 +26,11 @@ pub async fn handler(
         .insert(make_pinned_msgs_key(guild_id, channel_id), pinned_msgs_raw)
         .await?;
 
-    svc.send_event_through_chan(
-        EventSub::Guild(guild_id),
-        stream_event::Event::MessageUnpinned(stream_event::MessageUnpinned {
-            guild_id,
-            channel_id,
-            message_id,
-        }),
-        Some(PermCheck::new(
-            guild_id,
-            Some(channel_id),
-            all_permissions::MESSAGES_VIEW,
-            false,
-        )),
-        EventContext::empty(),
-    );
+    svc.deps.event_bus.publish(DomainEvent::MessageUnpinned {
+        guild_id,
+        channel_id,
+        message_id,
+    });
 
     Ok(UnpinMessageResponse::new().into_response())
 }
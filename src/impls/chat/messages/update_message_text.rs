@@ -1,36 +1,39 @@
 use super::*;
 
+use sha3::Digest;
+
+use crate::db::audit::TextDiff;
+
+#[require_perms(guild, channel, "messages.send")]
 pub async fn handler(
     svc: &ChatServer,
     request: Request<UpdateMessageTextRequest>,
 ) -> ServerResult<Response<UpdateMessageTextResponse>> {
     let user_id = svc.deps.valid_sessions.auth(&request)?;
 
-    let request = request.into_message().await?;
-
     let UpdateMessageTextRequest {
         guild_id,
         channel_id,
         message_id,
-        new_content,
-    } = request;
+        mut new_content,
+    } = request.into_message().await?;
 
     let chat_tree = &svc.deps.chat_tree;
 
-    chat_tree
-        .check_guild_user_channel(guild_id, user_id, channel_id)
-        .await?;
-    chat_tree
-        .check_perms(guild_id, Some(channel_id), user_id, "messages.send", false)
-        .await?;
-
     if new_content.as_ref().map_or(true, |f| f.text.is_empty()) {
         return Err(ServerError::MessageContentCantBeEmpty.into());
     }
+    if let Some(text) = &mut new_content {
+        svc.deps.profile_tree.resolve_mentions_logic(text).await?;
+    }
 
     let key = make_msg_key(guild_id, channel_id, message_id);
     let Some(message_raw) = chat_tree.get(key).await? else {
-        bail!(ServerError::NoSuchMessage { guild_id, channel_id, message_id });
+        bail!(ServerError::NoSuchMessage {
+            guild_id,
+            channel_id,
+            message_id
+        });
     };
     let message_archived = rkyv_arch::<Message>(&message_raw);
 
@@ -41,7 +44,37 @@ pub async fn handler(
         ));
     }
 
+    if chat_tree
+        .contains_key(make_quarantine_key(guild_id, channel_id, message_id))
+        .await?
+    {
+        bail!((
+            "h.message-quarantined",
+            "quarantined messages can't be edited until they are reviewed"
+        ));
+    }
+
+    let flagged_domain = match &new_content {
+        Some(content) => {
+            svc.scan_message_links(guild_id, &content.text, false)
+                .await?
+        }
+        None => None,
+    };
+
     let mut message: Message = message_archived.deserialize(&mut rkyv::Infallible).unwrap();
+    let old_text = match message.content.as_ref().and_then(|c| c.content.as_ref()) {
+        Some(content::Content::TextMessage(content::TextContent {
+            content: Some(FormattedText { text, .. }),
+        })) => text.as_str(),
+        _ => "",
+    };
+    let diff = diff_text(
+        old_text,
+        new_content
+            .as_ref()
+            .map_or("", |content| content.text.as_str()),
+    );
 
     let msg_content = if let Some(content) = &mut message.content {
         content
@@ -59,23 +92,83 @@ pub async fn handler(
     let buf = rkyv_ser(&message);
     chat_tree.insert(key, buf).await?;
 
-    svc.send_event_through_chan(
-        EventSub::Guild(guild_id),
-        stream_event::Event::EditedMessage(Box::new(stream_event::MessageUpdated {
+    if let (Some(domain), Some(content)) = (flagged_domain, &new_content) {
+        svc.flag_message_link(
             guild_id,
             channel_id,
             message_id,
-            edited_at,
-            new_content,
-        })),
-        Some(PermCheck::new(
-            guild_id,
-            Some(channel_id),
-            "messages.view",
-            false,
-        )),
-        EventContext::empty(),
-    );
+            user_id,
+            content.text.clone(),
+            domain,
+        )
+        .await?;
+    }
+
+    audit::record(
+        &svc.deps,
+        guild_id,
+        user_id,
+        AuditAction::MessageEdited {
+            channel_id,
+            message_id,
+            diff,
+        },
+    )
+    .await?;
+
+    svc.deps.event_bus.publish(DomainEvent::MessageUpdated {
+        guild_id,
+        channel_id,
+        message_id,
+        edited_at,
+        new_content,
+    });
 
     Ok((UpdateMessageTextResponse {}).into_response())
 }
+
+/// Finds the range of characters that changed between two versions of a text,
+/// by skipping the start and end they have in common.
+fn diff_text(old: &str, new: &str) -> TextDiff {
+    let old_chars = old.chars().collect::<Vec<_>>();
+    let new_chars = new.chars().collect::<Vec<_>>();
+    let prefix = old_chars
+        .iter()
+        .zip(&new_chars)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old_chars[prefix..]
+        .iter()
+        .rev()
+        .zip(new_chars[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    TextDiff {
+        old_hash: sha3::Sha3_256::digest(old.as_bytes())[..8]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect(),
+        start: prefix as u32,
+        removed: old_chars[prefix..old_chars.len() - suffix].iter().collect(),
+        inserted: new_chars[prefix..new_chars.len() - suffix].iter().collect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn diffs_changed_range() {
+        let diff = diff_text("hello wörld!", "hello brave new wörld!");
+        assert_eq!(diff.start, 6);
+        assert_eq!(diff.removed, "");
+        assert_eq!(diff.inserted, "brave new ");
+
+        let diff = diff_text("aaa", "aa");
+        assert_eq!(diff.start, 2);
+        assert_eq!(diff.removed, "a");
+        assert_eq!(diff.inserted, "");
+    }
+}
@@ -1,8 +1,16 @@
 use std::{
-    collections::HashSet, convert::TryInto, future::Future, io::BufReader, lazy::SyncOnceCell,
-    mem::size_of, ops::Not, path::Path, str::FromStr,
+    collections::{HashMap, HashSet},
+    convert::TryInto,
+    future::Future,
+    lazy::SyncOnceCell,
+    mem::size_of,
+    ops::Not,
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
 };
 
+use dashmap::DashMap;
 use harmony_rust_sdk::api::{
     chat::{
         get_channel_messages_request::Direction, overrides::Reason, permission::has_permission,
@@ -10,7 +18,7 @@ use harmony_rust_sdk::api::{
     },
     emote::Emote,
     exports::hrpc::{server::socket::Socket, Request},
-    harmonytypes::{item_position, Empty, ItemPosition, Metadata},
+    harmonytypes::{item_position, Anything, Empty, ItemPosition, Metadata},
     rest::FileId,
     sync::{
         event::{
@@ -26,19 +34,37 @@ use rand::{Rng, SeedableRng};
 use scherzo_derive::*;
 use smol_str::SmolStr;
 use tokio::{
-    sync::{broadcast::Sender as BroadcastSend, mpsc::UnboundedSender},
+    io::AsyncReadExt,
+    sync::{
+        broadcast::{error::RecvError, Receiver as BroadcastRecv, Sender as BroadcastSend},
+        mpsc::UnboundedSender,
+    },
     task::JoinHandle,
 };
 use triomphe::Arc;
 
 use crate::{
-    db::{self, chat::*, rkyv_ser, Batch, Db, DbResult},
+    config::BlockedDomainAction,
+    db::{
+        self,
+        audit::{AuditAction, AuditPermission},
+        chat::*,
+        rkyv_ser, Batch, Db, DbResult,
+    },
     impls::{
-        get_time_secs,
+        audit,
+        bus::DomainEvent,
+        gen_rand_u64, get_time_secs,
         prelude::*,
-        rest::download::{calculate_range, get_file_full, get_file_handle, is_id_jpeg, read_bufs},
+        profile::presence,
+        rest::{
+            download::{calculate_range, get_file_full, is_id_jpeg, read_bufs},
+            media_access,
+            media_store::{reader_from_vec, MediaStore, StoredMedia},
+        },
         sync::EventDispatch,
     },
+    utils::cursor::{Page, Pager},
 };
 
 use channels::*;
@@ -47,17 +73,52 @@ use invites::*;
 use messages::*;
 use moderation::*;
 use permissions::*;
+use reports::*;
+use stage::*;
 
 pub mod channels;
+pub mod event_coalescing;
+pub mod event_replay;
 pub mod guilds;
 pub mod invites;
 pub mod messages;
 pub mod moderation;
 pub mod permissions;
+pub mod reports;
+pub mod stage;
 pub mod stream_events;
+pub mod threads;
 pub mod trigger_action;
 
 pub const DEFAULT_ROLE_ID: u64 = 0;
+/// Key of the channel metadata extension the channel topic is stored in
+pub const CHANNEL_TOPIC_EXTENSION: &str = "scherzo.topic";
+/// Key of the channel metadata extension the stage state is stored in
+pub const CHANNEL_STAGE_EXTENSION: &str = "scherzo.stage";
+/// Key of the channel metadata extension that marks a channel as a gallery
+pub const CHANNEL_GALLERY_EXTENSION: &str = "scherzo.gallery";
+/// Key of the channel metadata extension the sticky message is stored in
+pub const CHANNEL_STICKY_EXTENSION: &str = "scherzo.sticky";
+/// Key of the channel metadata extension the disappearing message settings are stored in
+pub const CHANNEL_DISAPPEARING_EXTENSION: &str = "scherzo.disappearing";
+/// Key of the guild metadata extension the custom emoji of a guild are listed in
+pub const GUILD_EMOJI_EXTENSION: &str = "scherzo.emoji";
+/// Key of the guild metadata extension the banner, splash and theme color of a guild are stored in
+pub const GUILD_THEME_EXTENSION: &str = "scherzo.theme";
+/// Key of the message metadata extension that marks a message as having a thread,
+/// with the name of the thread
+pub const MESSAGE_THREAD_EXTENSION: &str = "scherzo.thread";
+/// Key of the message metadata extension that marks a message as sent in a thread,
+/// with the ID of the message the thread was started from
+pub const THREAD_PARENT_EXTENSION: &str = "scherzo.thread-parent";
+/// Maximum amount of topic changes kept for a channel
+pub const MAX_TOPIC_HISTORY_LEN: usize = 50;
+/// Maximum length of a channel topic, in characters
+pub const MAX_TOPIC_LEN: usize = 1024;
+/// Maximum amount of uses kept in the usage history of an invite
+pub const MAX_INVITE_USE_HISTORY: usize = 1000;
+
+const NO_SUCH_THREAD_ERR: (&str, &str) = ("h.no-such-thread", "this message doesn't have a thread");
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum EventSub {
@@ -110,6 +171,9 @@ impl EventContext {
 #[derive(Debug)]
 pub struct EventBroadcast {
     sub: EventSub,
+    /// Sequence number of the broadcast among the ones of its subscription,
+    /// set when it is sent. See [`event_replay`].
+    seq: u64,
     event: Event,
     perm_check: Option<PermCheck<'static>>,
     context: EventContext,
@@ -124,6 +188,7 @@ impl EventBroadcast {
     ) -> Self {
         Self {
             sub,
+            seq: 0,
             event,
             perm_check,
             context,
@@ -134,6 +199,36 @@ impl EventBroadcast {
 pub type EventSender = BroadcastSend<Arc<EventBroadcast>>;
 pub type EventDispatcher = UnboundedSender<EventDispatch>;
 
+/// Whether a user should get a broadcast, going by who it's for and the
+/// permission it needs.
+async fn can_receive(chat_tree: &ChatTree, user_id: u64, broadcast: &EventBroadcast) -> bool {
+    if !broadcast.context.user_ids.is_empty() && !broadcast.context.user_ids.contains(&user_id) {
+        return false;
+    }
+
+    match broadcast.perm_check {
+        Some(PermCheck {
+            guild_id,
+            channel_id,
+            check_for,
+            must_be_guild_owner,
+        }) => {
+            let perm = chat_tree
+                .check_perms(
+                    guild_id,
+                    channel_id,
+                    user_id,
+                    check_for,
+                    must_be_guild_owner,
+                )
+                .await;
+
+            matches!(perm, Ok(_) | Err(ServerError::EmptyPermissionQuery))
+        }
+        None => true,
+    }
+}
+
 #[derive(Clone)]
 pub struct ChatServer {
     deps: Arc<Dependencies>,
@@ -142,10 +237,86 @@ pub struct ChatServer {
 
 impl ChatServer {
     pub fn new(deps: Arc<Dependencies>) -> Self {
-        Self {
+        let this = Self {
             disable_ratelimits: deps.config.policy.ratelimit.disable,
             deps,
-        }
+        };
+        this.spawn_scheduled_message_dispatcher();
+        this.spawn_sticky_message_rebroadcaster();
+        this.spawn_ban_list_syncer();
+        this.spawn_ban_expirer();
+        this.spawn_disappearing_message_purger();
+        tokio::spawn(subscribe_to_member_list::follow_member_changes(
+            this.deps.clone(),
+        ));
+        this
+    }
+
+    /// Spawns a task that deletes disappearing messages once they expire.
+    fn spawn_disappearing_message_purger(&self) {
+        let deps = self.deps.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(err) = set_disappearing_messages::purge_expired(&deps).await {
+                    tracing::error!("couldn't delete disappearing messages: {}", err);
+                }
+                tokio::time::sleep(set_disappearing_messages::DISAPPEARING_MESSAGE_POLL_INTERVAL)
+                    .await;
+            }
+        });
+    }
+
+    /// Spawns a task that lifts temporary bans once they expire.
+    fn spawn_ban_expirer(&self) {
+        let svc = self.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(err) = unban_user::lift_expired_bans(&svc).await {
+                    tracing::error!("couldn't lift expired bans: {}", err);
+                }
+                tokio::time::sleep(unban_user::BAN_EXPIRY_POLL_INTERVAL).await;
+            }
+        });
+    }
+
+    /// Spawns a task that applies the ban lists guilds are subscribed to.
+    fn spawn_ban_list_syncer(&self) {
+        let svc = self.clone();
+        let interval = Duration::from_secs(self.deps.config.policy.ban_lists.sync_interval);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                if let Err(err) = ban_lists::sync_all(&svc).await {
+                    tracing::error!("couldn't apply ban lists: {}", err);
+                }
+            }
+        });
+    }
+
+    /// Spawns a task that broadcasts sticky messages again once their interval passes.
+    fn spawn_sticky_message_rebroadcaster(&self) {
+        let deps = self.deps.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(err) = rebroadcast_sticky_message::rebroadcast_due(&deps).await {
+                    tracing::error!("couldn't rebroadcast sticky messages: {}", err);
+                }
+                tokio::time::sleep(rebroadcast_sticky_message::STICKY_MESSAGE_POLL_INTERVAL).await;
+            }
+        });
+    }
+
+    /// Spawns a task that sends scheduled messages once they are due.
+    fn spawn_scheduled_message_dispatcher(&self) {
+        let svc = self.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(err) = schedule_message::dispatch_due_messages(&svc).await {
+                    tracing::error!("couldn't dispatch scheduled messages: {}", err);
+                }
+                tokio::time::sleep(schedule_message::SCHEDULED_MESSAGE_POLL_INTERVAL).await;
+            }
+        });
     }
 
     pub fn batch(mut self) -> Self {
@@ -157,17 +328,34 @@ impl ChatServer {
         &self,
         user_id: u64,
         socket: Socket<StreamEventsResponse, StreamEventsRequest>,
+        mut rx: BroadcastRecv<Arc<EventBroadcast>>,
+        stream: event_replay::OpenedStream,
     ) -> JoinHandle<Result<(), HrpcError>> {
         let (mut sock_tx, mut sock_rx) = socket.split();
 
-        let mut rx = self.deps.chat_event_sender.subscribe();
         let chat_tree = self.deps.chat_tree.clone();
+        let deps = self.deps.clone();
 
         let fut = async move {
-            let mut subs = HashSet::with_hasher(ahash::RandomState::new());
+            let _stream_guard = deps.diagnostics.track_event_stream();
+            let event_replay = deps.event_bus.replay();
+            let event_replay::OpenedStream { log, replay } = stream;
             let mut failed_writes: u8 = 0;
             let mut failed_reads: u8 = 0;
 
+            for broadcast in replay {
+                let is_new = log.lock().advance(&broadcast);
+                if !is_new || !can_receive(&chat_tree, user_id, &broadcast).await {
+                    continue;
+                }
+                sock_tx
+                    .send_message(StreamEventsResponse {
+                        event: Some(broadcast.event.clone().into()),
+                    })
+                    .await?;
+                event_replay.record_sent(&log, &broadcast);
+            }
+
             loop {
                 tokio::select! {
                     res = sock_rx.receive_message() => {
@@ -190,6 +378,9 @@ impl ChatServer {
                                 }
                             }
                         };
+                        if let Err(err) = presence::user_active(&deps, user_id).await {
+                            tracing::error!("couldn't update presence of user {}: {}", user_id, err);
+                        }
                         if let Some(req) = req.request {
                             use stream_events_request::*;
 
@@ -211,41 +402,32 @@ impl ChatServer {
                                 }
                             };
 
-                            subs.insert(sub);
+                            event_replay.subscribe(&log, sub);
                         }
                     }
-                    Ok(broadcast) = rx.recv() => {
-                        tracing::debug!("received event");
+                    res = rx.recv() => {
+                        let broadcast = match res {
+                            Ok(broadcast) => broadcast,
+                            Err(RecvError::Lagged(skipped)) => {
+                                tracing::warn!("event stream lagged behind, skipped {} events", skipped);
+                                deps.diagnostics.lagged_chat_events.fetch_add(skipped, Ordering::Relaxed);
+                                deps.diagnostics.lag_occurrences.fetch_add(1, Ordering::Relaxed);
+                                continue;
+                            }
+                            // the sender is only dropped when the server is shutting down
+                            Err(RecvError::Closed) => return Ok(()),
+                        };
 
-                        if !subs.contains(&broadcast.sub) {
-                            continue;
-                        }
+                        tracing::debug!("received event");
 
-                        if !broadcast.context.user_ids.is_empty() && !broadcast.context.user_ids.contains(&user_id) {
-                            continue;
+                        if log.lock().is_superseded() {
+                            tracing::debug!("event stream was resumed by another stream");
+                            return Ok(());
                         }
 
-                        let perm_check = match broadcast.perm_check {
-                            Some(PermCheck {
-                                guild_id,
-                                channel_id,
-                                check_for,
-                                must_be_guild_owner,
-                            }) => {
-                                let perm = chat_tree.check_perms(
-                                    guild_id,
-                                    channel_id,
-                                    user_id,
-                                    check_for,
-                                    must_be_guild_owner,
-                                ).await;
-
-                                matches!(perm, Ok(_) | Err(ServerError::EmptyPermissionQuery))
-                            }
-                            None => true,
-                        };
-
-                        if !perm_check {
+                        // the lock must not be held across the permission check
+                        let is_new = log.lock().advance(&broadcast);
+                        if !is_new || !can_receive(&chat_tree, user_id, &broadcast).await {
                             continue;
                         }
 
@@ -258,7 +440,10 @@ impl ChatServer {
                             .await;
 
                         match write_res {
-                            Ok(_) => failed_writes = 0,
+                            Ok(_) => {
+                                failed_writes = 0;
+                                event_replay.record_sent(&log, &broadcast);
+                            }
                             Err(err) => {
                                 tracing::error!(
                                     "couldnt write to stream events socket: {}",
@@ -282,29 +467,6 @@ impl ChatServer {
         tokio::spawn(fut)
     }
 
-    #[inline(always)]
-    fn send_event_through_chan(
-        &self,
-        sub: EventSub,
-        event: stream_event::Event,
-        perm_check: Option<PermCheck<'static>>,
-        context: EventContext,
-    ) {
-        let broadcast = EventBroadcast {
-            sub,
-            event: Event::Chat(event),
-            perm_check,
-            context,
-        };
-
-        tracing::debug!(
-            "broadcasting events to {} receivers",
-            self.deps.chat_event_sender.receiver_count()
-        );
-
-        drop(self.deps.chat_event_sender.send(Arc::new(broadcast)));
-    }
-
     #[inline(always)]
     fn dispatch_event(&self, target: SmolStr, event: DispatchKind) {
         let dispatch = EventDispatch {
@@ -328,15 +490,9 @@ impl ChatServer {
                     .chat_tree
                     .remove_guild_from_guild_list(user_id, guild_id, "")
                     .await?;
-                self.send_event_through_chan(
-                    EventSub::Homeserver,
-                    stream_event::Event::GuildRemovedFromList(stream_event::GuildRemovedFromList {
-                        guild_id,
-                        homeserver: String::new(),
-                    }),
-                    None,
-                    EventContext::new(vec![user_id]),
-                );
+                self.deps
+                    .event_bus
+                    .publish(DomainEvent::GuildRemovedFromList { guild_id, user_id });
             }
         }
         Ok(())
@@ -356,43 +512,159 @@ impl ChatServer {
                     .chat_tree
                     .add_guild_to_guild_list(user_id, guild_id, "")
                     .await?;
-                self.send_event_through_chan(
-                    EventSub::Homeserver,
-                    stream_event::Event::GuildAddedToList(stream_event::GuildAddedToList {
-                        guild_id,
-                        homeserver: String::new(),
-                    }),
-                    None,
-                    EventContext::new(vec![user_id]),
-                );
+                self.deps
+                    .event_bus
+                    .publish(DomainEvent::GuildAddedToList { guild_id, user_id });
             }
         }
         Ok(())
     }
 
-    fn send_reaction_event(
+    /// Checks the text of a message against the domain blocklist, unless the guild opted out.
+    ///
+    /// Fails if the message must be rejected, and returns the blocked domain
+    /// if the message should be flagged or quarantined after it is sent.
+    /// Messages that can't be quarantined are rejected instead.
+    async fn scan_message_links(
+        &self,
+        guild_id: u64,
+        text: &str,
+        can_quarantine: bool,
+    ) -> ServerResult<Option<SmolStr>> {
+        let blocklist = &self.deps.domain_blocklist;
+        if blocklist.is_empty()
+            || !self
+                .deps
+                .chat_tree
+                .is_link_scanning_enabled(guild_id)
+                .await?
+        {
+            return Ok(None);
+        }
+
+        match blocklist.find_blocked_in_text(text) {
+            Some(domain) => match self.deps.config.policy.domain_blocklist.action {
+                BlockedDomainAction::Quarantine if can_quarantine => Ok(Some(domain)),
+                BlockedDomainAction::Reject | BlockedDomainAction::Quarantine => {
+                    blocklist.blocked_messages.fetch_add(1, Ordering::Relaxed);
+                    bail!(ServerError::BlockedDomain(domain));
+                }
+                BlockedDomainAction::Flag => Ok(Some(domain)),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Reports a message linking to a blocked domain to the homeserver admins.
+    async fn flag_message_link(
         &self,
         guild_id: u64,
         channel_id: u64,
         message_id: u64,
-        reaction: Option<Reaction>,
-    ) {
-        self.send_event_through_chan(
-            EventSub::Guild(guild_id),
-            stream_event::Event::ReactionUpdated(stream_event::ReactionUpdated {
-                guild_id,
-                channel_id,
-                message_id,
-                reaction,
-            }),
-            Some(PermCheck {
-                guild_id,
-                channel_id: Some(channel_id),
-                check_for: all_permissions::MESSAGES_VIEW,
-                must_be_guild_owner: false,
-            }),
-            EventContext::empty(),
-        );
+        author_id: u64,
+        text: String,
+        domain: SmolStr,
+    ) -> ServerResult<()> {
+        self.deps
+            .domain_blocklist
+            .flagged_messages
+            .fetch_add(1, Ordering::Relaxed);
+
+        let now = get_time_secs();
+        let report = EscalatedReport {
+            guild_id,
+            channel_id,
+            message_id,
+            author_id,
+            content: text,
+            reason: format!("message links to blocked domain {}", domain),
+            escalated_by: 0,
+            escalated_at: now,
+            state: ReportState::Open,
+            assignee: None,
+            resolution_note: None,
+            updated_at: now,
+        };
+        self.deps.chat_tree.add_report_logic(&report).await?;
+
+        Ok(())
+    }
+
+    /// Holds a message back for review by the guild's moderators.
+    async fn quarantine_message(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+        domain: SmolStr,
+    ) -> ServerResult<()> {
+        self.deps
+            .domain_blocklist
+            .quarantined_messages
+            .fetch_add(1, Ordering::Relaxed);
+
+        let quarantined = QuarantinedMessage {
+            reason: format!("message links to blocked domain {}", domain),
+            quarantined_at: get_time_secs(),
+        };
+        self.deps
+            .chat_tree
+            .insert(
+                make_quarantine_key(guild_id, channel_id, message_id),
+                rkyv_ser(&quarantined),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Gives or takes the role bound to the given emote on a message, if there is one
+    async fn update_reaction_role(
+        &self,
+        user_id: u64,
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+        image_id: &str,
+        add: bool,
+    ) -> ServerResult<()> {
+        let chat_tree = &self.deps.chat_tree;
+
+        let maybe_role_id = chat_tree
+            .get_reaction_roles_logic(guild_id, channel_id, message_id)
+            .await?
+            .into_iter()
+            .find(|binding| binding.image_id == image_id)
+            .map(|binding| binding.role_id);
+        let role_id = match maybe_role_id {
+            Some(role_id) => role_id,
+            None => return Ok(()),
+        };
+
+        let has_role = chat_tree
+            .get_user_roles_logic(guild_id, user_id)
+            .await?
+            .contains(&role_id);
+        if has_role == add {
+            return Ok(());
+        }
+
+        let (give_role_ids, take_role_ids) = if add {
+            (vec![role_id], Vec::new())
+        } else {
+            (Vec::new(), vec![role_id])
+        };
+        let new_role_ids = chat_tree
+            .manage_user_roles_logic(guild_id, user_id, give_role_ids, take_role_ids)
+            .await?;
+
+        self.deps.event_bus.publish(DomainEvent::UserRolesUpdated {
+            guild_id,
+            user_id,
+            new_role_ids,
+        });
+
+        Ok(())
     }
 }
 
@@ -524,12 +796,32 @@ impl AdminGuildKeys {
     }
 }
 
+/// Lock of a channel's message IDs. A `std` `Arc`, so it can be told when
+/// nobody else holds it anymore.
+type MessageIdLock = std::sync::Arc<tokio::sync::Mutex<()>>;
+
 #[derive(Clone)]
 pub struct ChatTree {
     pub chat_tree: Tree,
     pub admin_guild_keys: SyncOnceCell<AdminGuildKeys>,
+    /// Serializes message ID allocation per channel, so that IDs stay strictly increasing in a
+    /// channel and messages are written along with the IDs they were given
+    message_id_locks: Arc<DashMap<(u64, u64), MessageIdLock, ahash::RandomState>>,
+    /// Serializes reaction updates, so that concurrent reactions to a message don't get lost
+    reaction_lock: Arc<tokio::sync::Mutex<()>>,
+    /// Serializes thread updates, so that thread message IDs are unique and counts stay correct
+    thread_lock: Arc<tokio::sync::Mutex<()>>,
+    /// Permissions of roles by guild, channel and role, so that checking permissions doesn't
+    /// scan the database every time
+    permission_cache: Arc<DashMap<PermissionCacheKey, Vec<(SmolStr, bool)>, ahash::RandomState>>,
+    /// Bumped every time cached permissions are evicted, so that permissions read before a change
+    /// aren't cached after it
+    permission_cache_generation: Arc<AtomicU64>,
 }
 
+/// Guild, channel and role ID of cached permissions. Guild permissions have no channel ID.
+type PermissionCacheKey = (u64, Option<u64>, u64);
+
 impl ChatTree {
     impl_db_methods!(chat_tree);
 
@@ -538,9 +830,36 @@ impl ChatTree {
         Ok(Self {
             chat_tree,
             admin_guild_keys: SyncOnceCell::new(),
+            message_id_locks: Arc::new(DashMap::default()),
+            reaction_lock: Arc::new(tokio::sync::Mutex::new(())),
+            thread_lock: Arc::new(tokio::sync::Mutex::new(())),
+            permission_cache: Arc::new(DashMap::default()),
+            permission_cache_generation: Arc::new(AtomicU64::new(0)),
         })
     }
 
+    /// Evicts cached permissions that match, after they were changed in the database.
+    fn evict_cached_permissions(&self, matches: impl Fn(&PermissionCacheKey) -> bool) {
+        self.permission_cache_generation
+            .fetch_add(1, Ordering::SeqCst);
+        self.permission_cache.retain(|key, _| !matches(key));
+    }
+
+    /// Evicts cached permissions of a guild, after it was deleted.
+    pub fn evict_guild_permissions(&self, guild_id: u64) {
+        self.evict_cached_permissions(|(gid, _, _)| *gid == guild_id);
+    }
+
+    /// Evicts cached permissions of a channel, after it was deleted.
+    pub fn evict_channel_permissions(&self, guild_id: u64, channel_id: u64) {
+        self.evict_cached_permissions(|(gid, cid, _)| *gid == guild_id && *cid == Some(channel_id));
+    }
+
+    /// Evicts cached permissions of a role, after it was deleted.
+    pub fn evict_role_permissions(&self, guild_id: u64, role_id: u64) {
+        self.evict_cached_permissions(|(gid, _, rid)| *gid == guild_id && *rid == role_id);
+    }
+
     pub async fn is_user_in_guild(&self, guild_id: u64, user_id: u64) -> ServerResult<()> {
         self.contains_key(&make_member_key(guild_id, user_id))
             .await?
@@ -578,6 +897,210 @@ impl ChatTree {
             .unwrap_or(Err(ServerError::NoSuchRole { guild_id, role_id }))
     }
 
+    pub async fn unban_user_logic(&self, guild_id: u64, user_id: u64) -> ServerResult<()> {
+        let mut batch = Batch::default();
+        if let Some(expires_at) = self
+            .get_ban_logic(guild_id, user_id)
+            .await?
+            .and_then(|ban| ban.expires_at)
+        {
+            batch.remove(make_ban_expiry_key(expires_at, guild_id, user_id));
+        }
+        batch.remove(make_banned_member_key(guild_id, user_id));
+        batch.remove(make_ban_provenance_key(guild_id, user_id));
+        self.apply_batch(batch).await?;
+
+        Ok(())
+    }
+
+    /// Stores a ban, replacing the one the user already had.
+    pub async fn insert_ban_logic(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        ban: &BanInfo,
+    ) -> ServerResult<()> {
+        let mut batch = Batch::default();
+        if let Some(expires_at) = self
+            .get_ban_logic(guild_id, user_id)
+            .await?
+            .and_then(|ban| ban.expires_at)
+        {
+            batch.remove(make_ban_expiry_key(expires_at, guild_id, user_id));
+        }
+        if let Some(expires_at) = ban.expires_at {
+            batch.insert(make_ban_expiry_key(expires_at, guild_id, user_id), []);
+        }
+        batch.insert(make_banned_member_key(guild_id, user_id), rkyv_ser(ban));
+        self.apply_batch(batch).await?;
+
+        Ok(())
+    }
+
+    pub async fn get_ban_logic(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+    ) -> ServerResult<Option<BanInfo>> {
+        Ok(self
+            .get(make_banned_member_key(guild_id, user_id))
+            .await?
+            .map(db::deser_ban_info))
+    }
+
+    /// Gets the next page of the bans of a guild, ordered by user ID.
+    pub async fn get_bans_logic(
+        &self,
+        guild_id: u64,
+        pager: &Pager,
+    ) -> ServerResult<Page<(u64, BanInfo)>> {
+        let start = make_banned_member_key(guild_id, 0);
+        let end = make_banned_member_key(guild_id, u64::MAX);
+        let Some((start, end)) = pager.range(&start, &end) else {
+            return Ok(Page::empty());
+        };
+        let prefix_len = make_guild_banned_mem_prefix(guild_id).len();
+        let entries = self
+            .chat_tree
+            .range(start.as_slice()..=end.as_slice())
+            .await;
+        let page = pager
+            .collect(entries, |key, value| {
+                // skip ban provenance keys
+                if key.len() != prefix_len + 8 {
+                    return None;
+                }
+                // Safety: ban keys are always a prefix followed by a u64, we checked the length above
+                let user_id = u64::from_be_bytes(unsafe {
+                    key.split_at(prefix_len).1.try_into().unwrap_unchecked()
+                });
+                Some((user_id, db::deser_ban_info(value)))
+            })
+            .map_err(ServerError::from)?;
+        Ok(page)
+    }
+
+    /// Gets the guild and user IDs of temporary bans that expired at or before `until`.
+    pub async fn get_expired_bans_logic(&self, until: u64) -> ServerResult<Vec<(u64, u64)>> {
+        let start = make_ban_expiry_key(0, 0, 0);
+        let end = make_ban_expiry_key(until, u64::MAX, u64::MAX);
+        let mut all = Vec::new();
+        for res in self
+            .chat_tree
+            .range(start.as_slice()..=end.as_slice())
+            .await
+        {
+            let (key, _) = res.map_err(ServerError::from)?;
+            let (_, ids) = key.split_at(BAN_EXPIRY_PREFIX.len() + size_of::<u64>());
+            let (guild_id, user_id) = ids.split_at(size_of::<u64>());
+            // Safety: ban expiry keys are always a prefix followed by three u64s [ref:ban_expiry_key]
+            all.push(unsafe {
+                (
+                    u64::from_be_bytes(guild_id.try_into().unwrap_unchecked()),
+                    u64::from_be_bytes(user_id.try_into().unwrap_unchecked()),
+                )
+            });
+        }
+        Ok(all)
+    }
+
+    pub async fn get_banned_users_logic(&self, guild_id: u64) -> ServerResult<Vec<u64>> {
+        let prefix = make_guild_banned_mem_prefix(guild_id);
+        self.scan_prefix(&prefix)
+            .await
+            .try_fold(Vec::new(), |mut all, res| {
+                let (key, _) = res?;
+                if key.len() == make_banned_member_key(0, 0).len() {
+                    all.push(u64::from_be_bytes(unsafe {
+                        key.split_at(prefix.len()).1.try_into().unwrap_unchecked()
+                    }));
+                }
+                ServerResult::Ok(all)
+            })
+    }
+
+    pub async fn get_ban_list_subscriptions_logic(
+        &self,
+        guild_id: u64,
+    ) -> ServerResult<Vec<BanListSubscription>> {
+        Ok(self
+            .get(make_ban_list_subs_key(guild_id))
+            .await?
+            .map(db::deser_ban_list_subscriptions)
+            .unwrap_or_default())
+    }
+
+    /// Sets the ban lists a guild is subscribed to. An empty list removes the subscriptions.
+    pub async fn set_ban_list_subscriptions_logic(
+        &self,
+        guild_id: u64,
+        subscriptions: Vec<BanListSubscription>,
+    ) -> ServerResult<()> {
+        let key = make_ban_list_subs_key(guild_id);
+        if subscriptions.is_empty() {
+            self.remove(key).await?;
+        } else {
+            self.insert(key, rkyv_ser(&subscriptions)).await?;
+        }
+        Ok(())
+    }
+
+    /// Gets the IDs of all guilds subscribed to at least one ban list.
+    pub async fn get_ban_list_subscribed_guilds_logic(&self) -> ServerResult<Vec<u64>> {
+        self.scan_prefix(BAN_LIST_SUBS_PREFIX)
+            .await
+            .map(|res| {
+                let (key, _) = res?;
+                // Safety: ban list subscription keys are always the prefix and a u64 [ref:ban_list_subs_key]
+                Ok(u64::from_be_bytes(unsafe {
+                    key.split_at(BAN_LIST_SUBS_PREFIX.len())
+                        .1
+                        .try_into()
+                        .unwrap_unchecked()
+                }))
+            })
+            .collect()
+    }
+
+    /// Gets the users of a guild banned by ban lists, with the lists that banned them.
+    pub async fn get_ban_provenances_logic(
+        &self,
+        guild_id: u64,
+    ) -> ServerResult<HashMap<u64, Vec<BanListSource>>> {
+        let prefix = make_guild_banned_mem_prefix(guild_id);
+        self.scan_prefix(&prefix)
+            .await
+            .try_fold(HashMap::new(), |mut all, res| {
+                let (key, value) = res?;
+                if key.len() == make_ban_provenance_key(0, 0).len() {
+                    // Safety: provenance keys are the prefix, a user ID and a tag byte
+                    let user_id = u64::from_be_bytes(unsafe {
+                        key[prefix.len()..key.len() - 1]
+                            .try_into()
+                            .unwrap_unchecked()
+                    });
+                    all.insert(user_id, db::deser_ban_provenance(value));
+                }
+                ServerResult::Ok(all)
+            })
+    }
+
+    /// Sets the ban lists that banned a user. An empty list makes it a regular ban.
+    pub async fn set_ban_provenance_logic(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        sources: Vec<BanListSource>,
+    ) -> ServerResult<()> {
+        let key = make_ban_provenance_key(guild_id, user_id);
+        if sources.is_empty() {
+            self.remove(key).await?;
+        } else {
+            self.insert(key, rkyv_ser(&sources)).await?;
+        }
+        Ok(())
+    }
+
     pub async fn is_user_banned_in_guild(&self, guild_id: u64, user_id: u64) -> ServerResult<bool> {
         self.contains_key(&make_banned_member_key(guild_id, user_id))
             .await
@@ -654,22 +1177,1315 @@ impl ChatTree {
         Ok((message, key))
     }
 
-    pub async fn get_guild_logic(&self, guild_id: u64) -> ServerResult<Guild> {
-        let guild = if let Some(guild_raw) = self.get(guild_id.to_be_bytes().as_ref()).await? {
-            db::deser_guild(guild_raw)
-        } else {
-            return Err(ServerError::NoSuchGuild(guild_id).into());
-        };
-
-        Ok(guild)
-    }
-
-    pub async fn put_guild_logic(&self, guild_id: u64, guild: Guild) -> ServerResult<()> {
-        let buf = rkyv_ser(&guild);
+    /// Returns whether the user can see and review quarantined messages in a channel.
+    pub async fn can_review_quarantine(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        user_id: u64,
+    ) -> ServerResult<bool> {
+        let res = self
+            .check_perms(
+                guild_id,
+                Some(channel_id),
+                user_id,
+                "messages.quarantine.review",
+                false,
+            )
+            .await;
+        match res {
+            Ok(_) => Ok(true),
+            Err(ServerError::NotEnoughPermissions { .. }) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub async fn get_quarantined_messages_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+    ) -> ServerResult<Vec<(u64, QuarantinedMessage)>> {
+        let prefix = make_quarantine_prefix(guild_id, channel_id);
+        self.scan_prefix(&prefix)
+            .await
+            .map(|res| {
+                let (key, value) = res?;
+                // Safety: this is safe since the only keys we get are quarantine keys, which after stripping prefix are message IDs
+                let message_id = u64::from_be_bytes(unsafe {
+                    key.split_at(prefix.len()).1.try_into().unwrap_unchecked()
+                });
+                Ok((message_id, db::deser_quarantined_message(value)))
+            })
+            .collect()
+    }
+
+    /// Gets the IDs of the quarantined messages in a channel that the user can't
+    /// see, unless they are the author. Moderators can see all of them.
+    pub async fn get_hidden_quarantined_ids(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        user_id: u64,
+    ) -> ServerResult<Vec<u64>> {
+        if self
+            .can_review_quarantine(guild_id, channel_id, user_id)
+            .await?
+        {
+            return Ok(Vec::new());
+        }
+        Ok(self
+            .get_quarantined_messages_logic(guild_id, channel_id)
+            .await?
+            .into_iter()
+            .map(|(message_id, _)| message_id)
+            .collect())
+    }
+
+    /// Gets the sorted IDs of the users who acknowledged a message, or `None`
+    /// if the message doesn't need to be acknowledged.
+    pub async fn get_message_acks_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+    ) -> ServerResult<Option<Vec<u64>>> {
+        let key = make_msg_acks_key(guild_id, channel_id, message_id);
+        Ok(self
+            .get(key)
+            .await?
+            .map(|raw| db::make_u64_iter_logic(&raw).collect()))
+    }
+
+    /// Makes a message need to be acknowledged, or not. Acknowledgements
+    /// already given are kept if the message already needed them.
+    pub async fn set_ack_required_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+        required: bool,
+    ) -> ServerResult<()> {
+        let key = make_msg_acks_key(guild_id, channel_id, message_id);
+        if !required {
+            self.remove(key).await?;
+        } else if !self.contains_key(key).await? {
+            self.insert(key, []).await?;
+        }
+        Ok(())
+    }
+
+    /// Records that a user acknowledged a message. Returns `false` if the message
+    /// doesn't need to be acknowledged.
+    pub async fn acknowledge_message_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+        user_id: u64,
+    ) -> ServerResult<bool> {
+        let Some(mut acks) = self
+            .get_message_acks_logic(guild_id, channel_id, message_id)
+            .await?
+        else {
+            return Ok(false);
+        };
+        if let Err(index) = acks.binary_search(&user_id) {
+            acks.insert(index, user_id);
+            self.insert(
+                make_msg_acks_key(guild_id, channel_id, message_id),
+                self.serialize_list_u64_logic(acks),
+            )
+            .await?;
+        }
+        Ok(true)
+    }
+
+    /// Gets the IDs of the messages in a channel that need to be acknowledged,
+    /// along with the sorted IDs of the users who acknowledged each.
+    pub async fn get_ack_required_messages_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+    ) -> ServerResult<Vec<(u64, Vec<u64>)>> {
+        let prefix = make_msg_acks_prefix(guild_id, channel_id);
+        self.scan_prefix(&prefix)
+            .await
+            .map(|res| {
+                let (key, value) = res?;
+                // Safety: this is safe since the only keys we get are ack keys, which after stripping prefix are message IDs
+                let message_id = u64::from_be_bytes(unsafe {
+                    key.split_at(prefix.len()).1.try_into().unwrap_unchecked()
+                });
+                Ok((message_id, db::make_u64_iter_logic(&value).collect()))
+            })
+            .collect()
+    }
+
+    pub async fn get_guild_list_logic(&self, user_id: u64) -> ServerResult<Vec<GuildListEntry>> {
+        let prefix = make_guild_list_key_prefix(user_id);
+        self.chat_tree
+            .scan_prefix(&prefix)
+            .await
+            .try_fold(Vec::new(), |mut all, res| {
+                let (guild_id_raw, _) = res.map_err(ServerError::from)?;
+                let (id_raw, host_raw) = guild_id_raw
+                    .split_at(prefix.len())
+                    .1
+                    .split_at(size_of::<u64>());
+
+                // Safety: this unwrap can never cause UB since we split at u64 boundary
+                let guild_id = u64::from_be_bytes(unsafe { id_raw.try_into().unwrap_unchecked() });
+                // Safety: we never store non UTF-8 hosts, so this can't cause UB
+                let host = unsafe { std::str::from_utf8_unchecked(host_raw) };
+
+                all.push(GuildListEntry {
+                    guild_id,
+                    server_id: host.to_string(),
+                });
+
+                ServerResult::Ok(all)
+            })
+    }
+
+    pub async fn get_guild_logic(&self, guild_id: u64) -> ServerResult<Guild> {
+        let guild = if let Some(guild_raw) = self.get(guild_id.to_be_bytes().as_ref()).await? {
+            db::deser_guild(guild_raw)
+        } else {
+            return Err(ServerError::NoSuchGuild(guild_id).into());
+        };
+
+        Ok(guild)
+    }
+
+    pub async fn put_guild_logic(&self, guild_id: u64, guild: Guild) -> ServerResult<()> {
+        let buf = rkyv_ser(&guild);
         self.insert(guild_id.to_be_bytes(), buf)
             .await
-            .map(|_| ())
-            .map_err(Into::into)
+            .map(|_| ())
+            .map_err(Into::into)
+    }
+
+    pub async fn get_guild_theme_logic(&self, guild_id: u64) -> ServerResult<GuildTheme> {
+        let theme = self
+            .get(make_guild_theme_key(guild_id))
+            .await?
+            .map_or_else(GuildTheme::default, db::deser_guild_theme);
+
+        Ok(theme)
+    }
+
+    /// Puts the theme of a guild, and puts it in the guild's metadata so
+    /// clients get it along with the guild.
+    ///
+    /// Returns the new metadata of the guild.
+    pub async fn put_guild_theme_logic(
+        &self,
+        guild_id: u64,
+        theme: GuildTheme,
+    ) -> ServerResult<Metadata> {
+        let is_default =
+            theme.banner.is_none() && theme.splash.is_none() && theme.theme_color.is_none();
+
+        let mut guild = self.get_guild_logic(guild_id).await?;
+        let metadata = guild.metadata.get_or_insert_with(Metadata::default);
+        if is_default {
+            metadata.extension.remove(GUILD_THEME_EXTENSION);
+        } else {
+            let value = json_extension(&GuildThemeInfo::from(theme.clone()))?;
+            metadata
+                .extension
+                .insert(GUILD_THEME_EXTENSION.to_string(), value);
+        }
+        let new_metadata = metadata.clone();
+
+        let key = make_guild_theme_key(guild_id);
+        let mut batch = Batch::default();
+        batch.insert(guild_id.to_be_bytes(), rkyv_ser(&guild));
+        if is_default {
+            batch.remove(key);
+        } else {
+            batch.insert(key, rkyv_ser(&theme));
+        }
+        self.apply_batch(batch).await?;
+
+        Ok(new_metadata)
+    }
+
+    pub async fn get_guild_locale_logic(&self, guild_id: u64) -> ServerResult<GuildLocale> {
+        let locale = self
+            .get(make_guild_locale_key(guild_id))
+            .await?
+            .map_or_else(GuildLocale::default, db::deser_guild_locale);
+
+        Ok(locale)
+    }
+
+    pub async fn put_guild_locale_logic(
+        &self,
+        guild_id: u64,
+        locale: &GuildLocale,
+    ) -> ServerResult<()> {
+        self.insert(make_guild_locale_key(guild_id), rkyv_ser(locale))
+            .await
+            .map(|_| ())
+            .map_err(Into::into)
+    }
+
+    /// Formats a time, in seconds since unix epoch, for the messages the
+    /// server writes in a guild, in the guild's locale and timezone.
+    pub async fn format_guild_time_logic(&self, guild_id: u64, secs: u64) -> ServerResult<String> {
+        let locale = self.get_guild_locale_logic(guild_id).await?;
+        Ok(format_time(&locale, secs))
+    }
+
+    pub async fn get_channel_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+    ) -> ServerResult<([u8; 17], Channel)> {
+        let key = make_chan_key(guild_id, channel_id);
+        let chan_info = if let Some(raw) = self.get(key).await? {
+            db::deser_chan(raw)
+        } else {
+            bail!(ServerError::NoSuchChannel {
+                guild_id,
+                channel_id,
+            });
+        };
+
+        Ok((key, chan_info))
+    }
+
+    pub async fn get_stage_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+    ) -> ServerResult<Option<StageState>> {
+        let stage = self
+            .get(make_stage_key(guild_id, channel_id))
+            .await?
+            .map(db::deser_stage);
+
+        Ok(stage)
+    }
+
+    /// Puts the stage state of a channel, or turns the channel back into a
+    /// regular channel if `stage` is `None`.
+    ///
+    /// Returns the new metadata of the channel.
+    pub async fn put_stage_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        stage: Option<StageState>,
+    ) -> ServerResult<Metadata> {
+        let (key, mut chan_info) = self.get_channel_logic(guild_id, channel_id).await?;
+        let value = stage
+            .as_ref()
+            .map(|stage| json_extension(&StageInfo::from(stage.clone())))
+            .transpose()?;
+        let new_metadata =
+            set_channel_metadata_extension(&mut chan_info, CHANNEL_STAGE_EXTENSION, value);
+
+        let mut batch = Batch::default();
+        batch.insert(key, rkyv_ser(&chan_info));
+        match stage {
+            Some(stage) => batch.insert(make_stage_key(guild_id, channel_id), rkyv_ser(&stage)),
+            None => batch.remove(make_stage_key(guild_id, channel_id)),
+        }
+        self.apply_batch(batch).await?;
+
+        Ok(new_metadata)
+    }
+
+    /// Fails if a user can't post a message in a channel, either because it's
+    /// a stage channel they can't speak in, or a gallery channel and the
+    /// message isn't media.
+    pub async fn check_can_post_in_channel(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        user_id: u64,
+        is_media: bool,
+    ) -> ServerResult<()> {
+        if !self
+            .can_speak_in_channel(guild_id, channel_id, user_id)
+            .await?
+        {
+            bail!(ServerError::NotAStageSpeaker);
+        }
+        if !is_media && self.is_gallery_channel(guild_id, channel_id).await? {
+            bail!(ServerError::GalleryMediaOnly);
+        }
+        Ok(())
+    }
+
+    /// Checks if a user can speak in a channel. Everyone can speak in regular channels,
+    /// while only speakers and users that can manage the stage can speak in stage channels.
+    pub async fn can_speak_in_channel(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        user_id: u64,
+    ) -> ServerResult<bool> {
+        let stage = match self.get_stage_logic(guild_id, channel_id).await? {
+            Some(stage) => stage,
+            None => return Ok(true),
+        };
+        if stage.speakers.contains(&user_id) {
+            return Ok(true);
+        }
+
+        match self
+            .check_perms(guild_id, Some(channel_id), user_id, "stage.manage", false)
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(ServerError::NotEnoughPermissions { .. }) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub async fn get_channel_topic_history_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+    ) -> ServerResult<Vec<TopicChange>> {
+        let history = self
+            .get(make_chan_topic_history_key(guild_id, channel_id))
+            .await?
+            .map_or_else(Vec::new, db::deser_topic_history);
+
+        Ok(history)
+    }
+
+    /// Sets the topic of a channel and records the change in the channel's topic history.
+    /// An empty topic removes the topic.
+    ///
+    /// Returns the new metadata of the channel.
+    pub async fn set_channel_topic_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        author_id: u64,
+        topic: String,
+    ) -> ServerResult<Metadata> {
+        let (key, mut chan_info) = self.get_channel_logic(guild_id, channel_id).await?;
+        let value = topic.is_empty().not().then(|| Anything {
+            kind: "text/plain".to_string(),
+            body: topic.clone().into_bytes().into(),
+        });
+        let new_metadata =
+            set_channel_metadata_extension(&mut chan_info, CHANNEL_TOPIC_EXTENSION, value);
+
+        let mut history = self
+            .get_channel_topic_history_logic(guild_id, channel_id)
+            .await?;
+        history.push(TopicChange {
+            topic,
+            author_id,
+            changed_at: get_time_secs(),
+        });
+        if history.len() > MAX_TOPIC_HISTORY_LEN {
+            history.drain(..history.len() - MAX_TOPIC_HISTORY_LEN);
+        }
+
+        let mut batch = Batch::default();
+        batch.insert(key, rkyv_ser(&chan_info));
+        batch.insert(
+            make_chan_topic_history_key(guild_id, channel_id),
+            rkyv_ser(&history),
+        );
+        self.apply_batch(batch).await?;
+
+        Ok(new_metadata)
+    }
+
+    pub async fn get_sticky_message_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+    ) -> ServerResult<Option<StickyMessage>> {
+        let sticky = self
+            .get(make_sticky_msg_key(guild_id, channel_id))
+            .await?
+            .map(db::deser_sticky_message);
+
+        Ok(sticky)
+    }
+
+    /// Gets the sticky messages of all channels, with the guild and channel they are in.
+    pub async fn get_sticky_messages_logic(&self) -> ServerResult<Vec<(u64, u64, StickyMessage)>> {
+        self.scan_prefix(STICKY_PREFIX)
+            .await
+            .map(|res| {
+                let (key, value) = res?;
+                // Safety: sticky message keys are always the prefix and two u64s [ref:sticky_msg_key]
+                let (guild_id, channel_id) = unsafe {
+                    let (_, ids) = key.split_at(STICKY_PREFIX.len());
+                    let (guild_id, channel_id) = ids.split_at(size_of::<u64>());
+                    (
+                        u64::from_be_bytes(guild_id.try_into().unwrap_unchecked()),
+                        u64::from_be_bytes(channel_id.try_into().unwrap_unchecked()),
+                    )
+                };
+                Ok((guild_id, channel_id, db::deser_sticky_message(value)))
+            })
+            .collect()
+    }
+
+    /// Puts the sticky message of a channel, or removes it if `sticky` is `None`.
+    ///
+    /// Returns the new metadata of the channel.
+    pub async fn put_sticky_message_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        sticky: Option<StickyMessage>,
+    ) -> ServerResult<Metadata> {
+        let (key, mut chan_info) = self.get_channel_logic(guild_id, channel_id).await?;
+        let value = sticky
+            .as_ref()
+            .map(|sticky| json_extension(&StickyMessageInfo::from(sticky.clone())))
+            .transpose()?;
+        let new_metadata =
+            set_channel_metadata_extension(&mut chan_info, CHANNEL_STICKY_EXTENSION, value);
+
+        let mut batch = Batch::default();
+        batch.insert(key, rkyv_ser(&chan_info));
+        match sticky {
+            Some(sticky) => {
+                batch.insert(make_sticky_msg_key(guild_id, channel_id), rkyv_ser(&sticky))
+            }
+            None => batch.remove(make_sticky_msg_key(guild_id, channel_id)),
+        }
+        self.apply_batch(batch).await?;
+
+        Ok(new_metadata)
+    }
+
+    pub async fn get_disappearing_messages_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+    ) -> ServerResult<Option<DisappearingMessages>> {
+        let settings = self
+            .get(make_chan_disappearing_key(guild_id, channel_id))
+            .await?
+            .map(db::deser_disappearing_messages);
+
+        Ok(settings)
+    }
+
+    /// Puts the disappearing message settings of a channel, or removes them if
+    /// `settings` is `None`. Messages that were already sent keep the settings
+    /// they were sent with.
+    ///
+    /// Returns the new metadata of the channel.
+    pub async fn put_disappearing_messages_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        settings: Option<DisappearingMessages>,
+    ) -> ServerResult<Metadata> {
+        let (key, mut chan_info) = self.get_channel_logic(guild_id, channel_id).await?;
+        let value = settings
+            .as_ref()
+            .map(|settings| json_extension(&DisappearingMessagesInfo::from(settings.clone())))
+            .transpose()?;
+        let new_metadata =
+            set_channel_metadata_extension(&mut chan_info, CHANNEL_DISAPPEARING_EXTENSION, value);
+
+        let mut batch = Batch::default();
+        batch.insert(key, rkyv_ser(&chan_info));
+        match settings {
+            Some(settings) => batch.insert(
+                make_chan_disappearing_key(guild_id, channel_id),
+                rkyv_ser(&settings),
+            ),
+            None => batch.remove(make_chan_disappearing_key(guild_id, channel_id)),
+        }
+        self.apply_batch(batch).await?;
+
+        Ok(new_metadata)
+    }
+
+    /// Starts the countdown of the messages up to `up_to` that disappear after
+    /// they are read, except the ones sent by `user_id`. Returns the IDs of
+    /// the messages with the time they will be deleted at.
+    pub async fn mark_messages_read_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        user_id: u64,
+        up_to: u64,
+    ) -> ServerResult<Vec<(u64, u64)>> {
+        let prefix = make_unread_disappearing_prefix(guild_id, channel_id);
+        let start = make_unread_disappearing_key(guild_id, channel_id, 0);
+        let end = make_unread_disappearing_key(guild_id, channel_id, up_to);
+
+        let now = get_time_secs();
+        let mut batch = Batch::default();
+        let mut expiring = Vec::new();
+        for res in self
+            .chat_tree
+            .range(start.as_slice()..=end.as_slice())
+            .await
+        {
+            let (key, value) = res.map_err(ServerError::from)?;
+            // Safety: unread keys are always the prefix and a message ID, and
+            // their values are two u64s [ref:unread_disappearing_key]
+            let (message_id, author_id, ttl_secs) = unsafe {
+                let (_, message_id) = key.split_at(prefix.len());
+                let (author_id, ttl_secs) = value.split_at(size_of::<u64>());
+                (
+                    u64::from_be_bytes(message_id.try_into().unwrap_unchecked()),
+                    u64::from_be_bytes(author_id.try_into().unwrap_unchecked()),
+                    u64::from_be_bytes(ttl_secs.try_into().unwrap_unchecked()),
+                )
+            };
+            if author_id == user_id {
+                continue;
+            }
+            let expires_at = now.saturating_add(ttl_secs);
+            batch.remove(key);
+            batch.insert(
+                make_disappear_expiry_key(expires_at, guild_id, channel_id, message_id),
+                [],
+            );
+            expiring.push((message_id, expires_at));
+        }
+        self.apply_batch(batch).await?;
+
+        Ok(expiring)
+    }
+
+    /// Gets the disappearing messages that expired at or before `until`, as
+    /// their guild, channel and message IDs.
+    pub async fn get_expired_disappearing_logic(
+        &self,
+        until: u64,
+    ) -> ServerResult<Vec<(u64, u64, u64)>> {
+        let start = make_disappear_expiry_key(0, 0, 0, 0);
+        let end = make_disappear_expiry_key(until, u64::MAX, u64::MAX, u64::MAX);
+        let mut all = Vec::new();
+        for res in self
+            .chat_tree
+            .range(start.as_slice()..=end.as_slice())
+            .await
+        {
+            let (key, _) = res.map_err(ServerError::from)?;
+            let (_, ids) = key.split_at(DISAPPEAR_EXPIRY_PREFIX.len() + size_of::<u64>());
+            let (guild_id, ids) = ids.split_at(size_of::<u64>());
+            let (channel_id, message_id) = ids.split_at(size_of::<u64>());
+            // Safety: disappearing message expiry keys are always a prefix followed by four u64s [ref:disappear_expiry_key]
+            all.push(unsafe {
+                (
+                    u64::from_be_bytes(guild_id.try_into().unwrap_unchecked()),
+                    u64::from_be_bytes(channel_id.try_into().unwrap_unchecked()),
+                    u64::from_be_bytes(message_id.try_into().unwrap_unchecked()),
+                )
+            });
+        }
+        Ok(all)
+    }
+
+    /// Removes the expiry times of disappearing messages that expired at or before `until`.
+    pub async fn clear_expired_disappearing_logic(&self, until: u64) -> ServerResult<()> {
+        let start = make_disappear_expiry_key(0, 0, 0, 0);
+        let end = make_disappear_expiry_key(until, u64::MAX, u64::MAX, u64::MAX);
+        let mut batch = Batch::default();
+        for res in self
+            .chat_tree
+            .range(start.as_slice()..=end.as_slice())
+            .await
+        {
+            let (key, _) = res.map_err(ServerError::from)?;
+            batch.remove(key);
+        }
+        self.apply_batch(batch).await?;
+
+        Ok(())
+    }
+
+    /// Checks if a channel is a category, which groups the channels after it
+    /// in the channel order and has no messages.
+    pub async fn is_category_channel(&self, guild_id: u64, channel_id: u64) -> ServerResult<bool> {
+        let (_, channel) = self.get_channel_logic(guild_id, channel_id).await?;
+        Ok(channel.kind == i32::from(ChannelKind::Category))
+    }
+
+    /// Gets the category a channel is in, which is the closest category before
+    /// it in the channel order.
+    pub async fn get_channel_category_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+    ) -> ServerResult<Option<u64>> {
+        let ordering = self
+            .get_list_u64_logic(&make_guild_chan_ordering_key(guild_id))
+            .await?;
+        let mut category_id = None;
+        for id in ordering {
+            if id == channel_id {
+                return Ok(category_id);
+            }
+            if self.is_category_channel(guild_id, id).await? {
+                category_id = Some(id);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Gets the channels in a category whose permissions are synced with it.
+    pub async fn get_synced_channels_logic(
+        &self,
+        guild_id: u64,
+        category_id: u64,
+    ) -> ServerResult<Vec<u64>> {
+        let ordering = self
+            .get_list_u64_logic(&make_guild_chan_ordering_key(guild_id))
+            .await?;
+        let mut synced = Vec::new();
+        let in_category = ordering
+            .into_iter()
+            .skip_while(|id| *id != category_id)
+            .skip(1);
+        for id in in_category {
+            if self.is_category_channel(guild_id, id).await? {
+                break;
+            }
+            if self.is_channel_perm_synced(guild_id, id).await? {
+                synced.push(id);
+            }
+        }
+        Ok(synced)
+    }
+
+    pub async fn is_channel_perm_synced(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+    ) -> ServerResult<bool> {
+        self.contains_key(make_chan_perm_sync_key(guild_id, channel_id))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Syncs the permissions of a channel with a category, replacing every
+    /// permission the channel had with the category's. Sync is kept up when
+    /// the category's permissions change, until it is turned off.
+    ///
+    /// Returns the roles whose permissions changed, with their new permissions.
+    pub async fn sync_channel_perms_logic(
+        &self,
+        guild_id: u64,
+        category_id: u64,
+        channel_id: u64,
+    ) -> ServerResult<Vec<(u64, Vec<Permission>)>> {
+        let perms_of = |channel_id| async move {
+            let prefix = make_channel_perms_prefix(guild_id, channel_id);
+            let mut perms: Vec<(u64, Vec<Permission>)> = Vec::new();
+            for res in self.scan_prefix(&prefix).await {
+                let (key, value) = res?;
+                let (role_id, matches) = key.split_at(prefix.len()).1.split_at(size_of::<u64>());
+                // Safety: this unwrap is safe since permission keys have a role ID after the prefix
+                let role_id = u64::from_be_bytes(unsafe { role_id.try_into().unwrap_unchecked() });
+                let perm = Permission {
+                    matches: String::from_utf8_lossy(matches).into_owned(),
+                    ok: value[0] != 0,
+                };
+                match perms.last_mut() {
+                    Some((last_id, role_perms)) if *last_id == role_id => role_perms.push(perm),
+                    _ => perms.push((role_id, vec![perm])),
+                }
+            }
+            ServerResult::Ok(perms)
+        };
+        let old_perms = perms_of(channel_id).await?;
+        let new_perms = perms_of(category_id).await?;
+
+        let mut batch = Batch::default();
+        for (role_id, perms) in &old_perms {
+            for perm in perms {
+                batch.remove(make_channel_perm_key(
+                    guild_id,
+                    channel_id,
+                    *role_id,
+                    &perm.matches,
+                ));
+            }
+        }
+        for (role_id, perms) in &new_perms {
+            for perm in perms {
+                batch.insert(
+                    make_channel_perm_key(guild_id, channel_id, *role_id, &perm.matches),
+                    perm.ok.then(|| [1]).unwrap_or([0]),
+                );
+            }
+        }
+        batch.insert(make_chan_perm_sync_key(guild_id, channel_id), []);
+        self.apply_batch(batch).await?;
+        self.evict_channel_permissions(guild_id, channel_id);
+
+        // roles that only had permissions in the channel don't have any now
+        let mut changed = new_perms;
+        for (role_id, _) in old_perms {
+            if !changed.iter().any(|(id, _)| *id == role_id) {
+                changed.push((role_id, Vec::new()));
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Stops syncing the permissions of a channel with its category. The
+    /// channel keeps the permissions it has.
+    pub async fn unsync_channel_perms_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+    ) -> ServerResult<()> {
+        self.remove(make_chan_perm_sync_key(guild_id, channel_id))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn is_gallery_channel(&self, guild_id: u64, channel_id: u64) -> ServerResult<bool> {
+        self.contains_key(make_chan_gallery_key(guild_id, channel_id))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Turns gallery mode on or off for a channel. Turning it on indexes the
+    /// messages with media already in the channel, turning it off drops the index.
+    ///
+    /// Returns the new metadata of the channel.
+    pub async fn set_gallery_mode_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        enabled: bool,
+    ) -> ServerResult<Metadata> {
+        let (key, mut chan_info) = self.get_channel_logic(guild_id, channel_id).await?;
+        let value = enabled.then(|| Anything {
+            kind: "text/plain".to_string(),
+            body: b"true".to_vec().into(),
+        });
+        let new_metadata =
+            set_channel_metadata_extension(&mut chan_info, CHANNEL_GALLERY_EXTENSION, value);
+
+        let mut batch = Batch::default();
+        batch.insert(key, rkyv_ser(&chan_info));
+        if enabled {
+            batch.insert(make_chan_gallery_key(guild_id, channel_id), Vec::new());
+            let msg_prefix = make_msg_prefix(guild_id, channel_id);
+            for res in self.scan_prefix(&msg_prefix).await {
+                let (key, value) = res?;
+                // only index message keys, not the reactions stored under them
+                if key.len() != make_msg_key(0, 0, 0).len() {
+                    continue;
+                }
+                let message = db::deser_message(value);
+                if is_media_message(&message) {
+                    // Safety: this is safe since message keys are message IDs after stripping the prefix
+                    let message_id = u64::from_be_bytes(unsafe {
+                        key.split_at(msg_prefix.len())
+                            .1
+                            .try_into()
+                            .unwrap_unchecked()
+                    });
+                    batch.insert(
+                        make_gallery_entry_key(guild_id, channel_id, message_id),
+                        Vec::new(),
+                    );
+                }
+            }
+        } else {
+            batch.remove(make_chan_gallery_key(guild_id, channel_id));
+            for res in self
+                .scan_prefix(&make_gallery_prefix(guild_id, channel_id))
+                .await
+            {
+                let (key, _) = res?;
+                batch.remove(key);
+            }
+        }
+        self.apply_batch(batch).await?;
+
+        Ok(new_metadata)
+    }
+
+    /// Gets up to `count` messages with media from a gallery channel, newest first,
+    /// starting before the given message. Also returns whether the oldest one was reached.
+    pub async fn get_gallery_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        before: Option<u64>,
+        count: usize,
+    ) -> ServerResult<(Vec<(u64, HarmonyMessage)>, bool)> {
+        let prefix = make_gallery_prefix(guild_id, channel_id);
+        let from_key = make_gallery_entry_key(guild_id, channel_id, 0);
+        let to_key = make_gallery_entry_key(
+            guild_id,
+            channel_id,
+            before.map_or(u64::MAX, |id| id.saturating_sub(1)),
+        );
+
+        let mut entries = self.chat_tree.range((&from_key)..=(&to_key)).await.rev();
+        let mut messages = Vec::with_capacity(count);
+        while messages.len() < count {
+            let (key, _) = match entries.next() {
+                Some(res) => res.map_err(ServerError::from)?,
+                None => return Ok((messages, true)),
+            };
+            // Safety: this is safe since gallery keys are message IDs after stripping the prefix
+            let message_id = u64::from_be_bytes(unsafe {
+                key.split_at(prefix.len()).1.try_into().unwrap_unchecked()
+            });
+            // the index might be stale if the message was removed without updating it
+            if let Some(raw) = self
+                .get(make_msg_key(guild_id, channel_id, message_id))
+                .await?
+            {
+                messages.push((message_id, db::deser_message(raw)));
+            }
+        }
+        let reached_top = entries.next().is_none();
+
+        Ok((messages, reached_top))
+    }
+
+    pub async fn get_screening_logic(
+        &self,
+        guild_id: u64,
+    ) -> ServerResult<Option<MembershipScreening>> {
+        let screening = self
+            .get(make_guild_screening_key(guild_id))
+            .await?
+            .map(db::deser_screening);
+
+        Ok(screening)
+    }
+
+    /// Sets the membership screening of a guild, or disables it if `screening` is `None`
+    pub async fn put_screening_logic(
+        &self,
+        guild_id: u64,
+        screening: Option<MembershipScreening>,
+    ) -> ServerResult<()> {
+        let key = make_guild_screening_key(guild_id);
+        match screening {
+            Some(screening) => self.insert(key, rkyv_ser(&screening)).await?,
+            None => self.remove(key).await?,
+        };
+
+        Ok(())
+    }
+
+    /// Checks if messages sent in a guild are scanned for links to blocked domains
+    pub async fn is_link_scanning_enabled(&self, guild_id: u64) -> ServerResult<bool> {
+        let opted_out = self
+            .contains_key(&make_guild_link_scanning_opt_out_key(guild_id))
+            .await?;
+        Ok(!opted_out)
+    }
+
+    pub async fn set_link_scanning_enabled(
+        &self,
+        guild_id: u64,
+        enabled: bool,
+    ) -> ServerResult<()> {
+        let key = make_guild_link_scanning_opt_out_key(guild_id);
+        if enabled {
+            self.remove(key).await?;
+        } else {
+            self.insert(key, []).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Gets the widget settings of a guild, `None` if it doesn't have a widget
+    pub async fn get_widget_settings_logic(
+        &self,
+        guild_id: u64,
+    ) -> ServerResult<Option<WidgetSettings>> {
+        let settings = self
+            .get(make_guild_widget_key(guild_id))
+            .await?
+            .map(db::deser_widget_settings);
+
+        Ok(settings)
+    }
+
+    /// Sets the widget settings of a guild, or disables its widget if `settings` is `None`
+    pub async fn set_widget_settings_logic(
+        &self,
+        guild_id: u64,
+        settings: Option<WidgetSettings>,
+    ) -> ServerResult<()> {
+        let key = make_guild_widget_key(guild_id);
+        match settings {
+            Some(settings) => self.insert(key, rkyv_ser(&settings)).await?,
+            None => self.remove(key).await?,
+        };
+
+        Ok(())
+    }
+
+    pub async fn get_text_macros_logic(&self, guild_id: u64) -> ServerResult<Vec<TextMacro>> {
+        let macros = self
+            .get(make_guild_text_macros_key(guild_id))
+            .await?
+            .map_or_else(Vec::new, db::deser_text_macros);
+
+        Ok(macros)
+    }
+
+    pub async fn set_text_macros_logic(
+        &self,
+        guild_id: u64,
+        macros: Vec<TextMacro>,
+    ) -> ServerResult<()> {
+        let key = make_guild_text_macros_key(guild_id);
+        if macros.is_empty() {
+            self.remove(key).await?;
+        } else {
+            self.insert(key, rkyv_ser(&macros)).await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn get_guild_emoji_logic(&self, guild_id: u64) -> ServerResult<Vec<GuildEmoji>> {
+        let emoji = self
+            .get(make_guild_emoji_key(guild_id))
+            .await?
+            .map_or_else(Vec::new, db::deser_guild_emoji);
+
+        Ok(emoji)
+    }
+
+    pub async fn get_guild_integrations_logic(
+        &self,
+        guild_id: u64,
+    ) -> ServerResult<Vec<GuildIntegration>> {
+        Ok(self
+            .get(make_guild_integrations_key(guild_id))
+            .await?
+            .map(db::deser_guild_integrations)
+            .unwrap_or_default())
+    }
+
+    /// Sets the integrations registered in a guild. An empty list removes them.
+    pub async fn set_guild_integrations_logic(
+        &self,
+        guild_id: u64,
+        integrations: Vec<GuildIntegration>,
+    ) -> ServerResult<()> {
+        let key = make_guild_integrations_key(guild_id);
+        if integrations.is_empty() {
+            self.remove(key).await?;
+        } else {
+            self.insert(key, rkyv_ser(&integrations)).await?;
+        }
+        Ok(())
+    }
+
+    /// Puts the custom emoji of a guild, and lists them in the guild's metadata
+    /// so clients get them along with the guild.
+    ///
+    /// Returns the new metadata of the guild.
+    pub async fn set_guild_emoji_logic(
+        &self,
+        guild_id: u64,
+        emoji: Vec<GuildEmoji>,
+    ) -> ServerResult<Metadata> {
+        let mut guild = self.get_guild_logic(guild_id).await?;
+        let metadata = guild.metadata.get_or_insert_with(Metadata::default);
+        if emoji.is_empty() {
+            metadata.extension.remove(GUILD_EMOJI_EXTENSION);
+        } else {
+            let infos = emoji
+                .iter()
+                .cloned()
+                .map(GuildEmojiInfo::from)
+                .collect::<Vec<_>>();
+            metadata
+                .extension
+                .insert(GUILD_EMOJI_EXTENSION.to_string(), json_extension(&infos)?);
+        }
+        let new_metadata = metadata.clone();
+
+        let key = make_guild_emoji_key(guild_id);
+        let mut batch = Batch::default();
+        batch.insert(guild_id.to_be_bytes(), rkyv_ser(&guild));
+        if emoji.is_empty() {
+            batch.remove(key);
+        } else {
+            batch.insert(key, rkyv_ser(&emoji));
+        }
+        self.apply_batch(batch).await?;
+
+        Ok(new_metadata)
+    }
+
+    /// Replaces the text of a message that is only the name of one of the
+    /// guild's text macros, like `/rules`, with the text of the macro.
+    pub async fn expand_text_macro_logic(
+        &self,
+        guild_id: u64,
+        content: &mut Content,
+    ) -> ServerResult<()> {
+        let Some(content::Content::TextMessage(content::TextContent {
+            content: Some(text),
+        })) = &mut content.content
+        else {
+            return Ok(());
+        };
+        let Some(name) = text.text.trim().strip_prefix('/') else {
+            return Ok(());
+        };
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Ok(());
+        }
+
+        let macros = self.get_text_macros_logic(guild_id).await?;
+        if let Some(text_macro) = macros.into_iter().find(|m| m.name == name) {
+            *text = FormattedText::new(text_macro.text, Vec::new());
+        }
+
+        Ok(())
+    }
+
+    /// Checks if a guild is listed in the guild directory
+    pub async fn is_guild_public(&self, guild_id: u64) -> ServerResult<bool> {
+        Ok(self.contains_key(&make_public_guild_key(guild_id)).await?)
+    }
+
+    pub async fn set_guild_public(&self, guild_id: u64, public: bool) -> ServerResult<()> {
+        let key = make_public_guild_key(guild_id);
+        if public {
+            self.insert(key, []).await?;
+        } else {
+            self.remove(key).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Gets the IDs of all guilds listed in the guild directory
+    pub async fn get_public_guilds_logic(&self) -> ServerResult<Vec<u64>> {
+        self.scan_prefix(PUBLIC_GUILD_PREFIX)
+            .await
+            .try_fold(Vec::new(), |mut all, res| {
+                let (key, _) = res?;
+                // Safety: public guild keys are always a prefix followed by a u64
+                all.push(u64::from_be_bytes(unsafe {
+                    key.split_at(PUBLIC_GUILD_PREFIX.len())
+                        .1
+                        .try_into()
+                        .unwrap_unchecked()
+                }));
+                ServerResult::Ok(all)
+            })
+    }
+
+    /// Gets all screening applications waiting for review in a guild, along with their user IDs
+    pub async fn get_screening_applications_logic(
+        &self,
+        guild_id: u64,
+    ) -> ServerResult<Vec<(u64, ScreeningApplication)>> {
+        let prefix = make_screening_application_prefix(guild_id);
+        self.scan_prefix(&prefix)
+            .await
+            .try_fold(Vec::new(), |mut all, res| {
+                let (key, value) = res?;
+                // Safety: application keys are always a prefix followed by a user id
+                let user_id = u64::from_be_bytes(unsafe {
+                    key.split_at(prefix.len()).1.try_into().unwrap_unchecked()
+                });
+                all.push((user_id, db::deser_screening_application(value)));
+                Ok(all)
+            })
+    }
+
+    /// Checks if a user is a homeserver admin, ie. a member of the admin guild.
+    pub async fn check_homeserver_admin(&self, user_id: u64) -> ServerResult<()> {
+        let is_admin = match self.admin_guild_keys.get() {
+            Some(keys) => {
+                self.contains_key(&make_member_key(keys.guild_id, user_id))
+                    .await?
+            }
+            None => false,
+        };
+
+        is_admin
+            .then(|| Ok(()))
+            .unwrap_or(Err(ServerError::NotAnAdmin))
+            .map_err(Into::into)
+    }
+
+    pub async fn get_report_logic(&self, report_id: u64) -> ServerResult<EscalatedReport> {
+        self.get(make_report_key(report_id))
+            .await?
+            .map(db::deser_report)
+            .ok_or_else(|| ServerError::NoSuchReport(report_id).into())
+    }
+
+    pub async fn put_report_logic(
+        &self,
+        report_id: u64,
+        report: &EscalatedReport,
+    ) -> ServerResult<()> {
+        self.insert(make_report_key(report_id), rkyv_ser(report))
+            .await?;
+        Ok(())
+    }
+
+    /// Adds a report to the homeserver admins' queue, returning its ID
+    pub async fn add_report_logic(&self, report: &EscalatedReport) -> ServerResult<u64> {
+        let report_id = loop {
+            let report_id = gen_rand_u64();
+            if !self.contains_key(&make_report_key(report_id)).await? {
+                break report_id;
+            }
+        };
+        self.put_report_logic(report_id, report).await?;
+        Ok(report_id)
+    }
+
+    /// Gets all reports in the homeserver admins' queue, along with their IDs
+    pub async fn get_reports_logic(&self) -> ServerResult<Vec<(u64, EscalatedReport)>> {
+        self.scan_prefix(REPORT_PREFIX)
+            .await
+            .try_fold(Vec::new(), |mut all, res| {
+                let (key, value) = res?;
+                // Safety: report keys are always a prefix followed by a report id
+                let report_id = u64::from_be_bytes(unsafe {
+                    key.split_at(REPORT_PREFIX.len())
+                        .1
+                        .try_into()
+                        .unwrap_unchecked()
+                });
+                all.push((report_id, db::deser_report(value)));
+                Ok(all)
+            })
+    }
+
+    /// Gets scheduled messages that are due at or before `until`, along with
+    /// their keys, send times and IDs. Earliest messages come first.
+    pub async fn get_scheduled_messages_logic(
+        &self,
+        until: u64,
+    ) -> ServerResult<Vec<([u8; 26], u64, u64, ScheduledMessage)>> {
+        let start = make_scheduled_msg_key(0, 0);
+        let end = make_scheduled_msg_key(until, u64::MAX);
+        let mut all = Vec::new();
+        for res in self
+            .chat_tree
+            .range(start.as_slice()..=end.as_slice())
+            .await
+        {
+            let (key, value) = res.map_err(ServerError::from)?;
+            let (_, ids) = key.split_at(SCHEDULED_PREFIX.len());
+            let (scheduled_for, scheduled_id) = ids.split_at(size_of::<u64>());
+            // Safety: scheduled message keys are always a prefix followed by two u64s [ref:scheduled_msg_key]
+            let (key, scheduled_for, scheduled_id) = unsafe {
+                (
+                    key.as_ref().try_into().unwrap_unchecked(),
+                    u64::from_be_bytes(scheduled_for.try_into().unwrap_unchecked()),
+                    u64::from_be_bytes(scheduled_id.try_into().unwrap_unchecked()),
+                )
+            };
+            all.push((
+                key,
+                scheduled_for,
+                scheduled_id,
+                db::deser_scheduled_message(value),
+            ));
+        }
+        Ok(all)
+    }
+
+    pub async fn get_guild_usage_logic(&self, guild_id: u64) -> ServerResult<GuildUsage> {
+        let usage = self
+            .get(make_guild_usage_key(guild_id))
+            .await?
+            .map_or_else(GuildUsage::default, db::deser_guild_usage);
+
+        Ok(usage)
+    }
+
+    /// Adds `delta` to the usage counters of a guild, or subtracts it if `add` is false.
+    /// Counters are swapped in, so updates from different channels don't get lost.
+    pub async fn update_guild_usage_logic(
+        &self,
+        guild_id: u64,
+        delta: GuildUsage,
+        add: bool,
+    ) -> ServerResult<()> {
+        let key = make_guild_usage_key(guild_id);
+        let apply = |count: &mut u64, delta: u64| {
+            *count = if add {
+                count.saturating_add(delta)
+            } else {
+                count.saturating_sub(delta)
+            };
+        };
+        loop {
+            let raw = self.get(&key).await?;
+            let mut usage = raw
+                .clone()
+                .map_or_else(GuildUsage::default, db::deser_guild_usage);
+            apply(&mut usage.message_count, delta.message_count);
+            apply(&mut usage.media_bytes, delta.media_bytes);
+            apply(&mut usage.emote_count, delta.emote_count);
+
+            let new = rkyv_ser(&usage);
+            if self
+                .compare_and_swap(
+                    &key,
+                    raw.as_ref().map(|raw| raw.as_ref()),
+                    Some(new.as_ref()),
+                )
+                .await?
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Gets the usage counters of all guilds, along with their IDs
+    pub async fn get_all_guild_usage_logic(&self) -> ServerResult<Vec<(u64, GuildUsage)>> {
+        self.scan_prefix(GUILD_USAGE_PREFIX)
+            .await
+            .try_fold(Vec::new(), |mut all, res| {
+                let (key, value) = res?;
+                // Safety: usage keys are always a prefix followed by a guild id
+                let guild_id = u64::from_be_bytes(unsafe {
+                    key.split_at(GUILD_USAGE_PREFIX.len())
+                        .1
+                        .try_into()
+                        .unwrap_unchecked()
+                });
+                all.push((guild_id, db::deser_guild_usage(value)));
+                Ok(all)
+            })
+    }
+
+    /// Gets every guild on this homeserver, along with their IDs
+    pub async fn get_all_guilds_logic(&self) -> ServerResult<Vec<(u64, Guild)>> {
+        let mut guilds = Vec::new();
+        for res in self.chat_tree.iter().await {
+            let (key, value) = res?;
+            // guilds are the only keys that are just an ID
+            if let Ok(raw_id) = key.as_ref().try_into() {
+                guilds.push((u64::from_be_bytes(raw_id), db::deser_guild(value)));
+            }
+        }
+        Ok(guilds)
     }
 
     pub async fn get_guild_invites_logic(
@@ -722,11 +2538,48 @@ impl ChatTree {
         Ok(GetGuildMembersResponse { members })
     }
 
+    /// Gets the next page of the members of a guild, ordered by user ID.
+    pub async fn get_guild_members_page_logic(
+        &self,
+        guild_id: u64,
+        pager: &Pager,
+    ) -> ServerResult<Page<u64>> {
+        let prefix = make_guild_mem_prefix(guild_id);
+        let start = make_member_key(guild_id, 0);
+        let end = make_member_key(guild_id, u64::MAX);
+        let Some((start, end)) = pager.range(&start, &end) else {
+            return Ok(Page::empty());
+        };
+        let entries = self
+            .chat_tree
+            .range(start.as_slice()..=end.as_slice())
+            .await;
+        let page = pager
+            .collect(entries, |key, _| {
+                // Safety: member keys are always a prefix followed by a u64
+                Some(u64::from_be_bytes(unsafe {
+                    key.split_at(prefix.len()).1.try_into().unwrap_unchecked()
+                }))
+            })
+            .map_err(ServerError::from)?;
+        Ok(page)
+    }
+
     pub async fn get_guild_channels_logic(
         &self,
         guild_id: u64,
         user_id: u64,
     ) -> Result<GetGuildChannelsResponse, ServerError> {
+        // role gated channels are only listed for the members who auto joined them,
+        // and for the ones managing the gates
+        let gated_channels = self
+            .get_member_gated_channels_logic(guild_id, user_id)
+            .await?;
+        let sees_all_gated = self.is_user_guild_owner(guild_id, user_id).await?
+            || self
+                .query_has_permission_logic(guild_id, None, user_id, "channels.role-gates.manage")
+                .await?;
+
         let prefix = make_guild_chan_prefix(guild_id);
         let mut channels = Vec::new();
         for res in self.scan_prefix(&prefix).await {
@@ -737,6 +2590,15 @@ impl ChatTree {
                     unsafe { key.split_at(prefix.len()).1.try_into().unwrap_unchecked() },
                 );
 
+                if !sees_all_gated
+                    && !gated_channels.contains(&channel_id)
+                    && self
+                        .contains_key(make_chan_role_gate_key(guild_id, channel_id))
+                        .await?
+                {
+                    continue;
+                }
+
                 let res_allowed = self
                     .check_perms(guild_id, Some(channel_id), user_id, "messages.view", false)
                     .await;
@@ -844,104 +2706,565 @@ impl ChatTree {
                     .find(|oid| 0.eq(*oid))
                     .unwrap_unchecked() = id;
             }
-        };
+        };
+
+        if let Some(position) = position {
+            let item_id = position.item_id;
+            match position.position() {
+                item_position::Position::After => {
+                    if let Some(index) = maybe_ord_index(item_id) {
+                        maybe_replace_with(&mut ordering, index.saturating_add(1));
+                    }
+                }
+                item_position::Position::BeforeUnspecified => {
+                    if let Some(index) = maybe_ord_index(item_id) {
+                        maybe_replace_with(&mut ordering, index);
+                    }
+                }
+            }
+        } else {
+            ordering.push(id);
+        }
+
+        let serialized_ordering = self.serialize_list_u64_logic(ordering);
+        self.insert(key, serialized_ordering).await?;
+
+        Ok(())
+    }
+
+    pub async fn get_channel_messages_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        message_id: Option<u64>,
+        direction: Option<Direction>,
+        count: Option<u32>,
+    ) -> ServerResult<GetChannelMessagesResponse> {
+        let direction = direction.unwrap_or_default();
+
+        // this is misleading... its actually the *next* message id to be used.
+        // it doesnt matter here though.
+        let last_message_id = self.get_last_message_id(guild_id, channel_id).await?;
+
+        // if 1 it means that no messages have been sent yet
+        if last_message_id == 1 {
+            return Ok(GetChannelMessagesResponse {
+                messages: Vec::new(),
+                reached_bottom: true,
+                reached_top: true,
+            });
+        }
+
+        let count = count.map_or_else(
+            || {
+                matches!(direction, Direction::Around)
+                    .then(|| 12)
+                    .unwrap_or(25)
+            },
+            |c| c as u64,
+        );
+
+        let to = message_id.unwrap_or(last_message_id);
+        let to = (to > 1).then(|| to - 1).unwrap_or(1);
+        let to_before = || (to > count).then(|| to - count).unwrap_or(1);
+        let to_after = || (to + count).min(last_message_id);
+        let (from, to) = match direction {
+            Direction::BeforeUnspecified => (to_before(), to),
+            Direction::After => (to, to_after()),
+            Direction::Around => (to_before(), to_after()),
+        };
+
+        let from_key = make_msg_key(guild_id, channel_id, from);
+        let to_key = make_msg_key(guild_id, channel_id, to);
+
+        let messages = self
+            .chat_tree
+            .range((&from_key)..=(&to_key))
+            .await
+            .rev()
+            .try_fold(Vec::new(), |mut all, res| {
+                let (key, value) = res.map_err(ServerError::from)?;
+                // Skip keys that are stored under a message key, like reactions and threads
+                if key.len() != from_key.len() {
+                    return ServerResult::Ok(all);
+                }
+                // Safety: this is safe since we checked that this is a message key, which after stripping prefix is a message ID
+                let message_id = u64::from_be_bytes(unsafe {
+                    key.split_at(make_msg_prefix(guild_id, channel_id).len())
+                        .1
+                        .try_into()
+                        .unwrap_unchecked()
+                });
+                let message = db::deser_message(value);
+                all.push(MessageWithId {
+                    message_id,
+                    message: Some(message),
+                });
+                ServerResult::Ok(all)
+            })?;
+
+        Ok(GetChannelMessagesResponse {
+            reached_top: from == 1,
+            reached_bottom: to == last_message_id,
+            messages,
+        })
+    }
+
+    /// Gets the next page of messages that come after the given position in a channel,
+    /// oldest first. Also returns the position of the latest message in the channel.
+    pub async fn get_messages_after_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        position: u64,
+        pager: &Pager,
+    ) -> ServerResult<(Page<(u64, HarmonyMessage)>, u64)> {
+        let latest_position = self.get_last_message_id(guild_id, channel_id).await? - 1;
+        if position >= latest_position {
+            return Ok((Page::empty(), latest_position));
+        }
+
+        let prefix = make_msg_prefix(guild_id, channel_id);
+        let from_key = make_msg_key(guild_id, channel_id, position + 1);
+        let to_key = make_msg_key(guild_id, channel_id, latest_position);
+        let Some((from_key, to_key)) = pager.range(&from_key, &to_key) else {
+            return Ok((Page::empty(), latest_position));
+        };
+
+        let entries = self
+            .chat_tree
+            .range(from_key.as_slice()..=to_key.as_slice())
+            .await;
+        let page = pager
+            .collect(entries, |key, value| {
+                // Skip keys that are stored under a message key, like reactions
+                if key.len() != prefix.len() + 8 {
+                    return None;
+                }
+                // Safety: this is safe since we checked that this is a message key, which after stripping prefix is a message ID
+                let message_id = u64::from_be_bytes(unsafe {
+                    key.split_at(prefix.len()).1.try_into().unwrap_unchecked()
+                });
+                Some((message_id, db::deser_message(value)))
+            })
+            .map_err(ServerError::from)?;
+
+        Ok((page, latest_position))
+    }
+
+    pub async fn get_thread_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+    ) -> ServerResult<Option<Thread>> {
+        let thread = self
+            .get(make_thread_prefix(guild_id, channel_id, message_id))
+            .await?
+            .map(db::deser_thread);
+
+        Ok(thread)
+    }
+
+    /// Starts a thread from a message. Fails if the message already has one.
+    pub async fn start_thread_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+        user_id: u64,
+        name: String,
+    ) -> ServerResult<Thread> {
+        let _guard = self.thread_lock.lock().await;
+
+        let (mut message, message_key) = self
+            .get_message_logic(guild_id, channel_id, message_id)
+            .await?;
+        if self
+            .get_thread_logic(guild_id, channel_id, message_id)
+            .await?
+            .is_some()
+        {
+            bail!(("h.thread-exists", "this message already has a thread"));
+        }
+
+        let thread = Thread {
+            name,
+            created_by: user_id,
+            created_at: get_time_secs(),
+            next_message_id: 1,
+            message_count: 0,
+            last_message_at: None,
+            subscribers: vec![user_id],
+        };
+        message
+            .metadata
+            .get_or_insert_with(Metadata::default)
+            .extension
+            .insert(
+                MESSAGE_THREAD_EXTENSION.to_string(),
+                Anything {
+                    kind: "text/plain".to_string(),
+                    body: thread.name.clone().into_bytes().into(),
+                },
+            );
+
+        let mut batch = Batch::default();
+        batch.insert(message_key, rkyv_ser(&message));
+        batch.insert(
+            make_thread_prefix(guild_id, channel_id, message_id),
+            rkyv_ser(&thread),
+        );
+        self.chat_tree
+            .apply_batch(batch)
+            .await
+            .map_err(ServerError::from)?;
+
+        Ok(thread)
+    }
+
+    /// Sends a message in the thread of a message, subscribing its author to
+    /// the thread. Returns the ID of the sent message and the updated thread.
+    pub async fn send_thread_message_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+        message: HarmonyMessage,
+    ) -> ServerResult<(u64, Thread)> {
+        let _guard = self.thread_lock.lock().await;
+
+        let Some(mut thread) = self
+            .get_thread_logic(guild_id, channel_id, message_id)
+            .await?
+        else {
+            bail!(NO_SUCH_THREAD_ERR);
+        };
+        let thread_message_id = thread.next_message_id;
+        thread.next_message_id += 1;
+        thread.message_count += 1;
+        thread.last_message_at = Some(message.created_at);
+        if !thread.subscribers.contains(&message.author_id) {
+            thread.subscribers.push(message.author_id);
+        }
+
+        let mut batch = Batch::default();
+        batch.insert(
+            make_thread_msg_key(guild_id, channel_id, message_id, thread_message_id),
+            rkyv_ser(&message),
+        );
+        batch.insert(
+            make_thread_prefix(guild_id, channel_id, message_id),
+            rkyv_ser(&thread),
+        );
+        self.chat_tree
+            .apply_batch(batch)
+            .await
+            .map_err(ServerError::from)?;
+        self.update_guild_usage_logic(guild_id, message_usage(&message), true)
+            .await?;
+
+        Ok((thread_message_id, thread))
+    }
+
+    /// Gets the next page of the messages in the thread of a message, ordered by ID.
+    pub async fn get_thread_messages_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+        pager: &Pager,
+    ) -> ServerResult<Page<(u64, HarmonyMessage)>> {
+        let prefix_len = make_thread_prefix(guild_id, channel_id, message_id).len();
+        let start = make_thread_msg_key(guild_id, channel_id, message_id, 0);
+        let end = make_thread_msg_key(guild_id, channel_id, message_id, u64::MAX);
+        let Some((start, end)) = pager.range(&start, &end) else {
+            return Ok(Page::empty());
+        };
+        let entries = self
+            .chat_tree
+            .range(start.as_slice()..=end.as_slice())
+            .await;
+        let page = pager
+            .collect(entries, |key, value| {
+                // Safety: thread message keys are always a prefix followed by a u64
+                let thread_message_id = u64::from_be_bytes(unsafe {
+                    key.split_at(prefix_len).1.try_into().unwrap_unchecked()
+                });
+                Some((thread_message_id, db::deser_message(value)))
+            })
+            .map_err(ServerError::from)?;
+        Ok(page)
+    }
+
+    /// Subscribes a user to the events of a thread, or unsubscribes them.
+    pub async fn set_thread_subscription_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+        user_id: u64,
+        subscribed: bool,
+    ) -> ServerResult<()> {
+        let _guard = self.thread_lock.lock().await;
+
+        let Some(mut thread) = self
+            .get_thread_logic(guild_id, channel_id, message_id)
+            .await?
+        else {
+            bail!(NO_SUCH_THREAD_ERR);
+        };
+        thread.subscribers.retain(|id| *id != user_id);
+        if subscribed {
+            thread.subscribers.push(user_id);
+        }
+        self.insert(
+            make_thread_prefix(guild_id, channel_id, message_id),
+            rkyv_ser(&thread),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Gets up to `max` of the oldest messages of a channel that were sent
+    /// in the given time range, oldest first. Times are in seconds since unix
+    /// epoch, `sent_after` is inclusive and `sent_before` is exclusive.
+    pub async fn get_messages_sent_between_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        sent_after: u64,
+        sent_before: u64,
+        max: usize,
+    ) -> ServerResult<Vec<(u64, HarmonyMessage)>> {
+        let prefix = make_msg_prefix(guild_id, channel_id);
+        let from_key = make_msg_key(guild_id, channel_id, 0);
+        let to_key = make_msg_key(guild_id, channel_id, u64::MAX);
+
+        let mut messages = Vec::new();
+        for res in self.chat_tree.range((&from_key)..=(&to_key)).await {
+            let (key, value) = res.map_err(ServerError::from)?;
+            if key.len() != from_key.len() {
+                continue;
+            }
+            if messages.len() >= max {
+                break;
+            }
+            let message = db::deser_message(value);
+            if message.created_at < sent_after {
+                continue;
+            }
+            // message IDs are sequential, so every message after this is newer too
+            if message.created_at >= sent_before {
+                break;
+            }
+            // Safety: this is safe since we checked that this is a message key, which after stripping prefix is a message ID
+            let message_id = u64::from_be_bytes(unsafe {
+                key.split_at(prefix.len()).1.try_into().unwrap_unchecked()
+            });
+            messages.push((message_id, message));
+        }
 
-        if let Some(position) = position {
-            let item_id = position.item_id;
-            match position.position() {
-                item_position::Position::After => {
-                    if let Some(index) = maybe_ord_index(item_id) {
-                        maybe_replace_with(&mut ordering, index.saturating_add(1));
-                    }
-                }
-                item_position::Position::BeforeUnspecified => {
-                    if let Some(index) = maybe_ord_index(item_id) {
-                        maybe_replace_with(&mut ordering, index);
-                    }
+        Ok(messages)
+    }
+
+    /// Deletes messages of a channel in one batch, along with everything
+    /// stored with them, and updates the guild's usage.
+    pub async fn delete_messages_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        messages: &[(u64, HarmonyMessage)],
+    ) -> ServerResult<()> {
+        let mut batch = Batch::default();
+        let mut usage = GuildUsage::default();
+        for (message_id, message) in messages {
+            let message_id = *message_id;
+            let msg_key = make_msg_key(guild_id, channel_id, message_id);
+            let thread_prefix = make_thread_prefix(guild_id, channel_id, message_id);
+            // Keys stored under a message key, like reactions and threads,
+            // start with the message key
+            for res in self.chat_tree.scan_prefix(&msg_key).await {
+                let (key, value) = res.map_err(ServerError::from)?;
+                if key.starts_with(&thread_prefix) && key.len() != thread_prefix.len() {
+                    let delta = message_usage(&db::deser_message(value));
+                    usage.message_count += delta.message_count;
+                    usage.media_bytes += delta.media_bytes;
+                    usage.emote_count += delta.emote_count;
                 }
+                batch.remove(key);
             }
-        } else {
-            ordering.push(id);
+            batch.remove(make_reaction_roles_key(guild_id, channel_id, message_id));
+            batch.remove(make_gallery_entry_key(guild_id, channel_id, message_id));
+            batch.remove(make_quarantine_key(guild_id, channel_id, message_id));
+            batch.remove(make_msg_acks_key(guild_id, channel_id, message_id));
+            batch.remove(make_unread_disappearing_key(
+                guild_id, channel_id, message_id,
+            ));
+
+            let delta = message_usage(message);
+            usage.message_count += delta.message_count;
+            usage.media_bytes += delta.media_bytes;
+            usage.emote_count += delta.emote_count;
         }
 
-        let serialized_ordering = self.serialize_list_u64_logic(ordering);
-        self.insert(key, serialized_ordering).await?;
+        self.chat_tree
+            .apply_batch(batch)
+            .await
+            .map_err(ServerError::DbError)?;
+        self.update_guild_usage_logic(guild_id, usage, false)
+            .await?;
 
         Ok(())
     }
 
-    pub async fn get_channel_messages_logic(
+    /// Deletes up to `max` of the oldest messages of a channel that were sent
+    /// before `sent_before`, along with everything stored with them. Returns
+    /// the deleted messages, oldest first.
+    pub async fn prune_channel_messages_logic(
         &self,
         guild_id: u64,
         channel_id: u64,
-        message_id: Option<u64>,
-        direction: Option<Direction>,
-        count: Option<u32>,
-    ) -> ServerResult<GetChannelMessagesResponse> {
-        let direction = direction.unwrap_or_default();
+        sent_before: u64,
+        max: usize,
+    ) -> ServerResult<Vec<(u64, HarmonyMessage)>> {
+        let prefix = make_msg_prefix(guild_id, channel_id);
+        let from_key = make_msg_key(guild_id, channel_id, 0);
+        let to_key = make_msg_key(guild_id, channel_id, u64::MAX);
 
-        // this is misleading... its actually the *next* message id to be used.
-        // it doesnt matter here though.
-        let last_message_id = self.get_last_message_id(guild_id, channel_id).await?;
+        let mut batch = Batch::default();
+        let mut messages = Vec::new();
+        for res in self.chat_tree.range((&from_key)..=(&to_key)).await {
+            let (key, value) = res.map_err(ServerError::from)?;
+            // Keys stored under a message key, like reactions, come right after
+            // the message and are deleted with it
+            if key.len() != from_key.len() {
+                if !messages.is_empty() {
+                    batch.remove(key);
+                }
+                continue;
+            }
+            if messages.len() >= max {
+                break;
+            }
+            let message = db::deser_message(value);
+            // message IDs are sequential, so every message after this is newer too
+            if message.created_at >= sent_before {
+                break;
+            }
+            // Safety: this is safe since we checked that this is a message key, which after stripping prefix is a message ID
+            let message_id = u64::from_be_bytes(unsafe {
+                key.split_at(prefix.len()).1.try_into().unwrap_unchecked()
+            });
+            batch.remove(key);
+            batch.remove(make_reaction_roles_key(guild_id, channel_id, message_id));
+            batch.remove(make_gallery_entry_key(guild_id, channel_id, message_id));
+            batch.remove(make_quarantine_key(guild_id, channel_id, message_id));
+            batch.remove(make_msg_acks_key(guild_id, channel_id, message_id));
+            messages.push((message_id, message));
+        }
+        if messages.is_empty() {
+            return Ok(messages);
+        }
 
-        // if 1 it means that no messages have been sent yet
-        if last_message_id == 1 {
-            return Ok(GetChannelMessagesResponse {
-                messages: Vec::new(),
-                reached_bottom: true,
-                reached_top: true,
+        self.chat_tree
+            .apply_batch(batch)
+            .await
+            .map_err(ServerError::DbError)?;
+        let usage = messages
+            .iter()
+            .fold(GuildUsage::default(), |mut usage, (_, message)| {
+                let delta = message_usage(message);
+                usage.message_count += delta.message_count;
+                usage.media_bytes += delta.media_bytes;
+                usage.emote_count += delta.emote_count;
+                usage
             });
+        self.update_guild_usage_logic(guild_id, usage, false)
+            .await?;
+
+        Ok(messages)
+    }
+
+    /// Gets the IDs of all channels of a guild.
+    pub async fn get_guild_channel_ids_logic(&self, guild_id: u64) -> ServerResult<Vec<u64>> {
+        let prefix = make_guild_chan_prefix(guild_id);
+        let mut channel_ids = Vec::new();
+        for res in self.scan_prefix(&prefix).await {
+            let (key, _) = res?;
+            if key.len() == prefix.len() + size_of::<u64>() {
+                // Safety: this unwrap is safe since we check if it's a valid u64 beforehand
+                channel_ids.push(u64::from_be_bytes(unsafe {
+                    key.split_at(prefix.len()).1.try_into().unwrap_unchecked()
+                }));
+            }
         }
+        Ok(channel_ids)
+    }
 
-        let count = count.map_or_else(
-            || {
-                matches!(direction, Direction::Around)
-                    .then(|| 12)
-                    .unwrap_or(25)
-            },
-            |c| c as u64,
-        );
+    /// Gets how many days messages are kept in a guild, if it has its own retention.
+    /// A retention of 0 days means messages are kept forever.
+    pub async fn get_guild_retention_logic(&self, guild_id: u64) -> ServerResult<Option<u64>> {
+        Ok(self
+            .get(make_guild_retention_key(guild_id))
+            .await?
+            .and_then(|raw| db::make_u64_iter_logic(&raw).next()))
+    }
 
-        let to = message_id.unwrap_or(last_message_id);
-        let to = (to > 1).then(|| to - 1).unwrap_or(1);
-        let to_before = || (to > count).then(|| to - count).unwrap_or(1);
-        let to_after = || (to + count).min(last_message_id);
-        let (from, to) = match direction {
-            Direction::BeforeUnspecified => (to_before(), to),
-            Direction::After => (to, to_after()),
-            Direction::Around => (to_before(), to_after()),
+    /// Sets how many days messages are kept in a guild, or removes its own
+    /// retention so the server wide one is used.
+    pub async fn set_guild_retention_logic(
+        &self,
+        guild_id: u64,
+        days: Option<u64>,
+    ) -> ServerResult<()> {
+        let key = make_guild_retention_key(guild_id);
+        match days {
+            Some(days) => self.insert(key, days.to_be_bytes()).await?,
+            None => self.remove(key).await?,
         };
+        Ok(())
+    }
 
-        let from_key = make_msg_key(guild_id, channel_id, from);
-        let to_key = make_msg_key(guild_id, channel_id, to);
-
-        let messages = self
-            .chat_tree
-            .range((&from_key)..=(&to_key))
-            .await
-            .rev()
-            .try_fold(Vec::new(), |mut all, res| {
-                let (key, value) = res.map_err(ServerError::from)?;
-                // Safety: this is safe since the only keys we get are message keys, which after stripping prefix are message IDs
-                let message_id = u64::from_be_bytes(unsafe {
-                    key.split_at(make_msg_prefix(guild_id, channel_id).len())
-                        .1
-                        .try_into()
-                        .unwrap_unchecked()
-                });
-                let message = db::deser_message(value);
-                all.push(MessageWithId {
-                    message_id,
-                    message: Some(message),
-                });
-                ServerResult::Ok(all)
-            })?;
+    /// Gets the latest `count` messages of a channel, newest first, optionally
+    /// only the ones sent by `author_id`
+    pub async fn get_latest_messages_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        author_id: Option<u64>,
+        count: u64,
+    ) -> ServerResult<Vec<(u64, HarmonyMessage)>> {
+        let prefix = make_msg_prefix(guild_id, channel_id);
+        let from_key = make_msg_key(guild_id, channel_id, 0);
+        let to_key = make_msg_key(guild_id, channel_id, u64::MAX);
+
+        let mut messages = Vec::new();
+        if count == 0 {
+            return Ok(messages);
+        }
+        for res in self.chat_tree.range((&from_key)..=(&to_key)).await.rev() {
+            let (key, value) = res.map_err(ServerError::from)?;
+            // Skip keys that are stored under a message key, like reactions
+            if key.len() != from_key.len() {
+                continue;
+            }
+            let message = db::deser_message(value);
+            if author_id.map_or(false, |author_id| message.author_id != author_id) {
+                continue;
+            }
+            // Safety: this is safe since we checked that this is a message key, which after stripping prefix is a message ID
+            let message_id = u64::from_be_bytes(unsafe {
+                key.split_at(prefix.len()).1.try_into().unwrap_unchecked()
+            });
+            messages.push((message_id, message));
+            if messages.len() as u64 >= count {
+                break;
+            }
+        }
 
-        Ok(GetChannelMessagesResponse {
-            reached_top: from == 1,
-            reached_bottom: to == last_message_id,
-            messages,
-        })
+        Ok(messages)
     }
 
     pub async fn get_user_roles_logic(
@@ -1024,10 +3347,84 @@ impl ChatTree {
         })
     }
 
+    /// Position of the highest role a user has in the guild's role ordering.
+    ///
+    /// Roles later in the ordering are higher, the "everyone" role being the
+    /// first one. Returns `None` if the user has no roles in the ordering.
+    pub async fn highest_role_position_logic(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+    ) -> Result<Option<usize>, ServerError> {
+        let ordering = self
+            .get_list_u64_logic(&make_guild_role_ordering_key(guild_id))
+            .await?;
+        let user_roles = self
+            .get_list_u64_logic(&make_guild_user_roles_key(guild_id, user_id))
+            .await?;
+        Ok(user_roles
+            .iter()
+            .filter_map(|role_id| ordering.iter().position(|id| id == role_id))
+            .max())
+    }
+
+    /// Checks that a user's highest role is above the highest role of the
+    /// member they are acting on, and above every role in `role_ids`.
+    ///
+    /// Guild owners are above everyone except other owners.
+    pub async fn check_role_hierarchy(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        target_id: Option<u64>,
+        role_ids: &[u64],
+    ) -> Result<(), ServerError> {
+        let target_is_owner = match target_id {
+            Some(target_id) => self.is_user_guild_owner(guild_id, target_id).await?,
+            None => false,
+        };
+        if self.is_user_guild_owner(guild_id, user_id).await? {
+            return if target_is_owner && target_id != Some(user_id) {
+                Err(ServerError::RoleHierarchy)
+            } else {
+                Ok(())
+            };
+        }
+        if target_is_owner {
+            return Err(ServerError::RoleHierarchy);
+        }
+
+        let Some(position) = self.highest_role_position_logic(guild_id, user_id).await? else {
+            return Err(ServerError::RoleHierarchy);
+        };
+        if let Some(target_id) = target_id.filter(|id| *id != user_id) {
+            let target_position = self
+                .highest_role_position_logic(guild_id, target_id)
+                .await?;
+            if target_position >= Some(position) {
+                return Err(ServerError::RoleHierarchy);
+            }
+        }
+        if role_ids.is_empty().not() {
+            let ordering = self
+                .get_list_u64_logic(&make_guild_role_ordering_key(guild_id))
+                .await?;
+            for role_id in role_ids {
+                let role_position = ordering.iter().position(|id| id == role_id);
+                if role_position.map_or(false, |role_position| role_position >= position) {
+                    return Err(ServerError::RoleHierarchy);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     pub async fn kick_user_logic(&self, guild_id: u64, user_id: u64) -> ServerResult<()> {
         let mut batch = Batch::default();
         batch.remove(make_member_key(guild_id, user_id));
         batch.remove(make_guild_user_roles_key(guild_id, user_id));
+        batch.remove(make_member_gated_channels_key(guild_id, user_id));
         self.chat_tree
             .apply_batch(batch)
             .await
@@ -1035,30 +3432,250 @@ impl ChatTree {
             .map_err(Into::into)
     }
 
-    pub async fn manage_user_roles_logic(
+    pub async fn manage_user_roles_logic(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        give_role_ids: Vec<u64>,
+        take_role_ids: Vec<u64>,
+    ) -> ServerResult<Vec<u64>> {
+        let mut roles = self.get_user_roles_logic(guild_id, user_id).await?;
+        for role_id in give_role_ids {
+            self.does_role_exist(guild_id, role_id).await?;
+            if roles.contains(&role_id).not() {
+                self.check_role_member_cap(guild_id, role_id).await?;
+            }
+            roles.push(role_id);
+        }
+        for role_id in take_role_ids {
+            self.does_role_exist(guild_id, role_id).await?;
+            if let Some(index) = roles.iter().position(|oid| role_id.eq(oid)) {
+                roles.remove(index);
+            }
+        }
+
+        let key = make_guild_user_roles_key(guild_id, user_id);
+        let ser_roles = self.serialize_list_u64_logic(roles.clone());
+        self.insert(key, ser_roles).await?;
+        self.update_member_gated_channels_logic(guild_id, user_id, &roles)
+            .await?;
+
+        Ok(roles)
+    }
+
+    /// Gives and takes roles of many users in a single batch, returning the
+    /// new role IDs of every user whose roles changed
+    pub async fn bulk_manage_user_roles_logic(
+        &self,
+        guild_id: u64,
+        changes: Vec<(u64, Vec<u64>, Vec<u64>)>,
+    ) -> ServerResult<Vec<(u64, Vec<u64>)>> {
+        let mut role_ids = changes
+            .iter()
+            .flat_map(|(_, give, take)| give.iter().chain(take.iter()).copied())
+            .collect::<Vec<_>>();
+        role_ids.sort_unstable();
+        role_ids.dedup();
+        let mut free_slots = HashMap::with_capacity(role_ids.len());
+        for role_id in role_ids {
+            self.does_role_exist(guild_id, role_id).await?;
+            if let Some(cap) = self.get_role_member_cap_logic(guild_id, role_id).await? {
+                let member_count = self.count_role_members_logic(guild_id, role_id).await?;
+                free_slots.insert(role_id, cap.saturating_sub(member_count));
+            }
+        }
+
+        let mut batch = Batch::default();
+        let mut updates = Vec::with_capacity(changes.len());
+        for (user_id, give_role_ids, take_role_ids) in changes {
+            let mut roles = self.get_user_roles_logic(guild_id, user_id).await?;
+            let mut changed = false;
+            for role_id in take_role_ids {
+                if let Some(index) = roles.iter().position(|oid| role_id.eq(oid)) {
+                    roles.remove(index);
+                    changed = true;
+                    if let Some(slots) = free_slots.get_mut(&role_id) {
+                        *slots += 1;
+                    }
+                }
+            }
+            for role_id in give_role_ids {
+                if roles.contains(&role_id) {
+                    continue;
+                }
+                if let Some(slots) = free_slots.get_mut(&role_id) {
+                    if *slots == 0 {
+                        bail!(ServerError::RoleMemberCapReached(role_id));
+                    }
+                    *slots -= 1;
+                }
+                roles.push(role_id);
+                changed = true;
+            }
+
+            if changed {
+                let key = make_guild_user_roles_key(guild_id, user_id);
+                batch.insert(key, self.serialize_list_u64_logic(roles.clone()));
+                updates.push((user_id, roles));
+            }
+        }
+        self.apply_batch(batch).await?;
+        for (user_id, roles) in &updates {
+            self.update_member_gated_channels_logic(guild_id, *user_id, roles)
+                .await?;
+        }
+
+        Ok(updates)
+    }
+
+    /// Gets the roles whose members auto join a channel. Empty if the channel
+    /// isn't role gated.
+    pub async fn get_channel_role_gate_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+    ) -> ServerResult<Vec<u64>> {
+        self.get_list_u64_logic(&make_chan_role_gate_key(guild_id, channel_id))
+            .await
+    }
+
+    /// Sets the roles whose members auto join a channel. An empty list makes
+    /// everyone join the channel.
+    ///
+    /// Only members of the roles that were added or removed are updated.
+    pub async fn set_channel_role_gate_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        mut role_ids: Vec<u64>,
+    ) -> ServerResult<()> {
+        role_ids.sort_unstable();
+        role_ids.dedup();
+        let old_role_ids = self
+            .get_channel_role_gate_logic(guild_id, channel_id)
+            .await?;
+
+        let mut changed_role_ids = Vec::new();
+        for role_id in old_role_ids.iter().filter(|id| !role_ids.contains(*id)) {
+            let key = make_role_gated_channels_key(guild_id, *role_id);
+            let mut channel_ids = self.get_list_u64_logic(&key).await?;
+            channel_ids.retain(|id| *id != channel_id);
+            self.put_list_u64_or_remove(&key, channel_ids).await?;
+            changed_role_ids.push(*role_id);
+        }
+        for role_id in role_ids.iter().filter(|id| !old_role_ids.contains(*id)) {
+            let key = make_role_gated_channels_key(guild_id, *role_id);
+            let mut channel_ids = self.get_list_u64_logic(&key).await?;
+            channel_ids.push(channel_id);
+            self.insert(key, self.serialize_list_u64_logic(channel_ids))
+                .await?;
+            changed_role_ids.push(*role_id);
+        }
+        self.put_list_u64_or_remove(&make_chan_role_gate_key(guild_id, channel_id), role_ids)
+            .await?;
+
+        self.recalculate_gated_channels_for_roles(guild_id, &changed_role_ids)
+            .await
+    }
+
+    /// Removes a role from the gates of every channel it gates, updating its members.
+    pub async fn remove_role_from_gates_logic(
+        &self,
+        guild_id: u64,
+        role_id: u64,
+    ) -> ServerResult<()> {
+        let key = make_role_gated_channels_key(guild_id, role_id);
+        let channel_ids = self.get_list_u64_logic(&key).await?;
+        if channel_ids.is_empty() {
+            return Ok(());
+        }
+        for channel_id in channel_ids {
+            let gate_key = make_chan_role_gate_key(guild_id, channel_id);
+            let mut role_ids = self.get_list_u64_logic(&gate_key).await?;
+            role_ids.retain(|id| *id != role_id);
+            self.put_list_u64_or_remove(&gate_key, role_ids).await?;
+        }
+        self.remove(key).await?;
+
+        self.recalculate_gated_channels_for_roles(guild_id, &[role_id])
+            .await
+    }
+
+    /// Gets the role gated channels a member auto joined.
+    pub async fn get_member_gated_channels_logic(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+    ) -> ServerResult<Vec<u64>> {
+        self.get_list_u64_logic(&make_member_gated_channels_key(guild_id, user_id))
+            .await
+    }
+
+    /// Recalculates the role gated channels a member auto joined from their roles.
+    pub async fn update_member_gated_channels_logic(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        role_ids: &[u64],
+    ) -> ServerResult<()> {
+        let mut channel_ids = Vec::new();
+        for role_id in role_ids {
+            let key = make_role_gated_channels_key(guild_id, *role_id);
+            channel_ids.extend(self.get_list_u64_logic(&key).await?);
+        }
+        channel_ids.sort_unstable();
+        channel_ids.dedup();
+
+        self.put_list_u64_or_remove(
+            &make_member_gated_channels_key(guild_id, user_id),
+            channel_ids,
+        )
+        .await
+    }
+
+    /// Recalculates the role gated channels of the members that have any of the given roles.
+    async fn recalculate_gated_channels_for_roles(
         &self,
         guild_id: u64,
-        user_id: u64,
-        give_role_ids: Vec<u64>,
-        take_role_ids: Vec<u64>,
-    ) -> ServerResult<Vec<u64>> {
-        let mut roles = self.get_user_roles_logic(guild_id, user_id).await?;
-        for role_id in give_role_ids {
-            self.does_role_exist(guild_id, role_id).await?;
-            roles.push(role_id);
+        role_ids: &[u64],
+    ) -> ServerResult<()> {
+        if role_ids.is_empty() {
+            return Ok(());
         }
-        for role_id in take_role_ids {
-            self.does_role_exist(guild_id, role_id).await?;
-            if let Some(index) = roles.iter().position(|oid| role_id.eq(oid)) {
-                roles.remove(index);
-            }
+
+        let prefix = make_guild_user_roles_prefix(guild_id);
+        let members = self
+            .scan_prefix(&prefix)
+            .await
+            .try_fold(Vec::new(), |mut all, res| {
+                let (key, value) = res?;
+                let roles = db::make_u64_iter_logic(&value).collect::<Vec<_>>();
+                if roles.iter().any(|id| role_ids.contains(id)) {
+                    // Safety: user roles keys are the prefix followed by a user ID
+                    let user_id = u64::from_be_bytes(unsafe {
+                        key.split_at(prefix.len()).1.try_into().unwrap_unchecked()
+                    });
+                    all.push((user_id, roles));
+                }
+                ServerResult::Ok(all)
+            })?;
+        for (user_id, roles) in members {
+            self.update_member_gated_channels_logic(guild_id, user_id, &roles)
+                .await?;
         }
 
-        let key = make_guild_user_roles_key(guild_id, user_id);
-        let ser_roles = self.serialize_list_u64_logic(roles.clone());
-        self.insert(key, ser_roles).await?;
+        Ok(())
+    }
 
-        Ok(roles)
+    /// Stores a list of IDs, removing the key instead if the list is empty.
+    async fn put_list_u64_or_remove(&self, key: &[u8], list: Vec<u64>) -> ServerResult<()> {
+        if list.is_empty() {
+            self.remove(key).await?;
+        } else {
+            self.insert(key, self.serialize_list_u64_logic(list))
+                .await?;
+        }
+        Ok(())
     }
 
     pub async fn add_default_role_to(&self, guild_id: u64, user_id: u64) -> ServerResult<()> {
@@ -1111,9 +3728,41 @@ impl ChatTree {
             .apply_batch(batch)
             .await
             .map_err(ServerError::from)?;
+        self.evict_cached_permissions(|key| *key == (guild_id, channel_id, role_id));
         Ok(())
     }
 
+    /// Sets permissions of a role in the channels synced with a category, in
+    /// one batch. Returns the synced channels.
+    pub async fn set_synced_permissions_logic(
+        &self,
+        guild_id: u64,
+        category_id: u64,
+        role_id: u64,
+        perms_to_give: &[Permission],
+    ) -> ServerResult<Vec<u64>> {
+        let channel_ids = self
+            .get_synced_channels_logic(guild_id, category_id)
+            .await?;
+        if channel_ids.is_empty() {
+            return Ok(channel_ids);
+        }
+        let mut batch = Batch::default();
+        for channel_id in &channel_ids {
+            for perm in perms_to_give {
+                batch.insert(
+                    make_channel_perm_key(guild_id, *channel_id, role_id, &perm.matches),
+                    perm.ok.then(|| [1]).unwrap_or([0]),
+                );
+            }
+        }
+        self.apply_batch(batch).await?;
+        self.evict_cached_permissions(|(gid, cid, rid)| {
+            *gid == guild_id && *rid == role_id && cid.map_or(false, |id| channel_ids.contains(&id))
+        });
+        Ok(channel_ids)
+    }
+
     pub async fn create_channel_logic(
         &self,
         guild_id: u64,
@@ -1267,6 +3916,248 @@ impl ChatTree {
         Ok(())
     }
 
+    /// Increments the use count of an invite, failing if it has no uses left.
+    ///
+    /// The count is written with a compare and swap, which is retried if the
+    /// invite changed in between. This way two joins racing each other can't
+    /// both take the last use, even from processes sharing the database.
+    pub async fn use_invite_logic(&self, invite_id: &str) -> ServerResult<u64> {
+        let key = make_invite_key(invite_id);
+
+        loop {
+            let Some(raw) = self.get(&key).await? else {
+                bail!(ServerError::NoSuchInvite(invite_id.into()));
+            };
+            let (guild_id, mut invite) = db::deser_invite_entry(raw.clone());
+
+            if invite.possible_uses != 0 && invite.use_count >= invite.possible_uses {
+                bail!(ServerError::InviteExpired);
+            }
+            self.check_invite_not_expired(invite_id).await?;
+            invite.use_count += 1;
+
+            let buf = rkyv_ser(&invite);
+            let new = [guild_id.to_be_bytes().as_ref(), buf.as_ref()].concat();
+            if self
+                .compare_and_swap(&key, Some(raw.as_ref()), Some(&new))
+                .await?
+            {
+                return Ok(guild_id);
+            }
+        }
+    }
+
+    /// Gives back a use of an invite, for a join that failed after using it.
+    pub async fn release_invite_use_logic(
+        &self,
+        invite_id: &str,
+        guild_id: u64,
+    ) -> ServerResult<()> {
+        let key = make_invite_key(invite_id);
+
+        loop {
+            // the invite could have been deleted or recreated in the meantime
+            let Some(raw) = self.get(&key).await? else {
+                return Ok(());
+            };
+            let (invite_guild_id, mut invite) = db::deser_invite_entry(raw.clone());
+            if invite_guild_id != guild_id || invite.use_count == 0 {
+                return Ok(());
+            }
+            invite.use_count -= 1;
+
+            let buf = rkyv_ser(&invite);
+            let new = [guild_id.to_be_bytes().as_ref(), buf.as_ref()].concat();
+            if self
+                .compare_and_swap(&key, Some(raw.as_ref()), Some(&new))
+                .await?
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Makes sure an invite exists and belongs to the given guild
+    pub async fn check_guild_invite(&self, guild_id: u64, invite_id: &str) -> ServerResult<()> {
+        let is_guild_invite = self
+            .get(make_invite_key(invite_id))
+            .await?
+            .map_or(false, |raw| {
+                db::deser_invite_entry_guild_id(&raw) == guild_id
+            });
+        if is_guild_invite.not() {
+            bail!(ServerError::NoSuchInvite(invite_id.into()));
+        }
+
+        Ok(())
+    }
+
+    pub async fn get_invite_role_grant_logic(
+        &self,
+        invite_id: &str,
+    ) -> ServerResult<Option<InviteRoleGrant>> {
+        let grant = self
+            .get(make_invite_grant_key(invite_id))
+            .await?
+            .map(db::deser_invite_role_grant);
+
+        Ok(grant)
+    }
+
+    pub async fn get_invite_info_logic(&self, invite_id: &str) -> ServerResult<Option<InviteInfo>> {
+        let info = self
+            .get(make_invite_info_key(invite_id))
+            .await?
+            .map(db::deser_invite_info);
+
+        Ok(info)
+    }
+
+    /// Sets who made an invite and when it expires, or removes that if `info` is `None`
+    pub async fn set_invite_info_logic(
+        &self,
+        invite_id: &str,
+        info: Option<InviteInfo>,
+    ) -> ServerResult<()> {
+        let key = make_invite_info_key(invite_id);
+        match info {
+            Some(info) => self.insert(key, rkyv_ser(&info)).await?,
+            None => self.remove(key).await?,
+        };
+
+        Ok(())
+    }
+
+    /// Fails with [`ServerError::InviteExpired`] if the invite is past its expiry time
+    pub async fn check_invite_not_expired(&self, invite_id: &str) -> ServerResult<()> {
+        let expired = self
+            .get_invite_info_logic(invite_id)
+            .await?
+            .and_then(|info| info.expires_at)
+            .map_or(false, |expires_at| expires_at <= get_time_secs());
+        if expired {
+            bail!(ServerError::InviteExpired);
+        }
+
+        Ok(())
+    }
+
+    /// Gets the latest uses of an invite, oldest first
+    pub async fn get_invite_uses_logic(&self, invite_id: &str) -> ServerResult<Vec<InviteUse>> {
+        let uses = self
+            .get(make_invite_uses_key(invite_id))
+            .await?
+            .map(db::deser_invite_uses)
+            .unwrap_or_default();
+
+        Ok(uses)
+    }
+
+    /// Adds a use to the usage history of an invite, dropping the oldest one
+    /// if the history is full
+    pub async fn record_invite_use_logic(&self, invite_id: &str, user_id: u64) -> ServerResult<()> {
+        let mut uses = self.get_invite_uses_logic(invite_id).await?;
+        if uses.len() >= MAX_INVITE_USE_HISTORY {
+            uses.drain(..=uses.len() - MAX_INVITE_USE_HISTORY);
+        }
+        uses.push(InviteUse {
+            user_id,
+            used_at: get_time_secs(),
+        });
+        self.insert(make_invite_uses_key(invite_id), rkyv_ser(&uses))
+            .await?;
+
+        Ok(())
+    }
+
+    /// Sets the role given to users joining with an invite, or removes it if `grant` is `None`
+    pub async fn set_invite_role_grant_logic(
+        &self,
+        invite_id: &str,
+        grant: Option<InviteRoleGrant>,
+    ) -> ServerResult<()> {
+        let key = make_invite_grant_key(invite_id);
+        match grant {
+            Some(grant) => self.insert(key, rkyv_ser(&grant)).await?,
+            None => self.remove(key).await?,
+        };
+
+        Ok(())
+    }
+
+    pub async fn get_role_member_cap_logic(
+        &self,
+        guild_id: u64,
+        role_id: u64,
+    ) -> ServerResult<Option<u64>> {
+        let cap = self
+            .get(make_role_member_cap_key(guild_id, role_id))
+            .await?
+            // Safety: caps are always stored as a u64
+            .map(|raw| u64::from_be_bytes(unsafe { raw.as_ref().try_into().unwrap_unchecked() }));
+
+        Ok(cap)
+    }
+
+    /// Gets all member caps of roles in a guild, as pairs of role ID and cap
+    pub async fn get_role_member_caps_logic(&self, guild_id: u64) -> ServerResult<Vec<(u64, u64)>> {
+        let prefix = make_role_member_cap_prefix(guild_id);
+        self.scan_prefix(&prefix)
+            .await
+            .try_fold(Vec::new(), |mut all, res| {
+                let (key, value) = res?;
+                // Safety: the unwraps cannot fail since keys end with a role ID and caps are stored as a u64
+                let role_id = u64::from_be_bytes(unsafe {
+                    key.split_at(prefix.len()).1.try_into().unwrap_unchecked()
+                });
+                let cap =
+                    u64::from_be_bytes(unsafe { value.as_ref().try_into().unwrap_unchecked() });
+                all.push((role_id, cap));
+                ServerResult::Ok(all)
+            })
+    }
+
+    /// Sets the maximum number of members a role can have, or removes the cap if `cap` is `None`
+    pub async fn set_role_member_cap_logic(
+        &self,
+        guild_id: u64,
+        role_id: u64,
+        cap: Option<u64>,
+    ) -> ServerResult<()> {
+        let key = make_role_member_cap_key(guild_id, role_id);
+        match cap {
+            Some(cap) => self.insert(key, cap.to_be_bytes()).await?,
+            None => self.remove(key).await?,
+        };
+
+        Ok(())
+    }
+
+    pub async fn count_role_members_logic(&self, guild_id: u64, role_id: u64) -> ServerResult<u64> {
+        let prefix = make_guild_user_roles_prefix(guild_id);
+        self.scan_prefix(&prefix).await.try_fold(0, |count, res| {
+            let (_, value) = res?;
+            let has_role = value
+                .chunks_exact(size_of::<u64>())
+                // Safety: this is safe since we split at u64 boundary
+                .any(|raw| {
+                    u64::from_be_bytes(unsafe { raw.try_into().unwrap_unchecked() }) == role_id
+                });
+            ServerResult::Ok(count + has_role as u64)
+        })
+    }
+
+    /// Makes sure a role has room for another member, if it has a member cap
+    pub async fn check_role_member_cap(&self, guild_id: u64, role_id: u64) -> ServerResult<()> {
+        if let Some(cap) = self.get_role_member_cap_logic(guild_id, role_id).await? {
+            if self.count_role_members_logic(guild_id, role_id).await? >= cap {
+                bail!(ServerError::RoleMemberCapReached(role_id));
+            }
+        }
+
+        Ok(())
+    }
+
     /// Calculates all users which can "see" the given user
     pub async fn calculate_users_seeing_user(&self, user_id: u64) -> ServerResult<Vec<u64>> {
         let prefix = make_guild_list_key_prefix(user_id);
@@ -1347,6 +4238,29 @@ impl ChatTree {
         guild_id: u64,
         channel_id: Option<u64>,
         role_id: u64,
+    ) -> Result<Vec<(SmolStr, bool)>, ServerError> {
+        let cache_key = (guild_id, channel_id, role_id);
+        if let Some(perms) = self.permission_cache.get(&cache_key) {
+            return Ok(perms.clone());
+        }
+
+        let generation = self.permission_cache_generation.load(Ordering::SeqCst);
+        let perms = self
+            .scan_permissions_logic(guild_id, channel_id, role_id)
+            .await?;
+        self.permission_cache.insert(cache_key, perms.clone());
+        // permissions might have changed while they were being read
+        if self.permission_cache_generation.load(Ordering::SeqCst) != generation {
+            self.permission_cache.remove(&cache_key);
+        }
+        Ok(perms)
+    }
+
+    async fn scan_permissions_logic(
+        &self,
+        guild_id: u64,
+        channel_id: Option<u64>,
+        role_id: u64,
     ) -> Result<Vec<(SmolStr, bool)>, ServerError> {
         if let Some(channel_id) = channel_id {
             let prefix = make_role_channel_perms_prefix(guild_id, channel_id, role_id);
@@ -1412,19 +4326,10 @@ impl ChatTree {
         })
     }
 
-    pub async fn get_next_message_id(
-        &self,
-        guild_id: u64,
-        channel_id: u64,
-    ) -> Result<u64, ServerError> {
-        let next_id_key = make_next_msg_id_key(guild_id, channel_id);
-        let id = self.get_last_message_id(guild_id, channel_id).await?;
-        self.chat_tree
-            .insert(&next_id_key, &(id + 1).to_be_bytes())
-            .await?;
-        Ok(id)
-    }
-
+    /// Allocates the ID of the next message in a channel.
+    ///
+    /// Message IDs start at 1 and are strictly increasing in a channel, so they
+    /// also serve as the position of the message in the channel.
     pub async fn get_last_message_id(
         &self,
         guild_id: u64,
@@ -1456,9 +4361,6 @@ impl ChatTree {
             metadata,
         } = request;
 
-        let message_id = self.get_next_message_id(guild_id, channel_id).await?;
-        let key = make_msg_key(guild_id, channel_id, message_id); // [tag:msg_key_u64]
-
         let created_at = get_time_secs();
         let edited_at = None;
 
@@ -1473,8 +4375,60 @@ impl ChatTree {
             reactions: Vec::new(),
         };
 
-        let value = db::rkyv_ser(&message);
-        self.insert(key, value).await?;
+        let is_gallery_entry =
+            is_media_message(&message) && self.is_gallery_channel(guild_id, channel_id).await?;
+        let disappearing = self
+            .get_disappearing_messages_logic(guild_id, channel_id)
+            .await?;
+
+        // the message ID is allocated and everything about the message is written under the
+        // channel's lock, so that the next message ID, the message and its indexes are always
+        // written together
+        let lock_key = (guild_id, channel_id);
+        let lock = self.message_id_locks.entry(lock_key).or_default().clone();
+        let guard = lock.lock().await;
+        let message_id = self.get_last_message_id(guild_id, channel_id).await?;
+
+        let mut batch = Batch::default();
+        batch.insert(
+            make_next_msg_id_key(guild_id, channel_id),
+            (message_id + 1).to_be_bytes(),
+        );
+        // [tag:msg_key_u64]
+        batch.insert(
+            make_msg_key(guild_id, channel_id, message_id),
+            db::rkyv_ser(&message),
+        );
+        if is_gallery_entry {
+            batch.insert(make_gallery_entry_key(guild_id, channel_id, message_id), []);
+        }
+        if let Some(settings) = disappearing {
+            match settings.after {
+                DisappearAfter::Send => {
+                    let expires_at = created_at.saturating_add(settings.ttl_secs);
+                    batch.insert(
+                        make_disappear_expiry_key(expires_at, guild_id, channel_id, message_id),
+                        [],
+                    );
+                }
+                DisappearAfter::Read => {
+                    // [tag:unread_disappearing_key]
+                    let value = [user_id.to_be_bytes(), settings.ttl_secs.to_be_bytes()].concat();
+                    batch.insert(
+                        make_unread_disappearing_key(guild_id, channel_id, message_id),
+                        value,
+                    );
+                }
+            }
+        }
+        self.apply_batch(batch).await?;
+        drop(guard);
+        // the map holds one reference, and this task another
+        self.message_id_locks
+            .remove_if(&lock_key, |_, lock| MessageIdLock::strong_count(lock) <= 2);
+
+        self.update_guild_usage_logic(guild_id, message_usage(&message), true)
+            .await?;
 
         Ok((message_id, message))
     }
@@ -1509,7 +4463,7 @@ impl ChatTree {
     pub async fn process_message_content(
         &self,
         content: Option<Content>,
-        media_root: &Path,
+        media_store: &dyn MediaStore,
         host: &str,
     ) -> ServerResult<Content> {
         use content::Content as MsgContent;
@@ -1543,24 +4497,33 @@ impl ChatTree {
                             };
 
                             let image_jpeg_id = format!("{}_{}", id, "jpeg");
-                            let image_jpeg_path = media_root.join(&image_jpeg_id);
                             let minithumbnail_jpeg_id = format!("{}_{}", id, "jpegthumb");
-                            let minithumbnail_jpeg_path = media_root.join(&minithumbnail_jpeg_id);
 
-                            let ((file_size, isize), minithumbnail) = if image_jpeg_path.exists()
-                                && minithumbnail_jpeg_path.exists()
+                            let ((file_size, isize), minithumbnail) = if media_store
+                                .exists(&image_jpeg_id)
+                                .await?
+                                && media_store.exists(&minithumbnail_jpeg_id).await?
                             {
-                                let minithumbnail_jpeg = tokio::fs::read(&minithumbnail_jpeg_path)
+                                let mut minithumbnail_jpeg = Vec::new();
+                                media_store
+                                    .get(&minithumbnail_jpeg_id)
+                                    .await?
+                                    .reader
+                                    .read_to_end(&mut minithumbnail_jpeg)
                                     .await
                                     .map_err(ServerError::from)?;
 
-                                let ifile = tokio::fs::File::open(&image_jpeg_path)
+                                let StoredMedia {
+                                    reader: mut ifile,
+                                    len: file_size,
+                                } = media_store.get(&image_jpeg_id).await?;
+                                let mut image_jpeg = Vec::with_capacity(file_size as usize);
+                                ifile
+                                    .read_to_end(&mut image_jpeg)
                                     .await
                                     .map_err(ServerError::from)?;
-                                let file_size =
-                                    ifile.metadata().await.map_err(ServerError::from)?.len();
-                                let ifile = BufReader::new(ifile.into_std().await);
-                                let mut ireader = image::io::Reader::new(ifile);
+                                let mut ireader =
+                                    image::io::Reader::new(std::io::Cursor::new(image_jpeg));
                                 ireader.set_format(FORMAT);
                                 // this should be cheap and shouldnt block...
                                 let isize = ireader
@@ -1576,7 +4539,7 @@ impl ChatTree {
                                     },
                                 )
                             } else {
-                                let (_, _, data, _) = get_file_full(media_root, id).await?;
+                                let (_, _, data, _) = get_file_full(media_store, id).await?;
 
                                 let (image, image_jpeg) = tokio::task::spawn_blocking(move || {
                                     let guessed_format = image::guess_format(&data)
@@ -1594,9 +4557,10 @@ impl ChatTree {
                                 .await
                                 .expect("failed to join task")?;
 
-                                tokio::fs::write(&image_jpeg_path, &image_jpeg)
-                                    .await
-                                    .map_err(ServerError::from)?;
+                                let image_jpeg_len = image_jpeg.len();
+                                media_store
+                                    .put(&image_jpeg_id, reader_from_vec(image_jpeg))
+                                    .await?;
 
                                 let (image, minithumbnail, minithumbnail_jpeg) =
                                     tokio::task::spawn_blocking(move || {
@@ -1611,13 +4575,16 @@ impl ChatTree {
                                     .await
                                     .expect("task panicked")?;
 
-                                tokio::fs::write(&minithumbnail_jpeg_path, &minithumbnail_jpeg)
-                                    .await
-                                    .map_err(ServerError::from)?;
+                                media_store
+                                    .put(
+                                        &minithumbnail_jpeg_id,
+                                        reader_from_vec(minithumbnail_jpeg.clone()),
+                                    )
+                                    .await?;
 
                                 let minithumb_size = minithumbnail.dimensions();
                                 (
-                                    (image_jpeg.len() as u32, image.dimensions()),
+                                    (image_jpeg_len as u32, image.dimensions()),
                                     Minithumbnail {
                                         width: minithumb_size.0,
                                         height: minithumb_size.1,
@@ -1648,15 +4615,11 @@ impl ChatTree {
                         if let Ok(id) = FileId::from_str(&attachment.id) {
                             let fill_file_local = move |attachment: Attachment, id: String| async move {
                                 let is_jpeg = is_id_jpeg(&id);
-                                let (mut file, metadata) = get_file_handle(media_root, &id).await?;
+                                let StoredMedia { reader, len } = media_store.get(&id).await?;
                                 let (filename_raw, mimetype_raw, _) =
-                                    read_bufs(&mut file, is_jpeg).await?;
-                                let (start, end) = calculate_range(
-                                    &filename_raw,
-                                    &mimetype_raw,
-                                    &metadata,
-                                    is_jpeg,
-                                );
+                                    read_bufs(reader, is_jpeg).await?;
+                                let (start, end) =
+                                    calculate_range(&filename_raw, &mimetype_raw, len, is_jpeg);
                                 let size = end - start;
 
                                 Result::<_, ServerError>::Ok(Attachment {
@@ -1749,6 +4712,10 @@ impl ChatTree {
         self.send_message_logic(0, request).await
     }
 
+    /// Adds or removes the reaction of a user to a message. Returns the reaction
+    /// with its new count, or `None` if nothing changed. Reactions are removed
+    /// from the message once nobody reacts with them anymore.
+    #[allow(clippy::too_many_arguments)]
     pub async fn update_reaction(
         &self,
         user_id: u64,
@@ -1757,7 +4724,10 @@ impl ChatTree {
         message_id: u64,
         emote: Emote,
         add: bool,
+        max_reactions: usize,
     ) -> ServerResult<Option<Reaction>> {
+        let _guard = self.reaction_lock.lock().await;
+
         let react_key =
             make_user_reacted_msg_key(guild_id, channel_id, message_id, user_id, &emote.image_id);
         let reacted = self
@@ -1778,19 +4748,30 @@ impl ChatTree {
             .await?;
 
         let mut batch = Batch::default();
-        let reaction = if let Some(reaction) = message.reactions.iter_mut().find(|r| {
+        let existing = message.reactions.iter().position(|r| {
             r.emote
                 .as_ref()
                 .map_or(false, |e| e.image_id == emote.image_id)
-        }) {
+        });
+        let reaction = if let Some(index) = existing {
+            let reaction = &mut message.reactions[index];
             reaction.count = add
                 .then(|| reaction.count.saturating_add(1))
                 .unwrap_or_else(|| reaction.count.saturating_sub(1));
-            if reaction.count == 0 {
+            let reaction = reaction.clone();
+            if add {
+                batch.insert(react_key, Vec::new());
+            } else {
                 batch.remove(react_key);
             }
-            Some(reaction.clone())
+            if reaction.count == 0 {
+                message.reactions.remove(index);
+            }
+            Some(reaction)
         } else if add {
+            if message.reactions.len() >= max_reactions {
+                bail!(ServerError::TooManyReactions(max_reactions));
+            }
             let reaction = Reaction {
                 count: 1,
                 emote: Some(emote),
@@ -1809,9 +4790,91 @@ impl ChatTree {
             .await
             .map_err(ServerError::from)?;
 
+        if reaction.is_some() {
+            let delta = GuildUsage {
+                emote_count: 1,
+                ..Default::default()
+            };
+            self.update_guild_usage_logic(guild_id, delta, add).await?;
+        }
+
         Ok(reaction)
     }
 
+    /// Gets the next page of the users that reacted to a message with an emote,
+    /// ordered by user ID.
+    pub async fn get_reactors_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+        image_id: &str,
+        pager: &Pager,
+    ) -> ServerResult<Page<u64>> {
+        let prefix = [
+            make_msg_key(guild_id, channel_id, message_id).as_ref(),
+            &[0],
+        ]
+        .concat();
+        let start = prefix.clone();
+        let end = [
+            make_msg_key(guild_id, channel_id, message_id).as_ref(),
+            &[1],
+        ]
+        .concat();
+        let Some((start, end)) = pager.range(&start, &end) else {
+            return Ok(Page::empty());
+        };
+        let entries = self
+            .chat_tree
+            .range(start.as_slice()..=end.as_slice())
+            .await;
+        let page = pager
+            .collect(entries, |key, _| {
+                let rest = key.strip_prefix(prefix.as_slice())?;
+                if rest.len() < 8 || &rest[8..] != image_id.as_bytes() {
+                    return None;
+                }
+                // Safety: we checked that there are at least 8 bytes, which are the user ID
+                Some(u64::from_be_bytes(unsafe {
+                    rest[..8].try_into().unwrap_unchecked()
+                }))
+            })
+            .map_err(ServerError::from)?;
+        Ok(page)
+    }
+
+    pub async fn get_reaction_roles_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+    ) -> ServerResult<Vec<ReactionRole>> {
+        let bindings = self
+            .get(make_reaction_roles_key(guild_id, channel_id, message_id))
+            .await?
+            .map_or_else(Vec::new, db::deser_reaction_roles);
+
+        Ok(bindings)
+    }
+
+    pub async fn put_reaction_roles_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+        bindings: Vec<ReactionRole>,
+    ) -> ServerResult<()> {
+        let key = make_reaction_roles_key(guild_id, channel_id, message_id);
+        if bindings.is_empty() {
+            self.remove(key).await?;
+        } else {
+            self.insert(key, rkyv_ser(&bindings)).await?;
+        }
+
+        Ok(())
+    }
+
     pub async fn get_pinned_messages_logic(
         &self,
         guild_id: u64,
@@ -1831,3 +4894,70 @@ impl ChatTree {
         }))
     }
 }
+
+/// Sets an extension in the metadata of a channel, or removes it if `value` is `None`.
+///
+/// Returns the new metadata of the channel.
+fn set_channel_metadata_extension(
+    chan_info: &mut Channel,
+    name: &str,
+    value: Option<Anything>,
+) -> Metadata {
+    let metadata = chan_info.metadata.get_or_insert_with(Metadata::default);
+    match value {
+        Some(value) => {
+            metadata.extension.insert(name.to_string(), value);
+        }
+        None => {
+            metadata.extension.remove(name);
+        }
+    }
+    metadata.clone()
+}
+
+/// Serializes a value to JSON, to be put in a metadata extension.
+fn json_extension(value: &impl serde::Serialize) -> Result<Anything, ServerError> {
+    let body = serde_json::to_vec(value).map_err(|err| {
+        tracing::error!("couldn't serialize metadata extension: {}", err);
+        ServerError::InternalServerError
+    })?;
+    Ok(Anything {
+        kind: "application/json".to_string(),
+        body: body.into(),
+    })
+}
+
+/// Checks if a message is made of attachments or photos, and has at least one of them.
+pub fn is_media_message(message: &HarmonyMessage) -> bool {
+    match message.content.as_ref().and_then(|c| c.content.as_ref()) {
+        Some(content::Content::AttachmentMessage(files)) => !files.files.is_empty(),
+        Some(content::Content::PhotoMessage(photos)) => !photos.photos.is_empty(),
+        _ => false,
+    }
+}
+
+/// Calculates the resources a message uses, to be added to its guild's usage.
+pub fn message_usage(message: &HarmonyMessage) -> GuildUsage {
+    let media_bytes = match message.content.as_ref().and_then(|c| c.content.as_ref()) {
+        Some(content::Content::AttachmentMessage(files)) => {
+            files.files.iter().map(|file| u64::from(file.size)).sum()
+        }
+        Some(content::Content::PhotoMessage(photos)) => photos
+            .photos
+            .iter()
+            .map(|photo| u64::from(photo.file_size))
+            .sum(),
+        _ => 0,
+    };
+    let emote_count = message
+        .reactions
+        .iter()
+        .map(|reaction| u64::from(reaction.count))
+        .sum();
+
+    GuildUsage {
+        message_count: 1,
+        media_bytes,
+        emote_count,
+    }
+}
@@ -0,0 +1,204 @@
+//! Shared ban lists guilds can subscribe to. Bans made by a list remember the
+//! lists that made them, so they can be undone once the user is taken off
+//! those lists or the guild unsubscribes from them.
+
+use std::collections::HashSet;
+
+use super::{ban_user::ban_user_logic, *};
+
+/// Maximum amount of ban lists a guild can subscribe to.
+pub const MAX_BAN_LIST_SUBSCRIPTIONS: usize = 10;
+
+/// Loads the users listed by a ban list source.
+pub async fn load_source(
+    deps: &Dependencies,
+    source: &BanListSource,
+) -> ServerResult<HashSet<u64>> {
+    match source {
+        BanListSource::File { name } => {
+            let Some(path) = deps.config.policy.ban_lists.files.get(name) else {
+                bail!(("h.no-such-ban-list", format!("no ban list named {}", name)));
+            };
+            let file = tokio::fs::read_to_string(path)
+                .await
+                .map_err(ServerError::from)?;
+            Ok(parse_user_ids(file.lines()))
+        }
+        BanListSource::Guild { guild_id } => {
+            // Only bans the guild made itself are listed, so that guilds
+            // subscribed to each other can't keep a ban alive between them
+            let chat_tree = &deps.chat_tree;
+            let list_bans = chat_tree.get_ban_provenances_logic(*guild_id).await?;
+            let banned = chat_tree.get_banned_users_logic(*guild_id).await?;
+            Ok(banned
+                .into_iter()
+                .filter(|user_id| !list_bans.contains_key(user_id))
+                .collect())
+        }
+    }
+}
+
+/// Parses lines of a ban list file. Empty lines and lines starting with `#`
+/// are ignored, and anything after the user ID on a line is treated as a comment.
+fn parse_user_ids<'a>(lines: impl Iterator<Item = &'a str>) -> HashSet<u64> {
+    lines
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_whitespace().next()?.parse().ok())
+        .collect()
+}
+
+/// Applies the ban lists a guild is subscribed to, banning users added to
+/// them and unbanning users that aren't on any of them anymore. Users the
+/// guild banned itself are left alone.
+///
+/// Lists that can't be loaded are skipped, keeping the bans they made.
+pub async fn sync_guild(svc: &ChatServer, guild_id: u64) -> ServerResult<()> {
+    let deps = &svc.deps;
+    let chat_tree = &deps.chat_tree;
+
+    let mut failed = Vec::new();
+    let mut listed: HashMap<u64, Vec<BanListSource>> = HashMap::new();
+    for subscription in chat_tree.get_ban_list_subscriptions_logic(guild_id).await? {
+        match load_source(deps, &subscription.source).await {
+            Ok(user_ids) => {
+                for user_id in user_ids {
+                    listed
+                        .entry(user_id)
+                        .or_default()
+                        .push(subscription.source.clone());
+                }
+            }
+            Err(err) => {
+                tracing::warn!(
+                    "couldn't load ban list {:?} for guild {}: {}",
+                    subscription.source,
+                    guild_id,
+                    err
+                );
+                failed.push(subscription.source);
+            }
+        }
+    }
+
+    let owners = chat_tree.get_guild_owners(guild_id).await?;
+    let current = chat_tree.get_ban_provenances_logic(guild_id).await?;
+
+    for (user_id, sources) in &current {
+        if listed.contains_key(user_id) {
+            continue;
+        }
+        let kept = sources
+            .iter()
+            .filter(|source| failed.contains(source))
+            .cloned()
+            .collect::<Vec<_>>();
+        if kept.is_empty() {
+            chat_tree.unban_user_logic(guild_id, *user_id).await?;
+            audit::record(
+                deps,
+                guild_id,
+                0,
+                AuditAction::UserUnbanned { user_id: *user_id },
+            )
+            .await?;
+        } else if kept.len() != sources.len() {
+            chat_tree
+                .set_ban_provenance_logic(guild_id, *user_id, kept)
+                .await?;
+        }
+    }
+
+    for (user_id, mut sources) in listed {
+        if owners.contains(&user_id) {
+            continue;
+        }
+        match current.get(&user_id) {
+            Some(current_sources) => {
+                sources.extend(
+                    current_sources
+                        .iter()
+                        .filter(|source| failed.contains(source))
+                        .cloned(),
+                );
+                let changed = sources.len() != current_sources.len()
+                    || sources
+                        .iter()
+                        .any(|source| !current_sources.contains(source));
+                if changed {
+                    chat_tree
+                        .set_ban_provenance_logic(guild_id, user_id, sources)
+                        .await?;
+                }
+            }
+            None => {
+                if chat_tree.is_user_banned_in_guild(guild_id, user_id).await? {
+                    continue;
+                }
+                ban(svc, guild_id, user_id).await?;
+                chat_tree
+                    .set_ban_provenance_logic(guild_id, user_id, sources)
+                    .await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Bans a user for a ban list, kicking them first if they are in the guild.
+async fn ban(svc: &ChatServer, guild_id: u64, user_id: u64) -> ServerResult<()> {
+    let chat_tree = &svc.deps.chat_tree;
+
+    if chat_tree
+        .contains_key(make_member_key(guild_id, user_id))
+        .await?
+    {
+        return ban_user_logic(svc, guild_id, 0, user_id, String::new(), None).await;
+    }
+
+    let ban = BanInfo {
+        banned_by: 0,
+        banned_at: get_time_secs(),
+        reason: String::new(),
+        expires_at: None,
+    };
+    chat_tree.insert_ban_logic(guild_id, user_id, &ban).await?;
+    audit::record(&svc.deps, guild_id, 0, AuditAction::UserBanned { user_id }).await?;
+
+    Ok(())
+}
+
+/// Applies the ban lists of all subscribed guilds. Subscriptions of guilds
+/// that don't exist anymore are removed.
+pub async fn sync_all(svc: &ChatServer) -> ServerResult<()> {
+    let chat_tree = &svc.deps.chat_tree;
+
+    for guild_id in chat_tree.get_ban_list_subscribed_guilds_logic().await? {
+        if !chat_tree.contains_key(guild_id.to_be_bytes()).await? {
+            chat_tree
+                .set_ban_list_subscriptions_logic(guild_id, Vec::new())
+                .await?;
+            continue;
+        }
+        if let Err(err) = sync_guild(svc, guild_id).await {
+            tracing::warn!("couldn't apply ban lists of guild {}: {}", guild_id, err);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_user_ids;
+
+    #[test]
+    fn parses_ban_list_files() {
+        let user_ids =
+            parse_user_ids("# spammers\n123\n  456 spam bot\n\nnot-an-id\n123\n".lines());
+        assert_eq!(user_ids.len(), 2);
+        assert!(user_ids.contains(&123));
+        assert!(user_ids.contains(&456));
+    }
+}
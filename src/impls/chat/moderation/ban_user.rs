@@ -1,5 +1,6 @@
 use super::*;
 
+#[require_perms(guild, "user.manage.ban")]
 pub async fn handler(
     svc: &ChatServer,
     request: Request<BanUserRequest>,
@@ -17,33 +18,55 @@ pub async fn handler(
 
     let chat_tree = &svc.deps.chat_tree;
 
-    chat_tree.check_guild_user(guild_id, user_id).await?;
     chat_tree.is_user_in_guild(guild_id, user_to_ban).await?;
     chat_tree
-        .check_perms(guild_id, None, user_id, "user.manage.ban", false)
+        .check_role_hierarchy(guild_id, user_id, Some(user_to_ban), &[])
         .await?;
 
-    chat_tree.kick_user_logic(guild_id, user_to_ban).await?;
+    ban_user_logic(svc, guild_id, user_id, user_to_ban, String::new(), None).await?;
 
-    chat_tree
-        .insert(
-            make_banned_member_key(guild_id, user_to_ban),
-            get_time_secs().to_be_bytes(),
-        )
-        .await?;
+    Ok((BanUserResponse {}).into_response())
+}
+
+/// Kicks a user from a guild and bans them from joining it again.
+///
+/// `banned_by` is recorded in the audit log, 0 if the ban wasn't made by a guild member.
+/// The ban is lifted at `expires_at` if it is set.
+pub async fn ban_user_logic(
+    svc: &ChatServer,
+    guild_id: u64,
+    banned_by: u64,
+    user_id: u64,
+    reason: String,
+    expires_at: Option<u64>,
+) -> ServerResult<()> {
+    let chat_tree = &svc.deps.chat_tree;
 
-    svc.send_event_through_chan(
-        EventSub::Guild(guild_id),
-        stream_event::Event::LeftMember(stream_event::MemberLeft {
-            guild_id,
-            member_id: user_to_ban,
-            leave_reason: LeaveReason::Banned.into(),
-        }),
-        None,
-        EventContext::empty(),
-    );
+    chat_tree.kick_user_logic(guild_id, user_id).await?;
 
-    svc.dispatch_guild_leave(guild_id, user_to_ban).await?;
+    let ban = BanInfo {
+        banned_by,
+        banned_at: get_time_secs(),
+        reason,
+        expires_at,
+    };
+    chat_tree.insert_ban_logic(guild_id, user_id, &ban).await?;
+    audit::record(
+        &svc.deps,
+        guild_id,
+        banned_by,
+        AuditAction::UserBanned { user_id },
+    )
+    .await?;
 
-    Ok((BanUserResponse {}).into_response())
+    svc.deps.event_bus.publish(DomainEvent::MemberLeft {
+        guild_id,
+        user_id,
+        reason: LeaveReason::Banned,
+        ban_reason: (!ban.reason.is_empty()).then(|| ban.reason),
+    });
+
+    svc.dispatch_guild_leave(guild_id, user_id).await?;
+
+    Ok(())
 }
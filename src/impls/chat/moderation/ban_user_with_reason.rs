@@ -0,0 +1,67 @@
+use super::{ban_user::ban_user_logic, *};
+
+use serde::{Deserialize, Serialize};
+
+/// Longest reason a ban can be given, in bytes.
+const MAX_BAN_REASON_LEN: usize = 512;
+
+#[derive(Debug, Deserialize)]
+pub struct BanUserWithReasonRequest {
+    pub guild_id: u64,
+    pub user_id: u64,
+    #[serde(default)]
+    pub reason: String,
+    /// How long the ban lasts for, in seconds. The ban is permanent if not set.
+    #[serde(default)]
+    pub duration_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BanUserWithReasonResponse {
+    /// When the ban is lifted, in seconds since unix epoch. Not set for permanent bans.
+    pub expires_at: Option<u64>,
+}
+
+/// Bans a user like `BanUser`, recording why they were banned and optionally
+/// lifting the ban after a while.
+#[require_perms(guild, "user.manage.ban")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: BanUserWithReasonRequest,
+) -> ServerResult<BanUserWithReasonResponse> {
+    let BanUserWithReasonRequest {
+        guild_id,
+        user_id: user_to_ban,
+        reason,
+        duration_secs,
+    } = request;
+
+    if user_id == user_to_ban {
+        bail!(ServerError::CantKickOrBanYourself);
+    }
+    if reason.len() > MAX_BAN_REASON_LEN {
+        bail!((
+            "h.ban-reason-too-long",
+            format!(
+                "ban reason can't be longer than {} bytes",
+                MAX_BAN_REASON_LEN
+            )
+        ));
+    }
+    if duration_secs == Some(0) {
+        bail!(("h.invalid-ban-duration", "ban duration can't be zero"));
+    }
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree.is_user_in_guild(guild_id, user_to_ban).await?;
+    chat_tree
+        .check_role_hierarchy(guild_id, user_id, Some(user_to_ban), &[])
+        .await?;
+
+    let expires_at = duration_secs.map(|secs| get_time_secs().saturating_add(secs));
+    ban_user_logic(svc, guild_id, user_id, user_to_ban, reason, expires_at).await?;
+
+    Ok(BanUserWithReasonResponse { expires_at })
+}
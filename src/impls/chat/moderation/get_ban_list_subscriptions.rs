@@ -0,0 +1,69 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct GetBanListSubscriptionsRequest {
+    pub guild_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BanListSubscriptionInfo {
+    pub source: BanListSource,
+    pub subscribed_by: u64,
+    /// In seconds since unix epoch.
+    pub subscribed_at: u64,
+    /// How many users of the guild are banned by this list.
+    pub banned_count: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetBanListSubscriptionsResponse {
+    pub subscriptions: Vec<BanListSubscriptionInfo>,
+    /// Names of the ban list files the guild can subscribe to.
+    pub available_files: Vec<String>,
+}
+
+/// Gets the ban lists a guild is subscribed to.
+#[require_perms(guild, "user.manage.ban")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetBanListSubscriptionsRequest,
+) -> ServerResult<GetBanListSubscriptionsResponse> {
+    let GetBanListSubscriptionsRequest { guild_id } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let provenances = chat_tree.get_ban_provenances_logic(guild_id).await?;
+    let subscriptions = chat_tree
+        .get_ban_list_subscriptions_logic(guild_id)
+        .await?
+        .into_iter()
+        .map(|subscription| BanListSubscriptionInfo {
+            banned_count: provenances
+                .values()
+                .filter(|sources| sources.contains(&subscription.source))
+                .count(),
+            source: subscription.source,
+            subscribed_by: subscription.subscribed_by,
+            subscribed_at: subscription.subscribed_at,
+        })
+        .collect();
+
+    let mut available_files = svc
+        .deps
+        .config
+        .policy
+        .ban_lists
+        .files
+        .keys()
+        .cloned()
+        .collect::<Vec<_>>();
+    available_files.sort_unstable();
+
+    Ok(GetBanListSubscriptionsResponse {
+        subscriptions,
+        available_files,
+    })
+}
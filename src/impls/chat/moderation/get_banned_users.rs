@@ -8,21 +8,7 @@ pub async fn handler(
 
     let GetBannedUsersRequest { guild_id } = request.into_message().await?;
 
-    let prefix = make_guild_banned_mem_prefix(guild_id);
-    let banned_users =
-        svc.deps
-            .chat_tree
-            .scan_prefix(&prefix)
-            .await
-            .try_fold(Vec::new(), |mut all, res| {
-                let (key, _) = res?;
-                if key.len() == make_banned_member_key(0, 0).len() {
-                    all.push(u64::from_be_bytes(unsafe {
-                        key.split_at(prefix.len()).1.try_into().unwrap_unchecked()
-                    }));
-                }
-                ServerResult::Ok(all)
-            })?;
+    let banned_users = svc.deps.chat_tree.get_banned_users_logic(guild_id).await?;
 
     Ok((GetBannedUsersResponse { banned_users }).into_response())
 }
@@ -0,0 +1,93 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{db::chat::make_banned_member_key, utils::cursor::Direction};
+
+/// Most bans returned at once.
+const MAX_BANS_PER_PAGE: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct GetBansRequest {
+    pub guild_id: u64,
+    /// Only bans of users with an ID higher than this are returned.
+    #[serde(default)]
+    pub after: Option<u64>,
+    /// `next_cursor` of the previous page.
+    #[serde(default)]
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Ban {
+    pub user_id: u64,
+    /// 0 if the ban wasn't made by a guild member.
+    pub banned_by: u64,
+    /// In seconds since unix epoch.
+    pub banned_at: u64,
+    pub reason: String,
+    /// In seconds since unix epoch. Not set for permanent bans.
+    pub expires_at: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetBansResponse {
+    pub bans: Vec<Ban>,
+    /// What to send as `after` to get the next page, not set if this is the last page.
+    pub next: Option<u64>,
+    /// Cursor to get the next page with, not set if this is the last page.
+    pub next_cursor: Option<String>,
+}
+
+/// Gets the bans of a guild, with who made them, why and until when.
+#[require_perms(guild, "user.manage.ban")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetBansRequest,
+) -> ServerResult<GetBansResponse> {
+    let GetBansRequest {
+        guild_id,
+        after,
+        cursor,
+        limit,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let limit = limit
+        .unwrap_or(MAX_BANS_PER_PAGE)
+        .clamp(1, MAX_BANS_PER_PAGE);
+    let pager = Pager::new(
+        Direction::Forward,
+        guild_id.to_be_bytes(),
+        cursor.as_deref(),
+        limit,
+    )?
+    .or_after(after.map(|after| make_banned_member_key(guild_id, after).to_vec()));
+    let page = chat_tree.get_bans_logic(guild_id, &pager).await?;
+    let bans = page
+        .items
+        .into_iter()
+        .map(|(user_id, ban)| Ban {
+            user_id,
+            banned_by: ban.banned_by,
+            banned_at: ban.banned_at,
+            reason: ban.reason,
+            expires_at: ban.expires_at,
+        })
+        .collect::<Vec<_>>();
+    let next = page
+        .next_cursor
+        .is_some()
+        .then(|| bans.last().map(|ban| ban.user_id))
+        .flatten();
+
+    Ok(GetBansResponse {
+        bans,
+        next,
+        next_cursor: page.next_cursor,
+    })
+}
@@ -0,0 +1,37 @@
+use super::*;
+
+use std::sync::atomic::Ordering;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct GetDomainBlocklistStatsRequest {}
+
+#[derive(Debug, Serialize)]
+pub struct GetDomainBlocklistStatsResponse {
+    pub blocked_domains: usize,
+    pub blocked_messages: u64,
+    pub flagged_messages: u64,
+    pub quarantined_messages: u64,
+    pub blocked_links: u64,
+}
+
+/// Gets statistics about the domain blocklist since the server started.
+/// Only homeserver admins can use this.
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    _request: GetDomainBlocklistStatsRequest,
+) -> ServerResult<GetDomainBlocklistStatsResponse> {
+    svc.deps.chat_tree.check_homeserver_admin(user_id).await?;
+
+    let blocklist = &svc.deps.domain_blocklist;
+
+    Ok(GetDomainBlocklistStatsResponse {
+        blocked_domains: blocklist.len(),
+        blocked_messages: blocklist.blocked_messages.load(Ordering::Relaxed),
+        flagged_messages: blocklist.flagged_messages.load(Ordering::Relaxed),
+        quarantined_messages: blocklist.quarantined_messages.load(Ordering::Relaxed),
+        blocked_links: blocklist.blocked_links.load(Ordering::Relaxed),
+    })
+}
@@ -1,5 +1,6 @@
 use super::*;
 
+#[require_perms(guild, "user.manage.kick")]
 pub async fn handler(
     svc: &ChatServer,
     request: Request<KickUserRequest>,
@@ -17,24 +18,28 @@ pub async fn handler(
 
     let chat_tree = &svc.deps.chat_tree;
 
-    chat_tree.check_guild_user(guild_id, user_id).await?;
     chat_tree.is_user_in_guild(guild_id, user_to_kick).await?;
     chat_tree
-        .check_perms(guild_id, None, user_id, "user.manage.kick", false)
+        .check_role_hierarchy(guild_id, user_id, Some(user_to_kick), &[])
         .await?;
 
     chat_tree.kick_user_logic(guild_id, user_to_kick).await?;
-
-    svc.send_event_through_chan(
-        EventSub::Guild(guild_id),
-        stream_event::Event::LeftMember(stream_event::MemberLeft {
-            guild_id,
-            member_id: user_to_kick,
-            leave_reason: LeaveReason::Kicked.into(),
-        }),
-        None,
-        EventContext::empty(),
-    );
+    audit::record(
+        &svc.deps,
+        guild_id,
+        user_id,
+        AuditAction::UserKicked {
+            user_id: user_to_kick,
+        },
+    )
+    .await?;
+
+    svc.deps.event_bus.publish(DomainEvent::MemberLeft {
+        guild_id,
+        user_id: user_to_kick,
+        reason: LeaveReason::Kicked,
+        ban_reason: None,
+    });
 
     svc.dispatch_guild_leave(guild_id, user_to_kick).await?;
 
@@ -1,6 +1,13 @@
 use super::*;
 
+pub mod ban_lists;
 pub mod ban_user;
+pub mod ban_user_with_reason;
+pub mod get_ban_list_subscriptions;
 pub mod get_banned_users;
+pub mod get_bans;
+pub mod get_domain_blocklist_stats;
 pub mod kick_user;
+pub mod subscribe_ban_list;
 pub mod unban_user;
+pub mod unsubscribe_ban_list;
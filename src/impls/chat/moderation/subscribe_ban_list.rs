@@ -0,0 +1,78 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct SubscribeBanListRequest {
+    pub guild_id: u64,
+    pub source: BanListSource,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SubscribeBanListResponse {}
+
+/// Subscribes a guild to a ban list, banning the users on it right away.
+#[require_perms(guild, "user.manage.ban")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: SubscribeBanListRequest,
+) -> ServerResult<SubscribeBanListResponse> {
+    let SubscribeBanListRequest { guild_id, source } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    match &source {
+        BanListSource::File { name } => {
+            if !svc.deps.config.policy.ban_lists.files.contains_key(name) {
+                bail!(("h.no-such-ban-list", format!("no ban list named {}", name)));
+            }
+        }
+        BanListSource::Guild {
+            guild_id: source_guild_id,
+        } => {
+            if *source_guild_id == guild_id {
+                bail!((
+                    "h.invalid-ban-list",
+                    "a guild can't subscribe to its own bans"
+                ));
+            }
+            // only members can see who a guild banned
+            chat_tree
+                .check_guild_user(*source_guild_id, user_id)
+                .await?;
+        }
+    }
+
+    let mut subscriptions = chat_tree.get_ban_list_subscriptions_logic(guild_id).await?;
+    if subscriptions
+        .iter()
+        .any(|subscription| subscription.source == source)
+    {
+        bail!((
+            "h.already-subscribed",
+            "the guild is already subscribed to this ban list"
+        ));
+    }
+    if subscriptions.len() >= ban_lists::MAX_BAN_LIST_SUBSCRIPTIONS {
+        bail!((
+            "h.too-many-ban-lists",
+            format!(
+                "guilds can't subscribe to more than {} ban lists",
+                ban_lists::MAX_BAN_LIST_SUBSCRIPTIONS
+            )
+        ));
+    }
+    subscriptions.push(BanListSubscription {
+        source,
+        subscribed_by: user_id,
+        subscribed_at: get_time_secs(),
+    });
+    chat_tree
+        .set_ban_list_subscriptions_logic(guild_id, subscriptions)
+        .await?;
+
+    ban_lists::sync_guild(svc, guild_id).await?;
+
+    Ok(SubscribeBanListResponse {})
+}
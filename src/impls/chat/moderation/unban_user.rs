@@ -1,5 +1,6 @@
 use super::*;
 
+#[require_perms(guild, "user.manage.unban")]
 pub async fn handler(
     svc: &ChatServer,
     request: Request<UnbanUserRequest>,
@@ -13,14 +14,42 @@ pub async fn handler(
 
     let chat_tree = &svc.deps.chat_tree;
 
-    chat_tree.check_guild_user(guild_id, user_id).await?;
-    chat_tree
-        .check_perms(guild_id, None, user_id, "user.manage.unban", false)
-        .await?;
+    chat_tree.unban_user_logic(guild_id, user_to_unban).await?;
+    audit::record(
+        &svc.deps,
+        guild_id,
+        user_id,
+        AuditAction::UserUnbanned {
+            user_id: user_to_unban,
+        },
+    )
+    .await?;
+
+    Ok((UnbanUserResponse {}).into_response())
+}
 
-    chat_tree
-        .remove(make_banned_member_key(guild_id, user_to_unban))
+/// How often temporary bans are checked for expiry.
+pub const BAN_EXPIRY_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Lifts the temporary bans that have expired.
+pub async fn lift_expired_bans(svc: &ChatServer) -> ServerResult<()> {
+    let chat_tree = &svc.deps.chat_tree;
+
+    for (guild_id, user_id) in chat_tree.get_expired_bans_logic(get_time_secs()).await? {
+        chat_tree.unban_user_logic(guild_id, user_id).await?;
+        // the guild might have been deleted since the ban was made
+        if chat_tree.does_guild_exist(guild_id).await.is_err() {
+            continue;
+        }
+        audit::record(
+            &svc.deps,
+            guild_id,
+            0,
+            AuditAction::UserUnbanned { user_id },
+        )
         .await?;
+        tracing::debug!("ban of user {} in guild {} expired", user_id, guild_id);
+    }
 
-    Ok((UnbanUserResponse {}).into_response())
+    Ok(())
 }
@@ -0,0 +1,41 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct UnsubscribeBanListRequest {
+    pub guild_id: u64,
+    pub source: BanListSource,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnsubscribeBanListResponse {}
+
+/// Unsubscribes a guild from a ban list, unbanning the users only it banned.
+#[require_perms(guild, "user.manage.ban")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: UnsubscribeBanListRequest,
+) -> ServerResult<UnsubscribeBanListResponse> {
+    let UnsubscribeBanListRequest { guild_id, source } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let mut subscriptions = chat_tree.get_ban_list_subscriptions_logic(guild_id).await?;
+    let len = subscriptions.len();
+    subscriptions.retain(|subscription| subscription.source != source);
+    if subscriptions.len() == len {
+        bail!((
+            "h.not-subscribed",
+            "the guild isn't subscribed to this ban list"
+        ));
+    }
+    chat_tree
+        .set_ban_list_subscriptions_logic(guild_id, subscriptions)
+        .await?;
+
+    ban_lists::sync_guild(svc, guild_id).await?;
+
+    Ok(UnsubscribeBanListResponse {})
+}
@@ -1,5 +1,6 @@
 use super::*;
 
+#[require_perms(guild, "roles.manage")]
 pub async fn handler(
     svc: &ChatServer,
     request: Request<AddGuildRoleRequest>,
@@ -17,11 +18,6 @@ pub async fn handler(
 
     let chat_tree = &svc.deps.chat_tree;
 
-    chat_tree.check_guild_user(guild_id, user_id).await?;
-    chat_tree
-        .check_perms(guild_id, None, user_id, "roles.manage", false)
-        .await?;
-
     let role = Role {
         name: name.clone(),
         color,
@@ -29,19 +25,24 @@ pub async fn handler(
         pingable,
     };
     let role_id = chat_tree.add_guild_role_logic(guild_id, None, role).await?;
-    svc.send_event_through_chan(
-        EventSub::Guild(guild_id),
-        stream_event::Event::RoleCreated(stream_event::RoleCreated {
-            guild_id,
+    audit::record(
+        &svc.deps,
+        guild_id,
+        user_id,
+        AuditAction::RoleCreated {
             role_id,
-            name,
-            color,
-            hoist,
-            pingable,
-        }),
-        None,
-        EventContext::empty(),
-    );
+            name: name.clone(),
+        },
+    )
+    .await?;
+    svc.deps.event_bus.publish(DomainEvent::RoleCreated {
+        guild_id,
+        role_id,
+        name,
+        color,
+        hoist,
+        pingable,
+    });
 
     Ok((AddGuildRoleResponse { role_id }).into_response())
 }
@@ -0,0 +1,72 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct ApplyPermissionPresetRequest {
+    pub guild_id: u64,
+    #[serde(default)]
+    pub channel_id: Option<u64>,
+    pub role_id: u64,
+    pub preset: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApplyPermissionPresetResponse {
+    /// The permissions the preset expanded to
+    pub applied_permissions: Vec<PresetPermission>,
+}
+
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: ApplyPermissionPresetRequest,
+) -> ServerResult<ApplyPermissionPresetResponse> {
+    let ApplyPermissionPresetRequest {
+        guild_id,
+        channel_id,
+        role_id,
+        preset,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    match channel_id {
+        Some(channel_id) => {
+            chat_tree
+                .check_guild_user_channel(guild_id, user_id, channel_id)
+                .await?
+        }
+        None => chat_tree.check_guild_user(guild_id, user_id).await?,
+    }
+    chat_tree
+        .check_perms(
+            guild_id,
+            channel_id,
+            user_id,
+            "permissions.manage.set",
+            false,
+        )
+        .await?;
+    chat_tree.does_role_exist(guild_id, role_id).await?;
+
+    let perms = expand_permission_preset(&svc.deps.config, &preset)
+        .ok_or_else(|| ServerError::NoSuchPermissionPreset(preset.into()))?;
+    if perms.is_empty() {
+        bail!(ServerError::NoPermissionsSpecified);
+    }
+
+    set_permissions::set_permissions_and_notify(
+        svc,
+        user_id,
+        guild_id,
+        channel_id,
+        role_id,
+        perms.clone(),
+    )
+    .await?;
+
+    Ok(ApplyPermissionPresetResponse {
+        applied_permissions: perms.into_iter().map(PresetPermission::from).collect(),
+    })
+}
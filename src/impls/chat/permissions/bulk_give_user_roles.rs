@@ -0,0 +1,75 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of roles that can be given in one request.
+const MAX_ROLES: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct BulkGiveUserRolesRequest {
+    pub guild_id: u64,
+    pub user_id: u64,
+    pub role_ids: Vec<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkGiveUserRolesResponse {
+    pub new_role_ids: Vec<u64>,
+}
+
+/// Gives many roles to a user at once. Either all roles are given or none are.
+#[require_perms(guild, "roles.user.manage")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: BulkGiveUserRolesRequest,
+) -> ServerResult<BulkGiveUserRolesResponse> {
+    let BulkGiveUserRolesRequest {
+        guild_id,
+        user_id: member_id,
+        role_ids,
+    } = request;
+
+    if role_ids.len() > MAX_ROLES {
+        bail!((
+            "h.too-many-roles",
+            format!("at most {} roles can be given at once", MAX_ROLES)
+        ));
+    }
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree.is_user_in_guild(guild_id, member_id).await?;
+    chat_tree
+        .check_role_hierarchy(guild_id, user_id, Some(member_id), &role_ids)
+        .await?;
+
+    let updates = chat_tree
+        .bulk_manage_user_roles_logic(guild_id, vec![(member_id, role_ids.clone(), Vec::new())])
+        .await?;
+    if updates.is_empty().not() {
+        audit::record(
+            &svc.deps,
+            guild_id,
+            user_id,
+            AuditAction::UserRolesUpdated {
+                user_id: member_id,
+                given_role_ids: role_ids,
+                taken_role_ids: Vec::new(),
+            },
+        )
+        .await?;
+    }
+    let new_role_ids = match updates.first() {
+        Some((_, new_role_ids)) => new_role_ids.clone(),
+        None => chat_tree.get_user_roles_logic(guild_id, member_id).await?,
+    };
+
+    if updates.is_empty().not() {
+        svc.deps
+            .event_bus
+            .publish(DomainEvent::UserRolesBulkUpdated { guild_id, updates });
+    }
+
+    Ok(BulkGiveUserRolesResponse { new_role_ids })
+}
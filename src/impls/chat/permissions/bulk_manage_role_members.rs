@@ -0,0 +1,93 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of users that can be changed in one request.
+const MAX_USERS: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct BulkManageRoleMembersRequest {
+    pub guild_id: u64,
+    pub role_id: u64,
+    /// Users to give the role to.
+    #[serde(default)]
+    pub give_user_ids: Vec<u64>,
+    /// Users to take the role from.
+    #[serde(default)]
+    pub take_user_ids: Vec<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkManageRoleMembersResponse {
+    /// Users whose roles changed. Users who already had (or didn't have)
+    /// the role are left out.
+    pub updated_user_ids: Vec<u64>,
+}
+
+/// Gives a role to and takes it from many users at once. Either all changes
+/// are applied or none are.
+#[require_perms(guild, "roles.user.manage")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: BulkManageRoleMembersRequest,
+) -> ServerResult<BulkManageRoleMembersResponse> {
+    let BulkManageRoleMembersRequest {
+        guild_id,
+        role_id,
+        give_user_ids,
+        take_user_ids,
+    } = request;
+
+    if give_user_ids.len() + take_user_ids.len() > MAX_USERS {
+        bail!((
+            "h.too-many-users",
+            format!("at most {} users can be changed at once", MAX_USERS)
+        ));
+    }
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let changes = give_user_ids
+        .into_iter()
+        .map(|user_id| (user_id, vec![role_id], Vec::new()))
+        .chain(
+            take_user_ids
+                .into_iter()
+                .map(|user_id| (user_id, Vec::new(), vec![role_id])),
+        )
+        .collect::<Vec<_>>();
+    for (member_id, _, _) in &changes {
+        chat_tree.is_user_in_guild(guild_id, *member_id).await?;
+        chat_tree
+            .check_role_hierarchy(guild_id, user_id, Some(*member_id), &[role_id])
+            .await?;
+    }
+
+    let updates = chat_tree
+        .bulk_manage_user_roles_logic(guild_id, changes.clone())
+        .await?;
+    let updated_user_ids: Vec<u64> = updates.iter().map(|(user_id, _)| *user_id).collect();
+
+    // only users whose roles actually changed are recorded
+    let audit_actions = changes
+        .into_iter()
+        .filter(|(member_id, _, _)| updated_user_ids.contains(member_id))
+        .map(
+            |(member_id, given_role_ids, taken_role_ids)| AuditAction::UserRolesUpdated {
+                user_id: member_id,
+                given_role_ids,
+                taken_role_ids,
+            },
+        )
+        .collect();
+    audit::record_all(&svc.deps, guild_id, user_id, audit_actions).await?;
+
+    if updates.is_empty().not() {
+        svc.deps
+            .event_bus
+            .publish(DomainEvent::UserRolesBulkUpdated { guild_id, updates });
+    }
+
+    Ok(BulkManageRoleMembersResponse { updated_user_ids })
+}
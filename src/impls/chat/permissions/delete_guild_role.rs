@@ -1,5 +1,6 @@
 use super::*;
 
+#[require_perms(guild, "roles.manage")]
 pub async fn handler(
     svc: &ChatServer,
     request: Request<DeleteGuildRoleRequest>,
@@ -10,24 +11,30 @@ pub async fn handler(
 
     let chat_tree = &svc.deps.chat_tree;
 
-    chat_tree.check_guild_user(guild_id, user_id).await?;
-    chat_tree
-        .check_perms(guild_id, None, user_id, "roles.manage", false)
-        .await?;
-
     chat_tree
         .chat_tree
         .remove(&make_guild_role_key(guild_id, role_id))
         .await
         .map_err(ServerError::DbError)?
         .ok_or(ServerError::NoSuchRole { guild_id, role_id })?;
+    chat_tree.evict_role_permissions(guild_id, role_id);
+    chat_tree
+        .set_role_member_cap_logic(guild_id, role_id, None)
+        .await?;
+    chat_tree
+        .remove_role_from_gates_logic(guild_id, role_id)
+        .await?;
+    audit::record(
+        &svc.deps,
+        guild_id,
+        user_id,
+        AuditAction::RoleDeleted { role_id },
+    )
+    .await?;
 
-    svc.send_event_through_chan(
-        EventSub::Guild(guild_id),
-        stream_event::Event::RoleDeleted(stream_event::RoleDeleted { guild_id, role_id }),
-        None,
-        EventContext::empty(),
-    );
+    svc.deps
+        .event_bus
+        .publish(DomainEvent::RoleDeleted { guild_id, role_id });
 
     Ok((DeleteGuildRoleResponse {}).into_response())
 }
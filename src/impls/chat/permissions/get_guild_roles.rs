@@ -1,5 +1,6 @@
 use super::*;
 
+#[require_perms(guild, "roles.get")]
 pub async fn handler(
     svc: &ChatServer,
     request: Request<GetGuildRolesRequest>,
@@ -10,11 +11,6 @@ pub async fn handler(
 
     let chat_tree = &svc.deps.chat_tree;
 
-    chat_tree.check_guild_user(guild_id, user_id).await?;
-    chat_tree
-        .check_perms(guild_id, None, user_id, "roles.get", false)
-        .await?;
-
     let roles = chat_tree.get_guild_roles_logic(guild_id).await?;
 
     Ok((GetGuildRolesResponse { roles }).into_response())
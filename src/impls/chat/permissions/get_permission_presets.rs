@@ -0,0 +1,38 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct GetPermissionPresetsRequest {}
+
+#[derive(Debug, Serialize)]
+pub struct PermissionPreset {
+    pub name: String,
+    pub permissions: Vec<PresetPermission>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetPermissionPresetsResponse {
+    pub presets: Vec<PermissionPreset>,
+}
+
+pub async fn handler(
+    svc: &ChatServer,
+    _user_id: u64,
+    _request: GetPermissionPresetsRequest,
+) -> ServerResult<GetPermissionPresetsResponse> {
+    let config = &svc.deps.config;
+
+    let presets = permission_preset_names(config)
+        .into_iter()
+        .filter_map(|name| {
+            let permissions = expand_permission_preset(config, &name)?
+                .into_iter()
+                .map(PresetPermission::from)
+                .collect();
+            Some(PermissionPreset { name, permissions })
+        })
+        .collect();
+
+    Ok(GetPermissionPresetsResponse { presets })
+}
@@ -0,0 +1,47 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct GetRoleMemberCapsRequest {
+    pub guild_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RoleMemberCap {
+    pub role_id: u64,
+    pub cap: u64,
+    /// Number of members who currently have the role.
+    pub member_count: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetRoleMemberCapsResponse {
+    pub caps: Vec<RoleMemberCap>,
+}
+
+#[require_perms(guild, "roles.get")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetRoleMemberCapsRequest,
+) -> ServerResult<GetRoleMemberCapsResponse> {
+    let GetRoleMemberCapsRequest { guild_id } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let role_caps = chat_tree.get_role_member_caps_logic(guild_id).await?;
+    let mut caps = Vec::with_capacity(role_caps.len());
+    for (role_id, cap) in role_caps {
+        let member_count = chat_tree
+            .count_role_members_logic(guild_id, role_id)
+            .await?;
+        caps.push(RoleMemberCap {
+            role_id,
+            cap,
+            member_count,
+        });
+    }
+
+    Ok(GetRoleMemberCapsResponse { caps })
+}
@@ -1,5 +1,6 @@
 use super::*;
 
+#[require_perms(guild, "roles.user.manage")]
 pub async fn handler(
     svc: &ChatServer,
     request: Request<ManageUserRolesRequest>,
@@ -15,31 +16,42 @@ pub async fn handler(
 
     let chat_tree = &svc.deps.chat_tree;
 
-    chat_tree.check_guild_user(guild_id, user_id).await?;
     chat_tree.is_user_in_guild(guild_id, user_to_manage).await?;
-    chat_tree
-        .check_perms(guild_id, None, user_id, "roles.user.manage", false)
-        .await?;
     let user_to_manage = if user_to_manage != 0 {
         user_to_manage
     } else {
         user_id
     };
-
-    let new_role_ids = chat_tree
-        .manage_user_roles_logic(guild_id, user_to_manage, give_role_ids, take_role_ids)
+    let changed_role_ids = [give_role_ids.as_slice(), take_role_ids.as_slice()].concat();
+    chat_tree
+        .check_role_hierarchy(guild_id, user_id, Some(user_to_manage), &changed_role_ids)
         .await?;
 
-    svc.send_event_through_chan(
-        EventSub::Guild(guild_id),
-        stream_event::Event::UserRolesUpdated(stream_event::UserRolesUpdated {
+    let new_role_ids = chat_tree
+        .manage_user_roles_logic(
             guild_id,
+            user_to_manage,
+            give_role_ids.clone(),
+            take_role_ids.clone(),
+        )
+        .await?;
+    audit::record(
+        &svc.deps,
+        guild_id,
+        user_id,
+        AuditAction::UserRolesUpdated {
             user_id: user_to_manage,
-            new_role_ids,
-        }),
-        None,
-        EventContext::empty(),
-    );
+            given_role_ids: give_role_ids,
+            taken_role_ids: take_role_ids,
+        },
+    )
+    .await?;
+
+    svc.deps.event_bus.publish(DomainEvent::UserRolesUpdated {
+        guild_id,
+        user_id: user_to_manage,
+        new_role_ids,
+    });
 
     Ok((ManageUserRolesResponse {}).into_response())
 }
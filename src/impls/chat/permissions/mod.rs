@@ -1,9 +1,16 @@
 use super::*;
 
+use crate::config::Config;
+
 pub mod add_guild_role;
+pub mod apply_permission_preset;
+pub mod bulk_give_user_roles;
+pub mod bulk_manage_role_members;
 pub mod delete_guild_role;
 pub mod get_guild_roles;
+pub mod get_permission_presets;
 pub mod get_permissions;
+pub mod get_role_member_caps;
 pub mod get_user_roles;
 pub mod give_up_ownership;
 pub mod grant_ownership;
@@ -11,4 +18,98 @@ pub mod manage_user_roles;
 pub mod modify_guild_role;
 pub mod move_role;
 pub mod query_has_permission;
+pub mod set_channel_permission_sync;
 pub mod set_permissions;
+pub mod set_role_member_cap;
+pub mod simulate_permission_change;
+
+/// Built-in permission presets. These can be overridden in config.
+pub const DEFAULT_PERMISSION_PRESETS: [(&str, &[&str]); 3] = [
+    (
+        "moderator",
+        &[
+            "messages.send",
+            "messages.view",
+            "messages.manage.delete",
+            "invites.view",
+            "invites.manage.create",
+            "user.manage.kick",
+            "user.manage.ban",
+            "user.manage.unban",
+            "stage.manage",
+            "voice.stats.view",
+            "members.screening.review",
+            "messages.reports.escalate",
+            "messages.quarantine.review",
+            "guild.audit-log.view",
+            "guild.manage.text-macros",
+            "guild.manage.emoji",
+            "roles.get",
+            "roles.user.get",
+            "permissions.query",
+        ],
+    ),
+    (
+        "member",
+        &[
+            "messages.send",
+            "messages.view",
+            "invites.view",
+            "invites.manage.create",
+            "roles.get",
+            "roles.user.get",
+            "permissions.query",
+        ],
+    ),
+    (
+        "read-only",
+        &["!messages.send", "messages.view", "roles.get"],
+    ),
+];
+
+/// Returns the names of all permission presets available on this server.
+pub fn permission_preset_names(config: &Config) -> Vec<String> {
+    let mut names = DEFAULT_PERMISSION_PRESETS
+        .iter()
+        .map(|(name, _)| name.to_string())
+        .chain(config.policy.permission_presets.keys().cloned())
+        .collect::<Vec<_>>();
+    names.sort_unstable();
+    names.dedup();
+    names
+}
+
+/// Expands a permission preset into the permissions it consists of.
+///
+/// Presets from config take priority over built-in ones.
+pub fn expand_permission_preset(config: &Config, name: &str) -> Option<Vec<Permission>> {
+    let make_perm = |node: &str| match node.strip_prefix('!') {
+        Some(matches) => Permission::new(matches.to_string(), false),
+        None => Permission::new(node.to_string(), true),
+    };
+
+    if let Some(nodes) = config.policy.permission_presets.get(name) {
+        return Some(nodes.iter().map(|node| make_perm(node)).collect());
+    }
+
+    DEFAULT_PERMISSION_PRESETS
+        .iter()
+        .find(|(preset_name, _)| *preset_name == name)
+        .map(|(_, nodes)| nodes.iter().map(|node| make_perm(node)).collect())
+}
+
+/// A permission in a form that can be sent over the JSON API.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PresetPermission {
+    pub matches: String,
+    pub ok: bool,
+}
+
+impl From<Permission> for PresetPermission {
+    fn from(perm: Permission) -> Self {
+        Self {
+            matches: perm.matches,
+            ok: perm.ok,
+        }
+    }
+}
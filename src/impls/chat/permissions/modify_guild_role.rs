@@ -1,5 +1,6 @@
 use super::*;
 
+#[require_perms(guild, "roles.manage")]
 pub async fn handler(
     svc: &ChatServer,
     request: Request<ModifyGuildRoleRequest>,
@@ -17,11 +18,6 @@ pub async fn handler(
 
     let chat_tree = &svc.deps.chat_tree;
 
-    chat_tree.check_guild_user(guild_id, user_id).await?;
-    chat_tree
-        .check_perms(guild_id, None, user_id, "roles.manage", false)
-        .await?;
-
     let key = make_guild_role_key(guild_id, role_id);
     let mut role = if let Some(raw) = chat_tree.get(key).await? {
         db::deser_role(raw)
@@ -44,20 +40,22 @@ pub async fn handler(
 
     let ser_role = rkyv_ser(&role);
     chat_tree.insert(key, ser_role).await?;
+    audit::record(
+        &svc.deps,
+        guild_id,
+        user_id,
+        AuditAction::RoleUpdated { role_id },
+    )
+    .await?;
 
-    svc.send_event_through_chan(
-        EventSub::Guild(guild_id),
-        stream_event::Event::RoleUpdated(stream_event::RoleUpdated {
-            guild_id,
-            role_id,
-            new_name,
-            new_color,
-            new_hoist,
-            new_pingable,
-        }),
-        None,
-        EventContext::empty(),
-    );
+    svc.deps.event_bus.publish(DomainEvent::RoleUpdated {
+        guild_id,
+        role_id,
+        new_name,
+        new_color,
+        new_hoist,
+        new_pingable,
+    });
 
     Ok((ModifyGuildRoleResponse {}).into_response())
 }
@@ -1,5 +1,6 @@
 use super::*;
 
+#[require_perms(guild, "roles.manage")]
 pub async fn handler(
     svc: &ChatServer,
     request: Request<MoveRoleRequest>,
@@ -14,26 +15,17 @@ pub async fn handler(
 
     let chat_tree = &svc.deps.chat_tree;
 
-    chat_tree.check_guild_user(guild_id, user_id).await?;
-    chat_tree
-        .check_perms(guild_id, None, user_id, "roles.manage", false)
-        .await?;
     chat_tree.does_role_exist(guild_id, role_id).await?;
 
     if let Some(pos) = new_position {
         chat_tree
             .move_role_logic(guild_id, role_id, Some(pos.clone()))
             .await?;
-        svc.send_event_through_chan(
-            EventSub::Guild(guild_id),
-            stream_event::Event::RoleMoved(stream_event::RoleMoved {
-                guild_id,
-                role_id,
-                new_position: Some(pos),
-            }),
-            None,
-            EventContext::empty(),
-        );
+        svc.deps.event_bus.publish(DomainEvent::RoleMoved {
+            guild_id,
+            role_id,
+            new_position: pos,
+        });
     }
 
     Ok((MoveRoleResponse {}).into_response())
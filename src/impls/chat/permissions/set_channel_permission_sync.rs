@@ -0,0 +1,191 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+use set_permissions::members_with_role;
+
+#[derive(Debug, Deserialize)]
+pub struct SetChannelPermissionSyncRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    /// Whether the channel's permissions should be synced with its category.
+    pub synced: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetChannelPermissionSyncResponse {
+    /// Category the channel's permissions are synced with, not set if they
+    /// aren't synced.
+    pub category_id: Option<u64>,
+}
+
+/// Syncs the permissions of a channel with its category, or stops syncing
+/// them. Syncing replaces the channel's permissions with the category's, and
+/// the channel then gets every permission change of the category. Stopping
+/// keeps the permissions the channel has.
+#[require_perms(guild, channel, "permissions.manage.set")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: SetChannelPermissionSyncRequest,
+) -> ServerResult<SetChannelPermissionSyncResponse> {
+    let SetChannelPermissionSyncRequest {
+        guild_id,
+        channel_id,
+        synced,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    if chat_tree.is_category_channel(guild_id, channel_id).await? {
+        bail!((
+            "h.category-cant-be-synced",
+            "categories don't have a category to sync permissions with"
+        ));
+    }
+
+    if !synced {
+        chat_tree
+            .unsync_channel_perms_logic(guild_id, channel_id)
+            .await?;
+        return Ok(SetChannelPermissionSyncResponse { category_id: None });
+    }
+
+    let Some(category_id) = chat_tree
+        .get_channel_category_logic(guild_id, channel_id)
+        .await?
+    else {
+        bail!((
+            "h.channel-not-in-category",
+            format!("channel {} isn't in a category", channel_id)
+        ));
+    };
+    chat_tree
+        .check_perms(
+            guild_id,
+            Some(category_id),
+            user_id,
+            "permissions.manage.set",
+            false,
+        )
+        .await?;
+
+    let changed = chat_tree
+        .sync_channel_perms_logic(guild_id, category_id, channel_id)
+        .await?;
+    for (role_id, new_perms) in changed {
+        audit::record(
+            &svc.deps,
+            guild_id,
+            user_id,
+            AuditAction::PermissionsUpdated {
+                role_id,
+                channel_id: Some(channel_id),
+                permissions: new_perms
+                    .iter()
+                    .map(|perm| AuditPermission {
+                        matches: perm.matches.clone(),
+                        ok: perm.ok,
+                    })
+                    .collect(),
+            },
+        )
+        .await?;
+        let affected_users = members_with_role(chat_tree, guild_id, role_id).await?;
+        svc.deps
+            .event_bus
+            .publish(DomainEvent::RolePermissionsUpdated {
+                guild_id,
+                channel_id: Some(channel_id),
+                role_id,
+                new_perms,
+                affected_users,
+                synced_channel_ids: Vec::new(),
+            });
+    }
+
+    Ok(SetChannelPermissionSyncResponse {
+        category_id: Some(category_id),
+    })
+}
+
+#[cfg(all(test, feature = "sled"))]
+mod test {
+    use super::*;
+
+    fn perm(matches: &str, ok: bool) -> Permission {
+        Permission {
+            matches: matches.to_string(),
+            ok,
+        }
+    }
+
+    #[tokio::test]
+    async fn synced_channels_follow_their_category() {
+        let db = crate::db::open_temp();
+        let chat_tree = ChatTree::new(&db).await.unwrap();
+        let guild_id = chat_tree
+            .create_guild_logic(
+                1,
+                "test".to_string(),
+                None,
+                None,
+                guild_kind::Kind::new_normal(guild_kind::Normal::new()),
+            )
+            .await
+            .unwrap();
+        let create = |name: &str, kind| {
+            chat_tree.create_channel_logic(guild_id, name.to_string(), kind, None, None)
+        };
+        let category_id = create("category", ChannelKind::Category).await.unwrap();
+        let channel_id = create("channel", ChannelKind::TextUnspecified)
+            .await
+            .unwrap();
+        assert_eq!(
+            chat_tree
+                .get_channel_category_logic(guild_id, channel_id)
+                .await
+                .unwrap(),
+            Some(category_id)
+        );
+
+        chat_tree
+            .set_permissions_logic(guild_id, Some(channel_id), 5, vec![perm("a", true)])
+            .await
+            .unwrap();
+        chat_tree
+            .set_permissions_logic(guild_id, Some(category_id), 0, vec![perm("b", false)])
+            .await
+            .unwrap();
+        let changed = chat_tree
+            .sync_channel_perms_logic(guild_id, category_id, channel_id)
+            .await
+            .unwrap();
+        assert_eq!(changed.len(), 2);
+        assert!(chat_tree
+            .get_permissions_logic(guild_id, Some(channel_id), 5)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            chat_tree
+                .get_permissions_logic(guild_id, Some(channel_id), 0)
+                .await
+                .unwrap(),
+            vec![("b".into(), false)]
+        );
+
+        let synced = chat_tree
+            .set_synced_permissions_logic(guild_id, category_id, 0, &[perm("b", true)])
+            .await
+            .unwrap();
+        assert_eq!(synced, vec![channel_id]);
+        assert_eq!(
+            chat_tree
+                .get_permissions_logic(guild_id, Some(channel_id), 0)
+                .await
+                .unwrap(),
+            vec![("b".into(), true)]
+        );
+    }
+}
@@ -28,55 +28,148 @@ pub async fn handler(
 
     // TODO: fix
     if !perms_to_give.is_empty() {
-        chat_tree
-            .set_permissions_logic(guild_id, channel_id, role_id, perms_to_give.clone())
+        set_permissions_and_notify(svc, user_id, guild_id, channel_id, role_id, perms_to_give)
             .await?;
-        let members = chat_tree.get_guild_members_logic(guild_id).await?.members;
-        let guild_owners = chat_tree.get_guild_owners(guild_id).await?;
-        let mut for_users = Vec::with_capacity(members.len());
-        for user_id in members.iter() {
-            if !guild_owners.contains(user_id) {
-                let maybe_user = chat_tree
-                    .get_user_roles_logic(guild_id, *user_id)
-                    .await?
-                    .contains(&role_id)
-                    .then(|| *user_id);
-                if let Some(user_id) = maybe_user {
-                    for_users.push(user_id);
-                }
-            }
+        Ok((SetPermissionsResponse {}).into_response())
+    } else {
+        Err(ServerError::NoPermissionsSpecified.into())
+    }
+}
+
+/// Sets the permissions of a role, and notifies the affected users. Setting
+/// permissions of a category sets them in the channels synced with it too,
+/// and setting permissions of a synced channel stops syncing it.
+pub async fn set_permissions_and_notify(
+    svc: &ChatServer,
+    set_by: u64,
+    guild_id: u64,
+    channel_id: Option<u64>,
+    role_id: u64,
+    perms_to_give: Vec<Permission>,
+) -> ServerResult<()> {
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree
+        .set_permissions_logic(guild_id, channel_id, role_id, perms_to_give.clone())
+        .await?;
+    let mut synced_channel_ids = Vec::new();
+    if let Some(channel_id) = channel_id {
+        if chat_tree.is_category_channel(guild_id, channel_id).await? {
+            synced_channel_ids = chat_tree
+                .set_synced_permissions_logic(guild_id, channel_id, role_id, &perms_to_give)
+                .await?;
+        } else {
+            // the channel's permissions aren't the same as its category's anymore
+            chat_tree
+                .unsync_channel_perms_logic(guild_id, channel_id)
+                .await?;
         }
-        for perm in &perms_to_give {
-            svc.send_event_through_chan(
-                EventSub::Guild(guild_id),
-                stream_event::Event::PermissionUpdated(stream_event::PermissionUpdated {
-                    guild_id,
-                    channel_id,
-                    query: perm.matches.clone(),
+    }
+    audit::record(
+        &svc.deps,
+        guild_id,
+        set_by,
+        AuditAction::PermissionsUpdated {
+            role_id,
+            channel_id,
+            permissions: perms_to_give
+                .iter()
+                .map(|perm| AuditPermission {
+                    matches: perm.matches.clone(),
                     ok: perm.ok,
-                }),
-                None,
-                EventContext::new(for_users.clone()),
-            );
+                })
+                .collect(),
+        },
+    )
+    .await?;
+    let for_users = members_with_role(chat_tree, guild_id, role_id).await?;
+    svc.deps
+        .event_bus
+        .publish(DomainEvent::RolePermissionsUpdated {
+            guild_id,
+            channel_id,
+            role_id,
+            new_perms: perms_to_give,
+            affected_users: for_users,
+            synced_channel_ids,
+        });
+
+    Ok(())
+}
+
+/// Gets the members that have a role, leaving out guild owners since
+/// permissions don't affect them.
+pub async fn members_with_role(
+    chat_tree: &ChatTree,
+    guild_id: u64,
+    role_id: u64,
+) -> ServerResult<Vec<u64>> {
+    let members = chat_tree.get_guild_members_logic(guild_id).await?.members;
+    let guild_owners = chat_tree.get_guild_owners(guild_id).await?;
+    let mut for_users = Vec::with_capacity(members.len());
+    for user_id in members.iter() {
+        if !guild_owners.contains(user_id) {
+            let maybe_user = chat_tree
+                .get_user_roles_logic(guild_id, *user_id)
+                .await?
+                .contains(&role_id)
+                .then(|| *user_id);
+            if let Some(user_id) = maybe_user {
+                for_users.push(user_id);
+            }
+        }
+    }
+    Ok(for_users)
+}
+
+#[cfg(all(test, feature = "sled"))]
+mod test {
+    use super::*;
+
+    fn perm(matches: &str, ok: bool) -> Permission {
+        Permission {
+            matches: matches.to_string(),
+            ok,
         }
-        svc.send_event_through_chan(
-            EventSub::Guild(guild_id),
-            stream_event::Event::RolePermsUpdated(stream_event::RolePermissionsUpdated {
-                guild_id,
-                channel_id,
-                role_id,
-                new_perms: perms_to_give,
-            }),
-            Some(PermCheck {
-                guild_id,
-                channel_id: None,
-                check_for: "guild.manage",
-                must_be_guild_owner: false,
-            }),
-            EventContext::empty(),
+    }
+
+    #[tokio::test]
+    async fn changed_permissions_arent_served_from_cache() {
+        let db = crate::db::open_temp();
+        let chat_tree = ChatTree::new(&db).await.unwrap();
+        chat_tree
+            .set_permissions_logic(1, Some(2), 3, vec![perm("messages.send", true)])
+            .await
+            .unwrap();
+        assert_eq!(
+            chat_tree
+                .get_permissions_logic(1, Some(2), 3)
+                .await
+                .unwrap(),
+            vec![("messages.send".into(), true)]
         );
-        Ok((SetPermissionsResponse {}).into_response())
-    } else {
-        Err(ServerError::NoPermissionsSpecified.into())
+
+        chat_tree
+            .set_permissions_logic(1, Some(2), 3, vec![perm("messages.send", false)])
+            .await
+            .unwrap();
+        assert_eq!(
+            chat_tree
+                .get_permissions_logic(1, Some(2), 3)
+                .await
+                .unwrap(),
+            vec![("messages.send".into(), false)]
+        );
+
+        chat_tree
+            .remove(make_channel_perm_key(1, 2, 3, "messages.send"))
+            .await
+            .unwrap();
+        chat_tree.evict_channel_permissions(1, 2);
+        assert!(chat_tree
+            .get_permissions_logic(1, Some(2), 3)
+            .await
+            .unwrap()
+            .is_empty());
     }
 }
@@ -0,0 +1,46 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct SetRoleMemberCapRequest {
+    pub guild_id: u64,
+    pub role_id: u64,
+    /// Maximum number of members the role can have. `None` removes the cap.
+    pub cap: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetRoleMemberCapResponse {}
+
+/// Limits how many members can have a role. Members who already have the
+/// role keep it even if there are more of them than the cap.
+#[require_perms(guild, "roles.manage")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: SetRoleMemberCapRequest,
+) -> ServerResult<SetRoleMemberCapResponse> {
+    let SetRoleMemberCapRequest {
+        guild_id,
+        role_id,
+        cap,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree.does_role_exist(guild_id, role_id).await?;
+
+    if role_id == DEFAULT_ROLE_ID && cap.is_some() {
+        bail!((
+            "h.bad-role-member-cap",
+            "the default role can't have a member cap"
+        ));
+    }
+
+    chat_tree
+        .set_role_member_cap_logic(guild_id, role_id, cap)
+        .await?;
+
+    Ok(SetRoleMemberCapResponse {})
+}
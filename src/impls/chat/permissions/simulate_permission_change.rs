@@ -0,0 +1,395 @@
+use std::collections::{BTreeSet, HashMap};
+
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+/// Most members whose changes are listed. The rest are only counted.
+const MAX_LISTED_MEMBERS: usize = 500;
+
+/// A change to simulate, with the same fields as the request that makes it.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PermissionChange {
+    SetPermissions {
+        #[serde(default)]
+        channel_id: Option<u64>,
+        role_id: u64,
+        permissions: Vec<PresetPermission>,
+    },
+    ManageUserRoles {
+        user_id: u64,
+        #[serde(default)]
+        give_role_ids: Vec<u64>,
+        #[serde(default)]
+        take_role_ids: Vec<u64>,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SimulatePermissionChangeRequest {
+    pub guild_id: u64,
+    pub change: PermissionChange,
+    /// Permissions to compare. Defaults to the ones in permission presets and
+    /// in the change.
+    #[serde(default)]
+    pub permissions: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AbilityChange {
+    pub permission: String,
+    /// Not set if the permission changed for the whole guild.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_id: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MemberEffect {
+    pub user_id: u64,
+    pub gained: Vec<AbilityChange>,
+    pub lost: Vec<AbilityChange>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChannelEffect {
+    pub channel_id: u64,
+    /// How many members would start seeing the channel.
+    pub became_visible: usize,
+    /// How many members would stop seeing the channel.
+    pub became_hidden: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SimulatePermissionChangeResponse {
+    /// How many members would gain or lose a permission.
+    pub affected_members: usize,
+    /// What every affected member would gain and lose, for up to 500 members.
+    pub members: Vec<MemberEffect>,
+    /// Channels that would become visible or hidden to some members.
+    pub channels: Vec<ChannelEffect>,
+}
+
+/// Computes what a permission or role change would do, without making it.
+/// Guild owners are left out, since they can do everything anyway, and so
+/// are channels hidden by role gates.
+#[require_perms(guild)]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: SimulatePermissionChangeRequest,
+) -> ServerResult<SimulatePermissionChangeResponse> {
+    let SimulatePermissionChangeRequest {
+        guild_id,
+        change,
+        permissions,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let owners = chat_tree.get_guild_owners(guild_id).await?;
+
+    // the members the change applies to, with their roles before the change
+    let mut members = Vec::new();
+    let channel_ids = match &change {
+        PermissionChange::SetPermissions {
+            channel_id,
+            role_id,
+            ..
+        } => {
+            chat_tree
+                .check_perms(
+                    guild_id,
+                    *channel_id,
+                    user_id,
+                    "permissions.manage.set",
+                    false,
+                )
+                .await?;
+            for member_id in chat_tree.get_guild_members_logic(guild_id).await?.members {
+                if owners.contains(&member_id) {
+                    continue;
+                }
+                let roles = chat_tree.get_user_roles_logic(guild_id, member_id).await?;
+                if roles.contains(role_id) {
+                    members.push((member_id, roles));
+                }
+            }
+            match channel_id {
+                Some(channel_id) => {
+                    chat_tree.does_channel_exist(guild_id, *channel_id).await?;
+                    vec![*channel_id]
+                }
+                None => chat_tree.get_guild_channel_ids_logic(guild_id).await?,
+            }
+        }
+        PermissionChange::ManageUserRoles {
+            user_id: target_id,
+            give_role_ids,
+            take_role_ids,
+        } => {
+            chat_tree.is_user_in_guild(guild_id, *target_id).await?;
+            chat_tree
+                .check_perms(guild_id, None, user_id, "roles.user.manage", false)
+                .await?;
+            let changed_role_ids = [give_role_ids.as_slice(), take_role_ids.as_slice()].concat();
+            chat_tree
+                .check_role_hierarchy(guild_id, user_id, Some(*target_id), &changed_role_ids)
+                .await?;
+            if !owners.contains(target_id) {
+                let roles = chat_tree.get_user_roles_logic(guild_id, *target_id).await?;
+                members.push((*target_id, roles));
+            }
+            chat_tree.get_guild_channel_ids_logic(guild_id).await?
+        }
+    };
+
+    // members with the same roles are affected the same way
+    let mut groups: HashMap<BTreeSet<u64>, Vec<u64>> = HashMap::new();
+    for (member_id, roles) in members {
+        groups
+            .entry(roles.into_iter().collect())
+            .or_default()
+            .push(member_id);
+    }
+
+    let role_ids = groups
+        .keys()
+        .flatten()
+        .copied()
+        .chain(match &change {
+            PermissionChange::SetPermissions { .. } => Vec::new(),
+            PermissionChange::ManageUserRoles { give_role_ids, .. } => give_role_ids.clone(),
+        })
+        .collect::<BTreeSet<_>>();
+    let mut before = PermissionTable::default();
+    for role_id in &role_ids {
+        before.load(chat_tree, guild_id, None, *role_id).await?;
+        for channel_id in &channel_ids {
+            before
+                .load(chat_tree, guild_id, Some(*channel_id), *role_id)
+                .await?;
+        }
+    }
+    let mut after = before.clone();
+    if let PermissionChange::SetPermissions {
+        channel_id,
+        role_id,
+        permissions,
+    } = &change
+    {
+        after.set(*channel_id, *role_id, permissions);
+    }
+
+    let permissions = if permissions.is_empty() {
+        default_permissions(&svc.deps.config, &change)
+    } else {
+        permissions
+    };
+
+    let mut affected_members = 0;
+    let mut member_effects = Vec::new();
+    let mut channel_effects: HashMap<u64, ChannelEffect> = HashMap::new();
+    for (roles_before, member_ids) in groups {
+        let roles_after = match &change {
+            PermissionChange::SetPermissions { .. } => roles_before.clone(),
+            PermissionChange::ManageUserRoles {
+                give_role_ids,
+                take_role_ids,
+                ..
+            } => roles_before
+                .iter()
+                .chain(give_role_ids)
+                .filter(|role_id| !take_role_ids.contains(*role_id))
+                .copied()
+                .collect(),
+        };
+        let roles_before = roles_before.into_iter().collect::<Vec<_>>();
+        let roles_after = roles_after.into_iter().collect::<Vec<_>>();
+        let has_before =
+            |channel_id, permission: &str| before.has(&roles_before, channel_id, permission);
+        let has_after =
+            |channel_id, permission: &str| after.has(&roles_after, channel_id, permission);
+
+        let (mut gained, mut lost) = (Vec::new(), Vec::new());
+        for permission in &permissions {
+            let guild_wide = (has_before(None, permission), has_after(None, permission));
+            let mut push = |channel_id, changed: (bool, bool)| match changed {
+                (false, true) => gained.push(AbilityChange {
+                    permission: permission.clone(),
+                    channel_id,
+                }),
+                (true, false) => lost.push(AbilityChange {
+                    permission: permission.clone(),
+                    channel_id,
+                }),
+                _ => {}
+            };
+            push(None, guild_wide);
+            for channel_id in &channel_ids {
+                let in_channel = (
+                    has_before(Some(*channel_id), permission),
+                    has_after(Some(*channel_id), permission),
+                );
+                // channels that change with the whole guild aren't listed
+                if in_channel != guild_wide {
+                    push(Some(*channel_id), in_channel);
+                }
+            }
+        }
+
+        for channel_id in &channel_ids {
+            let visible = (
+                has_before(Some(*channel_id), "messages.view"),
+                has_after(Some(*channel_id), "messages.view"),
+            );
+            if visible.0 == visible.1 {
+                continue;
+            }
+            let effect = channel_effects
+                .entry(*channel_id)
+                .or_insert_with(|| ChannelEffect {
+                    channel_id: *channel_id,
+                    became_visible: 0,
+                    became_hidden: 0,
+                });
+            if visible.1 {
+                effect.became_visible += member_ids.len();
+            } else {
+                effect.became_hidden += member_ids.len();
+            }
+        }
+
+        if gained.is_empty() && lost.is_empty() {
+            continue;
+        }
+        affected_members += member_ids.len();
+        for member_id in member_ids {
+            if member_effects.len() >= MAX_LISTED_MEMBERS {
+                break;
+            }
+            member_effects.push(MemberEffect {
+                user_id: member_id,
+                gained: gained.clone(),
+                lost: lost.clone(),
+            });
+        }
+    }
+    member_effects.sort_unstable_by_key(|effect| effect.user_id);
+    let mut channels = channel_effects.into_values().collect::<Vec<_>>();
+    channels.sort_unstable_by_key(|effect| effect.channel_id);
+
+    Ok(SimulatePermissionChangeResponse {
+        affected_members,
+        members: member_effects,
+        channels,
+    })
+}
+
+/// The permissions in permission presets and in the change, leaving out
+/// patterns.
+fn default_permissions(config: &Config, change: &PermissionChange) -> Vec<String> {
+    let mut permissions = permission_preset_names(config)
+        .into_iter()
+        .filter_map(|name| expand_permission_preset(config, &name))
+        .flatten()
+        .map(|perm| perm.matches)
+        .collect::<BTreeSet<_>>();
+    if let PermissionChange::SetPermissions {
+        permissions: changed,
+        ..
+    } = change
+    {
+        permissions.extend(changed.iter().map(|perm| perm.matches.clone()));
+    }
+    permissions.insert("messages.view".to_string());
+    permissions
+        .into_iter()
+        .filter(|perm| !perm.contains('*'))
+        .collect()
+}
+
+/// Permissions of roles, by channel and role. Guild-wide permissions have no
+/// channel.
+#[derive(Debug, Default, Clone)]
+struct PermissionTable {
+    perms: HashMap<(Option<u64>, u64), Vec<(SmolStr, bool)>>,
+}
+
+impl PermissionTable {
+    async fn load(
+        &mut self,
+        chat_tree: &ChatTree,
+        guild_id: u64,
+        channel_id: Option<u64>,
+        role_id: u64,
+    ) -> ServerResult<()> {
+        let perms = chat_tree
+            .get_permissions_logic(guild_id, channel_id, role_id)
+            .await?;
+        self.perms.insert((channel_id, role_id), perms);
+        Ok(())
+    }
+
+    /// Sets permissions like [`ChatTree::set_permissions_logic`] does.
+    fn set(&mut self, channel_id: Option<u64>, role_id: u64, permissions: &[PresetPermission]) {
+        let perms = self.perms.entry((channel_id, role_id)).or_default();
+        for perm in permissions {
+            match perms
+                .iter_mut()
+                .find(|(matches, _)| *matches == perm.matches)
+            {
+                Some((_, ok)) => *ok = perm.ok,
+                None => perms.push((perm.matches.as_str().into(), perm.ok)),
+            }
+        }
+        // stored permissions are scanned in key order
+        perms.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+    }
+
+    /// Checks a permission like [`ChatTree::query_has_permission_logic`] does.
+    fn has(&self, roles: &[u64], channel_id: Option<u64>, check_for: &str) -> bool {
+        let allowed_in = |channel_id| {
+            roles.iter().any(|role_id| {
+                self.perms
+                    .get(&(channel_id, *role_id))
+                    .and_then(|perms| {
+                        has_permission(perms.iter().map(|(m, ok)| (m.as_str(), *ok)), check_for)
+                    })
+                    .unwrap_or(false)
+            })
+        };
+        channel_id.map_or(false, |channel_id| allowed_in(Some(channel_id))) || allowed_in(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channel_permissions_override_guild_ones_when_granting() {
+        let mut table = PermissionTable::default();
+        table.set(
+            None,
+            0,
+            &[PresetPermission {
+                matches: "messages.send".to_string(),
+                ok: false,
+            }],
+        );
+        assert!(!table.has(&[0], Some(1), "messages.send"));
+
+        table.set(
+            Some(1),
+            0,
+            &[PresetPermission {
+                matches: "messages.send".to_string(),
+                ok: true,
+            }],
+        );
+        assert!(table.has(&[0], Some(1), "messages.send"));
+        assert!(!table.has(&[0], Some(2), "messages.send"));
+        assert!(!table.has(&[0], None, "messages.send"));
+    }
+}
@@ -0,0 +1,60 @@
+use super::*;
+
+#[derive(Debug, Deserialize)]
+pub struct EscalateReportRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    pub message_id: u64,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EscalateReportResponse {
+    pub report_id: u64,
+}
+
+/// Escalates a report about a message to the homeserver admins.
+#[require_perms(guild, channel, "messages.reports.escalate")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: EscalateReportRequest,
+) -> ServerResult<EscalateReportResponse> {
+    let EscalateReportRequest {
+        guild_id,
+        channel_id,
+        message_id,
+        reason,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let (message, _) = chat_tree
+        .get_message_logic(guild_id, channel_id, message_id)
+        .await?;
+    let content = match message.content.and_then(|c| c.content) {
+        Some(content::Content::TextMessage(content::TextContent {
+            content: Some(FormattedText { text, .. }),
+        })) => text,
+        _ => String::new(),
+    };
+
+    let now = get_time_secs();
+    let report = EscalatedReport {
+        guild_id,
+        channel_id,
+        message_id,
+        author_id: message.author_id,
+        content,
+        reason,
+        escalated_by: user_id,
+        escalated_at: now,
+        state: ReportState::Open,
+        assignee: None,
+        resolution_note: None,
+        updated_at: now,
+    };
+    let report_id = chat_tree.add_report_logic(&report).await?;
+
+    Ok(EscalateReportResponse { report_id })
+}
@@ -0,0 +1,49 @@
+use super::*;
+
+#[derive(Debug, Deserialize)]
+pub struct GetReportQueueRequest {
+    /// Only return reports in this state.
+    #[serde(default)]
+    pub state: Option<ReportStateInfo>,
+    /// Only return reports assigned to this admin.
+    #[serde(default)]
+    pub assignee: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetReportQueueResponse {
+    /// Reports, oldest first.
+    pub reports: Vec<ReportInfo>,
+}
+
+/// Gets the queue of escalated reports. Only homeserver admins can use this.
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetReportQueueRequest,
+) -> ServerResult<GetReportQueueResponse> {
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree.check_homeserver_admin(user_id).await?;
+
+    let state = request.state.map(ReportState::from);
+    let mut reports = chat_tree
+        .get_reports_logic()
+        .await?
+        .into_iter()
+        .filter(|(_, report)| state.map_or(true, |state| report.state == state))
+        .filter(|(_, report)| {
+            request
+                .assignee
+                .map_or(true, |assignee| report.assignee == Some(assignee))
+        })
+        .collect::<Vec<_>>();
+    reports.sort_unstable_by_key(|(_, report)| report.escalated_at);
+
+    Ok(GetReportQueueResponse {
+        reports: reports
+            .into_iter()
+            .map(|(report_id, report)| ReportInfo::new(report_id, report))
+            .collect(),
+    })
+}
@@ -0,0 +1,74 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+pub mod escalate_report;
+pub mod get_report_queue;
+pub mod update_report;
+
+/// State of a report, in a form that can be sent over the JSON API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportStateInfo {
+    Open,
+    Reviewing,
+    Resolved,
+}
+
+impl From<ReportState> for ReportStateInfo {
+    fn from(state: ReportState) -> Self {
+        match state {
+            ReportState::Open => Self::Open,
+            ReportState::Reviewing => Self::Reviewing,
+            ReportState::Resolved => Self::Resolved,
+        }
+    }
+}
+
+impl From<ReportStateInfo> for ReportState {
+    fn from(state: ReportStateInfo) -> Self {
+        match state {
+            ReportStateInfo::Open => Self::Open,
+            ReportStateInfo::Reviewing => Self::Reviewing,
+            ReportStateInfo::Resolved => Self::Resolved,
+        }
+    }
+}
+
+/// An escalated report, in a form that can be sent over the JSON API.
+#[derive(Debug, Serialize)]
+pub struct ReportInfo {
+    pub report_id: u64,
+    pub guild_id: u64,
+    pub channel_id: u64,
+    pub message_id: u64,
+    pub author_id: u64,
+    pub content: String,
+    pub reason: String,
+    pub escalated_by: u64,
+    pub escalated_at: u64,
+    pub state: ReportStateInfo,
+    pub assignee: Option<u64>,
+    pub resolution_note: Option<String>,
+    pub updated_at: u64,
+}
+
+impl ReportInfo {
+    fn new(report_id: u64, report: EscalatedReport) -> Self {
+        Self {
+            report_id,
+            guild_id: report.guild_id,
+            channel_id: report.channel_id,
+            message_id: report.message_id,
+            author_id: report.author_id,
+            content: report.content,
+            reason: report.reason,
+            escalated_by: report.escalated_by,
+            escalated_at: report.escalated_at,
+            state: report.state.into(),
+            assignee: report.assignee,
+            resolution_note: report.resolution_note,
+            updated_at: report.updated_at,
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use super::*;
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateReportRequest {
+    pub report_id: u64,
+    #[serde(default)]
+    pub new_state: Option<ReportStateInfo>,
+    /// Admin to assign the report to.
+    #[serde(default)]
+    pub new_assignee: Option<u64>,
+    /// Whether to remove the current assignee of the report.
+    #[serde(default)]
+    pub unassign: bool,
+    /// Note describing how the report was resolved. An empty note removes it.
+    #[serde(default)]
+    pub new_resolution_note: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UpdateReportResponse {
+    pub report: ReportInfo,
+}
+
+/// Changes the state, assignee or resolution note of a report. Only homeserver admins can use this.
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: UpdateReportRequest,
+) -> ServerResult<UpdateReportResponse> {
+    let UpdateReportRequest {
+        report_id,
+        new_state,
+        new_assignee,
+        unassign,
+        new_resolution_note,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree.check_homeserver_admin(user_id).await?;
+
+    let mut report = chat_tree.get_report_logic(report_id).await?;
+
+    if let Some(state) = new_state {
+        report.state = state.into();
+    }
+    if unassign {
+        report.assignee = None;
+    }
+    if let Some(assignee) = new_assignee {
+        chat_tree.check_homeserver_admin(assignee).await?;
+        report.assignee = Some(assignee);
+    }
+    if let Some(note) = new_resolution_note {
+        report.resolution_note = (!note.is_empty()).then(|| note);
+    }
+    report.updated_at = get_time_secs();
+
+    chat_tree.put_report_logic(report_id, &report).await?;
+
+    Ok(UpdateReportResponse {
+        report: ReportInfo::new(report_id, report),
+    })
+}
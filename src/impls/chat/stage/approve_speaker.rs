@@ -0,0 +1,43 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct ApproveSpeakerRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    /// The user whose raised hand to approve.
+    pub user_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApproveSpeakerResponse {}
+
+/// Approves a raise hand request, making the user a speaker.
+#[require_perms(guild, channel, "stage.manage")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: ApproveSpeakerRequest,
+) -> ServerResult<ApproveSpeakerResponse> {
+    let ApproveSpeakerRequest {
+        guild_id,
+        channel_id,
+        user_id: user_to_approve,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let mut stage = get_existing_stage(chat_tree, guild_id, channel_id).await?;
+    if !remove_user(&mut stage.raised_hands, user_to_approve) {
+        bail!((
+            "h.hand-not-raised",
+            "the user has not requested to become a speaker"
+        ));
+    }
+    remove_user(&mut stage.invited, user_to_approve);
+    stage.speakers.push(user_to_approve);
+    update_stage(svc, guild_id, channel_id, Some(stage)).await?;
+
+    Ok(ApproveSpeakerResponse {})
+}
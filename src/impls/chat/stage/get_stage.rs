@@ -0,0 +1,27 @@
+use super::*;
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct GetStageRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+}
+
+#[require_perms(guild, channel, "messages.view")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetStageRequest,
+) -> ServerResult<StageInfo> {
+    let GetStageRequest {
+        guild_id,
+        channel_id,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    get_existing_stage(chat_tree, guild_id, channel_id)
+        .await
+        .map(StageInfo::from)
+}
@@ -0,0 +1,41 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct InviteSpeakerRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    /// The user to invite to become a speaker.
+    pub user_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InviteSpeakerResponse {}
+
+/// Invites a listener to become a speaker. The user has to accept the invite
+/// before they become a speaker.
+#[require_perms(guild, channel, "stage.manage")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: InviteSpeakerRequest,
+) -> ServerResult<InviteSpeakerResponse> {
+    let InviteSpeakerRequest {
+        guild_id,
+        channel_id,
+        user_id: user_to_invite,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree.is_user_in_guild(guild_id, user_to_invite).await?;
+
+    let mut stage = get_existing_stage(chat_tree, guild_id, channel_id).await?;
+    if !stage.speakers.contains(&user_to_invite) && !stage.invited.contains(&user_to_invite) {
+        stage.invited.push(user_to_invite);
+        update_stage(svc, guild_id, channel_id, Some(stage)).await?;
+    }
+
+    Ok(InviteSpeakerResponse {})
+}
@@ -0,0 +1,70 @@
+use super::*;
+
+use serde::Serialize;
+
+pub mod approve_speaker;
+pub mod get_stage;
+pub mod invite_speaker;
+pub mod raise_hand;
+pub mod remove_speaker;
+pub mod respond_to_speaker_invite;
+pub mod set_stage;
+
+/// State of a stage channel, in a form that can be sent over the JSON API.
+#[derive(Debug, Serialize)]
+pub struct StageInfo {
+    pub speakers: Vec<u64>,
+    pub raised_hands: Vec<u64>,
+    pub invited: Vec<u64>,
+}
+
+impl From<StageState> for StageInfo {
+    fn from(stage: StageState) -> Self {
+        Self {
+            speakers: stage.speakers,
+            raised_hands: stage.raised_hands,
+            invited: stage.invited,
+        }
+    }
+}
+
+async fn get_existing_stage(
+    chat_tree: &ChatTree,
+    guild_id: u64,
+    channel_id: u64,
+) -> ServerResult<StageState> {
+    chat_tree
+        .get_stage_logic(guild_id, channel_id)
+        .await?
+        .ok_or_else(|| ServerError::NotAStageChannel.into())
+}
+
+/// Saves the stage state of a channel, and notifies everyone who can see the channel
+async fn update_stage(
+    svc: &ChatServer,
+    guild_id: u64,
+    channel_id: u64,
+    stage: Option<StageState>,
+) -> ServerResult<()> {
+    let new_metadata = svc
+        .deps
+        .chat_tree
+        .put_stage_logic(guild_id, channel_id, stage)
+        .await?;
+
+    svc.deps.event_bus.publish(DomainEvent::ChannelUpdated {
+        guild_id,
+        channel_id,
+        new_name: None,
+        new_metadata: Some(new_metadata),
+    });
+
+    Ok(())
+}
+
+/// Removes the user from the given list, returning whether the user was in it
+fn remove_user(list: &mut Vec<u64>, user_id: u64) -> bool {
+    let len = list.len();
+    list.retain(|id| *id != user_id);
+    len != list.len()
+}
@@ -0,0 +1,50 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct RaiseHandRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    /// Whether to raise or lower the hand.
+    pub raised: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RaiseHandResponse {}
+
+/// Requests to become a speaker in a stage channel, or withdraws the request.
+#[require_perms(guild, channel, "messages.view")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: RaiseHandRequest,
+) -> ServerResult<RaiseHandResponse> {
+    let RaiseHandRequest {
+        guild_id,
+        channel_id,
+        raised,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let mut stage = get_existing_stage(chat_tree, guild_id, channel_id).await?;
+    if stage.speakers.contains(&user_id) {
+        return Ok(RaiseHandResponse {});
+    }
+
+    let changed = if raised {
+        let has_raised = stage.raised_hands.contains(&user_id);
+        if !has_raised {
+            stage.raised_hands.push(user_id);
+        }
+        !has_raised
+    } else {
+        remove_user(&mut stage.raised_hands, user_id)
+    };
+    if changed {
+        update_stage(svc, guild_id, channel_id, Some(stage)).await?;
+    }
+
+    Ok(RaiseHandResponse {})
+}
@@ -0,0 +1,52 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct RemoveSpeakerRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    /// The speaker to turn back into a listener. `0` means the user
+    /// making the request.
+    #[serde(default)]
+    pub user_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RemoveSpeakerResponse {}
+
+/// Turns a speaker back into a listener. Speakers can always step down themselves.
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: RemoveSpeakerRequest,
+) -> ServerResult<RemoveSpeakerResponse> {
+    let RemoveSpeakerRequest {
+        guild_id,
+        channel_id,
+        user_id: user_to_remove,
+    } = request;
+    let user_to_remove = if user_to_remove != 0 {
+        user_to_remove
+    } else {
+        user_id
+    };
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree
+        .check_guild_user_channel(guild_id, user_id, channel_id)
+        .await?;
+    if user_to_remove != user_id {
+        chat_tree
+            .check_perms(guild_id, Some(channel_id), user_id, "stage.manage", false)
+            .await?;
+    }
+
+    let mut stage = get_existing_stage(chat_tree, guild_id, channel_id).await?;
+    if remove_user(&mut stage.speakers, user_to_remove) {
+        update_stage(svc, guild_id, channel_id, Some(stage)).await?;
+    }
+
+    Ok(RemoveSpeakerResponse {})
+}
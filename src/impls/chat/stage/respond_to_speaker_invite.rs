@@ -0,0 +1,47 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct RespondToSpeakerInviteRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    /// Whether to accept or reject the invite.
+    pub accept: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RespondToSpeakerInviteResponse {}
+
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: RespondToSpeakerInviteRequest,
+) -> ServerResult<RespondToSpeakerInviteResponse> {
+    let RespondToSpeakerInviteRequest {
+        guild_id,
+        channel_id,
+        accept,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree
+        .check_guild_user_channel(guild_id, user_id, channel_id)
+        .await?;
+
+    let mut stage = get_existing_stage(chat_tree, guild_id, channel_id).await?;
+    if !remove_user(&mut stage.invited, user_id) {
+        bail!((
+            "h.no-speaker-invite",
+            "you have not been invited to become a speaker"
+        ));
+    }
+    if accept {
+        remove_user(&mut stage.raised_hands, user_id);
+        stage.speakers.push(user_id);
+    }
+    update_stage(svc, guild_id, channel_id, Some(stage)).await?;
+
+    Ok(RespondToSpeakerInviteResponse {})
+}
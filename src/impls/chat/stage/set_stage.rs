@@ -0,0 +1,45 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct SetStageRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    /// Whether the channel should be a stage channel.
+    pub enabled: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetStageResponse {}
+
+/// Turns a channel into a stage channel, or back into a regular channel.
+/// The user who turns a channel into a stage becomes its first speaker.
+#[require_perms(guild, channel, "stage.manage")]
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: SetStageRequest,
+) -> ServerResult<SetStageResponse> {
+    let SetStageRequest {
+        guild_id,
+        channel_id,
+        enabled,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let is_stage = chat_tree
+        .get_stage_logic(guild_id, channel_id)
+        .await?
+        .is_some();
+    if is_stage != enabled {
+        let stage = enabled.then(|| StageState {
+            speakers: vec![user_id],
+            ..Default::default()
+        });
+        update_stage(svc, guild_id, channel_id, stage).await?;
+    }
+
+    Ok(SetStageResponse {})
+}
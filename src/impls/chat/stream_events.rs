@@ -18,10 +18,28 @@ pub fn handler(
         let user_id = user_id?;
         tracing::debug!("stream events validated");
 
+        // subscribe before looking for the events to replay, so that none are
+        // missed in between
+        let events = svc.deps.chat_event_sender.subscribe();
+        let stream = svc
+            .deps
+            .event_bus
+            .replay()
+            .open(user_id, request.header_map())?;
+        let stream_log = stream.log.clone();
+
         tracing::debug!("creating stream events");
-        let send_task = svc.spawn_event_stream_processor(user_id, socket);
+        if let Err(err) = presence::stream_opened(&svc.deps, user_id).await {
+            tracing::error!("couldn't update presence of user {}: {}", user_id, err);
+        }
+        let send_task = svc.spawn_event_stream_processor(user_id, socket, events, stream);
+        let send_res = send_task.await;
+        stream_log.lock().close();
 
-        if let Err(err) = send_task.await {
+        if let Err(err) = presence::stream_closed(&svc.deps, user_id).await {
+            tracing::error!("couldn't update presence of user {}: {}", user_id, err);
+        }
+        if let Err(err) = send_res {
             return Err(format!("stream events send loop task panicked: {}, aborting", err).into());
         }
 
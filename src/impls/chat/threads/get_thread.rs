@@ -0,0 +1,36 @@
+use super::*;
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct GetThreadRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    /// Message the thread was started from.
+    pub message_id: u64,
+}
+
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetThreadRequest,
+) -> ServerResult<ThreadInfo> {
+    let GetThreadRequest {
+        guild_id,
+        channel_id,
+        message_id,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    check_can_view_thread(chat_tree, guild_id, channel_id, user_id).await?;
+
+    let Some(thread) = chat_tree
+        .get_thread_logic(guild_id, channel_id, message_id)
+        .await?
+    else {
+        bail!(NO_SUCH_THREAD_ERR);
+    };
+
+    Ok(ThreadInfo::new(thread, user_id))
+}
@@ -0,0 +1,109 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+use crate::utils::cursor::Direction;
+
+/// Default amount of messages returned.
+const DEFAULT_LIMIT: usize = 50;
+/// Maximum amount of messages that can be returned at once.
+const MAX_LIMIT: usize = 200;
+
+#[derive(Debug, Deserialize)]
+pub struct GetThreadMessagesRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    /// Message the thread was started from.
+    pub message_id: u64,
+    /// Get the newest messages first.
+    #[serde(default)]
+    pub newest_first: bool,
+    /// `next_cursor` of the previous page.
+    #[serde(default)]
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ThreadMessage {
+    pub thread_message_id: u64,
+    pub author_id: u64,
+    /// In seconds since unix epoch.
+    pub created_at: u64,
+    pub text: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetThreadMessagesResponse {
+    pub messages: Vec<ThreadMessage>,
+    /// Cursor to get the next page with, not set if this is the last page.
+    pub next_cursor: Option<String>,
+}
+
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: GetThreadMessagesRequest,
+) -> ServerResult<GetThreadMessagesResponse> {
+    let GetThreadMessagesRequest {
+        guild_id,
+        channel_id,
+        message_id,
+        newest_first,
+        cursor,
+        limit,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    check_can_view_thread(chat_tree, guild_id, channel_id, user_id).await?;
+    if chat_tree
+        .get_thread_logic(guild_id, channel_id, message_id)
+        .await?
+        .is_none()
+    {
+        bail!(NO_SUCH_THREAD_ERR);
+    }
+
+    let direction = if newest_first {
+        Direction::Backward
+    } else {
+        Direction::Forward
+    };
+    let filter = [
+        guild_id.to_be_bytes(),
+        channel_id.to_be_bytes(),
+        message_id.to_be_bytes(),
+    ]
+    .concat();
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let pager = Pager::new(direction, filter, cursor.as_deref(), limit)?;
+    let page = chat_tree
+        .get_thread_messages_logic(guild_id, channel_id, message_id, &pager)
+        .await?;
+
+    let messages = page
+        .items
+        .into_iter()
+        .map(|(thread_message_id, message)| {
+            let text = match message.content.and_then(|c| c.content) {
+                Some(content::Content::TextMessage(content::TextContent {
+                    content: Some(FormattedText { text, .. }),
+                })) => text,
+                _ => String::new(),
+            };
+            ThreadMessage {
+                thread_message_id,
+                author_id: message.author_id,
+                created_at: message.created_at,
+                text,
+            }
+        })
+        .collect();
+
+    Ok(GetThreadMessagesResponse {
+        messages,
+        next_cursor: page.next_cursor,
+    })
+}
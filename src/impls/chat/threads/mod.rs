@@ -0,0 +1,52 @@
+use super::*;
+
+use serde::Serialize;
+
+pub mod get_thread;
+pub mod get_thread_messages;
+pub mod send_thread_message;
+pub mod set_thread_subscription;
+pub mod start_thread;
+
+/// A thread, in a form that can be sent over the JSON API.
+#[derive(Debug, Serialize)]
+pub struct ThreadInfo {
+    pub name: String,
+    pub created_by: u64,
+    /// In seconds since unix epoch.
+    pub created_at: u64,
+    pub message_count: u64,
+    /// In seconds since unix epoch, not set if no messages were sent in the thread.
+    pub last_message_at: Option<u64>,
+    /// Whether the user is subscribed to the thread, and gets its events.
+    pub subscribed: bool,
+}
+
+impl ThreadInfo {
+    fn new(thread: Thread, user_id: u64) -> Self {
+        Self {
+            subscribed: thread.subscribers.contains(&user_id),
+            name: thread.name,
+            created_by: thread.created_by,
+            created_at: thread.created_at,
+            message_count: thread.message_count,
+            last_message_at: thread.last_message_at,
+        }
+    }
+}
+
+/// Checks that the user can see the channel a thread is in.
+async fn check_can_view_thread(
+    chat_tree: &ChatTree,
+    guild_id: u64,
+    channel_id: u64,
+    user_id: u64,
+) -> ServerResult<()> {
+    chat_tree
+        .check_guild_user_channel(guild_id, user_id, channel_id)
+        .await?;
+    chat_tree
+        .check_perms(guild_id, Some(channel_id), user_id, "messages.view", false)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct SendThreadMessageRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    /// Message the thread was started from.
+    pub message_id: u64,
+    pub text: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SendThreadMessageResponse {
+    /// ID of the message in the thread.
+    pub thread_message_id: u64,
+}
+
+/// Sends a text message in a thread, and subscribes the user to it. The
+/// message is sent to the subscribers of the thread as a message event, with
+/// the thread parent extension in its metadata.
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: SendThreadMessageRequest,
+) -> ServerResult<SendThreadMessageResponse> {
+    let SendThreadMessageRequest {
+        guild_id,
+        channel_id,
+        message_id,
+        text,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    check_can_view_thread(chat_tree, guild_id, channel_id, user_id).await?;
+    chat_tree
+        .check_perms(guild_id, Some(channel_id), user_id, "messages.send", false)
+        .await?;
+    // thread messages are always text
+    chat_tree
+        .check_can_post_in_channel(guild_id, channel_id, user_id, false)
+        .await?;
+
+    if text.trim().is_empty() {
+        bail!(ServerError::MessageContentCantBeEmpty);
+    }
+    // thread messages can't be quarantined or flagged, so they are rejected instead
+    if let Some(domain) = svc.scan_message_links(guild_id, &text, false).await? {
+        bail!(ServerError::BlockedDomain(domain));
+    }
+
+    let mut metadata = Metadata::default();
+    metadata.extension.insert(
+        THREAD_PARENT_EXTENSION.to_string(),
+        Anything {
+            kind: "text/plain".to_string(),
+            body: message_id.to_string().into_bytes().into(),
+        },
+    );
+    let message = HarmonyMessage {
+        metadata: Some(metadata),
+        author_id: user_id,
+        created_at: get_time_secs(),
+        edited_at: None,
+        content: Some(Content {
+            content: Some(content::Content::TextMessage(content::TextContent {
+                content: Some(FormattedText::new(text, Vec::new())),
+            })),
+        }),
+        in_reply_to: None,
+        overrides: None,
+        reactions: Vec::new(),
+    };
+
+    let (thread_message_id, thread) = chat_tree
+        .send_thread_message_logic(guild_id, channel_id, message_id, message.clone())
+        .await?;
+    svc.deps
+        .typing_indicators
+        .stop(guild_id, channel_id, user_id);
+
+    svc.deps.event_bus.publish(DomainEvent::ThreadMessageSent {
+        guild_id,
+        channel_id,
+        message_id,
+        thread_message_id,
+        message,
+        subscribers: thread.subscribers,
+    });
+
+    Ok(SendThreadMessageResponse { thread_message_id })
+}
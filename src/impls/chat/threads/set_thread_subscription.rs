@@ -0,0 +1,40 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct SetThreadSubscriptionRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    /// Message the thread was started from.
+    pub message_id: u64,
+    pub subscribed: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetThreadSubscriptionResponse {}
+
+/// Subscribes the user to the events of a thread, or unsubscribes them.
+/// Sending a message in a thread subscribes to it too.
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: SetThreadSubscriptionRequest,
+) -> ServerResult<SetThreadSubscriptionResponse> {
+    let SetThreadSubscriptionRequest {
+        guild_id,
+        channel_id,
+        message_id,
+        subscribed,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    check_can_view_thread(chat_tree, guild_id, channel_id, user_id).await?;
+
+    chat_tree
+        .set_thread_subscription_logic(guild_id, channel_id, message_id, user_id, subscribed)
+        .await?;
+
+    Ok(SetThreadSubscriptionResponse {})
+}
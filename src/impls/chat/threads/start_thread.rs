@@ -0,0 +1,50 @@
+use super::*;
+
+use serde::Deserialize;
+
+/// Longest a thread name can be, in characters.
+const MAX_NAME_LEN: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct StartThreadRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    /// Message to start the thread from.
+    pub message_id: u64,
+    pub name: String,
+}
+
+/// Starts a thread from a message. The user who starts it is subscribed to it.
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    request: StartThreadRequest,
+) -> ServerResult<ThreadInfo> {
+    let StartThreadRequest {
+        guild_id,
+        channel_id,
+        message_id,
+        name,
+    } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    check_can_view_thread(chat_tree, guild_id, channel_id, user_id).await?;
+    chat_tree
+        .check_perms(guild_id, Some(channel_id), user_id, "messages.send", false)
+        .await?;
+
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+        bail!((
+            "h.invalid-thread-name",
+            "thread names must not be empty or longer than 100 characters"
+        ));
+    }
+
+    let thread = chat_tree
+        .start_thread_logic(guild_id, channel_id, message_id, user_id, name.to_string())
+        .await?;
+
+    Ok(ThreadInfo::new(thread, user_id))
+}
@@ -0,0 +1,252 @@
+//! Diagnostics to help find out why a running server is slow, without restarting it.
+//!
+//! Tokio task counts and poll durations aren't collected here, run scherzo
+//! with `--console` and use `tokio-console` to see them.
+
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+
+use ahash::RandomState;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+
+use super::{chat::ChatServer, mediaproxy, prelude::*, rest::remote_media::RemoteMediaHostStats};
+use crate::db::BlockingPoolStats;
+
+/// Counters updated by the rest of the server.
+#[derive(Default)]
+pub struct Diagnostics {
+    /// Number of event streams currently running.
+    pub active_event_streams: AtomicI64,
+    /// Events event streams missed because they couldn't keep up with the broadcast channel.
+    pub lagged_chat_events: AtomicU64,
+    /// Number of times an event stream fell behind the broadcast channel.
+    pub lag_occurrences: AtomicU64,
+    /// Logins that failed because of a wrong email or password.
+    pub failed_logins: AtomicU64,
+    /// Logins that weren't tried because they had to wait for a delay, or
+    /// were locked out.
+    pub rejected_logins: AtomicU64,
+    /// Number of times an account or IP got locked out.
+    pub login_lockouts: AtomicU64,
+    /// Call quality of everyone connected to voice.
+    pub voice: VoiceStats,
+}
+
+impl Diagnostics {
+    /// Counts an event stream as active until the returned guard is dropped.
+    pub fn track_event_stream(&self) -> EventStreamGuard<'_> {
+        self.active_event_streams.fetch_add(1, Ordering::Relaxed);
+        EventStreamGuard(self)
+    }
+}
+
+/// Call quality of a voice participant, from the RTCP reports of their producer.
+#[derive(Debug, Clone, Serialize)]
+pub struct VoiceParticipantStats {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    pub user_id: u64,
+    /// Round trip time in milliseconds. Not set until the client sent enough
+    /// reports to calculate it.
+    pub rtt_ms: Option<f64>,
+    /// Interarrival jitter, in RTP timestamp units.
+    pub jitter: u32,
+    /// Fraction of packets lost in the last report interval, between 0 and 1.
+    pub packet_loss: f64,
+    pub packets_lost: u64,
+    pub packets_received: u64,
+    /// When these stats were collected, in seconds since the unix epoch.
+    pub updated_at: u64,
+}
+
+/// Latest call quality stats of voice participants.
+///
+/// Participants that can't speak (like stage listeners) don't have a
+/// producer, so there are no stats for them.
+#[derive(Default)]
+pub struct VoiceStats {
+    participants: DashMap<(u64, u64, u64), VoiceParticipantStats, RandomState>,
+}
+
+impl VoiceStats {
+    pub fn update(&self, stats: VoiceParticipantStats) {
+        self.participants
+            .insert((stats.guild_id, stats.channel_id, stats.user_id), stats);
+    }
+
+    pub fn remove(&self, guild_id: u64, channel_id: u64, user_id: u64) {
+        self.participants.remove(&(guild_id, channel_id, user_id));
+    }
+
+    pub fn get(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        user_id: u64,
+    ) -> Option<VoiceParticipantStats> {
+        self.participants
+            .get(&(guild_id, channel_id, user_id))
+            .map(|stats| stats.clone())
+    }
+
+    /// Stats of everyone in a voice channel.
+    pub fn in_channel(&self, guild_id: u64, channel_id: u64) -> Vec<VoiceParticipantStats> {
+        self.participants
+            .iter()
+            .filter(|entry| entry.guild_id == guild_id && entry.channel_id == channel_id)
+            .map(|entry| entry.value().clone())
+            .collect()
+    }
+
+    pub fn summary(&self) -> VoiceStatsSummary {
+        summarize(self.participants.iter().map(|entry| entry.value().clone()))
+    }
+}
+
+/// Voice stats aggregated over all participants.
+#[derive(Debug, Default, Serialize)]
+pub struct VoiceStatsSummary {
+    pub participants: usize,
+    pub average_rtt_ms: Option<f64>,
+    pub average_jitter: f64,
+    pub average_packet_loss: f64,
+    pub worst_packet_loss: f64,
+}
+
+fn summarize(stats: impl Iterator<Item = VoiceParticipantStats>) -> VoiceStatsSummary {
+    let mut summary = VoiceStatsSummary::default();
+    let (mut rtt_total, mut rtt_count) = (0.0, 0);
+    let mut jitter_total = 0.0;
+    let mut packet_loss_total = 0.0;
+    for stats in stats {
+        summary.participants += 1;
+        if let Some(rtt) = stats.rtt_ms {
+            rtt_total += rtt;
+            rtt_count += 1;
+        }
+        jitter_total += f64::from(stats.jitter);
+        packet_loss_total += stats.packet_loss;
+        summary.worst_packet_loss = summary.worst_packet_loss.max(stats.packet_loss);
+    }
+    if rtt_count > 0 {
+        summary.average_rtt_ms = Some(rtt_total / rtt_count as f64);
+    }
+    if summary.participants > 0 {
+        summary.average_jitter = jitter_total / summary.participants as f64;
+        summary.average_packet_loss = packet_loss_total / summary.participants as f64;
+    }
+    summary
+}
+
+pub struct EventStreamGuard<'a>(&'a Diagnostics);
+
+impl<'a> Drop for EventStreamGuard<'a> {
+    fn drop(&mut self) {
+        self.0.active_event_streams.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiagnosticsReport {
+    pub valid_sessions: usize,
+    pub mediaproxy_cache_entries: usize,
+    pub federation_keys_cached: usize,
+    pub federation_clients_cached: usize,
+    pub domain_blocklist_entries: usize,
+    pub active_event_streams: i64,
+    pub chat_event_receivers: usize,
+    pub domain_event_receivers: usize,
+    pub lagged_chat_events: u64,
+    pub lag_occurrences: u64,
+    pub failed_logins: u64,
+    pub rejected_logins: u64,
+    pub login_lockouts: u64,
+    /// Requests rejected because their IP is banned.
+    pub blocked_ip_requests: u64,
+    /// Media fetched from other homeservers, per homeserver.
+    pub remote_media: Vec<RemoteMediaHostStats>,
+    pub voice: VoiceStatsSummary,
+    /// Not set if the database backend doesn't run operations on blocking threads.
+    pub db_blocking_pool: Option<BlockingPoolStats>,
+}
+
+impl DiagnosticsReport {
+    pub fn collect(deps: &Dependencies) -> Self {
+        let diagnostics = &deps.diagnostics;
+        let (federation_keys_cached, federation_clients_cached) = deps
+            .key_manager
+            .as_ref()
+            .map_or((0, 0), |manager| manager.cache_sizes());
+
+        Self {
+            valid_sessions: deps.valid_sessions.len(),
+            mediaproxy_cache_entries: mediaproxy::cache_len(),
+            federation_keys_cached,
+            federation_clients_cached,
+            domain_blocklist_entries: deps.domain_blocklist.len(),
+            active_event_streams: diagnostics.active_event_streams.load(Ordering::Relaxed),
+            chat_event_receivers: deps.chat_event_sender.receiver_count(),
+            domain_event_receivers: deps.event_bus.receiver_count(),
+            lagged_chat_events: diagnostics.lagged_chat_events.load(Ordering::Relaxed),
+            lag_occurrences: diagnostics.lag_occurrences.load(Ordering::Relaxed),
+            failed_logins: diagnostics.failed_logins.load(Ordering::Relaxed),
+            rejected_logins: diagnostics.rejected_logins.load(Ordering::Relaxed),
+            login_lockouts: diagnostics.login_lockouts.load(Ordering::Relaxed),
+            blocked_ip_requests: deps.ip_bans.blocked_requests(),
+            remote_media: deps.remote_media.stats(),
+            voice: diagnostics.voice.summary(),
+            db_blocking_pool: deps.db.blocking_pool_stats(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetDiagnosticsRequest {}
+
+/// Gets diagnostics about the running server. Only homeserver admins can use this.
+pub async fn handler(
+    svc: &ChatServer,
+    user_id: u64,
+    _request: GetDiagnosticsRequest,
+) -> ServerResult<DiagnosticsReport> {
+    svc.deps.chat_tree.check_homeserver_admin(user_id).await?;
+
+    if !svc.deps.config.policy.enable_diagnostics {
+        bail!((
+            "h.diagnostics-disabled",
+            "diagnostics are disabled on this server"
+        ));
+    }
+
+    Ok(DiagnosticsReport::collect(&svc.deps))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn participant(rtt_ms: Option<f64>, jitter: u32, packet_loss: f64) -> VoiceParticipantStats {
+        VoiceParticipantStats {
+            guild_id: 1,
+            channel_id: 2,
+            user_id: 3,
+            rtt_ms,
+            jitter,
+            packet_loss,
+            packets_lost: 0,
+            packets_received: 0,
+            updated_at: 0,
+        }
+    }
+
+    #[test]
+    fn summarizes_voice_stats() {
+        let summary =
+            summarize([participant(Some(40.0), 10, 0.0), participant(None, 30, 0.5)].into_iter());
+        assert_eq!(summary.participants, 2);
+        assert_eq!(summary.average_rtt_ms, Some(40.0));
+        assert_eq!(summary.average_jitter, 20.0);
+        assert_eq!(summary.average_packet_loss, 0.25);
+        assert_eq!(summary.worst_packet_loss, 0.5);
+    }
+}
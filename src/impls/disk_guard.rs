@@ -0,0 +1,405 @@
+//! Protects the server from running out of disk space, which can corrupt the
+//! database.
+//!
+//! A watchdog checks the free space of the disks the database and media are
+//! on. When the media disk gets low, uploads are denied. When the database
+//! disk gets low, the server becomes read-only: only requests that don't
+//! change anything, and admin requests so space can be freed, are served.
+//! Both are lifted once there is enough free space again.
+
+use std::{
+    convert::Infallible,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use harmony_rust_sdk::api::exports::hrpc::server::transport::http::{HttpRequest, HttpResponse};
+use hrpc::exports::futures_util::future::{self, Either, Ready};
+use tower::{Layer, Service};
+
+use crate::config::DiskWatchdogConfig;
+
+use super::prelude::*;
+
+/// Methods of hrpc services that only read, by the start of their names.
+const READ_METHOD_PREFIXES: &[&str] = &[
+    "Get",
+    "Query",
+    "Stream",
+    "Check",
+    "Search",
+    "Preview",
+    "Fetch",
+    "Key",
+    "CanInstantView",
+    "InstantView",
+];
+
+/// REST API endpoints that only read.
+const READ_ENDPOINTS: &[&str] = &[
+    "ready",
+    "chat/permission-presets",
+    "chat/simulate-permission-change",
+    "chat/guild-theme",
+    "chat/guild-locale",
+    "chat/preview-guild-theme",
+    "chat/channel-topic-history",
+    "chat/disappearing-messages",
+    "chat/scheduled-messages",
+    "chat/search-messages",
+    "chat/reactors",
+    "chat/reaction-roles",
+    "chat/thread",
+    "chat/thread-messages",
+    "chat/stage",
+    "chat/screening",
+    "chat/screening-applications",
+    "chat/quarantined-messages",
+    "chat/acknowledgements",
+    "chat/pending-acknowledgements",
+    "chat/member-list",
+    "chat/member-chunk",
+    "chat/subscribe-to-member-list",
+    "chat/member-list-updates",
+    "chat/members",
+    "chat/search-members",
+    "chat/initial-sync",
+    "chat/gallery",
+    "chat/channel-role-gate",
+    "chat/typing-users",
+    "chat/voice-stats",
+    "chat/messages-after",
+    "chat/bans",
+    "chat/ban-list-subscriptions",
+    "chat/text-macros",
+    "chat/widget",
+    "chat/guild-directory",
+    "chat/link-scanning",
+    "chat/audit-log",
+    "chat/guild-usage",
+    "chat/invite-role-grant",
+    "chat/invite-stats",
+    "chat/role-member-caps",
+    "chat/integrations",
+    "profile/notes",
+    "profile/blocked-users",
+    "profile/export-settings",
+    "profile/linked-accounts",
+    "auth/sessions",
+    "emote/aliases",
+    "emote/resolve",
+    "emote/equipped-packs",
+    "federation/keys",
+    "replication/snapshot",
+    "replication/ops",
+];
+
+/// What the server doesn't allow because of low disk space.
+#[derive(Debug, Clone, Default)]
+pub struct DiskGuard {
+    inner: Arc<DiskGuardState>,
+}
+
+#[derive(Debug, Default)]
+struct DiskGuardState {
+    uploads_denied: AtomicBool,
+    read_only: AtomicBool,
+}
+
+impl DiskGuard {
+    /// Fails if uploads are denied, or the server is read-only.
+    pub fn check_uploads(&self) -> Result<(), ServerError> {
+        self.check_writable()?;
+        if self.inner.uploads_denied.load(Ordering::Relaxed) {
+            return Err(ServerError::UploadsDisabled);
+        }
+        Ok(())
+    }
+
+    /// Fails if the server is read-only.
+    pub fn check_writable(&self) -> Result<(), ServerError> {
+        if self.inner.read_only.load(Ordering::Relaxed) {
+            return Err(ServerError::ServerReadOnly);
+        }
+        Ok(())
+    }
+}
+
+/// Gets the free space of the disk a path is on, in bytes and in percent.
+fn free_space(path: &Path) -> std::io::Result<(u64, u64)> {
+    let available = fs2::available_space(path)?;
+    let total = fs2::total_space(path)?;
+    let percent = available
+        .saturating_mul(100)
+        .checked_div(total)
+        .unwrap_or(100);
+    Ok((available, percent))
+}
+
+/// Turns a protection on or off depending on free space. It's only turned off
+/// again once there is `margin` percent more free space than `threshold`.
+/// Returns the new state if it changed.
+fn update_protection(
+    flag: &AtomicBool,
+    free_percent: u64,
+    threshold: u8,
+    margin: u8,
+) -> Option<bool> {
+    let threshold = u64::from(threshold);
+    let protected = flag.load(Ordering::Relaxed);
+    let should_protect = if protected {
+        free_percent < threshold + u64::from(margin)
+    } else {
+        free_percent < threshold
+    };
+    (should_protect != protected).then(|| {
+        flag.store(should_protect, Ordering::Relaxed);
+        should_protect
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Volume {
+    Database,
+    Media,
+}
+
+impl Volume {
+    const fn name(self) -> &'static str {
+        match self {
+            Volume::Database => "database",
+            Volume::Media => "media",
+        }
+    }
+}
+
+/// Spawns a task that checks free disk space, and protects the server when
+/// it gets low.
+pub fn spawn_watchdog(deps: Arc<Dependencies>, db_path: PathBuf) {
+    let config = deps.config.disk_watchdog.clone();
+    let alert_below = deps
+        .config
+        .alerts
+        .as_ref()
+        .map_or(0, |alerts| alerts.min_free_disk_percent);
+    if config.read_only_below_percent == 0
+        && config.deny_uploads_below_percent == 0
+        && alert_below == 0
+    {
+        return;
+    }
+
+    tokio::spawn(async move {
+        tracing::info!("starting disk watchdog task");
+        loop {
+            check_disk(&deps, &config, alert_below, Volume::Database, &db_path);
+            if deps.config.media.s3.is_none() {
+                check_disk(
+                    &deps,
+                    &config,
+                    alert_below,
+                    Volume::Media,
+                    &deps.config.media.media_root,
+                );
+            }
+            tokio::time::sleep(Duration::from_secs(config.check_interval_secs.max(1))).await;
+        }
+    });
+}
+
+fn check_disk(
+    deps: &Dependencies,
+    config: &DiskWatchdogConfig,
+    alert_below: u8,
+    volume: Volume,
+    path: &Path,
+) {
+    let name = volume.name();
+    let (available, free_percent) = match free_space(path) {
+        Ok(space) => space,
+        Err(err) => {
+            tracing::error!("couldn't get free space of {}: {}", path.display(), err);
+            return;
+        }
+    };
+
+    if free_percent < u64::from(alert_below) {
+        deps.alerts.raise(
+            deps,
+            format!("disk-full:{}", name),
+            format!(
+                "disk of the {} at {} has only {}% ({} MiB) free space left",
+                name,
+                path.display(),
+                free_percent,
+                available / 1024 / 1024
+            ),
+        );
+    }
+
+    let state = &deps.disk_guard.inner;
+    let (flag, threshold, protection) = match volume {
+        Volume::Database => (
+            &state.read_only,
+            config.read_only_below_percent,
+            "read-only",
+        ),
+        Volume::Media => (
+            &state.uploads_denied,
+            config.deny_uploads_below_percent,
+            "deny-uploads",
+        ),
+    };
+    if threshold == 0 {
+        return;
+    }
+    match update_protection(flag, free_percent, threshold, config.recover_margin_percent) {
+        Some(true) => {
+            tracing::error!(
+                "{} disk has {}% free space left, switching to {} mode",
+                name,
+                free_percent,
+                protection
+            );
+            deps.alerts.raise(
+                deps,
+                format!("write-protection:{}", protection),
+                format!(
+                    "{} disk at {} has only {}% free space left, the server switched to {} mode",
+                    name,
+                    path.display(),
+                    free_percent,
+                    protection
+                ),
+            );
+        }
+        Some(false) => tracing::warn!(
+            "{} disk has {}% free space again, leaving {} mode",
+            name,
+            free_percent,
+            protection
+        ),
+        None => {}
+    }
+}
+
+/// Whether a request can be served while the server is read-only.
+fn is_read_request(path: &str) -> bool {
+    if let Some(endpoint) = path.strip_prefix("/_scherzo/") {
+        return endpoint.starts_with("admin/")
+            || endpoint.starts_with("widget/")
+            || READ_ENDPOINTS.contains(&endpoint);
+    }
+    if path.starts_with("/_harmony/media/download/") || path == "/_harmony/about" {
+        return true;
+    }
+    if path == "/_harmony/media/upload" {
+        return false;
+    }
+    let method = path.rsplit('/').next().unwrap_or_default();
+    READ_METHOD_PREFIXES
+        .iter()
+        .any(|prefix| method.starts_with(prefix))
+}
+
+/// Rejects requests that would write to disk while the server is protecting it.
+#[derive(Clone)]
+pub struct WriteGuardLayer {
+    guard: DiskGuard,
+}
+
+impl WriteGuardLayer {
+    pub fn new(guard: DiskGuard) -> Self {
+        Self { guard }
+    }
+}
+
+impl<S> Layer<S> for WriteGuardLayer
+where
+    S: Service<HttpRequest, Response = HttpResponse, Error = Infallible> + Send + 'static,
+{
+    type Service = WriteGuardService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        WriteGuardService {
+            guard: self.guard.clone(),
+            inner,
+        }
+    }
+}
+
+pub struct WriteGuardService<S> {
+    guard: DiskGuard,
+    inner: S,
+}
+
+impl<S> Service<HttpRequest> for WriteGuardService<S>
+where
+    S: Service<HttpRequest, Response = HttpResponse, Error = Infallible> + Send + 'static,
+{
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = Either<Ready<Result<HttpResponse, Infallible>>, S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Service::poll_ready(&mut self.inner, cx)
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let path = request.uri().path();
+        let res = if path == "/_harmony/media/upload" {
+            self.guard.check_uploads()
+        } else if is_read_request(path) {
+            Ok(())
+        } else {
+            self.guard.check_writable()
+        };
+
+        match res {
+            Ok(()) => Either::Right(Service::call(&mut self.inner, request)),
+            Err(err) => {
+                let response =
+                    if path.starts_with("/_harmony/media/") || path.starts_with("/_scherzo/") {
+                        err.into_rest_http_response()
+                    } else {
+                        err.into_http_response()
+                    };
+                Either::Left(future::ready(Ok(response)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn protection_recovers_with_margin() {
+        let flag = AtomicBool::new(false);
+        assert_eq!(update_protection(&flag, 5, 2, 1), None);
+        assert_eq!(update_protection(&flag, 1, 2, 1), Some(true));
+        // still not enough free space above the threshold
+        assert_eq!(update_protection(&flag, 2, 2, 1), None);
+        assert_eq!(update_protection(&flag, 3, 2, 1), Some(false));
+    }
+
+    #[test]
+    fn classifies_read_requests() {
+        assert!(is_read_request("/protocol.chat.v1.ChatService/GetGuild"));
+        assert!(is_read_request(
+            "/protocol.chat.v1.ChatService/StreamEvents"
+        ));
+        assert!(!is_read_request(
+            "/protocol.chat.v1.ChatService/SendMessage"
+        ));
+        assert!(is_read_request("/_scherzo/chat/members"));
+        assert!(is_read_request("/_scherzo/admin/prune-guild"));
+        assert!(!is_read_request("/_scherzo/chat/set-text-macro"));
+        assert!(!is_read_request("/_harmony/media/upload"));
+    }
+}
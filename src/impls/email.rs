@@ -0,0 +1,59 @@
+//! Sending emails through the SMTP server set in config.
+
+use crate::config::EmailConfig;
+
+use super::prelude::*;
+
+#[cfg(feature = "email")]
+fn email_err(err: impl std::fmt::Display) -> ServerError {
+    tracing::error!("couldn't send email: {}", err);
+    ServerError::InternalServerError
+}
+
+/// Sends a plain text email to an address.
+#[cfg(feature = "email")]
+pub async fn send(config: &EmailConfig, to: &str, subject: &str, body: String) -> ServerResult<()> {
+    use lettre::{
+        transport::smtp::authentication::Credentials, AsyncSmtpTransport, AsyncTransport, Message,
+        Tokio1Executor,
+    };
+
+    let message = Message::builder()
+        .from(config.from.parse().map_err(email_err)?)
+        .to(to
+            .parse()
+            .map_err(|_| ("h.invalid-email", "email address is invalid"))?)
+        .subject(subject)
+        .body(body)
+        .map_err(email_err)?;
+
+    let mut transport = if config.starttls {
+        AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.smtp_host)
+    } else {
+        AsyncSmtpTransport::<Tokio1Executor>::relay(&config.smtp_host)
+    }
+    .map_err(email_err)?
+    .port(config.smtp_port);
+    if let Some(username) = &config.username {
+        transport = transport.credentials(Credentials::new(
+            username.clone(),
+            config.password.clone().unwrap_or_default(),
+        ));
+    }
+
+    transport.build().send(message).await.map_err(email_err)?;
+
+    Ok(())
+}
+
+/// Sends a plain text email to an address.
+#[cfg(not(feature = "email"))]
+pub async fn send(
+    _config: &EmailConfig,
+    _to: &str,
+    _subject: &str,
+    _body: String,
+) -> ServerResult<()> {
+    tracing::error!("can't send email, scherzo wasn't built with the `email` feature");
+    bail!(ServerError::NotImplemented);
+}
@@ -19,21 +19,21 @@ pub async fn handler(
 
         svc.deps.emote_tree.insert(emote_key, data).await?;
 
+        let version = svc.deps.emote_tree.bump_pack_version_logic(pack_id).await?;
         let equipped_users = svc
             .deps
             .emote_tree
             .calculate_users_pack_equipped(pack_id)
             .await?;
-        svc.send_event_through_chan(
-            EventSub::Homeserver,
-            stream_event::Event::EmotePackEmotesUpdated(EmotePackEmotesUpdated {
+        svc.deps
+            .event_bus
+            .publish(DomainEvent::EmotePackEmotesUpdated {
                 pack_id,
+                version,
                 added_emotes: vec![emote],
                 deleted_emotes: Vec::new(),
-            }),
-            None,
-            EventContext::new(equipped_users),
-        );
+                user_ids: equipped_users,
+            });
     }
 
     Ok((AddEmoteToPackResponse {}).into_response())
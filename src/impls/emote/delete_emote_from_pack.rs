@@ -16,22 +16,26 @@ pub async fn handler(
     let key = make_emote_pack_emote_key(pack_id, &name);
 
     svc.deps.emote_tree.remove(key).await?;
+    svc.deps
+        .emote_tree
+        .remove_aliases_to_logic(pack_id, &name)
+        .await?;
 
+    let version = svc.deps.emote_tree.bump_pack_version_logic(pack_id).await?;
     let equipped_users = svc
         .deps
         .emote_tree
         .calculate_users_pack_equipped(pack_id)
         .await?;
-    svc.send_event_through_chan(
-        EventSub::Homeserver,
-        stream_event::Event::EmotePackEmotesUpdated(EmotePackEmotesUpdated {
+    svc.deps
+        .event_bus
+        .publish(DomainEvent::EmotePackEmotesUpdated {
             pack_id,
+            version,
             added_emotes: Vec::new(),
             deleted_emotes: vec![name],
-        }),
-        None,
-        EventContext::new(equipped_users),
-    );
+            user_ids: equipped_users,
+        });
 
     Ok((DeleteEmoteFromPackResponse {}).into_response())
 }
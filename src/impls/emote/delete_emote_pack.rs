@@ -17,10 +17,20 @@ pub async fn handler(
 
     let mut batch = Batch::default();
     batch.remove(key);
+    batch.remove(make_emote_pack_version_key(pack_id));
     for res in svc.deps.emote_tree.scan_prefix(&key).await {
         let (key, _) = res?;
         batch.remove(key);
     }
+    for res in svc
+        .deps
+        .emote_tree
+        .scan_prefix(&make_emote_alias_prefix(pack_id))
+        .await
+    {
+        let (key, _) = res?;
+        batch.remove(key);
+    }
     svc.deps
         .emote_tree
         .inner
@@ -38,12 +48,10 @@ pub async fn handler(
         .emote_tree
         .calculate_users_pack_equipped(pack_id)
         .await?;
-    svc.send_event_through_chan(
-        EventSub::Homeserver,
-        stream_event::Event::EmotePackDeleted(EmotePackDeleted { pack_id }),
-        None,
-        EventContext::new(equipped_users),
-    );
+    svc.deps.event_bus.publish(DomainEvent::EmotePackDeleted {
+        pack_id,
+        user_ids: equipped_users,
+    });
 
     Ok((DeleteEmotePackResponse {}).into_response())
 }
@@ -13,12 +13,10 @@ pub async fn handler(
         .dequip_emote_pack_logic(user_id, pack_id)
         .await?;
 
-    svc.send_event_through_chan(
-        EventSub::Homeserver,
-        stream_event::Event::EmotePackDeleted(EmotePackDeleted { pack_id }),
-        None,
-        EventContext::new(vec![user_id]),
-    );
+    svc.deps.event_bus.publish(DomainEvent::EmotePackDeleted {
+        pack_id,
+        user_ids: vec![user_id],
+    });
 
     Ok((DequipEmotePackResponse {}).into_response())
 }
@@ -15,12 +15,10 @@ pub async fn handler(
             .emote_tree
             .equip_emote_pack_logic(user_id, pack_id)
             .await?;
-        svc.send_event_through_chan(
-            EventSub::Homeserver,
-            stream_event::Event::EmotePackAdded(EmotePackAdded { pack: Some(pack) }),
-            None,
-            EventContext::new(vec![user_id]),
-        );
+        svc.deps.event_bus.publish(DomainEvent::EmotePackAdded {
+            pack,
+            user_ids: vec![user_id],
+        });
     } else {
         return Err(ServerError::EmotePackNotFound.into());
     }
@@ -0,0 +1,46 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct GetEmoteAliasesRequest {
+    pub pack_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmoteAliasInfo {
+    pub alias: String,
+    pub emote_name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetEmoteAliasesResponse {
+    pub aliases: Vec<EmoteAliasInfo>,
+}
+
+pub async fn handler(
+    svc: &EmoteServer,
+    _user_id: u64,
+    request: GetEmoteAliasesRequest,
+) -> ServerResult<GetEmoteAliasesResponse> {
+    let GetEmoteAliasesRequest { pack_id } = request;
+
+    let emote_tree = &svc.deps.emote_tree;
+
+    if emote_tree
+        .get(make_emote_pack_key(pack_id))
+        .await?
+        .is_none()
+    {
+        return Err(ServerError::EmotePackNotFound.into());
+    }
+
+    let aliases = emote_tree
+        .get_emote_aliases_logic(pack_id)
+        .await?
+        .into_iter()
+        .map(|(alias, emote_name)| EmoteAliasInfo { alias, emote_name })
+        .collect();
+
+    Ok(GetEmoteAliasesResponse { aliases })
+}
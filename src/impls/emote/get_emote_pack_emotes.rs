@@ -14,19 +14,7 @@ pub async fn handler(
         return Err(ServerError::EmotePackNotFound.into());
     }
 
-    let emotes = svc
-        .deps
-        .emote_tree
-        .inner
-        .scan_prefix(&pack_key)
-        .await
-        .try_fold(Vec::new(), |mut all, res| {
-            let (key, value) = res.map_err(ServerError::from)?;
-            if key.len() > pack_key.len() {
-                all.push(db::deser_emote(value));
-            }
-            ServerResult::Ok(all)
-        })?;
+    let emotes = svc.deps.emote_tree.get_pack_emotes_logic(pack_id).await?;
 
     Ok((GetEmotePackEmotesResponse { emotes }).into_response())
 }
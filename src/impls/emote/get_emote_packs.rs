@@ -6,25 +6,11 @@ pub async fn handler(
 ) -> ServerResult<Response<GetEmotePacksResponse>> {
     let user_id = svc.deps.valid_sessions.auth(&request)?;
 
-    let prefix = make_equipped_emote_prefix(user_id);
     let equipped_packs = svc
         .deps
         .emote_tree
-        .inner
-        .scan_prefix(&prefix)
-        .await
-        .try_fold(Vec::new(), |mut all, res| {
-            let (key, _) = res.map_err(ServerError::from)?;
-            if key.len() == make_equipped_emote_key(user_id, 0).len() {
-                let pack_id =
-                    // Safety: since it will always be 8 bytes left afterwards
-                    u64::from_be_bytes(unsafe {
-                        key.split_at(prefix.len()).1.try_into().unwrap_unchecked()
-                    });
-                all.push(pack_id);
-            }
-            ServerResult::Ok(all)
-        })?;
+        .get_equipped_packs_logic(user_id)
+        .await?;
 
     let packs = svc
         .deps
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+use get_emote_aliases::EmoteAliasInfo;
+
+#[derive(Debug, Deserialize)]
+pub struct GetEquippedPacksRequest {
+    /// Versions of packs the client already has, by pack ID. Packs that are
+    /// still at these versions are sent without their emotes and aliases.
+    #[serde(default)]
+    pub known_versions: HashMap<u64, u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EquippedEmote {
+    pub name: String,
+    pub image_id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EquippedPack {
+    pub pack_id: u64,
+    pub pack_owner: u64,
+    pub pack_name: String,
+    pub version: u64,
+    /// Whether the pack is still at the version the client knows. If it is,
+    /// `emotes` and `aliases` are left empty.
+    pub unchanged: bool,
+    pub emotes: Vec<EquippedEmote>,
+    pub aliases: Vec<EmoteAliasInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetEquippedPacksResponse {
+    /// Packs the user has equipped, in the order emote names are looked up in.
+    pub packs: Vec<EquippedPack>,
+}
+
+/// Gets all packs the user has equipped with their emotes, so clients can
+/// sync them in one request. Clients should call this again when they get an
+/// emote pack event.
+pub async fn handler(
+    svc: &EmoteServer,
+    user_id: u64,
+    request: GetEquippedPacksRequest,
+) -> ServerResult<GetEquippedPacksResponse> {
+    let GetEquippedPacksRequest { known_versions } = request;
+
+    let emote_tree = &svc.deps.emote_tree;
+
+    let lookup_order = emote_tree.get_pack_lookup_order_logic(user_id).await?;
+    let mut packs = Vec::with_capacity(lookup_order.len());
+    for pack_id in lookup_order {
+        // the pack might have been deleted without everyone dequipping it
+        let Some(raw) = emote_tree.get(make_emote_pack_key(pack_id)).await? else {
+            continue;
+        };
+        let pack = db::deser_emote_pack(raw);
+        let version = emote_tree.get_pack_version_logic(pack_id).await?;
+        let unchanged = known_versions.get(&pack_id) == Some(&version);
+
+        let (emotes, aliases) = if unchanged {
+            (Vec::new(), Vec::new())
+        } else {
+            let emotes = emote_tree
+                .get_pack_emotes_logic(pack_id)
+                .await?
+                .into_iter()
+                .map(|emote| EquippedEmote {
+                    name: emote.name,
+                    image_id: emote.image_id,
+                })
+                .collect();
+            let aliases = emote_tree
+                .get_emote_aliases_logic(pack_id)
+                .await?
+                .into_iter()
+                .map(|(alias, emote_name)| EmoteAliasInfo { alias, emote_name })
+                .collect();
+            (emotes, aliases)
+        };
+
+        packs.push(EquippedPack {
+            pack_id,
+            pack_owner: pack.pack_owner,
+            pack_name: pack.pack_name,
+            version,
+            unchanged,
+            emotes,
+            aliases,
+        });
+    }
+
+    Ok(GetEquippedPacksResponse { packs })
+}
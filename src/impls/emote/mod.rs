@@ -1,13 +1,6 @@
-use harmony_rust_sdk::api::{
-    chat::Event,
-    emote::{emote_service_server::EmoteService, *},
-};
+use harmony_rust_sdk::api::emote::{emote_service_server::EmoteService, *};
 
-use super::{
-    chat::{EventBroadcast, EventContext, EventSub, PermCheck},
-    gen_rand_u64,
-    prelude::*,
-};
+use super::{bus::DomainEvent, gen_rand_u64, prelude::*};
 
 use db::{
     emote::*,
@@ -20,8 +13,13 @@ pub mod delete_emote_from_pack;
 pub mod delete_emote_pack;
 pub mod dequip_emote_pack;
 pub mod equip_emote_pack;
+pub mod get_emote_aliases;
 pub mod get_emote_pack_emotes;
 pub mod get_emote_packs;
+pub mod get_equipped_packs;
+pub mod resolve_emotes;
+pub mod set_emote_alias;
+pub mod set_emote_pack_priority;
 
 #[derive(Clone)]
 pub struct EmoteServer {
@@ -41,19 +39,6 @@ impl EmoteServer {
         self.disable_ratelimits = true;
         self
     }
-
-    #[inline(always)]
-    fn send_event_through_chan(
-        &self,
-        sub: EventSub,
-        event: stream_event::Event,
-        perm_check: Option<PermCheck<'static>>,
-        context: EventContext,
-    ) {
-        let broadcast = EventBroadcast::new(sub, Event::Emote(event), perm_check, context);
-
-        drop(self.deps.chat_event_sender.send(Arc::new(broadcast)));
-    }
 }
 
 impl EmoteService for EmoteServer {
@@ -124,6 +109,192 @@ impl EmoteTree {
         Ok(())
     }
 
+    pub async fn get_emote_logic(&self, pack_id: u64, name: &str) -> ServerResult<Option<Emote>> {
+        let emote = self
+            .get(make_emote_pack_emote_key(pack_id, name))
+            .await?
+            .map(db::deser_emote);
+
+        Ok(emote)
+    }
+
+    /// Gets the aliases of a pack, as pairs of alias and the name of the emote it points to.
+    pub async fn get_emote_aliases_logic(
+        &self,
+        pack_id: u64,
+    ) -> ServerResult<Vec<(String, String)>> {
+        let prefix = make_emote_alias_prefix(pack_id);
+        self.inner
+            .scan_prefix(&prefix)
+            .await
+            .try_fold(Vec::new(), |mut all, res| {
+                let (key, value) = res.map_err(ServerError::from)?;
+                // Safety: aliases and emote names are always valid UTF-8, since they come from strings
+                let (alias, name) = unsafe {
+                    (
+                        String::from_utf8_unchecked(key.split_at(prefix.len()).1.to_vec()),
+                        String::from_utf8_unchecked(value.to_vec()),
+                    )
+                };
+                all.push((alias, name));
+                ServerResult::Ok(all)
+            })
+    }
+
+    /// Points an alias to an emote in the same pack, or removes the alias if `name` is `None`.
+    pub async fn set_emote_alias_logic(
+        &self,
+        pack_id: u64,
+        alias: &str,
+        name: Option<&str>,
+    ) -> ServerResult<()> {
+        let key = make_emote_alias_key(pack_id, alias);
+        match name {
+            Some(name) => {
+                if self.get_emote_logic(pack_id, alias).await?.is_some() {
+                    bail!((
+                        "h.emote-alias-taken",
+                        "an emote in this pack already has this name"
+                    ));
+                }
+                if self.get_emote_logic(pack_id, name).await?.is_none() {
+                    bail!((
+                        "h.no-such-emote",
+                        "this pack doesn't have an emote with this name"
+                    ));
+                }
+                self.insert(key, name.as_bytes()).await?;
+            }
+            None => {
+                self.remove(key).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the aliases of a pack that point to the given emote.
+    pub async fn remove_aliases_to_logic(&self, pack_id: u64, name: &str) -> ServerResult<()> {
+        let mut batch = Batch::default();
+        for (alias, target) in self.get_emote_aliases_logic(pack_id).await? {
+            if target == name {
+                batch.remove(make_emote_alias_key(pack_id, &alias));
+            }
+        }
+        self.apply_batch(batch).await?;
+        Ok(())
+    }
+
+    pub async fn get_equipped_packs_logic(&self, user_id: u64) -> ServerResult<Vec<u64>> {
+        let prefix = make_equipped_emote_prefix(user_id);
+        self.inner
+            .scan_prefix(&prefix)
+            .await
+            .try_fold(Vec::new(), |mut all, res| {
+                let (key, _) = res.map_err(ServerError::from)?;
+                if key.len() == make_equipped_emote_key(user_id, 0).len() {
+                    let pack_id =
+                        // Safety: since it will always be 8 bytes left afterwards
+                        u64::from_be_bytes(unsafe {
+                            key.split_at(prefix.len()).1.try_into().unwrap_unchecked()
+                        });
+                    all.push(pack_id);
+                }
+                ServerResult::Ok(all)
+            })
+    }
+
+    /// Gets the packs a user has equipped, in the order emote names are looked up in.
+    ///
+    /// Packs the user put in their priority list come first, then the rest ordered by ID.
+    pub async fn get_pack_lookup_order_logic(&self, user_id: u64) -> ServerResult<Vec<u64>> {
+        let mut equipped = self.get_equipped_packs_logic(user_id).await?;
+        equipped.sort_unstable();
+
+        let mut order = self
+            .get(make_emote_pack_priority_key(user_id))
+            .await?
+            .map_or_else(Vec::new, |raw| {
+                db::make_u64_iter_logic(&raw).collect::<Vec<_>>()
+            });
+        order.retain(|pack_id| equipped.contains(pack_id));
+        equipped.retain(|pack_id| !order.contains(pack_id));
+        order.extend(equipped);
+
+        Ok(order)
+    }
+
+    pub async fn set_pack_priority_logic(
+        &self,
+        user_id: u64,
+        pack_ids: &[u64],
+    ) -> ServerResult<()> {
+        let raw = pack_ids
+            .iter()
+            .flat_map(|id| id.to_be_bytes())
+            .collect::<Vec<u8>>();
+        self.insert(make_emote_pack_priority_key(user_id), raw)
+            .await?;
+        Ok(())
+    }
+
+    /// Maps an emote name to an emote in the packs a user has equipped.
+    ///
+    /// Packs are searched in the given order, and in a pack an emote with the
+    /// name wins over an alias. Returns the pack ID and the emote.
+    pub async fn resolve_emote_logic(
+        &self,
+        packs: &[u64],
+        name: &str,
+    ) -> ServerResult<Option<(u64, Emote)>> {
+        for pack_id in packs.iter().copied() {
+            if let Some(emote) = self.get_emote_logic(pack_id, name).await? {
+                return Ok(Some((pack_id, emote)));
+            }
+            if let Some(raw) = self.get(make_emote_alias_key(pack_id, name)).await? {
+                // Safety: emote names are always valid UTF-8, since they come from strings
+                let target = unsafe { std::str::from_utf8_unchecked(&raw) };
+                if let Some(emote) = self.get_emote_logic(pack_id, target).await? {
+                    return Ok(Some((pack_id, emote)));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Gets all emotes of a pack.
+    pub async fn get_pack_emotes_logic(&self, pack_id: u64) -> ServerResult<Vec<Emote>> {
+        let pack_key = make_emote_pack_key(pack_id);
+        self.inner
+            .scan_prefix(&pack_key)
+            .await
+            .try_fold(Vec::new(), |mut all, res| {
+                let (key, value) = res.map_err(ServerError::from)?;
+                if key.len() > pack_key.len() {
+                    all.push(db::deser_emote(value));
+                }
+                ServerResult::Ok(all)
+            })
+    }
+
+    /// Gets the version of a pack. Packs whose emotes never changed are at version 0.
+    pub async fn get_pack_version_logic(&self, pack_id: u64) -> ServerResult<u64> {
+        let version = self
+            .get(make_emote_pack_version_key(pack_id))
+            .await?
+            .map_or(0, |raw| {
+                raw.as_ref().try_into().map_or(0, u64::from_be_bytes)
+            });
+        Ok(version)
+    }
+
+    /// Marks a pack as changed, returning its new version.
+    pub async fn bump_pack_version_logic(&self, pack_id: u64) -> ServerResult<u64> {
+        let version = self.get_pack_version_logic(pack_id).await? + 1;
+        self.insert(make_emote_pack_version_key(pack_id), version.to_be_bytes())
+            .await?;
+        Ok(version)
+    }
+
     pub async fn calculate_users_pack_equipped(&self, pack_id: u64) -> ServerResult<Vec<u64>> {
         let mut result = Vec::new();
         for user_id in
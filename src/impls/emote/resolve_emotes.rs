@@ -0,0 +1,68 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+/// Maximum amount of names that can be resolved at once.
+const MAX_NAMES: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct ResolveEmotesRequest {
+    pub names: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResolvedEmote {
+    pub name: String,
+    pub pack_id: u64,
+    pub image_id: String,
+    /// The name the emote has in its pack, differs from `name` if it was found through an alias.
+    pub emote_name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResolveEmotesResponse {
+    /// Emotes for the names that could be resolved, in the order of the requested names.
+    pub emotes: Vec<ResolvedEmote>,
+    /// Packs the user has equipped, in the order names were looked up in.
+    pub lookup_order: Vec<u64>,
+}
+
+/// Maps emote names to emotes in the packs the user has equipped.
+///
+/// When more than one equipped pack has an emote or alias with a name, the
+/// pack that comes first in the user's lookup order wins.
+pub async fn handler(
+    svc: &EmoteServer,
+    user_id: u64,
+    request: ResolveEmotesRequest,
+) -> ServerResult<ResolveEmotesResponse> {
+    let ResolveEmotesRequest { names } = request;
+
+    if names.len() > MAX_NAMES {
+        bail!((
+            "h.too-many-emote-names",
+            format!("can't resolve more than {} names at once", MAX_NAMES)
+        ));
+    }
+
+    let emote_tree = &svc.deps.emote_tree;
+
+    let lookup_order = emote_tree.get_pack_lookup_order_logic(user_id).await?;
+    let mut emotes = Vec::with_capacity(names.len());
+    for name in names {
+        if let Some((pack_id, emote)) = emote_tree.resolve_emote_logic(&lookup_order, &name).await?
+        {
+            emotes.push(ResolvedEmote {
+                name,
+                pack_id,
+                image_id: emote.image_id,
+                emote_name: emote.name,
+            });
+        }
+    }
+
+    Ok(ResolveEmotesResponse {
+        emotes,
+        lookup_order,
+    })
+}
@@ -0,0 +1,57 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct SetEmoteAliasRequest {
+    pub pack_id: u64,
+    pub alias: String,
+    /// Name of the emote in the pack the alias points to. An empty name removes the alias.
+    pub emote_name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetEmoteAliasResponse {}
+
+pub async fn handler(
+    svc: &EmoteServer,
+    user_id: u64,
+    request: SetEmoteAliasRequest,
+) -> ServerResult<SetEmoteAliasResponse> {
+    let SetEmoteAliasRequest {
+        pack_id,
+        alias,
+        emote_name,
+    } = request;
+
+    if alias.is_empty() {
+        bail!(("h.emote-alias-empty", "emote alias must not be empty"));
+    }
+
+    let emote_tree = &svc.deps.emote_tree;
+
+    emote_tree
+        .check_if_emote_pack_owner(pack_id, user_id)
+        .await?;
+    emote_tree
+        .set_emote_alias_logic(
+            pack_id,
+            &alias,
+            (!emote_name.is_empty()).then(|| emote_name.as_str()),
+        )
+        .await?;
+
+    let version = emote_tree.bump_pack_version_logic(pack_id).await?;
+    let equipped_users = emote_tree.calculate_users_pack_equipped(pack_id).await?;
+    svc.deps
+        .event_bus
+        .publish(DomainEvent::EmotePackEmotesUpdated {
+            pack_id,
+            version,
+            added_emotes: Vec::new(),
+            deleted_emotes: Vec::new(),
+            user_ids: equipped_users,
+        });
+
+    Ok(SetEmoteAliasResponse {})
+}
@@ -0,0 +1,44 @@
+use std::collections::HashSet;
+
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct SetEmotePackPriorityRequest {
+    /// Equipped packs, highest priority first. Packs left out come after these.
+    pub pack_ids: Vec<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetEmotePackPriorityResponse {
+    /// The order emote names will be looked up in.
+    pub lookup_order: Vec<u64>,
+}
+
+pub async fn handler(
+    svc: &EmoteServer,
+    user_id: u64,
+    request: SetEmotePackPriorityRequest,
+) -> ServerResult<SetEmotePackPriorityResponse> {
+    let SetEmotePackPriorityRequest { mut pack_ids } = request;
+
+    let emote_tree = &svc.deps.emote_tree;
+
+    let equipped = emote_tree.get_equipped_packs_logic(user_id).await?;
+    if let Some(pack_id) = pack_ids.iter().find(|id| !equipped.contains(id)) {
+        bail!((
+            "h.emote-pack-not-equipped",
+            format!("emote pack {} is not equipped", pack_id)
+        ));
+    }
+    let mut seen = HashSet::with_capacity(pack_ids.len());
+    pack_ids.retain(|id| seen.insert(*id));
+
+    emote_tree
+        .set_pack_priority_logic(user_id, &pack_ids)
+        .await?;
+    let lookup_order = emote_tree.get_pack_lookup_order_logic(user_id).await?;
+
+    Ok(SetEmotePackPriorityResponse { lookup_order })
+}
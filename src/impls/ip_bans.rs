@@ -0,0 +1,524 @@
+//! Bans of IP addresses and networks, for abusers that keep making new accounts.
+//!
+//! Bans are managed with the `ip_bans` console command and kept in the
+//! database. Requests from a banned IP are rejected before anything else
+//! looks at them, including authentication. Bans can expire, and count how
+//! many requests they blocked.
+
+use std::{
+    convert::Infallible,
+    fmt::{self, Display, Formatter},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll},
+};
+
+use harmony_rust_sdk::api::exports::hrpc::server::transport::http::{HttpRequest, HttpResponse};
+use hrpc::exports::futures_util::future::{self, Either, Ready};
+use parking_lot::RwLock;
+use serde::Serialize;
+use tower::{Layer, Service};
+
+use crate::{
+    config::RateLimitConfig,
+    db::auth::{make_ip_ban_key, IpBan, IP_BAN_PREFIX},
+};
+
+use super::{get_time_secs, prelude::*};
+
+/// An IP address, or a network of them in CIDR notation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNetwork {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, unmap_ipv4(ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => mask_v4(ip, self.prefix_len) == u32::from(net),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => mask_v6(ip, self.prefix_len) == u128::from(net),
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = ();
+
+    /// Parses an IP address, or a network like `192.0.2.0/24`. Host bits of
+    /// networks are cleared.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, len)) => (addr, Some(len.parse::<u8>().map_err(|_| ())?)),
+            None => (s, None),
+        };
+        let addr = IpAddr::from_str(addr).map_err(|_| ())?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = prefix_len.unwrap_or(max_len);
+        if prefix_len > max_len {
+            return Err(());
+        }
+        let addr = match addr {
+            IpAddr::V4(ip) => IpAddr::V4(Ipv4Addr::from(mask_v4(ip, prefix_len))),
+            IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from(mask_v6(ip, prefix_len))),
+        };
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+impl Display for IpNetwork {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+fn mask_v4(ip: Ipv4Addr, prefix_len: u8) -> u32 {
+    u32::from(ip)
+        & u32::MAX
+            .checked_shl(32 - u32::from(prefix_len))
+            .unwrap_or(0)
+}
+
+fn mask_v6(ip: Ipv6Addr, prefix_len: u8) -> u128 {
+    u128::from(ip)
+        & u128::MAX
+            .checked_shl(128 - u32::from(prefix_len))
+            .unwrap_or(0)
+}
+
+/// Treats IPv4 addresses mapped to IPv6, like `::ffff:192.0.2.1`, as IPv4, so
+/// IPv4 bans apply to them.
+fn unmap_ipv4(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.segments() {
+            [0, 0, 0, 0, 0, 0xffff, hi, lo] => {
+                IpAddr::V4(Ipv4Addr::from((u32::from(hi) << 16) | u32::from(lo)))
+            }
+            _ => ip,
+        },
+        ip => ip,
+    }
+}
+
+struct BanEntry {
+    network: IpNetwork,
+    ban: IpBan,
+    /// Requests blocked by this ban since the server started.
+    blocked: AtomicU64,
+}
+
+impl BanEntry {
+    fn is_active(&self, now: u64) -> bool {
+        self.ban.expires_at == 0 || self.ban.expires_at > now
+    }
+}
+
+/// The banned networks, kept in memory so requests can be checked quickly.
+#[derive(Clone)]
+pub struct IpBans {
+    inner: Arc<IpBansState>,
+}
+
+struct IpBansState {
+    bans: RwLock<Vec<BanEntry>>,
+    /// Requests blocked by any ban since the server started.
+    blocked_requests: AtomicU64,
+    client_ip_header_name: Option<String>,
+}
+
+impl IpBans {
+    pub async fn load(config: &RateLimitConfig, auth_tree: &Tree) -> DbResult<Self> {
+        let mut bans = Vec::new();
+        for res in auth_tree.scan_prefix(IP_BAN_PREFIX).await {
+            let (key, value) = res?;
+            let raw = String::from_utf8_lossy(&key[IP_BAN_PREFIX.len()..]);
+            match raw.parse() {
+                Ok(network) => bans.push(BanEntry {
+                    network,
+                    ban: db::deser_ip_ban(value),
+                    blocked: AtomicU64::new(0),
+                }),
+                Err(_) => tracing::warn!("skipping invalid IP ban for {}", raw),
+            }
+        }
+        Ok(Self {
+            inner: Arc::new(IpBansState {
+                bans: RwLock::new(bans),
+                blocked_requests: AtomicU64::new(0),
+                client_ip_header_name: config.client_ip_header_name.clone(),
+            }),
+        })
+    }
+
+    /// Fails if the IP is in a banned network, counting it as blocked.
+    pub fn check(&self, ip: IpAddr) -> Result<(), ServerError> {
+        let now = get_time_secs();
+        let bans = self.inner.bans.read();
+        let Some(entry) = bans
+            .iter()
+            .find(|entry| entry.is_active(now) && entry.network.contains(ip))
+        else {
+            return Ok(());
+        };
+        entry.blocked.fetch_add(1, Ordering::Relaxed);
+        self.inner.blocked_requests.fetch_add(1, Ordering::Relaxed);
+        Err(ServerError::IpBanned)
+    }
+
+    pub fn blocked_requests(&self) -> u64 {
+        self.inner.blocked_requests.load(Ordering::Relaxed)
+    }
+
+    /// Gets the IP a request was sent from, the same way rate limits do.
+    fn client_ip(&self, request: &HttpRequest) -> Option<IpAddr> {
+        self.inner
+            .client_ip_header_name
+            .as_deref()
+            .and_then(|name| request.headers().get(name))
+            .and_then(|val| val.to_str().ok())
+            .and_then(|ips| ips.split(',').map(str::trim).next())
+            .and_then(|ip| IpAddr::from_str(ip).ok())
+            .or_else(|| request.extensions().get::<SocketAddr>().map(SocketAddr::ip))
+    }
+
+    fn insert(&self, network: IpNetwork, ban: IpBan) {
+        let mut bans = self.inner.bans.write();
+        bans.retain(|entry| entry.network != network);
+        bans.push(BanEntry {
+            network,
+            ban,
+            blocked: AtomicU64::new(0),
+        });
+    }
+}
+
+/// A change to the IP bans, from the `ip_bans` console command.
+#[derive(Debug, Clone)]
+pub enum IpBanAction {
+    Show,
+    Add {
+        network: IpNetwork,
+        expires_in: Option<u64>,
+        reason: String,
+    },
+    Remove(IpNetwork),
+    Import {
+        path: String,
+        expires_in: Option<u64>,
+    },
+}
+
+impl FromStr for IpBanAction {
+    type Err = ();
+
+    /// Parses `list`, `add <ip | cidr> [expires_in=<secs>] [reason]`,
+    /// `remove <ip | cidr>` or `import <path> [expires_in=<secs>]`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut args = s.split_whitespace();
+        let action = args.next().ok_or(())?;
+        let target = args.next();
+        let mut rest = args.peekable();
+        let expires_in = match rest.peek().and_then(|arg| arg.strip_prefix("expires_in=")) {
+            Some(secs) => {
+                let secs = secs.parse().map_err(|_| ())?;
+                rest.next();
+                Some(secs)
+            }
+            None => None,
+        };
+        let rest = rest.collect::<Vec<_>>().join(" ");
+
+        let action = match (action, target) {
+            ("list", None) => IpBanAction::Show,
+            ("add", Some(network)) => IpBanAction::Add {
+                network: network.parse()?,
+                expires_in,
+                reason: rest,
+            },
+            ("remove", Some(network)) if expires_in.is_none() && rest.is_empty() => {
+                IpBanAction::Remove(network.parse()?)
+            }
+            ("import", Some(path)) if rest.is_empty() => IpBanAction::Import {
+                path: path.to_string(),
+                expires_in,
+            },
+            _ => return Err(()),
+        };
+        Ok(action)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct IpBanReport {
+    pub network: String,
+    pub reason: String,
+    pub banned_at: u64,
+    /// Not set if the ban doesn't expire.
+    pub expires_at: Option<u64>,
+    /// Requests blocked by this ban since the server started.
+    pub blocked_requests: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IpBansReport {
+    pub blocked_requests: u64,
+    pub bans: Vec<IpBanReport>,
+}
+
+/// Removes expired bans from memory and the database.
+async fn prune_expired(deps: &Dependencies) -> ServerResult<()> {
+    let now = get_time_secs();
+    let expired = {
+        let mut bans = deps.ip_bans.inner.bans.write();
+        let expired = bans
+            .iter()
+            .filter(|entry| !entry.is_active(now))
+            .map(|entry| entry.network)
+            .collect::<Vec<_>>();
+        bans.retain(|entry| entry.is_active(now));
+        expired
+    };
+    if expired.is_empty() {
+        return Ok(());
+    }
+    let mut batch = Batch::default();
+    for network in expired {
+        batch.remove(make_ip_ban_key(&network.to_string()));
+    }
+    deps.auth_tree.apply_batch(batch).await?;
+    Ok(())
+}
+
+/// Bans networks, replacing bans that were already there for them.
+async fn add_bans(deps: &Dependencies, bans: Vec<(IpNetwork, IpBan)>) -> ServerResult<()> {
+    let mut batch = Batch::default();
+    for (network, ban) in &bans {
+        batch.insert(make_ip_ban_key(&network.to_string()), rkyv_ser(ban));
+    }
+    deps.auth_tree.apply_batch(batch).await?;
+    for (network, ban) in bans {
+        deps.ip_bans.insert(network, ban);
+    }
+    Ok(())
+}
+
+/// Reads a file with an IP or network per line, optionally followed by a
+/// reason. Empty lines and lines starting with `#` are skipped.
+fn parse_import(contents: &str) -> Result<Vec<(IpNetwork, Option<&str>)>, String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(index, line)| {
+            let (network, reason) = match line.split_once(char::is_whitespace) {
+                Some((network, reason)) => (network, Some(reason.trim())),
+                None => (line, None),
+            };
+            network
+                .parse()
+                .map(|network| (network, reason))
+                .map_err(|_| format!("invalid IP or network on line {}", index + 1))
+        })
+        .collect()
+}
+
+/// Runs an `ip_bans` console command, returning what to show to the admin.
+pub async fn run_action(deps: &Dependencies, action: IpBanAction) -> ServerResult<String> {
+    prune_expired(deps).await?;
+    let now = get_time_secs();
+    let expires_at = |expires_in: Option<u64>| expires_in.map_or(0, |secs| now + secs);
+
+    match action {
+        IpBanAction::Show => {
+            let bans = deps
+                .ip_bans
+                .inner
+                .bans
+                .read()
+                .iter()
+                .map(|entry| IpBanReport {
+                    network: entry.network.to_string(),
+                    reason: entry.ban.reason.clone(),
+                    banned_at: entry.ban.banned_at,
+                    expires_at: (entry.ban.expires_at != 0).then(|| entry.ban.expires_at),
+                    blocked_requests: entry.blocked.load(Ordering::Relaxed),
+                })
+                .collect();
+            let report = IpBansReport {
+                blocked_requests: deps.ip_bans.blocked_requests(),
+                bans,
+            };
+            Ok(serde_json::to_string_pretty(&report).unwrap())
+        }
+        IpBanAction::Add {
+            network,
+            expires_in,
+            reason,
+        } => {
+            let ban = IpBan {
+                reason,
+                banned_at: now,
+                expires_at: expires_at(expires_in),
+            };
+            add_bans(deps, vec![(network, ban)]).await?;
+            tracing::info!("banned {}", network);
+            Ok(format!("banned `{}`", network))
+        }
+        IpBanAction::Remove(network) => {
+            let removed = {
+                let mut bans = deps.ip_bans.inner.bans.write();
+                let len = bans.len();
+                bans.retain(|entry| entry.network != network);
+                bans.len() != len
+            };
+            if !removed {
+                return Ok(format!("`{}` isn't banned", network));
+            }
+            deps.auth_tree
+                .remove(make_ip_ban_key(&network.to_string()))
+                .await?;
+            tracing::info!("unbanned {}", network);
+            Ok(format!("unbanned `{}`", network))
+        }
+        IpBanAction::Import { path, expires_in } => {
+            let contents = tokio::fs::read_to_string(&path)
+                .await
+                .map_err(ServerError::from)?;
+            let networks = match parse_import(&contents) {
+                Ok(networks) => networks,
+                Err(err) => return Ok(format!("couldn't import `{}`: {}", path, err)),
+            };
+            let bans = networks
+                .into_iter()
+                .map(|(network, reason)| {
+                    let ban = IpBan {
+                        reason: reason
+                            .map_or_else(|| format!("imported from {}", path), ToString::to_string),
+                        banned_at: now,
+                        expires_at: expires_at(expires_in),
+                    };
+                    (network, ban)
+                })
+                .collect::<Vec<_>>();
+            let count = bans.len();
+            add_bans(deps, bans).await?;
+            tracing::info!("imported {} IP bans from {}", count, path);
+            Ok(format!("imported {} bans from `{}`", count, path))
+        }
+    }
+}
+
+/// Rejects requests from banned IPs.
+#[derive(Clone)]
+pub struct IpBanLayer {
+    bans: IpBans,
+}
+
+impl IpBanLayer {
+    pub fn new(bans: IpBans) -> Self {
+        Self { bans }
+    }
+}
+
+impl<S> Layer<S> for IpBanLayer
+where
+    S: Service<HttpRequest, Response = HttpResponse, Error = Infallible> + Send + 'static,
+{
+    type Service = IpBanService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        IpBanService {
+            bans: self.bans.clone(),
+            inner,
+        }
+    }
+}
+
+pub struct IpBanService<S> {
+    bans: IpBans,
+    inner: S,
+}
+
+impl<S> Service<HttpRequest> for IpBanService<S>
+where
+    S: Service<HttpRequest, Response = HttpResponse, Error = Infallible> + Send + 'static,
+{
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = Either<Ready<Result<HttpResponse, Infallible>>, S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Service::poll_ready(&mut self.inner, cx)
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let res = self
+            .bans
+            .client_ip(&request)
+            .map_or(Ok(()), |ip| self.bans.check(ip));
+
+        match res {
+            Ok(()) => Either::Right(Service::call(&mut self.inner, request)),
+            Err(err) => {
+                let path = request.uri().path();
+                let response =
+                    if path.starts_with("/_harmony/media/") || path.starts_with("/_scherzo/") {
+                        err.into_rest_http_response()
+                    } else {
+                        err.into_http_response()
+                    };
+                Either::Left(future::ready(Ok(response)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn networks_contain_ips() {
+        let network: IpNetwork = "192.0.2.77/24".parse().unwrap();
+        assert_eq!(network.to_string(), "192.0.2.0/24");
+        assert!(network.contains("192.0.2.1".parse().unwrap()));
+        assert!(network.contains("::ffff:192.0.2.1".parse().unwrap()));
+        assert!(!network.contains("192.0.3.1".parse().unwrap()));
+
+        let single: IpNetwork = "2001:db8::1".parse().unwrap();
+        assert!(single.contains("2001:db8::1".parse().unwrap()));
+        assert!(!single.contains("2001:db8::2".parse().unwrap()));
+
+        let everything: IpNetwork = "0.0.0.0/0".parse().unwrap();
+        assert!(everything.contains("203.0.113.5".parse().unwrap()));
+
+        assert!("192.0.2.0/33".parse::<IpNetwork>().is_err());
+        assert!("example.org".parse::<IpNetwork>().is_err());
+    }
+
+    #[test]
+    fn parses_actions() {
+        assert!(matches!(
+            "add 192.0.2.0/24 expires_in=60 spam bots".parse::<IpBanAction>(),
+            Ok(IpBanAction::Add { expires_in: Some(60), reason, .. }) if reason == "spam bots"
+        ));
+        assert!(matches!(
+            "remove 192.0.2.1".parse::<IpBanAction>(),
+            Ok(IpBanAction::Remove(_))
+        ));
+        assert!("remove 192.0.2.1 extra".parse::<IpBanAction>().is_err());
+        assert!("add nonsense".parse::<IpBanAction>().is_err());
+        assert!(
+            parse_import("# comment\n\n192.0.2.1 spam\n10.0.0.0/8\n")
+                .unwrap()
+                .len()
+                == 2
+        );
+        assert!(parse_import("192.0.2.1\nnot an ip\n").is_err());
+    }
+}
@@ -0,0 +1,239 @@
+//! Database maintenance: reporting how much space the database uses and compacting it.
+
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{chat::ChatServer, get_time_secs, prelude::*};
+
+const SECS_IN_DAY: u64 = 24 * 60 * 60;
+const OVERHEAD_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Whether a compaction is currently running, so that compactions don't pile up.
+static COMPACTING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Serialize)]
+pub struct TreeUsageReport {
+    pub name: String,
+    pub entries: u64,
+    /// Total size of keys and values in the tree, in bytes.
+    pub bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DbUsageReport {
+    /// Size of the database on disk, in bytes. This includes space that
+    /// isn't used by any tree but hasn't been reclaimed yet.
+    pub size_on_disk: u64,
+    /// Approximate space on disk that isn't used by any tree, in bytes. This
+    /// is what compacting can reclaim at most.
+    pub overhead: u64,
+    pub trees: Vec<TreeUsageReport>,
+}
+
+impl DbUsageReport {
+    pub async fn collect(db: &Db) -> Result<Self, ServerError> {
+        let mut trees = Vec::with_capacity(db::TREES.len());
+        for name in db::TREES {
+            let usage = db.open_tree(name).await?.usage().await?;
+            trees.push(TreeUsageReport {
+                name: String::from_utf8_lossy(name).into_owned(),
+                entries: usage.entries,
+                bytes: usage.bytes,
+            });
+        }
+
+        let size_on_disk = db.size_on_disk().await?;
+        let used: u64 = trees.iter().map(|tree| tree.bytes).sum();
+        Ok(Self {
+            size_on_disk,
+            overhead: size_on_disk.saturating_sub(used),
+            trees,
+        })
+    }
+
+    /// Whether the database takes more than `percent` percent more space on
+    /// disk than its data.
+    pub fn overhead_exceeds(&self, percent: u32) -> bool {
+        let used = self.size_on_disk - self.overhead;
+        self.overhead > used.saturating_mul(u64::from(percent)) / 100
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CompactionReport {
+    /// Size of the database on disk before compacting, in bytes.
+    pub size_before: u64,
+    /// Size of the database on disk after compacting, in bytes.
+    pub size_after: u64,
+}
+
+/// Marks a compaction as running until it is dropped, so that the mark is
+/// cleared even if the compaction fails or its task is cancelled.
+struct CompactingGuard;
+
+impl CompactingGuard {
+    fn acquire() -> Option<Self> {
+        if COMPACTING.swap(true, Ordering::AcqRel) {
+            None
+        } else {
+            Some(CompactingGuard)
+        }
+    }
+}
+
+impl Drop for CompactingGuard {
+    fn drop(&mut self) {
+        COMPACTING.store(false, Ordering::Release);
+    }
+}
+
+/// Compacts the database, returning its size before and after.
+pub async fn compact(db: &Db) -> Result<CompactionReport, ServerError> {
+    let _guard = CompactingGuard::acquire().ok_or(ServerError::TooFast(Duration::from_secs(60)))?;
+
+    let size_before = db.size_on_disk().await?;
+    db.compact().await?;
+    let size_after = db.size_on_disk().await?;
+    Ok(CompactionReport {
+        size_before,
+        size_after,
+    })
+}
+
+fn log_compaction(res: Result<CompactionReport, ServerError>) {
+    match res {
+        Ok(report) => tracing::info!(
+            "compacted database from {} bytes to {} bytes",
+            report.size_before,
+            report.size_after
+        ),
+        Err(err) => tracing::error!("couldn't compact database: {}", err),
+    }
+}
+
+/// Spawns a task that compacts the database every day at the configured hour.
+pub fn spawn_compaction_task(deps: Arc<Dependencies>) {
+    let hour = match deps.config.db.compaction_hour {
+        Some(hour) if hour < 24 => u64::from(hour),
+        Some(hour) => {
+            tracing::error!(
+                "invalid database compaction hour {}, must be between 0 and 23",
+                hour
+            );
+            return;
+        }
+        None => return,
+    };
+
+    tokio::spawn(async move {
+        tracing::info!("starting database compaction task");
+        loop {
+            let secs_into_day = get_time_secs() % SECS_IN_DAY;
+            let wait = (hour * 60 * 60 + SECS_IN_DAY - secs_into_day) % SECS_IN_DAY;
+            // if we are right at the compaction hour, we just compacted
+            let wait = if wait == 0 { SECS_IN_DAY } else { wait };
+            tokio::time::sleep(Duration::from_secs(wait)).await;
+
+            log_compaction(compact(&deps.db).await);
+        }
+    });
+}
+
+/// Spawns a task that compacts the database whenever it takes more space on
+/// disk than its data by the configured percentage.
+pub fn spawn_overhead_compaction_task(deps: Arc<Dependencies>) {
+    let percent = match deps.config.db.compaction_overhead_percent {
+        Some(percent) => percent,
+        None => return,
+    };
+    if !deps.db.compact_reclaims_space() {
+        tracing::warn!(
+            "compacting this database doesn't reclaim space, not starting database overhead compaction task"
+        );
+        return;
+    }
+
+    tokio::spawn(async move {
+        tracing::info!("starting database overhead compaction task");
+        loop {
+            tokio::time::sleep(OVERHEAD_CHECK_INTERVAL).await;
+
+            let report = match DbUsageReport::collect(&deps.db).await {
+                Ok(report) => report,
+                Err(err) => {
+                    tracing::error!("couldn't get database usage: {}", err);
+                    continue;
+                }
+            };
+            if report.overhead_exceeds(percent) {
+                tracing::info!(
+                    "database has {} bytes of overhead, compacting",
+                    report.overhead
+                );
+                log_compaction(compact(&deps.db).await);
+            }
+        }
+    });
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetDbUsageRequest {}
+
+/// Gets how much space the database uses. Only homeserver admins can use this.
+pub async fn get_db_usage_handler(
+    svc: &ChatServer,
+    user_id: u64,
+    _request: GetDbUsageRequest,
+) -> ServerResult<DbUsageReport> {
+    svc.deps.chat_tree.check_homeserver_admin(user_id).await?;
+
+    DbUsageReport::collect(&svc.deps.db)
+        .await
+        .map_err(Into::into)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CompactDbRequest {}
+
+/// Compacts the database. Only homeserver admins can use this.
+pub async fn compact_db_handler(
+    svc: &ChatServer,
+    user_id: u64,
+    _request: CompactDbRequest,
+) -> ServerResult<CompactionReport> {
+    svc.deps.chat_tree.check_homeserver_admin(user_id).await?;
+
+    compact(&svc.deps.db).await.map_err(Into::into)
+}
+
+#[cfg(all(test, feature = "sled"))]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn compacting_clears_its_mark() {
+        let db = crate::db::open_temp();
+
+        compact(&db).await.unwrap();
+        compact(&db).await.unwrap();
+        assert!(!COMPACTING.load(Ordering::Acquire));
+    }
+
+    #[test]
+    fn overhead_is_compared_to_used_space() {
+        let report = |size_on_disk, overhead| DbUsageReport {
+            size_on_disk,
+            overhead,
+            trees: Vec::new(),
+        };
+
+        assert!(!report(200, 100).overhead_exceeds(100));
+        assert!(report(201, 101).overhead_exceeds(100));
+        assert!(report(150, 60).overhead_exceeds(50));
+        assert!(!report(0, 0).overhead_exceeds(0));
+    }
+}
@@ -8,6 +8,9 @@ pub async fn handler(
 
     let CanInstantViewRequest { url } = request.into_message().await?;
 
+    let parsed_url: Uri = url.parse().map_err(ServerError::InvalidUrl)?;
+    svc.deps.domain_blocklist.check_url(&parsed_url)?;
+
     if let Some(val) = get_from_cache(&url) {
         return Ok((CanInstantViewResponse {
             can_instant_view: matches!(val.value, Metadata::Site(_)),
@@ -15,8 +18,7 @@ pub async fn handler(
         .into_response());
     }
 
-    let url: Uri = url.parse().map_err(ServerError::InvalidUrl)?;
-    let response = svc.http.get(url).await.map_err(ServerError::from)?;
+    let response = svc.http.get(parsed_url).await.map_err(ServerError::from)?;
 
     let ok = get_mimetype(&response).eq("text/html");
 
@@ -59,6 +59,11 @@ lazy_static::lazy_static! {
     static ref CACHE: DashMap<String, TimedCacheValue<Metadata>, RandomState> = DashMap::with_capacity_and_hasher(512, RandomState::new());
 }
 
+/// Returns the number of entries in the metadata cache, including expired ones.
+pub fn cache_len() -> usize {
+    CACHE.len()
+}
+
 fn get_from_cache(url: &str) -> Option<Ref<'_, String, TimedCacheValue<Metadata>, RandomState>> {
     match CACHE.get(url) {
         // Value is available, check if it is expired
@@ -99,12 +104,14 @@ impl MediaproxyServer {
     }
 
     async fn fetch_metadata(&self, raw_url: String) -> Result<Metadata, ServerError> {
+        let url: Uri = raw_url.parse().map_err(ServerError::InvalidUrl)?;
+        self.deps.domain_blocklist.check_url(&url)?;
+
         // Get from cache if available
         if let Some(value) = get_from_cache(&raw_url) {
             return Ok(value.value.clone());
         }
 
-        let url: Uri = raw_url.parse().map_err(ServerError::InvalidUrl)?;
         let response = self.http.get(url.clone()).await?;
         if !response.status().is_success() {
             let err = if response.status() == StatusCode::NOT_FOUND {
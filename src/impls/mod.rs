@@ -1,11 +1,24 @@
 pub mod against;
+pub mod alerts;
+pub mod audit;
 pub mod auth;
 pub mod batch;
+pub mod blocklist;
+pub mod bus;
 pub mod chat;
+pub mod diagnostics;
+pub mod disk_guard;
+pub mod email;
 pub mod emote;
+pub mod ip_bans;
+pub mod maintenance;
 pub mod mediaproxy;
 pub mod profile;
+pub mod replication;
 pub mod rest;
+pub mod retention;
+pub mod search;
+pub mod startup;
 pub mod sync;
 #[cfg(feature = "voice")]
 pub mod voice;
@@ -14,7 +27,7 @@ use hrpc::client::transport::http::hyper::{http_client, HttpClient};
 use hyper::{http, Uri};
 use prelude::*;
 
-use std::{str::FromStr, time::UNIX_EPOCH};
+use std::{path::Path, str::FromStr, time::UNIX_EPOCH};
 
 use dashmap::DashMap;
 use harmony_rust_sdk::api::{exports::prost::bytes::Bytes, HomeserverIdentifier};
@@ -22,7 +35,15 @@ use parking_lot::Mutex;
 use rand::Rng;
 use tokio::sync::{broadcast, mpsc};
 
-use crate::{config::Config, key, SharedConfig, SharedConfigData};
+use crate::{
+    config::Config,
+    key,
+    utils::{
+        cursor::{Direction, Pager},
+        ratelimit::{self, RateLimiter},
+    },
+    SharedConfig, SharedConfigData,
+};
 
 use self::{
     auth::AuthTree, chat::ChatTree, emote::EmoteTree, profile::ProfileTree, rest::RestServiceLayer,
@@ -66,45 +87,109 @@ pub type FedEventReceiver = mpsc::UnboundedReceiver<EventDispatch>;
 pub type FedEventDispatcher = mpsc::UnboundedSender<EventDispatch>;
 
 pub struct Dependencies {
+    pub db: Db,
     pub auth_tree: AuthTree,
     pub chat_tree: ChatTree,
     pub profile_tree: ProfileTree,
     pub emote_tree: EmoteTree,
     pub sync_tree: Tree,
+    pub media_tree: Tree,
+    pub audit_tree: Tree,
+    pub ratelimit_tree: Tree,
 
     pub valid_sessions: SessionMap,
     pub chat_event_sender: chat::EventSender,
+    pub event_bus: bus::EventBus,
+    pub message_nonces: chat::messages::MessageNonces,
+    pub typing_indicators: chat::channels::TypingIndicators,
+    pub member_lists: chat::guilds::MemberListSubscriptions,
+    pub presence: profile::presence::Presence,
     pub fed_event_dispatcher: FedEventDispatcher,
     pub key_manager: Option<Arc<key::Manager>>,
+    pub trusted_hosts: sync::trust::TrustedHosts,
+    pub host_lists: sync::host_lists::HostLists,
+    pub ip_bans: ip_bans::IpBans,
     pub action_processor: ActionProcesser,
     pub http: HttpClient,
+    pub domain_blocklist: blocklist::DomainBlocklist,
+    pub media_store: Box<dyn rest::media_store::MediaStore>,
+    pub remote_media: rest::remote_media::RemoteMediaUsage,
+    /// Secret used to sign media share links.
+    pub media_share_key: Vec<u8>,
+    pub search: Option<Box<dyn search::SearchBackend>>,
+    pub diagnostics: diagnostics::Diagnostics,
+    pub alerts: alerts::Alerts,
+    pub disk_guard: disk_guard::DiskGuard,
+    pub rate_limiter: RateLimiter,
 
     pub config: Config,
     pub runtime_config: SharedConfig,
 }
 
 impl Dependencies {
-    pub async fn new(db: &Db, config: Config) -> DbResult<(Arc<Self>, FedEventReceiver)> {
+    pub async fn new(
+        db: &Db,
+        config: Config,
+    ) -> Result<(Arc<Self>, FedEventReceiver), ServerError> {
         let (fed_event_dispatcher, fed_event_receiver) = mpsc::unbounded_channel();
 
         let auth_tree = AuthTree::new(db).await?;
+        let sync_tree = db.open_tree(b"sync").await?;
+        let chat_event_sender = broadcast::channel(2048).0;
+        let http = http_client(&mut hyper::Client::builder());
+        let ratelimit_tree = db.open_tree(b"ratelimit").await?;
+        let media_tree = db.open_tree(b"media").await?;
+        let media_share_key =
+            rest::media_access::load_share_key(&media_tree, &config.media.access).await?;
+        let search = search::from_config(&config.search, &http)?;
+        let media_store = rest::media_store::from_config(&config.media)?;
+        let rate_limiter = RateLimiter::new(&config.policy.ratelimit);
+        if config.policy.ratelimit.persist {
+            rate_limiter.load(&ratelimit_tree).await?;
+        }
 
         let this = Self {
+            db: db.clone(),
             auth_tree: auth_tree.clone(),
             chat_tree: ChatTree::new(db).await?,
             profile_tree: ProfileTree::new(db).await?,
             emote_tree: EmoteTree::new(db).await?,
-            sync_tree: db.open_tree(b"sync").await?,
+            trusted_hosts: sync::trust::load_trusted_hosts(&sync_tree).await?,
+            host_lists: sync::host_lists::HostLists::load(config.federation.as_ref(), &sync_tree)
+                .await?,
+            ip_bans: ip_bans::IpBans::load(&config.policy.ratelimit, &auth_tree.inner).await?,
+            sync_tree,
+            media_tree,
+            audit_tree: db.open_tree(b"audit").await?,
+            ratelimit_tree,
 
             valid_sessions: Arc::new(DashMap::default()),
-            chat_event_sender: broadcast::channel(2048).0,
+            event_bus: bus::EventBus::new(
+                chat_event_sender.clone(),
+                &config.policy.event_replay,
+                &config.policy.event_coalescing,
+            ),
+            chat_event_sender,
+            message_nonces: chat::messages::MessageNonces::default(),
+            typing_indicators: chat::channels::TypingIndicators::default(),
+            member_lists: chat::guilds::MemberListSubscriptions::default(),
+            presence: profile::presence::Presence::default(),
             fed_event_dispatcher,
             key_manager: config
                 .federation
                 .as_ref()
-                .map(|fc| Arc::new(key::Manager::new(fc.key.clone()))),
+                .map(|fc| Arc::new(key::Manager::new(fc.key.clone(), http.clone()))),
             action_processor: ActionProcesser { auth_tree },
-            http: http_client(&mut hyper::Client::builder()),
+            search,
+            http,
+            domain_blocklist: blocklist::DomainBlocklist::new(&config.policy.domain_blocklist),
+            media_store,
+            remote_media: rest::remote_media::RemoteMediaUsage::default(),
+            media_share_key,
+            diagnostics: diagnostics::Diagnostics::default(),
+            alerts: alerts::Alerts::default(),
+            disk_guard: disk_guard::DiskGuard::default(),
+            rate_limiter,
 
             config,
             runtime_config: Arc::new(Mutex::new(SharedConfigData::default())),
@@ -112,12 +197,25 @@ impl Dependencies {
 
         Ok((Arc::new(this), fed_event_receiver))
     }
+
+    /// Checks if federating with the host is allowed, either by the
+    /// federation allow and block lists or by having redeemed a federation invite.
+    pub fn is_host_allowed(&self, host: &str) -> Result<(), ServerError> {
+        if self.config.federation.is_none() {
+            return Err(ServerError::FederationDisabled);
+        }
+        if self.trusted_hosts.contains(host) {
+            return Ok(());
+        }
+        self.host_lists.check(host)
+    }
 }
 
 pub fn setup_server(
     deps: Arc<Dependencies>,
     fed_event_receiver: tokio::sync::mpsc::UnboundedReceiver<EventDispatch>,
     log_level: tracing::Level,
+    startup: &startup::Startup,
 ) -> (impl MakeRoutes, RestServiceLayer) {
     use self::{
         auth::AuthServer, batch::BatchServer, chat::ChatServer, emote::EmoteServer,
@@ -139,14 +237,34 @@ pub fn setup_server(
     let auth_server = AuthServer::new(deps.clone());
     let chat_server = ChatServer::new(deps.clone());
     let mediaproxy_server = MediaproxyServer::new(deps.clone());
+    blocklist::DomainBlocklist::spawn_updater(deps.clone());
+    maintenance::spawn_compaction_task(deps.clone());
+    maintenance::spawn_overhead_compaction_task(deps.clone());
+    retention::spawn_pruning_task(deps.clone());
+    search::spawn_indexer(deps.clone());
+    profile::presence::spawn_idle_checker(deps.clone());
+    ratelimit::spawn_save_task(deps.clone());
+    bus::spawn_coalesced_sender(deps.clone());
     let sync_server = SyncServer::new(deps.clone(), fed_event_receiver);
     #[cfg(feature = "voice")]
     let voice_server = self::voice::VoiceServer::new(deps.clone(), log_level);
+    #[cfg(feature = "voice")]
+    {
+        let voice_server = voice_server.clone();
+        startup.spawn_optional("voice", async move { voice_server.check_workers().await });
+    }
 
     let profile = ProfileServiceServer::new(profile_server.clone());
     let emote = EmoteServiceServer::new(emote_server);
     let auth = AuthServiceServer::new(auth_server);
     let chat = ChatServiceServer::new(chat_server.clone());
+    let rest = RestServiceLayer::new(
+        deps.clone(),
+        chat_server.clone(),
+        #[cfg(feature = "voice")]
+        voice_server.clone(),
+        startup.clone(),
+    );
     let mediaproxy = MediaProxyServiceServer::new(mediaproxy_server);
     let sync = PostboxServiceServer::new(sync_server);
     #[cfg(feature = "voice")]
@@ -159,8 +277,6 @@ pub fn setup_server(
         combine_services!(profile, chat)
     };
 
-    let rest = RestServiceLayer::new(deps.clone());
-
     let batch_server = BatchServer::new(deps, batchable_services);
     let batch = BatchServiceServer::new(batch_server);
 
@@ -194,7 +310,6 @@ fn gen_rand_inline_str() -> SmolStr {
     SmolStr::new_inline(str)
 }
 
-#[allow(dead_code)]
 fn gen_rand_str<const LEN: usize>() -> SmolStr {
     let arr = gen_rand_arr::<_, LEN>(&mut rand::thread_rng());
     // Safety: arrays generated by gen_rand_arr are alphanumeric, so they are valid ASCII chars as well as UTF-8 chars [ref:alphanumeric_array_gen]
@@ -241,9 +356,22 @@ fn get_content_length<T>(response: &http::Response<T>) -> http::HeaderValue {
 
 pub struct AdminActionError;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum AdminAction {
     GenerateRegistrationToken,
+    Diagnostics,
+    DbUsage,
+    CompactDb,
+    ExportDb(String),
+    ImportDb(String),
+    ReplayEvents(sync::replay::ReplayFilter),
+    DeadLetters(sync::dead_letter::DeadLetterSelection),
+    RequeueDeadLetters(sync::dead_letter::DeadLetterSelection),
+    DiscardDeadLetters(sync::dead_letter::DeadLetterSelection),
+    GetAuditLog(u64),
+    Federation(sync::host_lists::HostListAction),
+    RotateKeys,
+    IpBans(ip_bans::IpBanAction),
     Help,
 }
 
@@ -251,8 +379,49 @@ impl FromStr for AdminAction {
     type Err = AdminActionError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let act = match s.trim_start_matches('/').trim() {
+        let s = s.trim_start_matches('/').trim();
+        if let Some(args) = s.strip_prefix("replay_events") {
+            let filter = args.parse().map_err(|_| AdminActionError)?;
+            return Ok(AdminAction::ReplayEvents(filter));
+        }
+        if let Some(args) = s.strip_prefix("requeue_dead_letters") {
+            let selection = args.parse().map_err(|_| AdminActionError)?;
+            return Ok(AdminAction::RequeueDeadLetters(selection));
+        }
+        if let Some(args) = s.strip_prefix("discard_dead_letters") {
+            let selection = args.parse().map_err(|_| AdminActionError)?;
+            return Ok(AdminAction::DiscardDeadLetters(selection));
+        }
+        if let Some(args) = s.strip_prefix("federation") {
+            let action = args.parse().map_err(|_| AdminActionError)?;
+            return Ok(AdminAction::Federation(action));
+        }
+        if let Some(args) = s.strip_prefix("ip_bans") {
+            let action = args.parse().map_err(|_| AdminActionError)?;
+            return Ok(AdminAction::IpBans(action));
+        }
+        if let Some(args) = s.strip_prefix("dead_letters") {
+            let selection = args.parse().map_err(|_| AdminActionError)?;
+            return Ok(AdminAction::DeadLetters(selection));
+        }
+        if let Some((cmd, arg)) = s.split_once(' ') {
+            let arg = arg.trim().to_string();
+            match cmd {
+                "export_db" => return Ok(AdminAction::ExportDb(arg)),
+                "import_db" => return Ok(AdminAction::ImportDb(arg)),
+                "get_audit_log" => {
+                    let guild_id = arg.parse().map_err(|_| AdminActionError)?;
+                    return Ok(AdminAction::GetAuditLog(guild_id));
+                }
+                _ => {}
+            }
+        }
+        let act = match s {
             "generate registration-token" => AdminAction::GenerateRegistrationToken,
+            "diagnostics" => AdminAction::Diagnostics,
+            "db_stats" | "db usage" => AdminAction::DbUsage,
+            "compact_db" | "db compact" => AdminAction::CompactDb,
+            "rotate_keys" => AdminAction::RotateKeys,
             "help" => AdminAction::Help,
             _ => return Err(AdminActionError),
         };
@@ -260,9 +429,29 @@ impl FromStr for AdminAction {
     }
 }
 
+/// How many audit log entries `get_audit_log` shows.
+const AUDIT_LOG_CONSOLE_LIMIT: usize = 50;
+
 pub const HELP_TEXT: &str = r#"
 commands are:
 `generate registration-token` -> generates a registration token
+`diagnostics` -> shows diagnostics about the running server
+`db_stats` -> shows how many keys every database tree has and how much space they use
+`compact_db` -> compacts the database, reclaiming unused space (with sled this only flushes it, sled reclaims space on its own)
+`export_db <path>` -> exports the whole database to a file at `path`
+`import_db <path>` -> imports a database export at `path` into the database
+`replay_events [apply] [guild=<id>] [since=<secs>] [until=<secs>]` -> replays logged federation events, only showing what would change unless `apply` is given
+`dead_letters [host=<host>]` -> lists federation events that couldn't be delivered
+`requeue_dead_letters <all | host=<host> | <id>...>` -> sends undelivered federation events again
+`discard_dead_letters <all | host=<host> | <id>...>` -> removes undelivered federation events for good
+`get_audit_log <guild_id>` -> shows the latest entries of a guild's audit log
+`federation list` -> shows the federation allow and block lists
+`federation <allow | unallow | block | unblock> <host>` -> adds a host to or removes it from the federation allow or block list
+`ip_bans list` -> shows the banned IPs and networks, with how many requests they blocked
+`ip_bans add <ip | cidr> [expires_in=<secs>] [reason]` -> bans an IP or network from the server
+`ip_bans remove <ip | cidr>` -> removes an IP ban
+`ip_bans import <path> [expires_in=<secs>]` -> bans the IPs or networks in a file, one per line with an optional reason after each
+`rotate_keys` -> replaces the federation key with a new one, still accepting the old one for a while
 `help` -> shows help
 "#;
 
@@ -272,7 +461,7 @@ pub struct ActionProcesser {
 }
 
 impl ActionProcesser {
-    pub async fn run(&self, action: &str) -> ServerResult<String> {
+    pub async fn run(&self, deps: &Dependencies, action: &str) -> ServerResult<String> {
         let maybe_action = AdminAction::from_str(action);
         match maybe_action {
             Ok(action) => match action {
@@ -280,6 +469,66 @@ impl ActionProcesser {
                     let token = self.auth_tree.put_rand_reg_token().await?;
                     Ok(token.into())
                 }
+                AdminAction::Diagnostics => {
+                    if !deps.config.policy.enable_diagnostics {
+                        return Ok("diagnostics are disabled on this server".to_string());
+                    }
+                    let report = diagnostics::DiagnosticsReport::collect(deps);
+                    Ok(serde_json::to_string_pretty(&report).unwrap())
+                }
+                AdminAction::DbUsage => {
+                    let report = maintenance::DbUsageReport::collect(&deps.db).await?;
+                    Ok(serde_json::to_string_pretty(&report).unwrap())
+                }
+                AdminAction::CompactDb => {
+                    let report = maintenance::compact(&deps.db).await?;
+                    Ok(serde_json::to_string_pretty(&report).unwrap())
+                }
+                AdminAction::ExportDb(path) => {
+                    let stats = db::dump::export(&deps.db, Path::new(&path))
+                        .await
+                        .map_err(ServerError::from)?;
+                    Ok(format_dump_stats("exported", &path, stats))
+                }
+                AdminAction::ImportDb(path) => {
+                    let stats = db::dump::import(&deps.db, Path::new(&path))
+                        .await
+                        .map_err(ServerError::from)?;
+                    Ok(format_dump_stats("imported", &path, stats))
+                }
+                AdminAction::ReplayEvents(filter) => {
+                    let report = sync::replay::replay_events(deps, filter).await?;
+                    Ok(serde_json::to_string_pretty(&report).unwrap())
+                }
+                AdminAction::DeadLetters(selection) => {
+                    let dead_letters =
+                        sync::dead_letter::list_dead_letters(deps, selection).await?;
+                    Ok(serde_json::to_string_pretty(&dead_letters).unwrap())
+                }
+                AdminAction::RequeueDeadLetters(selection) => {
+                    let report = sync::dead_letter::requeue_dead_letters(deps, selection).await?;
+                    Ok(format!("requeued {} dead letters", report.count))
+                }
+                AdminAction::DiscardDeadLetters(selection) => {
+                    let report = sync::dead_letter::discard_dead_letters(deps, selection).await?;
+                    Ok(format!("discarded {} dead letters", report.count))
+                }
+                AdminAction::GetAuditLog(guild_id) => {
+                    let pager = Pager::new(Direction::Backward, [], None, AUDIT_LOG_CONSOLE_LIMIT)?;
+                    let page = audit::get_audit_log(deps, guild_id, None, &pager).await?;
+                    Ok(serde_json::to_string_pretty(&page.items).unwrap())
+                }
+                AdminAction::Federation(action) => sync::host_lists::run_action(deps, action).await,
+                AdminAction::RotateKeys => {
+                    let (keys, config) = match (&deps.key_manager, &deps.config.federation) {
+                        (Some(keys), Some(config)) => (keys, config),
+                        _ => return Ok("federation is disabled on this server".to_string()),
+                    };
+                    let keep_old_for_secs = config.old_key_validity_days * 24 * 60 * 60;
+                    let new_key = keys.rotate_keys(keep_old_for_secs).await?;
+                    Ok(serde_json::to_string_pretty(&new_key).unwrap())
+                }
+                AdminAction::IpBans(action) => ip_bans::run_action(deps, action).await,
                 AdminAction::Help => Ok(HELP_TEXT.to_string()),
             },
             Err(_) => Ok(format!("invalid command: `{}`", action)),
@@ -287,6 +536,16 @@ impl ActionProcesser {
     }
 }
 
+fn format_dump_stats(action: &str, path: &str, stats: db::dump::DumpStats) -> String {
+    let trees = stats
+        .trees
+        .into_iter()
+        .map(|(name, entries)| format!("`{}`: {} entries", name, entries))
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!("{} database at `{}`:\n{}", action, path, trees)
+}
+
 macro_rules! impl_unary_handlers {
     ($(
         $( #[$attr:meta] )*
@@ -0,0 +1,78 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+/// Version of the settings bundle format. Bundles from newer versions can't
+/// be imported.
+pub const SETTINGS_BUNDLE_VERSION: u32 = 1;
+
+#[derive(Debug, Deserialize)]
+pub struct ExportSettingsRequest {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppDataEntry {
+    pub app_id: String,
+    pub app_data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserNoteEntry {
+    pub user_id: u64,
+    pub note: String,
+}
+
+/// A user's personal settings, which can be imported into another account.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettingsBundle {
+    pub version: u32,
+    /// Homeserver the bundle was exported from. The user and emote pack IDs
+    /// in it only refer to the same users and packs there.
+    pub exported_from: String,
+    pub exported_at: u64,
+    #[serde(default)]
+    pub app_data: Vec<AppDataEntry>,
+    /// Equipped emote packs, in the order emote names are looked up in.
+    #[serde(default)]
+    pub equipped_emote_packs: Vec<u64>,
+    #[serde(default)]
+    pub blocked_users: Vec<u64>,
+    #[serde(default)]
+    pub user_notes: Vec<UserNoteEntry>,
+}
+
+/// Exports the user's app data, equipped emote packs, blocked users and user
+/// notes, to be imported with `profile/import-settings`.
+pub async fn handler(
+    svc: &ProfileServer,
+    user_id: u64,
+    _request: ExportSettingsRequest,
+) -> ServerResult<SettingsBundle> {
+    let profile_tree = &svc.deps.profile_tree;
+
+    let app_data = profile_tree
+        .get_app_data_logic(user_id)
+        .await?
+        .into_iter()
+        .map(|(app_id, app_data)| AppDataEntry { app_id, app_data })
+        .collect();
+    let user_notes = profile_tree
+        .get_user_notes_logic(user_id)
+        .await?
+        .into_iter()
+        .map(|(user_id, note)| UserNoteEntry { user_id, note })
+        .collect();
+
+    Ok(SettingsBundle {
+        version: SETTINGS_BUNDLE_VERSION,
+        exported_from: svc.deps.config.host.clone(),
+        exported_at: get_time_secs(),
+        app_data,
+        equipped_emote_packs: svc
+            .deps
+            .emote_tree
+            .get_pack_lookup_order_logic(user_id)
+            .await?,
+        blocked_users: profile_tree.get_blocked_users_logic(user_id).await?,
+        user_notes,
+    })
+}
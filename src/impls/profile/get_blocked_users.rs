@@ -0,0 +1,28 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct GetBlockedUsersRequest {}
+
+/// Also the format block lists are imported in, so an exported list can be
+/// imported again as is.
+#[derive(Debug, Serialize)]
+pub struct GetBlockedUsersResponse {
+    pub blocked_users: Vec<u64>,
+}
+
+/// Gets the users the user blocked.
+pub async fn handler(
+    svc: &ProfileServer,
+    user_id: u64,
+    _request: GetBlockedUsersRequest,
+) -> ServerResult<GetBlockedUsersResponse> {
+    let blocked_users = svc
+        .deps
+        .profile_tree
+        .get_blocked_users_logic(user_id)
+        .await?;
+
+    Ok(GetBlockedUsersResponse { blocked_users })
+}
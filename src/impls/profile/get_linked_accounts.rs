@@ -0,0 +1,47 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct GetLinkedAccountsRequest {
+    pub user_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LinkedAccount {
+    pub host: String,
+    /// ID of the account on its homeserver.
+    pub user_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetLinkedAccountsResponse {
+    /// The local user the requested user is. This is a different user if the
+    /// requested user is from another homeserver, and linked their account
+    /// to a local one.
+    pub user_id: u64,
+    /// Accounts on other homeservers the user proved they own.
+    pub accounts: Vec<LinkedAccount>,
+}
+
+/// Gets the accounts on other homeservers linked to a user.
+pub async fn handler(
+    svc: &ProfileServer,
+    _user_id: u64,
+    request: GetLinkedAccountsRequest,
+) -> ServerResult<GetLinkedAccountsResponse> {
+    let profile_tree = &svc.deps.profile_tree;
+
+    profile_tree.does_user_exist(request.user_id).await?;
+    let user_id = profile_tree
+        .resolve_linked_user_logic(request.user_id)
+        .await?;
+    let accounts = profile_tree
+        .get_linked_accounts_logic(user_id)
+        .await?
+        .into_iter()
+        .map(|(user_id, host)| LinkedAccount { host, user_id })
+        .collect();
+
+    Ok(GetLinkedAccountsResponse { user_id, accounts })
+}
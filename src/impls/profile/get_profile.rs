@@ -1,4 +1,10 @@
-use super::*;
+use super::{get_linked_accounts::LinkedAccount, *};
+
+use hyper::http::HeaderValue;
+
+/// Response header with the accounts on other homeservers linked to the
+/// user, as a JSON array.
+const LINKED_ACCOUNTS_HEADER: &str = "x-scherzo-linked-accounts";
 
 pub async fn handler(
     svc: &ProfileServer,
@@ -8,11 +14,30 @@ pub async fn handler(
 
     let GetProfileRequest { user_id } = request.into_message().await?;
 
-    svc.deps
-        .profile_tree
-        .get_profile_logic(user_id)
-        .await
-        .map(|p| GetProfileResponse { profile: Some(p) })
-        .map(IntoResponse::into_response)
-        .map_err(Into::into)
+    let profile_tree = &svc.deps.profile_tree;
+    // An account linked to a local user has the local user's profile
+    let user_id = profile_tree.resolve_linked_user_logic(user_id).await?;
+    let mut profile = profile_tree.get_profile_logic(user_id).await?;
+    profile.user_status = svc.deps.presence.status(user_id, profile.user_status);
+
+    let accounts = profile_tree
+        .get_linked_accounts_logic(user_id)
+        .await?
+        .into_iter()
+        .map(|(user_id, host)| LinkedAccount { host, user_id })
+        .collect::<Vec<_>>();
+
+    let mut response = GetProfileResponse {
+        profile: Some(profile),
+    }
+    .into_response();
+    if !accounts.is_empty() {
+        let accounts = serde_json::to_string(&accounts).expect("linked accounts are serializable");
+        if let Ok(value) = HeaderValue::from_str(&accounts) {
+            response
+                .get_or_insert_header_map()
+                .insert(LINKED_ACCOUNTS_HEADER, value);
+        }
+    }
+    Ok(response)
 }
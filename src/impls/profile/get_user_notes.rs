@@ -0,0 +1,50 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct GetUserNotesRequest {
+    /// Only get the note about this user. Gets all notes if not set.
+    #[serde(default)]
+    pub user_id: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserNote {
+    pub user_id: u64,
+    pub note: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetUserNotesResponse {
+    pub notes: Vec<UserNote>,
+}
+
+/// Gets the notes the user wrote about other users.
+pub async fn handler(
+    svc: &ProfileServer,
+    user_id: u64,
+    request: GetUserNotesRequest,
+) -> ServerResult<GetUserNotesResponse> {
+    let profile_tree = &svc.deps.profile_tree;
+
+    let notes = match request.user_id {
+        Some(target_id) => profile_tree
+            .get(make_user_note_key(user_id, target_id))
+            .await?
+            .map(|note| UserNote {
+                user_id: target_id,
+                note: String::from_utf8_lossy(&note).into_owned(),
+            })
+            .into_iter()
+            .collect(),
+        None => profile_tree
+            .get_user_notes_logic(user_id)
+            .await?
+            .into_iter()
+            .map(|(user_id, note)| UserNote { user_id, note })
+            .collect(),
+    };
+
+    Ok(GetUserNotesResponse { notes })
+}
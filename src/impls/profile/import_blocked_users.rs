@@ -0,0 +1,59 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct ImportBlockedUsersRequest {
+    /// Users to block, in the format block lists are exported in.
+    pub blocked_users: Vec<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportBlockedUsersResponse {
+    /// How many users were blocked, not counting the ones that already were.
+    pub added: usize,
+    /// Users that were skipped since they don't exist on this server.
+    pub skipped: Vec<u64>,
+}
+
+/// Imports a block list, merging it with the users already blocked.
+pub async fn handler(
+    svc: &ProfileServer,
+    user_id: u64,
+    request: ImportBlockedUsersRequest,
+) -> ServerResult<ImportBlockedUsersResponse> {
+    let ImportBlockedUsersRequest { mut blocked_users } = request;
+
+    let profile_tree = &svc.deps.profile_tree;
+
+    blocked_users.sort_unstable();
+    blocked_users.dedup();
+    blocked_users.retain(|id| *id != user_id);
+
+    let already_blocked = profile_tree.get_blocked_users_logic(user_id).await?;
+    let mut to_block = Vec::with_capacity(blocked_users.len());
+    let mut skipped = Vec::new();
+    for blocked_id in blocked_users {
+        if already_blocked.contains(&blocked_id) {
+            continue;
+        }
+        if !profile_tree
+            .contains_key(make_user_profile_key(blocked_id))
+            .await?
+        {
+            skipped.push(blocked_id);
+            continue;
+        }
+        to_block.push(blocked_id);
+    }
+    if already_blocked.len() + to_block.len() > MAX_BLOCKED_USERS {
+        bail!((
+            "h.too-many-blocked-users",
+            format!("you can't block more than {} users", MAX_BLOCKED_USERS)
+        ));
+    }
+
+    let added = profile_tree.block_users_logic(user_id, &to_block).await?;
+
+    Ok(ImportBlockedUsersResponse { added, skipped })
+}
@@ -0,0 +1,286 @@
+use std::collections::{HashMap, HashSet};
+
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+use crate::db::emote::make_emote_pack_key;
+
+use export_settings::{SettingsBundle, SETTINGS_BUNDLE_VERSION};
+
+/// What to do with settings the account already has, that the bundle has too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    /// Keep the account's settings, only importing the ones it doesn't have.
+    KeepExisting,
+    /// Take the bundle's settings, keeping the account's other settings.
+    Overwrite,
+    /// Make the account's settings the same as the bundle's, removing the
+    /// ones the bundle doesn't have.
+    Replace,
+}
+
+impl Default for ConflictPolicy {
+    fn default() -> Self {
+        Self::KeepExisting
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportSettingsRequest {
+    /// A bundle exported with `profile/export-settings`.
+    pub bundle: SettingsBundle,
+    #[serde(default)]
+    pub on_conflict: ConflictPolicy,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportSettingsResponse {
+    /// How many settings were imported, not counting the ones that were
+    /// already set the same.
+    pub imported: usize,
+    /// How many settings were removed, with the `replace` policy.
+    pub removed: usize,
+    /// How many settings were kept as they were, with the `keep_existing` policy.
+    pub kept_existing: usize,
+    /// Settings that couldn't be imported, and why.
+    pub skipped: Vec<String>,
+}
+
+/// Imports a settings bundle, which can be exported from another account or
+/// homeserver. Blocked users, user notes and emote packs are only imported
+/// from bundles exported on this homeserver, since they refer to IDs here.
+pub async fn handler(
+    svc: &ProfileServer,
+    user_id: u64,
+    request: ImportSettingsRequest,
+) -> ServerResult<ImportSettingsResponse> {
+    let ImportSettingsRequest {
+        bundle,
+        on_conflict,
+    } = request;
+
+    if bundle.version > SETTINGS_BUNDLE_VERSION {
+        bail!((
+            "h.unsupported-settings-bundle",
+            format!(
+                "settings bundle is at version {}, but only up to version {} is supported",
+                bundle.version, SETTINGS_BUNDLE_VERSION
+            )
+        ));
+    }
+    if let Some(entry) = bundle
+        .user_notes
+        .iter()
+        .find(|entry| entry.note.len() > MAX_USER_NOTE_LENGTH)
+    {
+        bail!((
+            "h.user-note-too-long",
+            format!(
+                "note about user {} is longer than {} bytes",
+                entry.user_id, MAX_USER_NOTE_LENGTH
+            )
+        ));
+    }
+
+    let profile_tree = &svc.deps.profile_tree;
+    let emote_tree = &svc.deps.emote_tree;
+    let mut response = ImportSettingsResponse::default();
+    let mut batch = Batch::default();
+
+    // app data is the same wherever the bundle is from
+    let existing = profile_tree
+        .get_app_data_logic(user_id)
+        .await?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    let mut in_bundle = HashSet::with_capacity(bundle.app_data.len());
+    for entry in bundle.app_data {
+        in_bundle.insert(entry.app_id.clone());
+        match existing.get(&entry.app_id) {
+            Some(data) if *data == entry.app_data => continue,
+            Some(_) if on_conflict == ConflictPolicy::KeepExisting => {
+                response.kept_existing += 1;
+                continue;
+            }
+            _ => {}
+        }
+        batch.insert(
+            make_user_metadata_key(user_id, &entry.app_id),
+            entry.app_data,
+        );
+        response.imported += 1;
+    }
+    if on_conflict == ConflictPolicy::Replace {
+        for app_id in existing.keys().filter(|id| !in_bundle.contains(*id)) {
+            batch.remove(make_user_metadata_key(user_id, app_id));
+            response.removed += 1;
+        }
+    }
+
+    if bundle.exported_from != svc.deps.config.host {
+        let has_ids = !(bundle.blocked_users.is_empty()
+            && bundle.user_notes.is_empty()
+            && bundle.equipped_emote_packs.is_empty());
+        if has_ids {
+            response.skipped.push(format!(
+                "blocked users, user notes and emote packs were exported from {}, so they don't refer to the same users and packs here",
+                bundle.exported_from
+            ));
+        }
+        profile_tree.apply_batch(batch).await?;
+        return Ok(response);
+    }
+
+    // blocked users
+    let already_blocked = profile_tree
+        .get_blocked_users_logic(user_id)
+        .await?
+        .into_iter()
+        .collect::<HashSet<_>>();
+    let mut to_block = Vec::new();
+    let mut in_bundle = HashSet::with_capacity(bundle.blocked_users.len());
+    for blocked_id in bundle.blocked_users {
+        if blocked_id == user_id || !in_bundle.insert(blocked_id) {
+            continue;
+        }
+        if already_blocked.contains(&blocked_id) {
+            continue;
+        }
+        if !profile_tree
+            .contains_key(make_user_profile_key(blocked_id))
+            .await?
+        {
+            response
+                .skipped
+                .push(format!("blocked user {} doesn't exist", blocked_id));
+            continue;
+        }
+        to_block.push(blocked_id);
+    }
+    let unblocked = match on_conflict {
+        ConflictPolicy::Replace => already_blocked
+            .iter()
+            .filter(|id| !in_bundle.contains(*id))
+            .copied()
+            .collect(),
+        _ => Vec::new(),
+    };
+    if already_blocked.len() - unblocked.len() + to_block.len() > MAX_BLOCKED_USERS {
+        bail!((
+            "h.too-many-blocked-users",
+            format!("you can't block more than {} users", MAX_BLOCKED_USERS)
+        ));
+    }
+    let blocked_at = get_time_secs().to_be_bytes();
+    for blocked_id in &to_block {
+        batch.insert(make_user_blocked_key(user_id, *blocked_id), blocked_at);
+    }
+    for blocked_id in &unblocked {
+        batch.remove(make_user_blocked_key(user_id, *blocked_id));
+    }
+    response.imported += to_block.len();
+    response.removed += unblocked.len();
+
+    // user notes
+    let existing = profile_tree
+        .get_user_notes_logic(user_id)
+        .await?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    let mut note_count = existing.len();
+    let mut in_bundle = HashSet::with_capacity(bundle.user_notes.len());
+    for entry in bundle.user_notes {
+        if entry.note.is_empty() || !in_bundle.insert(entry.user_id) {
+            continue;
+        }
+        match existing.get(&entry.user_id) {
+            Some(note) if *note == entry.note => continue,
+            Some(_) if on_conflict == ConflictPolicy::KeepExisting => {
+                response.kept_existing += 1;
+                continue;
+            }
+            Some(_) => {}
+            None => {
+                if !profile_tree
+                    .contains_key(make_user_profile_key(entry.user_id))
+                    .await?
+                {
+                    response.skipped.push(format!(
+                        "note about user {} was left out, since they don't exist",
+                        entry.user_id
+                    ));
+                    continue;
+                }
+                note_count += 1;
+            }
+        }
+        batch.insert(
+            make_user_note_key(user_id, entry.user_id),
+            entry.note.into_bytes(),
+        );
+        response.imported += 1;
+    }
+    if on_conflict == ConflictPolicy::Replace {
+        for target_id in existing.keys().filter(|id| !in_bundle.contains(*id)) {
+            batch.remove(make_user_note_key(user_id, *target_id));
+            note_count -= 1;
+            response.removed += 1;
+        }
+    }
+    if note_count > MAX_USER_NOTES {
+        bail!((
+            "h.too-many-user-notes",
+            format!("you can't have more than {} notes", MAX_USER_NOTES)
+        ));
+    }
+
+    profile_tree.apply_batch(batch).await?;
+
+    // emote packs
+    let equipped = emote_tree.get_equipped_packs_logic(user_id).await?;
+    let mut lookup_order = Vec::with_capacity(bundle.equipped_emote_packs.len());
+    for pack_id in bundle.equipped_emote_packs {
+        if lookup_order.contains(&pack_id) {
+            continue;
+        }
+        // the pack might have been deleted since it was exported
+        let Some(raw) = emote_tree.get(make_emote_pack_key(pack_id)).await? else {
+            response
+                .skipped
+                .push(format!("emote pack {} doesn't exist", pack_id));
+            continue;
+        };
+        lookup_order.push(pack_id);
+        if equipped.contains(&pack_id) {
+            continue;
+        }
+        emote_tree.equip_emote_pack_logic(user_id, pack_id).await?;
+        svc.deps.event_bus.publish(DomainEvent::EmotePackAdded {
+            pack: db::deser_emote_pack(raw),
+            user_ids: vec![user_id],
+        });
+        response.imported += 1;
+    }
+    if on_conflict == ConflictPolicy::Replace {
+        for pack_id in equipped.iter().filter(|id| !lookup_order.contains(*id)) {
+            emote_tree
+                .dequip_emote_pack_logic(user_id, *pack_id)
+                .await?;
+            svc.deps.event_bus.publish(DomainEvent::EmotePackDeleted {
+                pack_id: *pack_id,
+                user_ids: vec![user_id],
+            });
+            response.removed += 1;
+        }
+    }
+    // the account's own pack order wins, unless it didn't have any packs
+    if on_conflict != ConflictPolicy::KeepExisting || equipped.is_empty() {
+        emote_tree
+            .set_pack_priority_logic(user_id, &lookup_order)
+            .await?;
+    }
+
+    Ok(response)
+}
@@ -0,0 +1,277 @@
+//! Linking accounts on different homeservers, so they are known to be the
+//! same person.
+//!
+//! The user starts linking on one homeserver with `profile/start-account-link`,
+//! and gets a token. Logged in as their account on the other homeserver, they
+//! give the token to `profile/link-account`. That homeserver signs the token
+//! secret and the account's ID with its federation key, and redeems the token
+//! at `federation/link-account` on the first homeserver. Both homeservers then
+//! store the link.
+
+use harmony_rust_sdk::api::harmonytypes::Token;
+use hyper::{http, Body, Method, StatusCode, Uri};
+use serde::{Deserialize, Serialize};
+use sha3::Digest;
+
+use crate::key::{self, Manager as KeyManager};
+
+use super::*;
+
+pub(super) fn hash_link_secret(secret: &str) -> impl AsRef<[u8]> {
+    sha3::Sha3_512::digest(secret.as_bytes())
+}
+
+/// The data signed by the homeserver redeeming a link token.
+fn link_message(host: &str, user_id: u64, time: u64, secret: &str) -> Vec<u8> {
+    format!("{}\n{}\n{}\n{}", host, user_id, time, secret).into_bytes()
+}
+
+fn keys_manager(deps: &Dependencies) -> Result<&Arc<KeyManager>, ServerError> {
+    deps.key_manager
+        .as_ref()
+        .ok_or(ServerError::FederationDisabled)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LinkAccountRequest {
+    /// Token from `profile/start-account-link` on the other homeserver.
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LinkAccountResponse {
+    /// Host of the homeserver the linked account is on.
+    pub host: String,
+    /// ID of the linked account there.
+    pub user_id: u64,
+}
+
+/// Links the account on another homeserver that made a link token to this one.
+pub async fn handler(
+    svc: &ProfileServer,
+    user_id: u64,
+    request: LinkAccountRequest,
+) -> ServerResult<LinkAccountResponse> {
+    let deps = &svc.deps;
+    let keys_manager = keys_manager(deps)?;
+
+    let invalid_token = || ("h.invalid-link-token", "account link token is invalid");
+    let Some((host, secret)) = request.token.rsplit_once('/') else {
+        bail!(invalid_token());
+    };
+    if host == deps.config.host {
+        bail!(invalid_token());
+    }
+    deps.is_host_allowed(host)?;
+    if deps
+        .profile_tree
+        .local_to_foreign_id(user_id)
+        .await?
+        .is_some()
+    {
+        bail!((
+            "h.cant-link-foreign-account",
+            "accounts from other homeservers can't be linked from here"
+        ));
+    }
+    let Ok(url) = format!("https://{}/_scherzo/federation/link-account", host).parse::<Uri>()
+    else {
+        bail!(invalid_token());
+    };
+
+    let our_host = deps.config.host.clone();
+    let time = get_time_secs();
+    let signature = keys_manager
+        .sign(&link_message(&our_host, user_id, time, secret))
+        .await?;
+    let body = serde_json::to_vec(&RedeemLinkTokenRequest {
+        host: our_host,
+        user_id,
+        time,
+        secret: secret.to_string(),
+        signature,
+    })
+    .expect("must be valid json");
+
+    let request = http::Request::builder()
+        .method(Method::POST)
+        .uri(url)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .expect("must be valid request");
+    let response = deps
+        .http
+        .request(request)
+        .await
+        .map_err(ServerError::from)?;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body())
+        .await
+        .map_err(ServerError::from)?;
+    if status != StatusCode::OK {
+        bail!((
+            "h.account-link-rejected",
+            format!(
+                "{} rejected the account link: {} {}",
+                host,
+                status,
+                String::from_utf8_lossy(&body)
+            )
+        ));
+    }
+    let Ok(linked) = serde_json::from_slice::<LinkAccountResponse>(&body) else {
+        bail!((
+            "h.account-link-rejected",
+            format!("{} sent an invalid response", host)
+        ));
+    };
+    if linked.host != host {
+        bail!((
+            "h.account-link-rejected",
+            format!("{} said it is {}", host, linked.host)
+        ));
+    }
+
+    deps.profile_tree
+        .link_account_logic(user_id, linked.user_id, &linked.host)
+        .await?;
+
+    tracing::info!(
+        "linked user {} to user {} on {}",
+        user_id,
+        linked.user_id,
+        linked.host
+    );
+
+    Ok(linked)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RedeemLinkTokenRequest {
+    /// Host of the homeserver redeeming the token.
+    pub host: String,
+    /// ID of the account to link, on the redeeming homeserver.
+    pub user_id: u64,
+    pub time: u64,
+    pub secret: String,
+    /// Signature of the host, user ID, time and secret by the federation key
+    /// of the redeeming homeserver.
+    pub signature: Vec<u8>,
+}
+
+/// Redeems a link token made on this homeserver, linking the account on the
+/// redeeming homeserver to the user who made the token.
+pub async fn redeem_link_token_handler(
+    deps: &Dependencies,
+    request: RedeemLinkTokenRequest,
+) -> ServerResult<LinkAccountResponse> {
+    let RedeemLinkTokenRequest {
+        host,
+        user_id: foreign_id,
+        time,
+        secret,
+        signature,
+    } = request;
+
+    let keys_manager = keys_manager(deps)?;
+    deps.is_host_allowed(&host)?;
+
+    let cur_time = get_time_secs();
+    // Check time variance (1 minute)
+    if time >= cur_time + 30 || time <= cur_time - 30 {
+        bail!(ServerError::InvalidTime);
+    }
+
+    let invalid_token = || ("h.invalid-link-token", "no such account link token");
+    let challenge_key = make_account_link_challenge_key(hash_link_secret(&secret).as_ref());
+    let Some(raw) = deps.profile_tree.get(&challenge_key).await? else {
+        bail!(invalid_token());
+    };
+    let (raw_expires_at, rest) = raw.split_at(size_of::<u64>());
+    let (raw_user_id, raw_host) = rest.split_at(size_of::<u64>());
+    // Safety: we store two u64s and a host as challenge values
+    let expires_at = u64::from_be_bytes(unsafe { raw_expires_at.try_into().unwrap_unchecked() });
+    let user_id = u64::from_be_bytes(unsafe { raw_user_id.try_into().unwrap_unchecked() });
+    if expires_at < cur_time {
+        deps.profile_tree.remove(&challenge_key).await?;
+        bail!(("h.link-token-expired", "the account link token has expired"));
+    }
+    // the token was made for linking an account on a specific homeserver
+    if raw_host != host.as_bytes() {
+        bail!(invalid_token());
+    }
+
+    let pubkey = keys_manager.get_key(host.as_str().into()).await?;
+    let token = Token {
+        sig: signature,
+        data: link_message(&host, foreign_id, time, &secret),
+    };
+    key::verify_token(&token, &pubkey)?;
+
+    // a token can only be redeemed once, even by requests racing each other
+    let consumed = deps
+        .profile_tree
+        .compare_and_swap(&challenge_key, Some(raw.as_ref()), None)
+        .await?;
+    if !consumed {
+        bail!(invalid_token());
+    }
+    deps.profile_tree
+        .link_account_logic(user_id, foreign_id, &host)
+        .await?;
+
+    tracing::info!("linked user {} to user {} on {}", user_id, foreign_id, host);
+
+    Ok(LinkAccountResponse {
+        host: deps.config.host.clone(),
+        user_id,
+    })
+}
+
+#[cfg(all(test, feature = "sled"))]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn linked_foreign_users_resolve_to_local_user() {
+        let db = crate::db::open_temp();
+        let profile_tree = ProfileTree::new(&db).await.unwrap();
+        // user 2 is user 20 from other.example, and logged in here before
+        let mut batch = Batch::default();
+        batch.insert(
+            make_local_to_foreign_user_key(2),
+            [20_u64.to_be_bytes().as_ref(), b"other.example"].concat(),
+        );
+        batch.insert(
+            make_foreign_to_local_user_key(20, "other.example"),
+            2_u64.to_be_bytes(),
+        );
+        profile_tree.apply_batch(batch).await.unwrap();
+        assert_eq!(profile_tree.resolve_linked_user_logic(2).await.unwrap(), 2);
+
+        profile_tree
+            .link_account_logic(1, 20, "other.example")
+            .await
+            .unwrap();
+        assert_eq!(profile_tree.resolve_linked_user_logic(2).await.unwrap(), 1);
+        assert_eq!(
+            profile_tree.get_linked_accounts_logic(1).await.unwrap(),
+            vec![(20, "other.example".to_string())]
+        );
+        assert!(profile_tree
+            .link_account_logic(3, 20, "other.example")
+            .await
+            .is_err());
+
+        assert!(profile_tree
+            .unlink_account_logic(1, 20, "other.example")
+            .await
+            .unwrap());
+        assert_eq!(profile_tree.resolve_linked_user_logic(2).await.unwrap(), 2);
+        assert!(profile_tree
+            .get_linked_accounts_logic(1)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
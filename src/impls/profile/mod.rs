@@ -1,19 +1,35 @@
-use super::{
-    chat::{EventBroadcast, EventContext, EventSub, PermCheck},
-    prelude::*,
-};
+use super::{bus::DomainEvent, get_time_secs, prelude::*};
 
 use db::profile::*;
 use harmony_rust_sdk::api::{
-    chat::Event,
+    chat::{format, FormattedText},
     profile::{profile_service_server::ProfileService, *},
 };
 
+pub mod export_settings;
 pub mod get_app_data;
+pub mod get_blocked_users;
+pub mod get_linked_accounts;
 pub mod get_profile;
+pub mod get_user_notes;
+pub mod import_blocked_users;
+pub mod import_settings;
+pub mod link_account;
+pub mod presence;
 pub mod set_app_data;
+pub mod set_user_blocked;
+pub mod set_user_note;
+pub mod start_account_link;
+pub mod unlink_account;
 pub mod update_profile;
 
+/// Maximum length of a user note, in bytes.
+pub const MAX_USER_NOTE_LENGTH: usize = 1024;
+/// Maximum amount of notes a user can have.
+pub const MAX_USER_NOTES: usize = 1000;
+/// Maximum amount of users a user can block.
+pub const MAX_BLOCKED_USERS: usize = 5000;
+
 #[derive(Clone)]
 pub struct ProfileServer {
     disable_ratelimits: bool,
@@ -32,19 +48,6 @@ impl ProfileServer {
         self.disable_ratelimits = true;
         self
     }
-
-    #[inline(always)]
-    fn send_event_through_chan(
-        &self,
-        sub: EventSub,
-        event: stream_event::Event,
-        perm_check: Option<PermCheck<'static>>,
-        context: EventContext,
-    ) {
-        let broadcast = EventBroadcast::new(sub, Event::Profile(event), perm_check, context);
-
-        drop(self.deps.chat_event_sender.send(Arc::new(broadcast)));
-    }
 }
 
 impl ProfileService for ProfileServer {
@@ -119,6 +122,107 @@ impl ProfileTree {
         Ok(profile)
     }
 
+    /// Gets the app data of every app a user has stored some for, by app ID.
+    pub async fn get_app_data_logic(&self, user_id: u64) -> ServerResult<Vec<(String, Vec<u8>)>> {
+        let prefix = make_user_metadata_prefix(user_id);
+        self.scan_prefix(&prefix)
+            .await
+            .map(|res| {
+                let (key, value) = res?;
+                let app_id = String::from_utf8_lossy(key.split_at(prefix.len()).1).into_owned();
+                Ok((app_id, value.as_ref().to_vec()))
+            })
+            .collect()
+    }
+
+    /// Gets the notes a user wrote about other users, along with the IDs of those users.
+    pub async fn get_user_notes_logic(&self, author_id: u64) -> ServerResult<Vec<(u64, String)>> {
+        let prefix = make_user_note_prefix(author_id);
+        self.scan_prefix(&prefix)
+            .await
+            .map(|res| {
+                let (key, value) = res?;
+                // Safety: this is safe since the only keys we get are note keys, which after stripping prefix are user IDs
+                let user_id = u64::from_be_bytes(unsafe {
+                    key.split_at(prefix.len()).1.try_into().unwrap_unchecked()
+                });
+                Ok((user_id, String::from_utf8_lossy(&value).into_owned()))
+            })
+            .collect()
+    }
+
+    /// Sets the note a user wrote about another user. An empty note removes it.
+    pub async fn set_user_note_logic(
+        &self,
+        author_id: u64,
+        user_id: u64,
+        note: &str,
+    ) -> ServerResult<()> {
+        let key = make_user_note_key(author_id, user_id);
+        if note.is_empty() {
+            self.remove(key).await?;
+        } else {
+            self.insert(key, note.as_bytes()).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn get_blocked_users_logic(&self, user_id: u64) -> ServerResult<Vec<u64>> {
+        let prefix = make_user_blocked_prefix(user_id);
+        self.scan_prefix(&prefix)
+            .await
+            .map(|res| {
+                let (key, _) = res?;
+                // Safety: this is safe since the only keys we get are block keys, which after stripping prefix are user IDs
+                Ok(u64::from_be_bytes(unsafe {
+                    key.split_at(prefix.len()).1.try_into().unwrap_unchecked()
+                }))
+            })
+            .collect()
+    }
+
+    /// Blocks the given users, skipping the ones that are already blocked.
+    ///
+    /// Returns how many users were newly blocked.
+    pub async fn block_users_logic(
+        &self,
+        user_id: u64,
+        blocked_ids: &[u64],
+    ) -> ServerResult<usize> {
+        let blocked_at = get_time_secs().to_be_bytes();
+        let mut batch = Batch::default();
+        let mut added = 0;
+        for blocked_id in blocked_ids {
+            let key = make_user_blocked_key(user_id, *blocked_id);
+            if !self.contains_key(key).await? {
+                batch.insert(key, blocked_at);
+                added += 1;
+            }
+        }
+        self.apply_batch(batch).await?;
+        Ok(added)
+    }
+
+    pub async fn unblock_user_logic(&self, user_id: u64, blocked_id: u64) -> ServerResult<()> {
+        self.remove(make_user_blocked_key(user_id, blocked_id))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_last_seen_logic(&self, user_id: u64) -> ServerResult<Option<u64>> {
+        Ok(self
+            .get(make_user_last_seen_key(user_id))
+            .await?
+            // Safety: we store u64's only for these keys
+            .map(|raw| u64::from_be_bytes(unsafe { raw.try_into().unwrap_unchecked() })))
+    }
+
+    pub async fn set_last_seen_logic(&self, user_id: u64, last_seen: u64) -> ServerResult<()> {
+        self.insert(make_user_last_seen_key(user_id), last_seen.to_be_bytes())
+            .await?;
+        Ok(())
+    }
+
     pub async fn does_user_exist(&self, user_id: u64) -> ServerResult<()> {
         self.contains_key(&make_user_profile_key(user_id))
             .await?
@@ -126,6 +230,108 @@ impl ProfileTree {
             .unwrap_or_else(|| Err(ServerError::NoSuchUser(user_id).into()))
     }
 
+    /// Gets the accounts on other homeservers linked to a user, as their IDs
+    /// there and their hosts.
+    pub async fn get_linked_accounts_logic(
+        &self,
+        user_id: u64,
+    ) -> ServerResult<Vec<(u64, String)>> {
+        let prefix = make_account_link_prefix(user_id);
+        self.scan_prefix(&prefix)
+            .await
+            .map(|res| {
+                let (key, _) = res?;
+                let (raw_id, raw_host) = key.split_at(prefix.len()).1.split_at(size_of::<u64>());
+                // Safety: safe since we split at u64 boundary.
+                let foreign_id =
+                    u64::from_be_bytes(unsafe { raw_id.try_into().unwrap_unchecked() });
+                Ok((foreign_id, String::from_utf8_lossy(raw_host).into_owned()))
+            })
+            .collect()
+    }
+
+    /// Gets the local user an account on another homeserver is linked to.
+    pub async fn get_linked_user_logic(
+        &self,
+        foreign_id: u64,
+        host: &str,
+    ) -> ServerResult<Option<u64>> {
+        Ok(self
+            .get(make_linked_foreign_user_key(foreign_id, host))
+            .await?
+            // Safety: we store u64's only for these keys
+            .map(|raw| u64::from_be_bytes(unsafe { raw.try_into().unwrap_unchecked() })))
+    }
+
+    /// Resolves a user to the local user they are linked to, if the user is
+    /// from another homeserver and linked their account here. Other users
+    /// resolve to themselves.
+    pub async fn resolve_linked_user_logic(&self, user_id: u64) -> ServerResult<u64> {
+        let Some((foreign_id, host)) = self.local_to_foreign_id(user_id).await? else {
+            return Ok(user_id);
+        };
+        Ok(self
+            .get_linked_user_logic(foreign_id, &host)
+            .await?
+            .unwrap_or(user_id))
+    }
+
+    /// Points the user mentions in a text at the local users the mentioned
+    /// accounts are linked to, so mentioning either identity mentions the
+    /// same person.
+    pub async fn resolve_mentions_logic(&self, text: &mut FormattedText) -> ServerResult<()> {
+        for format in &mut text.format {
+            if let Some(format::Format::UserMention(mention)) = &mut format.format {
+                mention.user_id = self.resolve_linked_user_logic(mention.user_id).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Links an account on another homeserver to a local user.
+    pub async fn link_account_logic(
+        &self,
+        user_id: u64,
+        foreign_id: u64,
+        host: &str,
+    ) -> ServerResult<()> {
+        match self.get_linked_user_logic(foreign_id, host).await? {
+            Some(linked_id) if linked_id == user_id => return Ok(()),
+            Some(_) => bail!((
+                "h.account-already-linked",
+                "the account is already linked to another user"
+            )),
+            None => {}
+        }
+        let mut batch = Batch::default();
+        batch.insert(make_account_link_key(user_id, foreign_id, host), []);
+        batch.insert(
+            make_linked_foreign_user_key(foreign_id, host),
+            user_id.to_be_bytes(),
+        );
+        self.apply_batch(batch).await?;
+        Ok(())
+    }
+
+    /// Unlinks an account on another homeserver from a local user. Returns
+    /// whether it was linked.
+    pub async fn unlink_account_logic(
+        &self,
+        user_id: u64,
+        foreign_id: u64,
+        host: &str,
+    ) -> ServerResult<bool> {
+        let key = make_account_link_key(user_id, foreign_id, host);
+        if !self.contains_key(&key).await? {
+            return Ok(false);
+        }
+        let mut batch = Batch::default();
+        batch.remove(key);
+        batch.remove(make_linked_foreign_user_key(foreign_id, host));
+        self.apply_batch(batch).await?;
+        Ok(true)
+    }
+
     /// Converts a local user ID to the corresponding foreign user ID and the host
     pub async fn local_to_foreign_id(&self, local_id: u64) -> ServerResult<Option<(u64, SmolStr)>> {
         let key = make_local_to_foreign_user_key(local_id);
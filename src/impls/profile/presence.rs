@@ -0,0 +1,203 @@
+//! Online, idle and offline status of users, tracked from their event streams.
+//!
+//! A user is online while they have an event stream open, and goes idle if
+//! none of their streams sent anything for [`IDLE_AFTER`]. Statuses users set
+//! themselves (like do not disturb) are kept while they are online.
+
+use std::time::{Duration, Instant};
+
+use ahash::RandomState;
+use dashmap::DashMap;
+use harmony_rust_sdk::api::profile::UserStatus;
+use serde::{Deserialize, Serialize};
+
+use crate::impls::get_time_secs;
+
+use super::*;
+
+/// How long a user can go without sending anything before they are shown as idle.
+pub const IDLE_AFTER: Duration = Duration::from_secs(5 * 60);
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Users with at least one event stream open.
+#[derive(Default)]
+pub struct Presence {
+    users: DashMap<u64, UserPresence, RandomState>,
+}
+
+struct UserPresence {
+    streams: usize,
+    last_active: Instant,
+    idle: bool,
+}
+
+impl Presence {
+    /// Returns whether the user just came online.
+    fn connect(&self, user_id: u64) -> bool {
+        let mut presence = self.users.entry(user_id).or_insert(UserPresence {
+            streams: 0,
+            last_active: Instant::now(),
+            idle: false,
+        });
+        presence.streams += 1;
+        presence.last_active = Instant::now();
+        let came_online = presence.streams == 1 || presence.idle;
+        presence.idle = false;
+        came_online
+    }
+
+    /// Returns whether the user just went offline.
+    fn disconnect(&self, user_id: u64) -> bool {
+        if let Some(mut presence) = self.users.get_mut(&user_id) {
+            presence.streams = presence.streams.saturating_sub(1);
+        }
+        self.users
+            .remove_if(&user_id, |_, presence| presence.streams == 0)
+            .is_some()
+    }
+
+    /// Returns whether the user was idle.
+    fn touch(&self, user_id: u64) -> bool {
+        self.users.get_mut(&user_id).map_or(false, |mut presence| {
+            presence.last_active = Instant::now();
+            std::mem::replace(&mut presence.idle, false)
+        })
+    }
+
+    /// Marks users that weren't active for a while as idle, and returns them.
+    fn mark_idle(&self) -> Vec<u64> {
+        let mut went_idle = Vec::new();
+        for mut entry in self.users.iter_mut() {
+            if !entry.idle && entry.last_active.elapsed() >= IDLE_AFTER {
+                entry.idle = true;
+                went_idle.push(*entry.key());
+            }
+        }
+        went_idle
+    }
+
+    pub fn is_online(&self, user_id: u64) -> bool {
+        self.users.contains_key(&user_id)
+    }
+
+    /// Gets the status to show for a user, given the status stored in their profile.
+    pub fn status(&self, user_id: u64, stored_status: i32) -> i32 {
+        match self.users.get(&user_id) {
+            None => UserStatus::OfflineUnspecified.into(),
+            Some(_) if is_custom_status(stored_status) => stored_status,
+            Some(presence) if presence.idle => UserStatus::Idle.into(),
+            Some(_) => UserStatus::Online.into(),
+        }
+    }
+}
+
+/// Whether a status was set by the user, instead of coming from their presence.
+fn is_custom_status(status: i32) -> bool {
+    status != i32::from(UserStatus::OfflineUnspecified)
+        && status != i32::from(UserStatus::Online)
+        && status != i32::from(UserStatus::Idle)
+}
+
+/// Tracks an event stream of a user, telling guild members if they came online.
+pub async fn stream_opened(deps: &Dependencies, user_id: u64) -> ServerResult<()> {
+    if deps.presence.connect(user_id) {
+        deps.profile_tree
+            .set_last_seen_logic(user_id, get_time_secs())
+            .await?;
+        publish_status(deps, user_id, false).await?;
+    }
+    Ok(())
+}
+
+/// Stops tracking an event stream of a user, telling guild members if they went offline.
+pub async fn stream_closed(deps: &Dependencies, user_id: u64) -> ServerResult<()> {
+    if deps.presence.disconnect(user_id) {
+        deps.profile_tree
+            .set_last_seen_logic(user_id, get_time_secs())
+            .await?;
+        publish_status(deps, user_id, false).await?;
+    }
+    Ok(())
+}
+
+/// Marks a user as active, telling guild members if they were idle.
+pub async fn user_active(deps: &Dependencies, user_id: u64) -> ServerResult<()> {
+    if deps.presence.touch(user_id) {
+        publish_status(deps, user_id, true).await?;
+    }
+    Ok(())
+}
+
+/// Tells everyone that can see a user about their status. Users with a
+/// custom status keep showing it while they are online, so changes between
+/// idle and online aren't sent for them.
+async fn publish_status(deps: &Dependencies, user_id: u64, idle_change: bool) -> ServerResult<()> {
+    let stored_status = deps
+        .profile_tree
+        .get_profile_logic(user_id)
+        .await?
+        .user_status;
+    if idle_change && is_custom_status(stored_status) {
+        return Ok(());
+    }
+
+    deps.event_bus.publish(DomainEvent::ProfileUpdated {
+        user_id,
+        new_username: None,
+        new_avatar: None,
+        new_status: Some(deps.presence.status(user_id, stored_status)),
+        new_is_bot: None,
+        seen_by: deps.chat_tree.calculate_users_seeing_user(user_id).await?,
+    });
+    Ok(())
+}
+
+/// Spawns a task that marks users as idle after they stop being active.
+pub fn spawn_idle_checker(deps: Arc<Dependencies>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(IDLE_CHECK_INTERVAL).await;
+
+            for user_id in deps.presence.mark_idle() {
+                if let Err(err) = publish_status(&deps, user_id, true).await {
+                    tracing::error!("couldn't publish idle status of user {}: {}", user_id, err);
+                }
+            }
+        }
+    });
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetPresenceRequest {
+    pub user_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetPresenceResponse {
+    pub user_status: i32,
+    /// When the user last opened or closed an event stream, in seconds since
+    /// the Unix epoch. Not set if they never did.
+    pub last_seen: Option<u64>,
+}
+
+/// Gets the status of a user, and when they were last seen.
+pub async fn handler(
+    svc: &ProfileServer,
+    _user_id: u64,
+    request: GetPresenceRequest,
+) -> ServerResult<GetPresenceResponse> {
+    let GetPresenceRequest { user_id } = request;
+
+    let profile_tree = &svc.deps.profile_tree;
+    let profile = profile_tree.get_profile_logic(user_id).await?;
+    let online = svc.deps.presence.is_online(user_id);
+
+    Ok(GetPresenceResponse {
+        user_status: svc.deps.presence.status(user_id, profile.user_status),
+        last_seen: if online {
+            Some(get_time_secs())
+        } else {
+            profile_tree.get_last_seen_logic(user_id).await?
+        },
+    })
+}
@@ -0,0 +1,47 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct SetUserBlockedRequest {
+    pub user_id: u64,
+    pub blocked: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetUserBlockedResponse {}
+
+/// Blocks or unblocks another user.
+pub async fn handler(
+    svc: &ProfileServer,
+    user_id: u64,
+    request: SetUserBlockedRequest,
+) -> ServerResult<SetUserBlockedResponse> {
+    let SetUserBlockedRequest {
+        user_id: target_id,
+        blocked,
+    } = request;
+
+    let profile_tree = &svc.deps.profile_tree;
+
+    if !blocked {
+        profile_tree.unblock_user_logic(user_id, target_id).await?;
+        return Ok(SetUserBlockedResponse {});
+    }
+
+    if user_id == target_id {
+        bail!(("h.cant-block-yourself", "you can't block yourself"));
+    }
+    profile_tree.does_user_exist(target_id).await?;
+    if profile_tree.get_blocked_users_logic(user_id).await?.len() >= MAX_BLOCKED_USERS {
+        bail!((
+            "h.too-many-blocked-users",
+            format!("you can't block more than {} users", MAX_BLOCKED_USERS)
+        ));
+    }
+    profile_tree
+        .block_users_logic(user_id, &[target_id])
+        .await?;
+
+    Ok(SetUserBlockedResponse {})
+}
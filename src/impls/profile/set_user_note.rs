@@ -0,0 +1,53 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct SetUserNoteRequest {
+    pub user_id: u64,
+    /// The note about the user. An empty note removes it.
+    pub note: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetUserNoteResponse {}
+
+/// Sets a private note about another user, only visible to the user who wrote it.
+pub async fn handler(
+    svc: &ProfileServer,
+    user_id: u64,
+    request: SetUserNoteRequest,
+) -> ServerResult<SetUserNoteResponse> {
+    let SetUserNoteRequest {
+        user_id: target_id,
+        note,
+    } = request;
+
+    if note.len() > MAX_USER_NOTE_LENGTH {
+        bail!((
+            "h.user-note-too-long",
+            format!("notes can be at most {} bytes long", MAX_USER_NOTE_LENGTH)
+        ));
+    }
+
+    let profile_tree = &svc.deps.profile_tree;
+
+    if !note.is_empty() {
+        profile_tree.does_user_exist(target_id).await?;
+        let is_new = !profile_tree
+            .contains_key(make_user_note_key(user_id, target_id))
+            .await?;
+        if is_new && profile_tree.get_user_notes_logic(user_id).await?.len() >= MAX_USER_NOTES {
+            bail!((
+                "h.too-many-user-notes",
+                format!("you can't have more than {} notes", MAX_USER_NOTES)
+            ));
+        }
+    }
+
+    profile_tree
+        .set_user_note_logic(user_id, target_id, &note)
+        .await?;
+
+    Ok(SetUserNoteResponse {})
+}
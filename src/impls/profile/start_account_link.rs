@@ -0,0 +1,77 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+use link_account::hash_link_secret;
+
+use crate::impls::gen_rand_inline_str;
+
+/// How long a link token can be used for, in seconds.
+const LINK_TOKEN_EXPIRE: u64 = 10 * 60;
+
+#[derive(Debug, Deserialize)]
+pub struct StartAccountLinkRequest {
+    /// Host of the homeserver the account to link is on.
+    pub host: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StartAccountLinkResponse {
+    /// Token to give to `profile/link-account` on the other homeserver,
+    /// logged in as the account to link.
+    pub token: String,
+    pub expires_at: u64,
+}
+
+/// Starts linking an account on another homeserver to this one. The link is
+/// made once the token is used from that account, proving the user owns both.
+pub async fn handler(
+    svc: &ProfileServer,
+    user_id: u64,
+    request: StartAccountLinkRequest,
+) -> ServerResult<StartAccountLinkResponse> {
+    let StartAccountLinkRequest { host } = request;
+
+    let deps = &svc.deps;
+    deps.key_manager
+        .as_ref()
+        .ok_or(ServerError::FederationDisabled)?;
+    if host == deps.config.host {
+        bail!((
+            "h.cant-link-local-account",
+            "only accounts on other homeservers can be linked"
+        ));
+    }
+    deps.is_host_allowed(&host)?;
+    if deps
+        .profile_tree
+        .local_to_foreign_id(user_id)
+        .await?
+        .is_some()
+    {
+        bail!((
+            "h.cant-link-foreign-account",
+            "accounts from other homeservers can't have accounts linked to them here"
+        ));
+    }
+
+    let secret = gen_rand_inline_str();
+    let expires_at = get_time_secs() + LINK_TOKEN_EXPIRE;
+    let value = [
+        expires_at.to_be_bytes().as_ref(),
+        &user_id.to_be_bytes(),
+        host.as_bytes(),
+    ]
+    .concat();
+    deps.profile_tree
+        .insert(
+            make_account_link_challenge_key(hash_link_secret(&secret).as_ref()),
+            value,
+        )
+        .await?;
+
+    Ok(StartAccountLinkResponse {
+        token: format!("{}/{}", deps.config.host, secret),
+        expires_at,
+    })
+}
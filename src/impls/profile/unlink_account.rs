@@ -0,0 +1,41 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct UnlinkAccountRequest {
+    /// Host of the homeserver the linked account is on.
+    pub host: String,
+    /// ID of the linked account there.
+    pub user_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnlinkAccountResponse {}
+
+/// Unlinks an account on another homeserver. The link is only removed here,
+/// the other homeserver has to be asked to remove it too.
+pub async fn handler(
+    svc: &ProfileServer,
+    user_id: u64,
+    request: UnlinkAccountRequest,
+) -> ServerResult<UnlinkAccountResponse> {
+    let UnlinkAccountRequest {
+        host,
+        user_id: foreign_id,
+    } = request;
+
+    let was_linked = svc
+        .deps
+        .profile_tree
+        .unlink_account_logic(user_id, foreign_id, &host)
+        .await?;
+    if !was_linked {
+        bail!((
+            "h.account-not-linked",
+            format!("user {} on {} isn't linked to you", foreign_id, host)
+        ));
+    }
+
+    Ok(UnlinkAccountResponse {})
+}
@@ -25,23 +25,18 @@ pub async fn handler(
         )
         .await?;
 
-    svc.send_event_through_chan(
-        EventSub::Homeserver,
-        stream_event::Event::ProfileUpdated(ProfileUpdated {
-            user_id,
-            new_username: new_user_name,
-            new_avatar: new_user_avatar,
-            new_status: new_user_status,
-            new_is_bot,
-        }),
-        None,
-        EventContext::new(
-            svc.deps
-                .chat_tree
-                .calculate_users_seeing_user(user_id)
-                .await?,
-        ),
-    );
+    svc.deps.event_bus.publish(DomainEvent::ProfileUpdated {
+        user_id,
+        new_username: new_user_name,
+        new_avatar: new_user_avatar,
+        new_status: new_user_status,
+        new_is_bot,
+        seen_by: svc
+            .deps
+            .chat_tree
+            .calculate_users_seeing_user(user_id)
+            .await?,
+    });
 
     Ok((UpdateProfileResponse {}).into_response())
 }
@@ -0,0 +1,260 @@
+//! Experimental replication of the database to a standby instance.
+//!
+//! The primary logs its committed writes in the [`OpLog`] of its database.
+//! A standby first loads a snapshot of every tree from
+//! `/_scherzo/replication/snapshot`, then long-polls
+//! `/_scherzo/replication/ops` for the writes made after it, and applies them
+//! in order. Both are authenticated with the replication secret. When the
+//! primary doesn't have the writes it needs anymore, for example because it
+//! restarted, the standby loads a snapshot again.
+//!
+//! A standby doesn't serve anything. It is promoted by creating the promote
+//! trigger file, after which it stops following the primary, and serves as a
+//! primary once it is restarted.
+//!
+//! [`OpLog`]: crate::db::op_log::OpLog
+
+use std::{collections::HashMap, path::Path, time::Duration};
+
+use hrpc::client::transport::http::hyper::{http_client, HttpClient};
+use hyper::{body, http, Body, StatusCode, Uri};
+
+use crate::{
+    config::ReplicationConfig,
+    db::{
+        dump,
+        migration::get_db_version,
+        op_log::{self, Op},
+        Batch, Db, DbResult, Tree, TREES,
+    },
+};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Key in the `version` tree, set when a standby is promoted.
+const PROMOTED_KEY: &[u8] = b"promoted";
+/// Most writes the primary sends in one response.
+pub const MAX_OPS_PER_RESPONSE: usize = 1000;
+/// How long the primary waits for new writes before responding without any.
+pub const OPS_WAIT: Duration = Duration::from_secs(20);
+/// How long a standby waits before trying again after replicating failed.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+/// How often a standby checks if it should be promoted.
+const PROMOTE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Starts logging writes for standbys, if this instance is a primary.
+pub fn setup_primary(db: &Db, config: Option<&ReplicationConfig>) {
+    if let Some(config) = config.filter(|config| config.primary_url.is_none()) {
+        db.op_log().enable(config.op_log_capacity);
+        tracing::info!("logging database writes for standbys");
+    }
+}
+
+/// Gets the replication config if this instance is a standby that wasn't
+/// promoted yet.
+pub async fn standby_config(
+    db: &Db,
+    config: Option<&ReplicationConfig>,
+) -> DbResult<Option<ReplicationConfig>> {
+    let config = match config {
+        Some(config) if config.primary_url.is_some() => config,
+        _ => return Ok(None),
+    };
+    let version_tree = db.open_tree(b"version").await?;
+    if version_tree.contains_key(PROMOTED_KEY).await? {
+        tracing::warn!(
+            "this standby was promoted, so it is serving as the primary; remove `primary_url` from the replication config so standbys can follow it"
+        );
+        return Ok(None);
+    }
+    Ok(Some(config.clone()))
+}
+
+struct Standby {
+    db: Db,
+    http: HttpClient,
+    primary_url: String,
+    secret: String,
+    /// Epoch of the primary's log and the last write applied from it. `None`
+    /// until a snapshot is loaded.
+    position: Option<(u64, u64)>,
+    trees: HashMap<Vec<u8>, Tree>,
+}
+
+/// Follows the primary until this standby is promoted.
+pub async fn follow(db: Db, config: ReplicationConfig) {
+    let primary_url = config.primary_url.clone().unwrap_or_default();
+    tracing::info!("running as a standby of {}", primary_url);
+    let mut standby = Standby {
+        db,
+        http: http_client(&mut hyper::Client::builder()),
+        primary_url: primary_url.trim_end_matches('/').to_string(),
+        secret: config.secret.clone(),
+        position: None,
+        trees: HashMap::new(),
+    };
+    let trigger_file = config.promote_trigger_file.as_path();
+
+    loop {
+        if trigger_file.exists() {
+            break;
+        }
+        let res = match standby.position {
+            None => standby.load_snapshot().await,
+            Some((epoch, after)) => {
+                let fetched = tokio::select! {
+                    res = standby.fetch_ops(epoch, after) => res,
+                    _ = wait_for_file(trigger_file) => break,
+                };
+                match fetched {
+                    Ok(Some(ops)) => standby.apply_ops(ops).await,
+                    Ok(None) => {
+                        tracing::warn!(
+                            "primary doesn't have the writes after {} anymore, loading a snapshot again",
+                            after
+                        );
+                        standby.position = None;
+                        Ok(())
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+        };
+        if let Err(err) = res {
+            tracing::error!("couldn't replicate from {}: {}", primary_url, err);
+            tokio::select! {
+                _ = tokio::time::sleep(RETRY_DELAY) => {}
+                _ = wait_for_file(trigger_file) => break,
+            }
+        }
+    }
+
+    if standby.position.is_none() {
+        tracing::warn!(
+            "promoting before a snapshot of the primary was loaded, the database may be incomplete"
+        );
+    }
+    match promote(&standby.db).await {
+        Ok(()) => {
+            let _ = std::fs::remove_file(trigger_file);
+            tracing::warn!("promoted this standby, restart it to serve as the primary");
+        }
+        Err(err) => tracing::error!("couldn't promote this standby: {}", err),
+    }
+}
+
+async fn wait_for_file(path: &Path) {
+    while !path.exists() {
+        tokio::time::sleep(PROMOTE_CHECK_INTERVAL).await;
+    }
+}
+
+async fn promote(db: &Db) -> DbResult<()> {
+    let version_tree = db.open_tree(b"version").await?;
+    version_tree.insert(PROMOTED_KEY, &[]).await?;
+    db.flush().await
+}
+
+impl Standby {
+    async fn get(&self, path_and_query: &str) -> Result<(StatusCode, body::Bytes), BoxError> {
+        let uri: Uri = format!("{}{}", self.primary_url, path_and_query).parse()?;
+        let request = http::Request::builder()
+            .method(http::Method::GET)
+            .uri(uri)
+            .header(http::header::AUTHORIZATION, self.secret.as_str())
+            .body(Body::empty())?;
+        let response = self.http.request(request).await?;
+        let status = response.status();
+        let body = body::to_bytes(response.into_body()).await?;
+        Ok((status, body))
+    }
+
+    /// Replaces every tree with a snapshot from the primary.
+    async fn load_snapshot(&mut self) -> Result<(), BoxError> {
+        let (status, raw) = self.get("/_scherzo/replication/snapshot").await?;
+        if status != StatusCode::OK {
+            return Err(format!("primary responded to snapshot request with {}", status).into());
+        }
+        let mut reader = raw.as_ref();
+        let epoch = dump::read_u64(&mut reader)?;
+        let seq = dump::read_u64(&mut reader)?;
+        let snapshot_version = dump::read_header(&mut reader)?;
+        let (db_version, _) = get_db_version(&self.db).await?;
+        if snapshot_version != db_version {
+            return Err(format!(
+                "primary's database is at version {}, but this one is at version {}; both must run the same scherzo version",
+                snapshot_version, db_version
+            )
+            .into());
+        }
+
+        for name in TREES {
+            let tree = self.tree(name).await?;
+            let mut batch = Batch::default();
+            for res in tree.iter().await {
+                let (key, _) = res?;
+                batch.remove(key);
+            }
+            tree.apply_batch(batch).await?;
+        }
+        let stats = dump::read_trees(&self.db, &mut reader).await?;
+        // the primary may have been promoted itself
+        self.tree(b"version").await?.remove(PROMOTED_KEY).await?;
+
+        self.position = Some((epoch, seq));
+        tracing::info!(
+            "loaded snapshot of the primary with {} entries",
+            stats.trees.iter().map(|(_, count)| count).sum::<u64>()
+        );
+        Ok(())
+    }
+
+    /// Gets the writes after `after`, or `None` if the primary doesn't have
+    /// them anymore.
+    async fn fetch_ops(&self, epoch: u64, after: u64) -> Result<Option<Vec<(u64, Op)>>, BoxError> {
+        let (status, raw) = self
+            .get(&format!(
+                "/_scherzo/replication/ops?epoch={}&after={}",
+                epoch, after
+            ))
+            .await?;
+        match status {
+            StatusCode::OK => {}
+            StatusCode::CONFLICT => return Ok(None),
+            status => {
+                return Err(format!("primary responded to ops request with {}", status).into())
+            }
+        }
+        let (ops_epoch, ops) = op_log::decode_ops(&raw)?;
+        Ok((ops_epoch == epoch).then(|| ops))
+    }
+
+    async fn apply_ops(&mut self, ops: Vec<(u64, Op)>) -> Result<(), BoxError> {
+        for (seq, mut op) in ops {
+            let (epoch, last_seq) = self.position.expect("a snapshot must be loaded");
+            if seq != last_seq + 1 {
+                self.position = None;
+                return Err(format!("expected write {}, got {}", last_seq + 1, seq).into());
+            }
+            if op.tree == b"version" {
+                op.writes.retain(|(key, _)| key.as_ref() != PROMOTED_KEY);
+            }
+            let tree = self.tree(&op.tree).await?;
+            tree.apply_batch(op.into_batch()).await?;
+            self.position = Some((epoch, seq));
+        }
+        Ok(())
+    }
+
+    async fn tree(&mut self, name: &[u8]) -> Result<Tree, BoxError> {
+        if let Some(tree) = self.trees.get(name) {
+            return Ok(tree.clone());
+        }
+        if !TREES.contains(&name) {
+            return Err(format!("unknown tree {}", String::from_utf8_lossy(name)).into());
+        }
+        let tree = self.db.open_tree(name).await?;
+        self.trees.insert(name.to_vec(), tree.clone());
+        Ok(tree)
+    }
+}
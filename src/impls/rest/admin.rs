@@ -0,0 +1,516 @@
+//! Moderation endpoints for homeserver admins, under `/_scherzo/admin/`.
+//!
+//! These can be used either by homeserver admins with their session, or by
+//! dashboards and bots with the admin token from config.
+
+use hyper::HeaderMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::audit::AuditAction,
+    impls::{
+        audit,
+        auth::AuthTree,
+        chat::{
+            guilds::delete_guild::delete_guild_logic,
+            messages::delete_message::delete_message_logic, moderation::ban_user::ban_user_logic,
+            ChatServer, ChatTree,
+        },
+        gen_rand_str, retention,
+        sync::{
+            dead_letter::{self, DeadLetterInfo, DeadLetterReport, DeadLetterSelection},
+            replay::{self, ReplayFilter, ReplayReport},
+            trust,
+        },
+    },
+};
+
+use super::*;
+
+/// Endpoints that can be used with the admin token.
+pub const ENDPOINTS: [&str; 18] = [
+    "admin/ban-user",
+    "admin/unban-user",
+    "admin/delete-guild",
+    "admin/purge-messages",
+    "admin/generate-registration-token",
+    "admin/list-guilds",
+    "admin/reset-password",
+    "admin/create-federation-invite",
+    "admin/accept-federation-invite",
+    "admin/trusted-hosts",
+    "admin/untrust-host",
+    "admin/replay-events",
+    "admin/dead-letters",
+    "admin/requeue-dead-letters",
+    "admin/discard-dead-letters",
+    "admin/guild-retention",
+    "admin/set-guild-retention",
+    "admin/prune-guild",
+];
+
+/// Maximum number of messages that can be purged in one request.
+const MAX_PURGE_COUNT: u64 = 1000;
+
+/// Checks if the request has the admin token from config.
+pub fn has_admin_token(deps: &Dependencies, headers: &HeaderMap) -> bool {
+    let token = match deps.config.policy.admin_token.as_deref() {
+        Some(token) if token.is_empty().not() => token,
+        _ => return false,
+    };
+    headers.get(header::AUTHORIZATION).map_or(false, |value| {
+        constant_time_eq(value.as_bytes(), token.as_bytes())
+    })
+}
+
+pub(super) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BanUserRequest {
+    pub guild_id: u64,
+    pub user_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BanUserResponse {}
+
+/// Bans a user from a guild, without needing to be in the guild.
+pub async fn ban_user(svc: &ChatServer, request: BanUserRequest) -> ServerResult<BanUserResponse> {
+    let BanUserRequest { guild_id, user_id } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+    chat_tree.is_user_in_guild(guild_id, user_id).await?;
+
+    ban_user_logic(svc, guild_id, 0, user_id, String::new(), None).await?;
+
+    Ok(BanUserResponse {})
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnbanUserRequest {
+    pub guild_id: u64,
+    pub user_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnbanUserResponse {}
+
+/// Unbans a user from a guild, without needing to be in the guild.
+pub async fn unban_user(
+    svc: &ChatServer,
+    request: UnbanUserRequest,
+) -> ServerResult<UnbanUserResponse> {
+    let UnbanUserRequest { guild_id, user_id } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+    chat_tree.does_guild_exist(guild_id).await?;
+    chat_tree.unban_user_logic(guild_id, user_id).await?;
+    audit::record(
+        &svc.deps,
+        guild_id,
+        0,
+        AuditAction::UserUnbanned { user_id },
+    )
+    .await?;
+
+    Ok(UnbanUserResponse {})
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteGuildRequest {
+    pub guild_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeleteGuildResponse {}
+
+/// Deletes a guild, without needing to be in the guild.
+pub async fn delete_guild(
+    svc: &ChatServer,
+    request: DeleteGuildRequest,
+) -> ServerResult<DeleteGuildResponse> {
+    let DeleteGuildRequest { guild_id } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+    chat_tree.does_guild_exist(guild_id).await?;
+    if chat_tree
+        .admin_guild_keys
+        .get()
+        .map_or(false, |keys| keys.guild_id == guild_id)
+    {
+        bail!((
+            "h.cant-delete-admin-guild",
+            "the admin guild can't be deleted"
+        ));
+    }
+
+    delete_guild_logic(svc, guild_id).await?;
+
+    Ok(DeleteGuildResponse {})
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PurgeMessagesRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    /// Only delete messages sent by this user.
+    #[serde(default)]
+    pub author_id: Option<u64>,
+    /// How many of the latest messages to delete, at most 1000.
+    pub count: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PurgeMessagesResponse {
+    pub deleted_message_ids: Vec<u64>,
+}
+
+/// Deletes the latest messages of a channel, optionally only the ones sent by a user.
+pub async fn purge_messages(
+    svc: &ChatServer,
+    request: PurgeMessagesRequest,
+) -> ServerResult<PurgeMessagesResponse> {
+    let PurgeMessagesRequest {
+        guild_id,
+        channel_id,
+        author_id,
+        count,
+    } = request;
+
+    if count > MAX_PURGE_COUNT {
+        bail!((
+            "h.purge-count-too-big",
+            format!("at most {} messages can be purged at once", MAX_PURGE_COUNT)
+        ));
+    }
+
+    let chat_tree = &svc.deps.chat_tree;
+    chat_tree.does_channel_exist(guild_id, channel_id).await?;
+
+    let messages = chat_tree
+        .get_latest_messages_logic(guild_id, channel_id, author_id, count)
+        .await?;
+    let mut deleted_message_ids = Vec::with_capacity(messages.len());
+    for (message_id, message) in messages {
+        delete_message_logic(svc, guild_id, channel_id, message_id, message).await?;
+        deleted_message_ids.push(message_id);
+    }
+
+    Ok(PurgeMessagesResponse {
+        deleted_message_ids,
+    })
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GenerateRegistrationTokenRequest {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GenerateRegistrationTokenResponse {
+    pub token: String,
+}
+
+/// Generates a token that can be used to register when registration is disabled.
+pub async fn generate_registration_token(
+    svc: &ChatServer,
+    _request: GenerateRegistrationTokenRequest,
+) -> ServerResult<GenerateRegistrationTokenResponse> {
+    let token = svc.deps.auth_tree.put_rand_reg_token().await?;
+
+    Ok(GenerateRegistrationTokenResponse {
+        token: token.into(),
+    })
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListGuildsRequest {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GuildEntry {
+    pub guild_id: u64,
+    pub name: String,
+    pub owner_ids: Vec<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListGuildsResponse {
+    pub guilds: Vec<GuildEntry>,
+}
+
+/// Lists every guild on this homeserver.
+pub async fn list_guilds(
+    svc: &ChatServer,
+    _request: ListGuildsRequest,
+) -> ServerResult<ListGuildsResponse> {
+    list_guilds_logic(&svc.deps.chat_tree).await
+}
+
+pub async fn list_guilds_logic(chat_tree: &ChatTree) -> ServerResult<ListGuildsResponse> {
+    let mut guilds = chat_tree
+        .get_all_guilds_logic()
+        .await?
+        .into_iter()
+        .map(|(guild_id, guild)| GuildEntry {
+            guild_id,
+            name: guild.name,
+            owner_ids: guild.owner_ids,
+        })
+        .collect::<Vec<_>>();
+    guilds.sort_unstable_by_key(|guild| guild.guild_id);
+
+    Ok(ListGuildsResponse { guilds })
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResetPasswordRequest {
+    /// ID or email of the user.
+    pub user: String,
+    /// The new password. One is generated if this isn't set.
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResetPasswordResponse {
+    pub user_id: u64,
+    pub password: String,
+}
+
+/// Sets a new password for a user, logging them out everywhere.
+pub async fn reset_password(
+    svc: &ChatServer,
+    request: ResetPasswordRequest,
+) -> ServerResult<ResetPasswordResponse> {
+    let auth_tree = &svc.deps.auth_tree;
+    let user_id = find_user(auth_tree, &request.user).await?;
+    for (token, _) in auth_tree.get_sessions_logic(user_id).await? {
+        svc.deps.valid_sessions.remove(token.as_str());
+    }
+
+    reset_password_logic(auth_tree, request).await
+}
+
+pub async fn reset_password_logic(
+    auth_tree: &AuthTree,
+    request: ResetPasswordRequest,
+) -> ServerResult<ResetPasswordResponse> {
+    let ResetPasswordRequest { user, password } = request;
+
+    let user_id = find_user(auth_tree, &user).await?;
+    let password = password.unwrap_or_else(|| gen_rand_str::<16>().to_string());
+    if password.is_empty() {
+        bail!(("h.invalid-password", "password can't be empty"));
+    }
+    auth_tree
+        .reset_password_logic(user_id, password.as_bytes())
+        .await?;
+
+    Ok(ResetPasswordResponse { user_id, password })
+}
+
+/// Gets the ID of a user from their ID or their email.
+async fn find_user(auth_tree: &AuthTree, user: &str) -> ServerResult<u64> {
+    if let Ok(user_id) = user.parse::<u64>() {
+        return Ok(user_id);
+    }
+    match auth_tree.get_user_id_by_email(user).await? {
+        Some(user_id) => Ok(user_id),
+        None => bail!(("h.no-such-user", "no user is registered with this email")),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateFederationInviteRequest {
+    /// How long the invite is valid for, in seconds. Defaults to a day.
+    #[serde(default)]
+    pub valid_for: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreateFederationInviteResponse {
+    pub token: String,
+    pub expires_at: u64,
+}
+
+/// Creates a token that an admin of another server can use to establish federation with this server.
+pub async fn create_federation_invite(
+    svc: &ChatServer,
+    request: CreateFederationInviteRequest,
+) -> ServerResult<CreateFederationInviteResponse> {
+    let (token, expires_at) = trust::create_invite(&svc.deps, request.valid_for).await?;
+
+    Ok(CreateFederationInviteResponse { token, expires_at })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AcceptFederationInviteRequest {
+    pub token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AcceptFederationInviteResponse {
+    pub host: String,
+}
+
+/// Redeems a federation invite token created on another server.
+pub async fn accept_federation_invite(
+    svc: &ChatServer,
+    request: AcceptFederationInviteRequest,
+) -> ServerResult<AcceptFederationInviteResponse> {
+    let host = trust::accept_invite(&svc.deps, &request.token).await?;
+
+    Ok(AcceptFederationInviteResponse { host })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetTrustedHostsRequest {}
+
+#[derive(Debug, Serialize)]
+pub struct GetTrustedHostsResponse {
+    pub hosts: Vec<String>,
+}
+
+/// Lists hosts trusted by redeeming federation invites.
+pub async fn get_trusted_hosts(
+    svc: &ChatServer,
+    _request: GetTrustedHostsRequest,
+) -> ServerResult<GetTrustedHostsResponse> {
+    let mut hosts = svc
+        .deps
+        .trusted_hosts
+        .iter()
+        .map(|host| host.to_string())
+        .collect::<Vec<_>>();
+    hosts.sort_unstable();
+
+    Ok(GetTrustedHostsResponse { hosts })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UntrustHostRequest {
+    pub host: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UntrustHostResponse {}
+
+/// Stops trusting a host that was trusted by redeeming a federation invite.
+/// Hosts in the federation config are not affected.
+pub async fn untrust_host(
+    svc: &ChatServer,
+    request: UntrustHostRequest,
+) -> ServerResult<UntrustHostResponse> {
+    trust::untrust_host(&svc.deps, &request.host).await?;
+
+    Ok(UntrustHostResponse {})
+}
+
+/// Replays logged federation events against the database. Only reports what
+/// would change, unless `apply` is set in the filter.
+pub async fn replay_events(svc: &ChatServer, request: ReplayFilter) -> ServerResult<ReplayReport> {
+    replay::replay_events(&svc.deps, request).await
+}
+
+/// Lists federation events that couldn't be delivered, or only the selected ones.
+pub async fn get_dead_letters(
+    svc: &ChatServer,
+    request: DeadLetterSelection,
+) -> ServerResult<Vec<DeadLetterInfo>> {
+    dead_letter::list_dead_letters(&svc.deps, request).await
+}
+
+/// Sends the selected undelivered federation events to their hosts again.
+pub async fn requeue_dead_letters(
+    svc: &ChatServer,
+    request: DeadLetterSelection,
+) -> ServerResult<DeadLetterReport> {
+    dead_letter::requeue_dead_letters(&svc.deps, request).await
+}
+
+/// Removes the selected undelivered federation events for good.
+pub async fn discard_dead_letters(
+    svc: &ChatServer,
+    request: DeadLetterSelection,
+) -> ServerResult<DeadLetterReport> {
+    dead_letter::discard_dead_letters(&svc.deps, request).await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetGuildRetentionRequest {
+    pub guild_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetGuildRetentionResponse {
+    /// Retention set for the guild itself, 0 if messages are kept forever.
+    pub guild_days: Option<u64>,
+    /// Retention that applies to the guild, `None` if messages are kept forever.
+    pub effective_days: Option<u64>,
+}
+
+/// Gets how long messages are kept in a guild.
+pub async fn get_guild_retention(
+    svc: &ChatServer,
+    request: GetGuildRetentionRequest,
+) -> ServerResult<GetGuildRetentionResponse> {
+    let GetGuildRetentionRequest { guild_id } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+    chat_tree.does_guild_exist(guild_id).await?;
+
+    Ok(GetGuildRetentionResponse {
+        guild_days: chat_tree.get_guild_retention_logic(guild_id).await?,
+        effective_days: retention::guild_retention_days(&svc.deps, guild_id).await?,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetGuildRetentionRequest {
+    pub guild_id: u64,
+    /// How many days messages are kept, 0 to keep them forever. The server
+    /// wide retention is used if this isn't set.
+    #[serde(default)]
+    pub days: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetGuildRetentionResponse {}
+
+/// Sets how long messages are kept in a guild. Old messages are deleted the
+/// next time messages are pruned.
+pub async fn set_guild_retention(
+    svc: &ChatServer,
+    request: SetGuildRetentionRequest,
+) -> ServerResult<SetGuildRetentionResponse> {
+    let SetGuildRetentionRequest { guild_id, days } = request;
+
+    let chat_tree = &svc.deps.chat_tree;
+    chat_tree.does_guild_exist(guild_id).await?;
+    chat_tree.set_guild_retention_logic(guild_id, days).await?;
+
+    Ok(SetGuildRetentionResponse {})
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PruneGuildRequest {
+    pub guild_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PruneGuildResponse {
+    pub deleted_messages: u64,
+}
+
+/// Deletes the messages of a guild that are older than its retention right away.
+pub async fn prune_guild(
+    svc: &ChatServer,
+    request: PruneGuildRequest,
+) -> ServerResult<PruneGuildResponse> {
+    let PruneGuildRequest { guild_id } = request;
+
+    svc.deps.chat_tree.does_guild_exist(guild_id).await?;
+    let deleted_messages = retention::prune_guild(&svc.deps, guild_id).await?;
+
+    Ok(PruneGuildResponse { deleted_messages })
+}
@@ -0,0 +1,854 @@
+use std::convert::Infallible;
+
+use hrpc::{
+    exports::futures_util::future::BoxFuture, proto::HrpcErrorIdentifier,
+    server::transport::http::HttpResponse,
+};
+use hyper::body::HttpBody;
+use serde::{de::DeserializeOwned, Serialize};
+use tower::Service;
+
+#[cfg(feature = "voice")]
+use crate::impls::voice::{reconnect, VoiceServer};
+use crate::{
+    impls::{
+        auth::{get_session_token, refresh_tokens, registration_challenge, sessions},
+        chat::{
+            channels::{
+                get_channel_role_gate, get_channel_topic_history, get_disappearing_messages,
+                get_gallery, get_typing_users, get_voice_stats, rebroadcast_sticky_message,
+                reorder_channels, set_channel_role_gate, set_channel_topic,
+                set_disappearing_messages, set_gallery_mode, set_sticky_message,
+            },
+            guilds::{
+                add_guild_emoji, add_guild_integration, delete_guild_emoji, get_audit_log,
+                get_guild_directory, get_guild_integrations, get_guild_locale, get_guild_theme,
+                get_guild_usage, get_link_scanning, get_member_chunk, get_member_list,
+                get_member_list_updates, get_members, get_screening, get_screening_applications,
+                get_text_macros, get_widget, initial_sync, list_guild_usage, preview_guild_theme,
+                remove_guild_integration, review_screening_application, search_members,
+                set_guild_public, set_link_scanning, set_screening, set_text_macro, set_widget,
+                submit_screening, subscribe_to_member_list, update_guild_locale,
+                update_guild_theme,
+            },
+            invites::{
+                get_invite_role_grant, get_invite_stats, set_invite_expiry, set_invite_role_grant,
+            },
+            messages::{
+                acknowledge_message, bind_reaction_role, cancel_scheduled_message, delete_messages,
+                get_acknowledgements, get_messages_after, get_pending_acknowledgements,
+                get_quarantined_messages, get_reaction_roles, get_reactors, get_scheduled_messages,
+                mark_messages_read, redact_attachment, require_acknowledgement,
+                review_quarantined_message, schedule_message, search_messages,
+                unbind_reaction_role,
+            },
+            moderation::{
+                ban_user_with_reason, get_ban_list_subscriptions, get_bans,
+                get_domain_blocklist_stats, subscribe_ban_list, unsubscribe_ban_list,
+            },
+            permissions::{
+                apply_permission_preset, bulk_give_user_roles, bulk_manage_role_members,
+                get_permission_presets, get_role_member_caps, set_channel_permission_sync,
+                set_role_member_cap, simulate_permission_change,
+            },
+            reports::{escalate_report, get_report_queue, update_report},
+            stage::{
+                approve_speaker, get_stage, invite_speaker, raise_hand, remove_speaker,
+                respond_to_speaker_invite, set_stage,
+            },
+            threads::{
+                get_thread, get_thread_messages, send_thread_message, set_thread_subscription,
+                start_thread,
+            },
+            ChatServer,
+        },
+        diagnostics,
+        emote::{
+            get_emote_aliases, get_equipped_packs, resolve_emotes, set_emote_alias,
+            set_emote_pack_priority, EmoteServer,
+        },
+        maintenance,
+        profile::{
+            export_settings, get_blocked_users, get_linked_accounts, get_user_notes,
+            import_blocked_users, import_settings, link_account, presence, set_user_blocked,
+            set_user_note, start_account_link, unlink_account, ProfileServer,
+        },
+        sync::trust,
+    },
+    rest_error_response,
+};
+
+use super::{admin, *};
+
+/// Maximum length of a request body, in bytes.
+const MAX_BODY_LENGTH: usize = 64 * 1024;
+
+pub fn handler(
+    deps: Arc<Dependencies>,
+    chat: ChatServer,
+    #[cfg(feature = "voice")] voice: VoiceServer,
+) -> RateLimit<ApiService> {
+    ServiceBuilder::new()
+        .rate_limit(10, Duration::from_secs(5))
+        .service(ApiService {
+            emote: EmoteServer::new(deps.clone()),
+            profile: ProfileServer::new(deps.clone()),
+            deps,
+            chat,
+            #[cfg(feature = "voice")]
+            voice,
+        })
+}
+
+/// Serves scherzo specific endpoints that aren't covered by the protocol.
+///
+/// All endpoints live under `/_scherzo/`, take a JSON body with a `POST`
+/// request and reply with JSON.
+pub struct ApiService {
+    deps: Arc<Dependencies>,
+    chat: ChatServer,
+    emote: EmoteServer,
+    profile: ProfileServer,
+    #[cfg(feature = "voice")]
+    voice: VoiceServer,
+}
+
+impl Service<HttpRequest> for ApiService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+        let chat = self.chat.clone();
+        let emote = self.emote.clone();
+        let profile = self.profile.clone();
+        #[cfg(feature = "voice")]
+        let voice = self.voice.clone();
+
+        Box::pin(async move {
+            if request.method() != Method::POST {
+                return Ok(rest_error_response(
+                    "method must be POST".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let (parts, body) = request.into_parts();
+            let path = parts.uri.path().trim_start_matches("/_scherzo/");
+
+            // Endpoints that don't need authentication
+            if path == "chat/preview-guild-theme" {
+                let response = call(body, |req| preview_guild_theme::handler(&chat, req)).await;
+                return Ok(response);
+            }
+            if path == "auth/registration-challenge" {
+                let response = call(body, |req| {
+                    registration_challenge::get_registration_challenge_handler(&deps, req)
+                })
+                .await;
+                return Ok(response);
+            }
+            // The session token might have stopped working, so the refresh token is used instead
+            if path == "auth/refresh" {
+                let device = sessions::get_device_from_headers(&parts.headers);
+                let response = call(body, |req| {
+                    refresh_tokens::refresh_handler(&deps, device, req)
+                })
+                .await;
+                return Ok(response);
+            }
+            // Other servers authenticate by signing the request with their federation key
+            if path == "federation/redeem-invite" {
+                let response = call(body, |req| trust::redeem_invite_handler(&deps, req)).await;
+                return Ok(response);
+            }
+            if path == "federation/link-account" {
+                let response = call(body, |req| {
+                    link_account::redeem_link_token_handler(&deps, req)
+                })
+                .await;
+                return Ok(response);
+            }
+
+            // Admin endpoints can also be used with the admin token from config
+            let is_admin_endpoint = path.starts_with("admin/");
+            if is_admin_endpoint && admin::has_admin_token(&deps, &parts.headers) {
+                let response = admin_call(&chat, path, body).await.unwrap_or_else(|| {
+                    rest_error_response("no such endpoint".to_string(), StatusCode::NOT_FOUND)
+                });
+                return Ok(response);
+            }
+
+            let user_id = match deps.valid_sessions.auth_header_map(&parts.headers) {
+                Ok(user_id) => user_id,
+                Err(err) => return Ok(err.into_rest_http_response()),
+            };
+
+            if is_admin_endpoint && admin::ENDPOINTS.contains(&path) {
+                if let Err(err) = chat.deps.chat_tree.check_homeserver_admin(user_id).await {
+                    return Ok(hrpc_error_response(err));
+                }
+                let response = admin_call(&chat, path, body).await.unwrap_or_else(|| {
+                    rest_error_response("no such endpoint".to_string(), StatusCode::NOT_FOUND)
+                });
+                return Ok(response);
+            }
+
+            let response = match path {
+                "chat/set-channel-permission-sync" => {
+                    call(body, |req| {
+                        set_channel_permission_sync::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/simulate-permission-change" => {
+                    call(body, |req| {
+                        simulate_permission_change::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/permission-presets" => {
+                    call(body, |req| {
+                        get_permission_presets::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/apply-permission-preset" => {
+                    call(body, |req| {
+                        apply_permission_preset::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/guild-theme" => {
+                    call(body, |req| get_guild_theme::handler(&chat, user_id, req)).await
+                }
+                "chat/update-guild-theme" => {
+                    call(body, |req| update_guild_theme::handler(&chat, user_id, req)).await
+                }
+                "chat/guild-locale" => {
+                    call(body, |req| get_guild_locale::handler(&chat, user_id, req)).await
+                }
+                "chat/update-guild-locale" => {
+                    call(body, |req| {
+                        update_guild_locale::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/set-channel-topic" => {
+                    call(body, |req| set_channel_topic::handler(&chat, user_id, req)).await
+                }
+                "chat/set-sticky-message" => {
+                    call(body, |req| set_sticky_message::handler(&chat, user_id, req)).await
+                }
+                "chat/rebroadcast-sticky-message" => {
+                    call(body, |req| {
+                        rebroadcast_sticky_message::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/disappearing-messages" => {
+                    call(body, |req| {
+                        get_disappearing_messages::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/set-disappearing-messages" => {
+                    call(body, |req| {
+                        set_disappearing_messages::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/reorder-channels" => {
+                    call(body, |req| reorder_channels::handler(&chat, user_id, req)).await
+                }
+                "chat/mark-messages-read" => {
+                    call(body, |req| mark_messages_read::handler(&chat, user_id, req)).await
+                }
+                "chat/channel-topic-history" => {
+                    call(body, |req| {
+                        get_channel_topic_history::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/schedule-message" => {
+                    call(body, |req| schedule_message::handler(&chat, user_id, req)).await
+                }
+                "chat/scheduled-messages" => {
+                    call(body, |req| {
+                        get_scheduled_messages::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/cancel-scheduled-message" => {
+                    call(body, |req| {
+                        cancel_scheduled_message::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/search-messages" => {
+                    call(body, |req| search_messages::handler(&chat, user_id, req)).await
+                }
+                "chat/delete-messages" => {
+                    call(body, |req| delete_messages::handler(&chat, user_id, req)).await
+                }
+                "chat/reactors" => {
+                    call(body, |req| get_reactors::handler(&chat, user_id, req)).await
+                }
+                "chat/reaction-roles" => {
+                    call(body, |req| get_reaction_roles::handler(&chat, user_id, req)).await
+                }
+                "chat/bind-reaction-role" => {
+                    call(body, |req| bind_reaction_role::handler(&chat, user_id, req)).await
+                }
+                "chat/unbind-reaction-role" => {
+                    call(body, |req| {
+                        unbind_reaction_role::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/thread" => call(body, |req| get_thread::handler(&chat, user_id, req)).await,
+                "chat/start-thread" => {
+                    call(body, |req| start_thread::handler(&chat, user_id, req)).await
+                }
+                "chat/thread-messages" => {
+                    call(body, |req| {
+                        get_thread_messages::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/send-thread-message" => {
+                    call(body, |req| {
+                        send_thread_message::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/set-thread-subscription" => {
+                    call(body, |req| {
+                        set_thread_subscription::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/stage" => call(body, |req| get_stage::handler(&chat, user_id, req)).await,
+                "chat/set-stage" => call(body, |req| set_stage::handler(&chat, user_id, req)).await,
+                "chat/stage/raise-hand" => {
+                    call(body, |req| raise_hand::handler(&chat, user_id, req)).await
+                }
+                "chat/stage/invite-speaker" => {
+                    call(body, |req| invite_speaker::handler(&chat, user_id, req)).await
+                }
+                "chat/stage/respond-to-speaker-invite" => {
+                    call(body, |req| {
+                        respond_to_speaker_invite::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/stage/approve-speaker" => {
+                    call(body, |req| approve_speaker::handler(&chat, user_id, req)).await
+                }
+                "chat/stage/remove-speaker" => {
+                    call(body, |req| remove_speaker::handler(&chat, user_id, req)).await
+                }
+                "chat/screening" => {
+                    call(body, |req| get_screening::handler(&chat, user_id, req)).await
+                }
+                "chat/set-screening" => {
+                    call(body, |req| set_screening::handler(&chat, user_id, req)).await
+                }
+                "chat/submit-screening" => {
+                    call(body, |req| submit_screening::handler(&chat, user_id, req)).await
+                }
+                "chat/screening-applications" => {
+                    call(body, |req| {
+                        get_screening_applications::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/review-screening-application" => {
+                    call(body, |req| {
+                        review_screening_application::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/quarantined-messages" => {
+                    call(body, |req| {
+                        get_quarantined_messages::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/review-quarantined-message" => {
+                    call(body, |req| {
+                        review_quarantined_message::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/require-acknowledgement" => {
+                    call(body, |req| {
+                        require_acknowledgement::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/acknowledge-message" => {
+                    call(body, |req| {
+                        acknowledge_message::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/acknowledgements" => {
+                    call(body, |req| {
+                        get_acknowledgements::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/pending-acknowledgements" => {
+                    call(body, |req| {
+                        get_pending_acknowledgements::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/redact-attachment" => {
+                    call(body, |req| redact_attachment::handler(&chat, user_id, req)).await
+                }
+                "chat/member-list" => {
+                    call(body, |req| get_member_list::handler(&chat, user_id, req)).await
+                }
+                "chat/member-chunk" => {
+                    call(body, |req| get_member_chunk::handler(&chat, user_id, req)).await
+                }
+                "chat/subscribe-to-member-list" => {
+                    call(body, |req| {
+                        subscribe_to_member_list::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/member-list-updates" => {
+                    call(body, |req| {
+                        get_member_list_updates::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/members" => call(body, |req| get_members::handler(&chat, user_id, req)).await,
+                "chat/search-members" => {
+                    call(body, |req| search_members::handler(&chat, user_id, req)).await
+                }
+                "chat/initial-sync" => {
+                    call(body, |req| initial_sync::handler(&chat, user_id, req)).await
+                }
+                "chat/gallery" => call(body, |req| get_gallery::handler(&chat, user_id, req)).await,
+                "chat/channel-role-gate" => {
+                    call(body, |req| {
+                        get_channel_role_gate::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/set-channel-role-gate" => {
+                    call(body, |req| {
+                        set_channel_role_gate::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/typing-users" => {
+                    call(body, |req| get_typing_users::handler(&chat, user_id, req)).await
+                }
+                "chat/voice-stats" => {
+                    call(body, |req| get_voice_stats::handler(&chat, user_id, req)).await
+                }
+                "chat/set-gallery-mode" => {
+                    call(body, |req| set_gallery_mode::handler(&chat, user_id, req)).await
+                }
+                "chat/messages-after" => {
+                    call(body, |req| get_messages_after::handler(&chat, user_id, req)).await
+                }
+                "chat/escalate-report" => {
+                    call(body, |req| escalate_report::handler(&chat, user_id, req)).await
+                }
+                "admin/reports" => {
+                    call(body, |req| get_report_queue::handler(&chat, user_id, req)).await
+                }
+                "admin/update-report" => {
+                    call(body, |req| update_report::handler(&chat, user_id, req)).await
+                }
+                "chat/ban-user" => {
+                    call(body, |req| {
+                        ban_user_with_reason::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/bans" => call(body, |req| get_bans::handler(&chat, user_id, req)).await,
+                "chat/ban-list-subscriptions" => {
+                    call(body, |req| {
+                        get_ban_list_subscriptions::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/subscribe-ban-list" => {
+                    call(body, |req| subscribe_ban_list::handler(&chat, user_id, req)).await
+                }
+                "chat/unsubscribe-ban-list" => {
+                    call(body, |req| {
+                        unsubscribe_ban_list::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/text-macros" => {
+                    call(body, |req| get_text_macros::handler(&chat, user_id, req)).await
+                }
+                "chat/set-text-macro" => {
+                    call(body, |req| set_text_macro::handler(&chat, user_id, req)).await
+                }
+                "chat/add-guild-emoji" => {
+                    call(body, |req| add_guild_emoji::handler(&chat, user_id, req)).await
+                }
+                "chat/delete-guild-emoji" => {
+                    call(body, |req| delete_guild_emoji::handler(&chat, user_id, req)).await
+                }
+                "chat/integrations" => {
+                    call(body, |req| {
+                        get_guild_integrations::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/add-integration" => {
+                    call(body, |req| {
+                        add_guild_integration::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/remove-integration" => {
+                    call(body, |req| {
+                        remove_guild_integration::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/widget" => call(body, |req| get_widget::handler(&chat, user_id, req)).await,
+                "chat/set-widget" => {
+                    call(body, |req| set_widget::handler(&chat, user_id, req)).await
+                }
+                "chat/guild-directory" => {
+                    call(body, |req| {
+                        get_guild_directory::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/set-guild-public" => {
+                    call(body, |req| set_guild_public::handler(&chat, user_id, req)).await
+                }
+                "chat/link-scanning" => {
+                    call(body, |req| get_link_scanning::handler(&chat, user_id, req)).await
+                }
+                "chat/set-link-scanning" => {
+                    call(body, |req| set_link_scanning::handler(&chat, user_id, req)).await
+                }
+                "admin/domain-blocklist-stats" => {
+                    call(body, |req| {
+                        get_domain_blocklist_stats::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/audit-log" => {
+                    call(body, |req| get_audit_log::handler(&chat, user_id, req)).await
+                }
+                "chat/guild-usage" => {
+                    call(body, |req| get_guild_usage::handler(&chat, user_id, req)).await
+                }
+                "admin/guild-usage" => {
+                    call(body, |req| list_guild_usage::handler(&chat, user_id, req)).await
+                }
+                "chat/invite-role-grant" => {
+                    call(body, |req| {
+                        get_invite_role_grant::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/set-invite-role-grant" => {
+                    call(body, |req| {
+                        set_invite_role_grant::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/invite-stats" => {
+                    call(body, |req| get_invite_stats::handler(&chat, user_id, req)).await
+                }
+                "chat/set-invite-expiry" => {
+                    call(body, |req| set_invite_expiry::handler(&chat, user_id, req)).await
+                }
+                "chat/role-member-caps" => {
+                    call(body, |req| {
+                        get_role_member_caps::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/set-role-member-cap" => {
+                    call(body, |req| {
+                        set_role_member_cap::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/bulk-manage-role-members" => {
+                    call(body, |req| {
+                        bulk_manage_role_members::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "chat/bulk-give-user-roles" => {
+                    call(body, |req| {
+                        bulk_give_user_roles::handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "profile/notes" => {
+                    call(body, |req| get_user_notes::handler(&profile, user_id, req)).await
+                }
+                "profile/set-note" => {
+                    call(body, |req| set_user_note::handler(&profile, user_id, req)).await
+                }
+                "profile/blocked-users" => {
+                    call(body, |req| {
+                        get_blocked_users::handler(&profile, user_id, req)
+                    })
+                    .await
+                }
+                "profile/set-blocked" => {
+                    call(body, |req| {
+                        set_user_blocked::handler(&profile, user_id, req)
+                    })
+                    .await
+                }
+                "profile/import-blocked-users" => {
+                    call(body, |req| {
+                        import_blocked_users::handler(&profile, user_id, req)
+                    })
+                    .await
+                }
+                "profile/export-settings" => {
+                    call(body, |req| export_settings::handler(&profile, user_id, req)).await
+                }
+                "profile/import-settings" => {
+                    call(body, |req| import_settings::handler(&profile, user_id, req)).await
+                }
+                "profile/presence" => {
+                    call(body, |req| presence::handler(&profile, user_id, req)).await
+                }
+                "profile/start-account-link" => {
+                    call(body, |req| {
+                        start_account_link::handler(&profile, user_id, req)
+                    })
+                    .await
+                }
+                "profile/link-account" => {
+                    call(body, |req| link_account::handler(&profile, user_id, req)).await
+                }
+                "profile/unlink-account" => {
+                    call(body, |req| unlink_account::handler(&profile, user_id, req)).await
+                }
+                "profile/linked-accounts" => {
+                    call(body, |req| {
+                        get_linked_accounts::handler(&profile, user_id, req)
+                    })
+                    .await
+                }
+                "auth/sessions" => {
+                    let current_token = get_session_token(&parts.headers);
+                    call(body, |req| {
+                        sessions::get_sessions_handler(&deps, user_id, current_token, req)
+                    })
+                    .await
+                }
+                "auth/refresh-token" => {
+                    let current_token = get_session_token(&parts.headers);
+                    call(body, |req| {
+                        refresh_tokens::create_refresh_token_handler(
+                            &deps,
+                            user_id,
+                            current_token,
+                            req,
+                        )
+                    })
+                    .await
+                }
+                "auth/revoke-session" => {
+                    call(body, |req| {
+                        sessions::revoke_session_handler(&deps, user_id, req)
+                    })
+                    .await
+                }
+                "auth/revoke-other-sessions" => {
+                    let current_token = get_session_token(&parts.headers);
+                    call(body, |req| {
+                        sessions::revoke_other_sessions_handler(&deps, user_id, current_token, req)
+                    })
+                    .await
+                }
+                "media/share-link" => {
+                    call(body, |req| {
+                        media_access::create_share_link_handler(&deps, user_id, req)
+                    })
+                    .await
+                }
+                #[cfg(feature = "voice")]
+                "voice/reconnect-token" => {
+                    call(body, |req| {
+                        reconnect::get_reconnect_token_handler(&voice, user_id, req)
+                    })
+                    .await
+                }
+                "emote/aliases" => {
+                    call(body, |req| get_emote_aliases::handler(&emote, user_id, req)).await
+                }
+                "emote/set-alias" => {
+                    call(body, |req| set_emote_alias::handler(&emote, user_id, req)).await
+                }
+                "emote/set-pack-priority" => {
+                    call(body, |req| {
+                        set_emote_pack_priority::handler(&emote, user_id, req)
+                    })
+                    .await
+                }
+                "emote/equipped-packs" => {
+                    call(body, |req| {
+                        get_equipped_packs::handler(&emote, user_id, req)
+                    })
+                    .await
+                }
+                "emote/resolve" => {
+                    call(body, |req| resolve_emotes::handler(&emote, user_id, req)).await
+                }
+                "admin/diagnostics" => {
+                    call(body, |req| diagnostics::handler(&chat, user_id, req)).await
+                }
+                "admin/db-usage" => {
+                    call(body, |req| {
+                        maintenance::get_db_usage_handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                "admin/compact-db" => {
+                    call(body, |req| {
+                        maintenance::compact_db_handler(&chat, user_id, req)
+                    })
+                    .await
+                }
+                _ => rest_error_response("no such endpoint".to_string(), StatusCode::NOT_FOUND),
+            };
+
+            Ok(response)
+        })
+    }
+}
+
+/// Runs admin endpoints that can be used with the admin token, returns `None`
+/// if `path` isn't one of them. Callers must make sure the request is from an admin.
+async fn admin_call(chat: &ChatServer, path: &str, body: Body) -> Option<HttpResponse> {
+    let response = match path {
+        "admin/ban-user" => call(body, |req| admin::ban_user(chat, req)).await,
+        "admin/unban-user" => call(body, |req| admin::unban_user(chat, req)).await,
+        "admin/delete-guild" => call(body, |req| admin::delete_guild(chat, req)).await,
+        "admin/purge-messages" => call(body, |req| admin::purge_messages(chat, req)).await,
+        "admin/generate-registration-token" => {
+            call(body, |req| admin::generate_registration_token(chat, req)).await
+        }
+        "admin/list-guilds" => call(body, |req| admin::list_guilds(chat, req)).await,
+        "admin/reset-password" => call(body, |req| admin::reset_password(chat, req)).await,
+        "admin/create-federation-invite" => {
+            call(body, |req| admin::create_federation_invite(chat, req)).await
+        }
+        "admin/accept-federation-invite" => {
+            call(body, |req| admin::accept_federation_invite(chat, req)).await
+        }
+        "admin/trusted-hosts" => call(body, |req| admin::get_trusted_hosts(chat, req)).await,
+        "admin/untrust-host" => call(body, |req| admin::untrust_host(chat, req)).await,
+        "admin/replay-events" => call(body, |req| admin::replay_events(chat, req)).await,
+        "admin/dead-letters" => call(body, |req| admin::get_dead_letters(chat, req)).await,
+        "admin/requeue-dead-letters" => {
+            call(body, |req| admin::requeue_dead_letters(chat, req)).await
+        }
+        "admin/discard-dead-letters" => {
+            call(body, |req| admin::discard_dead_letters(chat, req)).await
+        }
+        "admin/guild-retention" => call(body, |req| admin::get_guild_retention(chat, req)).await,
+        "admin/set-guild-retention" => {
+            call(body, |req| admin::set_guild_retention(chat, req)).await
+        }
+        "admin/prune-guild" => call(body, |req| admin::prune_guild(chat, req)).await,
+        _ => return None,
+    };
+    Some(response)
+}
+
+/// Reads a JSON request from the body, runs the handler with it and
+/// serializes its response.
+async fn call<Req, Resp, Fut, Handler>(body: Body, handler: Handler) -> HttpResponse
+where
+    Req: DeserializeOwned,
+    Resp: Serialize,
+    Fut: Future<Output = ServerResult<Resp>>,
+    Handler: FnOnce(Req) -> Fut,
+{
+    let raw = match read_body(body).await {
+        Ok(raw) => raw,
+        Err(resp) => return resp,
+    };
+    // Allow sending no body for requests that don't have any fields
+    let raw = if raw.is_empty() {
+        b"{}".as_ref()
+    } else {
+        raw.as_ref()
+    };
+
+    let req = match serde_json::from_slice(raw) {
+        Ok(req) => req,
+        Err(err) => return ServerError::InvalidJsonBody(err).into_rest_http_response(),
+    };
+
+    match handler(req).await {
+        Ok(resp) => json_response(&resp),
+        Err(err) => hrpc_error_response(err),
+    }
+}
+
+async fn read_body(mut body: Body) -> Result<Bytes, HttpResponse> {
+    let mut buf = BytesMut::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|err| ServerError::HttpError(err).into_rest_http_response())?;
+        if buf.len() + chunk.len() > MAX_BODY_LENGTH {
+            return Err(rest_error_response(
+                format!("body must not be longer than {} bytes", MAX_BODY_LENGTH),
+                StatusCode::PAYLOAD_TOO_LARGE,
+            ));
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(buf.freeze())
+}
+
+pub fn json_response<T: Serialize>(value: &T) -> HttpResponse {
+    let json = serde_json::to_vec(value).unwrap();
+
+    http::Response::builder()
+        .status(StatusCode::OK)
+        .header(
+            http::header::CONTENT_TYPE,
+            HeaderValue::from_static("text/json"),
+        )
+        .body(box_body(Body::from(json)))
+        .unwrap()
+}
+
+pub(super) fn hrpc_error_response(err: HrpcServerError) -> HttpResponse {
+    let status = ServerError::identifier_to_status(&err.identifier).unwrap_or_else(|| {
+        match HrpcErrorIdentifier::from_str(&err.identifier) {
+            Ok(HrpcErrorIdentifier::ResourceExhausted) => StatusCode::TOO_MANY_REQUESTS,
+            Ok(HrpcErrorIdentifier::NotFound) => StatusCode::NOT_FOUND,
+            Ok(HrpcErrorIdentifier::NotImplemented) => StatusCode::NOT_IMPLEMENTED,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    });
+
+    rest_error_response(err.human_message, status)
+}
@@ -49,6 +49,12 @@ impl Service<HttpRequest> for DownloadService {
             .strip_prefix("/_harmony/media/download/")
             .map(|id| urlencoding::decode(id).unwrap_or(Cow::Borrowed(id)))
             .and_then(|id| FileId::from_str(&id).ok());
+        let query = request.uri().query();
+        let maybe_size = query_param(query, "size").map(u32::from_str);
+        let share_link = media_access::ShareLink::new(
+            query_param(query, "expires"),
+            query_param(query, "signature"),
+        );
 
         let fut = async move {
             if request.method() != Method::GET {
@@ -62,10 +68,31 @@ impl Service<HttpRequest> for DownloadService {
                 Some(file_id) => file_id,
                 _ => return Ok(ServerError::InvalidFileId.into_rest_http_response()),
             };
+            let size = match maybe_size.transpose() {
+                Ok(size) => size,
+                Err(_) => {
+                    return Ok(rest_error_response(
+                        "size must be a number".to_string(),
+                        StatusCode::BAD_REQUEST,
+                    ))
+                }
+            };
 
-            let media_root = deps.config.media.media_root.as_path();
             let http_client = &deps.http;
             let host = &deps.config.host;
+            let headers = request.headers();
+
+            // other files are fetched by this server, which only logged in users can make it do
+            let is_local = match &file_id {
+                FileId::Id(_) => true,
+                FileId::Hmc(hmc) => format!("{}:{}", hmc.server(), hmc.port()) == host.as_str(),
+                FileId::External(_) => false,
+            };
+            if !is_local && deps.config.media.access.require_auth {
+                if let Err(err) = deps.valid_sessions.auth_header_map(headers) {
+                    return Ok(err.into_rest_http_response());
+                }
+            }
 
             let (content_disposition, content_type, content_body, content_length) = match file_id {
                 FileId::External(url) => {
@@ -103,44 +130,54 @@ impl Service<HttpRequest> for DownloadService {
                     info!("Serving HMC from {}", hmc);
                     if format!("{}:{}", hmc.server(), hmc.port()) == host.as_str() {
                         info!("Serving local media with id {}", hmc.id());
-                        match get_file(media_root, hmc.id()).await {
+                        let access = media_access::check_download(
+                            &deps,
+                            headers,
+                            hmc.id(),
+                            share_link.as_ref(),
+                        )
+                        .await;
+                        if let Err(err) = access {
+                            return Ok(err.into_rest_http_response());
+                        }
+                        match get_local_file(&deps, hmc.id(), size).await {
                             Ok(data) => data,
                             Err(err) => return Ok(err.into_rest_http_response()),
                         }
                     } else {
-                        // Safety: this is always valid, since HMC is a valid URL
-                        let url = unsafe {
-                            format!(
-                                "https://{}:{}/_harmony/media/download/{}",
-                                hmc.server(),
-                                hmc.port(),
-                                hmc.id()
-                            )
-                            .parse()
-                            .unwrap_unchecked()
-                        };
-                        let resp = match make_request(http_client, url).await {
-                            Ok(resp) => resp,
-                            Err(err) => return Ok(err.into_rest_http_response()),
-                        };
-
-                        let extract_result =
-                            extract_file_info_from_download_response(resp.headers())
-                                .map(|(name, mimetype, _)| {
-                                    (unsafe { disposition_header(name) }, mimetype.clone())
-                                })
-                                .map_err(|e| ServerError::FileExtractUnexpected(e.into()));
-                        let (disposition, mimetype) = match extract_result {
+                        let remote_host = SmolStr::from(hmc.server());
+                        let fetch_allowed = deps
+                            .config
+                            .federation
+                            .is_some()
+                            .then(|| deps.is_host_allowed(&remote_host))
+                            .unwrap_or(Ok(()));
+                        if let Err(err) = fetch_allowed {
+                            return Ok(err.into_rest_http_response());
+                        }
+
+                        let fetched = remote_media::get_remote_file(
+                            deps.clone(),
+                            remote_host,
+                            hmc.port(),
+                            hmc.id(),
+                        )
+                        .await;
+                        match fetched {
                             Ok(data) => data,
                             Err(err) => return Ok(err.into_rest_http_response()),
-                        };
-                        let len = get_content_length(&resp);
-                        (disposition, mimetype, resp.into_body(), len)
+                        }
                     }
                 }
                 FileId::Id(id) => {
                     info!("Serving local media with id {}", id);
-                    match get_file(media_root, &id).await {
+                    let access =
+                        media_access::check_download(&deps, headers, &id, share_link.as_ref())
+                            .await;
+                    if let Err(err) = access {
+                        return Ok(err.into_rest_http_response());
+                    }
+                    match get_local_file(&deps, &id, size).await {
                         Ok(data) => data,
                         Err(err) => return Ok(err.into_rest_http_response()),
                     }
@@ -165,31 +202,37 @@ pub fn handler(deps: Arc<Dependencies>) -> RateLimit<DownloadService> {
         .service(DownloadService { deps })
 }
 
+/// Gets the value of a parameter in the query of a request.
+pub(super) fn query_param<'a>(query: Option<&'a str>, name: &str) -> Option<&'a str> {
+    query?
+        .split('&')
+        .find_map(|param| param.strip_prefix(name)?.strip_prefix('='))
+}
+
+/// Gets a file uploaded to this server, or its thumbnail if a size was requested.
+async fn get_local_file(
+    deps: &Dependencies,
+    id: &str,
+    size: Option<u32>,
+) -> Result<(HeaderValue, HeaderValue, hyper::Body, HeaderValue), ServerError> {
+    match size {
+        Some(size) => thumbnail::get_thumbnail(deps, id, size).await,
+        None => get_file(deps.media_store.as_ref(), id).await,
+    }
+}
+
 // Safety: the `name` argument MUST ONLY contain ASCII characters.
-unsafe fn disposition_header(name: &str) -> HeaderValue {
+pub(super) unsafe fn disposition_header(name: &str) -> HeaderValue {
     HeaderValue::from_maybe_shared_unchecked(Bytes::from(
         format!("inline; filename={}", name).into_bytes(),
     ))
 }
 
-pub async fn get_file_handle(media_root: &Path, id: &str) -> Result<(File, Metadata), ServerError> {
-    let file_path = media_root.join(id);
-    let file = tokio::fs::File::open(file_path).await.map_err(|err| {
-        if let std::io::ErrorKind::NotFound = err.kind() {
-            ServerError::MediaNotFound
-        } else {
-            err.into()
-        }
-    })?;
-    let metadata = file.metadata().await?;
-    Ok((file, metadata))
-}
-
-pub async fn read_bufs(
-    file: &mut File,
+pub async fn read_bufs<R: AsyncRead + Unpin>(
+    reader: R,
     is_jpeg: bool,
-) -> Result<(Vec<u8>, Vec<u8>, BufReader<&mut File>), ServerError> {
-    let mut buf_reader = BufReader::new(file);
+) -> Result<(Vec<u8>, Vec<u8>, BufReader<R>), ServerError> {
+    let mut buf_reader = BufReader::new(reader);
 
     if is_jpeg {
         Ok((b"unknown.jpg".to_vec(), b"image/jpeg".to_vec(), buf_reader))
@@ -207,14 +250,14 @@ pub async fn read_bufs(
 }
 
 pub async fn get_file_full(
-    media_root: &Path,
+    store: &dyn MediaStore,
     id: &str,
 ) -> Result<(String, String, Vec<u8>, u64), ServerError> {
     let is_jpeg = is_id_jpeg(id);
-    let (mut file, metadata) = get_file_handle(media_root, id).await?;
-    let (filename_raw, mimetype_raw, mut buf_reader) = read_bufs(&mut file, is_jpeg).await?;
+    let StoredMedia { reader, len } = store.get(id).await?;
+    let (filename_raw, mimetype_raw, mut buf_reader) = read_bufs(reader, is_jpeg).await?;
 
-    let (start, end) = calculate_range(&filename_raw, &mimetype_raw, &metadata, is_jpeg);
+    let (start, end) = calculate_range(&filename_raw, &mimetype_raw, len, is_jpeg);
     let size = end - start;
     let mut file_raw = Vec::with_capacity(size as usize);
     buf_reader.read_to_end(&mut file_raw).await?;
@@ -232,14 +275,14 @@ pub async fn get_file_full(
 pub fn calculate_range(
     filename_raw: &[u8],
     mimetype_raw: &[u8],
-    metadata: &Metadata,
+    len: u64,
     is_jpeg: bool,
 ) -> (u64, u64) {
     // + 2 is because we need to factor in the 2 b'\n' seperators
     let start = is_jpeg
         .then(|| 0)
         .unwrap_or_else(|| (filename_raw.len() + mimetype_raw.len()) as u64 + 2);
-    let end = metadata.len();
+    let end = len;
 
     (start, end)
 }
@@ -249,14 +292,14 @@ pub fn is_id_jpeg(id: &str) -> bool {
 }
 
 pub async fn get_file(
-    media_root: &Path,
+    store: &dyn MediaStore,
     id: &str,
 ) -> Result<(HeaderValue, HeaderValue, hyper::Body, HeaderValue), ServerError> {
     let is_jpeg = is_id_jpeg(id);
-    let (mut file, metadata) = get_file_handle(media_root, id).await?;
-    let (filename_raw, mimetype_raw, _) = read_bufs(&mut file, is_jpeg).await?;
+    let StoredMedia { reader, len } = store.get(id).await?;
+    let (filename_raw, mimetype_raw, buf_reader) = read_bufs(reader, is_jpeg).await?;
 
-    let (start, end) = calculate_range(&filename_raw, &mimetype_raw, &metadata, is_jpeg);
+    let (start, end) = calculate_range(&filename_raw, &mimetype_raw, len, is_jpeg);
     let mimetype: Bytes = mimetype_raw.into();
 
     let disposition = unsafe {
@@ -267,11 +310,11 @@ pub async fn get_file(
     // Safety: mimetypes must be valid ASCII chars since we get them through only ASCII allowed structures [ref:ascii_mimetype_upload]
     let mimetype = unsafe { HeaderValue::from_maybe_shared_unchecked(mimetype) };
 
-    let buf_size = optimal_buf_size(&metadata);
     Ok((
         disposition,
         mimetype,
-        hyper::Body::wrap_stream(file_stream(file, buf_size, (start, end))),
+        // the prefix was already read, so only the file contents are left
+        hyper::Body::wrap_stream(ReaderStream::new(buf_reader.take(end - start))),
         unsafe {
             HeaderValue::from_maybe_shared_unchecked(Bytes::from(
                 (end - start).to_string().into_bytes(),
@@ -279,90 +322,3 @@ pub async fn get_file(
         },
     ))
 }
-
-fn file_stream(
-    mut file: tokio::fs::File,
-    buf_size: usize,
-    (start, end): (u64, u64),
-) -> impl Stream<Item = Result<Bytes, std::io::Error>> + Send {
-    use std::io::SeekFrom;
-
-    let seek = async move {
-        // We will always seek from a point that is non-zero
-        file.seek(SeekFrom::Start(start)).await?;
-        Ok(file)
-    };
-
-    seek.into_stream()
-        .map(move |result| {
-            let mut buf = BytesMut::new();
-            let mut len = end - start;
-            let mut f = match result {
-                Ok(f) => f,
-                Err(f) => return Either::Left(stream::once(future::err(f))),
-            };
-
-            Either::Right(stream::poll_fn(move |cx| {
-                if len == 0 {
-                    return Poll::Ready(None);
-                }
-                reserve_at_least(&mut buf, buf_size);
-
-                let n = match ready!(poll_read_buf(Pin::new(&mut f), cx, &mut buf)) {
-                    Ok(n) => n as u64,
-                    Err(err) => {
-                        tracing::debug!("file read error: {}", err);
-                        return Poll::Ready(Some(Err(err)));
-                    }
-                };
-
-                if n == 0 {
-                    tracing::debug!("file read found EOF before expected length");
-                    return Poll::Ready(None);
-                }
-
-                let mut chunk = buf.split().freeze();
-                if n > len {
-                    chunk = chunk.split_to(len as usize);
-                    len = 0;
-                } else {
-                    len -= n;
-                }
-
-                Poll::Ready(Some(Ok(chunk)))
-            }))
-        })
-        .flatten()
-}
-
-fn reserve_at_least(buf: &mut BytesMut, cap: usize) {
-    if buf.capacity() - buf.len() < cap {
-        buf.reserve(cap);
-    }
-}
-
-fn optimal_buf_size(metadata: &Metadata) -> usize {
-    let block_size = get_block_size(metadata);
-
-    // If file length is smaller than block size, don't waste space
-    // reserving a bigger-than-needed buffer.
-    cmp::min(block_size as u64, metadata.len()) as usize
-}
-
-#[cfg(unix)]
-fn get_block_size(metadata: &Metadata) -> usize {
-    use std::os::unix::fs::MetadataExt;
-    //TODO: blksize() returns u64, should handle bad cast...
-    //(really, a block size bigger than 4gb?)
-
-    // Use device blocksize unless it's really small.
-    cmp::max(metadata.blksize() as usize, DEFAULT_READ_BUF_SIZE)
-}
-
-#[cfg(not(unix))]
-#[inline(always)]
-const fn get_block_size(_metadata: &Metadata) -> usize {
-    DEFAULT_READ_BUF_SIZE
-}
-
-const DEFAULT_READ_BUF_SIZE: usize = 8_192;
@@ -0,0 +1,68 @@
+//! Federation signing keys of this server, served at
+//! `/_scherzo/federation/keys` without authentication.
+//!
+//! Lists the current key first, then keys replaced with `rotate_keys` that
+//! are still accepted, each with its key ID and validity period. Other
+//! homeservers use it to verify requests signed just before a rotation.
+
+use std::convert::Infallible;
+
+use tower::limit::{RateLimit, RateLimitLayer};
+
+use crate::rest_error_response;
+
+use super::*;
+
+pub fn handler(deps: Arc<Dependencies>) -> RateLimit<FederationKeysService> {
+    ServiceBuilder::new()
+        .layer(RateLimitLayer::new(10, Duration::from_secs(5)))
+        .service(FederationKeysService { deps })
+}
+
+pub struct FederationKeysService {
+    deps: Arc<Dependencies>,
+}
+
+impl Service<HttpRequest> for FederationKeysService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+
+        Box::pin(async move {
+            if request.method() != Method::GET {
+                return Ok(rest_error_response(
+                    "method must be GET".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let Some(keys_manager) = deps.key_manager.as_ref() else {
+                return Ok(ServerError::FederationDisabled.into_rest_http_response());
+            };
+            let keys = match keys_manager.get_own_keys().await {
+                Ok(keys) => keys,
+                Err(err) => return Ok(err.into_rest_http_response()),
+            };
+
+            Ok(http::Response::builder()
+                .status(StatusCode::OK)
+                .header(
+                    http::header::CONTENT_TYPE,
+                    HeaderValue::from_static("application/json"),
+                )
+                .body(box_body(Body::from(
+                    serde_json::to_vec(&keys).expect("must be valid json"),
+                )))
+                .unwrap())
+        })
+    }
+}
@@ -0,0 +1,372 @@
+//! Who can download media uploaded to this server, and share links that let
+//! anyone download a file until they expire.
+//!
+//! Access is only checked if `media.access.require_auth` is enabled in config.
+//! Files posted in messages can then be downloaded by their uploader and
+//! users who can see a channel they were posted in, other files by any user
+//! with a session.
+
+use harmony_rust_sdk::api::chat::{content, Content};
+use serde::{Deserialize, Serialize};
+use sha3::Digest;
+
+use crate::{
+    config::MediaAccessConfig,
+    db::{self, chat::make_msg_key, media::*, DbResult, Tree},
+    impls::{auth::AuthExt, get_time_secs},
+};
+
+use super::*;
+
+/// Block size of SHA3-256, in bytes.
+const SHA3_256_BLOCK_LEN: usize = 136;
+
+/// Loads the secret used to sign share links. Uses the secret from config if
+/// there is one, otherwise generates one the first time.
+pub async fn load_share_key(media_tree: &Tree, config: &MediaAccessConfig) -> DbResult<Vec<u8>> {
+    if let Some(secret) = &config.share_link_secret {
+        return Ok(secret.as_bytes().to_vec());
+    }
+    if let Some(key) = media_tree.get(SHARE_KEY).await? {
+        return Ok(key.to_vec());
+    }
+    let key: [u8; 32] = rand::random();
+    media_tree.insert(SHARE_KEY, &key[..]).await?;
+    Ok(key.to_vec())
+}
+
+/// Gets the ID of the uploaded file a stored file was generated from, so that
+/// converted photos use the access rules of the original upload.
+fn base_file_id(id: &str) -> &str {
+    id.strip_suffix("_jpegthumb")
+        .or_else(|| id.strip_suffix("_jpeg"))
+        .unwrap_or(id)
+}
+
+/// Gets the ID of a file uploaded to this server from an ID or a HMC. Returns
+/// `None` for HMCs of files on other homeservers.
+pub fn local_file_id(deps: &Dependencies, file_id: &str) -> Option<String> {
+    match FileId::from_str(file_id) {
+        Ok(FileId::Id(id)) => Some(id),
+        Ok(FileId::Hmc(hmc))
+            if format!("{}:{}", hmc.server(), hmc.port()) == deps.config.host.as_str() =>
+        {
+            Some(hmc.id().to_string())
+        }
+        _ => None,
+    }
+}
+
+/// Gets the IDs of the local files in a message content.
+fn local_file_ids(deps: &Dependencies, content: &Content) -> Vec<String> {
+    let ids: Vec<&str> = match &content.content {
+        Some(content::Content::AttachmentMessage(files)) => {
+            files.files.iter().map(|file| file.id.as_str()).collect()
+        }
+        Some(content::Content::PhotoMessage(photos)) => photos
+            .photos
+            .iter()
+            .map(|photo| photo.hmc.as_str())
+            .collect(),
+        _ => return Vec::new(),
+    };
+    ids.into_iter()
+        .filter_map(|id| local_file_id(deps, id))
+        .collect()
+}
+
+/// Records who uploaded a file.
+pub async fn set_owner(deps: &Dependencies, id: &str, user_id: u64) -> Result<(), ServerError> {
+    deps.media_tree
+        .insert(&make_owner_key(id), &user_id.to_be_bytes()[..])
+        .await?;
+    Ok(())
+}
+
+/// Records the local files of a message as posted in a channel, so that users
+/// who can see the channel can download them.
+pub async fn record_posted(
+    deps: &Dependencies,
+    guild_id: u64,
+    channel_id: u64,
+    content: &Content,
+) -> Result<(), ServerError> {
+    let mut batch = Batch::default();
+    for local_id in local_file_ids(deps, content) {
+        batch.insert(
+            make_posted_key(base_file_id(&local_id), guild_id, channel_id),
+            [],
+        );
+    }
+    deps.media_tree.apply_batch(batch).await?;
+
+    Ok(())
+}
+
+/// Removes the owner and the places a file was posted in.
+pub async fn delete_records(deps: &Dependencies, id: &str) -> Result<(), ServerError> {
+    let mut batch = Batch::default();
+    batch.remove(make_owner_key(id));
+    for res in deps.media_tree.scan_prefix(&make_posted_prefix(id)).await {
+        let (key, _) = res?;
+        batch.remove(key);
+    }
+    deps.media_tree.apply_batch(batch).await?;
+    Ok(())
+}
+
+/// Gets the guild and channel IDs of the channels a file was posted in.
+async fn posted_in(deps: &Dependencies, id: &str) -> Result<Vec<(u64, u64)>, ServerError> {
+    let prefix = make_posted_prefix(id);
+    let mut posted_in = Vec::new();
+    for res in deps.media_tree.scan_prefix(&prefix).await {
+        let (key, _) = res?;
+        // [ref:media_posted_key]
+        let (guild_raw, channel_raw) = key[prefix.len()..].split_at(8);
+        // Safety: posted keys always end with the guild and channel IDs
+        posted_in.push(unsafe {
+            (
+                u64::from_be_bytes(guild_raw.try_into().unwrap_unchecked()),
+                u64::from_be_bytes(channel_raw.try_into().unwrap_unchecked()),
+            )
+        });
+    }
+    Ok(posted_in)
+}
+
+/// Whether a message other than the given one still has a file in it.
+pub async fn is_posted_elsewhere(
+    deps: &Dependencies,
+    id: &str,
+    message_key: &[u8],
+) -> Result<bool, ServerError> {
+    let id = base_file_id(id);
+    for (guild_id, channel_id) in posted_in(deps, id).await? {
+        let from_key = make_msg_key(guild_id, channel_id, 0);
+        let to_key = make_msg_key(guild_id, channel_id, u64::MAX);
+        for res in deps
+            .chat_tree
+            .chat_tree
+            .range((&from_key)..=(&to_key))
+            .await
+        {
+            let (key, value) = res?;
+            if key.len() != from_key.len() || key.as_ref() == message_key {
+                continue;
+            }
+            let message = db::deser_message(value);
+            let has_file = message.content.as_ref().map_or(false, |content| {
+                local_file_ids(deps, content)
+                    .iter()
+                    .any(|other| base_file_id(other) == id)
+            });
+            if has_file {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Whether a user can download a file uploaded to this server.
+async fn can_access(deps: &Dependencies, user_id: u64, id: &str) -> Result<bool, ServerError> {
+    let is_owner = deps
+        .media_tree
+        .get(&make_owner_key(id))
+        .await?
+        .map_or(false, |raw| raw.as_ref() == user_id.to_be_bytes());
+    if is_owner {
+        return Ok(true);
+    }
+
+    let posted_in = posted_in(deps, id).await?;
+    // files that weren't posted anywhere are avatars, emotes and such
+    if posted_in.is_empty() {
+        return Ok(true);
+    }
+
+    let chat_tree = &deps.chat_tree;
+    for (guild_id, channel_id) in posted_in {
+        let can_view = chat_tree
+            .check_guild_user_channel(guild_id, user_id, channel_id)
+            .await
+            .is_ok()
+            && chat_tree
+                .check_perms(guild_id, Some(channel_id), user_id, "messages.view", false)
+                .await
+                .is_ok();
+        if can_view {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Expiry and signature sent in the query of a share link.
+pub struct ShareLink {
+    expires_at: String,
+    signature: String,
+}
+
+impl ShareLink {
+    pub fn new(expires_at: Option<&str>, signature: Option<&str>) -> Option<Self> {
+        Some(Self {
+            expires_at: expires_at?.to_string(),
+            signature: signature?.to_string(),
+        })
+    }
+
+    fn verify(&self, key: &[u8], id: &str) -> Result<(), ServerError> {
+        let expires_at = self
+            .expires_at
+            .parse::<u64>()
+            .map_err(|_| ServerError::InvalidMediaLink)?;
+        if get_time_secs() >= expires_at {
+            return Err(ServerError::InvalidMediaLink);
+        }
+        let expected = sign(key, id, expires_at);
+        // compare every byte, so the time taken doesn't tell how much matched
+        let matches = expected.len() == self.signature.len()
+            && expected
+                .bytes()
+                .zip(self.signature.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0;
+        matches.then(|| ()).ok_or(ServerError::InvalidMediaLink)
+    }
+}
+
+/// HMAC of a message with SHA3-256 as the hash function.
+fn hmac_sha3_256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut block_key = [0; SHA3_256_BLOCK_LEN];
+    if key.len() > SHA3_256_BLOCK_LEN {
+        let hashed = sha3::Sha3_256::digest(key);
+        block_key[..hashed.len()].copy_from_slice(&hashed);
+    } else {
+        block_key[..key.len()].copy_from_slice(key);
+    }
+    let pad = |byte: u8| block_key.iter().map(|k| k ^ byte).collect::<Vec<_>>();
+
+    let inner = sha3::Sha3_256::new()
+        .chain_update(pad(0x36))
+        .chain_update(message)
+        .finalize();
+    sha3::Sha3_256::new()
+        .chain_update(pad(0x5c))
+        .chain_update(inner)
+        .finalize()
+        .to_vec()
+}
+
+/// Signs a file ID and expiry time with the share key, giving a hex string.
+fn sign(key: &[u8], id: &str, expires_at: u64) -> String {
+    let message = [id.as_bytes(), b"\n", expires_at.to_be_bytes().as_ref()].concat();
+    hmac_sha3_256(key, &message)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Checks if a file uploaded to this server can be downloaded, either with a
+/// share link or with the session the request was made with.
+pub async fn check_download(
+    deps: &Dependencies,
+    headers: &http::HeaderMap,
+    id: &str,
+    share_link: Option<&ShareLink>,
+) -> Result<(), ServerError> {
+    let id = base_file_id(id);
+    // links are checked even if downloads are open, so expired links stop working
+    if let Some(link) = share_link {
+        return link.verify(&deps.media_share_key, id);
+    }
+    if !deps.config.media.access.require_auth {
+        return Ok(());
+    }
+
+    let user_id = deps.valid_sessions.auth_header_map(headers)?;
+    if can_access(deps, user_id, id).await? {
+        Ok(())
+    } else {
+        Err(ServerError::MediaAccessDenied)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateShareLinkRequest {
+    /// ID or HMC of a file uploaded to this server.
+    pub file_id: String,
+    /// How long the link should be valid for, in seconds. Defaults to, and
+    /// can't be longer than, the maximum set in config.
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreateShareLinkResponse {
+    /// Path of the link on this homeserver, including the query.
+    pub path: String,
+    /// In seconds since unix epoch.
+    pub expires_at: u64,
+}
+
+/// Creates a link that lets anyone download a file until it expires.
+pub async fn create_share_link_handler(
+    deps: &Dependencies,
+    user_id: u64,
+    request: CreateShareLinkRequest,
+) -> ServerResult<CreateShareLinkResponse> {
+    let CreateShareLinkRequest {
+        file_id,
+        expires_in,
+    } = request;
+
+    let id = match local_file_id(deps, &file_id) {
+        Some(id) => id,
+        None => bail!(ServerError::InvalidFileId),
+    };
+    if !deps.media_store.exists(&id).await? {
+        bail!(ServerError::MediaNotFound);
+    }
+    if !can_access(deps, user_id, base_file_id(&id)).await? {
+        bail!(ServerError::MediaAccessDenied);
+    }
+
+    let max_age = deps.config.media.access.share_link_max_age;
+    let expires_at = get_time_secs() + expires_in.unwrap_or(max_age).min(max_age);
+    let signature = sign(&deps.media_share_key, base_file_id(&id), expires_at);
+
+    Ok(CreateShareLinkResponse {
+        path: format!(
+            "/_harmony/media/download/{}?expires={}&signature={}",
+            id, expires_at, signature
+        ),
+        expires_at,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn share_link_signature() {
+        let key = b"key";
+        let expires_at = get_time_secs() + 60;
+        let link = ShareLink {
+            expires_at: expires_at.to_string(),
+            signature: sign(key, "abc", expires_at),
+        };
+
+        assert!(link.verify(key, "abc").is_ok());
+        assert!(link.verify(key, "abd").is_err());
+        assert!(link.verify(b"other key", "abc").is_err());
+
+        let expired = ShareLink {
+            expires_at: "1".to_string(),
+            signature: sign(key, "abc", 1),
+        };
+        assert!(expired.verify(key, "abc").is_err());
+    }
+}
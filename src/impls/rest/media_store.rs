@@ -0,0 +1,219 @@
+//! Where uploaded media (and files generated from it) is stored.
+//!
+//! Stores only deal with whole files. The file name and mimetype prefix of
+//! uploaded files is handled in the [`upload`](super::upload) and
+//! [`download`](super::download) modules.
+
+use std::io::{Cursor, ErrorKind};
+
+use crate::config::MediaConfig;
+
+use super::*;
+
+pub type MediaReader = Box<dyn AsyncRead + Send + Unpin>;
+
+/// A file read from a media store.
+pub struct StoredMedia {
+    pub reader: MediaReader,
+    /// Length of the whole file, in bytes.
+    pub len: u64,
+}
+
+pub trait MediaStore: Send + Sync {
+    /// Writes everything read from `data` as the file with the given ID.
+    fn put<'a>(&'a self, id: &'a str, data: MediaReader) -> BoxFuture<'a, Result<(), ServerError>>;
+    /// Reads the file with the given ID, returning [`ServerError::MediaNotFound`] if there is none.
+    fn get<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<StoredMedia, ServerError>>;
+    fn exists<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<bool, ServerError>>;
+    /// Deletes the file with the given ID. Deleting a file that doesn't exist is not an error.
+    fn delete<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), ServerError>>;
+}
+
+/// Creates the media store configured in `config`.
+pub fn from_config(config: &MediaConfig) -> Result<Box<dyn MediaStore>, ServerError> {
+    #[cfg(feature = "s3")]
+    if let Some(s3_config) = &config.s3 {
+        return Ok(Box::new(S3Store::new(s3_config)?));
+    }
+    #[cfg(not(feature = "s3"))]
+    if config.s3.is_some() {
+        tracing::warn!(
+            "s3 media storage is configured, but scherzo wasn't built with the `s3` feature; storing media in the media root instead"
+        );
+    }
+
+    Ok(Box::new(LocalStore::new(config.media_root.clone())))
+}
+
+/// Stores media as files in a directory.
+pub struct LocalStore {
+    root: PathBuf,
+}
+
+impl LocalStore {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+}
+
+impl MediaStore for LocalStore {
+    fn put<'a>(
+        &'a self,
+        id: &'a str,
+        mut data: MediaReader,
+    ) -> BoxFuture<'a, Result<(), ServerError>> {
+        Box::pin(async move {
+            let file = tokio::fs::OpenOptions::default()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(self.root.join(id))
+                .await?;
+            let mut buf_writer = BufWriter::new(file);
+            tokio::io::copy(&mut data, &mut buf_writer).await?;
+            buf_writer.flush().await?;
+
+            Ok(())
+        })
+    }
+
+    fn get<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<StoredMedia, ServerError>> {
+        Box::pin(async move {
+            let file = File::open(self.root.join(id)).await.map_err(|err| {
+                if let ErrorKind::NotFound = err.kind() {
+                    ServerError::MediaNotFound
+                } else {
+                    err.into()
+                }
+            })?;
+            let len = file.metadata().await?.len();
+
+            Ok(StoredMedia {
+                reader: Box::new(file),
+                len,
+            })
+        })
+    }
+
+    fn exists<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<bool, ServerError>> {
+        Box::pin(async move {
+            match tokio::fs::metadata(self.root.join(id)).await {
+                Ok(_) => Ok(true),
+                Err(err) if err.kind() == ErrorKind::NotFound => Ok(false),
+                Err(err) => Err(err.into()),
+            }
+        })
+    }
+
+    fn delete<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), ServerError>> {
+        Box::pin(async move {
+            match tokio::fs::remove_file(self.root.join(id)).await {
+                Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+                _ => Ok(()),
+            }
+        })
+    }
+}
+
+/// Stores media as objects in an S3 compatible bucket.
+#[cfg(feature = "s3")]
+pub struct S3Store {
+    bucket: s3::Bucket,
+}
+
+#[cfg(feature = "s3")]
+fn s3_err(err: impl std::fmt::Display) -> ServerError {
+    tracing::error!("s3 error: {}", err);
+    ServerError::InternalServerError
+}
+
+#[cfg(feature = "s3")]
+impl S3Store {
+    pub fn new(config: &crate::config::S3Config) -> Result<Self, ServerError> {
+        use s3::{creds::Credentials, Bucket, Region};
+
+        let region = match &config.endpoint {
+            Some(endpoint) => Region::Custom {
+                region: config.region.clone(),
+                endpoint: endpoint.clone(),
+            },
+            None => config.region.parse().map_err(s3_err)?,
+        };
+        let credentials = Credentials::new(
+            Some(&config.access_key),
+            Some(&config.secret_key),
+            None,
+            None,
+            None,
+        )
+        .map_err(s3_err)?;
+        let bucket = if config.path_style {
+            Bucket::new_with_path_style(&config.bucket, region, credentials)
+        } else {
+            Bucket::new(&config.bucket, region, credentials)
+        }
+        .map_err(s3_err)?;
+
+        Ok(Self { bucket })
+    }
+}
+
+#[cfg(feature = "s3")]
+impl MediaStore for S3Store {
+    fn put<'a>(
+        &'a self,
+        id: &'a str,
+        mut data: MediaReader,
+    ) -> BoxFuture<'a, Result<(), ServerError>> {
+        Box::pin(async move {
+            let status = self
+                .bucket
+                .put_object_stream(&mut data, id)
+                .await
+                .map_err(s3_err)?;
+            if (200..300).contains(&status).not() {
+                return Err(s3_err(format!("putting {} returned status {}", id, status)));
+            }
+
+            Ok(())
+        })
+    }
+
+    fn get<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<StoredMedia, ServerError>> {
+        Box::pin(async move {
+            let response = self.bucket.get_object(id).await.map_err(s3_err)?;
+            match response.status_code() {
+                404 => return Err(ServerError::MediaNotFound),
+                status if (200..300).contains(&status).not() => {
+                    return Err(s3_err(format!("getting {} returned status {}", id, status)))
+                }
+                _ => {}
+            }
+            let data = response.bytes().to_vec();
+
+            Ok(StoredMedia {
+                len: data.len() as u64,
+                reader: reader_from_vec(data),
+            })
+        })
+    }
+
+    fn exists<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<bool, ServerError>> {
+        Box::pin(async move {
+            let (_, status) = self.bucket.head_object(id).await.map_err(s3_err)?;
+            Ok((200..300).contains(&status))
+        })
+    }
+
+    fn delete<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), ServerError>> {
+        Box::pin(async move {
+            self.bucket.delete_object(id).await.map_err(s3_err)?;
+            Ok(())
+        })
+    }
+}
+
+/// Makes a reader for data that is already in memory.
+pub fn reader_from_vec(data: Vec<u8>) -> MediaReader {
+    Box::new(Cursor::new(data))
+}
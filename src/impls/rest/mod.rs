@@ -1,17 +1,29 @@
 use crate::http;
 
-use self::{about::AboutService, download::DownloadService, upload::UploadService};
+use self::{
+    about::AboutService,
+    api::ApiService,
+    download::DownloadService,
+    federation_keys::FederationKeysService,
+    media_store::{MediaStore, StoredMedia},
+    ready::ReadyService,
+    replication::ReplicationService,
+    upload::UploadService,
+    widget::WidgetService,
+};
 
-use super::{gen_rand_inline_str, get_content_length, prelude::*};
+#[cfg(feature = "voice")]
+use super::voice::VoiceServer;
+use super::{
+    chat::ChatServer, gen_rand_inline_str, get_content_length, prelude::*, startup::Startup,
+};
 
 use std::{
     borrow::Cow,
-    cmp,
     convert::Infallible,
-    fs::Metadata,
     future::Future,
     ops::Not,
-    path::{Path, PathBuf},
+    path::PathBuf,
     pin::Pin,
     str::FromStr,
     task::{Context, Poll},
@@ -23,8 +35,8 @@ use harmony_rust_sdk::api::{
         hrpc::{
             client::transport::http::hyper::HttpClient,
             exports::futures_util::{
-                future::{self, BoxFuture, Either},
-                ready, stream, FutureExt, Stream, StreamExt,
+                future::{self, BoxFuture},
+                stream, FutureExt, StreamExt,
             },
             server::transport::http::{box_body, HttpRequest, HttpResponse},
         },
@@ -38,15 +50,25 @@ use hyper::Body;
 use pin_project::pin_project;
 use tokio::{
     fs::File,
-    io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader, BufWriter},
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader, BufWriter},
 };
-use tokio_util::io::poll_read_buf;
+use tokio_util::io::{ReaderStream, StreamReader};
 use tower::{limit::RateLimit, Layer, Service, ServiceBuilder};
 use tracing::info;
 
 pub mod about;
+pub mod admin;
+pub mod api;
 pub mod download;
+pub mod federation_keys;
+pub mod media_access;
+pub mod media_store;
+pub mod ready;
+pub mod remote_media;
+pub mod replication;
+pub mod thumbnail;
 pub mod upload;
+pub mod widget;
 
 const SEPERATOR: u8 = b'\n';
 
@@ -55,11 +77,26 @@ type Out = Result<HttpResponse, Infallible>;
 #[derive(Clone)]
 pub struct RestServiceLayer {
     deps: Arc<Dependencies>,
+    chat: ChatServer,
+    #[cfg(feature = "voice")]
+    voice: VoiceServer,
+    startup: Startup,
 }
 
 impl RestServiceLayer {
-    pub fn new(deps: Arc<Dependencies>) -> Self {
-        Self { deps }
+    pub fn new(
+        deps: Arc<Dependencies>,
+        chat: ChatServer,
+        #[cfg(feature = "voice")] voice: VoiceServer,
+        startup: Startup,
+    ) -> Self {
+        Self {
+            deps,
+            chat,
+            #[cfg(feature = "voice")]
+            voice,
+            startup,
+        }
     }
 }
 
@@ -74,6 +111,16 @@ where
             download: download::handler(self.deps.clone()),
             upload: upload::handler(self.deps.clone()),
             about: about::handler(self.deps.clone()),
+            widget: widget::handler(self.deps.clone()),
+            federation_keys: federation_keys::handler(self.deps.clone()),
+            replication: replication::handler(self.deps.clone()),
+            ready: ready::handler(self.startup.clone()),
+            api: api::handler(
+                self.deps.clone(),
+                self.chat.clone(),
+                #[cfg(feature = "voice")]
+                self.voice.clone(),
+            ),
             inner,
         }
     }
@@ -83,6 +130,11 @@ pub struct RestService<S> {
     download: RateLimit<DownloadService>,
     upload: RateLimit<UploadService>,
     about: RateLimit<AboutService>,
+    widget: RateLimit<WidgetService>,
+    federation_keys: RateLimit<FederationKeysService>,
+    replication: ReplicationService,
+    ready: ReadyService,
+    api: RateLimit<ApiService>,
     inner: S,
 }
 
@@ -100,7 +152,12 @@ where
         let pending = Service::poll_ready(&mut self.inner, cx).is_pending()
             | Service::poll_ready(&mut self.about, cx).is_pending()
             | Service::poll_ready(&mut self.download, cx).is_pending()
-            | Service::poll_ready(&mut self.upload, cx).is_pending();
+            | Service::poll_ready(&mut self.upload, cx).is_pending()
+            | Service::poll_ready(&mut self.widget, cx).is_pending()
+            | Service::poll_ready(&mut self.federation_keys, cx).is_pending()
+            | Service::poll_ready(&mut self.replication, cx).is_pending()
+            | Service::poll_ready(&mut self.ready, cx).is_pending()
+            | Service::poll_ready(&mut self.api, cx).is_pending();
 
         pending
             .then(|| Poll::Pending)
@@ -112,10 +169,20 @@ where
 
         if path.starts_with("/_harmony/media/download/") {
             RestFuture::Other(Service::call(&mut self.download, req))
+        } else if path.starts_with("/_scherzo/widget/") {
+            RestFuture::Other(Service::call(&mut self.widget, req))
+        } else if path == "/_scherzo/federation/keys" {
+            RestFuture::Other(Service::call(&mut self.federation_keys, req))
+        } else if path.starts_with("/_scherzo/replication/") {
+            RestFuture::Other(Service::call(&mut self.replication, req))
+        } else if path == "/_scherzo/ready" {
+            RestFuture::Ready(Service::call(&mut self.ready, req))
+        } else if path.starts_with("/_scherzo/") {
+            RestFuture::Other(Service::call(&mut self.api, req))
         } else {
             match path {
                 "/_harmony/media/upload" => RestFuture::Other(Service::call(&mut self.upload, req)),
-                "/_harmony/about" => RestFuture::About(Service::call(&mut self.about, req)),
+                "/_harmony/about" => RestFuture::Ready(Service::call(&mut self.about, req)),
                 _ => RestFuture::Inner(Service::call(&mut self.inner, req)),
             }
         }
@@ -125,7 +192,7 @@ where
 #[pin_project(project = EnumProj)]
 pub enum RestFuture<Fut> {
     Inner(#[pin] Fut),
-    About(Ready<Out>),
+    Ready(Ready<Out>),
     Other(BoxFuture<'static, Out>),
 }
 
@@ -139,7 +206,7 @@ where
         let this = self.project();
 
         match this {
-            EnumProj::About(fut) => fut.poll_unpin(cx),
+            EnumProj::Ready(fut) => fut.poll_unpin(cx),
             EnumProj::Other(fut) => fut.poll_unpin(cx),
             EnumProj::Inner(fut) => Future::poll(fut, cx),
         }
@@ -0,0 +1,58 @@
+use std::convert::Infallible;
+
+use crate::{impls::startup::Startup, rest_error_response};
+
+use super::*;
+use hrpc::{
+    common::future::{ready, Ready},
+    server::transport::http::HttpResponse,
+};
+use tower::Service;
+
+/// Reports whether the host went through every required startup stage, with
+/// `200 OK` once it did and `503 Service Unavailable` before that.
+pub struct ReadyService {
+    startup: Startup,
+}
+
+pub fn handler(startup: Startup) -> ReadyService {
+    ReadyService { startup }
+}
+
+impl Service<HttpRequest> for ReadyService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = Ready<Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, req: HttpRequest) -> Self::Future {
+        if req.method() != Method::GET {
+            return ready(Ok(rest_error_response(
+                "method must be GET".to_string(),
+                StatusCode::METHOD_NOT_ALLOWED,
+            )));
+        }
+
+        let report = self.startup.report();
+        let status = if report.ready {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        let json = serde_json::to_vec(&report).unwrap();
+
+        ready(Ok(http::Response::builder()
+            .status(status)
+            .header(
+                http::header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            )
+            .body(box_body(Body::from(json)))
+            .unwrap()))
+    }
+}
@@ -0,0 +1,247 @@
+//! Fetching media from other homeservers.
+//!
+//! Fetched media is cached in the media store under `remote/`, named by the
+//! hash of its contents, so it is only fetched once. Fetches are limited and
+//! counted per homeserver, so that one homeserver can't use up all of this
+//! server's bandwidth.
+
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+use ahash::RandomState;
+use dashmap::DashMap;
+use serde::Serialize;
+
+use harmony_rust_sdk::api::{harmonytypes::Token, sync::AuthData};
+use hrpc::encode::encode_protobuf_message;
+use sha3::Digest;
+
+use crate::{config::RemoteMediaConfig, impls::get_time_secs};
+use db::media::make_remote_media_key;
+
+use super::{download::disposition_header, media_store::reader_from_vec, *};
+
+const MIB: u64 = 1024 * 1024;
+
+/// Media fetched from each homeserver.
+#[derive(Default)]
+pub struct RemoteMediaUsage {
+    hosts: DashMap<SmolStr, HostUsage, RandomState>,
+}
+
+struct HostUsage {
+    window_start: Instant,
+    window_bytes: u64,
+    total_bytes: u64,
+    fetches: u64,
+    rejected: u64,
+}
+
+impl Default for HostUsage {
+    fn default() -> Self {
+        Self {
+            window_start: Instant::now(),
+            window_bytes: 0,
+            total_bytes: 0,
+            fetches: 0,
+            rejected: 0,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RemoteMediaHostStats {
+    pub host: SmolStr,
+    /// Bytes fetched in the current bandwidth window.
+    pub window_bytes: u64,
+    /// Bytes fetched since the server started.
+    pub total_bytes: u64,
+    pub fetches: u64,
+    /// Fetches refused because the host was over its quota, or the media was too large.
+    pub rejected: u64,
+}
+
+impl RemoteMediaUsage {
+    /// Checks if media can be fetched from a host, and counts the fetch if so.
+    pub fn start_fetch(&self, config: &RemoteMediaConfig, host: &str) -> Result<(), ServerError> {
+        let mut usage = self.hosts.entry(host.into()).or_default();
+        if usage.window_start.elapsed() >= Duration::from_secs(config.bandwidth_window) {
+            usage.window_start = Instant::now();
+            usage.window_bytes = 0;
+        }
+        if let Some(limit) = config.host_bandwidth {
+            if usage.window_bytes >= limit * MIB {
+                usage.rejected += 1;
+                return Err(ServerError::RemoteMediaQuotaExceeded(host.into()));
+            }
+        }
+        usage.fetches += 1;
+        Ok(())
+    }
+
+    fn record(&self, host: &str, bytes: u64) {
+        if let Some(mut usage) = self.hosts.get_mut(host) {
+            usage.window_bytes += bytes;
+            usage.total_bytes += bytes;
+        }
+    }
+
+    fn reject(&self, host: &str) {
+        if let Some(mut usage) = self.hosts.get_mut(host) {
+            usage.rejected += 1;
+        }
+    }
+
+    pub fn stats(&self) -> Vec<RemoteMediaHostStats> {
+        let mut stats = self
+            .hosts
+            .iter()
+            .map(|entry| RemoteMediaHostStats {
+                host: entry.key().clone(),
+                window_bytes: entry.window_bytes,
+                total_bytes: entry.total_bytes,
+                fetches: entry.fetches,
+                rejected: entry.rejected,
+            })
+            .collect::<Vec<_>>();
+        stats.sort_unstable_by(|a, b| b.total_bytes.cmp(&a.total_bytes));
+        stats
+    }
+}
+
+/// Checks the size of a response from another homeserver, and makes a body
+/// that counts the bytes going through it, stopping once the media gets larger
+/// than allowed.
+pub fn limit_body(
+    deps: Arc<Dependencies>,
+    host: SmolStr,
+    resp: http::Response<Body>,
+) -> Result<Body, ServerError> {
+    let max_size = deps
+        .config
+        .media
+        .remote
+        .max_file_size
+        .map(|size| size * MIB);
+    let content_length = resp
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok())
+        .and_then(|len| len.parse::<u64>().ok());
+    if let (Some(max_size), Some(len)) = (max_size, content_length) {
+        if len > max_size {
+            deps.remote_media.reject(&host);
+            return Err(ServerError::RemoteMediaTooLarge);
+        }
+    }
+
+    let mut fetched = 0;
+    let stream = resp.into_body().map(move |chunk| {
+        let chunk = chunk.map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        let len = chunk.len() as u64;
+        fetched += len;
+        deps.remote_media.record(&host, len);
+        if max_size.map_or(false, |max_size| fetched > max_size) {
+            deps.remote_media.reject(&host);
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                ServerError::RemoteMediaTooLarge.to_string(),
+            ));
+        }
+        Ok(chunk)
+    });
+    Ok(Body::wrap_stream(stream))
+}
+
+/// Gets media uploaded to another homeserver, from the cache if it was
+/// fetched before.
+pub async fn get_remote_file(
+    deps: Arc<Dependencies>,
+    host: SmolStr,
+    port: u16,
+    id: &str,
+) -> Result<(HeaderValue, HeaderValue, hyper::Body, HeaderValue), ServerError> {
+    let cache_key = make_remote_media_key(&host, id);
+    if let Some(cached_id) = deps.media_tree.get(&cache_key).await? {
+        // Safety: cached IDs are always ASCII, since they are made of a hash in hex
+        let cached_id = unsafe { std::str::from_utf8_unchecked(&cached_id) };
+        match download::get_file(deps.media_store.as_ref(), cached_id).await {
+            Err(ServerError::MediaNotFound) => {
+                deps.media_tree.remove(&cache_key).await?;
+            }
+            res => return res,
+        }
+    }
+
+    deps.remote_media
+        .start_fetch(&deps.config.media.remote, &host)?;
+
+    let url: Uri = format!("https://{}:{}/_harmony/media/download/{}", host, port, id)
+        .parse()
+        .map_err(|_| ServerError::InvalidFileId)?;
+    let mut request = http::Request::builder().method(Method::GET).uri(url);
+    // prove to the other homeserver that we are fetching this, if we federate
+    if let Some(keys) = deps.key_manager.as_ref() {
+        // make sure the other host is a homeserver we can verify
+        keys.get_key(host.clone()).await?;
+        let token = keys
+            .generate_token(AuthData {
+                server_id: deps.config.host.clone(),
+                time: get_time_secs(),
+            })
+            .await?;
+        let token = encode_protobuf_message::<Token>(&token).freeze();
+        request = request.header(header::AUTHORIZATION, unsafe {
+            HeaderValue::from_maybe_shared_unchecked(token)
+        });
+    }
+    let request = request
+        .body(Body::empty())
+        .map_err(|_| ServerError::InternalServerError)?;
+
+    let resp = deps.http.request(request).await?;
+    if resp.status().is_success().not() {
+        return Err(if resp.status() == StatusCode::NOT_FOUND {
+            ServerError::MediaNotFound
+        } else {
+            // TODO: proper error
+            ServerError::InternalServerError
+        });
+    }
+
+    let (name, mimetype) = extract_file_info_from_download_response(resp.headers())
+        .map(|(name, mimetype, _)| (name.to_string(), mimetype.clone()))
+        .map_err(|e| ServerError::FileExtractUnexpected(e.into()))?;
+    let body = limit_body(deps.clone(), host.clone(), resp)?;
+    let data = hyper::body::to_bytes(body)
+        .await
+        .map_err(|_| ServerError::RemoteMediaTooLarge)?;
+
+    // same layout as uploaded files, so the cached file can be served like them
+    let hash = sha3::Sha3_256::digest(&data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    let cached_id = format!("remote/{}", hash);
+    let store = deps.media_store.as_ref();
+    if store.exists(&cached_id).await?.not() {
+        let mut file = Vec::with_capacity(name.len() + mimetype.len() + 2 + data.len());
+        file.extend_from_slice(name.as_bytes());
+        file.push(SEPERATOR);
+        file.extend_from_slice(mimetype.as_bytes());
+        file.push(SEPERATOR);
+        file.extend_from_slice(&data);
+        store.put(&cached_id, reader_from_vec(file)).await?;
+    }
+    deps.media_tree
+        .insert(&cache_key, cached_id.as_bytes())
+        .await?;
+    info!("cached media {} from {} as {}", id, host, cached_id);
+
+    let len = HeaderValue::from(data.len());
+    // Safety: names come from a header value, which can only have ASCII characters
+    let disposition = unsafe { disposition_header(&name) };
+    Ok((disposition, mimetype, Body::from(data), len))
+}
@@ -0,0 +1,130 @@
+//! Endpoints a standby follows the primary with, under
+//! `/_scherzo/replication/`. Requests must have the replication secret from
+//! config in their `Authorization` header.
+//!
+//! - `snapshot` responds with the epoch of the primary's write log and the
+//! sequence number of its latest write, both as big endian `u64`s, followed
+//! by a dump of every tree.
+//! - `ops?epoch=<epoch>&after=<seq>` responds with the writes made after
+//! `seq`, waiting for new ones if there are none yet. It responds with
+//! `409 Conflict` if the log doesn't have them anymore, in which case the
+//! standby has to load a snapshot again.
+
+use std::convert::Infallible;
+
+use crate::{
+    db::{dump, op_log},
+    impls::replication::{MAX_OPS_PER_RESPONSE, OPS_WAIT},
+    rest_error_response,
+};
+
+use super::{admin::constant_time_eq, download::query_param, *};
+
+pub fn handler(deps: Arc<Dependencies>) -> ReplicationService {
+    ReplicationService { deps }
+}
+
+pub struct ReplicationService {
+    deps: Arc<Dependencies>,
+}
+
+impl Service<HttpRequest> for ReplicationService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+
+        Box::pin(async move {
+            if request.method() != Method::GET {
+                return Ok(rest_error_response(
+                    "method must be GET".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let op_log = deps.db.op_log();
+            let secret = match deps.config.replication.as_ref() {
+                Some(config) if op_log.is_enabled() => config.secret.as_str(),
+                _ => {
+                    return Ok(rest_error_response(
+                        "replication isn't enabled".to_string(),
+                        StatusCode::NOT_FOUND,
+                    ))
+                }
+            };
+            let authorized = request
+                .headers()
+                .get(header::AUTHORIZATION)
+                .map_or(false, |value| {
+                    secret.is_empty().not() && constant_time_eq(value.as_bytes(), secret.as_bytes())
+                });
+            if authorized.not() {
+                return Ok(rest_error_response(
+                    "invalid replication secret".to_string(),
+                    StatusCode::UNAUTHORIZED,
+                ));
+            }
+
+            let body = match request.uri().path() {
+                "/_scherzo/replication/snapshot" => {
+                    let (epoch, seq) = op_log.position();
+                    let mut body = [epoch.to_be_bytes(), seq.to_be_bytes()].concat();
+                    if let Err(err) = dump::write_archive(&deps.db, &mut body).await {
+                        return Ok(ServerError::from(err).into_rest_http_response());
+                    }
+                    body
+                }
+                "/_scherzo/replication/ops" => {
+                    let query = request.uri().query();
+                    let position = query_param(query, "epoch")
+                        .and_then(|epoch| epoch.parse::<u64>().ok())
+                        .zip(query_param(query, "after").and_then(|after| after.parse().ok()));
+                    let (epoch, after) = match position {
+                        Some(position) => position,
+                        None => {
+                            return Ok(rest_error_response(
+                                "epoch and after must be numbers".to_string(),
+                                StatusCode::BAD_REQUEST,
+                            ))
+                        }
+                    };
+                    match op_log
+                        .wait(epoch, after, MAX_OPS_PER_RESPONSE, OPS_WAIT)
+                        .await
+                    {
+                        Some(ops) => op_log::encode_ops(epoch, &ops),
+                        None => {
+                            return Ok(rest_error_response(
+                                "writes after this position aren't logged anymore".to_string(),
+                                StatusCode::CONFLICT,
+                            ))
+                        }
+                    }
+                }
+                _ => {
+                    return Ok(rest_error_response(
+                        "no such endpoint".to_string(),
+                        StatusCode::NOT_FOUND,
+                    ))
+                }
+            };
+
+            Ok(http::Response::builder()
+                .status(StatusCode::OK)
+                .header(
+                    http::header::CONTENT_TYPE,
+                    HeaderValue::from_static("application/octet-stream"),
+                )
+                .body(box_body(Body::from(body)))
+                .unwrap())
+        })
+    }
+}
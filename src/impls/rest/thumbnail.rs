@@ -0,0 +1,138 @@
+//! Thumbnails of uploaded images, in the sizes set in the media config.
+//!
+//! Thumbnails are generated when an image is uploaded, and lazily for images
+//! that were uploaded before a size was configured. The media tree records
+//! which thumbnails exist, so they are only encoded once.
+
+use db::media::{make_thumbnail_key, make_thumbnail_prefix, ThumbnailInfo};
+use image::GenericImageView;
+
+use crate::impls::rest::media_store::reader_from_vec;
+
+use super::{
+    download::{get_file, get_file_full, read_bufs},
+    *,
+};
+
+const FORMAT: image::ImageFormat = image::ImageFormat::Jpeg;
+
+pub fn thumbnail_id(id: &str, size: u32) -> String {
+    format!("{}_thumb{}", id, size)
+}
+
+/// Picks the smallest configured size that is at least as big as the requested one.
+fn pick_size(sizes: &[u32], requested: u32) -> Option<u32> {
+    sizes
+        .iter()
+        .copied()
+        .filter(|size| *size >= requested)
+        .min()
+}
+
+/// Generates thumbnails of the file with the given ID in all configured sizes,
+/// if it is an image.
+pub async fn generate_thumbnails(deps: &Dependencies, id: &str) -> Result<(), ServerError> {
+    let sizes = deps.config.media.thumbnail_sizes.clone();
+    if sizes.is_empty() {
+        return Ok(());
+    }
+
+    let (_, mimetype, data, _) = get_file_full(deps.media_store.as_ref(), id).await?;
+    if mimetype.starts_with("image/").not() {
+        return Ok(());
+    }
+
+    store_thumbnails(deps, id, data, sizes).await
+}
+
+async fn store_thumbnails(
+    deps: &Dependencies,
+    id: &str,
+    data: Vec<u8>,
+    sizes: Vec<u32>,
+) -> Result<(), ServerError> {
+    let thumbnails = tokio::task::spawn_blocking(move || {
+        let guessed_format = image::guess_format(&data).map_err(|_| ServerError::NotAnImage)?;
+        let image = image::load_from_memory_with_format(&data, guessed_format)
+            .map_err(|_| ServerError::NotAnImage)?;
+
+        let mut thumbnails = Vec::with_capacity(sizes.len());
+        for size in sizes {
+            let thumbnail = image.thumbnail(size, size);
+            let mut thumbnail_jpeg = Vec::new();
+            thumbnail
+                .write_to(&mut thumbnail_jpeg, FORMAT)
+                .map_err(|_| ServerError::InternalServerError)?;
+            let (width, height) = thumbnail.dimensions();
+            thumbnails.push((size, ThumbnailInfo { width, height }, thumbnail_jpeg));
+        }
+
+        Result::<_, ServerError>::Ok(thumbnails)
+    })
+    .await
+    .expect("task panicked")?;
+
+    for (size, info, thumbnail_jpeg) in thumbnails {
+        deps.media_store
+            .put(&thumbnail_id(id, size), reader_from_vec(thumbnail_jpeg))
+            .await?;
+        // only record the thumbnail after it was stored, so a failed write gets retried
+        deps.media_tree
+            .insert(&make_thumbnail_key(id, size), rkyv_ser(&info))
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Gets the thumbnail of the file with the given ID that best fits the requested size.
+///
+/// Serves the original file if it isn't an image, or if no configured size is big enough.
+pub async fn get_thumbnail(
+    deps: &Dependencies,
+    id: &str,
+    requested: u32,
+) -> Result<(HeaderValue, HeaderValue, hyper::Body, HeaderValue), ServerError> {
+    let store = deps.media_store.as_ref();
+    let Some(size) = pick_size(&deps.config.media.thumbnail_sizes, requested) else {
+        return get_file(store, id).await;
+    };
+
+    let thumbnail_key = make_thumbnail_key(id, size);
+    if deps.media_tree.contains_key(&thumbnail_key).await?.not() {
+        let StoredMedia { reader, .. } = store.get(id).await?;
+        let (_, mimetype_raw, _) = read_bufs(reader, false).await?;
+        if mimetype_raw.starts_with(b"image/").not() {
+            return get_file(store, id).await;
+        }
+
+        let (_, _, data, _) = get_file_full(store, id).await?;
+        store_thumbnails(deps, id, data, vec![size]).await?;
+    }
+
+    let StoredMedia { reader, len } = store.get(&thumbnail_id(id, size)).await?;
+    Ok((
+        HeaderValue::from_static("inline; filename=thumbnail.jpg"),
+        HeaderValue::from_static("image/jpeg"),
+        hyper::Body::wrap_stream(ReaderStream::new(reader)),
+        HeaderValue::from(len),
+    ))
+}
+
+/// Deletes all thumbnails of the file with the given ID.
+pub async fn delete_thumbnails(deps: &Dependencies, id: &str) -> Result<(), ServerError> {
+    let prefix = make_thumbnail_prefix(id);
+    let mut batch = Batch::default();
+    for res in deps.media_tree.scan_prefix(&prefix).await {
+        let (key, _) = res?;
+        let size = u32::from_be_bytes(
+            // Safety: thumbnail keys always end with the size after the prefix
+            unsafe { key[prefix.len()..].try_into().unwrap_unchecked() },
+        );
+        deps.media_store.delete(&thumbnail_id(id, size)).await?;
+        batch.remove(key);
+    }
+    deps.media_tree.apply_batch(batch).await?;
+
+    Ok(())
+}
@@ -34,9 +34,10 @@ impl Service<HttpRequest> for UploadService {
         let auth_res = deps.valid_sessions.auth_header_map(request.headers());
 
         let fut = async move {
-            if let Err(err) = auth_res {
-                return Ok(err.into_rest_http_response());
-            }
+            let user_id = match auth_res {
+                Ok(user_id) => user_id,
+                Err(err) => return Ok(err.into_rest_http_response()),
+            };
             let boundary_res = request
                 .headers()
                 .get(&header::CONTENT_TYPE)
@@ -65,13 +66,27 @@ impl Service<HttpRequest> for UploadService {
             match multipart.next_field().await {
                 Ok(maybe_field) => match maybe_field {
                     Some(field) => {
-                        let id =
-                            match write_file(deps.config.media.media_root.as_path(), field, None)
-                                .await
-                            {
-                                Ok(id) => id,
-                                Err(err) => return Ok(err.into_rest_http_response()),
-                            };
+                        let id = match write_file(deps.media_store.as_ref(), field).await {
+                            Ok(id) => id,
+                            Err(err) => return Ok(err.into_rest_http_response()),
+                        };
+                        if let Err(err) = media_access::set_owner(&deps, &id, user_id).await {
+                            return Ok(err.into_rest_http_response());
+                        }
+
+                        {
+                            let deps = deps.clone();
+                            let id = id.clone();
+                            tokio::spawn(async move {
+                                if let Err(err) = thumbnail::generate_thumbnails(&deps, &id).await {
+                                    tracing::error!(
+                                        "couldn't generate thumbnails for {}: {}",
+                                        id,
+                                        err
+                                    );
+                                }
+                            });
+                        }
 
                         Ok(http::Response::builder()
                             .status(StatusCode::OK)
@@ -91,24 +106,12 @@ impl Service<HttpRequest> for UploadService {
 }
 
 pub async fn write_file(
-    media_root: &Path,
+    store: &dyn MediaStore,
     mut part: multer::Field<'static>,
-    write_to: Option<PathBuf>,
 ) -> Result<SmolStr, ServerError> {
     let id = gen_rand_inline_str();
-    let path = write_to.unwrap_or_else(|| media_root.join(id.as_str()));
-    if path.exists() {
-        return Ok(id);
-    }
     let first_chunk = part.chunk().await?.ok_or(ServerError::MissingFiles)?;
 
-    let file = tokio::fs::OpenOptions::default()
-        .append(true)
-        .create(true)
-        .open(path)
-        .await?;
-    let mut buf_writer = BufWriter::new(file);
-
     // [tag:ascii_filename_upload]
     let name = part.file_name().unwrap_or("unknown");
     // [tag:ascii_mimetype_upload]
@@ -118,24 +121,41 @@ pub async fn write_file(
         .or_else(|| infer::get(&first_chunk).map(|t| t.mime_type()))
         .unwrap_or("application/octet-stream");
 
-    // Write prefix
-    buf_writer.write_all(name.as_bytes()).await?;
-    buf_writer.write_all(&[SEPERATOR]).await?;
-    buf_writer.write_all(content_type.as_bytes()).await?;
-    buf_writer.write_all(&[SEPERATOR]).await?;
-
-    // Write our first chunk
-    buf_writer.write_all(&first_chunk).await?;
+    // Write prefix, then our first chunk
+    let mut head = BytesMut::with_capacity(name.len() + content_type.len() + 2 + first_chunk.len());
+    head.extend_from_slice(name.as_bytes());
+    head.extend_from_slice(&[SEPERATOR]);
+    head.extend_from_slice(content_type.as_bytes());
+    head.extend_from_slice(&[SEPERATOR]);
+    head.extend_from_slice(&first_chunk);
+
+    let chunks = stream::once(future::ok(head.freeze())).chain(
+        part.map(|res| res.map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))),
+    );
+    store
+        .put(id.as_str(), Box::new(StreamReader::new(chunks.boxed())))
+        .await?;
 
-    // flush before starting to write other chunks
-    buf_writer.flush().await?;
+    Ok(id)
+}
 
-    while let Some(chunk) = part.chunk().await? {
-        buf_writer.write_all(&chunk).await?;
+/// Deletes a file from the media store, along with any files generated from it.
+pub async fn delete_file(deps: &Dependencies, id: &str) -> Result<(), ServerError> {
+    // ids we generate are always alphanumeric, this also makes sure
+    // the id can't be used to escape the media root
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(ServerError::InvalidFileId);
     }
 
-    // flush everything else
-    buf_writer.flush().await?;
+    for id in [
+        id.to_string(),
+        format!("{}_jpeg", id),
+        format!("{}_jpegthumb", id),
+    ] {
+        deps.media_store.delete(&id).await?;
+    }
+    thumbnail::delete_thumbnails(deps, id).await?;
+    media_access::delete_records(deps, id).await?;
 
-    Ok(id)
+    Ok(())
 }
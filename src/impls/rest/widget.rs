@@ -0,0 +1,343 @@
+//! Public widgets of guilds that opted in to having one, served at
+//! `/_scherzo/widget/<guild id>.<json | svg | html>` without authentication.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use ahash::RandomState;
+use dashmap::DashMap;
+use serde::Serialize;
+use tokio::sync::broadcast::error::RecvError;
+use tower::limit::{RateLimit, RateLimitLayer};
+
+use crate::{impls::bus::DomainEvent, rest_error_response};
+
+use super::{api::hrpc_error_response, *};
+
+/// How long a rendered widget is served before its data is loaded again.
+const WIDGET_CACHE_TTL: Duration = Duration::from_secs(60);
+/// How far back messages count as recent activity.
+const ACTIVITY_WINDOW_SECS: u64 = 24 * 60 * 60;
+const SECS_IN_HOUR: u64 = 60 * 60;
+
+pub fn handler(deps: Arc<Dependencies>) -> RateLimit<WidgetService> {
+    let activity = Arc::new(Activity::default());
+    spawn_activity_tracker(deps.clone(), activity.clone());
+
+    ServiceBuilder::new()
+        .layer(RateLimitLayer::new(20, Duration::from_secs(5)))
+        .service(WidgetService {
+            deps,
+            activity,
+            cache: Arc::new(DashMap::default()),
+        })
+}
+
+/// Messages sent in each guild recently, counted per hour. Kept in memory,
+/// so it starts over when the server restarts.
+#[derive(Default)]
+struct Activity {
+    guilds: DashMap<u64, GuildActivity, RandomState>,
+}
+
+#[derive(Default)]
+struct GuildActivity {
+    last_message_at: u64,
+    /// Message counts of the last hours, keyed by hours since unix epoch.
+    hourly: HashMap<u64, u32>,
+}
+
+fn spawn_activity_tracker(deps: Arc<Dependencies>, activity: Arc<Activity>) {
+    let mut events = deps.event_bus.subscribe();
+    tokio::spawn(async move {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            };
+            match &*event {
+                DomainEvent::MessageSent { guild_id, .. } => activity.record(*guild_id),
+                DomainEvent::GuildDeleted { guild_id, .. } => {
+                    activity.guilds.remove(guild_id);
+                }
+                _ => {}
+            }
+        }
+    });
+}
+
+impl Activity {
+    fn record(&self, guild_id: u64) {
+        let now = get_time_secs();
+        let hour = now / SECS_IN_HOUR;
+        let mut activity = self.guilds.entry(guild_id).or_default();
+        activity.last_message_at = now;
+        *activity.hourly.entry(hour).or_default() += 1;
+        let oldest = hour.saturating_sub(ACTIVITY_WINDOW_SECS / SECS_IN_HOUR);
+        activity.hourly.retain(|hour, _| *hour > oldest);
+    }
+
+    fn get(&self, guild_id: u64) -> RecentActivity {
+        let oldest =
+            (get_time_secs() / SECS_IN_HOUR).saturating_sub(ACTIVITY_WINDOW_SECS / SECS_IN_HOUR);
+        self.guilds
+            .get(&guild_id)
+            .map_or_else(RecentActivity::default, |activity| RecentActivity {
+                last_message_at: Some(activity.last_message_at),
+                messages_last_day: activity
+                    .hourly
+                    .iter()
+                    .filter(|(hour, _)| **hour > oldest)
+                    .map(|(_, count)| *count)
+                    .sum(),
+            })
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct RecentActivity {
+    /// In seconds since unix epoch. Not set if no messages were sent since
+    /// the server started.
+    pub last_message_at: Option<u64>,
+    pub messages_last_day: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WidgetInvite {
+    pub host: String,
+    pub invite_id: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GuildWidget {
+    pub guild_id: u64,
+    pub name: String,
+    pub picture: Option<String>,
+    pub member_count: u64,
+    pub online_count: u64,
+    pub recent_activity: RecentActivity,
+    /// Invite people seeing the widget can join the guild with.
+    pub invite: Option<WidgetInvite>,
+}
+
+impl GuildWidget {
+    async fn load(
+        deps: &Dependencies,
+        activity: &Activity,
+        guild_id: u64,
+    ) -> ServerResult<Option<Self>> {
+        let chat_tree = &deps.chat_tree;
+
+        let Some(settings) = chat_tree.get_widget_settings_logic(guild_id).await? else {
+            return Ok(None);
+        };
+        let guild = chat_tree.get_guild_logic(guild_id).await?;
+        let members = chat_tree.get_guild_members_logic(guild_id).await?.members;
+        let online_count = members
+            .iter()
+            .filter(|member_id| deps.presence.is_online(**member_id))
+            .count() as u64;
+
+        let mut invite = None;
+        if let Some(invite_id) = settings.invite_id {
+            // the invite might have been deleted after it was put on the widget
+            if chat_tree
+                .check_guild_invite(guild_id, &invite_id)
+                .await
+                .is_ok()
+            {
+                invite = Some(WidgetInvite {
+                    host: deps.config.host.clone(),
+                    invite_id,
+                });
+            }
+        }
+
+        Ok(Some(Self {
+            guild_id,
+            name: guild.name,
+            picture: guild.picture,
+            member_count: members.len() as u64,
+            online_count,
+            recent_activity: activity.get(guild_id),
+            invite,
+        }))
+    }
+
+    /// Renders a badge with the name of the guild and how many members are online.
+    fn render_svg(&self) -> String {
+        let name = escape_xml(&self.name);
+        let online = format!("{} online", self.online_count);
+        // rough text widths, good enough for a badge in a sans-serif font
+        let name_width = 10 + 7 * self.name.chars().count();
+        let online_width = 10 + 7 * online.chars().count();
+        let width = name_width + online_width;
+        format!(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="20" role="img" aria-label="{name}: {online}">
+<title>{name}: {online}</title>
+<rect width="{name_width}" height="20" fill="#555"/>
+<rect x="{name_width}" width="{online_width}" height="20" fill="#4c1"/>
+<g fill="#fff" font-family="Verdana,DejaVu Sans,sans-serif" font-size="11" text-anchor="middle">
+<text x="{name_x}" y="14">{name}</text>
+<text x="{online_x}" y="14">{online}</text>
+</g>
+</svg>"##,
+            width = width,
+            name_width = name_width,
+            online_width = online_width,
+            name = name,
+            online = online,
+            name_x = name_width / 2,
+            online_x = name_width + online_width / 2,
+        )
+    }
+
+    /// Renders a small page that can be embedded with an iframe.
+    fn render_html(&self) -> String {
+        let name = escape_xml(&self.name);
+        let invite = self.invite.as_ref().map_or_else(String::new, |invite| {
+            format!(
+                "<p>Join with invite <code>{}</code> on <code>{}</code></p>",
+                escape_xml(&invite.invite_id),
+                escape_xml(&invite.host)
+            )
+        });
+        format!(
+            "<!DOCTYPE html>\n\
+            <html><head><meta charset=\"utf-8\"><title>{name}</title></head>\n\
+            <body style=\"font-family: sans-serif\">\n\
+            <h3>{name}</h3>\n\
+            <p>{online} online, {members} members</p>\n\
+            <p>{messages} messages in the last day</p>\n\
+            {invite}\n\
+            </body></html>",
+            name = name,
+            online = self.online_count,
+            members = self.member_count,
+            messages = self.recent_activity.messages_last_day,
+            invite = invite,
+        )
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub struct WidgetService {
+    deps: Arc<Dependencies>,
+    activity: Arc<Activity>,
+    cache: Arc<DashMap<u64, (Instant, GuildWidget), RandomState>>,
+}
+
+impl Service<HttpRequest> for WidgetService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+        let activity = self.activity.clone();
+        let cache = self.cache.clone();
+
+        Box::pin(async move {
+            if request.method() != Method::GET {
+                return Ok(rest_error_response(
+                    "method must be GET".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let not_found = || {
+                rest_error_response(
+                    "guild doesn't have a widget".to_string(),
+                    StatusCode::NOT_FOUND,
+                )
+            };
+
+            let path = request.uri().path().trim_start_matches("/_scherzo/widget/");
+            let Some((guild_id, format)) = path
+                .split_once('.')
+                .and_then(|(id, format)| Some((id.parse::<u64>().ok()?, format)))
+            else {
+                return Ok(not_found());
+            };
+
+            let cached = cache
+                .get(&guild_id)
+                .filter(|entry| entry.0.elapsed() < WIDGET_CACHE_TTL)
+                .map(|entry| entry.1.clone());
+            let widget = match cached {
+                Some(widget) => widget,
+                None => match GuildWidget::load(&deps, &activity, guild_id).await {
+                    Ok(Some(widget)) => {
+                        cache.insert(guild_id, (Instant::now(), widget.clone()));
+                        widget
+                    }
+                    Ok(None) => {
+                        cache.remove(&guild_id);
+                        return Ok(not_found());
+                    }
+                    Err(err) => return Ok(hrpc_error_response(err)),
+                },
+            };
+
+            let (content_type, body) = match format {
+                "json" => ("application/json", serde_json::to_vec(&widget).unwrap()),
+                "svg" => ("image/svg+xml", widget.render_svg().into_bytes()),
+                "html" => (
+                    "text/html; charset=utf-8",
+                    widget.render_html().into_bytes(),
+                ),
+                _ => return Ok(not_found()),
+            };
+
+            Ok(http::Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, HeaderValue::from_static(content_type))
+                .header(
+                    header::CACHE_CONTROL,
+                    HeaderValue::from_static("public, max-age=60"),
+                )
+                .header(
+                    header::ACCESS_CONTROL_ALLOW_ORIGIN,
+                    HeaderValue::from_static("*"),
+                )
+                .body(box_body(Body::from(body)))
+                .unwrap())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn escapes_guild_names() {
+        assert_eq!(
+            escape_xml("<a href=\"x\">&'</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&#39;&lt;/a&gt;"
+        );
+    }
+}
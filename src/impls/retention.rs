@@ -0,0 +1,98 @@
+//! Deleting messages that are older than the configured retention.
+
+use std::time::Duration;
+
+use super::{bus::DomainEvent, get_time_secs, maintenance, prelude::*};
+
+const SECS_IN_DAY: u64 = 24 * 60 * 60;
+/// How many messages are deleted at once.
+const PRUNE_BATCH_SIZE: usize = 500;
+
+/// Gets how many days messages are kept in a guild, `None` if they are kept forever.
+pub async fn guild_retention_days(deps: &Dependencies, guild_id: u64) -> ServerResult<Option<u64>> {
+    let days = match deps.chat_tree.get_guild_retention_logic(guild_id).await? {
+        Some(days) => Some(days),
+        None => deps.config.policy.message_retention.days,
+    };
+    Ok(days.filter(|days| *days > 0))
+}
+
+/// Deletes the messages of a guild that are older than its retention,
+/// returning how many were deleted.
+pub async fn prune_guild(deps: &Dependencies, guild_id: u64) -> ServerResult<u64> {
+    let Some(days) = guild_retention_days(deps, guild_id).await? else {
+        return Ok(0);
+    };
+    let sent_before = get_time_secs().saturating_sub(days.saturating_mul(SECS_IN_DAY));
+
+    let chat_tree = &deps.chat_tree;
+    let mut pruned = 0;
+    for channel_id in chat_tree.get_guild_channel_ids_logic(guild_id).await? {
+        loop {
+            let messages = chat_tree
+                .prune_channel_messages_logic(guild_id, channel_id, sent_before, PRUNE_BATCH_SIZE)
+                .await?;
+            pruned += messages.len() as u64;
+            let done = messages.len() < PRUNE_BATCH_SIZE;
+            if !messages.is_empty() {
+                deps.event_bus.publish(DomainEvent::MessagesDeleted {
+                    guild_id,
+                    channel_id,
+                    message_ids: messages.into_iter().map(|(id, _)| id).collect(),
+                });
+            }
+            if done {
+                break;
+            }
+        }
+    }
+
+    Ok(pruned)
+}
+
+/// Deletes the messages of every guild that are older than its retention,
+/// returning how many were deleted.
+pub async fn prune_all(deps: &Dependencies) -> ServerResult<u64> {
+    // every guild that has messages has usage counters
+    let guild_ids = deps
+        .chat_tree
+        .get_all_guild_usage_logic()
+        .await?
+        .into_iter()
+        .filter(|(_, usage)| usage.message_count > 0)
+        .map(|(guild_id, _)| guild_id);
+
+    let mut pruned = 0;
+    for guild_id in guild_ids {
+        match prune_guild(deps, guild_id).await {
+            Ok(count) => pruned += count,
+            Err(err) => tracing::error!("couldn't prune messages of guild {}: {}", guild_id, err),
+        }
+    }
+
+    Ok(pruned)
+}
+
+/// Spawns a task that deletes old messages every `prune_interval` seconds,
+/// compacting the database if any were deleted.
+pub fn spawn_pruning_task(deps: Arc<Dependencies>) {
+    let interval = Duration::from_secs(deps.config.policy.message_retention.prune_interval.max(1));
+
+    tokio::spawn(async move {
+        tracing::info!("starting message retention task");
+        loop {
+            tokio::time::sleep(interval).await;
+
+            match prune_all(&deps).await {
+                Ok(0) => {}
+                Ok(pruned) => {
+                    tracing::info!("deleted {} messages past their retention", pruned);
+                    if let Err(err) = maintenance::compact(&deps.db).await {
+                        tracing::warn!("couldn't compact database after pruning: {}", err);
+                    }
+                }
+                Err(err) => tracing::error!("couldn't prune messages: {}", err),
+            }
+        }
+    });
+}
@@ -0,0 +1,225 @@
+//! Indexing messages in an external [Meilisearch](https://www.meilisearch.com) instance.
+//!
+//! Deleting a whole channel or guild uses filters, which need Meilisearch 1.2 or newer.
+
+use std::ops::Not;
+
+use hyper::{body::Bytes, http, Body, Method, Uri};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::config::MeilisearchConfig;
+
+use super::*;
+
+fn meili_err(err: impl std::fmt::Display) -> ServerError {
+    tracing::error!("meilisearch error: {}", err);
+    ServerError::InternalServerError
+}
+
+fn document_id(guild_id: u64, channel_id: u64, message_id: u64) -> String {
+    format!("{}-{}-{}", guild_id, channel_id, message_id)
+}
+
+#[derive(Serialize)]
+struct Document<'a> {
+    id: String,
+    guild_id: u64,
+    channel_id: u64,
+    message_id: u64,
+    author_id: u64,
+    created_at: u64,
+    text: &'a str,
+}
+
+#[derive(Deserialize)]
+struct SearchResponse {
+    hits: Vec<Hit>,
+}
+
+#[derive(Deserialize)]
+struct Hit {
+    guild_id: u64,
+    channel_id: u64,
+    message_id: u64,
+}
+
+pub struct MeilisearchBackend {
+    url: String,
+    api_key: Option<String>,
+    index: String,
+    http: HttpClient,
+}
+
+impl MeilisearchBackend {
+    pub fn new(config: &MeilisearchConfig, http: HttpClient) -> Result<Self, ServerError> {
+        let url = config.url.trim_end_matches('/').to_string();
+        url.parse::<Uri>().map_err(meili_err)?;
+
+        Ok(Self {
+            url,
+            api_key: config.api_key.clone(),
+            index: config.index.clone(),
+            http,
+        })
+    }
+
+    /// Sends a request to the index, returning the response body.
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> Result<Bytes, ServerError> {
+        let uri = format!("{}/indexes/{}/{}", self.url, self.index, path);
+        let mut builder = http::Request::builder().method(method).uri(uri);
+        if let Some(api_key) = &self.api_key {
+            builder = builder.header(http::header::AUTHORIZATION, format!("Bearer {}", api_key));
+        }
+        let body = match body {
+            Some(body) => {
+                builder = builder.header(http::header::CONTENT_TYPE, "application/json");
+                Body::from(serde_json::to_vec(&body).expect("must be valid json"))
+            }
+            None => Body::empty(),
+        };
+        let request = builder.body(body).map_err(meili_err)?;
+
+        let response = self.http.request(request).await.map_err(meili_err)?;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(meili_err)?;
+        if status.is_success().not() {
+            return Err(meili_err(format!(
+                "{} {}: {}",
+                status,
+                path,
+                String::from_utf8_lossy(&body)
+            )));
+        }
+
+        Ok(body)
+    }
+
+    async fn delete_by_filter(&self, filter: String) -> Result<(), ServerError> {
+        self.request(
+            Method::POST,
+            "documents/delete",
+            Some(json!({ "filter": filter })),
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+impl SearchBackend for MeilisearchBackend {
+    fn setup(&self) -> BoxFuture<'_, Result<(), ServerError>> {
+        Box::pin(async move {
+            // this also creates the index if it doesn't exist
+            self.request(
+                Method::PATCH,
+                "settings",
+                Some(json!({
+                    "searchableAttributes": ["text"],
+                    "filterableAttributes": ["guild_id", "channel_id"],
+                })),
+            )
+            .await?;
+            Ok(())
+        })
+    }
+
+    fn index_message(&self, message: IndexedMessage) -> BoxFuture<'_, Result<(), ServerError>> {
+        Box::pin(async move {
+            let document = Document {
+                id: document_id(message.guild_id, message.channel_id, message.message_id),
+                guild_id: message.guild_id,
+                channel_id: message.channel_id,
+                message_id: message.message_id,
+                author_id: message.author_id,
+                created_at: message.created_at,
+                text: &message.text,
+            };
+            self.request(
+                Method::POST,
+                "documents?primaryKey=id",
+                Some(json!([document])),
+            )
+            .await?;
+            Ok(())
+        })
+    }
+
+    fn remove_message(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+    ) -> BoxFuture<'_, Result<(), ServerError>> {
+        Box::pin(async move {
+            let path = format!(
+                "documents/{}",
+                document_id(guild_id, channel_id, message_id)
+            );
+            self.request(Method::DELETE, &path, None).await?;
+            Ok(())
+        })
+    }
+
+    fn remove_channel(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+    ) -> BoxFuture<'_, Result<(), ServerError>> {
+        Box::pin(self.delete_by_filter(format!(
+            "guild_id = {} AND channel_id = {}",
+            guild_id, channel_id
+        )))
+    }
+
+    fn remove_guild(&self, guild_id: u64) -> BoxFuture<'_, Result<(), ServerError>> {
+        Box::pin(self.delete_by_filter(format!("guild_id = {}", guild_id)))
+    }
+
+    fn search<'a>(
+        &'a self,
+        query: &'a SearchQuery,
+    ) -> BoxFuture<'a, Result<Vec<SearchHit>, ServerError>> {
+        Box::pin(async move {
+            if query.channel_ids.is_empty() {
+                return Ok(Vec::new());
+            }
+
+            let channels = query
+                .channel_ids
+                .iter()
+                .map(u64::to_string)
+                .collect::<Vec<_>>()
+                .join(", ");
+            let body = self
+                .request(
+                    Method::POST,
+                    "search",
+                    Some(json!({
+                        "q": query.text,
+                        "filter": format!("guild_id = {} AND channel_id IN [{}]", query.guild_id, channels),
+                        "limit": query.limit,
+                        "attributesToRetrieve": ["guild_id", "channel_id", "message_id"],
+                    })),
+                )
+                .await?;
+            let response: SearchResponse = serde_json::from_slice(&body).map_err(meili_err)?;
+
+            Ok(response
+                .hits
+                .into_iter()
+                .map(|hit| SearchHit {
+                    guild_id: hit.guild_id,
+                    channel_id: hit.channel_id,
+                    message_id: hit.message_id,
+                })
+                .collect())
+        })
+    }
+}
@@ -0,0 +1,238 @@
+//! Full text search of messages, backed by a search engine instead of the database.
+//!
+//! Messages are indexed by a task subscribed to the [event bus](super::bus),
+//! so the index follows everything the message write path publishes.
+
+use harmony_rust_sdk::api::chat::{content, Content, FormattedText};
+use hrpc::{
+    client::transport::http::hyper::HttpClient,
+    exports::futures_util::future::{self, BoxFuture},
+};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::config::SearchConfig;
+
+use super::{bus::DomainEvent, prelude::*};
+
+pub mod meilisearch;
+#[cfg(feature = "tantivy")]
+pub mod tantivy_index;
+
+/// A message, as it is stored in a search index.
+#[derive(Debug, Clone)]
+pub struct IndexedMessage {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    pub message_id: u64,
+    pub author_id: u64,
+    pub created_at: u64,
+    pub text: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchQuery {
+    pub guild_id: u64,
+    /// Channels to search in. Nothing is found if this is empty.
+    pub channel_ids: Vec<u64>,
+    pub text: String,
+    pub limit: usize,
+}
+
+/// A message that matched a search, best matches come first.
+#[derive(Debug, Clone, Copy)]
+pub struct SearchHit {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    pub message_id: u64,
+}
+
+pub trait SearchBackend: Send + Sync {
+    /// Prepares the backend for use, called once before anything is indexed.
+    fn setup(&self) -> BoxFuture<'_, Result<(), ServerError>> {
+        Box::pin(future::ok(()))
+    }
+    /// Adds a message to the index, replacing it if it's already indexed.
+    fn index_message(&self, message: IndexedMessage) -> BoxFuture<'_, Result<(), ServerError>>;
+    fn remove_message(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+    ) -> BoxFuture<'_, Result<(), ServerError>>;
+    fn remove_channel(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+    ) -> BoxFuture<'_, Result<(), ServerError>>;
+    fn remove_guild(&self, guild_id: u64) -> BoxFuture<'_, Result<(), ServerError>>;
+    fn search<'a>(
+        &'a self,
+        query: &'a SearchQuery,
+    ) -> BoxFuture<'a, Result<Vec<SearchHit>, ServerError>>;
+}
+
+/// Creates the search backend configured in `config`, if any.
+pub fn from_config(
+    config: &SearchConfig,
+    http: &HttpClient,
+) -> Result<Option<Box<dyn SearchBackend>>, ServerError> {
+    #[cfg(feature = "tantivy")]
+    if let Some(tantivy_config) = &config.tantivy {
+        return Ok(Some(Box::new(tantivy_index::TantivyBackend::new(
+            tantivy_config,
+        )?)));
+    }
+    #[cfg(not(feature = "tantivy"))]
+    if config.tantivy.is_some() {
+        tracing::warn!(
+            "tantivy search is configured, but scherzo wasn't built with the `tantivy` feature; ignoring it"
+        );
+    }
+
+    if let Some(meilisearch_config) = &config.meilisearch {
+        return Ok(Some(Box::new(meilisearch::MeilisearchBackend::new(
+            meilisearch_config,
+            http.clone(),
+        )?)));
+    }
+
+    Ok(None)
+}
+
+/// Gets the text of a message that should be searchable, if it has any.
+pub fn searchable_text(content: Option<&Content>) -> Option<&str> {
+    match content.and_then(|content| content.content.as_ref())? {
+        content::Content::TextMessage(content::TextContent {
+            content: Some(FormattedText { text, .. }),
+        }) => Some(text.as_str()),
+        _ => None,
+    }
+}
+
+/// Spawns a task that keeps the search index up to date with published message events.
+pub fn spawn_indexer(deps: Arc<Dependencies>) {
+    if deps.search.is_none() {
+        return;
+    }
+
+    let mut events = deps.event_bus.subscribe();
+    tokio::spawn(async move {
+        let Some(search) = deps.search.as_deref() else {
+            return;
+        };
+        if let Err(err) = search.setup().await {
+            tracing::error!("couldn't set up search backend: {}", err);
+            return;
+        }
+
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!(
+                        "search indexer lagged behind, {} events weren't indexed",
+                        missed
+                    );
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+            if let Err(err) = handle_event(&deps, search, &event).await {
+                tracing::error!("couldn't update search index: {}", err);
+            }
+        }
+    });
+}
+
+async fn handle_event(
+    deps: &Dependencies,
+    search: &dyn SearchBackend,
+    event: &DomainEvent,
+) -> Result<(), ServerError> {
+    match event {
+        DomainEvent::MessageSent {
+            guild_id,
+            channel_id,
+            message_id,
+            message,
+            ..
+        } => {
+            if let Some(text) = searchable_text(message.content.as_ref()) {
+                search
+                    .index_message(IndexedMessage {
+                        guild_id: *guild_id,
+                        channel_id: *channel_id,
+                        message_id: *message_id,
+                        author_id: message.author_id,
+                        created_at: message.created_at,
+                        text: text.to_string(),
+                    })
+                    .await?;
+            }
+        }
+        DomainEvent::MessageUpdated {
+            guild_id,
+            channel_id,
+            message_id,
+            ..
+        } => {
+            // the event doesn't have the author, so index what was stored
+            let message = match deps
+                .chat_tree
+                .get_message_logic(*guild_id, *channel_id, *message_id)
+                .await
+            {
+                Ok((message, _)) => message,
+                // it was deleted since
+                Err(_) => return Ok(()),
+            };
+            match searchable_text(message.content.as_ref()) {
+                Some(text) => {
+                    search
+                        .index_message(IndexedMessage {
+                            guild_id: *guild_id,
+                            channel_id: *channel_id,
+                            message_id: *message_id,
+                            author_id: message.author_id,
+                            created_at: message.created_at,
+                            text: text.to_string(),
+                        })
+                        .await?
+                }
+                None => {
+                    search
+                        .remove_message(*guild_id, *channel_id, *message_id)
+                        .await?
+                }
+            }
+        }
+        DomainEvent::MessageDeleted {
+            guild_id,
+            channel_id,
+            message_id,
+        } => {
+            search
+                .remove_message(*guild_id, *channel_id, *message_id)
+                .await?
+        }
+        DomainEvent::MessagesDeleted {
+            guild_id,
+            channel_id,
+            message_ids,
+        } => {
+            for message_id in message_ids {
+                search
+                    .remove_message(*guild_id, *channel_id, *message_id)
+                    .await?;
+            }
+        }
+        DomainEvent::ChannelDeleted {
+            guild_id,
+            channel_id,
+        } => search.remove_channel(*guild_id, *channel_id).await?,
+        DomainEvent::GuildDeleted { guild_id, .. } => search.remove_guild(*guild_id).await?,
+        _ => {}
+    }
+
+    Ok(())
+}
@@ -0,0 +1,224 @@
+//! Indexing messages in an embedded [Tantivy](https://github.com/quickwit-oss/tantivy) index.
+
+use parking_lot::Mutex;
+use tantivy::{
+    collector::TopDocs,
+    directory::MmapDirectory,
+    doc,
+    query::{BooleanQuery, Occur, Query, QueryParser, TermQuery},
+    schema::{Field, IndexRecordOption, Schema, FAST, INDEXED, STORED, STRING, TEXT},
+    Index, IndexReader, IndexWriter, ReloadPolicy, Term,
+};
+
+use crate::config::TantivyConfig;
+
+use super::*;
+
+/// Memory used by the index writer for buffering documents, in bytes.
+const WRITER_MEMORY: usize = 16 * 1024 * 1024;
+
+fn tantivy_err(err: impl std::fmt::Display) -> ServerError {
+    tracing::error!("tantivy error: {}", err);
+    ServerError::InternalServerError
+}
+
+#[derive(Clone, Copy)]
+struct Fields {
+    /// `guild_id/channel_id/message_id`, used to replace and delete single messages.
+    key: Field,
+    /// `guild_id/channel_id`, used to delete whole channels.
+    channel_key: Field,
+    guild_id: Field,
+    channel_id: Field,
+    message_id: Field,
+    author_id: Field,
+    created_at: Field,
+    text: Field,
+}
+
+fn message_key(guild_id: u64, channel_id: u64, message_id: u64) -> String {
+    format!("{}/{}/{}", guild_id, channel_id, message_id)
+}
+
+fn channel_key(guild_id: u64, channel_id: u64) -> String {
+    format!("{}/{}", guild_id, channel_id)
+}
+
+pub struct TantivyBackend {
+    index: Index,
+    reader: IndexReader,
+    writer: Arc<Mutex<IndexWriter>>,
+    fields: Fields,
+}
+
+impl TantivyBackend {
+    pub fn new(config: &TantivyConfig) -> Result<Self, ServerError> {
+        let mut builder = Schema::builder();
+        let fields = Fields {
+            key: builder.add_text_field("key", STRING),
+            channel_key: builder.add_text_field("channel_key", STRING),
+            guild_id: builder.add_u64_field("guild_id", INDEXED | STORED | FAST),
+            channel_id: builder.add_u64_field("channel_id", INDEXED | STORED | FAST),
+            message_id: builder.add_u64_field("message_id", STORED),
+            author_id: builder.add_u64_field("author_id", STORED),
+            created_at: builder.add_u64_field("created_at", STORED),
+            text: builder.add_text_field("text", TEXT),
+        };
+        let schema = builder.build();
+
+        std::fs::create_dir_all(&config.index_path)?;
+        let directory = MmapDirectory::open(&config.index_path).map_err(tantivy_err)?;
+        let index = Index::open_or_create(directory, schema).map_err(tantivy_err)?;
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::OnCommit)
+            .try_into()
+            .map_err(tantivy_err)?;
+        let writer = index.writer(WRITER_MEMORY).map_err(tantivy_err)?;
+
+        Ok(Self {
+            index,
+            reader,
+            writer: Arc::new(Mutex::new(writer)),
+            fields,
+        })
+    }
+
+    /// Runs `f` with the index writer on a blocking thread, and commits after it.
+    fn write<F>(&self, f: F) -> BoxFuture<'_, Result<(), ServerError>>
+    where
+        F: FnOnce(&mut IndexWriter, Fields) + Send + 'static,
+    {
+        let writer = self.writer.clone();
+        let fields = self.fields;
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || {
+                let mut writer = writer.lock();
+                f(&mut writer, fields);
+                writer.commit().map_err(tantivy_err)?;
+                Ok(())
+            })
+            .await
+            .expect("task panicked")
+        })
+    }
+}
+
+impl SearchBackend for TantivyBackend {
+    fn index_message(&self, message: IndexedMessage) -> BoxFuture<'_, Result<(), ServerError>> {
+        self.write(move |writer, fields| {
+            let key = message_key(message.guild_id, message.channel_id, message.message_id);
+            writer.delete_term(Term::from_field_text(fields.key, &key));
+            writer.add_document(doc!(
+                fields.key => key,
+                fields.channel_key => channel_key(message.guild_id, message.channel_id),
+                fields.guild_id => message.guild_id,
+                fields.channel_id => message.channel_id,
+                fields.message_id => message.message_id,
+                fields.author_id => message.author_id,
+                fields.created_at => message.created_at,
+                fields.text => message.text,
+            ));
+        })
+    }
+
+    fn remove_message(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+    ) -> BoxFuture<'_, Result<(), ServerError>> {
+        self.write(move |writer, fields| {
+            writer.delete_term(Term::from_field_text(
+                fields.key,
+                &message_key(guild_id, channel_id, message_id),
+            ));
+        })
+    }
+
+    fn remove_channel(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+    ) -> BoxFuture<'_, Result<(), ServerError>> {
+        self.write(move |writer, fields| {
+            writer.delete_term(Term::from_field_text(
+                fields.channel_key,
+                &channel_key(guild_id, channel_id),
+            ));
+        })
+    }
+
+    fn remove_guild(&self, guild_id: u64) -> BoxFuture<'_, Result<(), ServerError>> {
+        self.write(move |writer, fields| {
+            writer.delete_term(Term::from_field_u64(fields.guild_id, guild_id));
+        })
+    }
+
+    fn search<'a>(
+        &'a self,
+        query: &'a SearchQuery,
+    ) -> BoxFuture<'a, Result<Vec<SearchHit>, ServerError>> {
+        let index = self.index.clone();
+        let searcher = self.reader.searcher();
+        let fields = self.fields;
+        let query = query.clone();
+        Box::pin(async move {
+            if query.channel_ids.is_empty() {
+                return Ok(Vec::new());
+            }
+
+            tokio::task::spawn_blocking(move || {
+                let text_query = QueryParser::for_index(&index, vec![fields.text])
+                    .parse_query(&query.text)
+                    .map_err(|_| ServerError::InvalidSearchQuery)?;
+                let guild_query = TermQuery::new(
+                    Term::from_field_u64(fields.guild_id, query.guild_id),
+                    IndexRecordOption::Basic,
+                );
+                let channels_query = BooleanQuery::new(
+                    query
+                        .channel_ids
+                        .iter()
+                        .map(|channel_id| {
+                            let query: Box<dyn Query> = Box::new(TermQuery::new(
+                                Term::from_field_u64(fields.channel_id, *channel_id),
+                                IndexRecordOption::Basic,
+                            ));
+                            (Occur::Should, query)
+                        })
+                        .collect(),
+                );
+                let query_all = BooleanQuery::new(vec![
+                    (Occur::Must, text_query),
+                    (Occur::Must, Box::new(guild_query)),
+                    (Occur::Must, Box::new(channels_query)),
+                ]);
+
+                let top_docs = searcher
+                    .search(&query_all, &TopDocs::with_limit(query.limit))
+                    .map_err(tantivy_err)?;
+                let mut hits = Vec::with_capacity(top_docs.len());
+                for (_, address) in top_docs {
+                    let doc = searcher.doc(address).map_err(tantivy_err)?;
+                    let get = |field| doc.get_first(field).and_then(|value| value.u64_value());
+                    if let (Some(guild_id), Some(channel_id), Some(message_id)) = (
+                        get(fields.guild_id),
+                        get(fields.channel_id),
+                        get(fields.message_id),
+                    ) {
+                        hits.push(SearchHit {
+                            guild_id,
+                            channel_id,
+                            message_id,
+                        });
+                    }
+                }
+
+                Ok(hits)
+            })
+            .await
+            .expect("task panicked")
+        })
+    }
+}
@@ -0,0 +1,211 @@
+//! Tracking the stages a host goes through while it starts.
+//!
+//! Every stage is logged with how long it took. A required stage failing
+//! stops the server, while an optional one, like checking that voice works,
+//! is only logged and reported. The host is ready once every required stage
+//! completed, which `/_scherzo/ready` reports.
+
+use std::{
+    fmt::Display,
+    future::Future,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use tracing::{error, info, warn};
+use triomphe::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StageStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StageReport {
+    pub name: &'static str,
+    pub required: bool,
+    pub status: StageStatus,
+    /// How long the stage took, or has taken so far, in milliseconds.
+    pub duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip)]
+    started: Instant,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StartupReport {
+    pub ready: bool,
+    pub stages: Vec<StageReport>,
+}
+
+/// The stages of a host, shared with the readiness endpoint.
+#[derive(Debug, Clone)]
+pub struct Startup {
+    inner: Arc<StartupInner>,
+}
+
+#[derive(Debug)]
+struct StartupInner {
+    host: String,
+    started: Instant,
+    stages: Mutex<Vec<StageReport>>,
+    ready: AtomicBool,
+}
+
+impl Startup {
+    pub fn new(host: impl Into<String>) -> Self {
+        Self {
+            inner: Arc::new(StartupInner {
+                host: host.into(),
+                started: Instant::now(),
+                stages: Mutex::new(Vec::new()),
+                ready: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    fn begin(&self, name: &'static str, required: bool) -> usize {
+        info!("[{}] starting stage {}", self.inner.host, name);
+        let mut stages = self.inner.stages.lock();
+        stages.push(StageReport {
+            name,
+            required,
+            status: StageStatus::Running,
+            duration_ms: 0,
+            error: None,
+            started: Instant::now(),
+        });
+        stages.len() - 1
+    }
+
+    fn finish(&self, index: usize, error: Option<String>) -> Duration {
+        let mut stages = self.inner.stages.lock();
+        let stage = &mut stages[index];
+        let took = stage.started.elapsed();
+        stage.duration_ms = took.as_millis() as u64;
+        stage.status = match error {
+            Some(_) => StageStatus::Failed,
+            None => StageStatus::Completed,
+        };
+        match (&error, stage.required) {
+            (None, _) => info!(
+                "[{}] stage {} completed in {:?}",
+                self.inner.host, stage.name, took
+            ),
+            (Some(err), true) => error!(
+                "[{}] required stage {} failed after {:?}: {}",
+                self.inner.host, stage.name, took, err
+            ),
+            (Some(err), false) => warn!(
+                "[{}] optional stage {} failed after {:?}, continuing without it: {}",
+                self.inner.host, stage.name, took, err
+            ),
+        }
+        stage.error = error;
+        took
+    }
+
+    /// Runs a required stage. If it fails, the process exits.
+    pub fn stage<T, E: Display>(&self, name: &'static str, f: impl FnOnce() -> Result<T, E>) -> T {
+        let index = self.begin(name, true);
+        match f() {
+            Ok(val) => {
+                self.finish(index, None);
+                val
+            }
+            Err(err) => {
+                self.finish(index, Some(err.to_string()));
+                std::process::exit(1);
+            }
+        }
+    }
+
+    /// Runs an optional stage in the background. Failing only gets reported,
+    /// and the host doesn't wait for it to be ready.
+    pub fn spawn_optional<E: Display>(
+        &self,
+        name: &'static str,
+        fut: impl Future<Output = Result<(), E>> + Send + 'static,
+    ) {
+        let index = self.begin(name, false);
+        let this = self.clone();
+        tokio::spawn(async move {
+            let res = tokio::spawn(fut).await;
+            let error = match res {
+                Ok(Ok(())) => None,
+                Ok(Err(err)) => Some(err.to_string()),
+                Err(err) => Some(format!("stage panicked: {}", err)),
+            };
+            this.finish(index, error);
+        });
+    }
+
+    /// Marks the host as ready. Every required stage must have completed.
+    pub fn mark_ready(&self) {
+        let stages = self.inner.stages.lock();
+        debug_assert!(stages
+            .iter()
+            .all(|stage| !stage.required || stage.status == StageStatus::Completed));
+        self.inner.ready.store(true, Ordering::Release);
+        info!(
+            "[{}] ready after {:?}, went through {} stages",
+            self.inner.host,
+            self.inner.started.elapsed(),
+            stages.len()
+        );
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.inner.ready.load(Ordering::Acquire)
+    }
+
+    pub fn report(&self) -> StartupReport {
+        let mut stages = self.inner.stages.lock().clone();
+        for stage in &mut stages {
+            if stage.status == StageStatus::Running {
+                stage.duration_ms = stage.started.elapsed().as_millis() as u64;
+            }
+        }
+        StartupReport {
+            ready: self.is_ready(),
+            stages,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn optional_stage_failing_doesnt_stop_readiness() {
+        let startup = Startup::new("test");
+        let value = startup.stage("database", || Ok::<_, String>(1));
+        assert_eq!(value, 1);
+        assert!(!startup.is_ready());
+
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        startup.spawn_optional("voice", async move {
+            let _ = rx.await;
+            Err("no workers")
+        });
+        startup.mark_ready();
+        assert!(startup.is_ready());
+        assert_eq!(startup.report().stages[1].status, StageStatus::Running);
+
+        tx.send(()).unwrap();
+        while startup.report().stages[1].status == StageStatus::Running {
+            tokio::task::yield_now().await;
+        }
+        let report = startup.report();
+        assert!(report.ready);
+        assert_eq!(report.stages[1].status, StageStatus::Failed);
+        assert_eq!(report.stages[1].error.as_deref(), Some("no workers"));
+    }
+}
@@ -0,0 +1,278 @@
+//! Dead-letter queue for federation events that couldn't be delivered.
+//!
+//! Events that can't be pushed to a host, even after retrying, are queued for
+//! the host to pull.
+//! If the host doesn't pull them for `dead_letter_after_hours`, they are moved
+//! to the dead-letter queue, where an admin can inspect them and requeue them
+//! once the host is reachable again.
+
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::impls::gen_rand_u64;
+
+use super::*;
+
+/// How often queues are checked for events that waited too long.
+const DEAD_LETTER_CHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+const SECS_IN_HOUR: u64 = 60 * 60;
+
+fn dead_letter_after_hours(deps: &Dependencies) -> u64 {
+    deps.config
+        .federation
+        .as_ref()
+        .map_or(0, |conf| conf.dead_letter_after_hours)
+}
+
+/// Records that events started waiting in the queue of a host, unless some already were.
+pub(super) async fn mark_queued(deps: &Dependencies, host: &str) -> Result<(), ServerError> {
+    let key = make_queued_since_key(host);
+    if !deps.sync_tree.contains_key(&key).await? {
+        deps.sync_tree
+            .insert(&key, get_time_secs().to_be_bytes())
+            .await?;
+    }
+    Ok(())
+}
+
+/// Records that a host pulled the events in its queue.
+pub(super) async fn clear_queued(deps: &Dependencies, host: &str) -> Result<(), ServerError> {
+    deps.sync_tree.remove(&make_queued_since_key(host)).await?;
+    Ok(())
+}
+
+/// Spawns a task that moves events that waited too long in a queue to the dead-letter queue.
+pub fn spawn_dead_letterer(deps: Arc<Dependencies>) {
+    let after_hours = dead_letter_after_hours(&deps);
+    if after_hours == 0 {
+        return;
+    }
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(DEAD_LETTER_CHECK_INTERVAL).await;
+            let cutoff = get_time_secs().saturating_sub(after_hours * SECS_IN_HOUR);
+            if let Err(err) = dead_letter_stale_queues(&deps, cutoff, after_hours).await {
+                error!(
+                    "couldn't move stale federation events to dead letters: {}",
+                    err
+                );
+            }
+        }
+    });
+}
+
+/// Moves the queues of hosts that haven't pulled them since `cutoff` to the dead-letter queue.
+async fn dead_letter_stale_queues(
+    deps: &Dependencies,
+    cutoff: u64,
+    after_hours: u64,
+) -> Result<(), ServerError> {
+    let mut stale_hosts = Vec::new();
+    for res in deps.sync_tree.scan_prefix(QUEUED_SINCE_PREFIX).await {
+        let (key, value) = res?;
+        let queued_since = value.as_ref().try_into().map_or(0, u64::from_be_bytes);
+        if queued_since <= cutoff {
+            let (_, host_raw) = key.split_at(QUEUED_SINCE_PREFIX.len());
+            stale_hosts.push(String::from_utf8_lossy(host_raw).into_owned());
+        }
+    }
+
+    let failed_at = get_time_secs();
+    let reason = format!(
+        "host didn't pull its queued events for {} hours",
+        after_hours
+    );
+    for host in stale_hosts {
+        let mut batch = Batch::default();
+        let mut moved = 0_usize;
+        for res in deps
+            .sync_tree
+            .scan_prefix(&make_pull_queue_prefix(&host))
+            .await
+        {
+            let (key, value) = res?;
+            let dead_letter = DeadLetter {
+                host: host.clone(),
+                reason: reason.clone(),
+                failed_at,
+                event: db::deser_outbox_event(value),
+            };
+            batch.insert(make_dead_letter_key(gen_rand_u64()), rkyv_ser(&dead_letter));
+            // the host key is kept, so its events are still pulled
+            batch.remove(key);
+            moved += 1;
+        }
+        batch.remove(make_queued_since_key(&host));
+        deps.sync_tree.apply_batch(batch).await?;
+
+        tracing::warn!("moved {} queued events for {} to dead letters", moved, host);
+    }
+
+    Ok(())
+}
+
+fn describe_event(event: &Event) -> String {
+    match &event.kind {
+        Some(Kind::UserAddedToGuild(UserAddedToGuild { user_id, guild_id })) => {
+            format!("user {} added to guild {}", user_id, guild_id)
+        }
+        Some(Kind::UserRemovedFromGuild(UserRemovedFromGuild { user_id, guild_id })) => {
+            format!("user {} removed from guild {}", user_id, guild_id)
+        }
+        Some(Kind::UserInvited(_)) | Some(Kind::UserRejectedInvite(_)) => {
+            "invite event".to_string()
+        }
+        None => "empty event".to_string(),
+    }
+}
+
+/// Which dead letters to act on. Nothing is selected if none of the fields are set.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct DeadLetterSelection {
+    /// Select every dead letter.
+    #[serde(default)]
+    pub all: bool,
+    /// Select the dead letters for this host.
+    #[serde(default)]
+    pub host: Option<String>,
+    /// Select the dead letters with these IDs.
+    #[serde(default)]
+    pub ids: Vec<u64>,
+}
+
+impl DeadLetterSelection {
+    fn is_empty(&self) -> bool {
+        !self.all && self.host.is_none() && self.ids.is_empty()
+    }
+
+    fn matches(&self, id: u64, dead_letter: &DeadLetter) -> bool {
+        self.all
+            || self.host.as_deref() == Some(dead_letter.host.as_str())
+            || self.ids.contains(&id)
+    }
+}
+
+impl FromStr for DeadLetterSelection {
+    type Err = ();
+
+    /// Parses a selection from space separated `all`, `host=<host>` and dead letter ID arguments.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut selection = DeadLetterSelection::default();
+        for arg in s.split_whitespace() {
+            match arg.split_once('=') {
+                None if arg == "all" => selection.all = true,
+                None => selection.ids.push(arg.parse().map_err(|_| ())?),
+                Some(("host", host)) => selection.host = Some(host.to_string()),
+                _ => return Err(()),
+            }
+        }
+        Ok(selection)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeadLetterInfo {
+    pub id: u64,
+    pub host: String,
+    pub reason: String,
+    pub failed_at: u64,
+    pub description: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeadLetterReport {
+    /// Number of dead letters that were requeued or discarded.
+    pub count: u64,
+}
+
+async fn get_dead_letters(deps: &Dependencies) -> Result<Vec<(u64, DeadLetter)>, ServerError> {
+    let mut all = Vec::new();
+    for res in deps.sync_tree.scan_prefix(DEAD_LETTER_PREFIX).await {
+        let (key, value) = res?;
+        // Safety: dead letter keys are always a prefix followed by a u64
+        let id = u64::from_be_bytes(unsafe {
+            key.split_at(DEAD_LETTER_PREFIX.len())
+                .1
+                .try_into()
+                .unwrap_unchecked()
+        });
+        all.push((id, db::deser_dead_letter(value)));
+    }
+    Ok(all)
+}
+
+/// Lists the dead letters matching the selection, or all of them if nothing
+/// is selected, oldest first.
+pub async fn list_dead_letters(
+    deps: &Dependencies,
+    selection: DeadLetterSelection,
+) -> ServerResult<Vec<DeadLetterInfo>> {
+    let everything = selection.is_empty();
+    let mut dead_letters = get_dead_letters(deps)
+        .await?
+        .into_iter()
+        .filter(|(id, dead_letter)| everything || selection.matches(*id, dead_letter))
+        .map(|(id, dead_letter)| DeadLetterInfo {
+            id,
+            description: describe_event(&dead_letter.event),
+            host: dead_letter.host,
+            reason: dead_letter.reason,
+            failed_at: dead_letter.failed_at,
+        })
+        .collect::<Vec<_>>();
+    dead_letters.sort_unstable_by_key(|dead_letter| (dead_letter.failed_at, dead_letter.id));
+    Ok(dead_letters)
+}
+
+/// Sends the dead letters matching the selection to their hosts again. The
+/// ones that still can't be pushed are queued for the host to pull.
+pub async fn requeue_dead_letters(
+    deps: &Dependencies,
+    selection: DeadLetterSelection,
+) -> ServerResult<DeadLetterReport> {
+    let mut batch = Batch::default();
+    let mut dispatches = Vec::new();
+    for (id, dead_letter) in get_dead_letters(deps).await? {
+        if selection.matches(id, &dead_letter) {
+            batch.remove(make_dead_letter_key(id));
+            dispatches.push(EventDispatch {
+                host: dead_letter.host.into(),
+                event: dead_letter.event,
+            });
+        }
+    }
+    deps.sync_tree
+        .apply_batch(batch)
+        .await
+        .map_err(ServerError::from)?;
+
+    let count = dispatches.len() as u64;
+    for dispatch in dispatches {
+        drop(deps.fed_event_dispatcher.send(dispatch));
+    }
+
+    Ok(DeadLetterReport { count })
+}
+
+/// Removes the dead letters matching the selection for good.
+pub async fn discard_dead_letters(
+    deps: &Dependencies,
+    selection: DeadLetterSelection,
+) -> ServerResult<DeadLetterReport> {
+    let mut batch = Batch::default();
+    let mut count = 0;
+    for (id, dead_letter) in get_dead_letters(deps).await? {
+        if selection.matches(id, &dead_letter) {
+            batch.remove(make_dead_letter_key(id));
+            count += 1;
+        }
+    }
+    deps.sync_tree
+        .apply_batch(batch)
+        .await
+        .map_err(ServerError::from)?;
+
+    Ok(DeadLetterReport { count })
+}
@@ -0,0 +1,190 @@
+//! Federation allow and block lists that can be changed while the server runs.
+//!
+//! The lists start out as the ones in the federation config. Hosts added with
+//! the `federation` console command are kept in the database and added on
+//! top of them, and only those can be removed again with the command.
+
+use std::str::FromStr;
+
+use dashmap::DashSet;
+use serde::Serialize;
+
+use crate::config::FederationConfig;
+
+use super::*;
+
+type HostSet = DashSet<SmolStr, RandomState>;
+
+#[derive(Default)]
+pub struct HostLists {
+    allowed: HostSet,
+    blocked: HostSet,
+    /// Hosts that are in a list because of config, so they can't be removed.
+    from_config: HostSet,
+}
+
+impl HostLists {
+    pub async fn load(config: Option<&FederationConfig>, sync_tree: &Tree) -> DbResult<Self> {
+        let lists = HostLists::default();
+        if let Some(config) = config {
+            for host in &config.host_allow_list {
+                lists.allowed.insert(host.into());
+                lists.from_config.insert(host.into());
+            }
+            for host in &config.host_block_list {
+                lists.blocked.insert(host.into());
+                lists.from_config.insert(host.into());
+            }
+        }
+        for (prefix, list) in [
+            (ALLOWED_HOST_PREFIX, &lists.allowed),
+            (BLOCKED_HOST_PREFIX, &lists.blocked),
+        ] {
+            for res in sync_tree.scan_prefix(prefix).await {
+                let (key, _) = res?;
+                let host = String::from_utf8_lossy(&key[prefix.len()..]);
+                list.insert(host.into());
+            }
+        }
+        Ok(lists)
+    }
+
+    /// Checks a host against the lists. If the allow list isn't empty, only
+    /// hosts in it are allowed.
+    pub fn check(&self, host: &str) -> Result<(), ServerError> {
+        (self.allowed.contains(host) || (self.allowed.is_empty() && !self.blocked.contains(host)))
+            .then(|| ())
+            .ok_or(ServerError::HostNotAllowed)
+    }
+
+    fn sorted(list: &HostSet) -> Vec<String> {
+        let mut hosts = list.iter().map(|host| host.to_string()).collect::<Vec<_>>();
+        hosts.sort_unstable();
+        hosts
+    }
+}
+
+/// A change to the federation lists, from the `federation` console command.
+#[derive(Debug, Clone)]
+pub enum HostListAction {
+    Show,
+    Allow(String),
+    Unallow(String),
+    Block(String),
+    Unblock(String),
+}
+
+impl FromStr for HostListAction {
+    type Err = ();
+
+    /// Parses `list`, or one of `allow`, `unallow`, `block` and `unblock`
+    /// followed by a host.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut args = s.split_whitespace();
+        let action = match (args.next(), args.next()) {
+            (Some("list"), None) => HostListAction::Show,
+            (Some("allow"), Some(host)) => HostListAction::Allow(host.to_string()),
+            (Some("unallow"), Some(host)) => HostListAction::Unallow(host.to_string()),
+            (Some("block"), Some(host)) => HostListAction::Block(host.to_string()),
+            (Some("unblock"), Some(host)) => HostListAction::Unblock(host.to_string()),
+            _ => return Err(()),
+        };
+        if args.next().is_some() {
+            return Err(());
+        }
+        Ok(action)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct HostListsReport {
+    pub allowed: Vec<String>,
+    pub blocked: Vec<String>,
+    /// Hosts trusted by redeeming a federation invite. These are allowed
+    /// unless they are blocked.
+    pub trusted: Vec<String>,
+}
+
+/// Runs a `federation` console command, returning what to show to the admin.
+pub async fn run_action(deps: &Dependencies, action: HostListAction) -> ServerResult<String> {
+    let lists = &deps.host_lists;
+    let (host, list, other_list, key, other_key, name) = match &action {
+        HostListAction::Show => {
+            let report = HostListsReport {
+                allowed: HostLists::sorted(&lists.allowed),
+                blocked: HostLists::sorted(&lists.blocked),
+                trusted: HostLists::sorted(&deps.trusted_hosts),
+            };
+            return Ok(serde_json::to_string_pretty(&report).unwrap());
+        }
+        HostListAction::Allow(host) | HostListAction::Unallow(host) => (
+            host,
+            &lists.allowed,
+            &lists.blocked,
+            make_allowed_host_key(host),
+            make_blocked_host_key(host),
+            "allow",
+        ),
+        HostListAction::Block(host) | HostListAction::Unblock(host) => (
+            host,
+            &lists.blocked,
+            &lists.allowed,
+            make_blocked_host_key(host),
+            make_allowed_host_key(host),
+            "block",
+        ),
+    };
+
+    if lists.from_config.contains(host.as_str()) {
+        return Ok(format!(
+            "`{}` is set in the federation config, change it there instead",
+            host
+        ));
+    }
+
+    let adding = matches!(action, HostListAction::Allow(_) | HostListAction::Block(_));
+    let mut batch = Batch::default();
+    if adding {
+        // a host can only be in one of the lists
+        batch.remove(other_key);
+        batch.insert(key, []);
+    } else {
+        batch.remove(key);
+    }
+    deps.sync_tree.apply_batch(batch).await?;
+
+    if adding {
+        other_list.remove(host.as_str());
+        list.insert(host.into());
+        if matches!(action, HostListAction::Block(_)) {
+            trust::untrust_host(deps, host).await?;
+        }
+        tracing::info!("added {} to the federation {} list", host, name);
+        Ok(format!("added `{}` to the {} list", host, name))
+    } else if list.remove(host.as_str()).is_some() {
+        tracing::info!("removed {} from the federation {} list", host, name);
+        Ok(format!("removed `{}` from the {} list", host, name))
+    } else {
+        Ok(format!("`{}` isn't in the {} list", host, name))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_actions() {
+        assert!(matches!(
+            "list".parse::<HostListAction>(),
+            Ok(HostListAction::Show)
+        ));
+        assert!(matches!(
+            " block example.org".parse::<HostListAction>(),
+            Ok(HostListAction::Block(host)) if host == "example.org"
+        ));
+        assert!("allow".parse::<HostListAction>().is_err());
+        assert!("allow a b".parse::<HostListAction>().is_err());
+        assert!("trust example.org".parse::<HostListAction>().is_err());
+    }
+}
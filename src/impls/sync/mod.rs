@@ -3,14 +3,23 @@
 use std::time::Duration;
 
 use ahash::RandomState;
-use dashmap::{mapref::one::RefMut, DashMap};
+use dashmap::DashMap;
 use harmony_rust_sdk::api::{
-    exports::hrpc::exports::futures_util::TryFutureExt,
+    exports::{
+        hrpc::exports::futures_util::TryFutureExt,
+        prost::bytes::{Bytes, BytesMut},
+    },
     harmonytypes::Token,
     sync::{event::*, postbox_service_client::PostboxServiceClient, *},
 };
-use hrpc::{client::transport::http::Hyper, encode::encode_protobuf_message};
-use hyper::{http::HeaderValue, Uri};
+use hrpc::{
+    body::Body, client::transport::http::Hyper, encode::encode_protobuf_message,
+    exports::futures_util::StreamExt, request, response,
+};
+use hyper::{
+    http::{HeaderMap, HeaderValue},
+    Uri,
+};
 use tokio::sync::mpsc::UnboundedReceiver;
 use tracing::error;
 
@@ -19,9 +28,61 @@ use crate::key::{self, Manager as KeyManager};
 use super::{get_time_secs, http, prelude::*};
 use db::sync::*;
 
+pub mod dead_letter;
+pub mod host_lists;
 pub mod notify_new_id;
+pub mod outbox;
 pub mod pull;
 pub mod push;
+pub mod replay;
+pub mod trust;
+
+/// Header with the signature of a federation payload, in hex.
+const PAYLOAD_SIGNATURE_HEADER: &str = "x-scherzo-payload-signature";
+/// Header with the time a federation payload was signed at, in seconds.
+const PAYLOAD_TIME_HEADER: &str = "x-scherzo-payload-time";
+/// How far the signing time of a payload can be from ours, in seconds.
+/// Signatures are remembered for this long, so payloads can't be replayed.
+const PAYLOAD_MAX_AGE_SECS: u64 = 5 * 60;
+
+/// What is signed for a federation payload: the host it's sent to, so it
+/// can't be replayed to other hosts, the time it was signed at, and then the
+/// body exactly as it is sent.
+fn signed_payload(to_host: &str, time: u64, body: &[u8]) -> Vec<u8> {
+    [to_host.as_bytes(), &[0], &time.to_be_bytes(), body].concat()
+}
+
+/// Fails if a payload wasn't signed within [`PAYLOAD_MAX_AGE_SECS`] of `now`.
+fn check_payload_time(time: u64, now: u64) -> Result<(), ServerError> {
+    if time.max(now) - time.min(now) > PAYLOAD_MAX_AGE_SECS {
+        return Err(ServerError::InvalidEventSignature);
+    }
+    Ok(())
+}
+
+/// Reads a whole request or response body, so its signature can be checked
+/// before it is decoded.
+async fn read_body(mut body: Body) -> Result<Bytes, HrpcServerError> {
+    let mut raw = BytesMut::new();
+    while let Some(chunk) = body.next().await {
+        raw.extend_from_slice(&chunk?);
+    }
+    Ok(raw.freeze())
+}
+
+/// A signed federation payload, ready to be put in a request or response.
+struct SignedPayload {
+    body: Bytes,
+    signature: HeaderValue,
+    time: HeaderValue,
+}
+
+impl SignedPayload {
+    fn insert_headers(&self, headers: &mut HeaderMap) {
+        headers.insert(PAYLOAD_SIGNATURE_HEADER, self.signature.clone());
+        headers.insert(PAYLOAD_TIME_HEADER, self.time.clone());
+    }
+}
 
 pub struct EventDispatch {
     pub host: SmolStr,
@@ -31,134 +92,90 @@ pub struct EventDispatch {
 struct Clients(DashMap<SmolStr, PostboxServiceClient<Hyper>, RandomState>);
 
 impl Clients {
-    fn get_client(
-        &self,
-        host: SmolStr,
-    ) -> RefMut<'_, SmolStr, PostboxServiceClient<Hyper>, RandomState> {
-        self.0.entry(host.clone()).or_insert_with(|| {
-            // TODO: Handle url parsing error
-            let host_url: Uri = host.parse().unwrap();
-
-            PostboxServiceClient::new_transport(Hyper::new(host_url).unwrap())
-        })
+    /// Gets the client of a host. Clients are cloned, so the map isn't locked
+    /// while requests are made with them.
+    fn get_client(&self, host: SmolStr) -> PostboxServiceClient<Hyper> {
+        self.0
+            .entry(host.clone())
+            .or_insert_with(|| {
+                // TODO: Handle url parsing error
+                let host_url: Uri = host.parse().unwrap();
+
+                PostboxServiceClient::new_transport(Hyper::new(host_url).unwrap())
+            })
+            .clone()
     }
 }
 
 #[derive(Clone)]
 pub struct SyncServer {
     deps: Arc<Dependencies>,
+    /// Signatures of the payloads verified recently, with the time they were
+    /// signed at.
+    seen_signatures: Arc<DashMap<Vec<u8>, u64, RandomState>>,
 }
 
 impl SyncServer {
-    pub fn new(deps: Arc<Dependencies>, mut dispatch_rx: UnboundedReceiver<EventDispatch>) -> Self {
-        let sync = Self { deps };
-        let sync2 = sync.clone();
-        let clients = Clients(DashMap::default());
+    pub fn new(deps: Arc<Dependencies>, dispatch_rx: UnboundedReceiver<EventDispatch>) -> Self {
+        replay::spawn_event_log_pruner(deps.clone());
+        dead_letter::spawn_dead_letterer(deps.clone());
+
+        let sync = Self {
+            deps,
+            seen_signatures: Arc::new(DashMap::default()),
+        };
+        let clients = Arc::new(Clients(DashMap::default()));
+        sync.spawn_delivery(clients.clone(), dispatch_rx);
 
+        let sync2 = sync.clone();
         tokio::spawn(async move {
             let span = tracing::info_span!("federation_sync_task");
             let _guard = span.enter();
             loop {
-                tokio::select! {
-                    _ = async {
-                        let hosts = sync2.deps.sync_tree.scan_prefix(HOST_PREFIX).await.flat_map(|res| {
-                            let key = match res {
-                                Ok((key, _)) => key,
-                                Err(err) => {
-                                    let err = ServerError::DbError(err);
-                                    error!("error occured while getting hosts for sync: {}", err);
-                                    return None;
-                                }
-                            };
-                            let (_, host_raw) = key.split_at(HOST_PREFIX.len());
-                            let host = unsafe { std::str::from_utf8_unchecked(host_raw) };
-                            Some(SmolStr::new(host))
-                        });
-
-                        for host in hosts {
-                            if sync2.is_host_allowed(&host).is_ok() {
-                                let mut client = clients.get_client(host.clone());
-                                if let Ok(queue) = sync2
-                                    .generate_request(PullRequest {})
-                                    .map_err(|_| ())
-                                    .and_then(|req| {
-                                        client.pull(req).map_err(|_| ())
-                                    }).and_then(|resp| {
-                                        resp.into_message().map_err(|_| ())
-                                    })
-                                    .await
-                                {
-                                    for event in queue.event_queue {
-                                        if let Err(err) = sync2.push_logic(&host, event).await {
-                                            error!("error while executing sync event: {}", err);
-                                        }
-                                    }
-                                }
-                            }
-                        }
-
-                        tokio::time::sleep(Duration::from_secs(60)).await;
-                    } => {}
-                    _ = async {
-                        while let Some(EventDispatch { host, event }) = dispatch_rx.recv().await {
-                            if sync2.is_host_allowed(&host).is_ok() {
-                                match sync2.get_event_queue_raw(&host).await {
-                                    Ok(raw_queue) => {
-                                        let maybe_arch_queue = raw_queue.as_ref().map(|raw_queue| rkyv_arch::<PullResponse>(raw_queue));
-                                        if !maybe_arch_queue.map_or(false, |v| v.event_queue.is_empty()) {
-                                            let queue = maybe_arch_queue.map_or_else(
-                                                PullResponse::default,
-                                                |v| v.deserialize(&mut rkyv::Infallible).unwrap()
-                                            );
-                                            if let Err(err) = sync2.push_to_event_queue(&host, queue, event).await {
-                                                error!("error while pushing to event queue: {}", err);
-                                            }
-                                            continue;
-                                        }
-
-                                        let mut client = clients.get_client(host.clone());
-                                        let mut push_result = sync2
-                                            .generate_request(PushRequest::new(Some(event.clone())))
-                                            .map_err(|_| ())
-                                            .and_then(|req| {
-                                                client.push(req).map_err(|_| ())
-                                            })
-                                            .await;
-                                        let mut try_count = 0;
-                                        while try_count < 5 && push_result.is_err() {
-                                            push_result = sync2
-                                                .generate_request(PushRequest::new(Some(event.clone())))
-                                                .map_err(|_| ())
-                                                .and_then(|req| {
-                                                    client.push(req).map_err(|_| ())
-                                                })
-                                                .await;
-                                            try_count += 1;
-                                        }
-
-                                        if push_result.is_err() {
-                                            let queue = maybe_arch_queue.map_or_else(
-                                                PullResponse::default,
-                                                |v| v.deserialize(&mut rkyv::Infallible).unwrap()
-                                            );
-                                            if let Err(err) = sync2.push_to_event_queue(&host, queue, event).await {
-                                                error!("error while pushing to event queue: {}", err);
-                                            }
-                                        }
-                                    }
-                                    Err(err) => error!("error occured while getting event queue: {}", err),
-                                }
+                // payloads signed before this are rejected as stale anyway
+                let now = get_time_secs();
+                sync2
+                    .seen_signatures
+                    .retain(|_, time| now.saturating_sub(*time) <= PAYLOAD_MAX_AGE_SECS);
+
+                let hosts = sync2
+                    .deps
+                    .sync_tree
+                    .scan_prefix(HOST_PREFIX)
+                    .await
+                    .flat_map(|res| {
+                        let key = match res {
+                            Ok((key, _)) => key,
+                            Err(err) => {
+                                let err = ServerError::DbError(err);
+                                error!("error occured while getting hosts for sync: {}", err);
+                                return None;
                             }
-                        }
-                    } => {}
+                        };
+                        let (_, host_raw) = key.split_at(HOST_PREFIX.len());
+                        let host = unsafe { std::str::from_utf8_unchecked(host_raw) };
+                        Some(SmolStr::new(host))
+                    });
+
+                for host in hosts {
+                    if sync2.is_host_allowed(&host).is_ok() {
+                        let mut client = clients.get_client(host.clone());
+                        sync2.pull_from(&mut client, &host).await;
+                    }
                 }
+
+                tokio::time::sleep(Duration::from_secs(60)).await;
             }
         });
 
         sync
     }
 
-    async fn generate_request<Msg: Message>(&self, msg: Msg) -> Result<Request<Msg>, ServerError> {
+    async fn generate_request<Msg: Message>(
+        &self,
+        to_host: &str,
+        msg: Msg,
+    ) -> Result<Request<Msg>, ServerError> {
         let data = AuthData {
             server_id: self.deps.config.host.clone(),
             time: get_time_secs(),
@@ -166,16 +183,82 @@ impl SyncServer {
 
         let token = self.keys_manager()?.generate_token(data).await?;
         let token = encode_protobuf_message(&token).freeze();
+        let payload = self.sign_payload(to_host, &msg).await?;
 
-        let mut req = Request::new(&msg);
-        req.get_or_insert_header_map()
-            .insert(http::header::AUTHORIZATION, unsafe {
-                HeaderValue::from_maybe_shared_unchecked(token)
-            });
+        let mut req = Request::new_with_body(Body::full(payload.body.clone()));
+        let headers = req.get_or_insert_header_map();
+        headers.insert(http::header::AUTHORIZATION, unsafe {
+            HeaderValue::from_maybe_shared_unchecked(token)
+        });
+        payload.insert_headers(headers);
 
         Ok(req)
     }
 
+    /// Encodes a payload sent to a host, and signs the encoded body with our
+    /// federation key.
+    async fn sign_payload(
+        &self,
+        to_host: &str,
+        msg: &impl Message,
+    ) -> Result<SignedPayload, ServerError> {
+        let body = encode_protobuf_message(msg).freeze();
+        let time = get_time_secs();
+        let signature = self
+            .keys_manager()?
+            .sign(&signed_payload(to_host, time, &body))
+            .await?;
+        Ok(SignedPayload {
+            body,
+            signature: HeaderValue::from_str(&key::encode_hex(&signature))
+                .expect("hex is a valid header"),
+            time: HeaderValue::from(time),
+        })
+    }
+
+    /// Fails if a payload body from a host isn't signed by it, was signed for
+    /// another host, is stale, or was already received.
+    async fn verify_payload(
+        &self,
+        from_host: &SmolStr,
+        headers: Option<&HeaderMap>,
+        body: &[u8],
+    ) -> Result<(), ServerError> {
+        let header = |name| {
+            headers
+                .and_then(|headers| headers.get(name))
+                .and_then(|value| value.to_str().ok())
+        };
+        let sig = header(PAYLOAD_SIGNATURE_HEADER)
+            .and_then(key::decode_hex)
+            .ok_or(ServerError::InvalidEventSignature)?;
+        let time = header(PAYLOAD_TIME_HEADER)
+            .and_then(|value| value.parse::<u64>().ok())
+            .ok_or(ServerError::InvalidEventSignature)?;
+        check_payload_time(time, get_time_secs())?;
+
+        let token = Token {
+            sig,
+            data: signed_payload(&self.deps.config.host, time, body),
+        };
+        self.keys_manager()?
+            .verify_host_token(from_host.clone(), &token)
+            .await
+            .map_err(|err| match err {
+                ServerError::InvalidTokenSignature | ServerError::CouldntVerifyTokenData => {
+                    ServerError::InvalidEventSignature
+                }
+                err => err,
+            })?;
+
+        // only valid signatures are remembered, so others can't be used to
+        // fill the map
+        if self.seen_signatures.insert(token.sig, time).is_some() {
+            return Err(ServerError::InvalidEventSignature);
+        }
+        Ok(())
+    }
+
     fn keys_manager(&self) -> Result<&Arc<KeyManager>, ServerError> {
         self.deps
             .key_manager
@@ -184,13 +267,7 @@ impl SyncServer {
     }
 
     fn is_host_allowed(&self, host: &str) -> Result<(), ServerError> {
-        self.deps
-            .config
-            .federation
-            .as_ref()
-            .map_or(Err(ServerError::FederationDisabled), |conf| {
-                conf.is_host_allowed(host)
-            })
+        self.deps.is_host_allowed(host)
     }
 
     async fn auth<T>(&self, request: &Request<T>) -> Result<SmolStr, ServerError> {
@@ -212,21 +289,9 @@ impl SyncServer {
                 let keys_manager = self.keys_manager()?;
 
                 let host: SmolStr = server_id.into();
-                let get_key = || keys_manager.get_key(host.clone());
-                let mut pubkey = get_key().await?;
-
-                let verify = |pubkey| key::verify_token(&token, &pubkey).map(|_| host.clone());
-
-                let first_verify_result = verify(pubkey);
-                // Fetch pubkey if the verification fails, it might have changed
-                if let Err(ServerError::CouldntVerifyTokenData) = first_verify_result {
-                    keys_manager.invalidate_key(&host);
-                    pubkey = get_key().await?;
-                } else {
-                    return first_verify_result;
-                }
+                keys_manager.verify_host_token(host.clone(), &token).await?;
 
-                return verify(pubkey);
+                return Ok(host);
             }
         }
 
@@ -234,59 +299,35 @@ impl SyncServer {
     }
 
     async fn push_logic(&self, host: &str, event: Event) -> ServerResult<()> {
-        if let Some(kind) = event.kind {
-            match kind {
-                Kind::UserRemovedFromGuild(UserRemovedFromGuild { user_id, guild_id }) => {
-                    self.deps
-                        .chat_tree
-                        .remove_guild_from_guild_list(user_id, guild_id, host)
-                        .await?;
-                }
-                Kind::UserAddedToGuild(UserAddedToGuild { user_id, guild_id }) => {
-                    self.deps
-                        .chat_tree
-                        .add_guild_to_guild_list(user_id, guild_id, host)
-                        .await?;
-                }
-                Kind::UserInvited(_) => todo!(),
-                Kind::UserRejectedInvite(_) => todo!(),
-            }
-        }
-        Ok(())
-    }
-
-    async fn get_event_queue_raw(&self, host: &str) -> Result<Option<EVec>, ServerError> {
-        let key = make_host_key(host);
-        let queue = self.deps.sync_tree.get(&key).await?;
-        self.deps.sync_tree.remove(&key).await?;
-        Ok(queue)
-    }
-
-    async fn get_event_queue(&self, host: &str) -> Result<PullResponse, ServerError> {
-        self.get_event_queue_raw(host).await.map(|val| {
-            val.map_or_else(PullResponse::default, |val| {
-                rkyv_arch::<PullResponse>(&val)
-                    .deserialize(&mut rkyv::Infallible)
-                    .unwrap()
-            })
-        })
+        replay::log_event(&self.deps, host, &event).await?;
+        apply_event(&self.deps, host, event).await
     }
+}
 
-    async fn push_to_event_queue(
-        &self,
-        host: &str,
-        mut queue: PullResponse,
-        event: Event,
-    ) -> Result<(), ServerError> {
-        // TODO: this is a waste, find a way to optimize this
-        queue.event_queue.push(event);
-        let buf = rkyv_ser(&queue);
-        self.deps
-            .sync_tree
-            .insert(&make_host_key(host), buf.as_ref())
-            .await?;
-        Ok(())
+/// Applies an event received from `host` to the database.
+async fn apply_event(deps: &Dependencies, host: &str, event: Event) -> ServerResult<()> {
+    if let Some(kind) = event.kind {
+        match kind {
+            Kind::UserRemovedFromGuild(UserRemovedFromGuild { user_id, guild_id }) => {
+                deps.chat_tree
+                    .remove_guild_from_guild_list(user_id, guild_id, host)
+                    .await?;
+            }
+            Kind::UserAddedToGuild(UserAddedToGuild { user_id, guild_id }) => {
+                deps.chat_tree
+                    .add_guild_to_guild_list(user_id, guild_id, host)
+                    .await?;
+            }
+            // direct message invites aren't implemented, so there is nothing to apply
+            Kind::UserInvited(_) | Kind::UserRejectedInvite(_) => {
+                tracing::warn!(
+                    "skipping direct message invite event from {}, they aren't supported",
+                    host
+                );
+            }
+        }
     }
+    Ok(())
 }
 
 impl postbox_service_server::PostboxService for SyncServer {
@@ -296,3 +337,26 @@ impl postbox_service_server::PostboxService for SyncServer {
         notify_new_id, NotifyNewIdRequest, NotifyNewIdResponse;
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn signed_payload_covers_host_time_and_body() {
+        let payload = signed_payload("a.org", 1, b"body");
+        assert_ne!(payload, signed_payload("b.org", 1, b"body"));
+        assert_ne!(payload, signed_payload("a.org", 2, b"body"));
+        assert_ne!(payload, signed_payload("a.org", 1, b"other"));
+    }
+
+    #[test]
+    fn rejects_stale_payload_times() {
+        let now = 1_000_000;
+        assert!(check_payload_time(now, now).is_ok());
+        assert!(check_payload_time(now - PAYLOAD_MAX_AGE_SECS, now).is_ok());
+        assert!(check_payload_time(now + PAYLOAD_MAX_AGE_SECS, now).is_ok());
+        assert!(check_payload_time(now - PAYLOAD_MAX_AGE_SECS - 1, now).is_err());
+        assert!(check_payload_time(now + PAYLOAD_MAX_AGE_SECS + 1, now).is_err());
+    }
+}
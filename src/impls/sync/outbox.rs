@@ -0,0 +1,270 @@
+//! Durable queue for federation events being pushed to other hosts.
+//!
+//! Dispatched events are written to the sync tree before they are pushed, and
+//! are only removed once the host got them, so they aren't lost if the server
+//! restarts. When a host can't be reached, pushing to it is retried with
+//! exponential backoff. After `retry_max_attempts` failed retries, the events
+//! are queued for the host to pull instead, and end up in the dead-letter
+//! queue if the host never pulls them.
+
+use std::{
+    mem::size_of,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::sync::Notify;
+
+use crate::config::FederationConfig;
+
+use super::*;
+
+/// How often the outbox is checked for hosts whose backoff is over, when
+/// nothing new is dispatched.
+const RETRY_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy)]
+struct Backoff {
+    failures: u32,
+    retry_at: Instant,
+}
+
+pub struct Outbox {
+    /// Orders the events in the outbox. Starts at the current time so it
+    /// keeps going up across restarts.
+    next_seq: AtomicU64,
+    /// Wakes up the delivery task when an event is dispatched.
+    notify: Notify,
+    backoffs: DashMap<SmolStr, Backoff, RandomState>,
+}
+
+impl Outbox {
+    pub fn new() -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos() as u64);
+        Self {
+            next_seq: AtomicU64::new(now),
+            notify: Notify::new(),
+            backoffs: DashMap::default(),
+        }
+    }
+
+    /// Writes an event to the outbox, and wakes up the delivery task.
+    async fn enqueue(&self, deps: &Dependencies, dispatch: EventDispatch) -> DbResult<()> {
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        deps.sync_tree
+            .insert(
+                &make_outbox_key(&dispatch.host, seq),
+                rkyv_ser(&dispatch.event),
+            )
+            .await?;
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    fn is_due(&self, host: &str) -> bool {
+        self.backoffs
+            .get(host)
+            .map_or(true, |backoff| backoff.retry_at <= Instant::now())
+    }
+
+    /// Records that pushing to a host failed, returning how many times it
+    /// failed in a row.
+    fn record_failure(&self, host: &SmolStr, config: &FederationConfig) -> u32 {
+        let mut backoff = self.backoffs.entry(host.clone()).or_insert(Backoff {
+            failures: 0,
+            retry_at: Instant::now(),
+        });
+        backoff.failures += 1;
+        let delay = retry_delay(
+            backoff.failures,
+            config.retry_base_delay_secs,
+            config.retry_max_delay_secs,
+        );
+        backoff.retry_at = Instant::now() + Duration::from_secs(delay);
+        backoff.failures
+    }
+
+    fn clear_failures(&self, host: &str) {
+        self.backoffs.remove(host);
+    }
+}
+
+/// How many seconds to wait after pushing to a host failed `failures` times in a row.
+fn retry_delay(failures: u32, base_secs: u64, max_secs: u64) -> u64 {
+    let doublings = failures.saturating_sub(1);
+    base_secs
+        .saturating_mul(1_u64.checked_shl(doublings).unwrap_or(u64::MAX))
+        .min(max_secs)
+}
+
+/// Gets the events in the outbox, grouped by host, in the order they were
+/// dispatched in.
+async fn get_pending(
+    deps: &Dependencies,
+) -> Result<Vec<(SmolStr, Vec<(EVec, Event)>)>, ServerError> {
+    let mut pending: Vec<(SmolStr, Vec<(EVec, Event)>)> = Vec::new();
+    for res in deps.sync_tree.scan_prefix(OUTBOX_PREFIX).await {
+        let (key, value) = res?;
+        let rest = &key[OUTBOX_PREFIX.len()..];
+        let host_len = rest.iter().position(|b| *b == 0).unwrap_or(rest.len());
+        let host = String::from_utf8_lossy(&rest[..host_len]);
+        let event = db::deser_outbox_event(value);
+        match pending.last_mut() {
+            Some((last_host, events)) if last_host.as_str() == host => events.push((key, event)),
+            _ => pending.push((host.into(), vec![(key, event)])),
+        }
+    }
+    Ok(pending)
+}
+
+impl SyncServer {
+    /// Spawns the tasks that write dispatched events to the outbox, and push
+    /// the events in the outbox to their hosts.
+    pub(super) fn spawn_delivery(
+        &self,
+        clients: Arc<Clients>,
+        mut dispatch_rx: UnboundedReceiver<EventDispatch>,
+    ) {
+        let outbox = Arc::new(Outbox::new());
+
+        let sync = self.clone();
+        let outbox2 = outbox.clone();
+        tokio::spawn(async move {
+            while let Some(dispatch) = dispatch_rx.recv().await {
+                if sync.is_host_allowed(&dispatch.host).is_err() {
+                    continue;
+                }
+                if let Err(err) = outbox2.enqueue(&sync.deps, dispatch).await {
+                    error!(
+                        "couldn't write federation event to outbox: {}",
+                        ServerError::from(err)
+                    );
+                }
+            }
+        });
+
+        let sync = self.clone();
+        tokio::spawn(async move {
+            let span = tracing::info_span!("federation_delivery_task");
+            let _guard = span.enter();
+            loop {
+                if let Err(err) = sync.deliver_pending(&clients, &outbox).await {
+                    error!("error while pushing federation events: {}", err);
+                }
+                tokio::select! {
+                    _ = outbox.notify.notified() => {}
+                    _ = tokio::time::sleep(RETRY_CHECK_INTERVAL) => {}
+                }
+            }
+        });
+    }
+
+    /// Pushes the events in the outbox to the hosts that aren't backed off.
+    async fn deliver_pending(&self, clients: &Clients, outbox: &Outbox) -> Result<(), ServerError> {
+        let config = self.deps.config.federation.clone().unwrap_or_default();
+        for (host, events) in get_pending(&self.deps).await? {
+            if !outbox.is_due(&host) {
+                continue;
+            }
+
+            if self.is_host_allowed(&host).is_err() {
+                let mut batch = Batch::default();
+                for (key, _) in &events {
+                    batch.remove(key.clone());
+                }
+                self.deps.sync_tree.apply_batch(batch).await?;
+                tracing::info!(
+                    "dropped {} federation events for {}, since it isn't allowed anymore",
+                    events.len(),
+                    host
+                );
+                continue;
+            }
+
+            // events already waiting to be pulled go first, keep them in order
+            if self.has_pull_queue(&host).await? {
+                self.queue_for_pull(&host, events).await?;
+                continue;
+            }
+
+            let mut client = clients.get_client(host.clone());
+            let mut events = events.into_iter();
+            while let Some((key, event)) = events.next() {
+                let pushed = self
+                    .generate_request(&host, PushRequest::new(Some(event.clone())))
+                    .map_err(|_| ())
+                    .and_then(|req| client.push(req).map_err(|_| ()))
+                    .await;
+
+                if pushed.is_ok() {
+                    outbox.clear_failures(&host);
+                    self.deps.sync_tree.remove(key.as_ref()).await?;
+                    continue;
+                }
+
+                let failures = outbox.record_failure(&host, &config);
+                if failures > config.retry_max_attempts {
+                    tracing::warn!(
+                        "couldn't push events to {} after {} retries, queueing them to be pulled",
+                        host,
+                        config.retry_max_attempts
+                    );
+                    outbox.clear_failures(&host);
+                    let rest = std::iter::once((key, event)).chain(events).collect();
+                    self.queue_for_pull(&host, rest).await?;
+                }
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    async fn has_pull_queue(&self, host: &str) -> Result<bool, ServerError> {
+        let mut queue = self
+            .deps
+            .sync_tree
+            .scan_prefix(&make_pull_queue_prefix(host))
+            .await;
+        Ok(queue.next().transpose()?.is_some())
+    }
+
+    /// Moves events from the outbox to the queue of a host, for it to pull them.
+    async fn queue_for_pull(
+        &self,
+        host: &str,
+        events: Vec<(EVec, Event)>,
+    ) -> Result<(), ServerError> {
+        let mut batch = Batch::default();
+        for (key, event) in events {
+            // events keep their outbox sequence number, so they stay in order
+            // Safety: outbox keys always end with a u64
+            let seq = u64::from_be_bytes(unsafe {
+                key[key.len() - size_of::<u64>()..]
+                    .try_into()
+                    .unwrap_unchecked()
+            });
+            batch.remove(key);
+            batch.insert(make_pull_queue_key(host, seq), rkyv_ser(&event));
+        }
+        batch.insert(make_host_key(host), []);
+        self.deps.sync_tree.apply_batch(batch).await?;
+        dead_letter::mark_queued(&self.deps, host).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn retry_delay_doubles_up_to_max() {
+        assert_eq!(retry_delay(1, 5, 900), 5);
+        assert_eq!(retry_delay(2, 5, 900), 10);
+        assert_eq!(retry_delay(4, 5, 900), 40);
+        assert_eq!(retry_delay(9, 5, 900), 900);
+        assert_eq!(retry_delay(100, 5, 900), 900);
+    }
+}
@@ -1,10 +1,140 @@
+//! Pulling events queued for a host, a page at a time.
+//!
+//! A host acknowledges the events it applied by sending the ID of the last
+//! one with its next pull. Events are only removed from the queue once they
+//! are acknowledged, so a pull that fails halfway doesn't lose them.
+
 use super::*;
 
+/// Most events sent in one pull response.
+const PULL_PAGE_SIZE: usize = 100;
+/// Request header with the ID of the last event the host applied.
+const PULL_ACK_HEADER: &str = "x-scherzo-pull-ack";
+/// Response header with the ID of the last event in the page.
+const PULL_LAST_ID_HEADER: &str = "x-scherzo-pull-last-id";
+/// Response header that is `true` if there are more events after the page.
+const PULL_MORE_HEADER: &str = "x-scherzo-pull-more";
+
 pub async fn handler(
     svc: &SyncServer,
     request: Request<PullRequest>,
 ) -> ServerResult<Response<PullResponse>> {
     let host = svc.auth(&request).await?;
-    let queue = svc.get_event_queue(&host).await?;
-    Ok(queue.into_response())
+    let ack = request
+        .header_map()
+        .and_then(|headers| headers.get(PULL_ACK_HEADER))
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok());
+
+    let page = svc.get_event_page(&host, ack).await?;
+    let payload = svc.sign_payload(&host, &page.queue).await?;
+    let mut response = Response::<PullResponse>::new_with_body(Body::full(payload.body.clone()));
+    let headers = response.get_or_insert_header_map();
+    payload.insert_headers(headers);
+    if let Some(last_id) = page.last_id {
+        headers.insert(PULL_LAST_ID_HEADER, HeaderValue::from(last_id));
+    }
+    headers.insert(
+        PULL_MORE_HEADER,
+        HeaderValue::from_static(if page.more { "true" } else { "false" }),
+    );
+    Ok(response)
+}
+
+struct EventPage {
+    queue: PullResponse,
+    last_id: Option<u64>,
+    more: bool,
+}
+
+impl SyncServer {
+    /// Removes the events a host acknowledged from its queue, and gets the
+    /// page of events after them.
+    async fn get_event_page(&self, host: &str, ack: Option<u64>) -> Result<EventPage, ServerError> {
+        let prefix = make_pull_queue_prefix(host);
+        let mut acked = Batch::default();
+        let mut page = EventPage {
+            queue: PullResponse::default(),
+            last_id: None,
+            more: false,
+        };
+        for res in self.deps.sync_tree.scan_prefix(&prefix).await {
+            let (key, value) = res?;
+            // Safety: pull queue keys always end with a u64
+            let id =
+                u64::from_be_bytes(unsafe { key[prefix.len()..].try_into().unwrap_unchecked() });
+            if ack.map_or(false, |ack| id <= ack) {
+                acked.remove(key);
+                continue;
+            }
+            if page.queue.event_queue.len() == PULL_PAGE_SIZE {
+                page.more = true;
+                break;
+            }
+            page.queue.event_queue.push(db::deser_outbox_event(value));
+            page.last_id = Some(id);
+        }
+        self.deps.sync_tree.apply_batch(acked).await?;
+
+        // the host is pulling, so the events still queued for it aren't stale
+        dead_letter::clear_queued(&self.deps, host).await?;
+        if page.last_id.is_some() {
+            dead_letter::mark_queued(&self.deps, host).await?;
+        }
+        Ok(page)
+    }
+
+    /// Pulls the events a host queued for us a page at a time, acknowledging
+    /// each page once its events are applied.
+    pub(super) async fn pull_from(&self, client: &mut PostboxServiceClient<Hyper>, host: &SmolStr) {
+        let mut ack: Option<u64> = None;
+        loop {
+            let mut request = match self.generate_request(host, PullRequest {}).await {
+                Ok(request) => request,
+                Err(err) => {
+                    error!("couldn't make pull request for {}: {}", host, err);
+                    return;
+                }
+            };
+            if let Some(ack) = ack {
+                request
+                    .get_or_insert_header_map()
+                    .insert(PULL_ACK_HEADER, HeaderValue::from(ack));
+            }
+
+            let Ok(response) = client.pull(request).await else {
+                return;
+            };
+            let headers = response.header_map().cloned();
+            let Ok(body) = read_body(response::Parts::from(response).body).await else {
+                return;
+            };
+            if let Err(err) = self.verify_payload(host, headers.as_ref(), &body).await {
+                error!("rejected events pulled from {}: {}", host, err);
+                return;
+            }
+            let Ok(queue) = PullResponse::decode(body) else {
+                return;
+            };
+            if queue.event_queue.is_empty() {
+                return;
+            }
+            for event in queue.event_queue {
+                if let Err(err) = self.push_logic(host, event).await {
+                    error!("error while executing sync event: {}", err);
+                }
+            }
+
+            // hosts that don't page send everything at once, without an ID
+            let last_id = headers
+                .as_ref()
+                .and_then(|headers| headers.get(PULL_LAST_ID_HEADER))
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<u64>().ok());
+            match last_id {
+                Some(last_id) if ack.map_or(true, |ack| last_id > ack) => ack = Some(last_id),
+                _ => return,
+            }
+        }
+    }
 }
@@ -5,6 +5,14 @@ pub async fn handler(
     request: Request<PushRequest>,
 ) -> ServerResult<Response<PushResponse>> {
     let host = svc.auth(&request).await?;
+    let headers = request.header_map().cloned();
+    // the signature is checked against the body as it was sent, before decoding
+    let body = read_body(request::Parts::from(request).body).await?;
+    svc.verify_payload(&host, headers.as_ref(), &body).await?;
+    let message = Request::<PushRequest>::new_with_body(Body::full(body))
+        .into_message()
+        .await?;
+
     let key = make_host_key(&host);
     if !svc
         .deps
@@ -19,7 +27,7 @@ pub async fn handler(
             .await
             .map_err(ServerError::DbError)?;
     }
-    if let Some(event) = request.into_message().await?.event {
+    if let Some(event) = message.event {
         svc.push_logic(&host, event).await?;
     }
     Ok((PushResponse {}).into_response())
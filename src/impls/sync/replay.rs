@@ -0,0 +1,230 @@
+//! Keeping a log of federation events received from other homeservers, and
+//! replaying them against the database to repair it (for example, to rebuild
+//! guild lists after they got out of sync).
+
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::impls::gen_rand_u64;
+
+use super::*;
+
+const SECS_IN_DAY: u64 = 24 * 60 * 60;
+
+/// Stores an event received from `host` in the event log, if the log is enabled.
+pub async fn log_event(deps: &Dependencies, host: &str, event: &Event) -> Result<(), ServerError> {
+    let retention = deps
+        .config
+        .federation
+        .as_ref()
+        .map_or(0, |conf| conf.event_log_retention_days);
+    if retention == 0 {
+        return Ok(());
+    }
+
+    let buf = rkyv_ser(&LoggedEvent {
+        host: host.to_string(),
+        event: event.clone(),
+    });
+    deps.sync_tree
+        .insert(&make_event_log_key(get_time_secs(), gen_rand_u64()), buf)
+        .await?;
+
+    Ok(())
+}
+
+/// Spawns a task that removes events older than the configured retention from the event log.
+pub fn spawn_event_log_pruner(deps: Arc<Dependencies>) {
+    let retention = deps
+        .config
+        .federation
+        .as_ref()
+        .map_or(0, |conf| conf.event_log_retention_days);
+    if retention == 0 {
+        return;
+    }
+
+    tokio::spawn(async move {
+        loop {
+            let cutoff = get_time_secs().saturating_sub(retention * SECS_IN_DAY);
+            if let Err(err) = prune_event_log(&deps, cutoff).await {
+                error!("couldn't prune federation event log: {}", err);
+            }
+            tokio::time::sleep(Duration::from_secs(60 * 60)).await;
+        }
+    });
+}
+
+async fn prune_event_log(deps: &Dependencies, cutoff: u64) -> Result<(), ServerError> {
+    let start = make_event_log_key(0, 0);
+    let end = make_event_log_key(cutoff, 0);
+    let mut batch = Batch::default();
+    for res in deps
+        .sync_tree
+        .range(start.as_slice()..=end.as_slice())
+        .await
+    {
+        let (key, _) = res?;
+        batch.remove(key);
+    }
+    deps.sync_tree.apply_batch(batch).await?;
+    Ok(())
+}
+
+/// Which logged events to replay, and whether to actually apply them.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct ReplayFilter {
+    /// Only replay events about this guild.
+    #[serde(default)]
+    pub guild_id: Option<u64>,
+    /// Only replay events received at or after this time, in seconds since the UNIX epoch.
+    #[serde(default)]
+    pub since: Option<u64>,
+    /// Only replay events received at or before this time, in seconds since the UNIX epoch.
+    #[serde(default)]
+    pub until: Option<u64>,
+    /// Apply the events to the database. If not set, only reports what would change.
+    #[serde(default)]
+    pub apply: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReplayedEvent {
+    pub received_at: u64,
+    pub host: String,
+    pub description: String,
+    /// Whether replaying the event changes (or would change) the database.
+    pub changes: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReplayReport {
+    pub applied: bool,
+    /// Number of events that change (or would change) the database.
+    pub changed: u64,
+    /// Number of events that can't be replayed.
+    pub skipped: u64,
+    pub events: Vec<ReplayedEvent>,
+}
+
+fn event_guild_id(kind: &Kind) -> Option<u64> {
+    match kind {
+        Kind::UserRemovedFromGuild(UserRemovedFromGuild { guild_id, .. })
+        | Kind::UserAddedToGuild(UserAddedToGuild { guild_id, .. }) => Some(*guild_id),
+        _ => None,
+    }
+}
+
+/// Replays logged events matching the filter against the database, oldest first.
+///
+/// In a dry run, every event is compared against the current state of the
+/// database on its own, so events that cancel each other out are all reported.
+pub async fn replay_events(
+    deps: &Dependencies,
+    filter: ReplayFilter,
+) -> ServerResult<ReplayReport> {
+    let start = make_event_log_key(filter.since.unwrap_or(0), 0);
+    let end = make_event_log_key(filter.until.unwrap_or(u64::MAX), u64::MAX);
+    let entries = deps
+        .sync_tree
+        .range(start.as_slice()..=end.as_slice())
+        .await
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(ServerError::from)?;
+
+    let mut report = ReplayReport {
+        applied: filter.apply,
+        changed: 0,
+        skipped: 0,
+        events: Vec::new(),
+    };
+    for (key, value) in entries {
+        let received_at = u64::from_be_bytes(
+            // Safety: event log keys always have the time right after the prefix [ref:event_log_key]
+            unsafe {
+                key[EVENT_LOG_PREFIX.len()..EVENT_LOG_PREFIX.len() + size_of::<u64>()]
+                    .try_into()
+                    .unwrap_unchecked()
+            },
+        );
+        let LoggedEvent { host, event } = db::deser_logged_event(value);
+        let Some(kind) = event.kind.as_ref() else {
+            continue;
+        };
+        if filter
+            .guild_id
+            .map_or(false, |guild_id| event_guild_id(kind) != Some(guild_id))
+        {
+            continue;
+        }
+
+        let (description, changes) = match kind {
+            Kind::UserAddedToGuild(UserAddedToGuild { user_id, guild_id }) => {
+                let exists = deps
+                    .chat_tree
+                    .contains_key(&db::chat::make_guild_list_key(*user_id, *guild_id, &host))
+                    .await?;
+                (
+                    format!("user {} added to guild {}", user_id, guild_id),
+                    !exists,
+                )
+            }
+            Kind::UserRemovedFromGuild(UserRemovedFromGuild { user_id, guild_id }) => {
+                let exists = deps
+                    .chat_tree
+                    .contains_key(&db::chat::make_guild_list_key(*user_id, *guild_id, &host))
+                    .await?;
+                (
+                    format!("user {} removed from guild {}", user_id, guild_id),
+                    exists,
+                )
+            }
+            // these aren't handled when received either
+            Kind::UserInvited(_) | Kind::UserRejectedInvite(_) => {
+                report.skipped += 1;
+                report.events.push(ReplayedEvent {
+                    received_at,
+                    host,
+                    description: "invite event, can't be replayed".to_string(),
+                    changes: false,
+                });
+                continue;
+            }
+        };
+
+        if changes {
+            report.changed += 1;
+            if filter.apply {
+                apply_event(deps, &host, event).await?;
+            }
+        }
+        report.events.push(ReplayedEvent {
+            received_at,
+            host,
+            description,
+            changes,
+        });
+    }
+
+    Ok(report)
+}
+
+impl FromStr for ReplayFilter {
+    type Err = ();
+
+    /// Parses a filter from space separated `apply`, `guild=<id>`, `since=<secs>` and `until=<secs>` arguments.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = ReplayFilter::default();
+        for arg in s.split_whitespace() {
+            match arg.split_once('=') {
+                None if arg == "apply" => filter.apply = true,
+                Some(("guild", id)) => filter.guild_id = Some(id.parse().map_err(|_| ())?),
+                Some(("since", secs)) => filter.since = Some(secs.parse().map_err(|_| ())?),
+                Some(("until", secs)) => filter.until = Some(secs.parse().map_err(|_| ())?),
+                _ => return Err(()),
+            }
+        }
+        Ok(filter)
+    }
+}
@@ -0,0 +1,236 @@
+//! Establishing federation between homeservers with invite tokens, so that
+//! servers using an allow list don't need their config edited by hand.
+//!
+//! An admin of this server creates an invite, and gives the token to an admin
+//! of the other server. Their server then redeems it here with a request
+//! signed by its federation key, and both servers trust each other from then on.
+
+use dashmap::DashSet;
+use harmony_rust_sdk::api::harmonytypes::Token;
+use hyper::{Body, Method, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha3::Digest;
+
+use crate::impls::gen_rand_inline_str;
+
+use super::*;
+
+/// How long invites are valid for by default, in seconds.
+const INVITE_EXPIRE_DEFAULT: u64 = 60 * 60 * 24;
+
+/// Hosts that were trusted by redeeming invites.
+pub type TrustedHosts = DashSet<SmolStr, RandomState>;
+
+pub async fn load_trusted_hosts(sync_tree: &Tree) -> DbResult<TrustedHosts> {
+    let hosts = TrustedHosts::default();
+    for res in sync_tree.scan_prefix(TRUSTED_HOST_PREFIX).await {
+        let (key, _) = res?;
+        let host = String::from_utf8_lossy(&key[TRUSTED_HOST_PREFIX.len()..]);
+        hosts.insert(host.into());
+    }
+    Ok(hosts)
+}
+
+fn hash_secret(secret: &str) -> impl AsRef<[u8]> {
+    sha3::Sha3_512::digest(secret.as_bytes())
+}
+
+/// The data signed by the redeeming server.
+fn redeem_message(host: &str, time: u64, secret: &str) -> Vec<u8> {
+    format!("{}\n{}\n{}", host, time, secret).into_bytes()
+}
+
+fn keys_manager(deps: &Dependencies) -> Result<&Arc<KeyManager>, ServerError> {
+    deps.key_manager
+        .as_ref()
+        .ok_or(ServerError::FederationDisabled)
+}
+
+pub async fn trust_host(deps: &Dependencies, host: &str) -> Result<(), ServerError> {
+    deps.sync_tree
+        .insert(&make_trusted_host_key(host), &[])
+        .await?;
+    deps.trusted_hosts.insert(host.into());
+    Ok(())
+}
+
+pub async fn untrust_host(deps: &Dependencies, host: &str) -> Result<(), ServerError> {
+    deps.sync_tree.remove(&make_trusted_host_key(host)).await?;
+    deps.trusted_hosts.remove(host);
+    Ok(())
+}
+
+/// Creates an invite that can be redeemed once, returning its token and when it expires.
+pub async fn create_invite(
+    deps: &Dependencies,
+    valid_for: Option<u64>,
+) -> Result<(String, u64), ServerError> {
+    keys_manager(deps)?;
+
+    let secret = gen_rand_inline_str();
+    let expires_at = get_time_secs() + valid_for.unwrap_or(INVITE_EXPIRE_DEFAULT);
+    deps.sync_tree
+        .insert(
+            &make_federation_invite_key(hash_secret(&secret).as_ref()),
+            expires_at.to_be_bytes(),
+        )
+        .await?;
+
+    Ok((format!("{}/{}", deps.config.host, secret), expires_at))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RedeemInviteRequest {
+    /// Host of the server redeeming the invite.
+    pub host: String,
+    pub time: u64,
+    pub secret: String,
+    /// Signature of the host, time and secret by the federation key of the redeeming server.
+    pub signature: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RedeemInviteResponse {
+    /// Host of the server the invite was redeemed at.
+    pub host: String,
+}
+
+/// Redeems an invite created on this server, trusting the redeeming server.
+pub async fn redeem_invite_handler(
+    deps: &Dependencies,
+    request: RedeemInviteRequest,
+) -> ServerResult<RedeemInviteResponse> {
+    let RedeemInviteRequest {
+        host,
+        time,
+        secret,
+        signature,
+    } = request;
+
+    let keys_manager = keys_manager(deps)?;
+
+    let cur_time = get_time_secs();
+    // Check time variance (1 minute)
+    if time >= cur_time + 30 || time <= cur_time - 30 {
+        bail!(ServerError::InvalidTime);
+    }
+
+    let invite_key = make_federation_invite_key(hash_secret(&secret).as_ref());
+    let maybe_expires_at = deps
+        .sync_tree
+        .get(&invite_key)
+        .await
+        .map_err(ServerError::from)?;
+    let Some(raw_expires_at) = maybe_expires_at else {
+        bail!(("h.invalid-federation-invite", "no such federation invite"));
+    };
+    // Safety: we only store u64s as invite values
+    let expires_at =
+        u64::from_be_bytes(unsafe { raw_expires_at.as_ref().try_into().unwrap_unchecked() });
+    if expires_at < cur_time {
+        deps.sync_tree
+            .remove(&invite_key)
+            .await
+            .map_err(ServerError::from)?;
+        bail!((
+            "h.federation-invite-expired",
+            "the federation invite has expired"
+        ));
+    }
+
+    // always fetch the key, this is the key exchange
+    let host: SmolStr = host.into();
+    keys_manager.invalidate_key(&host);
+    let pubkey = keys_manager.get_key(host.clone()).await?;
+    let token = Token {
+        sig: signature,
+        data: redeem_message(&host, time, &secret),
+    };
+    key::verify_token(&token, &pubkey)?;
+
+    // an invite can only be redeemed once, even by requests racing each other
+    let consumed = deps
+        .sync_tree
+        .compare_and_swap(&invite_key, Some(raw_expires_at.as_ref()), None)
+        .await
+        .map_err(ServerError::from)?;
+    if !consumed {
+        bail!(("h.invalid-federation-invite", "no such federation invite"));
+    }
+    trust_host(deps, &host).await?;
+
+    tracing::info!("redeemed federation invite from {}", host);
+
+    Ok(RedeemInviteResponse {
+        host: deps.config.host.clone(),
+    })
+}
+
+/// Redeems an invite token created on another server, and trusts that server if it succeeds.
+///
+/// Returns the host of the other server.
+pub async fn accept_invite(deps: &Dependencies, token: &str) -> ServerResult<String> {
+    let keys_manager = keys_manager(deps)?;
+
+    let invalid_invite = || {
+        (
+            "h.invalid-federation-invite",
+            "federation invite token is invalid",
+        )
+    };
+    let Some((host, secret)) = token.rsplit_once('/') else {
+        bail!(invalid_invite());
+    };
+    let Ok(url) = format!("https://{}/_scherzo/federation/redeem-invite", host).parse::<Uri>()
+    else {
+        bail!(invalid_invite());
+    };
+
+    let our_host = deps.config.host.clone();
+    let time = get_time_secs();
+    let signature = keys_manager
+        .sign(&redeem_message(&our_host, time, secret))
+        .await?;
+    let body = serde_json::to_vec(&RedeemInviteRequest {
+        host: our_host,
+        time,
+        secret: secret.to_string(),
+        signature,
+    })
+    .expect("must be valid json");
+
+    let request = http::Request::builder()
+        .method(Method::POST)
+        .uri(url)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .expect("must be valid request");
+    let response = deps
+        .http
+        .request(request)
+        .await
+        .map_err(ServerError::from)?;
+    if response.status() != StatusCode::OK {
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(ServerError::from)?;
+        bail!((
+            "h.federation-invite-rejected",
+            format!(
+                "{} rejected the federation invite: {} {}",
+                host,
+                status,
+                String::from_utf8_lossy(&body)
+            )
+        ));
+    }
+
+    keys_manager.invalidate_key(host);
+    keys_manager.get_key(host.into()).await?;
+    trust_host(deps, host).await?;
+
+    tracing::info!("accepted federation invite from {}", host);
+
+    Ok(host.to_string())
+}
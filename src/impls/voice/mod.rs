@@ -3,10 +3,10 @@ use std::{
     time::Duration,
 };
 
-use super::{chat::ChatTree, prelude::*};
+use super::{chat::ChatTree, diagnostics::VoiceParticipantStats, get_time_secs, prelude::*};
 
 use ahash::RandomState;
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
 use harmony_rust_sdk::api::{
     exports::hrpc::bail_result,
     voice::{
@@ -21,7 +21,7 @@ use harmony_rust_sdk::api::{
 };
 use mediasoup::{
     prelude::{
-        Consumer, ConsumerId, ConsumerOptions, DtlsParameters, TransportListenIp,
+        Consumer, ConsumerId, ConsumerOptions, DtlsParameters, IceParameters, TransportListenIp,
         TransportListenIps, WebRtcTransport, WebRtcTransportOptions,
         WebRtcTransportRemoteParameters,
     },
@@ -39,11 +39,15 @@ use parking_lot::Mutex;
 use tokio::sync::broadcast::{self, Receiver, Sender};
 use tracing::Level;
 
+pub mod reconnect;
 pub mod stream_message;
 
 type ChannelId = (u64, u64);
 type UserId = u64;
 
+/// How often call quality stats of participants are collected.
+const STATS_INTERVAL: Duration = Duration::from_secs(10);
+
 #[derive(Clone)]
 enum Event {
     UserJoined(UserJoined),
@@ -55,6 +59,9 @@ pub struct VoiceServer {
     valid_sessions: SessionMap,
     worker_pool: WorkerPool,
     channels: Channels,
+    /// Participants that currently have a voice stream open.
+    connected: Arc<DashSet<(ChannelId, UserId), RandomState>>,
+    reconnect_tokens: reconnect::ReconnectTokens,
     chat_tree: ChatTree,
     disable_ratelimits: bool,
     deps: Arc<Dependencies>,
@@ -66,11 +73,18 @@ impl VoiceServer {
             valid_sessions: deps.valid_sessions.clone(),
             worker_pool: WorkerPool::new(log_level),
             channels: Channels::new(),
+            connected: Arc::new(DashSet::default()),
+            reconnect_tokens: reconnect::ReconnectTokens::default(),
             chat_tree: deps.chat_tree.clone(),
             disable_ratelimits: deps.config.policy.ratelimit.disable,
             deps,
         }
     }
+
+    /// Checks that a media worker can be started, so voice channels work.
+    pub async fn check_workers(&self) -> ServerResult<()> {
+        self.worker_pool.get().await.map(drop)
+    }
 }
 
 impl VoiceService for VoiceServer {
@@ -295,6 +309,58 @@ impl User {
         })
     }
 
+    /// Restarts ICE on both transports of a resumed session, returning the
+    /// options the client needs to reconnect them.
+    async fn restart_transports(
+        &self,
+    ) -> Result<(TransportOptions, TransportOptions), RequestError> {
+        let guard = self.inner.transports.lock().await;
+        let consumer_ice_parameters = guard.consumer.restart_ice().await?;
+        let producer_ice_parameters = guard.producer.restart_ice().await?;
+        Ok((
+            transport_options(&guard.consumer, &consumer_ice_parameters),
+            transport_options(&guard.producer, &producer_ice_parameters),
+        ))
+    }
+
+    /// Gets the consumers this user has for everyone else in the channel.
+    async fn consumer_options(
+        &self,
+        channel: &Channel,
+        user_id: UserId,
+    ) -> Vec<UserConsumerOptions> {
+        let mut options = Vec::new();
+        for other_user in channel.get_all_users() {
+            if *other_user.key() == user_id {
+                continue;
+            }
+            let Some(producer_id) = other_user
+                .inner
+                .producer
+                .lock()
+                .await
+                .as_ref()
+                .map(|p| p.id())
+            else {
+                continue;
+            };
+            let consumer = self
+                .inner
+                .consumers
+                .iter()
+                .find(|consumer| consumer.producer_id() == producer_id);
+            if let Some(consumer) = consumer {
+                options.push(UserConsumerOptions {
+                    producer_id: into_json(&producer_id),
+                    consumer_id: into_json(&consumer.id()),
+                    rtp_parameters: into_json(consumer.rtp_parameters()),
+                    user_id: *other_user.key(),
+                });
+            }
+        }
+        options
+    }
+
     async fn resume_consumer(&self, consumer_id: ConsumerId) -> Result<(), RequestError> {
         if let Some(consumer) = self.inner.consumers.get_mut(&consumer_id) {
             consumer.resume().await?;
@@ -302,6 +368,35 @@ impl User {
         Ok(())
     }
 
+    /// Gets call quality stats from the RTCP reports of this user's producer.
+    /// Returns `None` if the user doesn't have a producer.
+    async fn quality_stats(
+        &self,
+        (guild_id, channel_id): ChannelId,
+        user_id: UserId,
+    ) -> Result<Option<VoiceParticipantStats>, RequestError> {
+        let producer = match self.inner.producer.lock().await.as_ref() {
+            Some(producer) => producer.clone(),
+            None => return Ok(None),
+        };
+        // audio producers only have one stream
+        let Some(stat) = producer.get_stats().await?.into_iter().next() else {
+            return Ok(None);
+        };
+
+        Ok(Some(VoiceParticipantStats {
+            guild_id,
+            channel_id,
+            user_id,
+            rtt_ms: stat.round_trip_time.map(f64::from),
+            jitter: stat.jitter,
+            packet_loss: f64::from(stat.fraction_lost) / 256.0,
+            packets_lost: u64::from(stat.packets_lost),
+            packets_received: stat.packet_count,
+            updated_at: get_time_secs(),
+        }))
+    }
+
     async fn create_consumer(
         &self,
         mut options: ConsumerOptions,
@@ -346,6 +441,18 @@ fn media_codecs() -> Vec<RtpCodecCapability> {
     }]
 }
 
+fn transport_options(
+    transport: &WebRtcTransport,
+    ice_parameters: &IceParameters,
+) -> TransportOptions {
+    TransportOptions {
+        id: into_json(&transport.id()),
+        dtls_parameters: into_json(&transport.dtls_parameters()),
+        ice_candidates: transport.ice_candidates().iter().map(into_json).collect(),
+        ice_parameters: into_json(ice_parameters),
+    }
+}
+
 fn into_json<T: serde::Serialize>(value: &T) -> String {
     serde_json::to_string(value).unwrap()
 }
@@ -0,0 +1,191 @@
+//! Reconnect tokens, which let a participant whose connection dropped resume
+//! their voice session instead of joining the channel again.
+//!
+//! A connected participant gets a token with the `voice/reconnect-token`
+//! endpoint. If their connection drops, they stay in the channel for
+//! [`RECONNECT_TIMEOUT`], with their producer and consumers kept as they
+//! were. Connecting to the voice stream with the token in the
+//! [`RECONNECT_TOKEN_HEADER`] header in that time resumes the session.
+
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+use super::*;
+use crate::impls::gen_rand_inline_str;
+
+/// Header that a reconnect token is sent with when connecting to the voice stream.
+pub const RECONNECT_TOKEN_HEADER: &str = "x-voice-reconnect-token";
+/// How long a voice session is kept after its connection drops.
+pub const RECONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub(super) const INVALID_TOKEN_ERR: (&str, &str) = (
+    "h.invalid-voice-reconnect-token",
+    "voice reconnect token is invalid or has expired",
+);
+
+struct ReconnectSession {
+    user_id: UserId,
+    channel_id: ChannelId,
+    /// When the connection of this session dropped, if it did.
+    disconnected_at: Option<Instant>,
+}
+
+#[derive(Clone, Default)]
+pub(super) struct ReconnectTokens {
+    inner: Arc<DashMap<SmolStr, ReconnectSession, RandomState>>,
+}
+
+impl ReconnectTokens {
+    /// Creates a new token for a connected participant, replacing the one
+    /// they had before.
+    fn issue(&self, user_id: UserId, channel_id: ChannelId) -> SmolStr {
+        self.discard(user_id, channel_id);
+        let token = gen_rand_inline_str();
+        self.inner.insert(
+            token.clone(),
+            ReconnectSession {
+                user_id,
+                channel_id,
+                disconnected_at: None,
+            },
+        );
+        token
+    }
+
+    /// Removes the tokens of a participant.
+    pub(super) fn discard(&self, user_id: UserId, channel_id: ChannelId) {
+        self.inner
+            .retain(|_, session| session.user_id != user_id || session.channel_id != channel_id);
+    }
+
+    /// Uses up a token to resume the session it was issued for.
+    pub(super) fn resume(
+        &self,
+        token: &str,
+        user_id: UserId,
+        channel_id: ChannelId,
+    ) -> ServerResult<()> {
+        let resumable = self.inner.get(token).map_or(false, |session| {
+            session.user_id == user_id
+                && session.channel_id == channel_id
+                && session
+                    .disconnected_at
+                    .map_or(false, |at| at.elapsed() < RECONNECT_TIMEOUT)
+        });
+        if !resumable {
+            bail!(INVALID_TOKEN_ERR);
+        }
+        self.inner.remove(token);
+        Ok(())
+    }
+
+    /// Marks the session of a participant as disconnected. Returns `false` if
+    /// they don't have a token, so the session can't be resumed.
+    fn disconnect(&self, user_id: UserId, channel_id: ChannelId) -> bool {
+        let mut has_token = false;
+        for mut session in self.inner.iter_mut() {
+            if session.user_id == user_id && session.channel_id == channel_id {
+                session.disconnected_at = Some(Instant::now());
+                has_token = true;
+            }
+        }
+        has_token
+    }
+
+    /// Removes the token of a participant if it wasn't used to resume their
+    /// session in time. Returns whether the session is still waiting to be
+    /// resumed.
+    fn expire(&self, user_id: UserId, channel_id: ChannelId) -> bool {
+        let mut waiting = false;
+        self.inner.retain(|_, session| {
+            if session.user_id != user_id || session.channel_id != channel_id {
+                return true;
+            }
+            match session.disconnected_at {
+                Some(at) if at.elapsed() >= RECONNECT_TIMEOUT => false,
+                // disconnected again after a resume, the newer timeout will expire it
+                Some(_) => {
+                    waiting = true;
+                    true
+                }
+                None => true,
+            }
+        });
+        waiting
+    }
+}
+
+impl VoiceServer {
+    /// Removes a participant from a channel once their connection is gone, or
+    /// keeps them in it for a while if they can resume the session.
+    pub(super) fn leave_channel(&self, channel: &Channel, user_id: UserId) {
+        let (guild_id, channel_id) = channel.id;
+        if !self.reconnect_tokens.disconnect(user_id, channel.id) {
+            channel.remove_user(user_id);
+            self.deps
+                .diagnostics
+                .voice
+                .remove(guild_id, channel_id, user_id);
+            return;
+        }
+
+        let svc = self.clone();
+        let channel = channel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(RECONNECT_TIMEOUT).await;
+            if !svc.reconnect_tokens.expire(user_id, channel.id)
+                && !svc.connected.contains(&(channel.id, user_id))
+            {
+                channel.remove_user(user_id);
+                svc.deps
+                    .diagnostics
+                    .voice
+                    .remove(guild_id, channel_id, user_id);
+                tracing::info!(
+                    { participant = %user_id, guild = %guild_id, channel = %channel_id },
+                    "voice session wasn't resumed in time",
+                );
+            }
+        });
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetReconnectTokenRequest {
+    pub guild_id: u64,
+    pub channel_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetReconnectTokenResponse {
+    pub token: SmolStr,
+    /// How long the session can be resumed for after the connection drops, in seconds.
+    pub timeout_secs: u64,
+}
+
+/// Gets a token to resume the voice session of the user in a channel with,
+/// if their connection drops. Any token they got before stops working.
+pub async fn get_reconnect_token_handler(
+    svc: &VoiceServer,
+    user_id: u64,
+    request: GetReconnectTokenRequest,
+) -> ServerResult<GetReconnectTokenResponse> {
+    let GetReconnectTokenRequest {
+        guild_id,
+        channel_id,
+    } = request;
+    let chan_id = (guild_id, channel_id);
+
+    if !svc.connected.contains(&(chan_id, user_id)) {
+        bail!((
+            "h.not-in-voice-channel",
+            "you aren't connected to this voice channel"
+        ));
+    }
+
+    Ok(GetReconnectTokenResponse {
+        token: svc.reconnect_tokens.issue(user_id, chan_id),
+        timeout_secs: RECONNECT_TIMEOUT.as_secs(),
+    })
+}
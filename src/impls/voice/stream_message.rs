@@ -11,6 +11,11 @@ pub async fn handler(
     mut socket: Socket<StreamMessageResponse, StreamMessageRequest>,
 ) -> Result<(), HrpcServerError> {
     let user_id = svc.valid_sessions.auth(&request)?;
+    let reconnect_token = request
+        .header_map()
+        .and_then(|headers| headers.get(reconnect::RECONNECT_TOKEN_HEADER))
+        .and_then(|value| value.to_str().ok())
+        .map(SmolStr::new);
 
     let fut = async move {
         let wait_for_initialize = socket.receive_message().map(|res| match res {
@@ -37,6 +42,11 @@ pub async fn handler(
         svc.chat_tree
             .check_guild_user_channel(guild_id, user_id, channel_id)
             .await?;
+        // listeners in stage channels don't get a producer, so they can only listen
+        let can_speak = svc
+            .chat_tree
+            .can_speak_in_channel(guild_id, channel_id, user_id)
+            .await?;
 
         let mut channel = svc.channels.get(&svc.worker_pool, chan_id).await?;
 
@@ -48,181 +58,234 @@ pub async fn handler(
             })
             .await?;
 
-        let wait_for_prepare = socket.receive_message().map(|res| match res {
-            Ok(StreamMessageRequest {
-                message: Some(RequestMessage::PrepareForJoinChannel(p)),
-            }) => from_json::<RtpCapabilities>(p.rtp_capabilities),
-            Err(err) => Err(err.into()),
-            _ => Err(zero_data_err()),
-        });
-
-        let client_capabilities = timeout(5, wait_for_prepare).await?;
-
-        let user = User::new(&channel).await.unwrap();
-        *user.inner.capabilities.lock() = Some(client_capabilities);
-
-        let consumer_transport_options = {
-            let guard = user.inner.transports.lock().await;
-            let consumer = &guard.consumer;
-            Some(TransportOptions {
-                id: into_json(&consumer.id()),
-                dtls_parameters: into_json(&consumer.dtls_parameters()),
-                ice_candidates: consumer.ice_candidates().iter().map(into_json).collect(),
-                ice_parameters: into_json(consumer.ice_parameters()),
-            })
-        };
-
-        let producer_transport_options = {
-            let guard = user.inner.transports.lock().await;
-            let producer = &guard.producer;
-            Some(TransportOptions {
-                id: into_json(&producer.id()),
-                dtls_parameters: into_json(&producer.dtls_parameters()),
-                ice_candidates: producer.ice_candidates().iter().map(into_json).collect(),
-                ice_parameters: into_json(producer.ice_parameters()),
-            })
-        };
-
-        socket
-            .send_message(StreamMessageResponse {
-                message: Some(ResponseMessage::PreparedForJoinChannel(
-                    PreparedForJoinChannel {
-                        consumer_transport_options,
-                        producer_transport_options,
-                    },
-                )),
-            })
-            .await?;
+        let (user, other_users) = match reconnect_token {
+            Some(token) => {
+                if svc.connected.contains(&(chan_id, user_id)) {
+                    bail!((
+                        "scherzo.voice-session-connected",
+                        "voice session to resume is still connected",
+                    ));
+                }
+                svc.reconnect_tokens.resume(&token, user_id, chan_id)?;
+                let Some(user) = channel.inner.clients.get(&user_id).map(|user| user.clone())
+                else {
+                    bail!(reconnect::INVALID_TOKEN_ERR);
+                };
 
-        let wait_for_join = socket.receive_message().map(|res| match res {
-            Ok(StreamMessageRequest {
-                message: Some(RequestMessage::JoinChannel(join)),
-            }) => {
-                let consumer_dtls_paramaters: DtlsParameters =
-                    from_json(join.consumer_dtls_paramaters)?;
-                let producer_dtls_paramaters: DtlsParameters =
-                    from_json(join.producer_dtls_paramaters)?;
-                let rtp_parameters: RtpParameters = from_json(join.rtp_paramaters)?;
-                Ok((
-                    consumer_dtls_paramaters,
-                    producer_dtls_paramaters,
-                    rtp_parameters,
-                ))
+                // the transports are already connected, so only ICE has to be
+                // done again for the client's new network
+                let (consumer_transport_options, producer_transport_options) =
+                    match user.restart_transports().await {
+                        Ok(options) => options,
+                        Err(err) => {
+                            return Err((
+                                "scherzo.voice-restart-ice",
+                                format!("could not restart ICE: {}", err),
+                            )
+                                .into());
+                        }
+                    };
+
+                socket
+                    .send_message(StreamMessageResponse {
+                        message: Some(ResponseMessage::PreparedForJoinChannel(
+                            PreparedForJoinChannel {
+                                consumer_transport_options: Some(consumer_transport_options),
+                                producer_transport_options: Some(producer_transport_options),
+                            },
+                        )),
+                    })
+                    .await?;
+
+                tracing::info!("resumed voice session");
+
+                let other_users = user.consumer_options(&channel, user_id).await;
+                (user, other_users)
             }
-            Err(err) => Err(err.into()),
-            _ => Err(zero_data_err()),
-        });
-
-        let (consumer_dtls_paramaters, producer_dtls_paramaters, rtp_parameters) =
-            timeout(8, wait_for_join).await?;
-
-        if let Err(err) = user
-            .inner
-            .transports
-            .lock()
-            .await
-            .producer
-            .connect(WebRtcTransportRemoteParameters {
-                dtls_parameters: producer_dtls_paramaters,
-            })
-            .await
-        {
-            return Err((
-                "scherzo.voice-producer-connect",
-                format!("could not connect producer transport: {}", err),
-            )
-                .into());
-        } else {
-            tracing::info!("connected producer transport",);
-        }
-        if let Err(err) = user
-            .inner
-            .transports
-            .lock()
-            .await
-            .consumer
-            .connect(WebRtcTransportRemoteParameters {
-                dtls_parameters: consumer_dtls_paramaters,
-            })
-            .await
-        {
-            return Err((
-                "scherzo.voice-consumer-connect",
-                format!("could not connect consumer transport: {}", err),
-            )
-                .into());
-        } else {
-            tracing::info!("connected consumer transport",);
-        }
+            None => {
+                let wait_for_prepare = socket.receive_message().map(|res| match res {
+                    Ok(StreamMessageRequest {
+                        message: Some(RequestMessage::PrepareForJoinChannel(p)),
+                    }) => from_json::<RtpCapabilities>(p.rtp_capabilities),
+                    Err(err) => Err(err.into()),
+                    _ => Err(zero_data_err()),
+                });
+
+                let client_capabilities = timeout(5, wait_for_prepare).await?;
+
+                let user = User::new(&channel).await.unwrap();
+                *user.inner.capabilities.lock() = Some(client_capabilities);
+
+                let (consumer_transport_options, producer_transport_options) = {
+                    let guard = user.inner.transports.lock().await;
+                    (
+                        Some(transport_options(
+                            &guard.consumer,
+                            guard.consumer.ice_parameters(),
+                        )),
+                        Some(transport_options(
+                            &guard.producer,
+                            guard.producer.ice_parameters(),
+                        )),
+                    )
+                };
 
-        let producer_options = ProducerOptions::new(MediaKind::Audio, rtp_parameters);
+                socket
+                    .send_message(StreamMessageResponse {
+                        message: Some(ResponseMessage::PreparedForJoinChannel(
+                            PreparedForJoinChannel {
+                                consumer_transport_options,
+                                producer_transport_options,
+                            },
+                        )),
+                    })
+                    .await?;
+
+                let wait_for_join = socket.receive_message().map(|res| match res {
+                    Ok(StreamMessageRequest {
+                        message: Some(RequestMessage::JoinChannel(join)),
+                    }) => {
+                        let consumer_dtls_paramaters: DtlsParameters =
+                            from_json(join.consumer_dtls_paramaters)?;
+                        let producer_dtls_paramaters: DtlsParameters =
+                            from_json(join.producer_dtls_paramaters)?;
+                        let rtp_parameters: RtpParameters = from_json(join.rtp_paramaters)?;
+                        Ok((
+                            consumer_dtls_paramaters,
+                            producer_dtls_paramaters,
+                            rtp_parameters,
+                        ))
+                    }
+                    Err(err) => Err(err.into()),
+                    _ => Err(zero_data_err()),
+                });
+
+                let (consumer_dtls_paramaters, producer_dtls_paramaters, rtp_parameters) =
+                    timeout(8, wait_for_join).await?;
+
+                if let Err(err) = user
+                    .inner
+                    .transports
+                    .lock()
+                    .await
+                    .producer
+                    .connect(WebRtcTransportRemoteParameters {
+                        dtls_parameters: producer_dtls_paramaters,
+                    })
+                    .await
+                {
+                    return Err((
+                        "scherzo.voice-producer-connect",
+                        format!("could not connect producer transport: {}", err),
+                    )
+                        .into());
+                } else {
+                    tracing::info!("connected producer transport",);
+                }
+                if let Err(err) = user
+                    .inner
+                    .transports
+                    .lock()
+                    .await
+                    .consumer
+                    .connect(WebRtcTransportRemoteParameters {
+                        dtls_parameters: consumer_dtls_paramaters,
+                    })
+                    .await
+                {
+                    return Err((
+                        "scherzo.voice-consumer-connect",
+                        format!("could not connect consumer transport: {}", err),
+                    )
+                        .into());
+                } else {
+                    tracing::info!("connected consumer transport",);
+                }
 
-        match user.create_producer(producer_options).await {
-            Ok(producer) => {
-                tracing::info!(
-                    { producer_id = %producer.id() },
-                    "created producer",
-                );
-                *user.inner.producer.lock().await = Some(producer);
-            }
-            Err(err) => {
-                return Err((
-                    "scherzo.voice-create-producer",
-                    format!("could not create producer: {}", err),
-                )
-                    .into());
-            }
-        }
+                if can_speak {
+                    let producer_options = ProducerOptions::new(MediaKind::Audio, rtp_parameters);
 
-        let mut other_users = Vec::new();
-        for val in channel.get_all_users() {
-            let maybe_rtp_capabilities = val.inner.capabilities.lock().clone();
-            let maybe_producer_id = val.inner.producer.lock().await.as_ref().map(|p| p.id());
-            if let (other_user_id, Some(user_producer_id), Some(user_rtp_capabilities)) =
-                (*val.key(), maybe_producer_id, maybe_rtp_capabilities)
-            {
-                let consumer_options =
-                    ConsumerOptions::new(user_producer_id, user_rtp_capabilities);
-
-                match user.create_consumer(consumer_options).await {
-                    Ok(consumer) => {
-                        tracing::info!(
-                            { for_producer = %user_producer_id },
-                            "created consumer",
-                        );
-                        other_users.push(UserConsumerOptions {
-                            producer_id: into_json(&user_producer_id),
-                            consumer_id: into_json(&consumer.id()),
-                            rtp_parameters: into_json(consumer.rtp_parameters()),
-                            user_id: other_user_id,
-                        });
-                        user.inner.consumers.insert(consumer.id(), consumer);
+                    match user.create_producer(producer_options).await {
+                        Ok(producer) => {
+                            tracing::info!(
+                                { producer_id = %producer.id() },
+                                "created producer",
+                            );
+                            *user.inner.producer.lock().await = Some(producer);
+                        }
+                        Err(err) => {
+                            return Err((
+                                "scherzo.voice-create-producer",
+                                format!("could not create producer: {}", err),
+                            )
+                                .into());
+                        }
                     }
-                    Err(err) => {
-                        tracing::error!(
-                            { for_producer = %user_producer_id },
-                            "could not create consumer: {}", err,
-                        );
-                        return Err((
-                            "scherzo.voice-create-consumer",
-                            format!("could not create consumer: {}", err),
-                        )
-                            .into());
+                }
+
+                let mut other_users = Vec::new();
+                for val in channel.get_all_users() {
+                    let maybe_rtp_capabilities = val.inner.capabilities.lock().clone();
+                    let maybe_producer_id =
+                        val.inner.producer.lock().await.as_ref().map(|p| p.id());
+                    if let (other_user_id, Some(user_producer_id), Some(user_rtp_capabilities)) =
+                        (*val.key(), maybe_producer_id, maybe_rtp_capabilities)
+                    {
+                        let consumer_options =
+                            ConsumerOptions::new(user_producer_id, user_rtp_capabilities);
+
+                        match user.create_consumer(consumer_options).await {
+                            Ok(consumer) => {
+                                tracing::info!(
+                                    { for_producer = %user_producer_id },
+                                    "created consumer",
+                                );
+                                other_users.push(UserConsumerOptions {
+                                    producer_id: into_json(&user_producer_id),
+                                    consumer_id: into_json(&consumer.id()),
+                                    rtp_parameters: into_json(consumer.rtp_parameters()),
+                                    user_id: other_user_id,
+                                });
+                                user.inner.consumers.insert(consumer.id(), consumer);
+                            }
+                            Err(err) => {
+                                tracing::error!(
+                                    { for_producer = %user_producer_id },
+                                    "could not create consumer: {}", err,
+                                );
+                                return Err((
+                                    "scherzo.voice-create-consumer",
+                                    format!("could not create consumer: {}", err),
+                                )
+                                    .into());
+                            }
+                        }
                     }
                 }
-            }
-        }
 
-        if let Err((user_id, err)) = channel.add_user(user_id, user.clone()).await {
-            tracing::error!({ for_participant = %user_id }, "could not create consumer: {}", err);
-            return Err((
-                "scherzo.voice-create-consumer",
-                format!("could not create consumer: {}", err),
-            )
-                .into());
-        } else {
-            tracing::info!("user added to voice channel successfully");
-        }
+                // a session that was left to be resumed is replaced by this one
+                svc.reconnect_tokens.discard(user_id, chan_id);
+                if !svc.connected.contains(&(chan_id, user_id))
+                    && channel.inner.clients.contains_key(&user_id)
+                {
+                    channel.remove_user(user_id);
+                }
+
+                if let Err((user_id, err)) = channel.add_user(user_id, user.clone()).await {
+                    tracing::error!({ for_participant = %user_id }, "could not create consumer: {}", err);
+                    return Err((
+                        "scherzo.voice-create-consumer",
+                        format!("could not create consumer: {}", err),
+                    )
+                        .into());
+                } else {
+                    tracing::info!("user added to voice channel successfully");
+                }
+
+                (user, other_users)
+            }
+        };
+        svc.connected.insert((chan_id, user_id));
 
         socket
             .send_message(StreamMessageResponse {
@@ -268,18 +331,27 @@ pub async fn handler(
             ServerResult::Ok(())
         };
 
-        tokio::select! {
-            res = process_server_events => {
-                channel.remove_user(user_id);
-                res?
-            },
-            res = process_client_messages => {
-                channel.remove_user(user_id);
-                res?
-            },
-        }
+        let collect_stats = async {
+            let mut interval = tokio::time::interval(STATS_INTERVAL);
+            loop {
+                interval.tick().await;
+                match user.quality_stats(chan_id, user_id).await {
+                    Ok(Some(stats)) => svc.deps.diagnostics.voice.update(stats),
+                    Ok(None) => {}
+                    Err(err) => tracing::warn!("could not get producer stats: {}", err),
+                }
+            }
+        };
+
+        let res = tokio::select! {
+            res = process_server_events => res,
+            res = process_client_messages => res,
+            _ = collect_stats => Ok(()),
+        };
+        svc.connected.remove(&(chan_id, user_id));
+        svc.leave_channel(&channel, user_id);
 
-        Ok(())
+        res
     };
 
     fut.instrument(tracing::info_span!(
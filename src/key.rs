@@ -1,4 +1,7 @@
-use std::path::PathBuf;
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use ahash::RandomState;
 use dashmap::{mapref::one::RefMut, DashMap};
@@ -8,9 +11,12 @@ use harmony_rust_sdk::api::{
     exports::{hrpc::encode::encode_protobuf_message, prost::Message},
     harmonytypes::Token,
 };
-use hrpc::client::transport::http::Hyper;
-use hyper::Uri;
+use hrpc::client::transport::http::{hyper::HttpClient, Hyper};
+use hyper::{http, Body, Uri};
+use serde::{Deserialize, Serialize};
+use sha3::Digest;
 use smol_str::SmolStr;
+use tokio::sync::Mutex;
 
 use crate::ServerError;
 
@@ -27,37 +33,106 @@ pub fn verify_token(token: &Token, pubkey: &PublicKey) -> Result<(), ServerError
         .map_err(|_| ServerError::CouldntVerifyTokenData)
 }
 
+/// A federation signing key, as published at `/_scherzo/federation/keys`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyInfo {
+    pub key_id: String,
+    /// The public key, in hex.
+    pub public_key: String,
+    /// In seconds since unix epoch.
+    pub valid_from: u64,
+    /// When the key stops being accepted, in seconds since unix epoch. Only
+    /// set for keys that were rotated out.
+    #[serde(default)]
+    pub valid_until: Option<u64>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Keyring {
+    /// When the current key was made, in seconds since unix epoch.
+    #[serde(default)]
+    current_valid_from: Option<u64>,
+    /// Keys that were rotated out, oldest first.
+    old_keys: Vec<KeyInfo>,
+}
+
+/// Identifies a key by the start of the hash of its public key.
+pub fn key_id(pubkey: &PublicKey) -> String {
+    encode_hex(&sha3::Sha3_256::digest(pubkey.as_ref())[..8])
+}
+
+fn parse_public_key(hex: &str) -> Option<PublicKey> {
+    PublicKey::from_slice(&decode_hex(hex)?).ok()
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+/// Writes a file by renaming a temporary one over it, so it's never left half written.
+async fn write_atomic(path: &Path, data: &[u8]) -> Result<(), ServerError> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    tokio::fs::write(&tmp, data)
+        .await
+        .map_err(|_| ServerError::CantGetKey)?;
+    tokio::fs::rename(&tmp, path)
+        .await
+        .map_err(|_| ServerError::CantGetKey)
+}
+
 #[derive(Debug)]
 pub struct Manager {
     keys: DashMap<SmolStr, PublicKey, RandomState>,
+    /// Keys other hosts rotated out but still accept, with when they stop
+    /// being valid.
+    old_keys: DashMap<SmolStr, Vec<(PublicKey, u64)>, RandomState>,
     clients: DashMap<SmolStr, AuthServiceClient<Hyper>, RandomState>,
+    http: HttpClient,
     federation_key: PathBuf,
+    /// Keeps two rotations from running at the same time.
+    rotation: Mutex<()>,
 }
 
 impl Manager {
-    pub fn new(federation_key: PathBuf) -> Self {
+    pub fn new(federation_key: PathBuf, http: HttpClient) -> Self {
         Self {
             federation_key,
+            http,
             keys: DashMap::default(),
+            old_keys: DashMap::default(),
             clients: DashMap::default(),
+            rotation: Mutex::new(()),
         }
     }
 
+    /// Returns the number of cached public keys and clients.
+    pub fn cache_sizes(&self) -> (usize, usize) {
+        (self.keys.len(), self.clients.len())
+    }
+
     pub async fn generate_token(&self, data: impl Message) -> Result<Token, ServerError> {
         let buf = encode_protobuf_message(&data);
         let data = buf.to_vec();
+        let sig = self.sign(&data).await?;
+
+        Ok(Token { sig, data })
+    }
 
+    /// Signs the data with our federation key.
+    pub async fn sign(&self, data: &[u8]) -> Result<Vec<u8>, ServerError> {
         let key = self.get_own_key().await?;
-        let sig = key
+        Ok(key
             .sk
-            .sign(&data, Some(ed25519_compact::Noise::generate()))
-            .to_vec();
-
-        Ok(Token { sig, data })
+            .sign(data, Some(ed25519_compact::Noise::generate()))
+            .to_vec())
     }
 
     pub fn invalidate_key(&self, host: &str) {
         self.keys.remove(host);
+        self.old_keys.remove(host);
     }
 
     pub async fn get_own_key(&self) -> Result<KeyPair, ServerError> {
@@ -79,6 +154,93 @@ impl Manager {
         }
     }
 
+    /// The keyring is kept next to the federation key, with `.old` added to its name.
+    fn keyring_path(&self) -> PathBuf {
+        let mut path = self.federation_key.as_os_str().to_owned();
+        path.push(".old");
+        path.into()
+    }
+
+    async fn read_keyring(&self) -> Result<Keyring, ServerError> {
+        match tokio::fs::read(self.keyring_path()).await {
+            Ok(raw) => serde_json::from_slice(&raw).map_err(|_| ServerError::CantGetKey),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Keyring::default()),
+            Err(_) => Err(ServerError::CantGetKey),
+        }
+    }
+
+    /// Gets our keys that other hosts should accept: the current one first,
+    /// then the rotated out ones that are still valid.
+    pub async fn get_own_keys(&self) -> Result<Vec<KeyInfo>, ServerError> {
+        let current = self.get_own_key().await?;
+        let keyring = self.read_keyring().await?;
+        let now = now_secs();
+
+        // keys from before rotation was possible only have their file to go by
+        let valid_from = match keyring.current_valid_from {
+            Some(valid_from) => valid_from,
+            None => tokio::fs::metadata(&self.federation_key)
+                .await
+                .ok()
+                .and_then(|meta| meta.modified().ok())
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map_or(now, |since| since.as_secs()),
+        };
+        let current = KeyInfo {
+            key_id: key_id(&current.pk),
+            public_key: encode_hex(current.pk.as_ref()),
+            valid_from,
+            valid_until: None,
+        };
+
+        let old_keys = keyring
+            .old_keys
+            .into_iter()
+            .rev()
+            .filter(|key| key.valid_until.map_or(false, |until| until > now));
+        Ok(std::iter::once(current).chain(old_keys).collect())
+    }
+
+    /// Replaces our federation key with a new one. The old key stays valid for
+    /// `keep_old_for_secs`, so tokens signed with it can still be verified.
+    pub async fn rotate_keys(&self, keep_old_for_secs: u64) -> Result<KeyInfo, ServerError> {
+        let _guard = self.rotation.lock().await;
+
+        let mut keys = self.get_own_keys().await?;
+        let now = now_secs();
+        let mut old_keys = keys.split_off(1);
+        old_keys.reverse();
+        let mut retired = keys.pop().expect("current key is always there");
+        retired.valid_until = Some(now + keep_old_for_secs);
+        old_keys.push(retired);
+
+        let keyring = Keyring {
+            current_valid_from: Some(now),
+            old_keys,
+        };
+        write_atomic(
+            &self.keyring_path(),
+            &serde_json::to_vec_pretty(&keyring).expect("must be valid json"),
+        )
+        .await?;
+
+        let new_key = ed25519_compact::KeyPair::from_seed(Seed::generate());
+        write_atomic(&self.federation_key, new_key.as_ref()).await?;
+
+        let info = KeyInfo {
+            key_id: key_id(&new_key.pk),
+            public_key: encode_hex(new_key.pk.as_ref()),
+            valid_from: now,
+            valid_until: None,
+        };
+        tracing::info!(
+            "rotated federation key, new key is {}, old keys: {}",
+            info.key_id,
+            keyring.old_keys.len()
+        );
+        Ok(info)
+    }
+
     pub async fn get_key(&self, host: SmolStr) -> Result<PublicKey, ServerError> {
         let key = if let Some(key) = self.keys.get(&host) {
             *key
@@ -101,6 +263,68 @@ impl Manager {
         Ok(key)
     }
 
+    /// Gets the keys a host rotated out but still accepts. Hosts that don't
+    /// publish them have none.
+    async fn get_old_keys(&self, host: &SmolStr) -> Vec<PublicKey> {
+        let now = now_secs();
+        if let Some(keys) = self.old_keys.get(host) {
+            return valid_keys(&keys, now);
+        }
+
+        let keys = self.fetch_old_keys(host).await.unwrap_or_default();
+        let valid = valid_keys(&keys, now);
+        self.old_keys.insert(host.clone(), keys);
+        valid
+    }
+
+    async fn fetch_old_keys(&self, host: &str) -> Option<Vec<(PublicKey, u64)>> {
+        let url: Uri = format!("{}/_scherzo/federation/keys", host.trim_end_matches('/'))
+            .parse()
+            .ok()?;
+        let request = http::Request::builder()
+            .method(http::Method::GET)
+            .uri(url)
+            .body(Body::empty())
+            .ok()?;
+        let response = self.http.request(request).await.ok()?;
+        if !response.status().is_success() {
+            return None;
+        }
+        let body = hyper::body::to_bytes(response.into_body()).await.ok()?;
+        let keys: Vec<KeyInfo> = serde_json::from_slice(&body).ok()?;
+        Some(
+            keys.into_iter()
+                .filter_map(|key| Some((parse_public_key(&key.public_key)?, key.valid_until?)))
+                .collect(),
+        )
+    }
+
+    /// Verifies a token a host signed. If its current key doesn't verify it,
+    /// the key is fetched again in case it changed, and then the keys the host
+    /// rotated out but still accepts are tried.
+    pub async fn verify_host_token(&self, host: SmolStr, token: &Token) -> Result<(), ServerError> {
+        let first_verify_result = verify_token(token, &self.get_key(host.clone()).await?);
+        if !matches!(
+            first_verify_result,
+            Err(ServerError::CouldntVerifyTokenData)
+        ) {
+            return first_verify_result;
+        }
+
+        self.invalidate_key(&host);
+        let verify_result = verify_token(token, &self.get_key(host.clone()).await?);
+        if !matches!(verify_result, Err(ServerError::CouldntVerifyTokenData)) {
+            return verify_result;
+        }
+
+        self.get_old_keys(&host)
+            .await
+            .iter()
+            .any(|key| verify_token(token, key).is_ok())
+            .then(|| ())
+            .ok_or(ServerError::CouldntVerifyTokenData)
+    }
+
     fn get_client(
         &self,
         host: SmolStr,
@@ -112,3 +336,37 @@ impl Manager {
         })
     }
 }
+
+fn valid_keys(keys: &[(PublicKey, u64)], now: u64) -> Vec<PublicKey> {
+    keys.iter()
+        .filter(|(_, valid_until)| *valid_until > now)
+        .map(|(key, _)| *key)
+        .collect()
+}
+
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(hex.get(at..at + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn public_keys_roundtrip_through_hex() {
+        let key = KeyPair::from_seed(Seed::generate());
+        let parsed = parse_public_key(&encode_hex(key.pk.as_ref())).expect("must parse");
+        assert_eq!(parsed, key.pk);
+        assert!(parse_public_key("abc").is_none());
+    }
+}
@@ -11,6 +11,8 @@ use harmony_rust_sdk::api::exports::hrpc::exports::http;
 use parking_lot::Mutex;
 use triomphe::Arc;
 
+pub mod admin_cli;
+pub mod check;
 pub mod config;
 pub mod db;
 pub mod error;
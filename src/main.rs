@@ -5,6 +5,7 @@
 static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
 
 use std::{
+    convert::Infallible,
     net::SocketAddr,
     path::{Path, PathBuf},
     time::Duration,
@@ -16,7 +17,7 @@ use harmony_rust_sdk::api::{
 };
 use hrpc::{
     common::layer::trace::TraceLayer as HrpcTraceLayer,
-    exports::futures_util::TryFutureExt,
+    exports::futures_util::{future::select_all, TryFutureExt},
     server::{
         transport::http::{
             box_body, layer::errid_to_status::ErrorIdentifierToStatusLayer, HttpConfig,
@@ -26,15 +27,18 @@ use hrpc::{
 };
 use hyper::header;
 use scherzo::{
+    admin_cli,
     config::Config,
     db::{
         migration::{apply_migrations, get_db_version},
         Db,
     },
     impls::{
-        against,
+        against, alerts,
         chat::{AdminGuildKeys, DEFAULT_ROLE_ID},
+        disk_guard, ip_bans, replication,
         rest::RestServiceLayer,
+        startup::Startup,
         Dependencies, HELP_TEXT,
     },
     utils, ServerError,
@@ -49,6 +53,7 @@ use tower_http::{
 };
 use tracing::{debug, error, info, info_span, warn, Instrument, Level};
 use tracing_subscriber::{filter::Targets, fmt, prelude::*};
+use triomphe::Arc;
 
 // in seconds
 // do once per hour
@@ -60,6 +65,9 @@ fn main() {
     let mut console = false;
     let mut jaeger = false;
     let mut level_filter = Level::INFO;
+    let mut check = false;
+    let mut admin = false;
+    let mut outbound_url = None;
 
     for (index, arg) in std::env::args().enumerate() {
         match arg.as_str() {
@@ -74,6 +82,9 @@ fn main() {
             "--enable-jaeger" => {
                 jaeger = true;
             }
+            "--outbound-url" => outbound_url = std::env::args().nth(index + 1),
+            "check" if index == 1 => check = true,
+            "admin" if index == 1 => admin = true,
             "-d" | "--debug" => level_filter = Level::DEBUG,
             "-v" | "--verbose" => level_filter = Level::TRACE,
             "-q" | "--quiet" => level_filter = Level::ERROR,
@@ -81,49 +92,79 @@ fn main() {
         }
     }
 
+    if check {
+        run_check(db_path, outbound_url);
+    }
+    if admin {
+        run_admin(db_path);
+    }
+
     run(db_path, console, jaeger, level_filter)
 }
 
-pub fn run(db_path: String, console: bool, jaeger: bool, log_level: Level) {
+/// Checks if the server would start fine, printing a report. Exits with 1 if
+/// any check failed.
+fn run_check(db_path: String, outbound_url: Option<String>) -> ! {
     let rt = tokio::runtime::Runtime::new().expect("failed to create tokio runtime");
-    let _rt_guard = rt.enter();
+    let report = rt.block_on(scherzo::check::run(
+        Path::new("./config.toml"),
+        &db_path,
+        outbound_url.as_deref(),
+    ));
+    println!("{}", report);
+    exit(if report.failed() { 1 } else { 0 })
+}
 
-    setup_tracing(console, jaeger, log_level);
-    let config = parse_config();
-    let (db, current_db_version) = rt.block_on(setup_db(db_path, &config));
-    let (deps, fed_event_receiver) = rt.block_on(Dependencies::new(&db, config)).unwrap();
+/// Runs a one-shot admin command, like `scherzo admin list-guilds`. Exits
+/// with 1 if it failed.
+fn run_admin(db_path: String) -> ! {
+    let args = std::env::args().skip(2).collect::<Vec<_>>();
+    if matches!(
+        args.first().map(String::as_str),
+        None | Some("help" | "-h" | "--help")
+    ) {
+        println!("{}", admin_cli::USAGE);
+        exit(0);
+    }
+    let args = match admin_cli::AdminArgs::parse(&args) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, admin_cli::USAGE);
+            exit(1);
+        }
+    };
 
-    if current_db_version == 0 {
-        rt.block_on(setup_admin_guild(deps.as_ref()));
+    let rt = tokio::runtime::Runtime::new().expect("failed to create tokio runtime");
+    match rt.block_on(admin_cli::run(args, Path::new("./config.toml"), &db_path)) {
+        Ok(output) => {
+            println!("{}", output.trim_end());
+            exit(0)
+        }
+        Err(err) => {
+            eprintln!("error: {}", err);
+            exit(1)
+        }
     }
+}
 
-    let admin_guild_keys = rt
-        .block_on(AdminGuildKeys::new(&deps.chat_tree))
-        .expect("failed to get keys")
-        .expect("keys must be created");
-    let _ = deps.chat_tree.admin_guild_keys.set(admin_guild_keys);
+pub fn run(db_path: String, console: bool, jaeger: bool, log_level: Level) {
+    let rt = tokio::runtime::Runtime::new().expect("failed to create tokio runtime");
+    let _rt_guard = rt.enter();
 
-    let (server, rest) = scherzo::impls::setup_server(deps.clone(), fed_event_receiver, log_level);
-    let server = server
-        .layer(ErrorIdentifierToStatusLayer::new(
-            ServerError::identifier_to_status,
-        ))
-        .layer(HrpcTraceLayer::default_debug().span_fn(|req| {
-            let socket_addr = req
-                .extensions()
-                .get::<SocketAddr>()
-                .map_or_else(String::new, SocketAddr::to_string);
-            tracing::debug_span!("hrpc_request", socket_addr = %socket_addr)
-        }));
-
-    let integrity = start_integrity_check_thread(deps.as_ref());
-
-    let transport = setup_transport(deps.as_ref(), rest);
-    let serve = tokio::spawn(
-        transport
-            .serve(server)
-            .instrument(info_span!("scherzo::serve")),
-    );
+    setup_tracing(console, jaeger, log_level);
+    let config = Startup::new("scherzo").stage("config", parse_config);
+
+    let mut hosts = Vec::with_capacity(config.virtual_hosts.len() + 1);
+    for vhost in &config.virtual_hosts {
+        info!("starting virtual host {}", vhost.host);
+        hosts.push(start_host(
+            &rt,
+            vhost.db_path.clone(),
+            vhost.apply(&config),
+            log_level,
+        ));
+    }
+    hosts.push(start_host(&rt, db_path, config, log_level));
 
     rt.block_on(async {
         tokio::select! {
@@ -131,18 +172,25 @@ pub fn run(db_path: String, console: bool, jaeger: bool, log_level: Level) {
             res = tokio::signal::ctrl_c() => {
                 res.expect("failed to wait for signal");
             }
-            res = serve => {
-                res.expect("serve task panicked").expect("failed to serve");
+            (res, _, _) = select_all(hosts.iter_mut().map(|host| &mut host.serve)) => {
+                res.expect("serve task panicked");
             }
         }
     });
 
     tracing::info!("shutting down...");
 
-    integrity.abort();
+    for host in hosts {
+        if let Some(integrity) = host.integrity {
+            integrity.abort();
+        }
 
-    if let Ok(Err(err)) = rt.block_on(tokio::time::timeout(Duration::from_secs(1), db.flush())) {
-        panic!("failed to flush: {}", err);
+        if let Ok(Err(err)) = rt.block_on(tokio::time::timeout(
+            Duration::from_secs(1),
+            host.db.flush(),
+        )) {
+            panic!("failed to flush: {}", err);
+        }
     }
 
     rt.shutdown_timeout(Duration::from_secs(1));
@@ -152,60 +200,201 @@ pub fn run(db_path: String, console: bool, jaeger: bool, log_level: Level) {
     std::process::exit(0);
 }
 
-fn parse_config() -> Config {
+/// A homeserver running in this process, either the main one or a virtual host.
+struct RunningHost {
+    db: Db,
+    /// Not running on standbys.
+    integrity: Option<tokio::task::JoinHandle<()>>,
+    /// Follows the primary instead on standbys, until they are promoted.
+    serve: tokio::task::JoinHandle<()>,
+}
+
+/// Starts a host in stages: media, database, migrations, replication, caches,
+/// services and listeners. The host is ready once the listeners are started.
+fn start_host(
+    rt: &tokio::runtime::Runtime,
+    db_path: String,
+    config: Config,
+    log_level: Level,
+) -> RunningHost {
+    let host = config.host.clone();
+    let startup = Startup::new(host.clone());
+    let watched_db_path = PathBuf::from(&db_path);
+
+    startup.stage("media", || {
+        if config.media.s3.is_none() {
+            std::fs::create_dir_all(config.media.media_root.join("remote"))
+                .map_err(|err| format!("could not create media root dir: {}", err))?;
+        }
+        Ok::<_, String>(())
+    });
+    let (db, current_db_version, needs_migration) = startup.stage("database", || {
+        rt.block_on(setup_db(db_path.clone(), &config))
+    });
+    startup.stage("migrations", || {
+        if !needs_migration {
+            return Ok(());
+        }
+        rt.block_on(migrate_db(&db, &db_path, &config, current_db_version))
+    });
+
+    let standby = startup.stage("replication", || {
+        replication::setup_primary(&db, config.replication.as_ref());
+        rt.block_on(replication::standby_config(
+            &db,
+            config.replication.as_ref(),
+        ))
+    });
+    if let Some(replication) = standby {
+        let serve = tokio::spawn(
+            replication::follow(db.clone(), replication)
+                .instrument(info_span!("scherzo::standby", host = %host)),
+        );
+        return RunningHost {
+            db,
+            integrity: None,
+            serve,
+        };
+    }
+
+    let (deps, fed_event_receiver) = startup.stage("caches", || {
+        rt.block_on(async {
+            let (deps, fed_event_receiver) = Dependencies::new(&db, config)
+                .await
+                .map_err(|err| err.to_string())?;
+
+            if current_db_version == 0 {
+                setup_admin_guild(deps.as_ref()).await;
+            }
+
+            let admin_guild_keys = AdminGuildKeys::new(&deps.chat_tree)
+                .await
+                .map_err(|err| format!("failed to get keys: {}", err))?
+                .ok_or_else(|| "admin guild keys must be created".to_string())?;
+            let _ = deps.chat_tree.admin_guild_keys.set(admin_guild_keys);
+
+            Ok::<_, String>((deps, fed_event_receiver))
+        })
+    });
+
+    let (server, rest, integrity) = startup.stage("services", || {
+        let (server, rest) =
+            scherzo::impls::setup_server(deps.clone(), fed_event_receiver, log_level, &startup);
+        let server = server
+            .layer(ErrorIdentifierToStatusLayer::new(
+                ServerError::identifier_to_status,
+            ))
+            .layer(HrpcTraceLayer::default_debug().span_fn(|req| {
+                let socket_addr = req
+                    .extensions()
+                    .get::<SocketAddr>()
+                    .map_or_else(String::new, SocketAddr::to_string);
+                tracing::debug_span!("hrpc_request", socket_addr = %socket_addr)
+            }));
+
+        let integrity = start_integrity_check_thread(&deps);
+        alerts::spawn_checker(deps.clone());
+        disk_guard::spawn_watchdog(deps.clone(), watched_db_path);
+
+        Ok::<_, Infallible>((server, rest, integrity))
+    });
+
+    let serve = startup.stage("listeners", || {
+        let transport = setup_transport(deps.as_ref(), rest);
+        Ok::<_, Infallible>(tokio::spawn(
+            async move {
+                transport.serve(server).await.expect("failed to serve");
+            }
+            .instrument(info_span!("scherzo::serve", host = %host)),
+        ))
+    });
+    // the readiness endpoint is served by the listeners, so it can't be
+    // reached before they are started anyway
+    startup.mark_ready();
+
+    RunningHost {
+        db,
+        integrity: Some(integrity),
+        serve,
+    }
+}
+
+fn parse_config() -> Result<Config, String> {
     let config_path = std::path::Path::new("./config.toml");
     let config: Config = if config_path.exists() {
-        toml::from_slice(&std::fs::read(config_path).expect("failed to read config file"))
-            .expect("failed to parse config file")
+        let raw = std::fs::read(config_path)
+            .map_err(|err| format!("failed to read config file: {}", err))?;
+        toml::from_slice(&raw).map_err(|err| format!("failed to parse config file: {}", err))?
     } else {
         info!("No config file found, writing default config file");
         std::fs::write(config_path, include_bytes!("../example_config.toml"))
-            .expect("failed to write default config file");
+            .map_err(|err| format!("failed to write default config file: {}", err))?;
         toml::from_slice(include_bytes!("../example_config.toml")).unwrap()
     };
     debug!("running with {:?}", config);
-    std::fs::create_dir_all(&config.media.media_root).expect("could not create media root dir");
 
     if config.policy.ratelimit.disable {
         warn!("rate limits are disabled, please take care!");
     }
 
-    config
+    Ok(config)
 }
 
-async fn setup_db(db_path: String, config: &Config) -> (Db, usize) {
-    let db = scherzo::db::open_db(db_path.clone(), config.db.clone()).await;
-    let (current_db_version, needs_migration) = get_db_version(&db)
-        .await
-        .expect("something went wrong while checking if the db needs migrations!!!");
-    if needs_migration {
-        // Backup db before attempting to apply migrations
-        if current_db_version > 0 {
-            let db_backup_name = format!("{}_backup_ver_{}", db_path, current_db_version);
-            let db_backup_path = config.db.db_backup_path.as_ref().map_or_else(
-                || Path::new(&db_backup_name).to_path_buf(),
-                |path| path.join(&db_backup_name),
-            );
-            if db_backup_path.exists() {
-                warn!(
-                    "there is already a backup with the same version. will not attempt to migrate"
-                );
-                exit(1);
-            }
-            warn!(
-                "preparing to migrate the database, backing up to {:?}!",
-                db_backup_path
+/// Opens the database, and gets its version and whether it needs migrations.
+async fn setup_db(db_path: String, config: &Config) -> Result<(Db, usize, bool), String> {
+    let db = scherzo::db::open_db(db_path, config.db.clone()).await;
+    let (current_db_version, needs_migration) = get_db_version(&db).await.map_err(|err| {
+        format!(
+            "something went wrong while checking if the db needs migrations: {}",
+            err
+        )
+    })?;
+    Ok((db, current_db_version, needs_migration))
+}
+
+async fn migrate_db(
+    db: &Db,
+    db_path: &str,
+    config: &Config,
+    current_db_version: usize,
+) -> Result<(), String> {
+    // Backup db before attempting to apply migrations
+    // databases that don't live in the db path (like postgres) need to be backed up by the admin
+    if current_db_version > 0 && !Path::new(db_path).exists() {
+        warn!("can't back up the database before migrating it, make sure you have a backup!");
+    } else if current_db_version > 0 {
+        let db_backup_name = format!("{}_backup_ver_{}", db_path, current_db_version);
+        let db_backup_path = config.db.db_backup_path.as_ref().map_or_else(
+            || Path::new(&db_backup_name).to_path_buf(),
+            |path| path.join(&db_backup_name),
+        );
+        if db_backup_path.exists() {
+            return Err(
+                "there is already a backup with the same version. will not attempt to migrate"
+                    .to_string(),
             );
-            copy_dir_all(Path::new(&db_path).to_path_buf(), db_backup_path)
-                .expect("could not backup the db, so not applying migrations!!!");
         }
-
-        warn!("applying database migrations!");
-        apply_migrations(&db, current_db_version)
-            .await
-            .expect("something went wrong while applying the migrations!!!");
+        warn!(
+            "preparing to migrate the database, backing up to {:?}!",
+            db_backup_path
+        );
+        utils::copy_dir_all(Path::new(db_path).to_path_buf(), db_backup_path).map_err(|err| {
+            format!(
+                "could not backup the db, so not applying migrations: {}",
+                err
+            )
+        })?;
     }
-    (db, current_db_version)
+
+    warn!("applying database migrations!");
+    apply_migrations(db, current_db_version)
+        .await
+        .map_err(|err| {
+            format!(
+                "something went wrong while applying the migrations: {}",
+                err
+            )
+        })
 }
 
 fn setup_transport(
@@ -244,7 +433,9 @@ fn setup_transport(
                 ),
         )
         .layer(rest)
-        .layer(against::AgainstLayer);
+        .layer(disk_guard::WriteGuardLayer::new(deps.disk_guard.clone()))
+        .layer(against::AgainstLayer)
+        .layer(ip_bans::IpBanLayer::new(deps.ip_bans.clone()));
 
     if let Some(tls_config) = deps.config.tls.as_ref() {
         transport = transport
@@ -373,7 +564,8 @@ async fn setup_admin_guild(deps: &Dependencies) {
     warn!("admin guild created! use the invite {} to join", invite_id);
 }
 
-fn start_integrity_check_thread(deps: &Dependencies) -> tokio::task::JoinHandle<()> {
+fn start_integrity_check_thread(deps: &Arc<Dependencies>) -> tokio::task::JoinHandle<()> {
+    let deps = deps.clone();
     let ctt = deps.chat_tree.clone();
     let att = deps.auth_tree.clone();
     let ptt = deps.profile_tree.clone();
@@ -394,6 +586,11 @@ fn start_integrity_check_thread(deps: &Dependencies) -> tokio::task::JoinHandle<
                 .await
             {
                 error!("database integrity check failed: {}", err);
+                deps.alerts.raise(
+                    &deps,
+                    "integrity-check".to_string(),
+                    format!("database integrity check failed: {}", err),
+                );
                 break;
             } else {
                 debug!("database integrity check successful");
@@ -404,23 +601,6 @@ fn start_integrity_check_thread(deps: &Dependencies) -> tokio::task::JoinHandle<
     tokio::spawn(fut.instrument(info_span!("scherzo::db")))
 }
 
-fn copy_dir_all(src: PathBuf, dst: PathBuf) -> std::io::Result<()> {
-    use std::fs;
-
-    fs::create_dir_all(&dst)?;
-    let dir = fs::read_dir(src)?;
-    for entry in dir {
-        let entry = entry?;
-        let ty = entry.file_type()?;
-        if ty.is_dir() {
-            copy_dir_all(entry.path(), dst.join(entry.file_name()))?;
-        } else {
-            fs::copy(entry.path(), dst.join(entry.file_name()))?;
-        }
-    }
-    Ok(())
-}
-
 fn exit(code: i32) -> ! {
     opentelemetry::global::shutdown_tracer_provider();
     std::process::exit(code)
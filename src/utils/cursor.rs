@@ -0,0 +1,258 @@
+//! Opaque cursors for paging through lists.
+//!
+//! A cursor records the database key of the last item a page ended at, which
+//! way the list is paged through, and a hash of the filters the list was
+//! requested with. The next page starts right after that key instead of at an
+//! index, so items added or removed while a client pages through a list don't
+//! make it skip or repeat items.
+
+use std::cmp::Ordering;
+
+use sha3::Digest;
+
+use crate::{ServerError, ServerResult};
+
+/// Which way a list is paged through, in terms of key order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Lowest keys first.
+    Forward,
+    /// Highest keys first.
+    Backward,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    pub direction: Direction,
+    filter_hash: u64,
+    /// Key of the last item of the page this cursor was made for.
+    pub position: Vec<u8>,
+}
+
+impl Cursor {
+    pub fn new(direction: Direction, filter: &[u8], position: Vec<u8>) -> Self {
+        Self {
+            direction,
+            filter_hash: hash_filter(filter),
+            position,
+        }
+    }
+
+    /// Encodes the cursor into a string that can be sent to clients.
+    pub fn encode(&self) -> String {
+        let direction = match self.direction {
+            Direction::Forward => 0_u8,
+            Direction::Backward => 1,
+        };
+        std::iter::once(direction)
+            .chain(self.filter_hash.to_be_bytes())
+            .chain(self.position.iter().copied())
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Decodes a cursor sent by a client. Fails if it wasn't made for a list
+    /// requested with the same filter.
+    pub fn decode(raw: &str, filter: &[u8]) -> ServerResult<Self> {
+        if raw.len() % 2 != 0 || !raw.is_ascii() {
+            return Err(ServerError::InvalidCursor);
+        }
+        let bytes = (0..raw.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&raw[index..index + 2], 16))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| ServerError::InvalidCursor)?;
+        if bytes.len() < 9 {
+            return Err(ServerError::InvalidCursor);
+        }
+
+        let direction = match bytes[0] {
+            0 => Direction::Forward,
+            1 => Direction::Backward,
+            _ => return Err(ServerError::InvalidCursor),
+        };
+        // Safety: we checked that there are at least 9 bytes above
+        let filter_hash = u64::from_be_bytes(unsafe { bytes[1..9].try_into().unwrap_unchecked() });
+        if filter_hash != hash_filter(filter) {
+            return Err(ServerError::InvalidCursor);
+        }
+
+        Ok(Self {
+            direction,
+            filter_hash,
+            position: bytes[9..].to_vec(),
+        })
+    }
+}
+
+fn hash_filter(filter: &[u8]) -> u64 {
+    let digest = sha3::Sha3_256::digest(filter);
+    // Safety: sha3-256 digests are 32 bytes long
+    u64::from_be_bytes(unsafe { digest[..8].try_into().unwrap_unchecked() })
+}
+
+/// A page of a list, with the cursor to get the next page with.
+#[derive(Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Not set if this is the last page.
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    pub fn empty() -> Self {
+        Self {
+            items: Vec::new(),
+            next_cursor: None,
+        }
+    }
+}
+
+/// Gets pages of a list that is stored under a range of keys.
+#[derive(Debug, Clone)]
+pub struct Pager {
+    direction: Direction,
+    filter: Vec<u8>,
+    after: Option<Vec<u8>>,
+    limit: usize,
+}
+
+impl Pager {
+    /// Starts paging from a cursor, or from the start of the list in the given
+    /// direction if there is no cursor. `filter` must contain everything the
+    /// list was requested with that changes which items are in it.
+    pub fn new(
+        direction: Direction,
+        filter: impl Into<Vec<u8>>,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> ServerResult<Self> {
+        let filter = filter.into();
+        let (direction, after) = match cursor {
+            Some(raw) => {
+                let cursor = Cursor::decode(raw, &filter)?;
+                (cursor.direction, Some(cursor.position))
+            }
+            None => (direction, None),
+        };
+
+        Ok(Self {
+            direction,
+            filter,
+            after,
+            limit,
+        })
+    }
+
+    /// Starts after the given key, if there is no cursor. Used by endpoints
+    /// that page with item IDs, from before they supported cursors.
+    pub fn or_after(mut self, key: Option<Vec<u8>>) -> Self {
+        if self.after.is_none() {
+            self.after = key;
+        }
+        self
+    }
+
+    /// Narrows the key range of the whole list down to the keys that are left
+    /// to page through. Returns `None` if there are none.
+    pub fn range(&self, start: &[u8], end: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+        let (mut start, mut end) = (start.to_vec(), end.to_vec());
+        if let Some(after) = &self.after {
+            match self.direction {
+                Direction::Forward if after > &start => start = after.clone(),
+                Direction::Backward if after < &end => end = after.clone(),
+                _ => {}
+            }
+        }
+        (start <= end && self.limit > 0).then(|| (start, end))
+    }
+
+    /// Collects the next page from the entries of a list, which must be in
+    /// key order. `map` can return `None` to leave out entries that aren't
+    /// items of the list.
+    pub fn collect<K, V, T, E>(
+        &self,
+        entries: impl DoubleEndedIterator<Item = Result<(K, V), E>>,
+        mut map: impl FnMut(&[u8], V) -> Option<T>,
+    ) -> Result<Page<T>, E>
+    where
+        K: AsRef<[u8]>,
+    {
+        let entries: Box<dyn Iterator<Item = Result<(K, V), E>>> = match self.direction {
+            Direction::Forward => Box::new(entries),
+            Direction::Backward => Box::new(entries.rev()),
+        };
+        // items must come after the cursor, in the direction we are going in
+        let past = match self.direction {
+            Direction::Forward => Ordering::Greater,
+            Direction::Backward => Ordering::Less,
+        };
+
+        let mut items = Vec::new();
+        let mut last_key = None;
+        let mut has_more = false;
+        for res in entries {
+            let (key, value) = res?;
+            let key = key.as_ref();
+            if let Some(after) = &self.after {
+                if key.cmp(after.as_slice()) != past {
+                    continue;
+                }
+            }
+            if let Some(item) = map(key, value) {
+                if items.len() >= self.limit {
+                    has_more = true;
+                    break;
+                }
+                items.push(item);
+                last_key = Some(key.to_vec());
+            }
+        }
+
+        let next_cursor = has_more
+            .then(|| last_key)
+            .flatten()
+            .map(|key| Cursor::new(self.direction, &self.filter, key).encode());
+
+        Ok(Page { items, next_cursor })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cursor_round_trip() {
+        let cursor = Cursor::new(Direction::Backward, b"filter", vec![0, 1, 255]);
+        let decoded = Cursor::decode(&cursor.encode(), b"filter").unwrap();
+        assert_eq!(cursor, decoded);
+        assert!(Cursor::decode(&cursor.encode(), b"other filter").is_err());
+        assert!(Cursor::decode("not a cursor", b"filter").is_err());
+    }
+
+    #[test]
+    fn pages_stay_stable_when_items_change() {
+        let entries = |keys: &[u8]| {
+            keys.iter()
+                .map(|key| Ok::<_, ServerError>(([*key], *key)))
+                .collect::<Vec<_>>()
+                .into_iter()
+        };
+
+        let pager = Pager::new(Direction::Forward, [], None, 2).unwrap();
+        let page = pager
+            .collect(entries(&[1, 2, 3, 4]), |_, value| Some(value))
+            .unwrap();
+        assert_eq!(page.items, [1, 2]);
+
+        // the last item of the page was removed and one was added before it
+        let cursor = page.next_cursor.unwrap();
+        let pager = Pager::new(Direction::Forward, [], Some(&cursor), 2).unwrap();
+        let page = pager
+            .collect(entries(&[0, 1, 3, 4]), |_, value| Some(value))
+            .unwrap();
+        assert_eq!(page.items, [3, 4]);
+        assert!(page.next_cursor.is_none());
+    }
+}
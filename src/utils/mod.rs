@@ -1,6 +1,24 @@
+pub mod cursor;
 pub mod either;
 pub mod evec;
 pub mod ratelimit;
 pub mod test;
 
-pub use ratelimit::rate_limit;
+use std::path::PathBuf;
+
+pub fn copy_dir_all(src: PathBuf, dst: PathBuf) -> std::io::Result<()> {
+    use std::fs;
+
+    fs::create_dir_all(&dst)?;
+    let dir = fs::read_dir(src)?;
+    for entry in dir {
+        let entry = entry?;
+        let ty = entry.file_type()?;
+        if ty.is_dir() {
+            copy_dir_all(entry.path(), dst.join(entry.file_name()))?;
+        } else {
+            fs::copy(entry.path(), dst.join(entry.file_name()))?;
+        }
+    }
+    Ok(())
+}
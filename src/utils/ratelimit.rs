@@ -1,47 +1,294 @@
+//! Rate limits for the endpoints marked with `#[rate(...)]`.
+//!
+//! Requests are counted per user if they have a valid session, and per IP
+//! address otherwise. Every endpoint has its own bucket, and the limits set
+//! with the attribute can be overridden from config.
+
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     net::{IpAddr, SocketAddr},
     str::FromStr,
-    time::Duration,
+    time::{Duration, UNIX_EPOCH},
 };
 
-use hrpc::{request::BoxRequest, server::layer::ratelimit::RateLimitLayer};
-
-pub fn rate_limit(
-    num: u64,
-    per: Duration,
-    check_header_for_ip: Option<String>,
-    allowed_ips: Option<Vec<String>>,
-) -> RateLimitLayer<
-    impl Fn(&mut BoxRequest) -> Option<IpAddr> + Clone,
-    impl Fn(&IpAddr) -> bool + Clone,
-> {
-    let allowed_ips = allowed_ips.map(|ips| {
-        ips.into_iter()
-            .map(|s| IpAddr::from_str(&s))
-            .flatten()
-            .collect::<HashSet<_, ahash::RandomState>>()
+use dashmap::DashMap;
+use hrpc::Request;
+use smol_str::SmolStr;
+use triomphe::Arc;
+
+use crate::{
+    config::{EndpointRateLimitConfig, RateLimitConfig},
+    db::{Batch, DbResult, Tree},
+    impls::{
+        auth::{AuthExt, SessionMap},
+        Dependencies,
+    },
+    ServerError,
+};
+
+/// Buckets are cleaned up once there are this many of them.
+const PRUNE_AFTER_LEN: usize = 4096;
+/// How often buckets are saved to the database, if persisting them is enabled.
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RateLimitKey {
+    User(u64),
+    Ip(IpAddr),
+}
+
+impl RateLimitKey {
+    fn to_db_key(&self, endpoint: &str) -> Vec<u8> {
+        match self {
+            RateLimitKey::User(user_id) => format!("user/{}/{}", user_id, endpoint),
+            RateLimitKey::Ip(ip) => format!("ip/{}/{}", ip, endpoint),
+        }
+        .into_bytes()
+    }
+
+    fn from_db_key(raw: &[u8]) -> Option<(Self, SmolStr)> {
+        let raw = std::str::from_utf8(raw).ok()?;
+        let mut split = raw.splitn(3, '/');
+        let kind = split.next()?;
+        let id = split.next()?;
+        let endpoint = split.next()?;
+        let key = match kind {
+            "user" => RateLimitKey::User(id.parse().ok()?),
+            "ip" => RateLimitKey::Ip(id.parse().ok()?),
+            _ => return None,
+        };
+        Some((key, endpoint.into()))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    /// Start of the current window, in milliseconds since the Unix epoch.
+    window_start: u64,
+    /// Length of the window, in milliseconds.
+    window_len: u64,
+    count: u64,
+}
+
+impl Bucket {
+    fn is_expired(&self, now: u64) -> bool {
+        now.saturating_sub(self.window_start) >= self.window_len
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        [
+            self.window_start.to_be_bytes(),
+            self.window_len.to_be_bytes(),
+            self.count.to_be_bytes(),
+        ]
+        .concat()
+    }
+
+    fn from_bytes(raw: &[u8]) -> Option<Self> {
+        let mut chunks = raw.chunks_exact(8).map(|chunk| {
+            // Safety: chunks_exact only gives 8 byte chunks
+            u64::from_be_bytes(unsafe { chunk.try_into().unwrap_unchecked() })
+        });
+        Some(Self {
+            window_start: chunks.next()?,
+            window_len: chunks.next()?,
+            count: chunks.next()?,
+        })
+    }
+}
+
+/// Request counts of all rate limited endpoints.
+pub struct RateLimiter {
+    buckets: DashMap<(RateLimitKey, SmolStr), Bucket, ahash::RandomState>,
+    overrides: HashMap<String, EndpointRateLimitConfig>,
+    client_ip_header_name: Option<String>,
+    allowed_ips: HashSet<IpAddr, ahash::RandomState>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            buckets: DashMap::default(),
+            overrides: config.endpoints.clone(),
+            client_ip_header_name: config.client_ip_header_name.clone(),
+            allowed_ips: config
+                .allowed_ips
+                .iter()
+                .flatten()
+                .flat_map(|ip| IpAddr::from_str(ip))
+                .collect(),
+        }
+    }
+
+    /// Counts a request to an endpoint, failing if there were too many.
+    ///
+    /// `num` and `per` are the limits from the `#[rate(...)]` attribute, used
+    /// if the endpoint doesn't have limits set in config.
+    pub fn check<T>(
+        &self,
+        valid_sessions: &SessionMap,
+        request: &Request<T>,
+        endpoint: &str,
+        num: u64,
+        per: Duration,
+    ) -> Result<(), ServerError> {
+        let key = match valid_sessions.auth(request) {
+            Ok(user_id) => RateLimitKey::User(user_id),
+            Err(_) => match self.client_ip(request) {
+                Some(ip) if self.allowed_ips.contains(&ip) => return Ok(()),
+                Some(ip) => RateLimitKey::Ip(ip),
+                None => return Ok(()),
+            },
+        };
+
+        let (num, per) = self.overrides.get(endpoint).map_or((num, per), |limit| {
+            (limit.requests, Duration::from_secs(limit.per))
+        });
+
+        self.hit(key, endpoint, num, per, now_millis())
+    }
+
+    fn hit(
+        &self,
+        key: RateLimitKey,
+        endpoint: &str,
+        num: u64,
+        per: Duration,
+        now: u64,
+    ) -> Result<(), ServerError> {
+        if self.buckets.len() >= PRUNE_AFTER_LEN {
+            self.buckets.retain(|_, bucket| !bucket.is_expired(now));
+        }
+
+        let window_len = per.as_millis() as u64;
+        let mut bucket = self
+            .buckets
+            .entry((key, endpoint.into()))
+            .or_insert(Bucket {
+                window_start: now,
+                window_len,
+                count: 0,
+            });
+        if bucket.is_expired(now) || bucket.window_len != window_len {
+            *bucket = Bucket {
+                window_start: now,
+                window_len,
+                count: 0,
+            };
+        }
+
+        if bucket.count >= num {
+            let remaining = (bucket.window_start + bucket.window_len).saturating_sub(now);
+            return Err(ServerError::TooFast(Duration::from_millis(remaining)));
+        }
+        bucket.count += 1;
+        Ok(())
+    }
+
+    /// Gets the IP a request was sent from, from the configured header if
+    /// there is one, since the server might be behind a reverse proxy.
+    pub fn client_ip<T>(&self, request: &Request<T>) -> Option<IpAddr> {
+        self.client_ip_header_name
+            .as_deref()
+            .and_then(|header_name| get_ip_addr_from_header(request, header_name))
+            .or_else(|| get_ip_addr(request))
+    }
+
+    /// Loads buckets saved with [`RateLimiter::save`], skipping expired ones.
+    pub async fn load(&self, tree: &Tree) -> DbResult<()> {
+        let now = now_millis();
+        for res in tree.iter().await {
+            let (key, value) = res?;
+            let loaded = RateLimitKey::from_db_key(&key).zip(Bucket::from_bytes(&value));
+            if let Some((key, bucket)) = loaded {
+                if !bucket.is_expired(now) {
+                    self.buckets.insert(key, bucket);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves the buckets that aren't expired, replacing the ones saved before.
+    pub async fn save(&self, tree: &Tree) -> DbResult<()> {
+        let now = now_millis();
+        let mut batch = Batch::default();
+        for res in tree.iter().await {
+            let (key, _) = res?;
+            batch.remove(key);
+        }
+        for entry in self.buckets.iter() {
+            let ((key, endpoint), bucket) = entry.pair();
+            if !bucket.is_expired(now) {
+                batch.insert(key.to_db_key(endpoint), bucket.to_bytes());
+            }
+        }
+        tree.apply_batch(batch).await
+    }
+}
+
+/// Spawns a task that saves rate limit buckets to the database, if enabled in config.
+pub fn spawn_save_task(deps: Arc<Dependencies>) {
+    if !deps.config.policy.ratelimit.persist {
+        return;
+    }
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(SAVE_INTERVAL).await;
+
+            if let Err(err) = deps.rate_limiter.save(&deps.ratelimit_tree).await {
+                tracing::error!("couldn't save rate limits: {}", err);
+            }
+        }
     });
+}
 
-    RateLimitLayer::new(num, per).set_key_fns(
-        move |req| {
-            check_header_for_ip
-                .as_deref()
-                .and_then(|header_name| get_ip_addr_from_header(req, header_name))
-                .or_else(|| get_ip_addr(req))
-        },
-        move |ip| allowed_ips.as_ref().map_or(false, |ips| ips.contains(ip)),
-    )
+fn now_millis() -> u64 {
+    UNIX_EPOCH
+        .elapsed()
+        .expect("time is before unix epoch")
+        .as_millis() as u64
 }
 
-fn get_ip_addr(req: &BoxRequest) -> Option<IpAddr> {
+fn get_ip_addr<T>(req: &Request<T>) -> Option<IpAddr> {
     req.extensions().get::<SocketAddr>().map(|addr| addr.ip())
 }
 
-fn get_ip_addr_from_header(req: &BoxRequest, check_header_for_ip: &str) -> Option<IpAddr> {
+fn get_ip_addr_from_header<T>(req: &Request<T>, check_header_for_ip: &str) -> Option<IpAddr> {
     req.header_map()
         .and_then(|headers| headers.get(check_header_for_ip))
         .and_then(|val| val.to_str().ok())
         .and_then(|ips| ips.split(',').map(str::trim).next())
         .and_then(|ip_raw| IpAddr::from_str(ip_raw).ok())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn limits_per_key_and_endpoint() {
+        let limiter = RateLimiter::new(&RateLimitConfig::default());
+        let per = Duration::from_secs(5);
+
+        assert!(limiter.hit(RateLimitKey::User(1), "a", 2, per, 0).is_ok());
+        assert!(limiter.hit(RateLimitKey::User(1), "a", 2, per, 1).is_ok());
+        assert!(limiter.hit(RateLimitKey::User(1), "a", 2, per, 2).is_err());
+        assert!(limiter.hit(RateLimitKey::User(1), "b", 2, per, 2).is_ok());
+        assert!(limiter.hit(RateLimitKey::User(2), "a", 2, per, 2).is_ok());
+        assert!(limiter
+            .hit(RateLimitKey::User(1), "a", 2, per, 5000)
+            .is_ok());
+    }
+
+    #[test]
+    fn db_key_roundtrip() {
+        let key = RateLimitKey::Ip("::1".parse().unwrap());
+        let raw = key.to_db_key("send_message");
+        assert_eq!(
+            RateLimitKey::from_db_key(&raw),
+            Some((key, SmolStr::new("send_message")))
+        );
+    }
+}
@@ -26,7 +26,8 @@ pub fn create_mock(
     let (tx, rx) = new_mock_channels();
 
     let transport = MockServer::new(rx);
-    let server = crate::impls::setup_server(deps, fed, tracing::Level::DEBUG);
+    let startup = crate::impls::startup::Startup::new("test");
+    let server = crate::impls::setup_server(deps, fed, tracing::Level::DEBUG, &startup);
     let fut = transport.serve(server.0);
 
     tokio::spawn(fut);